@@ -9,6 +9,7 @@ fn main() {
         .file("capnp/worker.capnp")
         .file("capnp/subworker.capnp")
         .file("capnp/monitor.capnp")
+        .file("capnp/broker.capnp")
         .run()
         .expect("schema compiler command");
 }
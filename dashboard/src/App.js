@@ -5,6 +5,7 @@ import './App.css';
 import Sessions from './components/Sessions.js';
 import Workers from './components/Workers.js';
 import Session from './components/Session.js';
+import Tasks from './components/Tasks.js';
 import { Route, BrowserRouter, Switch, Link } from 'react-router-dom';
 
 
@@ -27,12 +28,13 @@ class App extends Component {
   render() {
     return (
       <div className="App">
-      <BrowserRouter>
+      <BrowserRouter basename={window.RAIN_BASE_PATH || ""}>
       <div>
         <Navbar>
         <Nav>
           <NavItem><NavLink tag={Link} to="/sessions">Sessions</NavLink></NavItem>
           <NavItem><NavLink tag={Link} to="/workers">Workers</NavLink></NavItem>
+          <NavItem><NavLink tag={Link} to="/tasks">Tasks</NavLink></NavItem>
         </Nav>
         <NavbarBrand>Rain</NavbarBrand>
         </Navbar>
@@ -41,6 +43,7 @@ class App extends Component {
           <Switch>
           <Route path="/session/:id" render={props => <Session id={props.match.params.id}/>} />
           <Route path="/workers" render={() => <Workers/>}/>
+          <Route path="/tasks" render={() => <Tasks/>}/>
           <Route path="/sessions" render={() => <Sessions/>}/>
           <Route path="/" render={() => <Sessions/>}/>
           </Switch>
@@ -0,0 +1,77 @@
+import React, { Component } from 'react';
+import {Table, Form, Input, Button} from 'reactstrap';
+import { Link } from 'react-router-dom';
+import update from 'react-addons-update';
+
+import { fetch_from_server } from '../utils/fetch';
+import Error from './Error.js';
+
+
+// Search of tasks by tags ("key=value" separated by spaces) and state
+class Tasks extends Component {
+
+  constructor(props) {
+    super(props);
+    this.state = {tags: "", task_state: "", tasks: []}
+    this.search = this.search.bind(this);
+  }
+
+  search(event) {
+    event.preventDefault();
+    let params = this.state.tags.split(/\s+/).filter(tag => tag.length > 0)
+      .map(tag => "tag=" + encodeURIComponent(tag));
+    if (this.state.task_state) {
+      params.push("state=" + this.state.task_state);
+    }
+    fetch_from_server("api/v1/tasks?" + params.join("&"), undefined, "GET")
+      .then(response => response.json())
+      .then(response => {
+        if (response.error) {
+          this.setState(update(this.state, {error: {$set: response.error}}));
+        } else {
+          this.setState(update(this.state, {tasks: {$set: response}, error: {$set: null}}));
+        }
+      }, error => {
+        this.setState(update(this.state, {error: {$set: error.toString()}}));
+      });
+  }
+
+  render() {
+    return (
+        <div>
+          <Error error={this.state.error}/>
+          <h1>Tasks</h1>
+
+          <Form inline onSubmit={this.search}>
+            <Input placeholder="stage=qc sample=S123" value={this.state.tags}
+                   onChange={e => this.setState({tags: e.target.value})}/>
+            <Input type="select" value={this.state.task_state}
+                   onChange={e => this.setState({task_state: e.target.value})}>
+              <option value="">any state</option>
+              {["waiting", "ready", "running", "finished", "failed"].map(s =>
+                <option key={s} value={s}>{s}</option>)}
+            </Input>
+            <Button type="submit">Search</Button>
+          </Form>
+
+          <Table>
+            <thead>
+              <tr><th>Task</th><th>Type</th><th>State</th><th>Tags</th></tr>
+            </thead>
+            <tbody>
+              {this.state.tasks.map(t => {
+                return (<tr key={t.id.session_id + "/" + t.id.id}>
+                  <td><Link to={"session/" + t.id.session_id}>{t.id.session_id}/{t.id.id}</Link></td>
+                  <td>{t.task_type}</td>
+                  <td>{t.state}</td>
+                  <td>{Object.keys(t.tags).map(key => key + "=" + t.tags[key]).join(" ")}</td>
+                  </tr>);
+              })}
+            </tbody>
+          </Table>
+        </div>
+    );
+  }
+}
+
+export default Tasks;
@@ -1,7 +1,7 @@
 import React, { Component } from 'react';
 import update from 'react-addons-update';
 
-import { fetch_events } from '../utils/fetch';
+import { fetch_events, fetch_from_server } from '../utils/fetch';
 import { parse_date } from '../utils/date';
 import Error from './Error.js';
 import Chart from './Chart';
@@ -11,7 +11,8 @@ class Workers extends Component {
 
   constructor(props) {
     super(props);
-    this.state = {workers: []}
+    this.state = {workers: [], platforms: {}}
+    this.fetch_platforms();
     this.unsubscribe = fetch_events({"event_type": {value: "Monitoring", mode: "="}}, event => {
       //console.log("EVENT", event);
       let index = -1;
@@ -25,6 +26,9 @@ class Workers extends Component {
       }
 
       if (index === -1) {
+        if (!this.state.platforms[event.event.worker]) {
+          this.fetch_platforms();
+        }
         index = this.state.workers.length;
         this.setState(update(this.state, {workers: {$push: [{
           name: event.event.worker,
@@ -64,6 +68,19 @@ class Workers extends Component {
     });
   }
 
+  // Platforms of workers (os, arch, kernel, version) by worker ids
+  fetch_platforms() {
+    fetch_from_server("api/v1/info", undefined, "GET")
+      .then(response => response.json())
+      .then(info => {
+        let platforms = {};
+        for (let w of info.workers || []) {
+          platforms[w.id] = w.platform;
+        }
+        this.setState(update(this.state, {platforms: {$set: platforms}}));
+      }, error => console.log(error));
+  }
+
   componentWillUnmount() {
     this.unsubscribe();
   }
@@ -77,6 +94,12 @@ class Workers extends Component {
             this.state.workers.map(w =>
               <div key={w.name}>
                 <h2>Worker {w.name}</h2>
+                {this.state.platforms[w.name] && this.state.platforms[w.name].arch &&
+                  <p>
+                    {this.state.platforms[w.name].os} {this.state.platforms[w.name].arch},
+                    kernel {this.state.platforms[w.name].kernel},
+                    rain {this.state.platforms[w.name].version}
+                  </p>}
                 {<Chart data={w}/>}
               </div>
             )
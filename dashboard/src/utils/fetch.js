@@ -1,4 +1,5 @@
-let SERVER_URL = "/";
+// Base path is set by the server in index.html when it runs behind a reverse proxy
+let SERVER_URL = (window.RAIN_BASE_PATH || "") + "/";
 
 if (window.location.href.startsWith("http://localhost:3000")) {
     console.log("DEV mode detected, connecting to http://localhost:8080/");
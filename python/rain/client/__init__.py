@@ -1,10 +1,13 @@
 
 from .input import Input, InputDir, InputBase  # noqa
 from .output import Output, OutputDir, OutputBase  # noqa
-from .data import blob, pickled, directory, DataObject  # noqa
+from .data import blob, pickled, directory, artifact, DataObject  # noqa
 from .task import Task  # noqa
 from ..common import RainException, RainWarning, TaskException, SessionException # noqa
+from ..common import IdCollisionException  # noqa
 from .pycode import remote, Remote  # noqa
 from .client import Client  # noqa
+from .federation import Federation  # noqa
 from .program import Program  # noqa
 from .session import Session  # noqa
+from .monitor import SessionMonitor  # noqa
@@ -1,11 +1,17 @@
 import capnp
+import os
+import json
+import time
 from rain.client import rpc
 from rain.common import RainException, SessionException, TaskException
+from rain.common import IdCollisionException
 from rain.client.task import Task
-from rain.client.data import DataObject
+from rain.client.data import ArchiveDataObject, DataObject, PublishedDataObject
 from ..common import attributes, DataInstance, DataType
-from ..common.ids import id_from_capnp, id_to_capnp, worker_id_from_capnp
+from ..common.ids import ID, id_from_capnp, id_to_capnp, worker_id_from_capnp
+from ..common.units import parse_duration, parse_size
 from .session import Session
+from .tls import connect_tls
 
 CLIENT_PROTOCOL_VERSION = 0
 
@@ -44,8 +50,15 @@ def check_result(sessions, result):
 
         if result.error.debug:
             message.append("Debug:\n" + result.error.debug)
+        failure = None
+        if result.error.failure:
+            failure = json.loads(result.error.failure)
+            message.append("Failed on worker {} ({})".format(
+                failure.get("worker"), failure.get("host")))
         message = "\n".join(message)
-        raise cls(message)
+        exception = cls(message)
+        exception.failure = failure
+        raise exception
     else:
         raise Exception("Invalid result")
 
@@ -54,28 +67,93 @@ class Client:
     """
     A client connection object. Can hold multiple
     :py:class:`Session`\ s.
+
+    Args:
+        token (`str` or `None`): Access token when the server has access control
+            enabled, environment variable ``RAIN_TOKEN`` is used when not given.
+        tls_ca (`str` or `None`): Path to CA certificate (PEM) when the server
+            uses TLS, the server certificate has to be issued for ``address``.
+            Environment variable ``RAIN_TLS_CA`` is used when not given.
+        observer (`bool`): Read-only connection whatever the role of the token
+            is; it may inspect and watch sessions of other clients
+            (see :py:meth:`watch_session`), but it cannot change anything.
     """
 
-    def __init__(self, address, port):
-        self._rpc_client = capnp.TwoPartyClient("{}:{}".format(address, port))
+    def __init__(self, address, port, token=None, tls_ca=None, observer=False):
+        if tls_ca is None:
+            tls_ca = os.environ.get("RAIN_TLS_CA")
+        if tls_ca:
+            self._rpc_client = capnp.TwoPartyClient(connect_tls(address, port, tls_ca))
+        else:
+            self._rpc_client = capnp.TwoPartyClient("{}:{}".format(address, port))
 
+        if token is None:
+            token = os.environ.get("RAIN_TOKEN", "")
         bootstrap = self._rpc_client.bootstrap().cast_as(
             rpc.server.ServerBootstrap)
-        registration = bootstrap.registerAsClient(CLIENT_PROTOCOL_VERSION)
+        registration = bootstrap.registerAsClient(CLIENT_PROTOCOL_VERSION, token, observer)
         self._service = registration.wait().service
         self._datastore = self._service.getDataStore().wait().store
 
-    def new_session(self):
+    def new_session(self, max_running_tasks=None, detach_on_disconnect=False, ttl=None,
+                    cost_weight=0, env=None, server_ids=False, output_retention=None,
+                    output_max_size=None, tags=None):
         """
         Creates a new session.
 
-        Note the session is destroyed server-side when the client disconnects.
+        Note the session is destroyed server-side when the client disconnects,
+        unless it is detached.
+
+        Args:
+            max_running_tasks (`int` or `None`): Max number of tasks of the session
+                running at once, see :py:meth:`Session.set_max_running_tasks`.
+            detach_on_disconnect (`bool`): Keep the session running when the client
+                disconnects, see :py:meth:`Session.set_detach_on_disconnect`.
+            ttl (`float` or `None`): Time-to-live of the session in seconds,
+                see :py:meth:`Session.set_ttl`.
+            cost_weight (`float`): Weight of worker cost in scheduling,
+                see :py:meth:`Session.set_cost_weight`.
+            env (`dict` or `None`): Environment variables set for all tasks of the
+                session (programs and Python tasks), e.g. paths to credentials,
+                proxies or numbers of threads. Variables in ``env`` of a task
+                take precedence. Sub-sessions inherit them.
+            server_ids (`bool`): Ids of tasks and objects are assigned by the server
+                at submit (they are provisional until the submit).
+            output_retention (`str` or `None`): Retention of stdout/stderr of programs,
+                see :py:meth:`Session.set_output_policy`.
+            output_max_size (`int`, `str` or `None`): Max. bytes kept of stdout/stderr
+                (a string may have a unit, e.g. ``"10MiB"``),
+                see :py:meth:`Session.set_output_policy`.
+            tags (`dict` or `None`): Tags of the session, e.g. ``{"sample": "S123"}``;
+                tasks and sub-sessions inherit them.
 
         Returns:
             :class:`Session`: A new session
         """
-        session_id = self._service.newSession().wait().sessionId
-        return Session(self, session_id)
+        req = self._service.newSession_request()
+        if tags:
+            _tags_to_capnp(req, tags)
+        if env:
+            req.init("env", len(env))
+            for i, (name, value) in enumerate(sorted(env.items())):
+                req.env[i].name = name
+                req.env[i].value = str(value)
+        session_id = req.send().wait().sessionId
+        session = Session(self, session_id)
+        session._server_ids = server_ids
+        if max_running_tasks is not None or detach_on_disconnect or ttl is not None \
+                or cost_weight or output_retention or output_max_size:
+            session._max_running_tasks = max_running_tasks
+            session._detach_on_disconnect = detach_on_disconnect
+            session._cost_weight = cost_weight
+            session._output_retention = output_retention
+            if output_max_size is not None:
+                output_max_size = parse_size(output_max_size, "output_max_size")
+            session._output_max_size = output_max_size
+            if ttl is not None:
+                session._expires_at = time.time() + ttl
+            self._set_session_options(session)
+        return session
 
     def get_server_info(self):
         """
@@ -90,12 +168,182 @@ class Client:
                          "tasks": [id_from_capnp(t) for t in w.tasks],
                          "objects": [id_from_capnp(o) for o in w.objects],
                          "objects_to_delete": [id_from_capnp(o) for o in w.objectsToDelete],
-                         "resources": {"cpus": w.resources.nCpus}}
+                         "resources": {"cpus": w.resources.nCpus,
+                                       "memory": w.resources.memory,
+                                       "gpus": w.resources.nGpus,
+                                       "named": {r.name: r.count
+                                                 for r in w.resources.named}},
+                         "freed_bytes": w.freedBytes}
                         for w in info.workers]
         }
 
-    def _submit(self, tasks, dataobjs):
+    def set_secret(self, name, value):
+        """
+        Registers a named secret (e.g. a password) on the server or replaces
+        its value. Tasks reference secrets by name (argument ``secrets`` of
+        :class:`Task`), the value is set only in the environment of the running
+        task; it never appears in task attributes or logs.
+
+        Args:
+            name (`str`): Name of the secret
+            value (`str` or `bytes`): Value of the secret
+        """
+        if isinstance(value, str):
+            value = value.encode()
+        req = self._service.setSecret_request()
+        req.name = name
+        req.value = value
+        req.send().wait()
+
+    def remove_secret(self, name):
+        """Removes the secret from the server."""
+        req = self._service.removeSecret_request()
+        req.name = name
+        req.send().wait()
+
+    def get_graph_snapshot(self, session=None):
+        """
+        Returns a read-only snapshot of the graph on the server.
+
+        Args:
+            session: :class:`Session` (or its id); only the session and its
+                sub-sessions are included. All sessions are included when
+                it is ``None``.
+
+        Returns:
+            dict: A JSON-like dictionary with keys ``version``, ``sessions``,
+            ``tasks``, ``objects`` and ``workers``.
+        """
+        session_id = -1 if session is None else getattr(session, "session_id", session)
+        snapshot = self._service.getGraphSnapshot(session_id).wait().snapshot
+        return json.loads(snapshot)
+
+    def watch_session(self, session_id, cursor=0):
+        """
+        Observes a session, also a session of another client (e.g. from
+        a monitoring daemon). Returns tasks changed after ``cursor``; when
+        nothing changed yet, it waits for the next change (at most 30 seconds).
+
+        Returns:
+            dict: ``cursor`` (pass it to the next call), ``tasks`` (pairs
+            (task id, state) of submitted or changed tasks), ``finished``
+            (all submitted tasks are finished) and ``error`` (message of the
+            session error or ``None``).
+        """
+        result = self._service.watchSession(session_id, cursor).wait()
+        error = None
+        if result.state.which() == "error":
+            error = result.state.error.message
+        return {"cursor": result.cursor,
+                "tasks": [(t.id.id, t.state) for t in result.tasks],
+                "finished": result.finished,
+                "error": error}
+
+    def follow_session(self, session_id):
+        """
+        Generator of changes of a session (see :py:meth:`watch_session`),
+        it ends when the session is finished or failed.
+        """
+        cursor = 0
+        while True:
+            changes = self.watch_session(session_id, cursor)
+            cursor = changes["cursor"]
+            if changes["tasks"] or changes["error"]:
+                yield changes
+            if changes["finished"] or changes["error"]:
+                return
+
+    def upgrade_workers(self, image, batch=1):
+        """
+        Starts a rolling upgrade of workers (requires the admin role).
+
+        Workers started with another ``--image`` are drained in batches of
+        at most ``batch`` workers and they leave when nothing would be lost;
+        their supervisor is expected to start them again with the new image.
+
+        Args:
+            image (`str`): Image of upgraded workers.
+            batch (`int`): Maximal number of workers drained at once.
+        """
+        self._service.upgradeWorkers(image, batch).wait()
+
+    def get_upgrade_status(self):
+        """
+        Returns the state of the rolling upgrade as a dictionary with keys
+        ``image``, ``outdated``, ``draining``, ``upgraded`` and ``done``.
+        """
+        status = self._service.getUpgradeStatus().wait()
+        return {"image": status.image,
+                "outdated": status.outdated,
+                "draining": status.draining,
+                "upgraded": status.upgraded,
+                "done": status.done}
+
+    def set_chaos(self, drop_messages=0.0, delay_transfers=0, kill_subworkers=0.0,
+                  fail_fetches=0.0, seed=0):
+        """
+        Configures fault injection on all workers (requires the admin role and
+        workers built with cargo feature ``chaos``). Calling it without
+        arguments disables the fault injection.
+
+        Args:
+            drop_messages (`float`): Probability that an update of a worker
+                to the server is dropped (it is sent again with the next update).
+            delay_transfers (`int`): Delay of each fetch of an object between
+                workers in milliseconds.
+            kill_subworkers (`float`): Probability that a subworker is killed
+                when a task is started in it.
+            fail_fetches (`float`): Probability that a fetch of an object
+                between workers fails.
+            seed (`int`): Seed of the random generator, 0 = seeded from time.
+        """
+        config = {"drop_messages": drop_messages,
+                  "delay_transfers": delay_transfers,
+                  "kill_subworkers": kill_subworkers,
+                  "fail_fetches": fail_fetches,
+                  "seed": seed}
+        self._service.setChaos(json.dumps(config)).wait()
+
+    def configure_scheduler(self, paused=None, placement=None,
+                            locality_weight=None, scheduling_interval=None):
+        """
+        Pauses or resumes scheduling and changes parameters of the scheduler
+        without a restart of the server (requires the admin role). Arguments
+        that are not given are not changed.
+
+        Paused scheduling assigns no tasks to workers; running tasks continue,
+        so workers are drained. Sessions and submits are not affected.
+
+        Args:
+            paused (`bool`): Pause (True) or resume (False) scheduling.
+            placement (`str`): Placement policy: "any", "pack" or "spread"
+                (see ``rain server --placement``).
+            locality_weight (`float`): How much workers holding inputs of
+                a task are preferred (see ``rain server --locality-weight``).
+            scheduling_interval: Interval of scheduling rounds in seconds or
+                with units (e.g. "200ms"), 0 schedules after every event.
+
+        Returns:
+            Dictionary with the current ``paused``, ``placement``,
+            ``locality_weight`` and ``scheduling_interval`` (in seconds) and
+            numbers of ``ready_tasks`` and ``running_tasks``.
+        """
+        config = {}
+        if paused is not None:
+            config["paused"] = bool(paused)
+        if placement is not None:
+            config["placement"] = placement
+        if locality_weight is not None:
+            config["locality_weight"] = float(locality_weight)
+        if scheduling_interval is not None:
+            config["scheduling_interval"] = parse_duration(scheduling_interval,
+                                                           "scheduling_interval")
+        status = self._service.configureScheduler(json.dumps(config)).wait().status
+        return json.loads(status)
+
+    def _submit(self, tasks, dataobjs, hold=False):
         req = self._service.submit_request()
+        req.hold = hold
 
         # Serialize tasks
         req.init("tasks", len(tasks))
@@ -107,10 +355,27 @@ class Client:
         for i in range(len(dataobjs)):
             dataobjs[i].to_capnp(req.objects[i])
 
-        req.send().wait()
-
-    def _fetch(self, dataobj):
-        "Fetch the object data and update its state."
+        result = req.send().wait()
+        if result.which() == "idCollision":
+            raise IdCollisionException(
+                [id_from_capnp(i) for i in result.idCollision.tasks],
+                [id_from_capnp(i) for i in result.idCollision.objects])
+
+        # Replace provisional ids by ids assigned by the server
+        def assigned(items):
+            return {(a.sessionId, a.provisional): a.id for a in items}
+        task_ids = assigned(result.ok.tasks)
+        object_ids = assigned(result.ok.objects)
+        for item, ids in [(t, task_ids) for t in tasks] + [(o, object_ids) for o in dataobjs]:
+            new_id = ids.get(tuple(item.id))
+            if new_id is not None:
+                item.id = ID(session_id=item.id.session_id, id=new_id)
+
+    def _flush_session(self, session):
+        self._service.flushSession(session.session_id).wait()
+
+    def _fetch(self, dataobj, offset=0, length=None):
+        "Fetch the object data (or their byte range) and update its state."
         if not dataobj._keep:
             raise RainException(
                 "Can't fetch object {} without keep flag.".format(dataobj))
@@ -121,7 +386,8 @@ class Client:
 
         req = self._datastore.createReader_request()
         id_to_capnp(dataobj.id, req.id)
-        req.offset = 0
+        req.offset = offset
+        req.length = length or 0
         result = req.send().wait()
         check_result((dataobj.session,), result)
 
@@ -139,6 +405,20 @@ class Client:
                             data_object=dataobj,
                             data_type=DataType.from_capnp(result.dataType))
 
+    def _get_object_info(self, dataobj, checksum):
+        if dataobj.state is None:
+            raise RainException(
+                "Object {} is not submitted.".format(dataobj))
+        req = self._datastore.getInfo_request()
+        id_to_capnp(dataobj.id, req.id)
+        req.checksum = checksum
+        result = req.send().wait()
+        check_result((dataobj.session,), result)
+        return {"size": result.size if result.size >= 0 else None,
+                "data_type": DataType.from_capnp(result.dataType),
+                "content_type": result.contentType or None,
+                "checksum": result.checksum or None}
+
     def _wait(self, tasks, dataobjs):
         req = self._service.wait_request()
 
@@ -162,6 +442,14 @@ class Client:
     def _close_session(self, session):
         self._service.closeSession(session.session_id).wait()
 
+    def _new_sub_session(self, parent, name):
+        session_id = self._service.newSubSession(parent.session_id, name).wait().sessionId
+        session = Session(self, session_id)
+        session._server_ids = parent._server_ids
+        session.name = name
+        session.parent = parent
+        return session
+
     def _wait_some(self, tasks, dataobjs):
         req = self._service.waitSome_request()
 
@@ -203,6 +491,143 @@ class Client:
         result = req.send().wait()
         check_result([o.session for o in dataobjs], result)
 
+    def _invalidate(self, dataobjs, reason):
+        req = self._service.invalidate_request()
+        req.init("objectIds", len(dataobjs))
+        for i in range(len(dataobjs)):
+            id_to_capnp(dataobjs[i].id, req.objectIds[i])
+        req.reason = reason
+        result = req.send().wait()
+        return [id_from_capnp(task_id) for task_id in result.taskIds]
+
+    def _publish(self, dataobj, name):
+        if dataobj.state is None:
+            raise RainException(
+                "Object {} is not submitted.".format(dataobj))
+        req = self._service.publish_request()
+        id_to_capnp(dataobj.id, req.objectId)
+        req.name = name
+        req.send().wait()
+
+    def _unpublish(self, name):
+        req = self._service.unpublish_request()
+        req.name = name
+        req.send().wait()
+
+    def _get_published(self, session, name):
+        req = self._service.getPublished_request()
+        req.name = name
+        result = req.send().wait()
+        return PublishedDataObject(
+            session,
+            id_from_capnp(result.objectId),
+            result.label,
+            DataType.from_capnp(result.dataType),
+            attributes.attributes_from_capnp(result.attributes))
+
+    def _has_published(self, name):
+        req = self._service.getPublished_request()
+        req.name = name
+        try:
+            req.send().wait()
+            return True
+        except capnp.lib.capnp.KjException as e:
+            if "is not published" in e.description:
+                return False
+            raise
+
+    def _list_kept(self, session, label_prefix, min_size, min_age, tags):
+        req = self._service.listKept_request()
+        req.sessionId = session.session_id
+        _kept_filter_to_capnp(req.filter, label_prefix, min_size, min_age, tags)
+        result = req.send().wait()
+        return [{"id": id_from_capnp(o.id),
+                 "label": o.label,
+                 "state": o.state,
+                 "size": o.size if o.size >= 0 else None,
+                 "placement": [worker_id_from_capnp(w) for w in o.placement],
+                 "age": o.age}
+                for o in result.objects]
+
+    def _unkeep_matching(self, session, label_prefix, min_size, min_age, tags):
+        req = self._service.unkeepMatching_request()
+        req.sessionId = session.session_id
+        _kept_filter_to_capnp(req.filter, label_prefix, min_size, min_age, tags)
+        result = req.send().wait()
+        return [id_from_capnp(i) for i in result.objectIds]
+
+    def _archive_kept(self, session, label_prefix, min_size, min_age, tags, min_id):
+        req = self._service.archiveKept_request()
+        req.sessionId = session.session_id
+        _kept_filter_to_capnp(req.filter, label_prefix, min_size, min_age, tags)
+        req.minId = min_id
+        result = req.send().wait()
+        return ArchiveDataObject(session, id_from_capnp(result.objectId)), result.count
+
+    def _retry_failed(self, session, tags):
+        req = self._service.retryFailed_request()
+        req.sessionId = session.session_id
+        if tags:
+            _tags_to_capnp(req, tags)
+        result = req.send().wait()
+        return [id_from_capnp(i) for i in result.taskIds]
+
+    def _cancel_tasks(self, session, tags):
+        req = self._service.cancelTasks_request()
+        req.sessionId = session.session_id
+        _tags_to_capnp(req, tags)
+        result = req.send().wait()
+        return [id_from_capnp(i) for i in result.taskIds]
+
+    def _kept_size(self, session):
+        result = self._service.keptSize(session.session_id).wait()
+        return result.count, result.size
+
+    def _set_session_options(self, session):
+        req = self._service.setSessionOptions_request()
+        req.sessionId = session.session_id
+        req.options.maxRunningTasks = session._max_running_tasks or 0
+        req.options.detachOnDisconnect = session._detach_on_disconnect
+        req.options.keepFailed = session._keep_failed
+        req.options.costWeight = session._cost_weight
+        req.options.sampleRate = session._sample_rate
+        req.options.outputRetention = session._output_retention or ""
+        req.options.outputMaxSize = session._output_max_size or 0
+        if session._expires_at is not None:
+            # TTL is sent as remaining time, so changing other options does not prolong it
+            req.options.ttl = max(session._expires_at - time.time(), 0.001)
+            req.options.expiredRetention = session._expired_retention or 0
+        req.send().wait()
+
+    def _get_group_status(self, session, name):
+        result = self._service.getGroupStatus(session.session_id, name).wait()
+        return {
+            "total": result.total,
+            "finished": result.finished,
+            "running": result.running,
+            "failed": [id_from_capnp(t) for t in result.failed],
+        }
+
+    def _get_session_stats(self, session):
+        result = self._service.getSessionStats(session.session_id).wait()
+        return {
+            "waiting": result.waiting,
+            "ready": result.ready,
+            "running": result.running,
+            "finished": result.finished,
+            "failed": result.failed,
+            "cpu_time": result.cpuTime,
+            "produced_bytes": result.producedBytes,
+            "transferred_bytes": result.transferredBytes,
+            "failures": {f.kind: f.count for f in result.failures},
+            "sampled": result.sampled,
+            "sample_mismatches": [id_from_capnp(t) for t in result.sampleMismatches],
+        }
+
+    def _wait_group(self, session, name):
+        result = self._service.waitGroup(session.session_id, name).wait()
+        check_result((session,), result)
+
     def update(self, items):
         tasks, dataobjects = split_items(items)
         self._get_state(tasks, dataobjects)
@@ -239,6 +664,21 @@ class Client:
                 object_update.attributes)
 
 
+def _kept_filter_to_capnp(out, label_prefix, min_size, min_age, tags=None):
+    if label_prefix:
+        out.labelPrefix = label_prefix
+    out.minSize = min_size
+    out.minAge = min_age
+    if tags:
+        _tags_to_capnp(out, tags)
+
+
+def _tags_to_capnp(out, tags):
+    out.init("tags", len(tags))
+    for i, (key, value) in enumerate(sorted(tags.items())):
+        out.tags[i] = "{}={}".format(key, value)
+
+
 def split_items(items):
     """Split items into 'tasks' and 'dataobjects'
     Throws an error if an item is not task nor object"""
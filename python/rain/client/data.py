@@ -1,6 +1,7 @@
 import capnp
 import tarfile
 import io
+import os
 
 from .session import get_active_session
 from ..common import RainException, ids, ID
@@ -8,6 +9,15 @@ from ..common.attributes import attributes_to_capnp
 from ..common.content_type import check_content_type, encode_value
 from ..common import DataType
 
+# Modes of blobs mapped into working directories of programs, see `blob`
+DATA_MODES = ("executable", "extract")
+
+
+def check_data_mode(mode):
+    if mode is not None and mode not in DATA_MODES:
+        raise RainException("Invalid data object mode {!r} (use one of {})"
+                             .format(mode, ", ".join(DATA_MODES)))
+
 
 class DataObject:
 
@@ -24,6 +34,9 @@ class DataObject:
     # or by fetching from server)
     data = None
 
+    # Fingerprint of the submitted object (see Session.submit)
+    _fingerprint = None
+
     def __init__(self, label=None, session=None, data_type=DataType.BLOB, content_type=None):
         assert isinstance(data_type, DataType)
         if session is None:
@@ -41,6 +54,25 @@ class DataObject:
     def content_type(self):
         return self.attributes["spec"]["content_type"]
 
+    @property
+    def mode(self):
+        return self.attributes["spec"].get("mode")
+
+    def set_mode(self, mode):
+        """
+        Set how the blob is mapped into working directories of programs
+        (see `blob`).
+        """
+        if self.state is not None:
+            raise RainException("Cannot set mode of submitted object")
+        check_data_mode(mode)
+        if mode is not None and self.data_type != DataType.BLOB:
+            raise RainException("Only blobs can have a mode")
+        if mode is None:
+            self.attributes["spec"].pop("mode", None)
+        else:
+            self.attributes["spec"]["mode"] = mode
+
     def _free(self):
         """Set flag that object is not available on the server """
         self._keep = False
@@ -59,6 +91,30 @@ class DataObject:
         """Returns the value of self._keep"""
         return self._keep
 
+    def pin(self, *workers):
+        """
+        Pin the object to the given workers before submission.
+
+        Workers are given as addresses ("ip:port"), hosts ("ip") or labels
+        of workers ("key=value", see ``rain worker --label``).
+        The object is placed on (or its producer is scheduled to) one of them.
+        """
+        if self.state is not None:
+            raise RainException("Cannot pin submitted object")
+        self.attributes["placement"] = list(workers)
+
+    def pin_in_memory(self):
+        """
+        Keep the data of the object in memory of workers (it is never written
+        to the disk), e.g. hot objects of latency-critical pipelines.
+
+        Pinned memory is limited on each worker (see ``--pin-memory``),
+        the object is stored normally when the limit is reached.
+        """
+        if self.state is not None:
+            raise RainException("Cannot pin submitted object")
+        self.attributes["pin_in_memory"] = True
+
     def to_capnp(self, out):
         ids.id_to_capnp(self.id, out.id)
         out.keep = self._keep
@@ -76,18 +132,31 @@ class DataObject:
     def wait(self):
         self.session.wait((self,))
 
-    def fetch(self):
+    def fetch(self, offset=0, length=None):
         """
-        Fetch the object data and update its state.
+        Fetch the object data (or the byte range of at most `length` bytes
+        from `offset`) and update its state.
 
         Returns:
             DataInstance
         """
-        return self.session.fetch(self)
+        return self.session.fetch(self, offset, length)
+
+    def get_info(self, checksum=False):
+        """Metadata of the object, see :py:meth:`Session.get_object_info`"""
+        return self.session.get_object_info(self, checksum)
+
+    def invalidate(self, reason=""):
+        """Invalidate the corrupt object, see :py:meth:`Session.invalidate`"""
+        return self.session.invalidate((self,), reason)
 
     def update(self):
         self.session.update((self,))
 
+    def publish(self, name):
+        """Publish the object under the global name, see :py:meth:`Session.publish`"""
+        self.session.publish(self, name)
+
     def __del__(self):
         if self.state is not None and self._keep:
             try:
@@ -119,14 +188,56 @@ class DataObject:
             self.label, t, self.id, self.attributes)
 
 
-def blob(value, label="const", content_type=None, encode=None):
+class PublishedDataObject(DataObject):
+    """Object published by a session, obtained by :py:meth:`Session.published`.
+    The object is not owned by the session and it is never submitted."""
+
+    def __init__(self, session, object_id, label, data_type, attributes):
+        self.session = session
+        self.id = object_id
+        self.label = label
+        self.data_type = data_type
+        self.attributes = attributes
+        self.state = "finished"
+
+    def keep(self):
+        raise RainException("Published object cannot be kept")
+
+    def publish(self, name):
+        raise RainException("Object is already published")
+
+
+class ArchiveDataObject(DataObject):
+    """Kept directory object created by the server from other kept objects,
+    obtained by :py:meth:`Session.fetch_kept_archive`. It is never submitted."""
+
+    def __init__(self, session, object_id):
+        self.session = session
+        self.id = object_id
+        self.label = "archive"
+        self.data_type = DataType.DIRECTORY
+        self.attributes = {"spec": {"content_type": None}}
+        self.state = "unfinished"
+        self._keep = True
+
+    def keep(self):
+        raise RainException("Archive object is already kept")
+
+
+def blob(value, label="const", content_type=None, encode=None, mode=None):
     """
     Create a constant data object with accompanying data.
 
     Given `value` may be either `bytes` or any object to be encoded with
     `encoding` content type. Strings are encoded with utf-8 by default.
     Specify at most one of `content_type` and `encode`.
+
+    `mode` sets how the blob is mapped into working directories of programs:
+    "executable" maps it as an executable file (e.g. a tool binary),
+    "extract" maps a tar archive (optionally gzipped) as a directory with
+    extracted files; permissions of the archived files are kept.
     """
+    check_data_mode(mode)
 
     if content_type is not None:
         if encode is not None:
@@ -150,6 +261,7 @@ def blob(value, label="const", content_type=None, encode=None):
 
     dataobj = DataObject(label, content_type=content_type)
     dataobj.data = value
+    dataobj.set_mode(mode)
     return dataobj
 
 
@@ -174,6 +286,35 @@ def directory(path=None, label="const_dir"):
     return dataobj
 
 
+def artifact(path, label=None, extract=False):
+    """
+    Create a constant data object from a file to be run or used by programs,
+    e.g. a tool binary. An executable file is mapped as an executable file
+    (mode "executable").
+
+    With `extract`, the file is a tar archive (optionally gzipped) that is
+    extracted into a directory in the working directory of the program (mode
+    "extract"). A directory is packed into such an archive, so it keeps
+    permissions of its files. The default label is the name of the file.
+    """
+    if label is None:
+        label = os.path.basename(os.path.normpath(path))
+    if os.path.isdir(path):
+        f = io.BytesIO()
+        with tarfile.open(fileobj=f, mode="w:gz") as tf:
+            tf.add(path, ".")
+        return blob(f.getvalue(), label=label, mode="extract")
+    if extract and not tarfile.is_tarfile(path):
+        raise RainException("File '{}' is not a tar archive".format(path))
+    with open(path, "rb") as f:
+        data = f.read()
+    if extract:
+        mode = "extract"
+    else:
+        mode = "executable" if os.access(path, os.X_OK) else None
+    return blob(data, label=label, mode=mode)
+
+
 def to_data(obj):
     """Convert an object to DataObject/DataObjectPart"""
     if isinstance(obj, DataObject):
@@ -0,0 +1,125 @@
+import capnp
+
+from .client import Client
+from . import rpc
+from ..common import RainException
+
+
+class Federation:
+    """
+    Set of Rain servers (e.g. clusters at different sites) used from one program.
+
+    Each session runs as a whole on one of the servers. The server is chosen by
+    location of published data that the session uses, or by free capacity.
+    State of sessions is aggregated by :py:meth:`Federation.status` and
+    :py:meth:`Federation.wait_all`.
+
+    The federation lives in the client, there is no meta server: servers do not
+    know about each other, sessions are not forwarded between them and data
+    are not moved between servers.
+
+    Args:
+        servers (`dict`): Name of server -> :class:`Client` or (address, port)
+    """
+
+    def __init__(self, servers):
+        if not servers:
+            raise RainException("Federation needs at least one server")
+        self.clients = {}
+        for name, server in servers.items():
+            if not isinstance(server, Client):
+                server = Client(*server)
+            self.clients[name] = server
+        # Session -> name of server
+        self._sessions = {}
+
+    @classmethod
+    def from_broker(cls, address, port=7211, **kwargs):
+        """
+        Creates a federation of servers of session shards that share workers
+        through a broker (see ``rain broker``). Servers are named by their
+        addresses, other arguments are passed to :class:`Client`.
+        """
+        rpc_client = capnp.TwoPartyClient("{}:{}".format(address, port))
+        broker = rpc_client.bootstrap().cast_as(rpc.broker.Broker)
+        servers = broker.getServers().wait().servers
+        clients = {}
+        for server in servers:
+            host, server_port = server.rsplit(":", 1)
+            clients[server] = Client(host, int(server_port), **kwargs)
+        return cls(clients)
+
+    def capacity(self):
+        """
+        Returns free capacity of servers. Free cpus are estimated from
+        the number of tasks assigned to workers (one cpu per task).
+
+        Returns:
+            dict: Name of server -> {"workers", "cpus", "free_cpus"}
+        """
+        result = {}
+        for name, client in self.clients.items():
+            workers = client.get_server_info()["workers"]
+            cpus = sum(w["resources"]["cpus"] for w in workers)
+            used = sum(len(w["tasks"]) for w in workers)
+            result[name] = {"workers": len(workers),
+                            "cpus": cpus,
+                            "free_cpus": max(cpus - used, 0)}
+        return result
+
+    def select_server(self, published=()):
+        """
+        Returns name of server for a new session.
+
+        Servers that hold all the given published names are preferred
+        (data are not moved between servers), the server with the most free
+        cpus is chosen among them.
+        """
+        candidates = [name for name, client in self.clients.items()
+                      if all(client._has_published(p) for p in published)]
+        if not candidates:
+            raise RainException(
+                "No server holds all published objects {}".format(list(published)))
+        capacity = self.capacity()
+        return max(sorted(candidates), key=lambda name: capacity[name]["free_cpus"])
+
+    def new_session(self, published=(), server=None, **kwargs):
+        """
+        Creates a new session on the selected server (see :py:meth:`select_server`).
+        Other arguments are passed to :py:meth:`Client.new_session`.
+        """
+        if server is None:
+            server = self.select_server(published)
+        session = self.clients[server].new_session(**kwargs)
+        self._sessions[session] = server
+        return session
+
+    def server_of(self, session):
+        """Returns name of server that runs the session."""
+        return self._sessions[session]
+
+    def status(self):
+        """
+        Returns aggregated state of sessions created by the federation.
+
+        Returns:
+            dict: Name of server -> list of {"session_id", "tasks", "active"}
+        """
+        result = {name: [] for name in self.clients}
+        for session, name in self._sessions.items():
+            result[name].append({"session_id": session.session_id,
+                                 "tasks": len(session._submitted_tasks),
+                                 "active": session.active})
+        return result
+
+    def wait_all(self):
+        """Wait until all submitted tasks of all open sessions are finished."""
+        for session in list(self._sessions):
+            if session.active:
+                session.wait_all()
+
+    def close(self):
+        """Close all sessions created by the federation."""
+        for session in list(self._sessions):
+            if session.active:
+                session.close()
@@ -0,0 +1,76 @@
+"""
+Fingerprints of tasks and data objects used by incremental submits
+(see :py:meth:`Session.submit`). Two tasks have the same fingerprint when
+they have the same type, attributes and outputs and their inputs have the
+same fingerprints. The fingerprint of a constant object is derived from its
+data, the fingerprint of an output from its producer.
+"""
+
+import hashlib
+import json
+
+# Attributes that do not change results of a task (only its placement or reporting)
+IGNORED_TASK_ATTRIBUTES = ("group", "preemptible", "priority", "duration", "arch")
+IGNORED_OBJECT_ATTRIBUTES = ("placement", "pin_in_memory")
+
+
+def _digest(value):
+    data = json.dumps(value, sort_keys=True, default=repr).encode()
+    return hashlib.sha1(data).hexdigest()
+
+
+def _relevant(attributes, ignored):
+    return {key: value for key, value in attributes.items() if key not in ignored}
+
+
+def compute_fingerprints(tasks):
+    """Fingerprints of unsubmitted tasks and of their outputs.
+
+    Returns:
+        dict: ``id(task or object) -> fingerprint``"""
+    producers = {id(output): (task, i)
+                 for task in tasks for i, output in enumerate(task.outputs)}
+    fingerprints = {}
+
+    def object_fingerprint(dataobj):
+        key = id(dataobj)
+        if key in fingerprints:
+            return fingerprints[key]
+        if dataobj._fingerprint is not None:
+            # Object submitted earlier
+            return dataobj._fingerprint
+        if key in producers:
+            task, index = producers[key]
+            fingerprint = _digest([task_fingerprint(task), index])
+        elif dataobj.data is not None:
+            fingerprint = _digest(["data",
+                                   dataobj.data_type.value,
+                                   hashlib.sha1(dataobj.data).hexdigest(),
+                                   _relevant(dataobj.attributes, IGNORED_OBJECT_ATTRIBUTES)])
+        else:
+            # Object unknown to the client (e.g. published by another session)
+            fingerprint = _digest(["id", list(dataobj.id)])
+        fingerprints[key] = fingerprint
+        return fingerprint
+
+    def task_fingerprint(task):
+        key = id(task)
+        if key not in fingerprints:
+            inputs = [(label, object_fingerprint(dataobj))
+                      for label, dataobj in task.inputs.items()]
+            outputs = [(dataobj.label,
+                        dataobj.data_type.value,
+                        _relevant(dataobj.attributes, IGNORED_OBJECT_ATTRIBUTES))
+                       for dataobj in task.outputs]
+            fingerprints[key] = _digest([
+                task.task_type,
+                _relevant(task.attributes, IGNORED_TASK_ATTRIBUTES),
+                inputs,
+                outputs])
+        return fingerprints[key]
+
+    for task in tasks:
+        task_fingerprint(task)
+        for output in task.outputs:
+            object_fingerprint(output)
+    return fingerprints
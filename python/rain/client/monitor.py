@@ -0,0 +1,65 @@
+import json
+import os
+import urllib.request
+
+
+class SessionMonitor:
+    """
+    Progress of a session polled from the JSON API of the server
+    (``/api/v1/sessions/ID/snapshot``); suitable for progress bars in notebooks.
+
+    Each poll transfers only tasks changed since the previous one.
+
+    Args:
+        url (`str`): URL of the HTTP interface of the server, e.g.
+            ``http://server:8080`` (including the base path behind a proxy).
+        session: :class:`Session` or its id.
+        token (`str` or `None`): Access token, environment variable ``RAIN_TOKEN``
+            is used when not given.
+    """
+
+    def __init__(self, url, session, token=None):
+        session_id = getattr(session, "session_id", session)
+        self.url = "{}/api/v1/sessions/{}/snapshot".format(url.rstrip("/"), session_id)
+        if token is None:
+            token = os.environ.get("RAIN_TOKEN")
+        self.token = token
+        self.cursor = None
+        self.state = None
+        self.counts = {}
+        self.error = None
+        # task id -> state of the task
+        self.tasks = {}
+
+    def poll(self):
+        """Fetch changes since the last poll; returns the list of changed tasks
+        as pairs (task id, state)"""
+        url = self.url
+        if self.cursor is not None:
+            url += "?cursor={}".format(self.cursor)
+        request = urllib.request.Request(url)
+        if self.token:
+            request.add_header("Authorization", "Bearer {}".format(self.token))
+        with urllib.request.urlopen(request) as response:
+            snapshot = json.loads(response.read().decode())
+        if self.cursor is None or snapshot["cursor"] < self.cursor:
+            self.tasks = {}
+        self.cursor = snapshot["cursor"]
+        self.state = snapshot["state"]
+        self.counts = snapshot["counts"]
+        self.error = snapshot["error"]
+        changed = [tuple(item) for item in snapshot["changed"]]
+        self.tasks.update(changed)
+        return changed
+
+    @property
+    def progress(self):
+        """Ratio of finished tasks (0.0 - 1.0)"""
+        total = sum(self.counts.values())
+        if total == 0:
+            return 1.0
+        return self.counts.get("finished", 0) / total
+
+    def __repr__(self):
+        return "<SessionMonitor {} state={} counts={}>".format(
+            self.url, self.state, self.counts)
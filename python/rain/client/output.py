@@ -1,5 +1,5 @@
 
-from .data import DataObject, DataType
+from .data import DataObject, DataType, check_data_mode
 from ..common.content_type import check_content_type, merge_content_types
 from ..common import LabeledList
 from copy import copy
@@ -18,14 +18,26 @@ class OutputBase:
 
     data_type = None
 
+    # Checks of `validate` applied by the worker before the output is finished
+    VALIDATION_CHECKS = ("non_empty", "min_size", "max_size", "regex", "json")
+
     def __init__(self, label=None, *, size_hint=None, content_type=None,
-                 mode=None, encode=None, path=None):
+                 mode=None, encode=None, path=None, validate=None):
         assert self.data_type is not None
         self.label = label
         self.size_hint = size_hint
+        if validate is not None:
+            unknown = set(validate) - set(self.VALIDATION_CHECKS)
+            if unknown:
+                raise ValueError("Unknown validation checks: {}".format(
+                    ", ".join(sorted(unknown))))
+        self.validate = validate
         self.content_type = content_type
         check_content_type(self.content_type)
-        assert mode is None, "Data object modes not supported yet"
+        check_data_mode(mode)
+        if mode is not None and self.data_type != DataType.BLOB:
+            raise ValueError("Only blob Outputs accept `mode`.")
+        self.mode = mode
         self.encode = encode
         if (self.encode is not None and self.content_type is not None and
            self.content_type != self.encode and self.content_type != ""):
@@ -58,6 +70,10 @@ class OutputBase:
             o.label = proto.label
         if o.path is None:
             o.path = proto.path
+        if o.validate is None:
+            o.validate = proto.validate
+        if o.mode is None:
+            o.mode = proto.mode
         o.content_type = merge_content_types(o.content_type, proto.content_type)
         o.encode = merge_content_types(o.encode, proto.encode)
         return o
@@ -69,6 +85,10 @@ class OutputBase:
                        content_type=self.content_type)
         if self.size_hint is not None:
             d.attributes['size_hint'] = self.size_hint
+        if self.validate is not None:
+            d.attributes['validate'] = self.validate
+        if self.mode is not None:
+            d.set_mode(self.mode)
         return d
 
     @classmethod
@@ -1,6 +1,6 @@
 import shlex
 
-from .tasks import execute
+from .tasks import execute, CLIENT_ARCH
 from .input import Input
 from .output import Output
 
@@ -21,7 +21,17 @@ class Program:
                  stdout=None, stdin=None,
                  input_paths=(), output_paths=(),
                  shell=False,
-                 cpus=1):
+                 cpus=1,
+                 memory=None,
+                 gpus=0,
+                 resources=None,
+                 constraints=None,
+                 tags=None,
+                 spread=None,
+                 colocate=None,
+                 priority=0,
+                 duration=None,
+                 arch=CLIENT_ARCH):
 
         if stdin is not None:
             self.stdin = Input._for_program(stdin, label="stdin")
@@ -36,6 +46,16 @@ class Program:
         self.output_paths = tuple(Output._for_program(obj, label_as_path=True)
                                   for obj in output_paths)
         self.cpus = cpus
+        self.memory = memory
+        self.gpus = gpus
+        self.resources = resources
+        self.constraints = constraints
+        self.tags = tags
+        self.spread = spread
+        self.colocate = colocate
+        self.priority = priority
+        self.duration = duration
+        self.arch = arch
 
         if isinstance(args, str):
             args = shlex.split(args)
@@ -75,4 +95,14 @@ class Program:
                        input_paths=[apply_data(obj) for obj in self.input_paths],
                        output_paths=[obj for obj in self.output_paths],
                        shell=self.shell,
-                       cpus=self.cpus)
+                       cpus=self.cpus,
+                       memory=self.memory,
+                       gpus=self.gpus,
+                       resources=self.resources,
+                       constraints=self.constraints,
+                       tags=self.tags,
+                       spread=self.spread,
+                       colocate=self.colocate,
+                       priority=self.priority,
+                       duration=self.duration,
+                       arch=self.arch)
@@ -64,7 +64,15 @@ def remote(*,
            inputs=(),
            auto_load=None,
            auto_encode=None,
-           cpus=1):
+           cpus=1,
+           gpus=0,
+           resources=None,
+           constraints=None,
+           tags=None,
+           spread=None,
+           colocate=None,
+           priority=0,
+           duration=None):
     "Decorator for :py:class:`Remote`, see the documentation there."
     def make_remote(fn):
         if not inspect.isfunction(fn):
@@ -75,7 +83,15 @@ def remote(*,
                       inputs=inputs,
                       auto_load=auto_load,
                       auto_encode=auto_encode,
-                      cpus=cpus)
+                      cpus=cpus,
+                      gpus=gpus,
+                      resources=resources,
+                      constraints=constraints,
+                      tags=tags,
+                      spread=spread,
+                      colocate=colocate,
+                      priority=priority,
+                      duration=duration)
     return make_remote
 
 
@@ -93,10 +109,26 @@ class Remote:
                  outputs=None,
                  auto_load=False,
                  auto_encode=None,
-                 cpus=1):
+                 cpus=1,
+                 gpus=0,
+                 resources=None,
+                 constraints=None,
+                 tags=None,
+                 spread=None,
+                 colocate=None,
+                 priority=0,
+                 duration=None):
         self.fn = fn
         code = self.fn.__code__
         self.cpus = cpus
+        self.gpus = gpus
+        self.resources = resources
+        self.constraints = constraints
+        self.tags = tags
+        self.spread = spread
+        self.colocate = colocate
+        self.priority = priority
+        self.duration = duration
 
         if 'return' in fn.__annotations__:
             assert outputs is None
@@ -181,4 +213,8 @@ class Remote:
             'encode_outputs': [o.attributes['spec'].get('encode') for o in output_objs]
         }
 
-        return Task("py", task_config, input_objs, output_objs, cpus=self.cpus)
+        return Task("py", task_config, input_objs, output_objs, cpus=self.cpus,
+                    gpus=self.gpus, resources=self.resources,
+                    constraints=self.constraints, tags=self.tags,
+                    spread=self.spread, colocate=self.colocate,
+                    priority=self.priority, duration=self.duration)
@@ -2,3 +2,4 @@ from ..common.fs import load_capnp
 
 common = load_capnp("common.capnp")
 server = load_capnp("server.capnp")
+broker = load_capnp("broker.capnp")
@@ -12,9 +12,14 @@ or function
 get_active_session()
 """
 
+import time
+
 from rain.client import rpc
 from ..common import RainException, ID
+from ..common.units import parse_size
 from . import graph
+from .fingerprint import compute_fingerprints
+from . import tuning
 
 _global_sessions = []
 
@@ -72,11 +77,38 @@ class Session:
         self.client = client
         self.session_id = session_id
 
+        # Sub-sessions (see new_sub_session)
+        self.name = None
+        self.parent = None
+        self._sub_sessions = []
+
         self._tasks = []  # Unsubmitted task
         self._dataobjs = []  # Unsubmitted objects
         self._id_counter = 9
+        # Ids are provisional (negative) until the server assigns them at submit
+        self._server_ids = False
+        self._provisional_counter = 0
         self._submitted_tasks = []
         self._submitted_dataobjs = []
+        # Fingerprint -> the last submitted task with the fingerprint
+        self._fingerprints = {}
+        # Task definition -> list of usage samples of its finished tasks
+        self._usage_samples = {}
+        # Submitted tasks whose usage is not collected yet
+        self._unprofiled_tasks = []
+        # Some submitted tasks are held until the session is flushed
+        self._held = False
+
+        # Session options (see Client._set_session_options)
+        self._max_running_tasks = None
+        self._detach_on_disconnect = False
+        self._keep_failed = False
+        self._expires_at = None
+        self._expired_retention = None
+        self._cost_weight = 0
+        self._sample_rate = 0
+        self._output_retention = None
+        self._output_max_size = None
 
         # Cache for not submited constants: bytes/str -> DataObject
         # It is cleared on submit
@@ -108,18 +140,44 @@ class Session:
         self.close()
 
     def __repr__(self):
+        if self.name:
+            return "<Session session_id={} name={!r}>".format(self.session_id, self.name)
         return "<Session session_id={}>".format(self.session_id)
 
     def close(self):
-        """Closes session; all tasks are stopped, all objects freed."""
+        """Closes session; all tasks are stopped, all objects freed.
+        Sub-sessions are closed too."""
         if self.active and self.client:
             self.client._close_session(self)
+        for sub_session in self._sub_sessions:
+            sub_session._closed_by_parent()
         self._tasks = []
         self._dataobjs = []
         self._submitted_dataobjs = []
         self._submitted_dataobjs = []
         self.active = False
 
+    def _closed_by_parent(self):
+        # Server removes sub-sessions together with the parent
+        self.active = False
+        self.close()
+
+    def new_sub_session(self, name=""):
+        """
+        Creates a named sub-session of this session.
+
+        Tasks of the sub-session may use objects of this session (and of its
+        ancestors) without publishing them. The sub-session has its own
+        lifecycle (it can be closed or may fail independently and has its own
+        options); closing this session closes also all its sub-sessions.
+
+        Returns:
+            :class:`Session`: A new session
+        """
+        session = self.client._new_sub_session(self, name)
+        self._sub_sessions.append(session)
+        return session
+
     def bind_only(self):
         """
         This method serves to bind session without autoclose functionality.
@@ -139,8 +197,7 @@ class Session:
             ID: the assigned id."""
         assert task.session == self and task.id is None
         self._tasks.append(task)
-        self._id_counter += 1
-        return ID(session_id=self.session_id, id=self._id_counter)
+        return self._new_id()
 
     def _register_dataobj(self, dataobj):
         """Register data object into session.
@@ -149,6 +206,12 @@ class Session:
             ID: the assigned id."""
         assert dataobj.session == self and dataobj.id is None
         self._dataobjs.append(dataobj)
+        return self._new_id()
+
+    def _new_id(self):
+        if self._server_ids:
+            self._provisional_counter -= 1
+            return ID(session_id=self.session_id, id=self._provisional_counter)
         self._id_counter += 1
         return ID(session_id=self.session_id, id=self._id_counter)
 
@@ -157,18 +220,151 @@ class Session:
         for dataobj in self._dataobjs:
             dataobj.keep()
 
-    def submit(self):
-        """"Submit all unsubmitted objects."""
-        self.client._submit(self._tasks, self._dataobjs)
+    def submit(self, incremental=False, autotune=False, hold=False):
+        """Submit all unsubmitted objects.
+
+        When `incremental` is True, unsubmitted tasks identical to tasks submitted
+        earlier in the session (see :py:meth:`diff`) are not submitted again.
+        Such a task takes the id of the earlier task and its outputs become
+        the already computed (or still running) objects of the earlier task,
+        including their keep flags. Only new and changed tasks are scheduled.
+
+        When `autotune` is True, requested cpus of unsubmitted tasks are replaced
+        by suggestions of :py:meth:`suggest_resources`.
+
+        When `hold` is True, the server does not schedule the submitted tasks
+        until :py:meth:`flush` is called. A graph built in many small submits
+        (see :py:meth:`submit_stream`) is so scheduled at chosen flush points
+        instead of piece by piece.
+        """
+        if autotune:
+            for task, suggestion in self.suggest_resources().items():
+                task.attributes.setdefault("resources", {})["cpus"] = suggestion["cpus"]
         for task in self._tasks:
+            task._template = tuning.task_template(task)
+        fingerprints = compute_fingerprints(self._tasks)
+        reused = self._find_reusable(fingerprints) if incremental else {}
+        reused_outputs = set()
+        for task, previous in reused.items():
+            task.id = previous.id
+            task.state = previous.state
+            for output, previous_output in zip(task.outputs, previous.outputs):
+                output.id = previous_output.id
+                output.state = previous_output.state
+                output._keep = previous_output._keep
+                previous_output._keep = False
+                reused_outputs.add(id(output))
+
+        tasks = [task for task in self._tasks if task not in reused]
+        dataobjs = [dataobj for dataobj in self._dataobjs if id(dataobj) not in reused_outputs]
+        self.client._submit(tasks, dataobjs, hold)
+        if hold and tasks:
+            self._held = True
+        for task in tasks:
             task.state = rpc.common.TaskState.notAssigned
+        for dataobj in dataobjs:
+            dataobj.state = rpc.common.DataObjectState.unfinished
+        for task in self._tasks:
+            task._fingerprint = fingerprints[id(task)]
+            self._fingerprints[task._fingerprint] = task
             self._submitted_tasks.append(task)
+            if task not in reused:
+                self._unprofiled_tasks.append(task)
         for dataobj in self._dataobjs:
-            dataobj.state = rpc.common.DataObjectState.unfinished
+            dataobj._fingerprint = fingerprints.get(id(dataobj))
             self._submitted_dataobjs.append(dataobj)
         self._tasks = []
         self._dataobjs = []
 
+    def flush(self):
+        """Submit unsubmitted objects and let the server schedule all tasks
+        held by submits with `hold` (a flush point)."""
+        if self._tasks or self._dataobjs:
+            self.submit()
+        if self._held:
+            self.client._flush_session(self)
+            self._held = False
+
+    def submit_stream(self, items, batch_size=100):
+        """Submit tasks created while iterating over `items` (e.g. a generator
+        creating tasks in the session) in held batches of `batch_size` tasks.
+        The session is flushed when the iteration ends; the generator may call
+        :py:meth:`flush` itself to mark earlier flush points."""
+        for _ in items:
+            if len(self._tasks) >= batch_size:
+                self.submit(hold=True)
+        self.flush()
+
+    def diff(self):
+        """Compare unsubmitted tasks with tasks submitted earlier in the session
+        by their fingerprints (task type, attributes, outputs and recursively inputs).
+
+        An unsubmitted task is reused by an incremental submit when an earlier
+        task has the same fingerprint and each its output is kept or not needed
+        (it is neither kept nor used by unsubmitted tasks).
+
+        Returns:
+            `(changed, reused)`: Lists of unsubmitted tasks that are submitted
+            and that are reused by an incremental submit."""
+        reused = self._find_reusable(compute_fingerprints(self._tasks))
+        return ([task for task in self._tasks if task not in reused],
+                [task for task in self._tasks if task in reused])
+
+    def suggest_resources(self):
+        """Suggest resource requests of unsubmitted tasks from resource usage
+        (attribute "profile") of finished tasks of the session created from the same
+        definition (the same task type and attributes except resources, and the same
+        labels of inputs and outputs, regardless of input data).
+
+        A suggestion is made after at least `tuning.MIN_SAMPLES` tasks of the definition
+        finished. Suggested "cpus" cover the CPU utilization of most of the tasks,
+        "memory" (in bytes) is the maximal observed memory with a margin; it is
+        advisory, the scheduler does not account memory.
+
+        Returns:
+            dict: unsubmitted task -> ``{"cpus": ..., "memory": ...}`` for tasks
+            whose requested cpus differ from the suggestion"""
+        self._collect_usage()
+        suggestions = {}
+        for task in self._tasks:
+            samples = self._usage_samples.get(tuning.task_template(task), ())
+            cpus = task.attributes.get("resources", {}).get("cpus", 0)
+            suggestion = tuning.suggest(samples, cpus)
+            if suggestion is not None and suggestion["cpus"] != cpus:
+                suggestions[task] = suggestion
+        return suggestions
+
+    def _collect_usage(self):
+        """Update submitted tasks and store usage of the newly finished ones"""
+        if not self._unprofiled_tasks:
+            return
+        self.client.update(self._unprofiled_tasks)
+        unprofiled = []
+        for task in self._unprofiled_tasks:
+            if task.state == rpc.common.TaskState.finished:
+                sample = tuning.usage_sample(task)
+                if sample is not None:
+                    self._usage_samples.setdefault(task._template, []).append(sample)
+            elif task.state != rpc.common.TaskState.failed:
+                unprofiled.append(task)
+        self._unprofiled_tasks = unprofiled
+
+    def _find_reusable(self, fingerprints):
+        """Returns a dictionary: unsubmitted task -> earlier task with the same fingerprint"""
+        needed = set(id(dataobj) for task in self._tasks for dataobj in task.inputs)
+        reused = {}
+        used = set()
+        for task in self._tasks:
+            previous = self._fingerprints.get(fingerprints[id(task)])
+            if previous is None or id(previous) in used:
+                continue
+            if all(previous_output.is_kept() or
+                   not (output.is_kept() or id(output) in needed)
+                   for output, previous_output in zip(task.outputs, previous.outputs)):
+                reused[task] = previous
+                used.add(id(previous))
+        return reused
+
     def _split_tasks_objects(self, items):
         """Split `items` into `Task`s and `DataObject`s, raisong error on anything else.
 
@@ -185,8 +381,15 @@ class Session:
                 raise TypeError("Neither Task or DataObject: {!r}".format(i))
         return (tasks, dataobjs)
 
+    def _flush_held(self):
+        # Held tasks would never finish
+        if self._held:
+            self.client._flush_session(self)
+            self._held = False
+
     def wait(self, items):
         """Wait until *all* specified tasks and dataobjects are finished."""
+        self._flush_held()
         tasks, dataobjs = self._split_tasks_objects(items)
         self.client._wait(tasks, dataobjs)
 
@@ -201,6 +404,7 @@ class Session:
 
         Returns:
             `(finished_tasks, finished_dataobjs)`"""
+        self._flush_held()
         tasks, dataobjs = self._split_tasks_objects(items)
         finished_tasks, finished_dataobjs = self.client._wait_some(
             tasks, dataobjs)
@@ -215,6 +419,7 @@ class Session:
 
     def wait_all(self):
         """Wait until all submitted tasks and objects are finished."""
+        self._flush_held()
         self.client._wait_all(self)
 
         for task in self._submitted_tasks:
@@ -223,13 +428,46 @@ class Session:
         for dataobj in self._submitted_dataobjs:
             dataobj.state = rpc.common.DataObjectState.finished
 
-    def fetch(self, dataobject):
+    def group_status(self, name):
+        """Returns aggregated state of submitted tasks of the group
+        (tasks created with ``group=name``) as a dictionary with keys
+        "total", "finished", "running" and "failed" (list of ids of failed tasks)."""
+        return self.client._get_group_status(self, name)
+
+    def stats(self):
+        """Returns summary statistics of the session maintained by the server,
+        polling them is cheap even for sessions with many tasks.
+
+        Returns:
+            dict: Numbers of tasks by states (keys "waiting", "ready", "running",
+            "finished" and "failed"), "cpu_time" (seconds of CPU time of
+            finished and failed tasks), "produced_bytes", "transferred_bytes"
+            (bytes copied between workers), "failures" (numbers of failed
+            tasks by kinds of errors), "sampled" (sampled tasks compared with
+            their copies) and "sample_mismatches" (ids of sampled tasks whose
+            copies differ, see :py:meth:`set_sample_rate`)."""
+        return self.client._get_session_stats(self)
+
+    def wait_group(self, name):
+        """Wait until all submitted tasks of the group are finished."""
+        self.client._wait_group(self, name)
+
+    def fetch(self, dataobject, offset=0, length=None):
         """Wait for the object to finish, update its state and
-        fetch the object data.
+        fetch the object data. When `offset` or `length` is given, only the
+        byte range of the data is fetched (e.g. to preview a huge object or
+        to resume an interrupted download).
 
         Returns:
             `DataInstance`: The object data proxy."""
-        return self.client._fetch(dataobject)
+        return self.client._fetch(dataobject, offset, length)
+
+    def get_object_info(self, dataobject, checksum=False):
+        """Wait for the object to finish and return its metadata without fetching
+        the data: a dictionary with keys "size", "data_type", "content_type" and
+        "checksum" ("crc32:..." of the data, only when `checksum` is True;
+        it requires the worker to read all the data)."""
+        return self.client._get_object_info(dataobject, checksum)
 
     def unkeep(self, dataobjects):
         """Unset keep flag for given objects."""
@@ -253,10 +491,202 @@ class Session:
         for dataobj in submitted:
             dataobj._free()
 
+    def invalidate(self, dataobjects, reason=""):
+        """Invalidate finished objects that were found corrupt (e.g. their checksum
+        does not match). Their producers and all finished tasks that depend on them
+        are computed again, unfinished tasks using them wait for the new data.
+        The server refuses the invalidation when too many tasks would be computed
+        again (see ``--invalidation-limit``).
+        Returns ids of tasks that are computed again."""
+        from . import DataObject
+        for dataobj in dataobjects:
+            if not isinstance(dataobj, DataObject):
+                raise TypeError("Not a DataObject: {!r}".format(dataobj))
+            if dataobj.state is None:
+                raise RainException("Object {} is not submitted".format(dataobj.id))
+        return self.client._invalidate(dataobjects, reason)
+
     def update(self, items):
         """Update the status and metadata of given tasks and objects."""
         self.client.update(items)
 
+    def list_kept(self, label_prefix=None, min_size=0, min_age=0, tags=None):
+        """Returns a list of kept objects of the session on the server.
+        Objects may be filtered by label prefix, minimal size (in bytes),
+        minimal age (in seconds) and tags of their producers (e.g.
+        ``{"stage": "qc"}``, all of them are required). Every item is
+        a dictionary with keys "id", "label", "state", "size", "placement"
+        and "age"."""
+        return self.client._list_kept(self, label_prefix, min_size, min_age, tags)
+
+    def unkeep_matching(self, label_prefix=None, min_size=0, min_age=0, tags=None):
+        """Unset keep flag for all kept objects that match the filter
+        (see :py:meth:`list_kept`). Returns the number of affected objects."""
+        ids = set(self.client._unkeep_matching(
+            self, label_prefix, min_size, min_age, tags))
+        for dataobj in self._submitted_dataobjs:
+            if dataobj.id in ids:
+                dataobj._free()
+        return len(ids)
+
+    def fetch_kept_archive(self, label_prefix=None, min_size=0, min_age=0, tags=None):
+        """Fetch all kept objects that match the filter (see :py:meth:`list_kept`)
+        as one directory: the server packs them into files ``"<id>-<label>"``
+        (``/`` in labels is replaced by ``_``), unfinished objects are waited for.
+        The objects stay kept.
+
+        Returns:
+            `DataInstance`: The directory, e.g. ``write(path)`` extracts it."""
+        if self._server_ids:
+            min_id = 0
+        else:
+            min_id = self._id_counter + 1
+        dataobj, count = self.client._archive_kept(
+            self, label_prefix, min_size, min_age, tags, min_id)
+        if not self._server_ids:
+            # The server used ids of the packing task and of the archive
+            self._id_counter = max(self._id_counter, dataobj.id.id)
+        try:
+            return self.client._fetch(dataobj)
+        finally:
+            self.client._unkeep((dataobj,))
+
+    def cancel_tasks(self, tags):
+        """Cancel unfinished submitted tasks that have all the given tags
+        (e.g. ``{"stage": "qc"}``) together with all tasks that depend on their
+        outputs. Running tasks are stopped; the tasks and their outputs (also kept
+        ones) are removed from the server. Returns ids of the cancelled tasks."""
+        if not tags:
+            raise RainException("No tags of cancelled tasks were given")
+        task_ids = self.client._cancel_tasks(self, tags)
+        ids = set(task_ids)
+        removed = set()
+        for task in self._submitted_tasks:
+            if task.id in ids:
+                for dataobj in task.outputs:
+                    dataobj._free()
+                    removed.add(dataobj.id)
+        self._submitted_tasks = [t for t in self._submitted_tasks if t.id not in ids]
+        self._submitted_dataobjs = [o for o in self._submitted_dataobjs
+                                    if o.id not in removed]
+        return task_ids
+
+    def kept_size(self):
+        """Returns a pair (number of kept objects, total size of kept
+        objects in bytes)."""
+        return self.client._kept_size(self)
+
+    def set_max_running_tasks(self, limit):
+        """Set max number of tasks of the session that run at once
+        (e.g. when tasks use a rate-limited external service).
+        Ready tasks over the limit are held back by the scheduler.
+        `None` removes the limit."""
+        if limit is not None and limit < 1:
+            raise RainException("Limit has to be a positive number or None")
+        self._max_running_tasks = limit
+        self.client._set_session_options(self)
+
+    def set_detach_on_disconnect(self, value=True):
+        """When set, the session is not cancelled when the client disconnects.
+        It keeps running until all its tasks are finished and then it is removed
+        from the server."""
+        self._detach_on_disconnect = value
+        self.client._set_session_options(self)
+
+    def set_keep_failed(self, value=True):
+        """When set, a failed task does not remove tasks and objects of the
+        session from the server. The session fails as usual (waiting raises
+        the error), but its unfinished tasks are only stopped and the failed
+        tasks may be computed again by :py:meth:`retry_failed`."""
+        self._keep_failed = value
+        self.client._set_session_options(self)
+
+    def retry_failed(self, tags=None):
+        """Compute again failed tasks of the session that failed with
+        :py:meth:`set_keep_failed` (only tasks with all the given tags, e.g.
+        ``{"stage": "qc"}``, when `tags` are given). Finished objects of the
+        session are reused. When no failed task remains, the session continues
+        and its stopped tasks are scheduled again. Returns ids of retried tasks."""
+        return self.client._retry_failed(self, tags)
+
+    def set_ttl(self, ttl, retention=None):
+        """Set time-to-live of the session in seconds (counted from now).
+        When it is exceeded, the server cancels remaining tasks and removes
+        all objects of the session (including kept ones); the session fails
+        with "Session expired" error. `None` removes the limit.
+        When `retention` (in seconds) is given, finished objects are removed
+        only after the retention, kept objects may be fetched meanwhile."""
+        if ttl is not None and ttl <= 0:
+            raise RainException("TTL has to be a positive number or None")
+        if retention is not None and retention <= 0:
+            raise RainException("Retention has to be a positive number or None")
+        self._expires_at = time.time() + ttl if ttl is not None else None
+        self._expired_retention = retention
+        self.client._set_session_options(self)
+
+    def set_cost_weight(self, weight):
+        """Set how much the scheduler prefers cheaper workers (workers announce
+        their cost per cpu by `--cost`, e.g. cloud spot price) for tasks
+        of the session. The weight is the amount of transferred bytes the session
+        accepts to save a unit of cost; 0 (default) means that only speed matters."""
+        if weight < 0:
+            raise RainException("Cost weight cannot be negative")
+        self._cost_weight = weight
+        self.client._set_session_options(self)
+
+    def set_sample_rate(self, rate):
+        """Run a random part of submitted tasks (`rate` from 0 to 1, e.g. 0.05
+        for 5 %) again on another worker and compare checksums of outputs of both
+        runs, to find tasks that are nondeterministic or depend on their worker.
+        The result is in attribute "sample" of a sampled task and in "sampled" and
+        "sample_mismatches" of :py:meth:`stats`. Copies of tasks get ids from the
+        server, so the session has to be created with ``server_ids=True``.
+        0 (default) disables sampling."""
+        if not 0 <= rate <= 1:
+            raise RainException("Sample rate has to be between 0 and 1")
+        if rate > 0 and not self._server_ids:
+            raise RainException("Sampling needs a session with server_ids=True")
+        self._sample_rate = rate
+        self.client._set_session_options(self)
+
+    def set_output_policy(self, retention, max_size=None):
+        """Set what happens with stdout and stderr of programs of the session
+        that are not outputs of their tasks. Retention is one of:
+
+        * ``"discard"`` (default): removed when the task finishes
+        * ``"keep_on_failure"``: kept in the log directory of the worker
+          when the task fails, the paths are in task attribute ``failure``
+        * ``"keep_as_object"``: stored as outputs ``"stdout"`` and ``"stderr"``
+          of tasks created by :func:`tasks.execute` after this call
+
+        ``max_size`` caps the bytes kept of each stream (its beginning and
+        end are kept), workers may have a stricter cap (``--max-task-output``).
+        It is in bytes or a string with a unit, e.g. ``"10MiB"``.
+        """
+        if retention not in ("discard", "keep_on_failure", "keep_as_object"):
+            raise RainException("Invalid output retention {!r}".format(retention))
+        if max_size is not None:
+            max_size = parse_size(max_size, "max_size")
+        if max_size is not None and max_size <= 0:
+            raise RainException("Max size of output has to be positive")
+        self._output_retention = retention
+        self._output_max_size = max_size
+        self.client._set_session_options(self)
+
+    def publish(self, dataobj, name):
+        """Publish a submitted kept object under the global name.
+        Names are namespaced by the identity of the client (see access tokens)."""
+        self.client._publish(dataobj, name)
+
+    def unpublish(self, name):
+        """Withdraw the published name."""
+        self.client._unpublish(name)
+
+    def published(self, name):
+        """Returns an object published under the given name (possibly
+        by another session). It can be used as an input of tasks."""
+        return self.client._get_published(self, name)
+
     def make_graph(self, show_ids=True):
         """Create a graph of tasks and objects that were *not yet* submitted."""
 
@@ -3,6 +3,7 @@ from .data import DataObject, to_data
 from .output import OutputBase
 from ..common import RainException, ID, LabeledList, ids
 from ..common.attributes import attributes_to_capnp
+from ..common.units import parse_duration, parse_size
 
 import traceback
 
@@ -33,6 +34,59 @@ class Task:
         session (`Session` or `None`): Session to create the task in.
             If not specified, the current `Session` is used.
         cpus (`int`): Number of cpus.
+        memory (`int`, `str` or `None`): Memory in bytes reserved for the task on its
+            worker, a string may have a unit (e.g. ``"512M"`` or ``"2GiB"``).
+        gpus (`int`): Number of GPUs. Only the allocated GPUs are visible to the task
+            (``CUDA_VISIBLE_DEVICES``).
+        resources (`dict` or `None`): Named resources declared by workers
+            (``--resource``) required by the task, e.g. ``{"fpga": 1}``.
+        global_resources (`dict` or `None`): Cluster-wide resources required by the task,
+            e.g. ``{"license:gurobi": 1}``. They have to be defined on the server
+            (``--global-resource``).
+        constraints (sequence of `str` or `None`): Placement constraints on labels of
+            workers (``--label``): ``"key=value"``, ``"key!=value"``, ``"key"`` (the label
+            is present) or ``"!key"`` (the label is absent), e.g. ``["rack=a"]``.
+        arch (`str` or `None`): CPU architecture of workers the task may run on,
+            e.g. ``"x86_64"`` or ``"aarch64"``.
+        tags (`dict` or `None`): Tags of the task, e.g. ``{"stage": "qc"}``, they
+            are added to tags of the session; tasks are searched by tags in JSON API.
+        spread (`str` or `None`): Name of a spread group in the session, tasks of the
+            group do not run on the same worker at the same time.
+        colocate (`str` or `None`): Name of a colocation group in the session, tasks
+            of the group run on one worker.
+        group (`str` or `None`): Name of a task group (e.g. a job array) in the session,
+            see :py:meth:`Session.group_status`.
+        limits (`dict` or `None`): Limits of spawned processes, e.g.
+            ``{"nofile": 256, "nproc": 64}`` (max. open file descriptors and processes).
+            Programs of :py:func:`rain.client.tasks.Execute` may also have limit
+            ``"cpu"`` (CPU time in seconds); a program killed for exceeding it fails
+            with error kind ``limit_exceeded``. Exceeding ``"nofile"`` or ``"nproc"``
+            is not detected, the process gets errors from the system (e.g. "Too many
+            open files") and the task fails with its own error. Unknown limits are
+            errors.
+        preemptible (`bool`): The task tolerates eviction of its worker (it is
+            retryable or checkpointed), so it is preferably placed on preemptible
+            workers (e.g. spot instances).
+        priority (`int`): Tasks with higher priority are scheduled first; when
+            there are not enough free resources for a ready task, running tasks
+            with lower priority are stopped and computed again later. Default is 0.
+        duration (`float`, `str` or `None`): Expected duration of the task in seconds
+            or a string with units (e.g. ``"2h30m"``).
+            Tasks on long chains of expected durations (and of transfers of
+            their outputs) are scheduled first, so the whole graph finishes
+            sooner.
+        timeout (`float`, `str` or `None`): The task fails when it runs longer
+            than this (seconds or a string with units, e.g. ``"1h30m"``).
+        max_retries (`int`): A failed run of the task is retried up to this many
+            times, preferably on other workers; the session fails only when the
+            retries are exhausted. Default is 0.
+        retry_delay (`float`, `str` or `None`): Delay before the first retry
+            (default 1 second), it doubles with each retry.
+        env (`dict` or `None`): Environment variables of the task, they override
+            environment variables of the session.
+        secrets (`dict` or `None`): Name of environment variable -> name of secret
+            (see :py:meth:`Client.set_secret`). The variables are set to values
+            of the secrets while the task runs.
 
     Attributes:
         id (`ID`): Auto-assigned task ID.
@@ -49,6 +103,10 @@ class Task:
     id = None
     config = None
     stack = None
+    # Fingerprint of the submitted task (see Session.submit)
+    _fingerprint = None
+    # Fingerprint of the definition of the task (see Session.suggest_resources)
+    _template = None
 
     def __init__(self,
                  task_type,
@@ -56,7 +114,26 @@ class Task:
                  inputs=(),
                  outputs=None,
                  session=None,
-                 cpus=1):
+                 cpus=1,
+                 memory=None,
+                 gpus=0,
+                 resources=None,
+                 global_resources=None,
+                 constraints=None,
+                 arch=None,
+                 tags=None,
+                 spread=None,
+                 colocate=None,
+                 group=None,
+                 limits=None,
+                 preemptible=False,
+                 priority=0,
+                 duration=None,
+                 timeout=None,
+                 max_retries=0,
+                 retry_delay=None,
+                 env=None,
+                 secrets=None):
         if session is None:
             session = get_active_session()
         self.session = session
@@ -72,6 +149,67 @@ class Task:
         if cpus is not None:
             self.attributes["resources"] = {"cpus": cpus}
 
+        if memory is not None:
+            self.attributes.setdefault("resources", {"cpus": 0})
+            self.attributes["resources"]["memory"] = parse_size(memory, "memory")
+
+        if gpus:
+            self.attributes.setdefault("resources", {"cpus": 0})
+            self.attributes["resources"]["gpus"] = int(gpus)
+
+        if resources:
+            self.attributes.setdefault("resources", {"cpus": 0})
+            self.attributes["resources"]["named"] = resources
+
+        if global_resources:
+            self.attributes.setdefault("resources", {"cpus": 0})
+            self.attributes["resources"]["global"] = global_resources
+
+        if constraints:
+            self.attributes["constraints"] = list(constraints)
+
+        if arch is not None:
+            self.attributes["arch"] = str(arch)
+
+        if tags:
+            self.attributes["tags"] = {str(key): str(value) for key, value in tags.items()}
+
+        if spread is not None:
+            self.attributes["spread"] = spread
+
+        if colocate is not None:
+            self.attributes["colocate"] = colocate
+
+        if group is not None:
+            self.attributes["group"] = group
+
+        if limits is not None:
+            self.attributes["limits"] = limits
+
+        if preemptible:
+            self.attributes["preemptible"] = True
+
+        if priority:
+            self.attributes["priority"] = int(priority)
+
+        if duration is not None:
+            self.attributes["duration"] = parse_duration(duration, "duration")
+
+        if timeout is not None:
+            self.attributes["timeout"] = parse_duration(timeout, "timeout")
+
+        if max_retries:
+            self.attributes["max_retries"] = int(max_retries)
+
+        if retry_delay is not None:
+            self.attributes["retry_delay"] = parse_duration(retry_delay, "retry_delay")
+
+        if env:
+            self.attributes["env"] = {name: str(value) for name, value in env.items()}
+
+        if secrets:
+            self.attributes["secrets"] = dict(secrets)
+
         def to_data_object(o):
             if isinstance(o, int):
                 o = "out{}".format(o)
@@ -3,32 +3,92 @@ from .data import to_data
 from .input import Input, InputBase
 from .output import Output, OutputBase, OutputDir
 from .data import DataObject
+from .session import get_active_session
 
+from ..common import RainException
+from ..common.units import parse_duration, parse_size
+
+import platform
 import shlex
 
+# Max size of the source of an embedded script (see `script`)
+MAX_SCRIPT_SIZE = 64 * 1024
+
+# Names of architectures as reported by workers
+_ARCH_ALIASES = {"amd64": "x86_64", "x64": "x86_64", "arm64": "aarch64",
+                 "i386": "x86", "i686": "x86"}
+
+# Architecture of the client, the default architecture of programs (see `execute`)
+CLIENT_ARCH = _ARCH_ALIASES.get(platform.machine().lower(),
+                                platform.machine().lower()) or None
+
 
 def concat(objs):
     """Creates a task concatenating data objects"""
     return Task("!concat", inputs=tuple(objs), outputs=1)
 
 
-def sleep(timeout, dataobj, cpus=1):
-    """Task that forwards argument 'dataobj' after 'timeout' seconds.
+def identity(dataobj, label="output"):
+    """Creates a task whose output is `dataobj` under a new id and label.
+    Data are not copied, the task uses no cpus."""
+    dataobj = to_data(dataobj)
+    return Task("!identity",
+                inputs=(dataobj,),
+                outputs=(dataobj.__class__(label),),
+                cpus=0)
+
+
+def select(dataobjs, index, label="output"):
+    """Creates a task whose output is `index`-th of `dataobjs` (of the same type).
+    Data are not copied, the task uses no cpus."""
+    dataobjs = [to_data(o) for o in dataobjs]
+    if not 0 <= index < len(dataobjs):
+        raise RainException("Index {} is out of range ({} inputs)"
+                            .format(index, len(dataobjs)))
+    return Task("!select",
+                {"index": index},
+                inputs=dataobjs,
+                outputs=(dataobjs[0].__class__(label),),
+                cpus=0)
+
+
+def sleep(timeout, dataobj, cpus=1, memory=None, resources=None, constraints=None,
+          tags=None, spread=None, colocate=None, priority=0, duration=None):
+    """Task that forwards argument 'dataobj' after 'timeout' seconds
+    (or a string with units, e.g. "500ms").
     The type of resulting data object is the same as type of input data object
     This task serves for testing purpose"""
-    time_ms = int(timeout * 1000)
+    time_ms = int(parse_duration(timeout, "timeout") * 1000)
     dataobj = to_data(dataobj)
     return Task("!sleep",
                 time_ms,
                 inputs=(dataobj,),
                 outputs=(dataobj.__class__("output"),),
-                cpus=cpus)
+                cpus=cpus,
+                memory=memory,
+                resources=resources,
+                constraints=constraints,
+                tags=tags,
+                spread=spread,
+                colocate=colocate,
+                priority=priority,
+                duration=duration)
 
 
 def open(filename):
     return Task("!open", {"path": filename}, outputs=1)
 
 
+def download(url, label="output"):
+    """Creates a task downloading a file from http(s) `url`. Workers cache
+    downloaded files and download them again only when the server reports
+    a change (by ETag or Last-Modified), see ``rain worker --download-cache``.
+    The task attribute "download" says whether the cached file was used."""
+    if not url.startswith(("http://", "https://")):
+        raise RainException("Only http and https URLs can be downloaded: {}".format(url))
+    return Task("!download", {"url": url}, outputs=(Output(label),))
+
+
 def export(dataobj, filename):
     return Task("!export", {"path": filename}, inputs=(dataobj,))
 
@@ -50,13 +110,70 @@ def slice_directory(dataobj, path, content_type=None):
                 outputs=(c("output", content_type=content_type),))
 
 
+def csv_to_parquet(dataobj, delimiter=",", has_header=True, compression=None):
+    """Creates a task converting CSV blob into Parquet blob.
+    Types of columns are inferred from the beginning of CSV.
+    Compression may be "snappy" (default), "gzip", "zstd" or "none"."""
+    config = {"delimiter": delimiter, "has_header": has_header}
+    if compression is not None:
+        config["compression"] = compression
+    return Task("!csv_to_parquet", config,
+                inputs=(to_data(dataobj),),
+                outputs=(Output("output", content_type="parquet"),))
+
+
+def parquet_select(dataobj, columns=None, limit=None, compression=None):
+    """Creates a task selecting columns (all if `None`) and at most `limit`
+    first rows of Parquet blob. The result is Parquet blob."""
+    config = {}
+    if columns is not None:
+        config["columns"] = list(columns)
+    if limit is not None:
+        config["limit"] = limit
+    if compression is not None:
+        config["compression"] = compression
+    return Task("!parquet_select", config,
+                inputs=(to_data(dataobj),),
+                outputs=(Output("output", content_type="parquet"),))
+
+
+def jmespath(dataobj, expression):
+    """Creates a task evaluating JMESPath expression (http://jmespath.org)
+    over JSON data object. The task runs directly in the worker, so it
+    is cheap glue between other tasks. The result is JSON data object."""
+    return Task("!jmespath", {"expression": expression},
+                inputs=(to_data(dataobj),),
+                outputs=(Output("output", content_type="json"),))
+
+
 def execute(args,
             stdout=None,
             stdin=None,
             input_paths=(),
             output_paths=(),
             shell=False,
-            cpus=1):
+            cpus=1,
+            memory=None,
+            gpus=0,
+            resources=None,
+            constraints=None,
+            tags=None,
+            spread=None,
+            colocate=None,
+            priority=0,
+            duration=None,
+            timeout=None,
+            max_retries=0,
+            retry_delay=None,
+            env=None,
+            secrets=None,
+            script=None,
+            tmpfs=None,
+            limits=None,
+            arch=CLIENT_ARCH):
+    """Creates a task running a program (see the user guide). The task runs only
+    on workers of architecture `arch`, by default the architecture of the client;
+    pass ``arch=None`` for programs that run anywhere (e.g. scripts)."""
 
     ins = []
     outs = []
@@ -91,7 +208,11 @@ def execute(args,
         elif isinstance(a, InputBase) or isinstance(a, DataObject) or isinstance(a, Task):
             arg = Input._for_program(a, execute=True, label=argname)
             ins.append(arg)
-            proc_args.append(arg.path)
+            if i == 0 and "/" not in arg.path:
+                # The program itself is a data object (e.g. with mode "executable")
+                proc_args.append("./" + arg.path)
+            else:
+                proc_args.append(arg.path)
         elif isinstance(a, OutputBase):
             arg = OutputBase._for_program(a, execute=True, label=argname)
             outs.append(arg)
@@ -100,17 +221,85 @@ def execute(args,
             raise Exception("Argument {!r} is invalid".format(arg))
 
     if shell:
+        if script is not None:
+            raise RainException("Embedded script cannot be run by shell")
         proc_args = ("/bin/sh", "-c", " ".join(proc_args))
 #        proc_args = ("/bin/sh", "-c", " ".join(shlex.quote(a) for a in proc_args))
 
+    # Session keeps stdout/stderr as objects, see Session.set_output_policy
+    log_outputs = []
+    if get_active_session()._output_retention == "keep_as_object":
+        for label, path in (("stdout", "+out"), ("stderr", "+err")):
+            if not any(obj.path == path for obj in outs):
+                output = OutputBase._for_program(label, label=label, execute=True)
+                output.path = path
+                outs.append(output)
+                log_outputs.append(path)
+
     task_inputs = [obj.dataobj for obj in ins]
     task_outputs = [output.create_data_object() for output in outs]
-    return Task("!run",
-                {
-                    "args": proc_args,
-                    "in_paths": [{"path": obj.path, "write": obj.write} for obj in ins],
-                    "out_paths": [obj.path for obj in outs],
-                },
+    config = {
+        "args": proc_args,
+        "in_paths": [{"path": obj.path, "write": obj.write} for obj in ins],
+        "out_paths": [obj.path for obj in outs],
+    }
+    if script is not None:
+        config["script"] = script
+    if log_outputs:
+        config["log_outputs"] = log_outputs
+    task = Task("!run",
+                config,
                 inputs=task_inputs,
                 outputs=task_outputs,
-                cpus=cpus)
+                cpus=cpus,
+                memory=memory,
+                gpus=gpus,
+                resources=resources,
+                constraints=constraints,
+                arch=arch,
+                tags=tags,
+                spread=spread,
+                colocate=colocate,
+                priority=priority,
+                duration=duration,
+                timeout=timeout,
+                max_retries=max_retries,
+                retry_delay=retry_delay,
+                env=env,
+                secrets=secrets,
+                limits=limits)
+    if tmpfs is not None:
+        # Size of tmpfs (in bytes or with a unit) for the working directory,
+        # see --task-tmpfs of worker
+        task.attributes["tmpfs"] = parse_size(tmpfs, "tmpfs")
+    return task
+
+
+def script(source, args=(), language="sh", **kwargs):
+    """Creates a task running a small script embedded in the task config,
+    so one-off glue steps do not need script files distributed as data objects.
+    The worker writes `source` into file "+script" in the working directory
+    of the task and runs it by the interpreter of `language` ("sh", "bash"
+    or "python"). Arguments `args` of the script and other keyword arguments
+    (`stdout`, `stdin`, `input_paths`, ...) are the same as in :func:`execute`;
+    scripts are not restricted to the architecture of the client."""
+    if language not in ("sh", "bash", "python"):
+        raise RainException("Unsupported language of script {!r}".format(language))
+    if len(source.encode()) > MAX_SCRIPT_SIZE:
+        raise RainException("Embedded script is larger than {} bytes, "
+                            "pass it as a data object".format(MAX_SCRIPT_SIZE))
+    kwargs.setdefault("arch", None)
+    return execute(args, script={"language": language, "source": source}, **kwargs)
+
+
+def bridge_script(subworker_type, source, inputs=(), outputs=1, **kwargs):
+    """Creates a task running `source` in a subworker started by
+    ``rain.subworker.bridge`` (e.g. R or Julia scripts, see worker option
+    ``--subworkers``). Inputs are files named by their labels in the working
+    directory of the script, the script writes each output into a file named
+    by its label. Other keyword arguments (`cpus`, `env`, ...) are passed to `Task`."""
+    if len(source.encode()) > MAX_SCRIPT_SIZE:
+        raise RainException("Embedded script is larger than {} bytes, "
+                            "pass it as a data object".format(MAX_SCRIPT_SIZE))
+    return Task(subworker_type, {"source": source},
+                inputs=inputs, outputs=outputs, **kwargs)
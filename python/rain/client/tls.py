@@ -0,0 +1,42 @@
+import selectors
+import socket
+import ssl
+import threading
+
+BUFFER_SIZE = 64 * 1024
+
+
+def _relay(local, remote):
+    selector = selectors.DefaultSelector()
+    selector.register(local, selectors.EVENT_READ, remote)
+    selector.register(remote, selectors.EVENT_READ, local)
+    try:
+        while True:
+            for key, _ in selector.select():
+                # TLS socket may have buffered decrypted data, so read it all
+                data = key.fileobj.recv(BUFFER_SIZE)
+                if not data:
+                    return
+                key.data.sendall(data)
+                while isinstance(key.fileobj, ssl.SSLSocket) and key.fileobj.pending():
+                    key.data.sendall(key.fileobj.recv(BUFFER_SIZE))
+    except OSError:
+        pass
+    finally:
+        selector.close()
+        local.close()
+        remote.close()
+
+
+def connect_tls(address, port, ca_file, server_name=None):
+    """Open TLS connection to the server and return a local socket
+    whose traffic is relayed through it (pycapnp works only with plain sockets).
+
+    The relay runs in a daemon thread and ends when any side closes."""
+    context = ssl.create_default_context(cafile=ca_file)
+    remote = context.wrap_socket(socket.create_connection((address, port)),
+                                 server_hostname=server_name or address)
+    local, relayed = socket.socketpair()
+    thread = threading.Thread(target=_relay, args=(relayed, remote), daemon=True)
+    thread.start()
+    return local
@@ -0,0 +1,56 @@
+"""
+Suggestions of resource requests of tasks based on resource usage of finished
+tasks (attributes "info" and "profile") created from the same definition
+(see :py:meth:`Session.suggest_resources`).
+"""
+
+import math
+
+from .fingerprint import IGNORED_TASK_ATTRIBUTES, _digest, _relevant
+
+# Number of finished tasks of a definition needed before anything is suggested
+MIN_SAMPLES = 3
+# Suggested memory is the maximal observed RSS with a margin
+MEMORY_HEADROOM = 1.25
+# Fraction of samples whose CPU utilization has to be covered by suggested cpus
+CPU_PERCENTILE = 0.9
+
+
+def task_template(task):
+    """Fingerprint of the definition of a task: task type, attributes (except
+    resources) and labels of inputs and outputs, but not the input data."""
+    ignored = IGNORED_TASK_ATTRIBUTES + ("resources",)
+    return _digest([task.task_type,
+                    _relevant(task.attributes, ignored),
+                    [label for label, _ in task.inputs.items()],
+                    [output.label for output in task.outputs]])
+
+
+def usage_sample(task):
+    """Pair (CPU utilization, max RSS) of a finished task,
+    None when the task was not profiled"""
+    profile = task.attributes.get("profile")
+    info = task.attributes.get("info")
+    if not profile or not info:
+        return None
+    duration = max(int(info["duration"]), 1)
+    return (profile["cpu_time"] / duration, profile["max_rss"])
+
+
+def suggest(samples, cpus):
+    """Suggested resources for samples of a definition and current request `cpus`.
+
+    Returns:
+        dict with keys "cpus" and "memory" (bytes) or None when there are not enough samples"""
+    if len(samples) < MIN_SAMPLES:
+        return None
+    utilizations = sorted(utilization for utilization, _ in samples)
+    utilization = utilizations[min(int(len(utilizations) * CPU_PERCENTILE),
+                                   len(utilizations) - 1)]
+    # Rounding tolerates small overhead of the process over whole cpus
+    suggested_cpus = max(1, int(math.ceil(round(utilization, 1))))
+    if cpus == 0:
+        # Tasks not requesting cpus (e.g. waiting for external services) are kept so
+        suggested_cpus = 0
+    memory = int(max(max_rss for _, max_rss in samples) * MEMORY_HEADROOM)
+    return {"cpus": suggested_cpus, "memory": memory}
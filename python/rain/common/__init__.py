@@ -2,5 +2,6 @@ from .labeled_list import LabeledList  # noqa
 from .data_instance import DataInstance  # noqa
 from .errors import RainException, RainWarning  # noqa
 from .errors import SessionException, TaskException  # noqa
+from .errors import IdCollisionException  # noqa
 from .ids import ID  # noqa
 from .datatype import DataType  # noqa
\ No newline at end of file
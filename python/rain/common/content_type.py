@@ -5,7 +5,7 @@ import pickle
 
 def check_content_type(name):
     if name in [None, "pickle", "json", "dir", "text", "cbor",
-                "protobuf", "cloudpickle"]:
+                "protobuf", "cloudpickle", "csv", "parquet"]:
         return True
     if (name.startswith("text:") or
        name.startswith("user:") or
@@ -22,5 +22,26 @@ class SessionException(RainException):
 class TaskException(SessionException):
     """
     Task failure
+
+    Attributes:
+        failure (`dict` or `None`): Context of the failed task collected by the worker
+            (e.g. "exit_code", "stdout_tail", "stderr_tail", "worker", "host").
     """
-    pass
+    failure = None
+
+
+class IdCollisionException(RainException):
+    """
+    Submit rejected because ids of tasks or objects are already used
+    in their sessions (nothing is submitted)
+
+    Attributes:
+        task_ids (`list` of :class:`ID`): Colliding ids of tasks
+        object_ids (`list` of :class:`ID`): Colliding ids of objects
+    """
+
+    def __init__(self, task_ids, object_ids):
+        super().__init__("Ids already used in their sessions: tasks {}, objects {}"
+                         .format(task_ids, object_ids))
+        self.task_ids = task_ids
+        self.object_ids = object_ids
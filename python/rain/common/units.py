@@ -0,0 +1,81 @@
+import math
+import re
+
+from .errors import RainException
+
+# The same units as accepted by the server and workers (src/common/units.rs)
+_SIZE_PREFIXES = {"k": 1, "m": 2, "g": 3, "t": 4, "p": 5}
+_DURATION_UNITS = {"ms": 0.001, "s": 1, "m": 60, "h": 3600, "d": 86400}
+
+_SIZE_RE = re.compile(r"^([0-9.]+)\s*([a-zA-Z]*)$")
+_DURATION_PART_RE = re.compile(r"([0-9.]+)([a-z]+)")
+
+
+def _size_multiplier(unit):
+    unit = unit.lower()
+    if unit in ("", "b"):
+        return 1
+    exponent = _SIZE_PREFIXES.get(unit[0])
+    if exponent is None:
+        return None
+    if unit[1:] in ("", "i", "ib"):
+        return 1024 ** exponent
+    if unit[1:] == "b":
+        return 1000 ** exponent
+    return None
+
+
+def parse_size(value, field="size"):
+    """
+    Size in bytes given as a number or as a string with a unit;
+    "k", "M", "G", "T", "P" with or without "iB" are binary, with "B" decimal.
+
+    >>> parse_size("20GiB")
+    21474836480
+    >>> parse_size("100kB")
+    100000
+    """
+    if isinstance(value, int) and not isinstance(value, bool):
+        if value < 0:
+            raise RainException("Invalid {} {}: size cannot be negative".format(field, value))
+        return value
+    match = _SIZE_RE.match(str(value).strip())
+    multiplier = _size_multiplier(match.group(2)) if match else None
+    if multiplier is None:
+        raise RainException("Invalid {} '{}': use e.g. 512, 100kB, 20GiB or 512M"
+                            .format(field, value))
+    try:
+        return int(round(float(match.group(1)) * multiplier))
+    except ValueError:
+        raise RainException("Invalid {} '{}': expected a number".format(field, value))
+
+
+def parse_duration(value, field="duration"):
+    """
+    Duration in seconds given as a number or as a string with units
+    "d", "h", "m", "s" and "ms".
+
+    >>> parse_duration("2h30m")
+    9000.0
+    """
+    if isinstance(value, (int, float)) and not isinstance(value, bool):
+        secs = float(value)
+    else:
+        text = str(value).strip()
+        try:
+            secs = float(text)
+        except ValueError:
+            parts = _DURATION_PART_RE.findall(text)
+            if not parts or "".join(n + u for n, u in parts) != text \
+                    or any(u not in _DURATION_UNITS for _, u in parts):
+                raise RainException(
+                    "Invalid {} '{}': use e.g. 90, 1.5s, 500ms, 10m or 2h30m"
+                    .format(field, value))
+            try:
+                secs = sum(float(n) * _DURATION_UNITS[u] for n, u in parts)
+            except ValueError:
+                raise RainException("Invalid {} '{}': expected a number"
+                                    .format(field, value))
+    if secs < 0 or not math.isfinite(secs):
+        raise RainException("Invalid {} '{}': duration out of range".format(field, value))
+    return secs
@@ -0,0 +1,149 @@
+"""
+Minimal subworker running each task as a script of an interpreter
+(e.g. ``Rscript`` or ``julia``). It is a reference implementation of
+the subworker protocol that uses nothing but pycapnp and the protocol
+schema, see "Subworker protocol" in the developer guide.
+
+Usage (as COMMAND of worker option ``--subworker``)::
+
+  python3 -m rain.subworker.bridge INTERPRETER [ARGS...]
+
+Task config is ``{"source": SOURCE}``. The source is written into file
+``+script`` in an empty task directory and ``INTERPRETER ARGS... +script``
+is executed there. Inputs are available as files named by their labels
+(``input<i>`` for inputs without a label), the script writes each output
+into a file named by the label of the output.
+"""
+
+import json
+import os
+import shutil
+import socket
+import subprocess
+import sys
+
+import capnp
+
+SUBWORKER_PROTOCOL_VERSION = 0
+SCRIPT_NAME = "+script"
+# Max size of stderr of the script included in the error message of a task
+STDERR_TAIL = 4096
+
+schema = capnp.load(os.path.join(os.path.dirname(__file__), "..", "capnp",
+                                 "subworker.capnp"))
+
+
+def attributes_from_capnp(reader):
+    return {item.key: json.loads(item.value) for item in reader.items}
+
+
+def attributes_to_capnp(attributes, builder):
+    items = builder.init("items", len(attributes))
+    for i, (key, value) in enumerate(attributes.items()):
+        items[i].key = key
+        items[i].value = json.dumps(value)
+
+
+def clean_dir(path):
+    shutil.rmtree(path, ignore_errors=True)
+    os.mkdir(path)
+
+
+class BridgeControl(schema.SubworkerControl.Server):
+
+    def __init__(self, interpreter, task_dir):
+        self.interpreter = interpreter
+        self.task_dir = task_dir
+
+    def removeCachedObjects(self, objectIds, _context):
+        # Nothing is cached, the subworker kind has not "cache_first_input"
+        pass
+
+    def runTask(self, task, _context):
+        results = _context.results
+        attributes = attributes_from_capnp(task.attributes)
+        try:
+            clean_dir(self.task_dir)
+            for i, reader in enumerate(task.inputs):
+                self.write_input(reader, reader.label or "input{}".format(i))
+            with open(os.path.join(self.task_dir, SCRIPT_NAME), "w") as f:
+                f.write(attributes["config"]["source"])
+
+            env = dict(os.environ)
+            env.update(attributes.get("env", {}))
+            env.update((s.name, s.value.decode(errors="surrogateescape"))
+                       for s in task.secrets)
+            process = subprocess.run(self.interpreter + [SCRIPT_NAME],
+                                     cwd=self.task_dir, env=env,
+                                     stdin=subprocess.DEVNULL,
+                                     stderr=subprocess.PIPE)
+            if process.returncode != 0:
+                stderr = process.stderr[-STDERR_TAIL:].decode(errors="replace")
+                raise Exception("{} exited with code {}\nStderr: {}".format(
+                    self.interpreter[0], process.returncode, stderr))
+
+            data = results.init("data", len(task.outputs))
+            for i, reader in enumerate(task.outputs):
+                path = os.path.join(self.task_dir, reader.label)
+                if not os.path.isfile(path):
+                    raise Exception("Output '{}' was not created".format(reader.label))
+                # Moved by the worker, so the file is not removed by the next task
+                target = os.path.join(os.getcwd(), "output-{}-{}".format(
+                    reader.id.id, reader.id.sessionId))
+                os.rename(path, target)
+                data[i].storage.path = target
+                attributes_to_capnp(attributes_from_capnp(reader.attributes),
+                                    data[i].attributes)
+            results.ok = True
+        except Exception as e:
+            results.errorMessage = str(e)
+            results.ok = False
+        attributes_to_capnp({}, results.taskAttributes)
+
+    def write_input(self, reader, name):
+        storage = reader.data.storage
+        path = os.path.join(self.task_dir, name)
+        if storage.which() == "memory":
+            with open(path, "wb") as f:
+                f.write(storage.memory)
+        elif storage.which() == "path":
+            os.symlink(storage.path, path)
+        else:
+            raise Exception("Unsupported storage of input '{}'".format(name))
+
+
+def main():
+    if len(sys.argv) < 2:
+        print("Usage: python3 -m rain.subworker.bridge INTERPRETER [ARGS...]",
+              file=sys.stderr)
+        sys.exit(1)
+    interpreter = sys.argv[1:]
+    subworker_id = int(os.environ["RAIN_SUBWORKER_ID"])
+    subworker_type = os.environ["RAIN_SUBWORKER_TYPE"]
+    address = os.environ["RAIN_SUBWORKER_SOCKET"]
+
+    # The working directory is the directory of the subworker
+    task_dir = os.path.abspath("task")
+
+    # Names of unix sockets are limited, so it is connected by a relative path
+    sock = socket.socket(socket.AF_UNIX, socket.SOCK_STREAM)
+    cwd = os.getcwd()
+    os.chdir(os.path.dirname(address))
+    sock.connect(os.path.basename(address))
+    os.chdir(cwd)
+
+    client = capnp.TwoPartyClient(sock)
+    upstream = client.bootstrap().cast_as(schema.SubworkerUpstream)
+    register = upstream.register_request()
+    register.version = SUBWORKER_PROTOCOL_VERSION
+    register.subworkerId = subworker_id
+    register.subworkerType = subworker_type
+    register.control = BridgeControl(interpreter, task_dir)
+    register.send().wait()
+    print("Subworker {} ({}) registered".format(subworker_id, subworker_type))
+    sys.stdout.flush()
+    capnp.wait_forever()
+
+
+if __name__ == "__main__":
+    main()
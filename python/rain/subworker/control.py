@@ -1,6 +1,7 @@
 from .rpc import subworker as rpc_subworker
 from ..common.data_instance import DataInstance
 from .context import Context
+from .profile import ProcessUsage
 from ..common.attributes import attributes_to_capnp, attributes_from_capnp
 from ..common.ids import id_from_capnp
 import traceback
@@ -16,7 +17,8 @@ def load_worker_object(reader, cache):
     return data
 
 
-def write_attributes(context, builder):
+def write_attributes(context, builder, start_usage):
+    context.attributes["profile"] = ProcessUsage().profile_since(start_usage)
     if context._debug_messages:
         context.attributes["debug"] = "\n".join(context._debug_messages)
     attributes_to_capnp(context.attributes, builder)
@@ -37,11 +39,15 @@ class ControlImpl(rpc_subworker.SubworkerControl.Server):
 
     def runTask(self, task, _context):
         task_context = Context(self.subworker)
+        start_usage = ProcessUsage()
         try:
             params = _context.params
 
             task_context.attributes = attributes_from_capnp(
                 params.task.attributes)
+            # Secrets are kept out of attributes, they are only set in environment
+            secrets = {s.name: s.value.decode(errors="surrogateescape")
+                       for s in params.task.secrets}
             cfg = task_context.attributes["config"]
 
             inputs = []
@@ -61,17 +67,17 @@ class ControlImpl(rpc_subworker.SubworkerControl.Server):
                                                  cfg['encode_outputs'])]
 
             task_results = self.subworker.run_task(
-                task_context, inputs, outputs)
+                task_context, inputs, outputs, secrets)
 
             results = _context.results.init("data", len(task_results))
             for i, data in enumerate(task_results):
                 data._to_capnp(results[i])
             task_context._cleanup(task_results)
-            write_attributes(task_context, _context.results.taskAttributes)
+            write_attributes(task_context, _context.results.taskAttributes, start_usage)
             _context.results.ok = True
 
         except Exception:
             task_context._cleanup_on_fail()
             _context.results.errorMessage = traceback.format_exc()
-            write_attributes(task_context, _context.results.taskAttributes)
+            write_attributes(task_context, _context.results.taskAttributes, start_usage)
             _context.results.ok = False
@@ -0,0 +1,37 @@
+import resource
+
+
+def _io_bytes():
+    """Bytes read from and written to the storage layer by this process
+    (zeros when /proc/self/io is not available)"""
+    counters = {}
+    try:
+        with open("/proc/self/io") as f:
+            for line in f:
+                name, _, value = line.partition(":")
+                counters[name] = int(value)
+    except (OSError, ValueError):
+        pass
+    return counters.get("read_bytes", 0), counters.get("write_bytes", 0)
+
+
+class ProcessUsage:
+    """Resource usage of the subworker process (and its waited-for children)
+    at the time of creation. `profile_since` returns the usage of a task run
+    since then in the format of task attribute "profile"."""
+
+    def __init__(self):
+        self_usage = resource.getrusage(resource.RUSAGE_SELF)
+        children_usage = resource.getrusage(resource.RUSAGE_CHILDREN)
+        self.cpu_time = sum(u.ru_utime + u.ru_stime for u in (self_usage, children_usage))
+        self.max_rss = max(self_usage.ru_maxrss, children_usage.ru_maxrss) * 1024
+        self.read_bytes, self.write_bytes = _io_bytes()
+
+    def profile_since(self, start):
+        # Max RSS is the peak of the whole subworker process, not only of the task
+        return {
+            "cpu_time": int((self.cpu_time - start.cpu_time) * 1000),
+            "max_rss": self.max_rss,
+            "read_bytes": self.read_bytes - start.read_bytes,
+            "write_bytes": self.write_bytes - start.write_bytes,
+        }
@@ -34,6 +34,22 @@ def _unpickle_inputs_context(inputs):
         _global_unpickle_inputs = None
 
 
+@contextlib.contextmanager
+def _task_environ(env):
+    """Context manager setting environment variables of the task
+    (attribute "env") and restoring the original values afterwards."""
+    backup = {name: os.environ.get(name) for name in env}
+    os.environ.update(env)
+    try:
+        yield
+    finally:
+        for name, value in backup.items():
+            if value is None:
+                os.environ.pop(name, None)
+            else:
+                os.environ[name] = value
+
+
 def unpickle_input_object(name, index, load, content_type):
     """Helper to replace encoded input object placeholders with actual
     local data objects data."""
@@ -50,7 +66,8 @@ def unpickle_input_object(name, index, load, content_type):
 
 class Subworker:
 
-    def __init__(self, address, subworker_id, task_path, stage_path):
+    def __init__(self, address, subworker_id, task_path, stage_path,
+                 subworker_type="py"):
         self.task_path = task_path
         self.stage_path = stage_path
         sock = socket.socket(socket.AF_UNIX, socket.SOCK_STREAM)
@@ -73,15 +90,16 @@ class Subworker:
         register = upstream.register_request()
         register.version = SUBWORKER_PROTOCOL_VERSION
         register.subworkerId = subworker_id
-        register.subworkerType = "py"
+        register.subworkerType = subworker_type
         register.control = control
         register.send().wait()
 
-    def run_task(self, context, inputs, outputs):
+    def run_task(self, context, inputs, outputs, secrets={}):
         """
         Args:
             inputs: is a list of `DataInstance`.
             outputs: is list of `ControlImpl.OutputSpec`.
+            secrets: dict of environment variables with values of secrets.
         Returns:
             list(DataInstance)
         """
@@ -97,7 +115,10 @@ class Subworker:
         os.chdir(self.task_path)
 
         # Run the function
-        result = fn(context, *args, **kwargs)
+        env = dict(context.attributes.get("env", {}))
+        env.update(secrets)
+        with _task_environ(env):
+            result = fn(context, *args, **kwargs)
 
         if len(outputs) == 0:
             if result is not None and result != ():
@@ -149,6 +170,11 @@ def get_environ_int(name):
 
 
 def main():
+    if "--zygote" in sys.argv[1:]:
+        from .zygote import zygote_main
+        zygote_main()
+        return
+
     subworker_id = get_environ_int("RAIN_SUBWORKER_ID")
 
     print("Initalizing subworker {} ...".format(subworker_id))
@@ -164,7 +190,8 @@ def main():
     Subworker(get_environ("RAIN_SUBWORKER_SOCKET"),
               subworker_id,
               task_path,
-              stage_path)
+              stage_path,
+              os.environ.get("RAIN_SUBWORKER_TYPE", "py"))
 
     print("Subworker initialized")
     sys.stdout.flush()
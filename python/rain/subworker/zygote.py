@@ -0,0 +1,104 @@
+import os
+import sys
+import json
+import signal
+import importlib
+import traceback
+
+from .subworker import main
+
+# pid -> subworker id of forked subworkers
+children = {}
+
+
+def preload():
+    """Import modules listed in RAIN_SUBWORKER_PRELOAD (comma separated),
+    forked subworkers get them already imported."""
+    for name in os.environ.get("RAIN_SUBWORKER_PRELOAD", "").split(","):
+        name = name.strip()
+        if name:
+            print("Preloading module {}".format(name), file=sys.stderr)
+            importlib.import_module(name)
+
+
+def report(message):
+    # Single write, so it is not mixed with a report from signal handler
+    os.write(1, (json.dumps(message) + "\n").encode())
+
+
+def reap_children(signum, frame):
+    while True:
+        try:
+            pid, status = os.waitpid(-1, os.WNOHANG)
+        except ChildProcessError:
+            return
+        if pid == 0:
+            return
+        subworker_id = children.pop(pid, None)
+        if subworker_id is None:
+            continue
+        if os.WIFSIGNALED(status):
+            code = -os.WTERMSIG(status)
+        else:
+            code = os.WEXITSTATUS(status)
+        report({"exited": subworker_id, "status": code})
+
+
+def run_subworker(request):
+    signal.signal(signal.SIGCHLD, signal.SIG_DFL)
+    signal.pthread_sigmask(signal.SIG_UNBLOCK, [signal.SIGCHLD])
+    try:
+        devnull = os.open(os.devnull, os.O_RDONLY)
+        os.dup2(devnull, 0)
+        os.close(devnull)
+        for fd, path in ((1, request["stdout"]), (2, request["stderr"])):
+            log = os.open(path, os.O_WRONLY | os.O_CREAT | os.O_TRUNC, 0o644)
+            os.dup2(log, fd)
+            os.close(log)
+        os.chdir(request["work_dir"])
+        os.environ["RAIN_SUBWORKER_ID"] = str(request["id"])
+        sys.argv = sys.argv[:1]
+        main()
+    except BaseException:
+        traceback.print_exc()
+        sys.stderr.flush()
+        os._exit(1)
+    os._exit(0)
+
+
+def fork_subworker(request):
+    # SIGCHLD is blocked until the child is registered
+    signal.pthread_sigmask(signal.SIG_BLOCK, [signal.SIGCHLD])
+    try:
+        pid = os.fork()
+        if pid == 0:
+            run_subworker(request)
+        children[pid] = request["id"]
+    finally:
+        signal.pthread_sigmask(signal.SIG_UNBLOCK, [signal.SIGCHLD])
+
+
+def kill_subworker(request):
+    for pid, subworker_id in list(children.items()):
+        if subworker_id == request["id"]:
+            os.kill(pid, signal.SIGKILL)
+
+
+def zygote_main():
+    """Initialized process that forks new subworkers on requests from worker.
+
+    Requests are JSON lines on stdin, exits of forked subworkers are reported
+    as JSON lines on stdout. Preloaded modules should not start threads,
+    they would not exist in forked subworkers."""
+    print("Initializing zygote ...", file=sys.stderr)
+    preload()
+    signal.signal(signal.SIGCHLD, reap_children)
+    print("Zygote initialized", file=sys.stderr)
+    sys.stderr.flush()
+
+    for line in sys.stdin:
+        request = json.loads(line)
+        if "fork" in request:
+            fork_subworker(request["fork"])
+        elif "kill" in request:
+            kill_subworker(request["kill"])
@@ -0,0 +1,175 @@
+use std::net::SocketAddr;
+use std::time::Duration;
+
+use capnp_rpc::rpc_twoparty_capnp;
+use futures::Future;
+use tokio_core::net::TcpStream;
+use tokio_core::reactor::Core;
+
+use broker::ShardLoad;
+use common::rpc::new_rpc_system;
+use errors::{Error, Result};
+use CLIENT_PROTOCOL_VERSION;
+
+/// How often `upgrade_workers` polls the status of the upgrade when it waits for it
+const UPGRADE_POLL_INTERVAL: u64 = 2;
+
+/// How often `wait_for_drain` polls the number of running tasks
+const DRAIN_POLL_INTERVAL: u64 = 1;
+
+/// Register as a client of the server and return its client service
+/// (the RPC system runs on `core`)
+pub fn connect_client_service(
+    core: &mut Core,
+    server_address: SocketAddr,
+    token: &str,
+) -> Result<::client_capnp::client_service::Client> {
+    let stream = core.run(TcpStream::connect(&server_address, &core.handle()))?;
+    stream.set_nodelay(true)?;
+    let mut rpc_system = new_rpc_system(stream, None);
+    let bootstrap: ::server_capnp::server_bootstrap::Client =
+        rpc_system.bootstrap(rpc_twoparty_capnp::Side::Server);
+    core.handle()
+        .spawn(rpc_system.map_err(|e| error!("RPC error: {:?}", e)));
+
+    let mut req = bootstrap.register_as_client_request();
+    req.get().set_version(CLIENT_PROTOCOL_VERSION);
+    req.get().set_token(token);
+    let service = core.run(req.send().promise.and_then(|response| {
+        response.get().and_then(|r| r.get_service())
+    }))?;
+    Ok(service)
+}
+
+/// Client of the server for administrative commands (`rain admin`)
+pub struct AdminClient {
+    core: Core,
+    service: ::client_capnp::client_service::Client,
+}
+
+impl AdminClient {
+    /// Connect to the server; `token` is needed when the server has access control enabled
+    pub fn connect(server_address: SocketAddr, token: &str) -> Result<Self> {
+        let mut core = Core::new()?;
+        let service = connect_client_service(&mut core, server_address, token)?;
+        Ok(AdminClient { core, service })
+    }
+
+    /// Stop scheduling and fail all sessions before shutdown, returns the number of sessions
+    pub fn stop_scheduling(&mut self) -> Result<u32> {
+        let req = self.service.stop_scheduling_request();
+        self.core.run(
+            req.send()
+                .promise
+                .map_err(Error::from)
+                .and_then(|response| -> Result<u32> { Ok(response.get()?.get_sessions()) }),
+        )
+    }
+
+    /// Ask all workers to terminate, returns their number
+    pub fn stop_workers(&mut self) -> Result<u32> {
+        let req = self.service.stop_workers_request();
+        self.core.run(
+            req.send()
+                .promise
+                .map_err(Error::from)
+                .and_then(|response| -> Result<u32> { Ok(response.get()?.get_workers()) }),
+        )
+    }
+
+    /// Release up to `count` workers to the broker of session shards, returns their number
+    pub fn release_workers(&mut self, count: u32) -> Result<u32> {
+        let mut req = self.service.release_workers_request();
+        req.get().set_count(count);
+        self.core.run(
+            req.send()
+                .promise
+                .map_err(Error::from)
+                .and_then(|response| -> Result<u32> { Ok(response.get()?.get_workers()) }),
+        )
+    }
+
+    /// Load of the server used by the broker of session shards
+    pub fn get_shard_load(&mut self) -> Result<ShardLoad> {
+        let req = self.service.get_shard_load_request();
+        self.core.run(req.send().promise.map_err(Error::from).and_then(
+            |response| -> Result<ShardLoad> {
+                let load = response.get()?;
+                Ok(ShardLoad {
+                    workers: load.get_workers(),
+                    draining: load.get_draining(),
+                    ready_tasks: load.get_ready_tasks(),
+                    running_tasks: load.get_running_tasks(),
+                })
+            },
+        ))
+    }
+
+    /// Quit the server
+    pub fn terminate_server(&mut self) -> Result<()> {
+        let req = self.service.terminate_server_request();
+        self.core.run(req.send().promise)?;
+        Ok(())
+    }
+
+    /// Change parameters of the scheduler (`SchedulerConfig` as JSON, "{}" changes nothing),
+    /// returns its status (`SchedulerStatus` as JSON)
+    pub fn configure_scheduler(&mut self, config: &str) -> Result<String> {
+        let mut req = self.service.configure_scheduler_request();
+        req.get().set_config(config);
+        self.core.run(req.send().promise.map_err(Error::from).and_then(
+            |response| -> Result<String> { Ok(response.get()?.get_status()?.to_string()) },
+        ))
+    }
+
+    /// Block until no tasks run on workers (after scheduling was paused)
+    pub fn wait_for_drain(&mut self) -> Result<()> {
+        loop {
+            let status: ::serde_json::Value =
+                ::serde_json::from_str(&self.configure_scheduler("{}")?)?;
+            let running = status["running_tasks"].as_u64().unwrap_or(0);
+            if running == 0 {
+                info!("Workers are drained");
+                return Ok(());
+            }
+            info!("Draining: {} running tasks", running);
+            ::std::thread::sleep(Duration::from_secs(DRAIN_POLL_INTERVAL));
+        }
+    }
+
+    /// Start a rolling upgrade of workers; when `wait` is set, block until it is finished
+    pub fn upgrade_workers(&mut self, image: &str, batch: u32, wait: bool) -> Result<()> {
+        let mut req = self.service.upgrade_workers_request();
+        req.get().set_image(image);
+        req.get().set_batch(batch);
+        self.core.run(req.send().promise)?;
+        info!("Upgrade of workers to image '{}' started", image);
+        if !wait {
+            return Ok(());
+        }
+        loop {
+            let req = self.service.get_upgrade_status_request();
+            let (outdated, draining, upgraded, done) =
+                self.core.run(req.send().promise.map_err(Error::from).and_then(
+                    |response| -> Result<_> {
+                        let status = response.get()?;
+                        Ok((
+                            status.get_outdated(),
+                            status.get_draining(),
+                            status.get_upgraded(),
+                            status.get_done(),
+                        ))
+                    },
+                ))?;
+            info!(
+                "Upgrade: {} outdated workers ({} draining), {} upgraded",
+                outdated, draining, upgraded
+            );
+            if done {
+                info!("Upgrade finished");
+                return Ok(());
+            }
+            ::std::thread::sleep(Duration::from_secs(UPGRADE_POLL_INTERVAL));
+        }
+    }
+}
@@ -0,0 +1,371 @@
+use std::net::SocketAddr;
+use std::time::{Duration, Instant};
+
+use futures::{future, Future};
+use tokio_core::reactor::Core;
+
+use admin::connect_client_service;
+use common::Attributes;
+use common::convert::ToCapnp;
+use common::id::{DataObjectId, Id, SessionId, TaskId};
+use common::resources::Resources;
+use errors::{Error, Result};
+
+/// Names of benchmarks of `rain bench`
+pub const BENCHMARKS: &[&str] = &["task-throughput", "object-transfer", "submission-latency"];
+
+/// Size of a chunk requested from a reader when data are downloaded
+const READ_CHUNK_SIZE: u64 = 1 << 20;
+
+/// Parameters of benchmarks
+#[derive(Clone, Debug)]
+pub struct BenchConfig {
+    /// Number of tasks of graphs of "task-throughput" and "submission-latency"
+    pub tasks: usize,
+    /// Size of the object of "object-transfer" in bytes
+    pub bytes: usize,
+    /// How many times each measurement is repeated (results are averaged)
+    pub repeats: usize,
+}
+
+impl Default for BenchConfig {
+    fn default() -> Self {
+        BenchConfig {
+            tasks: 1000,
+            bytes: 64 * 1024 * 1024,
+            repeats: 3,
+        }
+    }
+}
+
+/// Result of one measurement, `rain bench` prints it as one line of JSON
+#[derive(Clone, Debug, Serialize)]
+pub struct BenchResult {
+    pub benchmark: String,
+    /// Version of rain running the benchmark
+    pub version: String,
+    pub workers: usize,
+    /// Number of tasks or bytes processed by one repeat
+    pub size: usize,
+    pub repeats: usize,
+    /// Mean duration of one repeat in seconds
+    pub seconds: f64,
+    /// `size` per second
+    pub rate: f64,
+    /// Unit of `rate` ("tasks/s" or "bytes/s")
+    pub unit: String,
+}
+
+struct BenchObject {
+    id: DataObjectId,
+    keep: bool,
+    data: Option<Vec<u8>>,
+}
+
+/// Built-in "!concat" task; without inputs it is an empty task producing an empty blob
+struct BenchTask {
+    id: TaskId,
+    inputs: Vec<DataObjectId>,
+    output: DataObjectId,
+}
+
+#[derive(Default)]
+struct BenchGraph {
+    objects: Vec<BenchObject>,
+    tasks: Vec<BenchTask>,
+    next_id: Id,
+}
+
+impl BenchGraph {
+    fn object(&mut self, session_id: SessionId, keep: bool, data: Option<Vec<u8>>) -> DataObjectId {
+        self.next_id += 1;
+        let id = DataObjectId::new(session_id, self.next_id);
+        self.objects.push(BenchObject { id, keep, data });
+        id
+    }
+
+    fn concat(
+        &mut self,
+        session_id: SessionId,
+        inputs: Vec<DataObjectId>,
+        keep: bool,
+    ) -> DataObjectId {
+        let output = self.object(session_id, keep, None);
+        self.next_id += 1;
+        self.tasks.push(BenchTask {
+            id: TaskId::new(session_id, self.next_id),
+            inputs,
+            output,
+        });
+        output
+    }
+
+    /// Independent empty tasks
+    fn empty_tasks(session_id: SessionId, count: usize) -> Self {
+        let mut graph = BenchGraph::default();
+        for _ in 0..count {
+            graph.concat(session_id, Vec::new(), false);
+        }
+        graph
+    }
+}
+
+fn seconds(duration: Duration) -> f64 {
+    duration.as_secs() as f64 + f64::from(duration.subsec_nanos()) * 1e-9
+}
+
+/// Client running benchmarks against a live cluster (`rain bench`)
+pub struct BenchClient {
+    core: Core,
+    service: ::client_capnp::client_service::Client,
+}
+
+impl BenchClient {
+    /// Connect to the server; `token` is needed when the server has access control enabled
+    pub fn connect(server_address: SocketAddr, token: &str) -> Result<Self> {
+        let mut core = Core::new()?;
+        let service = connect_client_service(&mut core, server_address, token)?;
+        Ok(BenchClient { core, service })
+    }
+
+    /// Run the benchmark of the given name (see `BENCHMARKS`)
+    pub fn run(&mut self, name: &str, config: &BenchConfig) -> Result<Vec<BenchResult>> {
+        if config.repeats == 0 {
+            bail!("Number of repeats has to be at least 1");
+        }
+        let workers = self.worker_count()?;
+        if workers == 0 {
+            bail!("No workers are connected to the server");
+        }
+        let measured: Vec<(&str, usize, &str, Duration)> = match name {
+            "task-throughput" => {
+                let duration = self.repeat(config.repeats, |client, session_id| {
+                    let graph = BenchGraph::empty_tasks(session_id, config.tasks);
+                    let start = Instant::now();
+                    client.submit(&graph)?;
+                    client.wait_all(session_id)?;
+                    Ok(start.elapsed())
+                })?;
+                vec![("task-throughput", config.tasks, "tasks/s", duration)]
+            }
+            "submission-latency" => {
+                let duration = self.repeat(config.repeats, |client, session_id| {
+                    let graph = BenchGraph::empty_tasks(session_id, config.tasks);
+                    let start = Instant::now();
+                    client.submit(&graph)?;
+                    Ok(start.elapsed())
+                })?;
+                vec![("submission-latency", config.tasks, "tasks/s", duration)]
+            }
+            "object-transfer" => {
+                let mut download = Duration::from_secs(0);
+                let upload = self.repeat(config.repeats, |client, session_id| {
+                    let mut graph = BenchGraph::default();
+                    let input = graph.object(session_id, false, Some(vec![0u8; config.bytes]));
+                    let output = graph.concat(session_id, vec![input], true);
+                    // Upload to the server and transfer to a worker
+                    let start = Instant::now();
+                    client.submit(&graph)?;
+                    client.wait_all(session_id)?;
+                    let upload = start.elapsed();
+                    // Transfer from the worker through the server to the client
+                    let start = Instant::now();
+                    let size = client.download(output)?;
+                    download += start.elapsed();
+                    if size != config.bytes {
+                        bail!("Downloaded {} bytes instead of {}", size, config.bytes);
+                    }
+                    Ok(upload)
+                })?;
+                let download = download / config.repeats as u32;
+                vec![
+                    ("object-transfer-upload", config.bytes, "bytes/s", upload),
+                    ("object-transfer-download", config.bytes, "bytes/s", download),
+                ]
+            }
+            _ => bail!(
+                "Unknown benchmark '{}' (expected one of: {})",
+                name,
+                BENCHMARKS.join(", ")
+            ),
+        };
+        Ok(measured
+            .into_iter()
+            .map(|(benchmark, size, unit, duration)| {
+                let secs = seconds(duration);
+                BenchResult {
+                    benchmark: benchmark.to_string(),
+                    version: ::VERSION.to_string(),
+                    workers,
+                    size,
+                    repeats: config.repeats,
+                    seconds: secs,
+                    rate: if secs > 0.0 { size as f64 / secs } else { 0.0 },
+                    unit: unit.to_string(),
+                }
+            })
+            .collect())
+    }
+
+    /// Run the measurement in a fresh session `repeats` times, return the mean duration
+    fn repeat<F>(&mut self, repeats: usize, mut measure: F) -> Result<Duration>
+    where
+        F: FnMut(&mut Self, SessionId) -> Result<Duration>,
+    {
+        let mut total = Duration::from_secs(0);
+        for _ in 0..repeats {
+            let session_id = self.new_session()?;
+            let result = measure(self, session_id);
+            self.close_session(session_id)?;
+            total += result?;
+        }
+        Ok(total / repeats as u32)
+    }
+
+    fn worker_count(&mut self) -> Result<usize> {
+        let req = self.service.get_server_info_request();
+        let count = self.core.run(req.send().promise.map_err(Error::from).and_then(
+            |response| -> Result<usize> { Ok(response.get()?.get_workers()?.len() as usize) },
+        ))?;
+        Ok(count)
+    }
+
+    fn new_session(&mut self) -> Result<SessionId> {
+        let mut req = self.service.new_session_request();
+        req.get().init_env(0);
+        let session_id = self.core.run(req.send().promise.map_err(Error::from).and_then(
+            |response| -> Result<SessionId> { Ok(response.get()?.get_session_id()) },
+        ))?;
+        Ok(session_id)
+    }
+
+    fn close_session(&mut self, session_id: SessionId) -> Result<()> {
+        let mut req = self.service.close_session_request();
+        req.get().set_session_id(session_id);
+        self.core.run(req.send().promise)?;
+        Ok(())
+    }
+
+    fn submit(&mut self, graph: &BenchGraph) -> Result<()> {
+        let mut req = self.service.submit_request();
+        {
+            let mut attributes = Attributes::new();
+            attributes.set(
+                "resources",
+                Resources {
+                    cpus: 1,
+                    ..Default::default()
+                },
+            )?;
+            let mut params = req.get();
+            {
+                let mut objects = params.borrow().init_objects(graph.objects.len() as u32);
+                for (i, object) in graph.objects.iter().enumerate() {
+                    let mut builder = objects.borrow().get(i as u32);
+                    object.id.to_capnp(&mut builder.borrow().init_id());
+                    builder.set_keep(object.keep);
+                    builder.set_data_type(::common_capnp::DataType::Blob);
+                    builder.set_label("");
+                    if let Some(ref data) = object.data {
+                        builder.set_has_data(true);
+                        builder.set_data(data);
+                    }
+                    Attributes::new().to_capnp(&mut builder.init_attributes());
+                }
+            }
+            let mut tasks = params.init_tasks(graph.tasks.len() as u32);
+            for (i, task) in graph.tasks.iter().enumerate() {
+                let mut builder = tasks.borrow().get(i as u32);
+                task.id.to_capnp(&mut builder.borrow().init_id());
+                builder.set_task_type("!concat");
+                {
+                    let mut inputs = builder.borrow().init_inputs(task.inputs.len() as u32);
+                    for (j, input) in task.inputs.iter().enumerate() {
+                        let mut input_builder = inputs.borrow().get(j as u32);
+                        input.to_capnp(&mut input_builder.borrow().init_id());
+                        input_builder.set_label("");
+                        input_builder.set_path("");
+                    }
+                }
+                task.output
+                    .to_capnp(&mut builder.borrow().init_outputs(1).get(0));
+                attributes.to_capnp(&mut builder.init_attributes());
+            }
+        }
+        self.core.run(req.send().promise.map_err(Error::from).and_then(
+            |response| -> Result<()> {
+                match response.get()?.which()? {
+                    ::client_capnp::submit_result::Which::Ok(_) => Ok(()),
+                    ::client_capnp::submit_result::Which::IdCollision(_) => {
+                        bail!("Ids of the benchmark graph collide")
+                    }
+                }
+            },
+        ))
+    }
+
+    /// Wait until all tasks of the session are finished
+    fn wait_all(&mut self, session_id: SessionId) -> Result<()> {
+        let mut req = self.service.wait_request();
+        {
+            let mut params = req.get();
+            let mut task_ids = params.borrow().init_task_ids(1);
+            let mut id = task_ids.borrow().get(0);
+            id.set_id(::common_capnp::ALL_TASKS_ID);
+            id.set_session_id(session_id);
+        }
+        req.get().init_object_ids(0);
+        self.core.run(req.send().promise.map_err(Error::from).and_then(
+            |response| -> Result<()> {
+                match response.get()?.which()? {
+                    ::common_capnp::unit_result::Which::Ok(()) => Ok(()),
+                    ::common_capnp::unit_result::Which::Error(e) => {
+                        bail!("Benchmark graph failed: {}", e?.get_message()?)
+                    }
+                }
+            },
+        ))
+    }
+
+    /// Download the data of a kept object, return their size
+    fn download(&mut self, object_id: DataObjectId) -> Result<usize> {
+        let req = self.service.get_data_store_request();
+        let store = self.core.run(req.send().promise.map_err(Error::from).and_then(
+            |response| -> Result<::datastore_capnp::data_store::Client> {
+                Ok(response.get()?.get_store()?)
+            },
+        ))?;
+        let mut req = store.create_reader_request();
+        {
+            let mut params = req.get();
+            object_id.to_capnp(&mut params.borrow().init_id());
+            params.set_path("");
+            params.set_offset(0);
+        }
+        let reader = self.core.run(req.send().promise.map_err(Error::from).and_then(
+            move |response| -> Result<::datastore_capnp::reader::Client> {
+                let response = response.get()?;
+                match response.which()? {
+                    ::datastore_capnp::reader_response::Which::Ok(()) => {
+                        Ok(response.get_reader()?)
+                    }
+                    _ => bail!("Object {} cannot be downloaded", object_id),
+                }
+            },
+        ))?;
+        self.core.run(future::loop_fn(0usize, move |size| {
+            let mut req = reader.read_request();
+            req.get().set_size(READ_CHUNK_SIZE);
+            req.send().promise.map_err(Error::from).and_then(
+                move |response| -> Result<future::Loop<usize, usize>> {
+                    let read = response.get()?;
+                    let size = size + read.get_data()?.len();
+                    Ok(match read.get_status()? {
+                        ::datastore_capnp::read_reply::Status::Ok => future::Loop::Continue(size),
+                        ::datastore_capnp::read_reply::Status::Eof => future::Loop::Break(size),
+                    })
+                },
+            )
+        }))
+    }
+}
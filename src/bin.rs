@@ -10,8 +10,13 @@ extern crate librain;
 extern crate log;
 extern crate nix;
 extern crate num_cpus;
+#[macro_use]
+extern crate serde_derive;
 extern crate serde_json;
 extern crate tokio_core;
+extern crate tokio_tls;
+extern crate tokio_uds;
+extern crate toml;
 
 pub mod start;
 
@@ -20,19 +25,34 @@ use std::error::Error;
 use std::net::{IpAddr, Ipv4Addr, SocketAddr, ToSocketAddrs};
 use std::path::{Path, PathBuf};
 use std::process::exit;
+use std::time::Duration;
 use std::io::Write;
 
 use clap::{App, Arg, ArgMatches, SubCommand};
+use tokio_tls::TlsAcceptor;
 use nix::unistd::getpid;
 
 use librain::{server, worker, VERSION};
+use librain::admin::AdminClient;
+use librain::bench::{BenchClient, BenchConfig, BENCHMARKS};
+use librain::bundle::{BundleClient, SessionBundle};
 use librain::errors::Result;
+use librain::common::framing::Framing;
+use librain::common::tls;
+use librain::common::units;
+use librain::common::logging::shipping::ShippingLogger;
 
 const DEFAULT_SERVER_PORT: u16 = 7210;
 const DEFAULT_WORKER_PORT: u16 = 0;
+const DEFAULT_BROKER_PORT: u16 = 7211;
 
 const DEFAULT_HTTP_SERVER_PORT: u16 = 8080;
 
+const DEFAULT_INLINE_THRESHOLD: usize = 64 * 1024;
+
+// Memory for objects pinned by the worker of `rain start --in-process`
+const DEFAULT_PIN_MEMORY: usize = 1024 * 1024 * 1024;
+
 fn parse_listen_arg(key: &str, args: &ArgMatches, default_port: u16) -> SocketAddr {
     if !args.is_present(key) {
         return SocketAddr::new(IpAddr::V4(Ipv4Addr::new(0, 0, 0, 0)), default_port);
@@ -47,6 +67,67 @@ fn parse_listen_arg(key: &str, args: &ArgMatches, default_port: u16) -> SocketAd
     })
 }
 
+/// Name of the option of the argument in messages, e.g. "--drain-grace" for "DRAIN_GRACE"
+fn option_name(key: &str) -> String {
+    format!("--{}", key.to_lowercase().replace('_', "-"))
+}
+
+/// Size in bytes of the argument, units are accepted (e.g. "20GiB" or "512M")
+fn parse_size_arg(key: &str, args: &ArgMatches) -> Option<u64> {
+    args.value_of(key).map(|value| {
+        units::parse_size(&option_name(key), value).unwrap_or_else(|e| {
+            error!("{}", e);
+            exit(1);
+        })
+    })
+}
+
+/// Duration of the argument, a number of seconds or with units (e.g. "2h30m")
+fn parse_duration_arg(key: &str, args: &ArgMatches) -> Option<Duration> {
+    args.value_of(key).map(|value| {
+        units::parse_duration(&option_name(key), value).unwrap_or_else(|e| {
+            error!("{}", e);
+            exit(1);
+        })
+    })
+}
+
+/// Parse listening port, address or address:port given in a cluster spec
+fn parse_listen_value(value: &str, default_port: u16) -> Option<SocketAddr> {
+    value
+        .parse::<SocketAddr>()
+        .ok()
+        .or_else(|| {
+            value
+                .parse::<IpAddr>()
+                .ok()
+                .map(|ip| SocketAddr::new(ip, default_port))
+        })
+        .or_else(|| {
+            value
+                .parse::<u16>()
+                .ok()
+                .map(|port| SocketAddr::new(IpAddr::V4(Ipv4Addr::new(0, 0, 0, 0)), port))
+        })
+}
+
+/// Load TLS acceptor when both the certificate and the key argument are given
+fn parse_tls_args(cert_key: &str, key_key: &str, args: &ArgMatches) -> Option<TlsAcceptor> {
+    match (args.value_of(cert_key), args.value_of(key_key)) {
+        (None, None) => None,
+        (Some(cert), Some(key)) => Some(
+            tls::load_acceptor(Path::new(cert), Path::new(key)).unwrap_or_else(|e| {
+                error!("{}", e);
+                exit(1);
+            }),
+        ),
+        _ => {
+            error!("TLS requires both certificate and key");
+            exit(1);
+        }
+    }
+}
+
 fn run_server(_global_args: &ArgMatches, cmd_args: &ArgMatches) {
     let listen_address = parse_listen_arg("LISTEN_ADDRESS", cmd_args, DEFAULT_SERVER_PORT);
     let http_listen_address =
@@ -85,13 +166,154 @@ fn run_server(_global_args: &ArgMatches, cmd_args: &ArgMatches) {
         info!("TESTING mode enabled");
     }
 
+    let global_resources = ::librain::common::resources::ResourcePool::from_specs(
+        cmd_args.values_of("GLOBAL_RESOURCE").into_iter().flat_map(|v| v),
+    ).unwrap_or_else(|e| {
+        error!("{}", e);
+        exit(1);
+    });
+
+    let client_keepalive = parse_duration_arg("CLIENT_KEEPALIVE", cmd_args)
+        .and_then(|d| if d > Duration::from_secs(0) { Some(d) } else { None });
+    let orphan_grace =
+        parse_duration_arg("ORPHAN_GRACE", cmd_args).unwrap_or_else(|| Duration::from_secs(0));
+    let inline_threshold = parse_size_arg("INLINE_THRESHOLD", cmd_args)
+        .map(|size| size as usize)
+        .unwrap_or(DEFAULT_INLINE_THRESHOLD);
+    let scheduler_seed = cmd_args.value_of("SCHEDULER_SEED").map(|value| {
+        value.parse::<u64>().unwrap_or_else(|_| {
+            error!("Invalid scheduler seed '{}'", value);
+            exit(1);
+        })
+    });
+    let locality_weight = cmd_args.value_of("LOCALITY_WEIGHT").map(|value| {
+        match value.parse::<f64>() {
+            Ok(weight) if weight >= 0f64 => weight,
+            _ => {
+                error!("Invalid locality weight '{}'", value);
+                exit(1);
+            }
+        }
+    });
+    let placement = cmd_args
+        .value_of("PLACEMENT")
+        .map(|value| server::scheduler::Placement::parse(value).unwrap());
+    let scheduling_interval = parse_duration_arg("SCHEDULING_INTERVAL", cmd_args);
+    let invalidation_limit = cmd_args.value_of("INVALIDATION_LIMIT").map(|value| {
+        value.parse::<usize>().unwrap_or_else(|_| {
+            error!("Invalid invalidation limit '{}'", value);
+            exit(1);
+        })
+    });
+    let alerts = cmd_args.value_of("ALERTS").map(|path| {
+        let alerts = server::alerts::Alerts::load(Path::new(path)).unwrap_or_else(|e| {
+            error!("{}", e);
+            exit(1);
+        });
+        info!("{} alert rule(s) loaded", alerts.len());
+        alerts
+    });
+    let notifier = cmd_args.value_of("NOTIFICATIONS").map(|path| {
+        let notifier = server::notify::Notifier::load(Path::new(path)).unwrap_or_else(|e| {
+            error!("{}", e);
+            exit(1);
+        });
+        info!("{} notifier backend(s) loaded", notifier.len());
+        notifier
+    });
+    let mut redaction = server::redact::Redaction::new();
+    for rule in cmd_args.values_of("REDACT").into_iter().flat_map(|v| v) {
+        redaction.add_rule(rule).unwrap_or_else(|e| {
+            error!("{}", e);
+            exit(1);
+        });
+    }
+    if !redaction.is_empty() {
+        info!("{} redaction rule(s) set", redaction.len());
+    }
+    let access = cmd_args.value_of("ACCESS_FILE").map(|path| {
+        let access = server::access::AccessControl::load(Path::new(path)).unwrap_or_else(|e| {
+            error!("{}", e);
+            exit(1);
+        });
+        info!("Access control enabled, {} access token(s) loaded", access.len());
+        access
+    });
+    let rpc_tls = parse_tls_args("TLS_CERT", "TLS_KEY", cmd_args);
+    if rpc_tls.is_some() {
+        info!("RPC connections use TLS");
+    }
+    let http_tls = parse_tls_args("HTTP_TLS_CERT", "HTTP_TLS_KEY", cmd_args);
+    let mut http_config = server::http::HttpConfig::default();
+    if let Some(path) = cmd_args.value_of("HTTP_BASE_PATH") {
+        if !path.starts_with('/') {
+            error!("HTTP base path has to start with '/'");
+            exit(1);
+        }
+        let path = path.trim_right_matches('/');
+        if !server::http::is_valid_base_path(path) {
+            error!("HTTP base path may contain only letters, digits and '-._~/'");
+            exit(1);
+        }
+        http_config.base_path = path.to_string();
+    }
+    if let Some(proxies) = cmd_args.values_of("HTTP_TRUSTED_PROXY") {
+        http_config.trusted_proxies = proxies
+            .map(|value| {
+                value.parse().unwrap_or_else(|_| {
+                    error!("Invalid address of trusted proxy '{}'", value);
+                    exit(1);
+                })
+            })
+            .collect();
+    }
+    http_config.auth_header = cmd_args.value_of("HTTP_AUTH_HEADER").map(|s| s.to_string());
+    http_config.cors_origins = cmd_args
+        .values_of("HTTP_CORS_ORIGIN")
+        .map(|v| v.map(|s| s.to_string()).collect())
+        .unwrap_or_else(Vec::new);
+    if http_config.auth_header.is_some() && http_config.trusted_proxies.is_empty() {
+        error!("--http-auth-header requires --http-trusted-proxy");
+        exit(1);
+    }
+
     let state = server::state::StateRef::new(
         tokio_core.handle(),
         listen_address,
         http_listen_address,
         log_dir,
         test_mode,
+        global_resources,
+        client_keepalive,
+        orphan_grace,
+        inline_threshold,
+        access,
+        rpc_tls,
+        http_tls,
+        http_config,
     );
+    if let Some(seed) = scheduler_seed {
+        state.get_mut().set_scheduler_seed(seed);
+    }
+    if let Some(weight) = locality_weight {
+        state.get_mut().set_locality_weight(weight);
+    }
+    if let Some(placement) = placement {
+        state.get_mut().set_placement(placement);
+    }
+    if let Some(interval) = scheduling_interval {
+        state.get_mut().set_scheduling_interval(interval);
+    }
+    if let Some(limit) = invalidation_limit {
+        state.get_mut().set_invalidation_limit(limit);
+    }
+    if let Some(alerts) = alerts {
+        state.get_mut().set_alerts(alerts);
+    }
+    if let Some(notifier) = notifier {
+        state.get_mut().set_notifier(notifier);
+    }
+    state.get_mut().set_redaction(redaction);
     state.start();
 
     // Create ready file - a file that is created when server is ready
@@ -136,27 +358,52 @@ fn ensure_directory(dir: &Path, name: &str) -> Result<()> {
     Ok(())
 }
 
-fn run_worker(_global_args: &ArgMatches, cmd_args: &ArgMatches) {
-    let ready_file = cmd_args.value_of("READY_FILE");
-    let listen_address = parse_listen_arg("LISTEN_ADDRESS", cmd_args, DEFAULT_WORKER_PORT);
-    let mut server_address = cmd_args.value_of("SERVER_ADDRESS").unwrap().to_string();
-    if !server_address.contains(':') {
-        server_address = format!("{}:{}", server_address, DEFAULT_SERVER_PORT);
+/// Parse and resolve argument SERVER_ADDRESS (address or address:port)
+fn parse_server_address(cmd_args: &ArgMatches) -> (String, SocketAddr) {
+    resolve_address(
+        cmd_args.value_of("SERVER_ADDRESS").unwrap(),
+        DEFAULT_SERVER_PORT,
+    )
+}
+
+/// Resolve address or address:port
+fn resolve_address(address: &str, default_port: u16) -> (String, SocketAddr) {
+    let mut address = address.to_string();
+    if !address.contains(':') {
+        address = format!("{}:{}", address, default_port);
     }
 
-    let server_addr = match server_address.to_socket_addrs() {
+    let addr = match address.to_socket_addrs() {
         Err(_) => {
-            error!("Cannot resolve server address");
+            error!("Cannot resolve address {}", address);
             exit(1);
         }
         Ok(mut addrs) => match addrs.next() {
             None => {
-                error!("Cannot resolve server address");
+                error!("Cannot resolve address {}", address);
                 exit(1);
             }
             Some(ref addr) => *addr,
         },
     };
+    (address, addr)
+}
+
+fn run_worker(_global_args: &ArgMatches, cmd_args: &ArgMatches) {
+    let ready_file = cmd_args.value_of("READY_FILE");
+    let listen_address = parse_listen_arg("LISTEN_ADDRESS", cmd_args, DEFAULT_WORKER_PORT);
+    let (server_address, server_addr) = match cmd_args.value_of("BROKER") {
+        Some(broker) => {
+            let (broker_address, broker_addr) = resolve_address(broker, DEFAULT_BROKER_PORT);
+            let address = ::librain::broker::assign_worker(broker_addr).unwrap_or_else(|e| {
+                error!("Cannot get server from broker {}: {}", broker_address, e);
+                exit(1);
+            });
+            info!("Broker {} assigned server {}", broker_address, address);
+            resolve_address(&address, DEFAULT_SERVER_PORT)
+        }
+        None => parse_server_address(cmd_args),
+    };
 
     fn detect_cpus() -> i32 {
         debug!("Detecting number of cpus");
@@ -188,6 +435,56 @@ fn run_worker(_global_args: &ArgMatches, cmd_args: &ArgMatches) {
     };
     assert!(cpus >= 0);
 
+    let memory = if cmd_args.value_of("MEMORY") != Some("detect") {
+        parse_size_arg("MEMORY", cmd_args).unwrap()
+    } else {
+        debug!("Detecting size of memory");
+        ::librain::common::sys::get_total_memory().unwrap_or_else(|| {
+            error!("Autodetection of memory failed. Use --memory with the size in bytes.");
+            exit(1);
+        })
+    };
+
+    let gpus = if cmd_args.value_of("GPUS") != Some("detect") {
+        // Devices 0..N-1 (or the first N devices of CUDA_VISIBLE_DEVICES)
+        let n = value_t_or_exit!(cmd_args, "GPUS", usize);
+        let mut devices = ::librain::common::sys::get_gpu_devices();
+        if ::std::env::var("CUDA_VISIBLE_DEVICES").is_err() || devices.len() < n {
+            devices = (0..n as u32).collect();
+        }
+        devices.truncate(n);
+        devices
+    } else {
+        debug!("Detecting GPUs");
+        ::librain::common::sys::get_gpu_devices()
+    };
+
+    let named_resources = ::librain::common::resources::parse_resource_specs(
+        cmd_args.values_of("RESOURCE").into_iter().flat_map(|v| v),
+    ).unwrap_or_else(|e| {
+        error!("{}", e);
+        exit(1);
+    });
+
+    let labels = ::librain::common::labels::parse_labels(
+        cmd_args.values_of("LABEL").into_iter().flat_map(|v| v),
+    ).unwrap_or_else(|e| {
+        error!("{}", e);
+        exit(1);
+    });
+
+    // Limit of concurrently running tasks, independent on cpus
+    let max_parallel_tasks = if cmd_args.is_present("MAX_PARALLEL_TASKS") {
+        let value = value_t_or_exit!(cmd_args, "MAX_PARALLEL_TASKS", u32);
+        if value == 0 {
+            error!("--max-parallel-tasks has to be a positive number");
+            exit(1);
+        }
+        value
+    } else {
+        4 * ::std::cmp::max(cpus as u32, 1)
+    };
+
     let work_dir = cmd_args
         .value_of("WORK_DIR")
         .map(PathBuf::from)
@@ -210,34 +507,151 @@ fn run_worker(_global_args: &ArgMatches, cmd_args: &ArgMatches) {
 
     info!("Starting Rain {} worker", VERSION);
     info!("Resources: {} cpus", cpus);
+    info!("Max parallel tasks: {}", max_parallel_tasks);
     info!("Working directory: {:?}", work_dir);
     info!(
         "Server address {} was resolved as {}",
         server_address, server_addr
     );
 
+    let cost = if cmd_args.is_present("COST") {
+        let value = value_t_or_exit!(cmd_args, "COST", f64);
+        if value < 0f64 {
+            error!("Cost cannot be negative");
+            exit(1);
+        }
+        value
+    } else {
+        0f64
+    };
+
+    let drain_grace = if cmd_args.is_present("PREEMPTIBLE") {
+        let grace = parse_duration_arg("DRAIN_GRACE", cmd_args).unwrap();
+        info!("Worker is preemptible, grace period of draining is {:?}", grace);
+        Some(grace)
+    } else {
+        None
+    };
+
+    let pin_memory = parse_size_arg("PIN_MEMORY", cmd_args).unwrap() as usize;
+
+    let task_tmpfs = if cmd_args.is_present("TASK_TMPFS") {
+        let limit = parse_size_arg("TASK_TMPFS", cmd_args).unwrap() as usize;
+        info!("Tasks may use tmpfs working directories up to {} bytes", limit);
+        Some(limit)
+    } else {
+        None
+    };
+
+    let data_storage = worker::data::new_data_storage(cmd_args.value_of("DATA_STORAGE").unwrap())
+        .unwrap_or_else(|e| {
+            error!("{}", e);
+            exit(1);
+        });
+    info!("Data storage: {}", data_storage.name());
+
+    let numa = if cmd_args.is_present("NUMA") {
+        let numa = worker::numa::NumaTopology::detect().unwrap_or_else(|e| {
+            error!("{}", e);
+            exit(1);
+        });
+        info!("Subworkers are bound to NUMA nodes {:?}", numa.nodes());
+        Some(numa)
+    } else {
+        None
+    };
+
+    let framing = if cmd_args.is_present("COMPRESS_CONTROL") {
+        info!("Connection to server is compressed");
+        Framing::Compressed
+    } else {
+        Framing::Plain
+    };
+
     let mut tokio_core = tokio_core::reactor::Core::new().unwrap();
 
     let mut subworkers = HashMap::new();
     subworkers.insert(
-        "py".to_string(),
-        vec![
-            "python3".to_string(),
-            "-m".to_string(),
-            "rain.subworker".to_string(),
-        ],
+        worker::subworkers::PYTHON_SUBWORKER.to_string(),
+        worker::subworkers::SubworkerKind::python(),
     );
+    if let Some(path) = cmd_args.value_of("SUBWORKERS") {
+        let kinds = worker::subworkers::load_subworker_kinds(Path::new(path)).unwrap_or_else(|e| {
+            error!("{}", e);
+            exit(1);
+        });
+        subworkers.extend(kinds);
+    }
+    for spec in cmd_args.values_of("SUBWORKER").into_iter().flat_map(|v| v) {
+        let (subworker_type, kind) =
+            worker::subworkers::parse_subworker_spec(spec).unwrap_or_else(|e| {
+                error!("{}", e);
+                exit(1);
+            });
+        subworkers.insert(subworker_type, kind);
+    }
+    for (subworker_type, kind) in &subworkers {
+        info!("Subworker type '{}': {}", subworker_type, kind.command.join(" "));
+    }
 
     let state = worker::state::StateRef::new(
         tokio_core.handle(),
         work_dir,
         log_dir,
         cpus as u32,
-        // Python subworker
+        max_parallel_tasks,
         subworkers,
+        framing,
+        cmd_args.is_present("VIA_GATEWAY"),
+        cost,
+        drain_grace,
+        cmd_args.is_present("WARM_SUBWORKERS"),
+        pin_memory,
+        task_tmpfs,
+        numa,
+        data_storage,
+        cmd_args
+            .value_of("IMAGE")
+            .map(|s| s.to_string())
+            .unwrap_or_else(|| VERSION.to_string()),
     );
 
-    state.start(server_addr, listen_address, ready_file);
+    // Domain name of the server verified in its certificate
+    let server_tls = cmd_args.value_of("SERVER_TLS_CA").map(|ca| {
+        let connector = tls::load_connector(Path::new(ca)).unwrap_or_else(|e| {
+            error!("{}", e);
+            exit(1);
+        });
+        let domain = cmd_args
+            .value_of("SERVER_TLS_NAME")
+            .unwrap_or_else(|| server_address.rsplitn(2, ':').last().unwrap())
+            .to_string();
+        info!("Connection to server uses TLS (server name '{}')", domain);
+        (connector, domain)
+    });
+
+    let subworker_timeout = parse_duration_arg("SUBWORKER_TIMEOUT", cmd_args).unwrap();
+    state.get_mut().set_subworker_timeout(subworker_timeout);
+    state.get_mut().set_memory(memory);
+    state.get_mut().set_gpus(gpus);
+    state.get_mut().set_named_resources(named_resources);
+    state.get_mut().set_rejoin(cmd_args.is_present("BROKER"));
+    state.get_mut().set_labels(labels);
+
+    if cmd_args.is_present("MAX_TASK_OUTPUT") {
+        let limit = parse_size_arg("MAX_TASK_OUTPUT", cmd_args).unwrap();
+        state.get_mut().set_task_output_limit(Some(limit));
+    }
+    if let Some(capacity) = parse_size_arg("DOWNLOAD_CACHE", cmd_args) {
+        state.get_mut().set_download_cache(capacity);
+    }
+
+    let ship_logs = cmd_args.is_present("SHIP_LOGS");
+    if ship_logs {
+        info!("Warnings and errors are shipped to server");
+    }
+
+    state.start(server_addr, server_tls, listen_address, ready_file, ship_logs);
 
     loop {
         tokio_core.turn(None);
@@ -245,13 +659,226 @@ fn run_worker(_global_args: &ArgMatches, cmd_args: &ArgMatches) {
     }
 }
 
-fn run_starter(_global_args: &ArgMatches, cmd_args: &ArgMatches) {
+fn run_admin(_global_args: &ArgMatches, cmd_args: &ArgMatches) {
+    match cmd_args.subcommand() {
+        ("upgrade", Some(cmd_args)) => {
+            let (_, server_addr) = parse_server_address(cmd_args);
+            let token = ::std::env::var("RAIN_TOKEN").unwrap_or_default();
+            let batch = value_t_or_exit!(cmd_args, "BATCH", u32);
+            let result = AdminClient::connect(server_addr, &token).and_then(|mut client| {
+                client.upgrade_workers(
+                    cmd_args.value_of("IMAGE").unwrap(),
+                    batch,
+                    cmd_args.is_present("WAIT"),
+                )
+            });
+            if let Err(e) = result {
+                error!("{}", e);
+                exit(1);
+            }
+        }
+        ("scheduler", Some(cmd_args)) => {
+            let (_, server_addr) = parse_server_address(cmd_args);
+            let token = ::std::env::var("RAIN_TOKEN").unwrap_or_default();
+            let mut config = serde_json::Map::new();
+            if cmd_args.is_present("PAUSE") || cmd_args.is_present("RESUME") {
+                config.insert("paused".into(), cmd_args.is_present("PAUSE").into());
+            }
+            if let Some(placement) = cmd_args.value_of("PLACEMENT") {
+                config.insert("placement".into(), placement.into());
+            }
+            if cmd_args.is_present("LOCALITY_WEIGHT") {
+                let weight = value_t_or_exit!(cmd_args, "LOCALITY_WEIGHT", f64);
+                config.insert("locality_weight".into(), weight.into());
+            }
+            if let Some(interval) = parse_duration_arg("SCHEDULING_INTERVAL", cmd_args) {
+                config.insert(
+                    "scheduling_interval".into(),
+                    units::duration_secs(interval).into(),
+                );
+            }
+            let config = serde_json::Value::Object(config).to_string();
+            let result = AdminClient::connect(server_addr, &token).and_then(|mut client| {
+                println!("{}", client.configure_scheduler(&config)?);
+                if cmd_args.is_present("DRAIN") {
+                    client.wait_for_drain()?;
+                }
+                Ok(())
+            });
+            if let Err(e) = result {
+                error!("{}", e);
+                exit(1);
+            }
+        }
+        _ => {
+            error!("No admin command provided.");
+            exit(1);
+        }
+    }
+}
+
+fn run_bench(_global_args: &ArgMatches, cmd_args: &ArgMatches) {
+    let (_, server_addr) = parse_server_address(cmd_args);
+    let token = ::std::env::var("RAIN_TOKEN").unwrap_or_default();
+    let config = BenchConfig {
+        tasks: value_t_or_exit!(cmd_args, "TASKS", usize),
+        bytes: value_t_or_exit!(cmd_args, "BYTES", usize),
+        repeats: value_t_or_exit!(cmd_args, "REPEATS", usize),
+    };
+    let benchmarks: Vec<&str> = cmd_args
+        .values_of("BENCHMARK")
+        .map(|v| v.collect())
+        .unwrap_or_else(|| BENCHMARKS.to_vec());
+    let result = BenchClient::connect(server_addr, &token).and_then(|mut client| {
+        for name in benchmarks {
+            info!("Running benchmark '{}'", name);
+            for result in client.run(name, &config)? {
+                println!("{}", serde_json::to_string(&result)?);
+            }
+        }
+        Ok(())
+    });
+    if let Err(e) = result {
+        error!("{}", e);
+        exit(1);
+    }
+}
+
+fn run_export_session(_global_args: &ArgMatches, cmd_args: &ArgMatches) {
+    let (_, server_addr) = parse_server_address(cmd_args);
+    let token = ::std::env::var("RAIN_TOKEN").unwrap_or_default();
+    let session_id = value_t_or_exit!(cmd_args, "SESSION_ID", i32);
+    let result = BundleClient::connect(server_addr, &token).and_then(|mut client| {
+        let bundle = client.export(session_id)?;
+        match cmd_args.value_of("OUTPUT") {
+            Some(path) => ::std::fs::File::create(path)
+                .and_then(|mut file| file.write_all(bundle.as_bytes()))
+                .map_err(|e| format!("Cannot write {}: {}", path, e).into()),
+            None => {
+                println!("{}", bundle);
+                Ok(())
+            }
+        }
+    });
+    if let Err(e) = result {
+        error!("{}", e);
+        exit(1);
+    }
+}
+
+fn run_import_session(_global_args: &ArgMatches, cmd_args: &ArgMatches) {
+    let (_, server_addr) = parse_server_address(cmd_args);
+    let token = ::std::env::var("RAIN_TOKEN").unwrap_or_default();
+    let result = SessionBundle::load(Path::new(cmd_args.value_of("BUNDLE").unwrap()))
+        .and_then(|bundle| {
+            let mut client = BundleClient::connect(server_addr, &token)?;
+            client.import(&bundle, cmd_args.value_of("DATA").map(Path::new))
+        });
+    match result {
+        Ok(session_id) => println!("{}", session_id),
+        Err(e) => {
+            error!("{}", e);
+            exit(1);
+        }
+    }
+}
+
+fn run_gateway(_global_args: &ArgMatches, cmd_args: &ArgMatches) {
     let listen_address = parse_listen_arg("LISTEN_ADDRESS", cmd_args, DEFAULT_SERVER_PORT);
-    let http_listen_address =
-        parse_listen_arg("HTTP_LISTEN_ADDRESS", cmd_args, DEFAULT_HTTP_SERVER_PORT);
+    let (server_address, server_addr) = parse_server_address(cmd_args);
+
+    info!("Starting Rain {} gateway", VERSION);
+    info!(
+        "Server address {} was resolved as {}",
+        server_address, server_addr
+    );
+
+    let mut tokio_core = tokio_core::reactor::Core::new().unwrap();
+    ::librain::gateway::start_gateway(&tokio_core.handle(), listen_address, server_addr)
+        .unwrap_or_else(|e| {
+            error!("Starting gateway failed: {}", e);
+            exit(1);
+        });
+
+    loop {
+        tokio_core.turn(None);
+    }
+}
+
+fn run_broker(_global_args: &ArgMatches, cmd_args: &ArgMatches) {
+    let listen_address = parse_listen_arg("LISTEN_ADDRESS", cmd_args, DEFAULT_BROKER_PORT);
+    let servers: Vec<_> = cmd_args
+        .values_of("SERVER_ADDRESS")
+        .unwrap()
+        .map(|address| resolve_address(address, DEFAULT_SERVER_PORT))
+        .collect();
+    let interval = parse_duration_arg("INTERVAL", cmd_args).unwrap();
+    let token = ::std::env::var("RAIN_TOKEN").unwrap_or_default();
+
+    info!("Starting Rain {} broker", VERSION);
+    for &(ref address, ref addr) in &servers {
+        info!("Server {} was resolved as {}", address, addr);
+    }
+
+    let mut tokio_core = tokio_core::reactor::Core::new().unwrap();
+    ::librain::broker::start_broker(
+        &tokio_core.handle(),
+        listen_address,
+        servers,
+        token,
+        interval,
+    ).unwrap_or_else(|e| {
+        error!("Starting broker failed: {}", e);
+        exit(1);
+    });
+
+    loop {
+        tokio_core.turn(None);
+    }
+}
+
+fn run_starter(_global_args: &ArgMatches, cmd_args: &ArgMatches) {
+    let spec = cmd_args.value_of("SPEC").map(|path| {
+        start::spec::ClusterSpec::load(Path::new(path)).unwrap_or_else(|e| {
+            error!("{}", e);
+            exit(1);
+        })
+    });
+    if spec.is_some()
+        && ["SIMPLE", "LOCAL_WORKERS", "WORKER_HOST_FILE", "AUTOCONF"]
+            .iter()
+            .any(|name| cmd_args.is_present(name))
+    {
+        error!("--spec cannot be combined with options defining workers");
+        exit(1);
+    }
+    // Options of the command line take precedence over the spec
+    let spec_address = |value: Option<&String>, default_port| {
+        value.map(|value| {
+            parse_listen_value(value, default_port).unwrap_or_else(|| {
+                error!("Invalid listening address '{}' in cluster spec", value);
+                exit(1);
+            })
+        })
+    };
+    let listen_address = match spec {
+        Some(ref spec) if !cmd_args.is_present("LISTEN_ADDRESS") => {
+            spec_address(spec.server.listen.as_ref(), DEFAULT_SERVER_PORT)
+        }
+        _ => None,
+    }.unwrap_or_else(|| parse_listen_arg("LISTEN_ADDRESS", cmd_args, DEFAULT_SERVER_PORT));
+    let http_listen_address = match spec {
+        Some(ref spec) if !cmd_args.is_present("HTTP_LISTEN_ADDRESS") => {
+            spec_address(spec.server.http_listen.as_ref(), DEFAULT_HTTP_SERVER_PORT)
+        }
+        _ => None,
+    }.unwrap_or_else(|| {
+        parse_listen_arg("HTTP_LISTEN_ADDRESS", cmd_args, DEFAULT_HTTP_SERVER_PORT)
+    });
     let log_dir = cmd_args
         .value_of("LOG_DIR")
         .map(PathBuf::from)
+        .or_else(|| spec.as_ref().and_then(|spec| spec.log_dir.clone()))
         .unwrap_or_else(|| default_logging_directory("rain"));
 
     info!("Starting Rain {}", VERSION);
@@ -269,6 +896,11 @@ fn run_starter(_global_args: &ArgMatches, cmd_args: &ArgMatches) {
         exit(1);
     }
 
+    if cmd_args.is_present("IN_PROCESS") {
+        run_in_process(cmd_args, listen_address, http_listen_address, &log_dir);
+        return;
+    }
+
     if cmd_args.is_present("SIMPLE") {
         local_workers.push(None);
     }
@@ -306,6 +938,32 @@ fn run_starter(_global_args: &ArgMatches, cmd_args: &ArgMatches) {
     );
 
     config.worker_host_file = cmd_args.value_of("WORKER_HOST_FILE").map(PathBuf::from);
+    config.compress_control = cmd_args.is_present("COMPRESS_CONTROL");
+    config.remote_launcher =
+        start::launcher::RemoteLauncher::parse(cmd_args.value_of("REMOTE_LAUNCHER").unwrap())
+            .unwrap_or_else(|e| {
+                error!("{}", e);
+                exit(1);
+            });
+    config.global_resources = cmd_args
+        .values_of("GLOBAL_RESOURCE")
+        .map(|v| v.map(|s| s.to_string()).collect())
+        .unwrap_or_else(Vec::new);
+
+    if let Some(ref spec) = spec {
+        spec.apply(&mut config).unwrap_or_else(|e| {
+            error!("Invalid cluster spec: {}", e);
+            exit(1);
+        });
+        if let Some(remote_init) = cmd_args.value_of("REMOTE_INIT") {
+            config.remote_init = remote_init.to_string();
+        }
+        if cmd_args.occurrences_of("REMOTE_LAUNCHER") > 0 {
+            config.remote_launcher = start::launcher::RemoteLauncher::parse(
+                cmd_args.value_of("REMOTE_LAUNCHER").unwrap(),
+            ).unwrap();
+        }
+    }
 
     // Autoconf
     match cmd_args.value_of("AUTOCONF") {
@@ -322,16 +980,142 @@ fn run_starter(_global_args: &ArgMatches, cmd_args: &ArgMatches) {
         .unwrap();
 
     // Ignite starter
+    start::starter::install_interrupt_handlers();
     let mut starter = start::starter::Starter::new(config);
 
     match starter.start() {
-        Ok(()) => info!("Rain started. \u{1F327}"),
+        Ok(()) => {
+            info!("Rain started. \u{1F327}");
+            if cmd_args.is_present("WAIT") {
+                info!("Press Ctrl-C to stop the server and workers");
+                if let Err(e) = starter.wait() {
+                    error!("{}", e.description());
+                }
+                info!("Stopping started processes ...");
+                starter.shutdown();
+            }
+        }
         Err(e) => {
-            error!("{}", e.description());
-            if starter.has_processes() {
+            if start::starter::is_interrupted() {
+                info!("Interrupted; stopping started processes ...");
+            } else {
+                error!("{}", e.description());
                 info!("Error occurs; clean up started processes ...");
-                starter.kill_all();
             }
+            if starter.has_processes() {
+                starter.shutdown();
+            }
+        }
+    }
+}
+
+/// Run the server and one worker in this process on one reactor (`--simple --in-process`);
+/// the worker is connected to the server by a socket pair, no processes are started
+fn run_in_process(
+    cmd_args: &ArgMatches,
+    listen_address: SocketAddr,
+    http_listen_address: SocketAddr,
+    log_dir: &Path,
+) {
+    let server_log_dir = log_dir.join("server");
+    let worker_log_dir = log_dir.join("worker");
+    let work_dir = cmd_args
+        .value_of("WORK_DIR")
+        .map(PathBuf::from)
+        .unwrap_or_else(default_working_directory);
+    for &(dir, name) in &[
+        (&server_log_dir, "logging directory"),
+        (&worker_log_dir, "logging directory"),
+        (&work_dir, "working directory"),
+    ] {
+        ensure_directory(dir, name).unwrap_or_else(|e| {
+            error!("{}", e);
+            exit(1);
+        });
+    }
+
+    let global_resources = ::librain::common::resources::ResourcePool::from_specs(
+        cmd_args.values_of("GLOBAL_RESOURCE").into_iter().flat_map(|v| v),
+    ).unwrap_or_else(|e| {
+        error!("{}", e);
+        exit(1);
+    });
+    let test_mode = ::std::env::var("RAIN_TEST_MODE")
+        .map(|s| s == "1")
+        .unwrap_or(false);
+    let cpus = ::std::cmp::max(num_cpus::get() as u32, 1);
+    let memory = ::librain::common::sys::get_total_memory().unwrap_or_else(|| {
+        error!("Autodetection of memory failed");
+        exit(1);
+    });
+    let data_storage = worker::data::new_data_storage("disk").unwrap();
+    let mut subworkers = HashMap::new();
+    subworkers.insert(
+        worker::subworkers::PYTHON_SUBWORKER.to_string(),
+        worker::subworkers::SubworkerKind::python(),
+    );
+
+    info!("Server and worker run in one process");
+    info!("Resources of worker: {} cpus", cpus);
+    info!("Working directory: {:?}", work_dir);
+
+    let mut tokio_core = tokio_core::reactor::Core::new().unwrap();
+    let handle = tokio_core.handle();
+
+    let server = server::state::StateRef::new(
+        handle.clone(),
+        listen_address,
+        http_listen_address,
+        server_log_dir,
+        test_mode,
+        global_resources,
+        None,
+        Duration::from_secs(0),
+        DEFAULT_INLINE_THRESHOLD,
+        None,
+        None,
+        None,
+        server::http::HttpConfig::default(),
+    );
+    server.start();
+
+    let worker = worker::state::StateRef::new(
+        handle.clone(),
+        work_dir,
+        worker_log_dir,
+        cpus,
+        4 * cpus,
+        subworkers,
+        Framing::Plain,
+        false,
+        0f64,
+        None,
+        false,
+        DEFAULT_PIN_MEMORY,
+        None,
+        None,
+        data_storage,
+        VERSION.to_string(),
+    );
+    worker.get_mut().set_memory(memory);
+    worker.get_mut().set_gpus(::librain::common::sys::get_gpu_devices());
+
+    let (server_stream, worker_stream) =
+        ::tokio_uds::UnixStream::pair(&handle).unwrap_or_else(|e| {
+            error!("Cannot create connection of worker: {}", e);
+            exit(1);
+        });
+    server.connect_local_worker(server_stream);
+    // Other workers do not exist, the worker listens only for fetches of the server
+    let worker_listen_address = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 0);
+    worker.start_in_process(worker_stream, worker_listen_address, false);
+    info!("Rain started. \u{1F327}");
+
+    loop {
+        tokio_core.turn(None);
+        worker.turn();
+        if !server.turn() {
+            break;
         }
     }
 }
@@ -342,7 +1126,7 @@ fn init_log() {
     if std::env::var("RUST_LOG").is_err() {
         std::env::set_var("RUST_LOG", "info");
     }
-    if ::atty::is(::atty::Stream::Stdout) {
+    let logger = if ::atty::is(::atty::Stream::Stdout) {
         ::env_logger::Builder::new()
             .format(|buf, record| {
                 use env_logger::Color;
@@ -371,10 +1155,14 @@ fn init_log() {
                 )
             })
             .parse(&::std::env::var("RUST_LOG").unwrap_or("info".into()))
-            .init();
+            .build()
     } else {
-        ::env_logger::init();
-    }
+        ::env_logger::Builder::from_default_env().build()
+    };
+    // Warnings are passed to the shipping logger even when they are filtered out locally
+    let max_level = ::std::cmp::max(logger.filter(), ::log::LevelFilter::Warn);
+    ::log::set_boxed_logger(Box::new(ShippingLogger::new(logger))).unwrap();
+    ::log::set_max_level(max_level);
 }
 
 fn main() {
@@ -405,13 +1193,132 @@ fn main() {
                 .arg(Arg::with_name("READY_FILE")
                     .long("--ready-file")
                     .help("Create a file when server is initialized and ready to accept connections")
+                    .takes_value(true))
+                .arg(Arg::with_name("GLOBAL_RESOURCE")
+                    .long("--global-resource")
+                    .value_name("NAME=COUNT")
+                    .help("Cluster-wide counted resource, e.g. license:gurobi=16 (may be repeated)")
+                    .multiple(true)
+                    .number_of_values(1)
+                    .takes_value(true))
+                .arg(Arg::with_name("CLIENT_KEEPALIVE")
+                    .long("--client-keepalive")
+                    .value_name("DURATION")
+                    .help("Interval of keepalive probes detecting disappeared clients, e.g. 30s (default = disabled)")
+                    .takes_value(true))
+                .arg(Arg::with_name("ORPHAN_GRACE")
+                    .long("--orphan-grace")
+                    .value_name("DURATION")
+                    .help("How long sessions of a disconnected client are kept before cancelling, e.g. 10m (default = 0)")
+                    .takes_value(true))
+                .arg(Arg::with_name("INLINE_THRESHOLD")
+                    .long("--inline-threshold")
+                    .value_name("SIZE")
+                    .help("Objects up to this size are sent inline in messages (default = 64KiB)")
+                    .takes_value(true))
+                .arg(Arg::with_name("SCHEDULER_SEED")
+                    .long("--scheduler-seed")
+                    .value_name("SEED")
+                    .help("Deterministic scheduling: the same graph gets the same placement in every run")
+                    .takes_value(true))
+                .arg(Arg::with_name("LOCALITY_WEIGHT")
+                    .long("--locality-weight")
+                    .value_name("WEIGHT")
+                    .help("How much the scheduler prefers workers holding inputs of a task, 0 ignores locality (default = 1)")
+                    .takes_value(true))
+                .arg(Arg::with_name("PLACEMENT")
+                    .long("--placement")
+                    .value_name("POLICY")
+                    .help("Placement of tasks among equally good workers: any, pack (fill busy workers first) or spread (default = any)")
+                    .possible_values(&["any", "pack", "spread"])
+                    .takes_value(true))
+                .arg(Arg::with_name("SCHEDULING_INTERVAL")
+                    .long("--scheduling-interval")
+                    .value_name("DURATION")
+                    .help("Newly ready tasks are collected and placed in one scheduling round at most this often, e.g. 200ms (default = 0, after every event)")
+                    .takes_value(true))
+                .arg(Arg::with_name("INVALIDATION_LIMIT")
+                    .long("--invalidation-limit")
+                    .value_name("TASKS")
+                    .help("Max number of tasks computed again when a client invalidates a corrupt object, 0 disables invalidations (default = 1000)")
+                    .takes_value(true))
+                .arg(Arg::with_name("ALERTS")
+                    .long("--alerts")
+                    .value_name("FILE")
+                    .help("JSON file with alert rules (e.g. age of ready tasks, missing workers, session failure rate) that log events and call webhooks")
+                    .takes_value(true))
+                .arg(Arg::with_name("NOTIFICATIONS")
+                    .long("--notifications")
+                    .value_name("FILE")
+                    .help("JSON file with notifier backends (webhook, slack, smtp) of finished and failed sessions and alerts")
+                    .takes_value(true))
+                .arg(Arg::with_name("REDACT")
+                    .long("--redact")
+                    .value_name("SCOPE:PATTERN")
+                    .help("Redact values of task configs (config:), environment variables (env:) or attributes (attribute:) with names matching the pattern in event logs, dashboard and exports, e.g. config:*password* (may be repeated)")
+                    .multiple(true)
+                    .number_of_values(1)
+                    .takes_value(true))
+                .arg(Arg::with_name("ACCESS_FILE")
+                    .long("--access-file")
+                    .value_name("FILE")
+                    .help("File with lines 'NAME ROLE TOKEN' (roles: admin, user, readonly); clients have to authenticate")
+                    .takes_value(true))
+                .arg(Arg::with_name("TLS_CERT")
+                    .long("--tls-cert")
+                    .value_name("FILE")
+                    .help("Certificate (PEM) for TLS on RPC connections of clients and workers")
+                    .takes_value(true))
+                .arg(Arg::with_name("TLS_KEY")
+                    .long("--tls-key")
+                    .value_name("FILE")
+                    .help("Private key (PEM, PKCS#8) for TLS on RPC connections")
+                    .takes_value(true))
+                .arg(Arg::with_name("HTTP_TLS_CERT")
+                    .long("--http-tls-cert")
+                    .value_name("FILE")
+                    .help("Certificate (PEM) for HTTPS dashboard")
+                    .takes_value(true))
+                .arg(Arg::with_name("HTTP_TLS_KEY")
+                    .long("--http-tls-key")
+                    .value_name("FILE")
+                    .help("Private key (PEM, PKCS#8) for HTTPS dashboard")
+                    .takes_value(true))
+                .arg(Arg::with_name("HTTP_BASE_PATH")
+                    .long("--http-base-path")
+                    .value_name("PATH")
+                    .help("URL path under which a reverse proxy exposes the dashboard, e.g. /rain")
+                    .takes_value(true))
+                .arg(Arg::with_name("HTTP_TRUSTED_PROXY")
+                    .long("--http-trusted-proxy")
+                    .value_name("IP")
+                    .help("Address of a reverse proxy whose X-Forwarded-* headers are trusted (may be repeated)")
+                    .multiple(true)
+                    .number_of_values(1)
+                    .takes_value(true))
+                .arg(Arg::with_name("HTTP_AUTH_HEADER")
+                    .long("--http-auth-header")
+                    .value_name("NAME")
+                    .help("Header with the user authenticated by a trusted proxy, e.g. X-Forwarded-User")
+                    .takes_value(true))
+                .arg(Arg::with_name("HTTP_CORS_ORIGIN")
+                    .long("--http-cors-origin")
+                    .value_name("ORIGIN")
+                    .help("Origin allowed to use JSON API from browsers, e.g. https://ui.example.com or * (may be repeated)")
+                    .multiple(true)
+                    .number_of_values(1)
                     .takes_value(true)))
         .subcommand( // ---- WORKER ----
             SubCommand::with_name("worker")
                 .about("Rain worker")
                 .arg(Arg::with_name("SERVER_ADDRESS")
                     .help("Listening address: port/address/address:port (default 0.0.0.0:7210)")
-                    .required(true))
+                    .required_unless("BROKER"))
+                .arg(Arg::with_name("BROKER")
+                    .long("--broker")
+                    .value_name("ADDRESS")
+                    .help("Ask the broker of session shards for the server (address/address:port, default port 7211); SERVER_ADDRESS is ignored")
+                    .takes_value(true))
                 .arg(Arg::with_name("LISTEN_ADDRESS")
                     .short("l")
                     .long("--listen")
@@ -423,6 +1330,35 @@ fn main() {
                     .help("Number of cpus or 'detect' (default = detect)")
                     .value_name("N")
                     .default_value("detect"))
+                .arg(Arg::with_name("MEMORY")
+                    .long("--memory")
+                    .help("Memory for tasks (e.g. 16GiB) or 'detect' (default = detect, total memory of the machine)")
+                    .value_name("SIZE")
+                    .default_value("detect"))
+                .arg(Arg::with_name("GPUS")
+                    .long("--gpus")
+                    .help("Number of GPUs or 'detect' (default = detect, CUDA_VISIBLE_DEVICES or /dev/nvidia*)")
+                    .value_name("N")
+                    .default_value("detect"))
+                .arg(Arg::with_name("RESOURCE")
+                    .long("--resource")
+                    .value_name("NAME=COUNT")
+                    .help("Counted resource of the worker requested by tasks, e.g. fpga=1 (may be repeated)")
+                    .multiple(true)
+                    .number_of_values(1)
+                    .takes_value(true))
+                .arg(Arg::with_name("LABEL")
+                    .long("--label")
+                    .value_name("KEY=VALUE")
+                    .help("Label of the worker matched by placement constraints of tasks, e.g. rack=a (may be repeated)")
+                    .multiple(true)
+                    .number_of_values(1)
+                    .takes_value(true))
+                .arg(Arg::with_name("MAX_PARALLEL_TASKS")
+                    .long("--max-parallel-tasks")
+                    .help("Maximal number of concurrently running tasks, independently on cpus (default = 4 * cpus)")
+                    .value_name("N")
+                    .takes_value(true))
                 .arg(Arg::with_name("WORK_DIR")
                     .long("--workdir")
                     .help("Workding directory (default /tmp/rain-work/worker-$HOSTANE-$PID)")
@@ -436,18 +1372,253 @@ fn main() {
                     .long("--ready-file")
                     .value_name("DIR")
                     .help("Create a file when worker is initialized and connected to the server")
+                    .takes_value(true))
+                .arg(Arg::with_name("COMPRESS_CONTROL")
+                    .long("--compress-control")
+                    .help("Compress the connection to server (useful when server is far away)"))
+                .arg(Arg::with_name("VIA_GATEWAY")
+                    .long("--via-gateway")
+                    .help("SERVER_ADDRESS is an address of a gateway (see 'rain gateway')"))
+                .arg(Arg::with_name("SHIP_LOGS")
+                    .long("--ship-logs")
+                    .help("Send warnings and errors to server (they appear in its log and events)"))
+                .arg(Arg::with_name("SERVER_TLS_CA")
+                    .long("--server-tls-ca")
+                    .value_name("FILE")
+                    .help("Connect to server by TLS, the server certificate is verified by this CA certificate (PEM)")
+                    .takes_value(true))
+                .arg(Arg::with_name("SERVER_TLS_NAME")
+                    .long("--server-tls-name")
+                    .value_name("NAME")
+                    .help("Name expected in the server certificate (default = host of SERVER_ADDRESS)")
+                    .takes_value(true))
+                .arg(Arg::with_name("COST")
+                    .long("--cost")
+                    .value_name("COST")
+                    .help("Cost of running on the worker per cpu, e.g. spot price (default 0)")
+                    .takes_value(true))
+                .arg(Arg::with_name("WARM_SUBWORKERS")
+                    .long("--warm-subworkers")
+                    .help("Start subworkers by forking an initialized process (see RAIN_SUBWORKER_PRELOAD)"))
+                .arg(Arg::with_name("PREEMPTIBLE")
+                    .long("--preemptible")
+                    .help("Worker may be evicted (e.g. spot instance), it drains on SIGTERM"))
+                .arg(Arg::with_name("DRAIN_GRACE")
+                    .long("--drain-grace")
+                    .value_name("DURATION")
+                    .help("How long may the draining of a preemptible worker take (default 30s)")
+                    .default_value("30"))
+                .arg(Arg::with_name("PIN_MEMORY")
+                    .long("--pin-memory")
+                    .value_name("SIZE")
+                    .help("Memory budget for objects with attribute 'pin_in_memory' (default 1GiB)")
+                    .default_value("1GiB"))
+                .arg(Arg::with_name("TASK_TMPFS")
+                    .long("--task-tmpfs")
+                    .value_name("SIZE")
+                    .help("Memory budget for working directories of tasks with attribute 'tmpfs', mounted as tmpfs (requires privileges to mount; default: disabled)")
+                    .takes_value(true))
+                .arg(Arg::with_name("NUMA")
+                    .long("--numa")
+                    .help("Bind subworkers to NUMA nodes and run tasks on nodes holding their inputs (requires numactl)"))
+                .arg(Arg::with_name("DATA_STORAGE")
+                    .long("--data-storage")
+                    .value_name("STORAGE")
+                    .possible_values(&["disk", "memory"])
+                    .help("Placement of data of objects: 'disk' keeps larger objects in files, 'memory' keeps all objects in memory (default disk)")
+                    .default_value("disk"))
+                .arg(Arg::with_name("IMAGE")
+                    .long("--image")
+                    .value_name("NAME")
+                    .help("Installation of the worker announced to the server, used by rolling upgrades (default: version of rain)")
+                    .takes_value(true))
+                .arg(Arg::with_name("SUBWORKER")
+                    .long("--subworker")
+                    .value_name("TYPE=COMMAND")
+                    .help("Start subworkers of TYPE by COMMAND (split by whitespace); may be repeated")
+                    .multiple(true)
+                    .number_of_values(1))
+                .arg(Arg::with_name("SUBWORKERS")
+                    .long("--subworkers")
+                    .value_name("FILE")
+                    .help("JSON file with subworker types, see 'Subworker types' in the documentation")
+                    .takes_value(true))
+                .arg(Arg::with_name("SUBWORKER_TIMEOUT")
+                    .long("--subworker-timeout")
+                    .value_name("DURATION")
+                    .help("Time for a started subworker to register, then it is killed and its task fails (default 60s)")
+                    .default_value("60"))
+                .arg(Arg::with_name("MAX_TASK_OUTPUT")
+                    .long("--max-task-output")
+                    .value_name("SIZE")
+                    .help("Max. bytes kept of stdout/stderr of each program, the beginning and the end are kept (default: unlimited)")
+                    .takes_value(true))
+                .arg(Arg::with_name("DOWNLOAD_CACHE")
+                    .long("--download-cache")
+                    .value_name("SIZE")
+                    .help("Size of the cache of files downloaded by tasks, 0 disables it (default 1GiB)")
                     .takes_value(true)))
+        .subcommand( // ---- ADMIN ----
+            SubCommand::with_name("admin")
+                .about("Administration of a running server (token is taken from RAIN_TOKEN)")
+                .subcommand(SubCommand::with_name("upgrade")
+                    .about("Rolling upgrade: drain workers with another image in batches and let them leave")
+                    .arg(Arg::with_name("SERVER_ADDRESS")
+                        .help("Server address: address/address:port (default port 7210)")
+                        .required(true))
+                    .arg(Arg::with_name("IMAGE")
+                        .long("--image")
+                        .value_name("NAME")
+                        .help("Image of upgraded workers (their --image)")
+                        .required(true))
+                    .arg(Arg::with_name("BATCH")
+                        .long("--batch")
+                        .value_name("N")
+                        .help("Maximal number of workers drained at once (default 1)")
+                        .default_value("1"))
+                    .arg(Arg::with_name("WAIT")
+                        .long("--wait")
+                        .help("Wait until the upgrade is finished")))
+                .subcommand(SubCommand::with_name("scheduler")
+                    .about("Pause or resume scheduling and change the scheduler at runtime, prints its status as JSON")
+                    .arg(Arg::with_name("SERVER_ADDRESS")
+                        .help("Server address: address/address:port (default port 7210)")
+                        .required(true))
+                    .arg(Arg::with_name("PAUSE")
+                        .long("--pause")
+                        .help("No tasks are assigned to workers, running tasks continue")
+                        .conflicts_with("RESUME"))
+                    .arg(Arg::with_name("RESUME")
+                        .long("--resume")
+                        .help("Resume paused scheduling"))
+                    .arg(Arg::with_name("DRAIN")
+                        .long("--drain")
+                        .help("Wait until no tasks run on workers (use with --pause)"))
+                    .arg(Arg::with_name("PLACEMENT")
+                        .long("--placement")
+                        .value_name("POLICY")
+                        .help("Placement of tasks among equally good workers (see 'rain server --placement')")
+                        .possible_values(&["any", "pack", "spread"])
+                        .takes_value(true))
+                    .arg(Arg::with_name("LOCALITY_WEIGHT")
+                        .long("--locality-weight")
+                        .value_name("WEIGHT")
+                        .help("How much the scheduler prefers workers holding inputs of a task")
+                        .takes_value(true))
+                    .arg(Arg::with_name("SCHEDULING_INTERVAL")
+                        .long("--scheduling-interval")
+                        .value_name("DURATION")
+                        .help("Interval of scheduling rounds, 0 schedules after every event")
+                        .takes_value(true))))
+        .subcommand( // ---- BENCH ----
+            SubCommand::with_name("bench")
+                .about("Run benchmarks against a running cluster, results are printed as JSON lines")
+                .arg(Arg::with_name("SERVER_ADDRESS")
+                    .help("Server address: address/address:port (default port 7210)")
+                    .required(true))
+                .arg(Arg::with_name("BENCHMARK")
+                    .long("--benchmark")
+                    .value_name("NAME")
+                    .help("Benchmark to run (may be repeated, default = all)")
+                    .possible_values(BENCHMARKS)
+                    .multiple(true)
+                    .number_of_values(1)
+                    .takes_value(true))
+                .arg(Arg::with_name("TASKS")
+                    .long("--tasks")
+                    .value_name("N")
+                    .help("Number of tasks of task-throughput and submission-latency graphs")
+                    .default_value("1000"))
+                .arg(Arg::with_name("BYTES")
+                    .long("--bytes")
+                    .value_name("BYTES")
+                    .help("Size of the object of object-transfer")
+                    .default_value("67108864"))
+                .arg(Arg::with_name("REPEATS")
+                    .long("--repeats")
+                    .value_name("N")
+                    .help("Number of repeats of each measurement (results are averaged)")
+                    .default_value("3")))
+        .subcommand( // ---- EXPORT-SESSION ----
+            SubCommand::with_name("export-session")
+                .about("Export the task graph of a session (without data) as JSON bundle")
+                .arg(Arg::with_name("SERVER_ADDRESS")
+                    .help("Server address: address/address:port (default port 7210)")
+                    .required(true))
+                .arg(Arg::with_name("SESSION_ID")
+                    .help("Id of the exported session")
+                    .required(true))
+                .arg(Arg::with_name("OUTPUT")
+                    .short("o")
+                    .long("--output")
+                    .value_name("FILE")
+                    .help("Write the bundle into the file (default: standard output)")
+                    .takes_value(true)))
+        .subcommand( // ---- IMPORT-SESSION ----
+            SubCommand::with_name("import-session")
+                .about("Submit a bundle made by export-session into a new session, prints id of the session")
+                .arg(Arg::with_name("SERVER_ADDRESS")
+                    .help("Server address: address/address:port (default port 7210)")
+                    .required(true))
+                .arg(Arg::with_name("BUNDLE")
+                    .help("File with the bundle, '-' reads it from the standard input")
+                    .required(true))
+                .arg(Arg::with_name("DATA")
+                    .long("--data")
+                    .value_name("DIR")
+                    .help("Directory with data of constant objects of the bundle, files are named by ids of objects")
+                    .takes_value(true)))
+        .subcommand( // ---- GATEWAY ----
+            SubCommand::with_name("gateway")
+                .about("Gateway forwarding connections of workers behind a firewall to server \
+                        (one server connection per worker, no multiplexing)")
+                .arg(Arg::with_name("SERVER_ADDRESS")
+                    .help("Server address: address/address:port (default port 7210)")
+                    .required(true))
+                .arg(Arg::with_name("LISTEN_ADDRESS")
+                    .short("l")
+                    .long("--listen")
+                    .value_name("ADDRESS")
+                    .help("Listening port/address/address:port (default = 0.0.0.0:7210)")
+                    .takes_value(true)))
+        .subcommand( // ---- BROKER ----
+            SubCommand::with_name("broker")
+                .about("Broker sharing a pool of workers among servers of session shards")
+                .arg(Arg::with_name("SERVER_ADDRESS")
+                    .help("Server addresses: address/address:port (default port 7210)")
+                    .multiple(true)
+                    .required(true))
+                .arg(Arg::with_name("LISTEN_ADDRESS")
+                    .short("l")
+                    .long("--listen")
+                    .value_name("ADDRESS")
+                    .help("Listening port/address/address:port (default = 0.0.0.0:7211)")
+                    .takes_value(true))
+                .arg(Arg::with_name("INTERVAL")
+                    .long("--interval")
+                    .value_name("DURATION")
+                    .help("How often loads of servers are polled and workers rebalanced (default 10s)")
+                    .default_value("10")))
         .subcommand( // ---- START ----
             SubCommand::with_name("start")
                 .about("Start server & workers at once")
                 .arg(Arg::with_name("SIMPLE")
                     .long("--simple")
                     .help("Start server and one local worker"))
+                .arg(Arg::with_name("IN_PROCESS")
+                    .long("--in-process")
+                    .requires("SIMPLE")
+                    .help("Run server and the worker in this process (no child processes)"))
                 .arg(Arg::with_name("LOCAL_WORKERS")
                     .long("--local-workers")
                     .help("Specify local workers (e.g. --local-workers=[4,4])")
                      .value_name("RESOURCES")
                     .takes_value(true))
+                .arg(Arg::with_name("SPEC")
+                     .long("--spec")
+                     .help("Cluster spec (TOML file) with options of server and groups of workers")
+                     .value_name("FILE")
+                     .takes_value(true))
                 .arg(Arg::with_name("WORKER_HOST_FILE")
                      .long("--worker-host-file")
                      .help("File with hosts for workers, one each line")
@@ -463,9 +1634,21 @@ fn main() {
                      .help("Commands executed on each remote host")
                      .value_name("COMMANDS")
                      .takes_value(true))
+                .arg(Arg::with_name("REMOTE_LAUNCHER")
+                     .long("--remote-launcher")
+                     .help("How workers are started on remote hosts (default = ssh)")
+                     .value_name("LAUNCHER")
+                     .possible_values(&["ssh", "pbsdsh", "srun", "mpirun"])
+                     .default_value("ssh"))
                 .arg(Arg::with_name("RCOS") // RCOS = Reserve CPUs on Server
                      .short("-S")
                      .help("Reserve a CPU on server machine"))
+                .arg(Arg::with_name("COMPRESS_CONTROL")
+                     .long("--compress-control")
+                     .help("Remote workers compress their connection to server"))
+                .arg(Arg::with_name("WAIT")
+                     .long("--wait")
+                     .help("Keep running after start; Ctrl-C then stops workers and server in order"))
                 .arg(Arg::with_name("LISTEN_ADDRESS")
                     .short("l")
                     .value_name("ADDRESS")
@@ -477,6 +1660,13 @@ fn main() {
                     .value_name("ADDRESS")
                     .help("Server listening HTTP port/address/address:port (default = 0.0.0.0:8080)")
                     .takes_value(true))
+                .arg(Arg::with_name("GLOBAL_RESOURCE")
+                    .long("--global-resource")
+                    .value_name("NAME=COUNT")
+                    .help("Cluster-wide counted resource, e.g. license:gurobi=16 (may be repeated)")
+                    .multiple(true)
+                    .number_of_values(1)
+                    .takes_value(true))
                 .arg(Arg::with_name("RUN_PREFIX")
                     .long("--runprefix")
                     .value_name("COMMAND")
@@ -496,6 +1686,12 @@ fn main() {
         ("server", Some(cmd_args)) => run_server(&args, cmd_args),
         ("worker", Some(cmd_args)) => run_worker(&args, cmd_args),
         ("start", Some(cmd_args)) => run_starter(&args, cmd_args),
+        ("gateway", Some(cmd_args)) => run_gateway(&args, cmd_args),
+        ("broker", Some(cmd_args)) => run_broker(&args, cmd_args),
+        ("admin", Some(cmd_args)) => run_admin(&args, cmd_args),
+        ("bench", Some(cmd_args)) => run_bench(&args, cmd_args),
+        ("export-session", Some(cmd_args)) => run_export_session(&args, cmd_args),
+        ("import-session", Some(cmd_args)) => run_import_session(&args, cmd_args),
         _ => {
             error!("No subcommand provided.");
             ::std::process::exit(1);
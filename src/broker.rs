@@ -0,0 +1,378 @@
+use std::cmp::Reverse;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use capnp::capability::Promise;
+use capnp_rpc::rpc_twoparty_capnp;
+use futures::{Future, Stream};
+use tokio_core::net::{TcpListener, TcpStream};
+use tokio_core::reactor::{Core, Handle};
+
+use admin::AdminClient;
+use broker_capnp::broker;
+use common::rpc::new_rpc_system;
+use errors::{Error, Result};
+
+/// Load of a server of a session shard (see `getShardLoad` in client.capnp)
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct ShardLoad {
+    /// Connected workers that are not draining
+    pub workers: u32,
+    pub draining: u32,
+    pub ready_tasks: u32,
+    /// Tasks assigned to workers
+    pub running_tasks: u32,
+}
+
+impl ShardLoad {
+    fn demand(&self) -> u64 {
+        self.ready_tasks as u64 + self.running_tasks as u64
+    }
+}
+
+/// Target numbers of workers of shards for `total` workers: each shard with a demand
+/// (ready and running tasks) gets one worker and the rest is divided in proportion
+/// to the demands (D'Hondt method). Workers are spread evenly when there is no demand.
+pub fn target_workers(loads: &[ShardLoad], total: u32) -> Vec<u32> {
+    let mut demands: Vec<u64> = loads.iter().map(|l| l.demand()).collect();
+    if demands.iter().all(|&d| d == 0) {
+        demands = vec![1; loads.len()];
+    }
+    let mut targets = vec![0u32; loads.len()];
+    let mut remaining = total;
+
+    let mut order: Vec<usize> = (0..loads.len()).filter(|&i| demands[i] > 0).collect();
+    order.sort_by_key(|&i| (Reverse(demands[i]), i));
+    for &i in order.iter().take(remaining as usize) {
+        targets[i] = 1;
+    }
+    remaining -= ::std::cmp::min(remaining, order.len() as u32);
+
+    for _ in 0..remaining {
+        // Shard with the highest demands[i] / (targets[i] + 1), the lowest index on a tie
+        let best = order
+            .iter()
+            .cloned()
+            .max_by(|&i, &j| {
+                (demands[i] * (targets[j] as u64 + 1))
+                    .cmp(&(demands[j] * (targets[i] as u64 + 1)))
+                    .then(j.cmp(&i))
+            })
+            .unwrap();
+        targets[best] += 1;
+    }
+    targets
+}
+
+struct Shard {
+    address: String,
+    /// None when the server is not reachable
+    load: Option<ShardLoad>,
+    /// Workers assigned to the server since its load was updated
+    pending: u32,
+}
+
+impl Shard {
+    /// Workers serving the server or assigned to it
+    fn workers(&self) -> u32 {
+        self.load.map(|l| l.workers).unwrap_or(0) + self.pending
+    }
+}
+
+/// Assignment of the shared workers to servers of session shards
+pub struct ShardPool {
+    shards: Vec<Shard>,
+}
+
+impl ShardPool {
+    pub fn new(addresses: Vec<String>) -> Self {
+        ShardPool {
+            shards: addresses
+                .into_iter()
+                .map(|address| Shard {
+                    address,
+                    load: None,
+                    pending: 0,
+                })
+                .collect(),
+        }
+    }
+
+    pub fn addresses(&self) -> Vec<String> {
+        self.shards.iter().map(|s| s.address.clone()).collect()
+    }
+
+    /// New load of the server polled by the broker, None when the server is not reachable
+    pub fn set_load(&mut self, index: usize, load: Option<ShardLoad>) {
+        let shard = &mut self.shards[index];
+        shard.load = load;
+        shard.pending = 0;
+    }
+
+    /// Reachable shards and their targets for all workers including `extra` new ones;
+    /// draining workers are counted, they return to the pool
+    fn targets(&self, extra: u32) -> Vec<(usize, u32)> {
+        let reachable: Vec<usize> = (0..self.shards.len())
+            .filter(|&i| self.shards[i].load.is_some())
+            .collect();
+        let loads: Vec<ShardLoad> = reachable
+            .iter()
+            .map(|&i| self.shards[i].load.unwrap())
+            .collect();
+        let total = reachable
+            .iter()
+            .map(|&i| self.shards[i].workers() + self.shards[i].load.unwrap().draining)
+            .sum::<u32>() + extra;
+        reachable
+            .into_iter()
+            .zip(target_workers(&loads, total))
+            .collect()
+    }
+
+    /// Choose the server of a starting worker: the reachable one most below its target
+    /// (the least loaded one when no server was reached yet)
+    pub fn assign(&mut self) -> String {
+        let index = self.targets(1)
+            .into_iter()
+            .max_by_key(|&(i, target)| {
+                (
+                    target as i64 - self.shards[i].workers() as i64,
+                    Reverse(i),
+                )
+            })
+            .map(|(i, _)| i)
+            .unwrap_or_else(|| {
+                (0..self.shards.len())
+                    .min_by_key(|&i| (self.shards[i].workers(), i))
+                    .unwrap()
+            });
+        let shard = &mut self.shards[index];
+        shard.pending += 1;
+        shard.address.clone()
+    }
+
+    /// Numbers of workers that servers should release, so that servers below their
+    /// targets get them; workers already draining are expected to come back first
+    pub fn releases(&self) -> Vec<(usize, u32)> {
+        let targets = self.targets(0);
+        let deficit: u32 = targets
+            .iter()
+            .map(|&(i, target)| target.saturating_sub(self.shards[i].workers()))
+            .sum();
+        let draining: u32 = targets
+            .iter()
+            .map(|&(i, _)| self.shards[i].load.unwrap().draining)
+            .sum();
+        let mut wanted = deficit.saturating_sub(draining);
+        let mut releases = Vec::new();
+        for (i, target) in targets {
+            let surplus = self.shards[i].workers().saturating_sub(target);
+            let count = ::std::cmp::min(surplus, wanted);
+            if count > 0 {
+                releases.push((i, count));
+                wanted -= count;
+            }
+        }
+        releases
+    }
+}
+
+struct BrokerImpl {
+    pool: Arc<Mutex<ShardPool>>,
+}
+
+impl broker::Server for BrokerImpl {
+    fn assign_worker(
+        &mut self,
+        _: broker::AssignWorkerParams,
+        mut results: broker::AssignWorkerResults,
+    ) -> Promise<(), ::capnp::Error> {
+        let address = self.pool.lock().unwrap().assign();
+        info!("Worker assigned to server {}", address);
+        results.get().set_server_address(&address);
+        Promise::ok(())
+    }
+
+    fn get_servers(
+        &mut self,
+        _: broker::GetServersParams,
+        mut results: broker::GetServersResults,
+    ) -> Promise<(), ::capnp::Error> {
+        let addresses = self.pool.lock().unwrap().addresses();
+        let mut list = results.get().init_servers(addresses.len() as u32);
+        for (i, address) in addresses.iter().enumerate() {
+            list.set(i as u32, address);
+        }
+        Promise::ok(())
+    }
+}
+
+/// Poll loads of servers and ask servers with a surplus of workers to release them;
+/// it runs in its own thread, `AdminClient` blocks
+fn rebalance_shards(
+    pool: Arc<Mutex<ShardPool>>,
+    servers: Vec<SocketAddr>,
+    token: String,
+    interval: Duration,
+) {
+    let mut clients: Vec<Option<AdminClient>> = servers.iter().map(|_| None).collect();
+    loop {
+        for (i, address) in servers.iter().enumerate() {
+            if clients[i].is_none() {
+                match AdminClient::connect(*address, &token) {
+                    Ok(client) => clients[i] = Some(client),
+                    Err(e) => warn!("Cannot connect to server {}: {}", address, e),
+                }
+            }
+            let load = match clients[i] {
+                Some(ref mut client) => client
+                    .get_shard_load()
+                    .map_err(|e| warn!("Cannot get load of server {}: {}", address, e))
+                    .ok(),
+                None => None,
+            };
+            if load.is_none() {
+                clients[i] = None;
+            }
+            pool.lock().unwrap().set_load(i, load);
+        }
+
+        let releases = pool.lock().unwrap().releases();
+        for (i, count) in releases {
+            if let Some(ref mut client) = clients[i] {
+                match client.release_workers(count) {
+                    Ok(released) => info!("Server {} released {} workers", servers[i], released),
+                    Err(e) => warn!("Releasing workers of server {} failed: {}", servers[i], e),
+                }
+            }
+        }
+        ::std::thread::sleep(interval);
+    }
+}
+
+/// Start the broker of session shards. Sessions are divided among several servers
+/// (`servers`: the address given to workers and its resolved address) that share
+/// one pool of workers. Workers started with `--broker` ask the broker for their server;
+/// every `interval` the broker polls loads of servers and moves workers to servers
+/// with more work (see `ShardPool`). `token` is used by the broker to access servers.
+pub fn start_broker(
+    handle: &Handle,
+    listen_address: SocketAddr,
+    servers: Vec<(String, SocketAddr)>,
+    token: String,
+    interval: Duration,
+) -> Result<()> {
+    if servers.is_empty() {
+        bail!("No servers given to the broker");
+    }
+    let pool = Arc::new(Mutex::new(ShardPool::new(
+        servers.iter().map(|&(ref address, _)| address.clone()).collect(),
+    )));
+    let pool2 = pool.clone();
+    let addresses = servers.into_iter().map(|(_, addr)| addr).collect();
+    ::std::thread::Builder::new()
+        .name("rebalance".to_string())
+        .spawn(move || rebalance_shards(pool2, addresses, token, interval))?;
+
+    let listener = TcpListener::bind(&listen_address, handle)?;
+    info!("Broker listens on {}", listen_address);
+    let handle2 = handle.clone();
+    let future = listener
+        .incoming()
+        .for_each(move |(stream, address)| {
+            debug!("New connection from {}", address);
+            stream.set_nodelay(true).unwrap();
+            let bootstrap = broker::ToClient::new(BrokerImpl { pool: pool.clone() })
+                .from_server::<::capnp_rpc::Server>();
+            let rpc_system = new_rpc_system(stream, Some(bootstrap.client));
+            handle2.spawn(
+                rpc_system.map_err(move |e| error!("RPC error on connection {}: {:?}", address, e)),
+            );
+            Ok(())
+        })
+        .map_err(|e| {
+            panic!("Listening failed {:?}", e);
+        });
+    handle.spawn(future);
+    Ok(())
+}
+
+/// Ask the broker for the server a starting worker connects to
+pub fn assign_worker(broker_address: SocketAddr) -> Result<String> {
+    let mut core = Core::new()?;
+    let stream = core.run(TcpStream::connect(&broker_address, &core.handle()))?;
+    stream.set_nodelay(true)?;
+    let mut rpc_system = new_rpc_system(stream, None);
+    let broker: broker::Client = rpc_system.bootstrap(rpc_twoparty_capnp::Side::Server);
+    core.handle()
+        .spawn(rpc_system.map_err(|e| debug!("Broker connection: {:?}", e)));
+    let req = broker.assign_worker_request();
+    core.run(req.send().promise.map_err(Error::from).and_then(
+        |response| -> Result<String> { Ok(response.get()?.get_server_address()?.to_string()) },
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn load(workers: u32, ready_tasks: u32, running_tasks: u32) -> ShardLoad {
+        ShardLoad {
+            workers,
+            draining: 0,
+            ready_tasks,
+            running_tasks,
+        }
+    }
+
+    #[test]
+    fn test_target_workers() {
+        assert_eq!(target_workers(&[load(0, 0, 0), load(0, 0, 0)], 5), vec![3, 2]);
+        assert_eq!(target_workers(&[load(0, 30, 0), load(0, 0, 10)], 4), vec![3, 1]);
+        assert_eq!(target_workers(&[load(0, 100, 0), load(0, 1, 0)], 4), vec![3, 1]);
+        assert_eq!(target_workers(&[load(0, 1, 0), load(0, 0, 0)], 3), vec![3, 0]);
+        assert_eq!(target_workers(&[load(0, 1, 0), load(0, 5, 0)], 1), vec![0, 1]);
+    }
+
+    #[test]
+    fn test_shard_pool() {
+        let mut pool = ShardPool::new(vec!["a:7210".to_string(), "b:7210".to_string()]);
+        // No server reached yet, workers are spread
+        assert_eq!(pool.assign(), "a:7210");
+        assert_eq!(pool.assign(), "b:7210");
+
+        pool.set_load(0, Some(load(4, 0, 4)));
+        pool.set_load(1, Some(load(0, 12, 0)));
+        assert_eq!(pool.assign(), "b:7210");
+        assert_eq!(pool.releases(), vec![(0, 3)]);
+
+        // Released workers are draining, nothing more is released
+        let mut draining = load(1, 0, 1);
+        draining.draining = 3;
+        pool.set_load(0, Some(draining));
+        assert!(pool.releases().is_empty());
+
+        // Unreachable server gets no workers
+        pool.set_load(1, None);
+        assert_eq!(pool.assign(), "a:7210");
+    }
+
+    #[test]
+    fn test_shard_pool_assign_beyond_targets() {
+        let mut pool = ShardPool::new(vec!["a:7210".to_string(), "b:7210".to_string()]);
+        pool.set_load(0, Some(load(1, 6, 1)));
+        pool.set_load(1, Some(load(1, 0, 2)));
+        // Pending workers are counted, so new workers follow the targets of the growing pool
+        let assigned: Vec<String> = (0..8).map(|_| pool.assign()).collect();
+        let mut expected = vec!["a:7210"; 6];
+        expected.extend(vec!["b:7210", "a:7210"]);
+        assert_eq!(assigned, expected);
+
+        // A server with more workers than its target gets none until others reach theirs
+        pool.set_load(0, Some(load(10, 1, 0)));
+        pool.set_load(1, Some(load(0, 5, 0)));
+        assert_eq!(pool.assign(), "b:7210");
+        assert_eq!(pool.releases(), vec![(0, 8)]);
+    }
+}
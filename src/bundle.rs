@@ -0,0 +1,309 @@
+use std::collections::{BTreeMap, HashSet};
+use std::fs::File;
+use std::io::Read;
+use std::net::SocketAddr;
+use std::path::Path;
+
+use futures::Future;
+use tokio_core::reactor::Core;
+
+use admin::connect_client_service;
+use common::{Attributes, DataType};
+use common::convert::ToCapnp;
+use common::id::{DataObjectId, Id, SessionId, TaskId};
+use errors::{Error, Result};
+
+/// Version of `SessionBundle`; fields may be added within a version,
+/// other changes need a new version
+pub const SESSION_BUNDLE_VERSION: u32 = 1;
+
+/// Attributes set by workers when tasks run; they are not a part of definitions of tasks
+pub const RESULT_ATTRIBUTES: &[&str] = &["info", "profile", "error", "debug", "failure"];
+
+/// Portable definition of the task graph of a session without data of objects
+/// (`rain export-session`); it can be submitted into a new session (`rain import-session`).
+/// Ids are ids within the session.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SessionBundle {
+    pub version: u32,
+    /// Version of rain that exported the bundle
+    pub rain_version: String,
+    /// Environment variables of the session
+    pub env: BTreeMap<String, String>,
+    pub max_running_tasks: Option<usize>,
+    pub cost_weight: f64,
+    pub objects: Vec<BundleObject>,
+    pub tasks: Vec<BundleTask>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct BundleObject {
+    pub id: Id,
+    pub label: String,
+    pub data_type: DataType,
+    pub keep: bool,
+    /// The object was given by the client (it has no producer); its data are not
+    /// exported, they are read from the data directory when the bundle is imported
+    pub constant: bool,
+    pub attributes: Attributes,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct BundleInput {
+    pub id: Id,
+    pub label: String,
+    pub path: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct BundleTask {
+    pub id: Id,
+    pub task_type: String,
+    pub inputs: Vec<BundleInput>,
+    pub outputs: Vec<Id>,
+    pub attributes: Attributes,
+}
+
+impl SessionBundle {
+    /// Load the bundle from the file, "-" reads it from the standard input
+    pub fn load(path: &Path) -> Result<Self> {
+        let bundle: SessionBundle = if path == Path::new("-") {
+            let stdin = ::std::io::stdin();
+            let lock = stdin.lock();
+            ::serde_json::from_reader(lock)
+                .map_err(|e| format!("Invalid session bundle on standard input: {}", e))?
+        } else {
+            let file = File::open(path).map_err(|e| format!("Cannot open {:?}: {}", path, e))?;
+            ::serde_json::from_reader(file)
+                .map_err(|e| format!("Invalid session bundle {:?}: {}", path, e))?
+        };
+        bundle.validate()?;
+        Ok(bundle)
+    }
+
+    /// Check the version and that the graph is consistent: unique ids, inputs and outputs
+    /// are objects of the bundle, each object has at most one producer and constant
+    /// objects have none
+    pub fn validate(&self) -> Result<()> {
+        if self.version != SESSION_BUNDLE_VERSION {
+            bail!(
+                "Unsupported version of session bundle {} (expected {})",
+                self.version,
+                SESSION_BUNDLE_VERSION
+            );
+        }
+        let mut objects = HashSet::new();
+        for object in &self.objects {
+            if !objects.insert(object.id) {
+                bail!("Object {} is defined more than once", object.id);
+            }
+        }
+        let mut tasks = HashSet::new();
+        let mut produced = HashSet::new();
+        for task in &self.tasks {
+            if !tasks.insert(task.id) {
+                bail!("Task {} is defined more than once", task.id);
+            }
+            for input in &task.inputs {
+                if !objects.contains(&input.id) {
+                    bail!("Input {} of task {} is not in the bundle", input.id, task.id);
+                }
+            }
+            for output in &task.outputs {
+                if !objects.contains(output) {
+                    bail!("Output {} of task {} is not in the bundle", output, task.id);
+                }
+                if !produced.insert(*output) {
+                    bail!("Object {} has more than one producer", output);
+                }
+            }
+        }
+        for object in &self.objects {
+            if object.constant == produced.contains(&object.id) {
+                bail!(
+                    "Object {} has to be either constant or produced by a task",
+                    object.id
+                );
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Client exporting and importing sessions (`rain export-session`, `rain import-session`)
+pub struct BundleClient {
+    core: Core,
+    service: ::client_capnp::client_service::Client,
+}
+
+impl BundleClient {
+    /// Connect to the server; `token` is needed when the server has access control enabled
+    pub fn connect(server_address: SocketAddr, token: &str) -> Result<Self> {
+        let mut core = Core::new()?;
+        let service = connect_client_service(&mut core, server_address, token)?;
+        Ok(BundleClient { core, service })
+    }
+
+    /// Bundle of the session as exported by the server (JSON)
+    pub fn export(&mut self, session_id: SessionId) -> Result<String> {
+        let mut req = self.service.export_session_request();
+        req.get().set_session_id(session_id);
+        self.core.run(req.send().promise.map_err(Error::from).and_then(
+            |response| -> Result<String> { Ok(response.get()?.get_bundle()?.to_string()) },
+        ))
+    }
+
+    /// Submit the bundle into a new session, data of constant objects are read
+    /// from files `data_dir/ID`. The session is kept running when the client disconnects.
+    /// Returns the id of the new session.
+    pub fn import(&mut self, bundle: &SessionBundle, data_dir: Option<&Path>) -> Result<SessionId> {
+        let mut data = BTreeMap::new();
+        for object in bundle.objects.iter().filter(|o| o.constant) {
+            let dir = match data_dir {
+                Some(dir) => dir,
+                None => bail!(
+                    "The bundle contains constant objects (e.g. {}), their data have to \
+                     be given by --data",
+                    object.id
+                ),
+            };
+            let path = dir.join(object.id.to_string());
+            let mut content = Vec::new();
+            File::open(&path)
+                .and_then(|mut file| file.read_to_end(&mut content))
+                .map_err(|e| {
+                    format!("Cannot read data of object {} from {:?}: {}", object.id, path, e)
+                })?;
+            data.insert(object.id, content);
+        }
+
+        let mut req = self.service.new_session_request();
+        {
+            let mut env = req.get().init_env(bundle.env.len() as u32);
+            for (i, (name, value)) in bundle.env.iter().enumerate() {
+                let mut var = env.borrow().get(i as u32);
+                var.set_name(name);
+                var.set_value(value);
+            }
+        }
+        let session_id = self.core.run(req.send().promise.map_err(Error::from).and_then(
+            |response| -> Result<SessionId> { Ok(response.get()?.get_session_id()) },
+        ))?;
+
+        let mut req = self.service.set_session_options_request();
+        {
+            let mut params = req.get();
+            params.set_session_id(session_id);
+            let mut options = params.init_options();
+            options.set_max_running_tasks(bundle.max_running_tasks.unwrap_or(0) as u32);
+            options.set_detach_on_disconnect(true);
+            options.set_cost_weight(bundle.cost_weight);
+        }
+        self.core.run(req.send().promise)?;
+
+        let mut req = self.service.submit_request();
+        {
+            let mut params = req.get();
+            {
+                let mut objects = params.borrow().init_objects(bundle.objects.len() as u32);
+                for (i, object) in bundle.objects.iter().enumerate() {
+                    let mut builder = objects.borrow().get(i as u32);
+                    DataObjectId::new(session_id, object.id)
+                        .to_capnp(&mut builder.borrow().init_id());
+                    builder.set_keep(object.keep);
+                    builder.set_data_type(object.data_type.to_capnp());
+                    builder.set_label(&object.label);
+                    if let Some(data) = data.get(&object.id) {
+                        builder.set_has_data(true);
+                        builder.set_data(data);
+                    }
+                    object.attributes.to_capnp(&mut builder.init_attributes());
+                }
+            }
+            let mut tasks = params.init_tasks(bundle.tasks.len() as u32);
+            for (i, task) in bundle.tasks.iter().enumerate() {
+                let mut builder = tasks.borrow().get(i as u32);
+                TaskId::new(session_id, task.id).to_capnp(&mut builder.borrow().init_id());
+                builder.set_task_type(&task.task_type);
+                {
+                    let mut inputs = builder.borrow().init_inputs(task.inputs.len() as u32);
+                    for (j, input) in task.inputs.iter().enumerate() {
+                        let mut input_builder = inputs.borrow().get(j as u32);
+                        DataObjectId::new(session_id, input.id)
+                            .to_capnp(&mut input_builder.borrow().init_id());
+                        input_builder.set_label(&input.label);
+                        input_builder.set_path(&input.path);
+                    }
+                }
+                {
+                    let mut outputs = builder.borrow().init_outputs(task.outputs.len() as u32);
+                    for (j, output) in task.outputs.iter().enumerate() {
+                        DataObjectId::new(session_id, *output)
+                            .to_capnp(&mut outputs.borrow().get(j as u32));
+                    }
+                }
+                task.attributes.to_capnp(&mut builder.init_attributes());
+            }
+        }
+        self.core.run(req.send().promise.map_err(Error::from).and_then(
+            |response| -> Result<()> {
+                match response.get()?.which()? {
+                    ::client_capnp::submit_result::Which::Ok(_) => Ok(()),
+                    ::client_capnp::submit_result::Which::IdCollision(_) => {
+                        bail!("Ids of the bundle collide")
+                    }
+                }
+            },
+        ))?;
+        Ok(session_id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn bundle(json: &str) -> SessionBundle {
+        ::serde_json::from_str(json).unwrap()
+    }
+
+    const OBJECTS: &str = r#"[
+        {"id": 1, "label": "", "data_type": "blob", "keep": false, "constant": true,
+         "attributes": {"items": {}}},
+        {"id": 2, "label": "", "data_type": "blob", "keep": true, "constant": false,
+         "attributes": {"items": {}}}]"#;
+
+    #[test]
+    fn test_bundle_validate() {
+        let valid = bundle(&format!(
+            r#"{{"version": 1, "rain_version": "0.3.0", "env": {{}}, "max_running_tasks": null,
+                "cost_weight": 0.0, "objects": {},
+                "tasks": [{{"id": 3, "task_type": "!concat", "outputs": [2],
+                           "inputs": [{{"id": 1, "label": "", "path": ""}}],
+                           "attributes": {{"items": {{}}}}}}]}}"#,
+            OBJECTS
+        ));
+        assert!(valid.validate().is_ok());
+
+        let missing_input = bundle(&format!(
+            r#"{{"version": 1, "rain_version": "0.3.0", "env": {{}}, "max_running_tasks": null,
+                "cost_weight": 0.0, "objects": {},
+                "tasks": [{{"id": 3, "task_type": "!concat", "outputs": [2],
+                           "inputs": [{{"id": 7, "label": "", "path": ""}}],
+                           "attributes": {{"items": {{}}}}}}]}}"#,
+            OBJECTS
+        ));
+        assert!(missing_input.validate().is_err());
+
+        let no_producer = bundle(&format!(
+            r#"{{"version": 1, "rain_version": "0.3.0", "env": {{}}, "max_running_tasks": null,
+                "cost_weight": 0.0, "objects": {}, "tasks": []}}"#,
+            OBJECTS
+        ));
+        assert!(no_producer.validate().is_err());
+
+        let mut other_version = valid;
+        other_version.version = 2;
+        assert!(other_version.validate().is_err());
+    }
+}
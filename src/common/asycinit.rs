@@ -3,12 +3,15 @@ use futures::{unsync, Future, IntoFuture};
 
 /// This code serves for "async" initialization Item may be in state "Initing"
 /// that stores oneshots that are fired when the item is in ready state. The
-/// object becomes ready when `set_value` is called
+/// object becomes ready when `set_value` is called. When initialization fails,
+/// `fail` fails all waiters and consumes the wrapper, so the initialization may be retried.
+
+type Waiter = unsync::oneshot::Sender<Result<(), Error>>;
 
 enum State<T> {
     // Object is still in initialization, vector contains callbacks when
     // object is ready
-    Initing(Vec<unsync::oneshot::Sender<()>>),
+    Initing(Vec<Waiter>),
 
     // Value is ready
     Ready(T),
@@ -27,8 +30,8 @@ impl<T> AsyncInitWrapper<T> {
 
     pub fn is_ready(&self) -> bool {
         match self.state {
-            State::Initing(_) => false,
             State::Ready(_) => true,
+            State::Initing(_) => false,
         }
     }
 
@@ -40,16 +43,41 @@ impl<T> AsyncInitWrapper<T> {
     }
 
     /// Function that sets the value of the object when it is finally ready
-    /// It triggers all waiting oneshots
+    /// It triggers all waiting oneshots. Waiters that were already dropped are skipped.
     pub fn set_value(&mut self, value: T) {
         match ::std::mem::replace(&mut self.state, State::Ready(value)) {
             State::Initing(senders) => for sender in senders {
-                sender.send(()).unwrap();
+                if sender.send(Ok(())).is_err() {
+                    debug!("Waiter of async init was dropped");
+                }
             },
             State::Ready(_) => panic!("Element is already finished"),
         }
     }
 
+    /// Fail the initialization. Pending waiters are resolved with the message of the error
+    /// including its causes, the original error is returned (e.g. to the initiator).
+    /// The wrapper is consumed; its owner drops it, so the next `wait` starts
+    /// the initialization again.
+    pub fn fail(self, error: Error) -> Error {
+        match self.state {
+            State::Initing(senders) => {
+                let message = error
+                    .iter()
+                    .map(|e| e.to_string())
+                    .collect::<Vec<_>>()
+                    .join(": ");
+                for sender in senders {
+                    if sender.send(Err(message.as_str().into())).is_err() {
+                        debug!("Waiter of async init was dropped");
+                    }
+                }
+            }
+            State::Ready(_) => panic!("Element is already finished"),
+        }
+        error
+    }
+
     /// Returns future that is finished when object is ready,
     /// If object is already prepared than future is finished immediately
     pub fn wait(&mut self) -> Box<Future<Item = (), Error = Error>> {
@@ -58,9 +86,33 @@ impl<T> AsyncInitWrapper<T> {
             State::Initing(ref mut senders) => {
                 let (sender, receiver) = unsync::oneshot::channel();
                 senders.push(sender);
-                // TODO: Convert to testable error
-                Box::new(receiver.map_err(|_| "Cancelled".into()))
+                Box::new(
+                    receiver
+                        .map_err(|_| "Async initialization was cancelled".into())
+                        .and_then(|r| r),
+                )
             }
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::AsyncInitWrapper;
+    use errors::Error;
+    use futures::Future;
+    use std::io;
+
+    #[test]
+    fn test_fail_waiters() {
+        let mut wrapper: AsyncInitWrapper<()> = AsyncInitWrapper::new();
+        let waiter = wrapper.wait();
+        let cause = io::Error::new(io::ErrorKind::ConnectionRefused, "refused");
+        let error = wrapper.fail(Error::with_chain(cause, "Cannot connect"));
+        assert_eq!(error.to_string(), "Cannot connect");
+        assert_eq!(
+            waiter.wait().err().unwrap().to_string(),
+            "Cannot connect: refused"
+        );
+    }
+}
@@ -2,7 +2,7 @@ use std::collections::HashMap;
 use errors::Result;
 use std::error::Error;
 
-#[derive(Default, Debug)]
+#[derive(Default, Debug, Clone, Serialize, Deserialize)]
 pub struct Attributes {
     // TODO: Int & Float types
     items: HashMap<String, String>,
@@ -82,6 +82,10 @@ impl Attributes {
         }
     }
 
+    pub fn remove(&mut self, key: &str) -> Option<String> {
+        self.items.remove(key)
+    }
+
     pub fn as_hashmap(&self) -> &HashMap<String, String> {
         &self.items
     }
@@ -0,0 +1,108 @@
+use std::collections::BTreeSet;
+use std::collections::btree_set::Iter;
+
+use capnp::text_list;
+
+/// Object data may be sent inline in control messages
+pub const TRANSFER_INLINE: &str = "transfer:inline";
+/// Objects of type directory are supported
+pub const DATA_DIRECTORY: &str = "data:directory";
+/// Task "!run" may carry an embedded script in its config
+pub const RUN_SCRIPT: &str = "run:script";
+
+/// Set of features of a worker or the server exchanged at the registration of the worker.
+/// Capabilities are strings "kind:name", e.g. "task:!concat" (a built-in task),
+/// "subworker:py" (tasks of a subworker type) or "transfer:inline".
+///
+/// Peers of an older version do not send any capabilities; an empty set is
+/// treated as "legacy" that supports everything, so mixed clusters keep working
+/// during upgrades.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Capabilities {
+    items: BTreeSet<String>,
+}
+
+impl Capabilities {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    pub fn insert<S: Into<String>>(&mut self, capability: S) {
+        self.items.insert(capability.into());
+    }
+
+    /// Capability of running a built-in task type (e.g. "!concat")
+    pub fn insert_task(&mut self, task_type: &str) {
+        self.insert(format!("task:{}", task_type));
+    }
+
+    /// Capability of running tasks of a subworker type (e.g. "py")
+    pub fn insert_subworker(&mut self, subworker_type: &str) {
+        self.insert(format!("subworker:{}", subworker_type));
+    }
+
+    /// Peer did not announce its capabilities
+    #[inline]
+    pub fn is_legacy(&self) -> bool {
+        self.items.is_empty()
+    }
+
+    pub fn has(&self, capability: &str) -> bool {
+        self.is_legacy() || self.items.contains(capability)
+    }
+
+    /// Can the peer run tasks of the given type? Types starting with "!" are built-in
+    /// tasks, other types are served by subworkers of the same name.
+    pub fn supports_task(&self, task_type: &str) -> bool {
+        if task_type.starts_with('!') {
+            self.has(&format!("task:{}", task_type))
+        } else {
+            self.has(&format!("subworker:{}", task_type))
+        }
+    }
+
+    pub fn iter(&self) -> Iter<String> {
+        self.items.iter()
+    }
+
+    pub fn from_capnp(reader: &text_list::Reader) -> ::errors::Result<Self> {
+        let mut capabilities = Capabilities::new();
+        for item in reader.iter() {
+            capabilities.insert(item?);
+        }
+        Ok(capabilities)
+    }
+
+    pub fn to_capnp(&self, builder: &mut text_list::Builder) {
+        for (i, item) in self.items.iter().enumerate() {
+            builder.set(i as u32, item);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_capabilities_legacy() {
+        let capabilities = Capabilities::new();
+        assert!(capabilities.is_legacy());
+        assert!(capabilities.supports_task("!concat"));
+        assert!(capabilities.has(TRANSFER_INLINE));
+    }
+
+    #[test]
+    fn test_capabilities_tasks() {
+        let mut capabilities = Capabilities::new();
+        capabilities.insert_task("!concat");
+        capabilities.insert_subworker("py");
+        capabilities.insert(TRANSFER_INLINE);
+        assert!(capabilities.supports_task("!concat"));
+        assert!(capabilities.supports_task("py"));
+        assert!(!capabilities.supports_task("!jmespath"));
+        assert!(!capabilities.supports_task("r"));
+        assert!(capabilities.has(TRANSFER_INLINE));
+        assert!(!capabilities.has(DATA_DIRECTORY));
+    }
+}
@@ -0,0 +1,160 @@
+/* Fault injection for testing of fault tolerance (cargo feature "chaos") */
+
+use errors::Result;
+
+/// Faults injected by workers; probabilities are in range 0.0 - 1.0
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ChaosConfig {
+    /// Probability that an update of the worker to the server is dropped
+    /// (its content is sent again with the next update)
+    pub drop_messages: f64,
+    /// Delay of every fetch of an object from another worker (in milliseconds)
+    pub delay_transfers: u64,
+    /// Probability that the subworker is killed when a task is started in it
+    pub kill_subworkers: f64,
+    /// Probability that a fetch of an object from another worker fails
+    pub fail_fetches: f64,
+    /// Seed of the random generator (for reproducible runs), 0 = seed from time
+    pub seed: u64,
+}
+
+impl ChaosConfig {
+    pub fn check(&self) -> Result<()> {
+        for &(name, p) in &[
+            ("drop_messages", self.drop_messages),
+            ("kill_subworkers", self.kill_subworkers),
+            ("fail_fetches", self.fail_fetches),
+        ] {
+            // NaN is rejected too
+            if !(p >= 0.0 && p <= 1.0) {
+                bail!("Probability '{}' has to be in range 0.0 - 1.0", name);
+            }
+        }
+        Ok(())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.drop_messages == 0.0 && self.delay_transfers == 0 && self.kill_subworkers == 0.0
+            && self.fail_fetches == 0.0
+    }
+}
+
+/// Source of injected faults; without feature "chaos" no fault is ever injected
+/// and it cannot be configured
+#[derive(Default)]
+pub struct Chaos {
+    config: ChaosConfig,
+    rng_state: u64,
+}
+
+impl Chaos {
+    #[cfg(feature = "chaos")]
+    pub fn configure(&mut self, config: ChaosConfig) -> Result<()> {
+        config.check()?;
+        if config.is_empty() {
+            info!("Fault injection disabled");
+        } else {
+            warn!("Fault injection enabled: {:?}", config);
+        }
+        self.rng_state = if config.seed != 0 {
+            config.seed
+        } else {
+            let now = ::std::time::SystemTime::now()
+                .duration_since(::std::time::UNIX_EPOCH)
+                .unwrap();
+            (now.as_secs() ^ u64::from(now.subsec_nanos())) | 1
+        };
+        self.config = config;
+        Ok(())
+    }
+
+    #[cfg(not(feature = "chaos"))]
+    pub fn configure(&mut self, _config: ChaosConfig) -> Result<()> {
+        bail!("Fault injection is not available (rain was built without feature 'chaos')")
+    }
+
+    #[inline]
+    pub fn config(&self) -> &ChaosConfig {
+        &self.config
+    }
+
+    /// Xorshift generator, uniform in range 0.0 - 1.0
+    fn next_f64(&mut self) -> f64 {
+        let mut x = self.rng_state;
+        x ^= x << 13;
+        x ^= x >> 7;
+        x ^= x << 17;
+        self.rng_state = x;
+        (x >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    fn happens(&mut self, probability: f64) -> bool {
+        cfg!(feature = "chaos") && probability > 0.0 && self.next_f64() < probability
+    }
+
+    pub fn drop_message(&mut self) -> bool {
+        let p = self.config.drop_messages;
+        self.happens(p)
+    }
+
+    pub fn kill_subworker(&mut self) -> bool {
+        let p = self.config.kill_subworkers;
+        self.happens(p)
+    }
+
+    pub fn fail_fetch(&mut self) -> bool {
+        let p = self.config.fail_fetches;
+        self.happens(p)
+    }
+
+    /// Delay of a fetch in milliseconds (0 = no delay)
+    pub fn transfer_delay(&self) -> u64 {
+        if cfg!(feature = "chaos") {
+            self.config.delay_transfers
+        } else {
+            0
+        }
+    }
+}
+
+#[cfg(all(test, feature = "chaos"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_chaos_probabilities() {
+        let mut chaos = Chaos::default();
+        chaos
+            .configure(ChaosConfig {
+                fail_fetches: 0.5,
+                seed: 42,
+                ..Default::default()
+            })
+            .unwrap();
+        assert!(!chaos.drop_message());
+        let failed = (0..1000).filter(|_| chaos.fail_fetch()).count();
+        assert!(failed > 400 && failed < 600);
+    }
+
+    #[test]
+    fn test_chaos_invalid_config() {
+        let mut chaos = Chaos::default();
+        assert!(
+            chaos
+                .configure(ChaosConfig {
+                    kill_subworkers: 1.5,
+                    ..Default::default()
+                })
+                .is_err()
+        );
+        assert!(
+            chaos
+                .configure(ChaosConfig {
+                    drop_messages: ::std::f64::NAN,
+                    ..Default::default()
+                })
+                .is_err()
+        );
+    }
+}
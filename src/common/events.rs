@@ -1,11 +1,21 @@
 use super::id::{ClientId, DataObjectId, SessionId, TaskId, WorkerId};
 use server::graph::{DataObject, Task};
+use server::redact::Redaction;
 use common::id::SId;
+use common::convert::{FromCapnp, ToCapnp};
 
 use std::collections::HashMap;
 
+use chrono::{DateTime, TimeZone, Utc};
+use errors::Result;
+
 pub type EventId = i64;
 
+/// Version of the event schema. It is increased when a field of an event is removed,
+/// renamed or its meaning is changed. Added fields have to be optional (`#[serde(default)]`),
+/// so consumers of events are not broken by them and the version is kept.
+pub const EVENT_SCHEMA_VERSION: u32 = 1;
+
 #[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
 pub struct WorkerNewEvent {
     pub worker: WorkerId, // TODO: Resources
@@ -35,6 +45,13 @@ pub struct SessionNewEvent {
     pub client: ClientId,
 }
 
+#[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
+pub struct SessionExpiredEvent {
+    pub session: SessionId,
+    // Number of tasks that were cancelled
+    pub unfinished_tasks: usize,
+}
+
 #[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
 pub struct ClientSubmitEvent {
     pub tasks: Vec<TaskDescriptor>,
@@ -56,7 +73,8 @@ pub struct TaskDescriptor {
 }
 
 impl TaskDescriptor {
-    pub fn from(task: &Task) -> Self {
+    /// Descriptor of the task, values of attributes are redacted by the server rules
+    pub fn from(task: &Task, redaction: &Redaction) -> Self {
         TaskDescriptor {
             id: task.id(),
             inputs: task.inputs()
@@ -67,7 +85,7 @@ impl TaskDescriptor {
                 })
                 .collect(),
             task_type: task.task_type().clone(),
-            attributes: task.attributes().as_hashmap().clone(),
+            attributes: redaction.attributes(task.attributes()).as_hashmap().clone(),
         }
     }
 }
@@ -110,6 +128,23 @@ pub struct DataObjectFinishedEvent {
     pub size: usize,
 }
 
+/// Finished object was found corrupt, its producer and tasks depending
+/// on it are computed again
+#[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
+pub struct DataObjectInvalidatedEvent {
+    pub dataobject: DataObjectId,
+    pub tasks: Vec<TaskId>,
+    pub reason: String,
+}
+
+/// Alert rule of server (--alerts) started or stopped firing
+#[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
+pub struct AlertEvent {
+    pub alert: String,
+    pub firing: bool,
+    pub message: String,
+}
+
 pub type CpuUsage = u8;
 pub type MemUsage = u8;
 
@@ -128,6 +163,15 @@ pub struct TaskFailedEvent {
     pub error_msg: String,
 }
 
+/// Warning or error logged by a worker (when it ships logs)
+#[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
+pub struct WorkerLogEvent {
+    pub worker: WorkerId,
+    pub level: String,
+    pub target: String,
+    pub message: String,
+}
+
 #[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
 pub struct ClientInvalidRequestEvent {
     pub client: ClientId,
@@ -144,6 +188,7 @@ pub enum Event {
     ClientRemoved(ClientRemovedEvent),
 
     SessionNew(SessionNewEvent),
+    SessionExpired(SessionExpiredEvent),
 
     ClientSubmit(ClientSubmitEvent),
     ClientUnkeep(ClientUnkeepEvent),
@@ -151,16 +196,78 @@ pub enum Event {
     TaskStarted(TaskStartedEvent),
     TaskFinished(TaskFinishedEvent),
     DataObjectFinished(DataObjectFinishedEvent),
+    DataObjectInvalidated(DataObjectInvalidatedEvent),
 
     Monitoring(MonitoringEvent),
+    WorkerLog(WorkerLogEvent),
 
     TaskFailed(TaskFailedEvent),
     ClientInvalidRequest(ClientInvalidRequestEvent),
 
+    Alert(AlertEvent),
+
     Dummy(i32),
 }
 
+/// Event with the version of its schema, the form stored in the event log
+/// (JSON object with fields "version", "type" and fields of the event)
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct VersionedEvent {
+    pub version: u32,
+    #[serde(flatten)]
+    pub event: Event,
+}
+
+impl VersionedEvent {
+    pub fn new(event: Event) -> Self {
+        VersionedEvent {
+            version: EVENT_SCHEMA_VERSION,
+            event,
+        }
+    }
+}
+
+impl<'a> FromCapnp<'a> for DateTime<Utc> {
+    type Reader = ::common_capnp::timestamp::Reader<'a>;
+
+    fn from_capnp(read: &Self::Reader) -> Self {
+        Utc.timestamp(read.get_seconds() as i64, read.get_subsec_nanos())
+    }
+}
+
+impl<'a> ToCapnp<'a> for DateTime<Utc> {
+    type Builder = ::common_capnp::timestamp::Builder<'a>;
+
+    fn to_capnp(&self, build: &mut Self::Builder) {
+        build.set_seconds(self.timestamp() as u64);
+        build.set_subsec_nanos(self.timestamp_subsec_nanos());
+    }
+}
+
 impl Event {
+    /// Serialize the event with its time into a message (worker -> server)
+    pub fn to_capnp(&self, time: &DateTime<Utc>, builder: &mut ::common_capnp::event::Builder) {
+        builder.set_version(EVENT_SCHEMA_VERSION);
+        builder.set_event(&::serde_json::to_string(self).unwrap());
+        time.to_capnp(&mut builder.borrow().init_timestamp());
+    }
+
+    /// Read the event with its time from a message, events of other schema versions
+    /// are rejected
+    pub fn from_capnp(reader: &::common_capnp::event::Reader) -> Result<(Event, DateTime<Utc>)> {
+        let version = reader.get_version();
+        if version != EVENT_SCHEMA_VERSION {
+            bail!(
+                "Unsupported version of event schema {} (expected {})",
+                version,
+                EVENT_SCHEMA_VERSION
+            );
+        }
+        let event = ::serde_json::from_str(reader.get_event()?)?;
+        let time = DateTime::from_capnp(&reader.get_timestamp()?);
+        Ok((event, time))
+    }
+
     pub fn event_type(&self) -> &'static str {
         match self {
             &Event::WorkerNew(_) => "WorkerNew",
@@ -168,14 +275,18 @@ impl Event {
             &Event::ClientNew(_) => "ClientNew",
             &Event::ClientRemoved(_) => "ClientRemoved",
             &Event::SessionNew(_) => "SessionNew",
+            &Event::SessionExpired(_) => "SessionExpired",
             &Event::ClientSubmit(_) => "ClientSubmit",
             &Event::ClientUnkeep(_) => "ClientUnkeep",
             &Event::TaskStarted(_) => "TaskStarted",
             &Event::TaskFinished(_) => "TaskFinished",
             &Event::TaskFailed(_) => "TaskFailed",
             &Event::DataObjectFinished(_) => "ObjectFinished",
+            &Event::DataObjectInvalidated(_) => "ObjectInvalidated",
             &Event::Monitoring(_) => "Monitoring",
+            &Event::WorkerLog(_) => "WorkerLog",
             &Event::ClientInvalidRequest(_) => "InvalidRequest",
+            &Event::Alert(_) => "Alert",
             &Event::Dummy(_) => "Dummy",
         }
     }
@@ -185,7 +296,9 @@ impl Event {
             &Event::TaskFinished(ref e) => Some(e.task.get_session_id()),
             &Event::TaskStarted(ref e) => Some(e.task.get_session_id()),
             &Event::TaskFailed(ref e) => Some(e.task.get_session_id()),
+            &Event::DataObjectInvalidated(ref e) => Some(e.dataobject.get_session_id()),
             &Event::SessionNew(ref e) => Some(e.session),
+            &Event::SessionExpired(ref e) => Some(e.session),
             &Event::ClientSubmit(ref e) => {
                 // TODO: Quick hack, we expect that submit contains only tasks/obj from one session
                 e.tasks.get(0).map(|t| t.id.get_session_id())
@@ -194,3 +307,61 @@ impl Event {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{Event, VersionedEvent, WorkerNewEvent, EVENT_SCHEMA_VERSION};
+    use capnp::message;
+    use chrono::{TimeZone, Utc};
+
+    fn create_event() -> Event {
+        Event::WorkerNew(WorkerNewEvent {
+            worker: "127.0.0.1:7211".parse().unwrap(),
+        })
+    }
+
+    #[test]
+    fn test_capnp_roundtrip() {
+        let event = create_event();
+        let time = Utc.timestamp(1_500_000_000, 123_456_789);
+        let mut msg = message::Builder::new_default();
+        let mut builder = msg.init_root::<::common_capnp::event::Builder>();
+        event.to_capnp(&time, &mut builder);
+        assert_eq!(
+            Event::from_capnp(&builder.as_reader()).unwrap(),
+            (event, time)
+        );
+    }
+
+    #[test]
+    fn test_capnp_other_version() {
+        let mut msg = message::Builder::new_default();
+        let mut builder = msg.init_root::<::common_capnp::event::Builder>();
+        create_event().to_capnp(&Utc::now(), &mut builder);
+        builder.set_version(EVENT_SCHEMA_VERSION + 1);
+        assert!(Event::from_capnp(&builder.as_reader()).is_err());
+    }
+
+    #[test]
+    fn test_versioned_json() {
+        let value = ::serde_json::to_value(VersionedEvent::new(create_event())).unwrap();
+        assert_eq!(
+            value,
+            json!({
+                "version": EVENT_SCHEMA_VERSION,
+                "type": "WorkerNew",
+                "worker": "127.0.0.1:7211"
+            })
+        );
+
+        // Unknown fields (added in the same version) are ignored
+        let versioned: VersionedEvent = ::serde_json::from_value(json!({
+            "version": EVENT_SCHEMA_VERSION,
+            "type": "WorkerNew",
+            "worker": "127.0.0.1:7211",
+            "resources": {"cpus": 4}
+        })).unwrap();
+        assert_eq!(versioned.version, EVENT_SCHEMA_VERSION);
+        assert_eq!(versioned.event, create_event());
+    }
+}
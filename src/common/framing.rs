@@ -0,0 +1,309 @@
+use std::io::{self, Read, Write};
+use std::time::Duration;
+
+use flate2::{Compress, Compression, Decompress, FlushCompress, FlushDecompress};
+use futures::{future, Future, Poll};
+use tokio_core::reactor::{Handle, Timeout};
+use tokio_io::{AsyncRead, AsyncWrite};
+use tokio_io::io::{read_exact, write_all};
+
+/// Framing of a control connection between server and worker
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Framing {
+    /// Plain Cap'n Proto messages
+    Plain,
+    /// Cap'n Proto messages in a zlib stream (sync-flushed after each write)
+    Compressed,
+}
+
+/// Preamble sent by the connecting side to request compressed framing.
+/// Plain connection starts with the segment table of the first message,
+/// its first word is never equal to the preamble (it would mean ~22M segments).
+pub const COMPRESSED_PREAMBLE: [u8; 4] = *b"RNZ\x01";
+
+const READ_BUFFER_SIZE: usize = 16 * 1024;
+
+fn compression_error<E: ::std::fmt::Debug>(e: E) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, format!("{:?}", e))
+}
+
+/// Send the preamble that announces the framing (done by the connecting side)
+pub fn announce_framing<S>(stream: S, framing: Framing) -> Box<Future<Item = S, Error = io::Error>>
+where
+    S: AsyncWrite + 'static,
+{
+    match framing {
+        Framing::Plain => Box::new(future::ok(stream)),
+        Framing::Compressed => {
+            Box::new(write_all(stream, COMPRESSED_PREAMBLE).map(|(stream, _)| stream))
+        }
+    }
+}
+
+/// Detect the framing of an incoming connection from its first bytes.
+/// Bytes that do not belong to the preamble are returned and has to be passed to
+/// `Prefixed` reader. It expects that the connecting side speaks first,
+/// what is true for Cap'n Proto clients (they start with bootstrap message).
+/// Fails with `TimedOut` when the first bytes do not come within `timeout`
+/// (e.g. a peer that connects and stays silent).
+pub fn detect_framing<S>(
+    stream: S,
+    timeout: Duration,
+    handle: &Handle,
+) -> Box<Future<Item = (S, Framing, Vec<u8>), Error = io::Error>>
+where
+    S: AsyncRead + 'static,
+{
+    let timeout = match Timeout::new(timeout, handle) {
+        Ok(timeout) => timeout,
+        Err(e) => return Box::new(future::err(e)),
+    };
+    let expired = timeout.and_then(|()| -> io::Result<(S, Framing, Vec<u8>)> {
+        Err(io::Error::new(
+            io::ErrorKind::TimedOut,
+            "no data received to detect framing",
+        ))
+    });
+    Box::new(
+        read_exact(stream, [0u8; 4])
+            .map(|(stream, head)| {
+                if head == COMPRESSED_PREAMBLE {
+                    (stream, Framing::Compressed, Vec::new())
+                } else {
+                    (stream, Framing::Plain, head.to_vec())
+                }
+            })
+            .select(expired)
+            .map(|(result, _)| result)
+            .map_err(|(e, _)| e),
+    )
+}
+
+/// Reader that returns already read bytes before reading from the inner reader
+pub struct Prefixed<R> {
+    prefix: Vec<u8>,
+    position: usize,
+    inner: R,
+}
+
+impl<R> Prefixed<R> {
+    pub fn new(prefix: Vec<u8>, inner: R) -> Self {
+        Prefixed {
+            prefix,
+            position: 0,
+            inner,
+        }
+    }
+}
+
+impl<R: Read> Read for Prefixed<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.position < self.prefix.len() {
+            let n = (&self.prefix[self.position..]).read(buf)?;
+            self.position += n;
+            return Ok(n);
+        }
+        self.inner.read(buf)
+    }
+}
+
+impl<R: AsyncRead> AsyncRead for Prefixed<R> {}
+
+/// Reader of zlib stream written by `CompressedWriter`
+pub struct CompressedReader<R> {
+    inner: R,
+    decompress: Decompress,
+    buffer: Vec<u8>,
+    start: usize,
+    end: usize,
+}
+
+impl<R> CompressedReader<R> {
+    pub fn new(inner: R) -> Self {
+        CompressedReader {
+            inner,
+            decompress: Decompress::new(true),
+            buffer: vec![0; READ_BUFFER_SIZE],
+            start: 0,
+            end: 0,
+        }
+    }
+}
+
+impl<R: Read> Read for CompressedReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        loop {
+            if self.start == self.end {
+                // May return WouldBlock, nothing is consumed in such case
+                let n = self.inner.read(&mut self.buffer)?;
+                if n == 0 {
+                    return Ok(0);
+                }
+                self.start = 0;
+                self.end = n;
+            }
+            let total_in = self.decompress.total_in();
+            let total_out = self.decompress.total_out();
+            self.decompress
+                .decompress(
+                    &self.buffer[self.start..self.end],
+                    buf,
+                    FlushDecompress::None,
+                )
+                .map_err(compression_error)?;
+            let consumed = (self.decompress.total_in() - total_in) as usize;
+            let produced = (self.decompress.total_out() - total_out) as usize;
+            self.start += consumed;
+            if produced > 0 {
+                return Ok(produced);
+            }
+            if consumed == 0 {
+                return Err(compression_error("Compressed stream is stuck"));
+            }
+        }
+    }
+}
+
+impl<R: AsyncRead> AsyncRead for CompressedReader<R> {}
+
+/// Writer that compresses each written buffer and sync-flushes the zlib stream,
+/// so the peer can decode every message without waiting for more data
+/// (RPC layer never calls flush).
+///
+/// When the inner writer would block, the compressed data are kept and
+/// the write is finished when the caller retries it with the same buffer
+/// (as `write_all` does).
+pub struct CompressedWriter<W> {
+    inner: W,
+    compress: Compress,
+    buffer: Vec<u8>,
+    position: usize,
+    staged: Option<usize>,
+}
+
+impl<W> CompressedWriter<W> {
+    pub fn new(inner: W) -> Self {
+        CompressedWriter {
+            inner,
+            compress: Compress::new(Compression::fast(), true),
+            buffer: Vec::new(),
+            position: 0,
+            staged: None,
+        }
+    }
+}
+
+impl<W: Write> Write for CompressedWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.staged.is_none() {
+            self.buffer.clear();
+            self.position = 0;
+            self.buffer.reserve(buf.len() + 64);
+            let mut consumed = 0;
+            loop {
+                let total_in = self.compress.total_in();
+                self.compress
+                    .compress_vec(&buf[consumed..], &mut self.buffer, FlushCompress::Sync)
+                    .map_err(compression_error)?;
+                consumed += (self.compress.total_in() - total_in) as usize;
+                // Flush is finished when zlib does not fill the whole output space
+                if consumed == buf.len() && self.buffer.len() < self.buffer.capacity() {
+                    break;
+                }
+                let len = self.buffer.len();
+                self.buffer.reserve(len.max(64));
+            }
+            self.staged = Some(buf.len());
+        }
+        while self.position < self.buffer.len() {
+            let n = self.inner.write(&self.buffer[self.position..])?;
+            if n == 0 {
+                return Err(io::ErrorKind::WriteZero.into());
+            }
+            self.position += n;
+        }
+        Ok(self.staged.take().unwrap())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+impl<W: AsyncWrite> AsyncWrite for CompressedWriter<W> {
+    fn shutdown(&mut self) -> Poll<(), io::Error> {
+        self.inner.shutdown()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_compressed_roundtrip() {
+        let mut writer = CompressedWriter::new(Vec::new());
+        let message1: Vec<u8> = (0..50)
+            .flat_map(|_| b"{\"resources\": {\"cpus\": 1}}".iter().cloned())
+            .collect();
+        let message2 = b"second message";
+        assert_eq!(writer.write(&message1).unwrap(), message1.len());
+        assert_eq!(writer.write(message2).unwrap(), message2.len());
+        assert!(writer.inner.len() < message1.len());
+
+        let mut reader = CompressedReader::new(&writer.inner[..]);
+        let mut output = vec![0; message1.len() + message2.len()];
+        let mut position = 0;
+        while position < output.len() {
+            let n = reader.read(&mut output[position..]).unwrap();
+            assert!(n > 0);
+            position += n;
+        }
+        assert_eq!(&output[..message1.len()], &message1[..]);
+        assert_eq!(&output[message1.len()..], &message2[..]);
+    }
+
+    /// Stream that never has any data
+    struct Silent;
+
+    impl Read for Silent {
+        fn read(&mut self, _buf: &mut [u8]) -> io::Result<usize> {
+            Err(io::ErrorKind::WouldBlock.into())
+        }
+    }
+
+    impl AsyncRead for Silent {}
+
+    #[test]
+    fn test_detect_framing() {
+        let mut core = ::tokio_core::reactor::Core::new().unwrap();
+        let handle = core.handle();
+        let timeout = Duration::from_secs(5);
+
+        let compressed: &'static [u8] = b"RNZ\x01rest";
+        let (_, framing, prefix) = core.run(detect_framing(compressed, timeout, &handle))
+            .unwrap();
+        assert_eq!(framing, Framing::Compressed);
+        assert!(prefix.is_empty());
+
+        let plain: &'static [u8] = &[1, 2, 3, 4, 5];
+        let (_, framing, prefix) = core.run(detect_framing(plain, timeout, &handle)).unwrap();
+        assert_eq!(framing, Framing::Plain);
+        assert_eq!(prefix, vec![1, 2, 3, 4]);
+
+        let silent = detect_framing(Silent, Duration::from_millis(10), &handle);
+        let error = core.run(silent).err().unwrap();
+        assert_eq!(error.kind(), io::ErrorKind::TimedOut);
+    }
+
+    #[test]
+    fn test_prefixed() {
+        let mut reader = Prefixed::new(vec![1, 2], &[3, 4][..]);
+        let mut output = Vec::new();
+        reader.read_to_end(&mut output).unwrap();
+        assert_eq!(output, vec![1, 2, 3, 4]);
+    }
+}
@@ -1,6 +1,6 @@
 use std::path::{Path, PathBuf};
 
-use common::id::SubworkerId;
+use common::id::{SId, SubworkerId, TaskId};
 
 pub struct LogDir {
     path: PathBuf,
@@ -8,9 +8,11 @@ pub struct LogDir {
 
 impl LogDir {
     pub fn new(path: PathBuf) -> Self {
-        let sw = path.join("subworkers");
-        if !sw.exists() {
-            ::std::fs::create_dir(&sw).unwrap();
+        for name in &["subworkers", "tasks"] {
+            let dir = path.join(name);
+            if !dir.exists() {
+                ::std::fs::create_dir(&dir).unwrap();
+            }
         }
         LogDir { path }
     }
@@ -24,6 +26,19 @@ impl LogDir {
         (out, err)
     }
 
+    /// Get path to kept stdout/stderr of a failed task
+    pub fn task_log_paths(&self, id: TaskId) -> (PathBuf, PathBuf) {
+        let name = format!("tasks/task-{}-{}", id.get_session_id(), id.get_id());
+        let out = self.path.join(Path::new(&format!("{}.out", name)));
+        let err = self.path.join(Path::new(&format!("{}.err", name)));
+        (out, err)
+    }
+
+    pub fn zygote_log_path(&self, subworker_type: &str) -> PathBuf {
+        self.path
+            .join(Path::new(&format!("subworkers/zygote-{}.err", subworker_type)))
+    }
+
     pub fn get_path(&self) -> PathBuf {
         self.path.clone()
     }
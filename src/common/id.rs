@@ -4,10 +4,21 @@ use super::convert::{FromCapnp, ReadCapnp, ToCapnp, WriteCapnp};
 use std::io::Read;
 use capnp::serialize;
 use std::fmt;
+use std::hash::Hash;
+use std::collections::HashMap;
+
+use errors::Result;
 
 /// Generic ID type. Negative values have special meaning.
 pub type Id = i32;
 
+/// Negative ids of tasks and objects are provisional, the server assigns
+/// ids to them at submit
+#[inline]
+pub fn is_provisional(id: Id) -> bool {
+    id < 0
+}
+
 /// Session ID type. Negative values have special meaning.
 pub type SessionId = i32;
 
@@ -195,6 +206,48 @@ impl<'a> FromCapnp<'a> for DataObjectId {
     }
 }
 
+/// Ids assigned to provisional ids within a single submit
+pub struct IdAssignment<T: SId + Hash + Eq + Copy> {
+    assigned: HashMap<T, T>,
+}
+
+impl<T: SId + Hash + Eq + Copy> IdAssignment<T> {
+    pub fn new() -> Self {
+        IdAssignment {
+            assigned: HashMap::new(),
+        }
+    }
+
+    pub fn insert(&mut self, provisional: T, id: T) {
+        assert!(is_provisional(provisional.get_id()) && !is_provisional(id.get_id()));
+        self.assigned.insert(provisional, id);
+    }
+
+    /// Final id: assigned id for a provisional id, other ids are returned unchanged
+    pub fn resolve(&self, id: T) -> Result<T> {
+        if !is_provisional(id.get_id()) {
+            return Ok(id);
+        }
+        match self.assigned.get(&id) {
+            Some(id) => Ok(*id),
+            None => bail!(
+                "Provisional id {} in session {} is not assigned in the submit",
+                id.get_id(),
+                id.get_session_id()
+            ),
+        }
+    }
+
+    pub fn iter(&self) -> ::std::collections::hash_map::Iter<T, T> {
+        self.assigned.iter()
+    }
+
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.assigned.len()
+    }
+}
+
 // TODO(gavento): Replace Sid by Task/DO ID
 pub type Sid = TaskId;
 
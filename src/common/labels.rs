@@ -0,0 +1,109 @@
+use std::collections::BTreeMap;
+use std::fmt;
+
+use errors::Result;
+
+/// Labels of a worker (e.g. "rack" = "a", "storage" = "ssd"), set by --label
+pub type Labels = BTreeMap<String, String>;
+
+/// Parse labels in form "key=value"
+pub fn parse_labels<'a, I: IntoIterator<Item = &'a str>>(specs: I) -> Result<Labels> {
+    let mut result = Labels::new();
+    for spec in specs {
+        let (key, value) = match spec.find('=') {
+            Some(pos) => (&spec[..pos], &spec[pos + 1..]),
+            None => bail!("Invalid label '{}', expected format is key=value", spec),
+        };
+        if key.is_empty() || result.insert(key.to_string(), value.to_string()).is_some() {
+            bail!("Invalid or duplicate label key in '{}'", spec);
+        }
+    }
+    Ok(result)
+}
+
+/// Placement constraint of a task on labels of workers (attribute "constraints")
+#[derive(Debug, Clone, PartialEq)]
+pub enum Constraint {
+    /// "key=value"
+    Equal(String, String),
+    /// "key!=value", also satisfied when the worker has no such label
+    NotEqual(String, String),
+    /// "key"
+    Present(String),
+    /// "!key"
+    Absent(String),
+}
+
+impl Constraint {
+    pub fn parse(value: &str) -> Result<Self> {
+        let constraint = if let Some(pos) = value.find("!=") {
+            Constraint::NotEqual(value[..pos].to_string(), value[pos + 2..].to_string())
+        } else if let Some(pos) = value.find('=') {
+            Constraint::Equal(value[..pos].to_string(), value[pos + 1..].to_string())
+        } else if value.starts_with('!') {
+            Constraint::Absent(value[1..].to_string())
+        } else {
+            Constraint::Present(value.to_string())
+        };
+        if constraint.key().is_empty() {
+            bail!("Invalid placement constraint '{}'", value);
+        }
+        Ok(constraint)
+    }
+
+    fn key(&self) -> &str {
+        match *self {
+            Constraint::Equal(ref key, _)
+            | Constraint::NotEqual(ref key, _)
+            | Constraint::Present(ref key)
+            | Constraint::Absent(ref key) => key,
+        }
+    }
+
+    pub fn is_satisfied(&self, labels: &Labels) -> bool {
+        let label = labels.get(self.key());
+        match *self {
+            Constraint::Equal(_, ref value) => label == Some(value),
+            Constraint::NotEqual(_, ref value) => label != Some(value),
+            Constraint::Present(_) => label.is_some(),
+            Constraint::Absent(_) => label.is_none(),
+        }
+    }
+}
+
+impl fmt::Display for Constraint {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Constraint::Equal(ref key, ref value) => write!(f, "{}={}", key, value),
+            Constraint::NotEqual(ref key, ref value) => write!(f, "{}!={}", key, value),
+            Constraint::Present(ref key) => write!(f, "{}", key),
+            Constraint::Absent(ref key) => write!(f, "!{}", key),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_constraints() {
+        let labels = parse_labels(vec!["rack=a", "storage=ssd", "empty="]).unwrap();
+        assert_eq!(labels["empty"], "");
+        assert!(parse_labels(vec!["rack"]).is_err());
+        assert!(parse_labels(vec!["rack=a", "rack=b"]).is_err());
+
+        let satisfied = |c: &str| Constraint::parse(c).unwrap().is_satisfied(&labels);
+        assert!(satisfied("rack=a"));
+        assert!(!satisfied("rack=b"));
+        assert!(satisfied("rack!=b"));
+        assert!(satisfied("gpu!=v100"));
+        assert!(satisfied("storage"));
+        assert!(!satisfied("gpu"));
+        assert!(satisfied("!gpu"));
+        assert!(!satisfied("!rack"));
+        assert_eq!(Constraint::parse("rack!=b").unwrap().to_string(), "rack!=b");
+        assert!(Constraint::parse("=a").is_err());
+        assert!(Constraint::parse("!").is_err());
+    }
+}
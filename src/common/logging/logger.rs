@@ -103,6 +103,29 @@ pub trait Logger {
         }));
     }
 
+    fn add_dataobject_invalidated_event(
+        &mut self,
+        dataobject: DataObjectId,
+        tasks: Vec<TaskId>,
+        reason: String,
+    ) {
+        self.add_event(Event::DataObjectInvalidated(
+            events::DataObjectInvalidatedEvent {
+                dataobject,
+                tasks,
+                reason,
+            },
+        ));
+    }
+
+    fn add_alert_event(&mut self, alert: String, firing: bool, message: String) {
+        self.add_event(Event::Alert(events::AlertEvent {
+            alert,
+            firing,
+            message,
+        }));
+    }
+
     fn add_dummy_event(&mut self) {
         self.add_event(Event::Dummy(1));
     }
@@ -125,6 +148,13 @@ pub trait Logger {
         }));
     }
 
+    fn add_session_expired_event(&mut self, session: SessionId, unfinished_tasks: usize) {
+        self.add_event(Event::SessionExpired(events::SessionExpiredEvent {
+            session,
+            unfinished_tasks,
+        }));
+    }
+
     fn get_events(
         &self,
         search_criteria: SearchCriteria,
@@ -1,2 +1,3 @@
 pub mod logger;
 pub mod sqlite_logger;
+pub mod shipping;
@@ -0,0 +1,87 @@
+use std::mem;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicBool, Ordering, ATOMIC_BOOL_INIT};
+
+use chrono::{DateTime, Utc};
+use log::{Level, Log, Metadata, Record};
+
+/// Maximal number of records waiting for shipping, further records are dropped
+const MAX_PENDING_RECORDS: usize = 1000;
+
+static ENABLED: AtomicBool = ATOMIC_BOOL_INIT;
+
+/// Log record kept for shipping to the server
+#[derive(Clone, Debug)]
+pub struct ShippedRecord {
+    pub time: DateTime<Utc>,
+    pub level: Level,
+    pub target: String,
+    pub message: String,
+}
+
+#[derive(Default)]
+struct Pending {
+    records: Vec<ShippedRecord>,
+    dropped: usize,
+}
+
+lazy_static! {
+    static ref PENDING: Mutex<Pending> = Mutex::new(Default::default());
+}
+
+/// Logger that passes records to the inner logger and keeps warnings and errors
+/// for shipping when the shipping is enabled
+pub struct ShippingLogger<L: Log> {
+    inner: L,
+}
+
+impl<L: Log> ShippingLogger<L> {
+    pub fn new(inner: L) -> Self {
+        ShippingLogger { inner }
+    }
+}
+
+fn is_shipped(level: Level) -> bool {
+    level <= Level::Warn && ENABLED.load(Ordering::Relaxed)
+}
+
+impl<L: Log> Log for ShippingLogger<L> {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        self.inner.enabled(metadata) || is_shipped(metadata.level())
+    }
+
+    fn log(&self, record: &Record) {
+        if self.inner.enabled(record.metadata()) {
+            self.inner.log(record);
+        }
+        if is_shipped(record.level()) {
+            let mut pending = PENDING.lock().unwrap();
+            if pending.records.len() < MAX_PENDING_RECORDS {
+                pending.records.push(ShippedRecord {
+                    time: Utc::now(),
+                    level: record.level(),
+                    target: record.target().to_string(),
+                    message: record.args().to_string(),
+                });
+            } else {
+                pending.dropped += 1;
+            }
+        }
+    }
+
+    fn flush(&self) {
+        self.inner.flush()
+    }
+}
+
+/// Start keeping warnings and errors for shipping
+pub fn enable() {
+    ENABLED.store(true, Ordering::Relaxed);
+}
+
+/// Take records waiting for shipping and the number of dropped records
+pub fn take_records() -> (Vec<ShippedRecord>, usize) {
+    let mut pending = PENDING.lock().unwrap();
+    let dropped = mem::replace(&mut pending.dropped, 0);
+    (mem::replace(&mut pending.records, Vec::new()), dropped)
+}
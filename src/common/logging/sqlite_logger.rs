@@ -44,7 +44,7 @@ fn save_events(conn: &mut Connection, events: Vec<EventWrapper>) -> Result<()> {
                 &e.timestamp,
                 &e.event.event_type(),
                 &e.event.session_id(),
-                &serde_json::to_string(&e.event)?,
+                &serde_json::to_string(&events::VersionedEvent::new(e.event.clone()))?,
             ])?;
         }
     }
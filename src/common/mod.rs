@@ -1,13 +1,20 @@
 pub mod id;
 pub mod convert;
 pub mod rpc;
+pub mod framing;
 pub mod wrapped;
 pub mod resources;
 pub mod events;
 pub mod asycinit;
 pub mod attributes;
+pub mod capabilities;
+pub mod chaos;
 pub mod sys;
 pub mod datatype;
+pub mod tls;
+pub mod output_policy;
+pub mod labels;
+pub mod units;
 
 use std::collections::HashSet;
 use futures::unsync::oneshot;
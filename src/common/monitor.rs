@@ -4,7 +4,7 @@ use std::io::BufRead;
 use std::io::BufReader;
 use common::id::WorkerId;
 use std::collections::HashMap;
-use chrono::{DateTime, Utc};
+use std::time::Instant;
 use sys_info::mem_info;
 
 type CpuTimes = Vec<u64>;
@@ -14,7 +14,7 @@ type MemUsage = u8;
 
 pub struct Monitor {
     clk_tck: isize, // Result of syscall CLK_TCK
-    last_timestamp: DateTime<Utc>,
+    last_timestamp: Instant, // Monotonic, so usage is not affected by clock adjustments
     last_cpu_time: CpuTimes,
 }
 
@@ -28,7 +28,7 @@ impl Monitor {
                 warn!("Syscall sysconf(CLK_TCK) failed. Set to default value 100");
                 100isize
             }),
-            last_timestamp: Utc::now(),
+            last_timestamp: Instant::now(),
             last_cpu_time: Vec::new(),
         }
     }
@@ -61,10 +61,11 @@ impl Monitor {
         cpu_time_vec
     }
 
-    fn get_cpu_usage(&self, cpu_time: &CpuTimes, timestamp: DateTime<Utc>) -> Vec<CpuUsage> {
+    fn get_cpu_usage(&self, cpu_time: &CpuTimes, timestamp: Instant) -> Vec<CpuUsage> {
         let mut cpu_usage = Vec::with_capacity(cpu_time.len());
-        let time_diff = timestamp.signed_duration_since(self.last_timestamp);
-        let mut millis = time_diff.num_milliseconds();
+        let time_diff = timestamp.duration_since(self.last_timestamp);
+        let mut millis =
+            time_diff.as_secs() as i64 * 1000 + i64::from(time_diff.subsec_nanos() / 1_000_000);
         if millis < 1 {
             warn!(
                 "get_cpu_usage() called too often ({}ms since the last measurements)",
@@ -119,7 +120,7 @@ impl Monitor {
     }
 
     pub fn build_event(&mut self, worker_id: &WorkerId) -> ::common::events::Event {
-        let timestamp = Utc::now();
+        let timestamp = Instant::now();
         let cpu_time = self.get_cpu_time();
         let cpu_usage = self.get_cpu_usage(&cpu_time, timestamp);
         let mem_usage = self.get_mem_usage();
@@ -151,7 +152,7 @@ mod tests {
     #[test]
     fn test_cpu_uasge() {
         let monitor = Monitor::new();
-        let cpu_usage = monitor.get_cpu_usage(&(monitor.get_cpu_time()), Utc::now());
+        let cpu_usage = monitor.get_cpu_usage(&(monitor.get_cpu_time()), Instant::now());
         for u in cpu_usage {
             assert!(u <= 100)
         }
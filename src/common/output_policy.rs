@@ -0,0 +1,79 @@
+use common::units::deserialize_size_opt;
+use errors::Result;
+
+/// What happens with captured stdout and stderr of programs (tasks "!run")
+/// that are not outputs of the task
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum Retention {
+    /// Removed with the working directory of the task (default)
+    Discard,
+    /// Moved into the log directory of the worker when the task fails
+    KeepOnFailure,
+    /// Stored as outputs "stdout" and "stderr" of the task (the client adds the outputs)
+    KeepAsObject,
+}
+
+impl Retention {
+    pub fn parse(value: &str) -> Result<Self> {
+        Ok(match value {
+            "discard" => Retention::Discard,
+            "keep_on_failure" => Retention::KeepOnFailure,
+            "keep_as_object" => Retention::KeepAsObject,
+            _ => bail!("Invalid output retention '{}'", value),
+        })
+    }
+}
+
+/// Retention and size cap of captured stdout and stderr, set for a session
+/// and sent to workers in task attribute "output_policy"
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct OutputPolicy {
+    pub retention: Retention,
+    /// Max. number of bytes kept of each stream (its beginning and end), None = unlimited;
+    /// units are accepted (e.g. "10MiB")
+    #[serde(default, deserialize_with = "deserialize_size_opt")]
+    pub max_size: Option<u64>,
+}
+
+impl Default for OutputPolicy {
+    fn default() -> Self {
+        OutputPolicy {
+            retention: Retention::Discard,
+            max_size: None,
+        }
+    }
+}
+
+impl OutputPolicy {
+    /// The stricter of the size caps of the policy and of the worker
+    pub fn effective_max_size(&self, worker_max_size: Option<u64>) -> Option<u64> {
+        match (self.max_size, worker_max_size) {
+            (Some(a), Some(b)) => Some(::std::cmp::min(a, b)),
+            (a, b) => a.or(b),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_output_policy() {
+        let policy: OutputPolicy =
+            ::serde_json::from_str(r#"{"retention": "keep_on_failure", "max_size": 1024}"#)
+                .unwrap();
+        assert_eq!(policy.retention, Retention::KeepOnFailure);
+        assert_eq!(policy.effective_max_size(None), Some(1024));
+        assert_eq!(policy.effective_max_size(Some(100)), Some(100));
+        assert_eq!(OutputPolicy::default().effective_max_size(None), None);
+        let policy: OutputPolicy =
+            ::serde_json::from_str(r#"{"retention": "discard", "max_size": "1KiB"}"#).unwrap();
+        assert_eq!(policy.max_size, Some(1024));
+        let json = ::serde_json::to_string(&OutputPolicy::default()).unwrap();
+        assert_eq!(::serde_json::from_str::<OutputPolicy>(&json).unwrap(), Default::default());
+        assert!(Retention::parse("keep_as_object").is_ok());
+        assert!(Retention::parse("keep").is_err());
+    }
+}
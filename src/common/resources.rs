@@ -1,6 +1,25 @@
+use std::collections::HashMap;
+
+use common::units::deserialize_size;
+use errors::Result;
+
 #[derive(Default, Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
 pub struct Resources {
     pub cpus: u32,
+    /// Memory in bytes requested by a task (0 = no request) or available on a worker;
+    /// units are accepted in attributes (e.g. "512M")
+    #[serde(default, deserialize_with = "deserialize_size")]
+    pub memory: u64,
+    /// Number of GPUs requested by a task or available on a worker
+    #[serde(default)]
+    pub gpus: u32,
+    /// Counted resources declared by workers (e.g. "fpga" = 1), requested by tasks
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub named: HashMap<String, u32>,
+    /// Cluster-wide counted resources requested by a task (see `ResourcePool`),
+    /// not used for worker resources.
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub global: HashMap<String, u32>,
 }
 
 impl Resources {
@@ -9,34 +28,215 @@ impl Resources {
         self.cpus
     }
 
+    #[inline]
+    pub fn memory(&self) -> u64 {
+        self.memory
+    }
+
+    #[inline]
+    pub fn gpus(&self) -> u32 {
+        self.gpus
+    }
+
+    /// Count of a named resource (0 when not present)
+    #[inline]
+    pub fn named(&self, name: &str) -> u32 {
+        self.named.get(name).cloned().unwrap_or(0)
+    }
+
     pub fn add(&mut self, resources: &Resources) {
         self.cpus += resources.cpus;
+        self.memory += resources.memory;
+        self.gpus += resources.gpus;
+        for (name, &count) in &resources.named {
+            *self.named.entry(name.clone()).or_insert(0) += count;
+        }
     }
 
     pub fn remove(&mut self, resources: &Resources) {
-        assert!(self.cpus >= resources.cpus);
+        assert!(resources.is_subset_of(self));
         self.cpus -= resources.cpus;
+        self.memory -= resources.memory;
+        self.gpus -= resources.gpus;
+        for (name, &count) in &resources.named {
+            if count > 0 {
+                *self.named.get_mut(name).unwrap() -= count;
+            }
+        }
     }
 
     pub fn difference(&self, resources: &Resources) -> Resources {
-        assert!(self.cpus >= resources.cpus);
-        Resources {
-            cpus: self.cpus - resources.cpus,
-        }
+        let mut result = self.clone();
+        result.global = Default::default();
+        result.remove(resources);
+        result
     }
 
     pub fn from_capnp(reader: &::common_capnp::resources::Reader) -> Self {
         Resources {
             cpus: reader.get_n_cpus(),
+            memory: reader.get_memory(),
+            gpus: reader.get_n_gpus(),
+            named: reader
+                .get_named()
+                .unwrap()
+                .iter()
+                .map(|r| (r.get_name().unwrap().to_string(), r.get_count()))
+                .collect(),
+            global: Default::default(),
         }
     }
 
     pub fn to_capnp(&self, builder: &mut ::common_capnp::resources::Builder) {
         builder.set_n_cpus(self.cpus);
+        builder.set_memory(self.memory);
+        builder.set_n_gpus(self.gpus);
+        let mut named = builder.borrow().init_named(self.named.len() as u32);
+        for (i, (name, &count)) in self.named.iter().enumerate() {
+            let mut item = named.borrow().get(i as u32);
+            item.set_name(name);
+            item.set_count(count);
+        }
     }
 
     #[inline]
     pub fn is_subset_of(&self, resources: &Resources) -> bool {
-        self.cpus <= resources.cpus
+        self.cpus <= resources.cpus && self.memory <= resources.memory
+            && self.gpus <= resources.gpus
+            && self.named
+                .iter()
+                .all(|(name, &count)| count <= resources.named(name))
+    }
+}
+
+/// Parse specifications of counted resources in form "name=count"
+pub fn parse_resource_specs<'a, I: IntoIterator<Item = &'a str>>(
+    specs: I,
+) -> Result<HashMap<String, u32>> {
+    let mut result = HashMap::new();
+    for spec in specs {
+        let (name, count) = match spec.rfind('=') {
+            Some(pos) => (&spec[..pos], &spec[pos + 1..]),
+            None => bail!("Invalid resource '{}', expected format is name=count", spec),
+        };
+        let count: u32 = count
+            .parse()
+            .map_err(|_| format!("Invalid count of resource '{}'", spec))?;
+        if name.is_empty() || result.insert(name.to_string(), count).is_some() {
+            bail!("Invalid or duplicate resource name in '{}'", spec);
+        }
+    }
+    Ok(result)
+}
+
+/// Cluster-wide counted resources tracked by the server
+/// (e.g. "license:gurobi" = 16 or "api:ncbi" = 3).
+/// Tasks acquire them when scheduled and release them when finished.
+#[derive(Default, Debug, Clone)]
+pub struct ResourcePool {
+    capacity: HashMap<String, u32>,
+    used: HashMap<String, u32>,
+}
+
+impl ResourcePool {
+    /// Create pool from specifications in form "name=count"
+    pub fn from_specs<'a, I: IntoIterator<Item = &'a str>>(specs: I) -> Result<Self> {
+        Ok(ResourcePool {
+            capacity: parse_resource_specs(specs)?,
+            used: HashMap::new(),
+        })
+    }
+
+    #[inline]
+    pub fn capacity(&self, name: &str) -> u32 {
+        self.capacity.get(name).cloned().unwrap_or(0)
+    }
+
+    #[inline]
+    pub fn used(&self, name: &str) -> u32 {
+        self.used.get(name).cloned().unwrap_or(0)
+    }
+
+    /// Can the request be ever satisfied (when nothing is used)?
+    pub fn is_feasible(&self, resources: &Resources) -> bool {
+        resources
+            .global
+            .iter()
+            .all(|(name, &count)| count <= self.capacity(name))
+    }
+
+    /// Can the request be satisfied now?
+    pub fn is_available(&self, resources: &Resources) -> bool {
+        resources
+            .global
+            .iter()
+            .all(|(name, &count)| self.used(name) + count <= self.capacity(name))
+    }
+
+    pub fn acquire(&mut self, resources: &Resources) {
+        for (name, &count) in &resources.global {
+            *self.used.entry(name.clone()).or_insert(0) += count;
+            assert!(self.used(name) <= self.capacity(name));
+        }
+    }
+
+    pub fn release(&mut self, resources: &Resources) {
+        for (name, &count) in &resources.global {
+            let used = self.used.get_mut(name).unwrap();
+            assert!(*used >= count);
+            *used -= count;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ResourcePool, Resources};
+
+    #[test]
+    fn test_resource_pool() {
+        let mut pool = ResourcePool::from_specs(vec!["license:gurobi=2", "api:ncbi=3"]).unwrap();
+        let mut r = Resources::default();
+        r.global.insert("license:gurobi".to_string(), 2);
+        assert!(pool.is_feasible(&r));
+        assert!(pool.is_available(&r));
+        pool.acquire(&r);
+        assert!(!pool.is_available(&r));
+        pool.release(&r);
+        assert!(pool.is_available(&r));
+
+        r.global.insert("api:ncbi".to_string(), 4);
+        assert!(!pool.is_feasible(&r));
+        r.global.insert("unknown".to_string(), 1);
+        assert!(!pool.is_feasible(&r));
+
+        assert!(ResourcePool::from_specs(vec!["abc"]).is_err());
+        assert!(ResourcePool::from_specs(vec!["abc=x"]).is_err());
+    }
+
+    #[test]
+    fn test_named_resources() {
+        let mut worker = Resources {
+            cpus: 4,
+            ..Default::default()
+        };
+        worker.named.insert("fpga".to_string(), 2);
+        let mut task = Resources {
+            cpus: 1,
+            ..Default::default()
+        };
+        task.named.insert("fpga".to_string(), 1);
+        assert!(task.is_subset_of(&worker));
+        worker.remove(&task);
+        assert_eq!(worker.named("fpga"), 1);
+        assert!(task.is_subset_of(&worker));
+        assert_eq!(worker.difference(&task).named("fpga"), 0);
+        worker.remove(&task);
+        assert!(!task.is_subset_of(&worker));
+        worker.add(&task);
+        assert_eq!(worker.named("fpga"), 1);
+
+        task.named.insert("license".to_string(), 1);
+        assert!(!task.is_subset_of(&worker));
     }
 }
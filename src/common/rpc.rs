@@ -1,6 +1,8 @@
 use capnp_rpc::{rpc_twoparty_capnp, twoparty, RpcSystem};
 use tokio_io::{AsyncRead, AsyncWrite};
 
+use common::framing::{CompressedReader, CompressedWriter, Framing, Prefixed};
+
 pub fn new_rpc_system<Stream>(
     stream: Stream,
     bootstrap: Option<::capnp::capability::Client>,
@@ -9,6 +11,41 @@ where
     Stream: AsyncRead + AsyncWrite + 'static,
 {
     let (reader, writer) = stream.split();
+    new_rpc_system_from_parts(reader, writer, bootstrap)
+}
+
+/// Create RPC system over the stream with the given framing.
+/// `prefix` are bytes that were already read from the stream (see `detect_framing`).
+pub fn new_framed_rpc_system<Stream>(
+    stream: Stream,
+    framing: Framing,
+    prefix: Vec<u8>,
+    bootstrap: Option<::capnp::capability::Client>,
+) -> RpcSystem<twoparty::VatId>
+where
+    Stream: AsyncRead + AsyncWrite + 'static,
+{
+    let (reader, writer) = stream.split();
+    let reader = Prefixed::new(prefix, reader);
+    match framing {
+        Framing::Plain => new_rpc_system_from_parts(reader, writer, bootstrap),
+        Framing::Compressed => new_rpc_system_from_parts(
+            CompressedReader::new(reader),
+            CompressedWriter::new(writer),
+            bootstrap,
+        ),
+    }
+}
+
+fn new_rpc_system_from_parts<Reader, Writer>(
+    reader: Reader,
+    writer: Writer,
+    bootstrap: Option<::capnp::capability::Client>,
+) -> RpcSystem<twoparty::VatId>
+where
+    Reader: AsyncRead + 'static,
+    Writer: AsyncWrite + 'static,
+{
     let network = Box::new(twoparty::VatNetwork::new(
         reader,
         writer,
@@ -4,3 +4,151 @@ pub fn get_hostname() -> String {
     let mut buf = [0u8; 256];
     gethostname(&mut buf).unwrap().to_str().unwrap().to_string()
 }
+
+/// Total memory of the machine in bytes (MemTotal of /proc/meminfo), None when unknown
+pub fn get_total_memory() -> Option<u64> {
+    use std::io::Read;
+    let mut meminfo = String::new();
+    if ::std::fs::File::open("/proc/meminfo")
+        .and_then(|mut f| f.read_to_string(&mut meminfo))
+        .is_err()
+    {
+        return None;
+    }
+    meminfo
+        .lines()
+        .find(|line| line.starts_with("MemTotal:"))
+        .and_then(|line| line.split_whitespace().nth(1))
+        .and_then(|kb| kb.parse::<u64>().ok())
+        .map(|kb| kb * 1024)
+}
+
+/// Kill (SIGKILL) all descendants of this process found in /proc and reap its children,
+/// e.g. before the process is replaced by exec, as the new program does not know them
+pub fn kill_descendants() {
+    use nix::libc;
+
+    let own_pid = ::std::process::id() as libc::pid_t;
+    // (pid, parent pid) of all processes
+    let mut processes = Vec::new();
+    if let Ok(entries) = ::std::fs::read_dir("/proc") {
+        for entry in entries.filter_map(|entry| entry.ok()) {
+            let pid = match entry.file_name().to_str().and_then(|s| s.parse().ok()) {
+                Some(pid) => pid,
+                None => continue,
+            };
+            // The parent pid follows the state after the name of the program
+            // in parentheses (the name may contain spaces)
+            let parent = ::std::fs::read_to_string(entry.path().join("stat"))
+                .ok()
+                .and_then(|stat| {
+                    stat.rsplit(')')
+                        .next()
+                        .and_then(|rest| rest.split_whitespace().nth(1))
+                        .and_then(|s| s.parse().ok())
+                });
+            if let Some(parent) = parent {
+                processes.push((pid, parent));
+            }
+        }
+    }
+    let mut descendants: Vec<libc::pid_t> = vec![own_pid];
+    let mut i = 0;
+    while i < descendants.len() {
+        let parent = descendants[i];
+        descendants.extend(
+            processes
+                .iter()
+                .filter(|&&(_, p)| p == parent)
+                .map(|&(pid, _)| pid),
+        );
+        i += 1;
+    }
+    for &pid in &descendants[1..] {
+        unsafe {
+            libc::kill(pid, libc::SIGKILL);
+        }
+    }
+    for &(pid, parent) in &processes {
+        if parent == own_pid {
+            unsafe {
+                libc::waitpid(pid, ::std::ptr::null_mut(), 0);
+            }
+        }
+    }
+}
+
+/// Devices of GPUs of the machine: CUDA_VISIBLE_DEVICES when it is set
+/// (the worker is restricted to these devices), otherwise /dev/nvidia<N>
+pub fn get_gpu_devices() -> Vec<u32> {
+    if let Ok(devices) = ::std::env::var("CUDA_VISIBLE_DEVICES") {
+        return devices
+            .split(',')
+            .filter_map(|device| device.trim().parse().ok())
+            .collect();
+    }
+    let mut devices: Vec<u32> = match ::std::fs::read_dir("/dev") {
+        Ok(entries) => entries
+            .filter_map(|entry| entry.ok())
+            .filter_map(|entry| {
+                let name = entry.file_name();
+                let name = name.to_str().unwrap_or("");
+                if name.starts_with("nvidia") {
+                    name["nvidia".len()..].parse().ok()
+                } else {
+                    None
+                }
+            })
+            .collect(),
+        Err(_) => Vec::new(),
+    };
+    devices.sort();
+    devices
+}
+
+/// Platform of a worker, reported at its registration
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq, Eq)]
+pub struct Platform {
+    /// Operating system, e.g. "linux"
+    pub os: String,
+    /// CPU architecture, e.g. "x86_64" or "aarch64"
+    pub arch: String,
+    /// Release of the kernel
+    pub kernel: String,
+    /// Version of rain
+    pub version: String,
+}
+
+impl Platform {
+    /// Platform of this machine and this build of rain
+    pub fn detect() -> Self {
+        Platform {
+            os: ::std::env::consts::OS.to_string(),
+            arch: ::std::env::consts::ARCH.to_string(),
+            kernel: ::nix::sys::utsname::uname().release().to_string(),
+            version: ::VERSION.to_string(),
+        }
+    }
+
+    /// The platform is not known (a worker of an older version)
+    #[inline]
+    pub fn is_unknown(&self) -> bool {
+        self.arch.is_empty()
+    }
+
+    pub fn from_capnp(reader: &::common_capnp::platform::Reader) -> ::errors::Result<Self> {
+        Ok(Platform {
+            os: reader.get_os()?.to_string(),
+            arch: reader.get_arch()?.to_string(),
+            kernel: reader.get_kernel()?.to_string(),
+            version: reader.get_version()?.to_string(),
+        })
+    }
+
+    pub fn to_capnp(&self, builder: &mut ::common_capnp::platform::Builder) {
+        builder.set_os(&self.os);
+        builder.set_arch(&self.arch);
+        builder.set_kernel(&self.kernel);
+        builder.set_version(&self.version);
+    }
+}
@@ -0,0 +1,40 @@
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+
+use native_tls;
+use tokio_tls::{TlsAcceptor, TlsConnector};
+
+use errors::Result;
+
+fn read_file(path: &Path, what: &str) -> Result<Vec<u8>> {
+    let mut data = Vec::new();
+    File::open(path)
+        .and_then(|mut file| file.read_to_end(&mut data))
+        .map_err(|e| format!("Cannot read {} {:?}: {}", what, path, e))?;
+    Ok(data)
+}
+
+/// Create acceptor of TLS connections from PEM files with
+/// the certificate (chain) and the private key (PKCS#8)
+pub fn load_acceptor(cert: &Path, key: &Path) -> Result<TlsAcceptor> {
+    let identity = native_tls::Identity::from_pkcs8(
+        &read_file(cert, "TLS certificate")?,
+        &read_file(key, "TLS key")?,
+    ).map_err(|e| format!("Invalid TLS certificate {:?} or key {:?}: {}", cert, key, e))?;
+    let acceptor = native_tls::TlsAcceptor::new(identity)
+        .map_err(|e| format!("Cannot initialize TLS: {}", e))?;
+    Ok(acceptor.into())
+}
+
+/// Create connector that verifies the peer against the system roots
+/// and the CA certificate from the PEM file
+pub fn load_connector(ca: &Path) -> Result<TlsConnector> {
+    let certificate = native_tls::Certificate::from_pem(&read_file(ca, "CA certificate")?)
+        .map_err(|e| format!("Invalid CA certificate {:?}: {}", ca, e))?;
+    let connector = native_tls::TlsConnector::builder()
+        .add_root_certificate(certificate)
+        .build()
+        .map_err(|e| format!("Cannot initialize TLS: {}", e))?;
+    Ok(connector.into())
+}
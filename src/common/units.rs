@@ -0,0 +1,282 @@
+use std::fmt;
+use std::time::Duration;
+
+use serde::de::{self, Deserialize, Deserializer, Visitor};
+
+use errors::Result;
+
+/// Multiplier of a unit of size; units are case insensitive, prefixes without "b"
+/// and with "ib" are binary (e.g. "M" and "MiB" are 1024^2), with "b" decimal ("MB" is 10^6)
+fn size_unit(unit: &str) -> Option<u64> {
+    let unit = unit.to_lowercase();
+    if unit.is_empty() || unit == "b" {
+        return Some(1);
+    }
+    let mut chars = unit.chars();
+    let exponent = match chars.next() {
+        Some('k') => 1,
+        Some('m') => 2,
+        Some('g') => 3,
+        Some('t') => 4,
+        Some('p') => 5,
+        _ => return None,
+    };
+    match chars.as_str() {
+        "" | "i" | "ib" => Some(1024u64.pow(exponent)),
+        "b" => Some(1000u64.pow(exponent)),
+        _ => None,
+    }
+}
+
+fn parse_size_value(value: &str) -> ::std::result::Result<u64, String> {
+    let value = value.trim();
+    let split = value
+        .find(|c: char| !c.is_digit(10) && c != '.')
+        .unwrap_or_else(|| value.len());
+    let (number, unit) = (&value[..split], value[split..].trim_left());
+    let multiplier = size_unit(unit).ok_or_else(|| {
+        format!("unknown unit '{}' (use e.g. 512, 100kB, 20GiB or 512M)", unit)
+    })?;
+    if let Ok(count) = number.parse::<u64>() {
+        return count
+            .checked_mul(multiplier)
+            .ok_or_else(|| "size is too large".to_string());
+    }
+    match number.parse::<f64>() {
+        Ok(count) if count * (multiplier as f64) < ::std::u64::MAX as f64 => {
+            Ok((count * multiplier as f64).round() as u64)
+        }
+        Ok(_) => Err("size is too large".to_string()),
+        Err(_) => Err(format!("expected a number, got '{}'", number)),
+    }
+}
+
+/// Seconds of a unit of duration
+fn duration_unit(unit: &str) -> Option<f64> {
+    Some(match unit {
+        "ms" => 0.001,
+        "s" => 1.0,
+        "m" => 60.0,
+        "h" => 3600.0,
+        "d" => 86400.0,
+        _ => return None,
+    })
+}
+
+fn parse_duration_value(value: &str) -> ::std::result::Result<Duration, String> {
+    let value = value.trim();
+    if value.is_empty() {
+        return Err("empty value".to_string());
+    }
+    let mut secs = 0f64;
+    let mut rest = value;
+    if let Ok(number) = rest.parse::<f64>() {
+        // A number without unit is in seconds
+        secs = number;
+        rest = "";
+    }
+    while !rest.is_empty() {
+        let split = rest.find(|c: char| !c.is_digit(10) && c != '.')
+            .unwrap_or_else(|| rest.len());
+        let number = rest[..split]
+            .parse::<f64>()
+            .map_err(|_| format!("expected a number in '{}'", rest))?;
+        rest = &rest[split..];
+        let split = rest.find(|c: char| c.is_digit(10) || c == '.')
+            .unwrap_or_else(|| rest.len());
+        let unit = &rest[..split];
+        secs += number * duration_unit(unit).ok_or_else(|| {
+            format!(
+                "unknown unit '{}' (use e.g. 90, 1.5s, 500ms, 10m or 2h30m)",
+                unit
+            )
+        })?;
+        rest = &rest[split..];
+    }
+    if secs < 0f64 || !secs.is_finite() || secs > ::std::u64::MAX as f64 {
+        return Err("duration out of range".to_string());
+    }
+    Ok(Duration::new(
+        secs.trunc() as u64,
+        (secs.fract() * 1e9).round().min(999_999_999f64) as u32,
+    ))
+}
+
+/// Parse size in bytes with an optional unit, e.g. "1048576", "20GiB", "512M" or "100kB"
+/// (see `size_unit`); `field` names the option or the attribute in errors
+pub fn parse_size(field: &str, value: &str) -> Result<u64> {
+    parse_size_value(value).map_err(|e| format!("Invalid {} '{}': {}", field, value, e).into())
+}
+
+/// Parse duration: a number of seconds, or numbers with units "d", "h", "m", "s"
+/// and "ms", e.g. "90", "1.5s", "500ms" or "2h30m"; `field` names the option
+/// or the attribute in errors
+pub fn parse_duration(field: &str, value: &str) -> Result<Duration> {
+    parse_duration_value(value)
+        .map_err(|e| format!("Invalid {} '{}': {}", field, value, e).into())
+}
+
+/// Duration in seconds as a floating point number
+#[inline]
+pub fn duration_secs(duration: Duration) -> f64 {
+    duration.as_secs() as f64 + f64::from(duration.subsec_nanos()) / 1e9
+}
+
+struct SizeVisitor;
+
+impl<'de> Visitor<'de> for SizeVisitor {
+    type Value = u64;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("size in bytes or a string with a unit (e.g. \"20GiB\")")
+    }
+
+    fn visit_u64<E: de::Error>(self, value: u64) -> ::std::result::Result<u64, E> {
+        Ok(value)
+    }
+
+    fn visit_i64<E: de::Error>(self, value: i64) -> ::std::result::Result<u64, E> {
+        if value < 0 {
+            return Err(E::custom("size cannot be negative"));
+        }
+        Ok(value as u64)
+    }
+
+    fn visit_str<E: de::Error>(self, value: &str) -> ::std::result::Result<u64, E> {
+        parse_size_value(value).map_err(E::custom)
+    }
+}
+
+struct DurationVisitor;
+
+impl<'de> Visitor<'de> for DurationVisitor {
+    type Value = Duration;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("number of seconds or a string with units (e.g. \"2h30m\")")
+    }
+
+    fn visit_u64<E: de::Error>(self, value: u64) -> ::std::result::Result<Duration, E> {
+        Ok(Duration::from_secs(value))
+    }
+
+    fn visit_i64<E: de::Error>(self, value: i64) -> ::std::result::Result<Duration, E> {
+        if value < 0 {
+            return Err(E::custom("duration cannot be negative"));
+        }
+        Ok(Duration::from_secs(value as u64))
+    }
+
+    fn visit_f64<E: de::Error>(self, value: f64) -> ::std::result::Result<Duration, E> {
+        parse_duration_value(&value.to_string()).map_err(E::custom)
+    }
+
+    fn visit_str<E: de::Error>(self, value: &str) -> ::std::result::Result<Duration, E> {
+        parse_duration_value(value).map_err(E::custom)
+    }
+}
+
+/// Size in bytes deserialized from a number or from a string with a unit,
+/// e.g. `attributes.find::<Size>("tmpfs")`
+pub struct Size(pub u64);
+
+impl<'de> Deserialize<'de> for Size {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> ::std::result::Result<Self, D::Error> {
+        deserializer.deserialize_any(SizeVisitor).map(Size)
+    }
+}
+
+/// Duration deserialized from a number of seconds or from a string with units
+pub struct Span(pub Duration);
+
+impl<'de> Deserialize<'de> for Span {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> ::std::result::Result<Self, D::Error> {
+        deserializer.deserialize_any(DurationVisitor).map(Span)
+    }
+}
+
+/// Deserialize size in bytes given as a number or as a string with a unit
+/// (`#[serde(deserialize_with = "...")]` of configs and attributes)
+pub fn deserialize_size<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> ::std::result::Result<u64, D::Error> {
+    Size::deserialize(deserializer).map(|s| s.0)
+}
+
+/// Optional variant of `deserialize_size`, null is `None`
+pub fn deserialize_size_opt<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> ::std::result::Result<Option<u64>, D::Error> {
+    Option::<Size>::deserialize(deserializer).map(|s| s.map(|s| s.0))
+}
+
+/// Deserialize duration given as a number of seconds or as a string with units
+pub fn deserialize_duration<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> ::std::result::Result<Duration, D::Error> {
+    Span::deserialize(deserializer).map(|s| s.0)
+}
+
+/// Optional variant of `deserialize_duration`, null is `None`
+pub fn deserialize_duration_opt<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> ::std::result::Result<Option<Duration>, D::Error> {
+    Option::<Span>::deserialize(deserializer).map(|s| s.map(|s| s.0))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_size() {
+        assert_eq!(parse_size("size", "1234").unwrap(), 1234);
+        assert_eq!(parse_size("size", "20GiB").unwrap(), 20 << 30);
+        assert_eq!(parse_size("size", "512M").unwrap(), 512 << 20);
+        assert_eq!(parse_size("size", "100 kB").unwrap(), 100_000);
+        assert_eq!(parse_size("size", "1.5k").unwrap(), 1536);
+        assert_eq!(parse_size("size", "7b").unwrap(), 7);
+        let error = parse_size("--memory", "12X").unwrap_err().to_string();
+        assert!(error.contains("--memory") && error.contains("'X'"));
+        assert!(parse_size("size", "").is_err());
+        assert!(parse_size("size", "-1").is_err());
+        assert!(parse_size("size", "100000PiB").is_err());
+    }
+
+    #[test]
+    fn test_parse_duration() {
+        let secs = |value| duration_secs(parse_duration("timeout", value).unwrap());
+        assert_eq!(secs("90"), 90.0);
+        assert_eq!(secs("0.25"), 0.25);
+        assert_eq!(secs("2h30m"), 9000.0);
+        assert_eq!(secs("1d"), 86400.0);
+        assert_eq!(secs("1.5s"), 1.5);
+        assert_eq!(secs("1m500ms"), 60.5);
+        let error = parse_duration("timeout", "10y").unwrap_err().to_string();
+        assert!(error.contains("timeout") && error.contains("'y'"));
+        assert!(parse_duration("timeout", "").is_err());
+        assert!(parse_duration("timeout", "h").is_err());
+        assert!(parse_duration("timeout", "-5").is_err());
+    }
+
+    #[test]
+    fn test_deserialize() {
+        #[derive(Deserialize)]
+        struct Config {
+            #[serde(deserialize_with = "deserialize_size")]
+            size: u64,
+            #[serde(default, deserialize_with = "deserialize_duration_opt")]
+            timeout: Option<Duration>,
+        }
+        let config: Config =
+            ::serde_json::from_str(r#"{"size": "2KiB", "timeout": "1m"}"#).unwrap();
+        assert_eq!(config.size, 2048);
+        assert_eq!(config.timeout, Some(Duration::from_secs(60)));
+        let config: Config = ::serde_json::from_str(r#"{"size": 10}"#).unwrap();
+        assert_eq!(config.size, 10);
+        assert_eq!(config.timeout, None);
+        let config: Config = ::serde_json::from_str(r#"{"size": 0, "timeout": null}"#).unwrap();
+        assert_eq!(config.timeout, None);
+        assert!(::serde_json::from_str::<Config>(r#"{"size": "10 parsecs"}"#).is_err());
+    }
+}
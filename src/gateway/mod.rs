@@ -0,0 +1,104 @@
+//! Gateway for workers behind a firewall. It is a plain TCP forwarder: every incoming
+//! connection is forwarded as a separate connection to the server, there is no
+//! multiplexing of worker connections.
+
+use std::io::{self, Read, Write};
+use std::net::{Shutdown, SocketAddr};
+use std::rc::Rc;
+
+use futures::{Future, Poll, Stream};
+use tokio_core::net::{TcpListener, TcpStream};
+use tokio_core::reactor::Handle;
+use tokio_io::{AsyncRead, AsyncWrite};
+use tokio_io::io::{copy, shutdown};
+
+use errors::Result;
+
+/// Shared half of relayed TCP stream; shutdown closes the writing side of the socket,
+/// so the end of stream is propagated to the peer
+#[derive(Clone)]
+struct RelayStream(Rc<TcpStream>);
+
+impl Read for RelayStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        (&*self.0).read(buf)
+    }
+}
+
+impl Write for RelayStream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        (&*self.0).write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl AsyncRead for RelayStream {}
+
+impl AsyncWrite for RelayStream {
+    fn shutdown(&mut self) -> Poll<(), io::Error> {
+        self.0.shutdown(Shutdown::Write)?;
+        Ok(().into())
+    }
+}
+
+/// Copy data in both directions until both sides close the connection
+fn relay(
+    downstream: TcpStream,
+    upstream: TcpStream,
+) -> Box<Future<Item = (u64, u64), Error = io::Error>> {
+    let downstream = RelayStream(Rc::new(downstream));
+    let upstream = RelayStream(Rc::new(upstream));
+    let to_server = copy(downstream.clone(), upstream.clone())
+        .and_then(|(n, _, upstream)| shutdown(upstream).map(move |_| n));
+    let to_worker = copy(upstream, downstream)
+        .and_then(|(n, _, downstream)| shutdown(downstream).map(move |_| n));
+    Box::new(to_server.join(to_worker))
+}
+
+/// Start the gateway for workers behind a firewall (e.g. a cloud site of a hybrid cluster).
+/// It listens on `listen_address` and forwards each incoming connection to the server
+/// through its own outbound connection, so only the gateway needs an access to the
+/// server and no inbound port has to be opened on worker nodes. Workers started with
+/// `--via-gateway` announce it to the server, so the server reaches their data stores
+/// through the control connection.
+/// Transfers between workers of the same site stay local (they connect to each other directly).
+pub fn start_gateway(
+    handle: &Handle,
+    listen_address: SocketAddr,
+    server_address: SocketAddr,
+) -> Result<()> {
+    let listener = TcpListener::bind(&listen_address, handle)?;
+    info!(
+        "Gateway listens on {} and relays to {}",
+        listen_address, server_address
+    );
+    let handle2 = handle.clone();
+    let future = listener
+        .incoming()
+        .for_each(move |(stream, address)| {
+            info!("New connection from {}", address);
+            stream.set_nodelay(true).unwrap();
+            let relayed = TcpStream::connect(&server_address, &handle2)
+                .and_then(move |upstream| {
+                    upstream.set_nodelay(true).unwrap();
+                    relay(stream, upstream)
+                })
+                .map(move |(sent, received)| {
+                    info!(
+                        "Connection {} closed ({} bytes sent, {} bytes received)",
+                        address, sent, received
+                    );
+                })
+                .map_err(move |e| error!("Relaying connection {} failed: {}", address, e));
+            handle2.spawn(relayed);
+            Ok(())
+        })
+        .map_err(|e| {
+            panic!("Listening failed {:?}", e);
+        });
+    handle.spawn(future);
+    Ok(())
+}
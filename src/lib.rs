@@ -1,26 +1,35 @@
 #[macro_use]
 extern crate arrayref;
+extern crate arrow;
 extern crate bytes;
 #[macro_use]
 extern crate capnp;
 #[macro_use]
 extern crate capnp_rpc;
+extern crate chacha20poly1305;
 extern crate chrono;
 #[macro_use]
 extern crate error_chain;
+extern crate flate2;
 extern crate fs_extra;
 extern crate futures;
+extern crate futures_cpupool;
 extern crate hyper;
+extern crate jmespath;
 #[macro_use]
 extern crate lazy_static;
 #[macro_use]
 extern crate log;
 extern crate memmap;
+extern crate native_tls;
 extern crate nix;
+extern crate parquet;
+extern crate regex;
 extern crate rusqlite;
 extern crate serde;
 #[macro_use]
 extern crate serde_derive;
+#[macro_use]
 extern crate serde_json;
 extern crate sys_info;
 extern crate sysconf;
@@ -30,12 +39,19 @@ extern crate tokio_core;
 extern crate tokio_io;
 extern crate tokio_process;
 extern crate tokio_timer;
+extern crate tokio_tls;
 extern crate tokio_uds;
 extern crate walkdir;
 
 pub mod common;
 pub mod worker;
 pub mod server;
+pub mod gateway;
+pub mod local;
+pub mod admin;
+pub mod bench;
+pub mod bundle;
+pub mod broker;
 
 pub const VERSION: &str = env!("CARGO_PKG_VERSION");
 pub const WORKER_PROTOCOL_VERSION: i32 = 0;
@@ -70,6 +86,27 @@ pub mod errors {
             Ignored {
                 description("Request asked for ignored id")
             }
+            LimitExceeded(limit: String, message: String) {
+                description("Process limit exceeded")
+                display("Process limit '{}' exceeded\n{}", limit, message)
+            }
+            InfeasibleTasks(tasks: Vec<::common::id::TaskId>, largest: ::common::resources::Resources) {
+                description("Tasks cannot be scheduled on any worker")
+                display("Resources of tasks {} exceed capacity of every worker \
+                         (largest worker has {} cpus, the most memory of a worker is {} bytes, \
+                         the most gpus of a worker is {}{})",
+                        tasks.iter().map(|t| t.to_string()).collect::<Vec<_>>().join(", "),
+                        largest.cpus, largest.memory, largest.gpus,
+                        largest.named.iter()
+                            .map(|(name, count)| format!(", the most '{}' is {}", name, count))
+                            .collect::<String>())
+            }
+            IdCollision(tasks: Vec<::common::id::TaskId>, objects: Vec<::common::id::DataObjectId>) {
+                description("Ids of submitted tasks or objects are already used")
+                display("Ids already used in their sessions: tasks [{}], objects [{}]",
+                        tasks.iter().map(|t| t.to_string()).collect::<Vec<_>>().join(", "),
+                        objects.iter().map(|o| o.to_string()).collect::<Vec<_>>().join(", "))
+            }
         }
     }
     // Explicit alias just to make the IDEs happier
@@ -109,3 +146,7 @@ pub mod subworker_capnp {
 pub mod monitor_capnp {
     include!(concat!(env!("OUT_DIR"), "/capnp/monitor_capnp.rs"));
 }
+
+pub mod broker_capnp {
+    include!(concat!(env!("OUT_DIR"), "/capnp/broker_capnp.rs"));
+}
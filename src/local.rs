@@ -0,0 +1,320 @@
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+
+use tempdir::TempDir;
+use tokio_core::reactor::Core;
+
+use common::id::{DataObjectId, Id, SId, TaskId};
+use common::framing::Framing;
+use common::resources::{ResourcePool, Resources};
+use common::{Attributes, DataType};
+use errors::Result;
+use server;
+use server::access::Identity;
+use server::graph::TaskInput;
+use worker;
+use worker::subworkers::{SubworkerKind, PYTHON_SUBWORKER};
+
+/// How long `run_graph` waits for the registration of the worker
+const WORKER_START_TIMEOUT: u64 = 10;
+
+/// Finished objects up to this size are sent inline from the worker to the server
+const LOCAL_INLINE_THRESHOLD: usize = 64 * 1024;
+
+/// Configuration of `run_graph`
+pub struct LocalConfig {
+    /// Number of cpus of the worker
+    pub cpus: u32,
+    /// Directory for working and logging directories of the server and the worker,
+    /// a temporary directory (removed at the end) is used when it is not given
+    pub dir: Option<PathBuf>,
+    /// Subworkers of the worker by their types; "py" is started by `python3 -m rain.subworker`
+    /// when not given
+    pub subworkers: HashMap<String, Vec<String>>,
+}
+
+impl Default for LocalConfig {
+    fn default() -> Self {
+        LocalConfig {
+            cpus: 1,
+            dir: None,
+            subworkers: HashMap::new(),
+        }
+    }
+}
+
+/// Reference to an object of `LocalGraph`
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct ObjectHandle(Id);
+
+struct LocalObject {
+    id: Id,
+    label: String,
+    data: Option<Vec<u8>>,
+    keep: bool,
+}
+
+struct LocalTask {
+    id: Id,
+    task_type: String,
+    inputs: Vec<(ObjectHandle, String)>,
+    outputs: Vec<ObjectHandle>,
+    attributes: Attributes,
+}
+
+/// Graph of tasks and objects executed by `run_graph`
+#[derive(Default)]
+pub struct LocalGraph {
+    objects: Vec<LocalObject>,
+    tasks: Vec<LocalTask>,
+    next_id: Id,
+}
+
+impl LocalGraph {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    fn new_object(&mut self, label: String, data: Option<Vec<u8>>) -> ObjectHandle {
+        self.next_id += 1;
+        self.objects.push(LocalObject {
+            id: self.next_id,
+            label,
+            data,
+            keep: false,
+        });
+        ObjectHandle(self.next_id)
+    }
+
+    /// Constant blob
+    pub fn blob(&mut self, data: Vec<u8>) -> ObjectHandle {
+        self.new_object("const".to_string(), Some(data))
+    }
+
+    /// Add a task with labeled inputs, returns its outputs (with the given labels).
+    /// Attribute "resources" is 1 cpu when it is not set.
+    pub fn task(
+        &mut self,
+        task_type: &str,
+        inputs: &[(ObjectHandle, &str)],
+        outputs: &[&str],
+        mut attributes: Attributes,
+    ) -> Result<Vec<ObjectHandle>> {
+        if attributes.find::<Resources>("resources")?.is_none() {
+            attributes.set(
+                "resources",
+                Resources {
+                    cpus: 1,
+                    ..Default::default()
+                },
+            )?;
+        }
+        let outputs: Vec<_> = outputs
+            .iter()
+            .map(|label| self.new_object(label.to_string(), None))
+            .collect();
+        self.next_id += 1;
+        self.tasks.push(LocalTask {
+            id: self.next_id,
+            task_type: task_type.to_string(),
+            inputs: inputs
+                .iter()
+                .map(|&(object, label)| (object, label.to_string()))
+                .collect(),
+            outputs: outputs.clone(),
+            attributes,
+        });
+        Ok(outputs)
+    }
+
+    /// Data of kept objects are returned by `run_graph`
+    pub fn keep(&mut self, object: ObjectHandle) {
+        self.objects
+            .iter_mut()
+            .find(|o| o.id == object.0)
+            .expect("Object is not in the graph")
+            .keep = true;
+    }
+}
+
+/// Run the graph in this process by a server and one worker (communicating
+/// only over the loopback interface) and return the data of kept objects.
+/// The call blocks until all tasks are finished; failure of a task is returned as an error.
+/// Intended for quick experiments and tests of workflows, not for production.
+pub fn run_graph(
+    graph: LocalGraph,
+    config: LocalConfig,
+) -> Result<HashMap<ObjectHandle, Vec<u8>>> {
+    let temp_dir = match config.dir {
+        Some(_) => None,
+        None => Some(TempDir::new("rain-local")?),
+    };
+    let dir = config
+        .dir
+        .clone()
+        .unwrap_or_else(|| temp_dir.as_ref().unwrap().path().to_path_buf());
+    for name in &["server-logs", "worker-logs", "worker-work"] {
+        ::std::fs::create_dir_all(dir.join(name))?;
+    }
+
+    let mut core = Core::new()?;
+    let loopback: SocketAddr = "127.0.0.1:0".parse().unwrap();
+
+    let server = server::state::StateRef::new(
+        core.handle(),
+        loopback,
+        loopback,
+        dir.join("server-logs"),
+        false,
+        ResourcePool::default(),
+        None,
+        Duration::from_secs(0),
+        LOCAL_INLINE_THRESHOLD,
+        None,
+        None,
+        None,
+        Default::default(),
+    );
+    server.start();
+
+    let mut subworkers: HashMap<_, _> = config
+        .subworkers
+        .into_iter()
+        .map(|(subworker_type, command)| (subworker_type, SubworkerKind::new(command)))
+        .collect();
+    subworkers
+        .entry(PYTHON_SUBWORKER.to_string())
+        .or_insert_with(SubworkerKind::python);
+    let cpus = ::std::cmp::max(config.cpus, 1);
+    let worker = worker::state::StateRef::new(
+        core.handle(),
+        dir.join("worker-work"),
+        dir.join("worker-logs"),
+        cpus,
+        4 * cpus,
+        subworkers,
+        Framing::Plain,
+        false,
+        0f64,
+        None,
+        false,
+        0,
+        None,
+        None,
+        Box::new(worker::data::DiskStorage::default()),
+        ::VERSION.to_string(),
+    );
+    worker
+        .get_mut()
+        .set_memory(::common::sys::get_total_memory().unwrap_or(0));
+    worker
+        .get_mut()
+        .set_gpus(::common::sys::get_gpu_devices());
+    worker.start(server.listen_address(), None, loopback, None, false);
+
+    let turn = |core: &mut Core| {
+        core.turn(Some(Duration::from_millis(100)));
+        server.turn();
+        worker.turn();
+    };
+
+    let deadline = Instant::now() + Duration::from_secs(WORKER_START_TIMEOUT);
+    while server.get().worker_count() == 0 {
+        if Instant::now() > deadline {
+            bail!("Local worker was not registered in {}s", WORKER_START_TIMEOUT);
+        }
+        turn(&mut core);
+    }
+
+    // Submit the graph as a client of the server would do
+    let session = {
+        let mut s = server.get_mut();
+        let client = s.add_client(loopback, &Identity::anonymous().name)?;
+        let session = s.add_session(&client)?;
+        let session_id = session.get_id();
+        let mut objects = HashMap::new();
+        let mut created_objects = Vec::new();
+        for o in &graph.objects {
+            let oref = s.add_object(
+                &session,
+                DataObjectId::new(session_id, o.id),
+                o.keep,
+                o.label.clone(),
+                DataType::Blob,
+                o.data.clone(),
+                Attributes::new(),
+            )?;
+            objects.insert(o.id, oref.clone());
+            created_objects.push(oref);
+        }
+        let mut created_tasks = Vec::new();
+        for t in &graph.tasks {
+            let inputs = t.inputs
+                .iter()
+                .map(|&(object, ref label)| TaskInput {
+                    object: objects[&object.0].clone(),
+                    label: label.clone(),
+                    path: String::new(),
+                })
+                .collect();
+            let outputs = t.outputs.iter().map(|o| objects[&o.0].clone()).collect();
+            let resources = t.attributes.get("resources")?;
+            created_tasks.push(s.add_task(
+                &session,
+                TaskId::new(session_id, t.id),
+                inputs,
+                outputs,
+                t.task_type.clone(),
+                t.attributes.clone(),
+                resources,
+            )?);
+        }
+        s.verify_submit(&created_tasks, &created_objects)?;
+        session
+    };
+
+    loop {
+        if let Some(ref error) = *session.get().get_error() {
+            return Err(error.clone().into());
+        }
+        if session.get().unfinished_tasks() == 0 {
+            break;
+        }
+        turn(&mut core);
+    }
+
+    let mut results = HashMap::new();
+    for o in graph.objects.iter().filter(|o| o.keep) {
+        let id = DataObjectId::new(session.get_id(), o.id);
+        let data = match o.data {
+            Some(ref data) => data.clone(),
+            None => match worker.get().object_data(id) {
+                Some(data) => data.read_bytes()?
+                    .ok_or_else(|| format!("Object {} is a directory", id))?,
+                None => bail!("Data of object {} not found on the worker", id),
+            },
+        };
+        results.insert(ObjectHandle(o.id), data);
+    }
+    Ok(results)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_run_graph_concat() {
+        let mut graph = LocalGraph::new();
+        let a = graph.blob(b"Hello ".to_vec());
+        let b = graph.blob(b"world".to_vec());
+        let outputs = graph
+            .task("!concat", &[(a, ""), (b, "")], &["output"], Attributes::new())
+            .unwrap();
+        graph.keep(outputs[0]);
+        let results = run_graph(graph, Default::default()).unwrap();
+        assert_eq!(results[&outputs[0]], b"Hello world".to_vec());
+    }
+}
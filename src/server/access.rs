@@ -0,0 +1,138 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+use std::path::Path;
+use std::str::FromStr;
+
+use errors::{Error, Result};
+
+/// Role of an authenticated client; each role allows also operations of lower roles
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Role {
+    /// Inspect states of sessions, tasks and objects, fetch data
+    Readonly,
+    /// Create sessions, submit, cancel and close them, manage own secrets
+    User,
+    /// Server administration (terminating the server)
+    Admin,
+}
+
+impl FromStr for Role {
+    type Err = Error;
+
+    fn from_str(value: &str) -> Result<Self> {
+        match value {
+            "readonly" => Ok(Role::Readonly),
+            "user" => Ok(Role::User),
+            "admin" => Ok(Role::Admin),
+            _ => bail!("Invalid role '{}' (expected admin, user or readonly)", value),
+        }
+    }
+}
+
+impl fmt::Display for Role {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match *self {
+            Role::Readonly => "readonly",
+            Role::User => "user",
+            Role::Admin => "admin",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// Authenticated client
+#[derive(Clone, Debug)]
+pub struct Identity {
+    pub name: String,
+    pub role: Role,
+}
+
+impl Identity {
+    /// Identity of clients when access control is disabled
+    pub fn anonymous() -> Self {
+        Identity {
+            name: "anonymous".to_string(),
+            role: Role::Admin,
+        }
+    }
+
+    /// The same client on an observer connection, it may not change anything
+    pub fn observer(self) -> Self {
+        Identity {
+            role: ::std::cmp::min(self.role, Role::Readonly),
+            ..self
+        }
+    }
+
+    pub fn check_role(&self, role: Role) -> Result<()> {
+        if self.role < role {
+            bail!(
+                "Permission denied: '{}' has role {}, the operation requires role {}",
+                self.name,
+                self.role,
+                role
+            );
+        }
+        Ok(())
+    }
+}
+
+/// Identities of clients by their access tokens (set by --access-file)
+pub struct AccessControl {
+    tokens: HashMap<String, Identity>,
+}
+
+impl AccessControl {
+    /// Load the access file; each line contains "NAME ROLE TOKEN",
+    /// empty lines and lines starting with '#' are ignored
+    pub fn load(path: &Path) -> Result<Self> {
+        let file = File::open(path)
+            .map_err(|e| format!("Cannot open access file {:?}: {}", path, e))?;
+        let mut tokens = HashMap::new();
+        for (i, line) in BufReader::new(file).lines().enumerate() {
+            let line = line?;
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let items: Vec<_> = line.split_whitespace().collect();
+            if items.len() != 3 {
+                bail!(
+                    "Invalid line {} of access file {:?}, expected: NAME ROLE TOKEN",
+                    i + 1,
+                    path
+                );
+            }
+            let identity = Identity {
+                name: items[0].to_string(),
+                role: items[1].parse()?,
+            };
+            if tokens.insert(items[2].to_string(), identity).is_some() {
+                bail!("Duplicate token on line {} of access file {:?}", i + 1, path);
+            }
+        }
+        Ok(AccessControl { tokens })
+    }
+
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.tokens.len()
+    }
+
+    pub fn authenticate(&self, token: &str) -> Result<Identity> {
+        match self.tokens.get(token) {
+            Some(identity) => Ok(identity.clone()),
+            None => bail!("Authentication failed: invalid access token"),
+        }
+    }
+
+    /// Identity of a user authenticated by someone else (e.g. reverse proxy)
+    pub fn identity_by_name(&self, name: &str) -> Result<Identity> {
+        match self.tokens.values().find(|identity| identity.name == name) {
+            Some(identity) => Ok(identity.clone()),
+            None => bail!("Authentication failed: unknown user '{}'", name),
+        }
+    }
+}
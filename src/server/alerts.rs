@@ -0,0 +1,235 @@
+use std::collections::{HashMap, VecDeque};
+use std::fs::File;
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+use errors::Result;
+
+/// Window of sessions counted in `Metric::SessionFailureRate`
+const SESSION_WINDOW: u64 = 3600; // seconds
+
+/// Value watched by an alert rule
+#[derive(Deserialize, Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[serde(rename_all = "snake_case")]
+pub enum Metric {
+    /// Age of the oldest task that is ready but not assigned to a worker (seconds)
+    ReadyTaskAge,
+    /// Number of tasks that are ready but not assigned to a worker
+    ReadyTasks,
+    /// How long is the longest lost worker missing (seconds); a worker is missing
+    /// until a worker from the same host registers again
+    WorkerDown,
+    /// Number of registered workers
+    Workers,
+    /// Fraction of failed sessions among sessions finished in the last hour
+    SessionFailureRate,
+    /// Fraction of cpus of workers allocated by running tasks
+    CpuUtilization,
+}
+
+/// Rule of alert file (server option --alerts); the alert fires when the value
+/// of the metric is above the threshold (or below it when `below` is set)
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct AlertRule {
+    pub name: String,
+    pub metric: Metric,
+    pub threshold: f64,
+    #[serde(default)]
+    pub below: bool,
+    /// URL (http) where the alert is posted as JSON when it fires and when it is resolved
+    pub webhook: Option<String>,
+}
+
+impl AlertRule {
+    fn is_violated(&self, value: f64) -> bool {
+        if self.below {
+            value < self.threshold
+        } else {
+            value > self.threshold
+        }
+    }
+}
+
+/// Change of state of an alert, it is logged as an event and posted to the webhook
+#[derive(Serialize, Debug)]
+pub struct AlertChange {
+    pub alert: String,
+    pub firing: bool,
+    pub value: f64,
+    pub threshold: f64,
+    #[serde(skip)]
+    pub webhook: Option<String>,
+}
+
+impl AlertChange {
+    pub fn message(&self) -> String {
+        format!(
+            "Alert '{}' {} (value {}, threshold {})",
+            self.alert,
+            if self.firing { "fired" } else { "resolved" },
+            self.value,
+            self.threshold
+        )
+    }
+}
+
+/// Alert rules with the state needed to evaluate them
+pub struct Alerts {
+    rules: Vec<AlertRule>,
+    firing: Vec<bool>,
+    /// Hosts of lost workers and when they were lost
+    lost_workers: HashMap<String, Instant>,
+    /// Finished sessions (time, failed) in the last `SESSION_WINDOW`
+    sessions: VecDeque<(Instant, bool)>,
+}
+
+impl Alerts {
+    /// Load alert rules from a JSON file with a list of rules
+    pub fn load(path: &Path) -> Result<Self> {
+        let file = File::open(path).map_err(|e| format!("Cannot open {:?}: {}", path, e))?;
+        let rules: Vec<AlertRule> = ::serde_json::from_reader(file)
+            .map_err(|e| format!("Invalid alert file {:?}: {}", path, e))?;
+        Ok(Self::new(rules))
+    }
+
+    pub fn new(rules: Vec<AlertRule>) -> Self {
+        Alerts {
+            firing: vec![false; rules.len()],
+            rules,
+            lost_workers: HashMap::new(),
+            sessions: VecDeque::new(),
+        }
+    }
+
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.rules.len()
+    }
+
+    pub fn worker_added(&mut self, host: &str) {
+        self.lost_workers.remove(host);
+    }
+
+    pub fn worker_lost(&mut self, host: &str) {
+        self.lost_workers
+            .entry(host.to_string())
+            .or_insert_with(Instant::now);
+    }
+
+    pub fn session_finished(&mut self, failed: bool) {
+        self.sessions.push_back((Instant::now(), failed));
+    }
+
+    /// Value of a metric that is not computed from the graph
+    fn own_value(&mut self, metric: Metric) -> Option<f64> {
+        match metric {
+            Metric::WorkerDown => Some(
+                self.lost_workers
+                    .values()
+                    .map(|lost| lost.elapsed().as_secs())
+                    .max()
+                    .unwrap_or(0) as f64,
+            ),
+            Metric::SessionFailureRate => {
+                let window = Duration::from_secs(SESSION_WINDOW);
+                while self.sessions
+                    .front()
+                    .map(|&(time, _)| time.elapsed() > window)
+                    .unwrap_or(false)
+                {
+                    self.sessions.pop_front();
+                }
+                if self.sessions.is_empty() {
+                    return None;
+                }
+                let failed = self.sessions.iter().filter(|&&(_, failed)| failed).count();
+                Some(failed as f64 / self.sessions.len() as f64)
+            }
+            _ => None,
+        }
+    }
+
+    /// Evaluate rules with the given values of metrics computed from the graph;
+    /// returns alerts that started or stopped firing
+    pub fn check(&mut self, values: &HashMap<Metric, f64>) -> Vec<AlertChange> {
+        let mut changes = Vec::new();
+        for i in 0..self.rules.len() {
+            let metric = self.rules[i].metric;
+            let value = match values.get(&metric).cloned() {
+                Some(value) => value,
+                None => match self.own_value(metric) {
+                    Some(value) => value,
+                    None => continue,
+                },
+            };
+            let rule = &self.rules[i];
+            let firing = rule.is_violated(value);
+            if firing != self.firing[i] {
+                self.firing[i] = firing;
+                changes.push(AlertChange {
+                    alert: rule.name.clone(),
+                    firing,
+                    value,
+                    threshold: rule.threshold,
+                    webhook: rule.webhook.clone(),
+                });
+            }
+        }
+        changes
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rules(json: &str) -> Alerts {
+        Alerts::new(::serde_json::from_str(json).unwrap())
+    }
+
+    #[test]
+    fn test_alert_fires_and_resolves() {
+        let mut alerts =
+            rules(r#"[{"name": "queue", "metric": "ready_task_age", "threshold": 600}]"#);
+        let mut values = HashMap::new();
+        values.insert(Metric::ReadyTaskAge, 10.0);
+        assert!(alerts.check(&values).is_empty());
+        values.insert(Metric::ReadyTaskAge, 700.0);
+        let changes = alerts.check(&values);
+        assert_eq!(changes.len(), 1);
+        assert!(changes[0].firing);
+        assert!(alerts.check(&values).is_empty());
+        values.insert(Metric::ReadyTaskAge, 0.0);
+        let changes = alerts.check(&values);
+        assert_eq!(changes.len(), 1);
+        assert!(!changes[0].firing);
+    }
+
+    #[test]
+    fn test_alert_session_failure_rate() {
+        let mut alerts = rules(
+            r#"[{"name": "failures", "metric": "session_failure_rate", "threshold": 0.3},
+                {"name": "idle", "metric": "cpu_utilization", "threshold": 0.5, "below": true}]"#,
+        );
+        let mut values = HashMap::new();
+        values.insert(Metric::CpuUtilization, 0.8);
+        // No finished sessions, rate is not evaluated
+        assert!(alerts.check(&values).is_empty());
+        alerts.session_finished(false);
+        alerts.session_finished(false);
+        assert!(alerts.check(&values).is_empty());
+        alerts.session_finished(true);
+        let changes = alerts.check(&values);
+        assert_eq!(changes.len(), 1);
+        assert_eq!(changes[0].alert, "failures");
+        values.insert(Metric::CpuUtilization, 0.1);
+        assert_eq!(alerts.check(&values)[0].alert, "idle");
+    }
+
+    #[test]
+    fn test_alert_unknown_field() {
+        let json = r#"[{"name": "x", "metric": "workers", "treshold": 1}]"#;
+        assert!(::serde_json::from_str::<Vec<AlertRule>>(json).is_err());
+    }
+}
@@ -0,0 +1,422 @@
+use std::collections::BTreeMap;
+
+use server::graph::{SessionRef, TaskRef, TaskState};
+use server::state::State;
+use server::snapshot::GraphSnapshot;
+use server::scheduler::SchedulerStatus;
+use server::tags::split_tag;
+use common::id::{Id, SId, SessionId, TaskId, WorkerId};
+use common::labels::Labels;
+use common::sys::Platform;
+use errors::Result;
+
+/// Version of JSON API, it is a part of its paths ("/api/v1/...");
+/// fields may be added to responses, other changes need a new version
+pub const API_VERSION: u32 = 1;
+
+#[derive(Serialize)]
+struct WorkerInfo {
+    id: WorkerId,
+    cpus: u32,
+    assigned_tasks: usize,
+    located_objects: usize,
+    error: Option<String>,
+    /// Empty strings for workers of older versions
+    platform: Platform,
+}
+
+#[derive(Serialize)]
+struct ServerInfo {
+    version: &'static str,
+    api_version: u32,
+    workers: Vec<WorkerInfo>,
+    scheduler: SchedulerStatus,
+}
+
+#[derive(Serialize)]
+struct SessionErrorInfo {
+    message: String,
+    task: TaskId,
+}
+
+#[derive(Serialize)]
+struct GroupInfo {
+    name: String,
+    total: usize,
+    finished: usize,
+    running: usize,
+    failed: usize,
+}
+
+#[derive(Serialize)]
+struct SessionInfo {
+    id: SessionId,
+    name: String,
+    parent: Option<SessionId>,
+    client: String,
+    /// "running", "finished" or "error"
+    state: &'static str,
+    tasks: usize,
+    objects: usize,
+    unfinished_tasks: usize,
+    running_tasks: usize,
+    /// Limit of running tasks of the session set by its client
+    max_running_tasks: Option<usize>,
+    error: Option<SessionErrorInfo>,
+    tags: Labels,
+    /// Only in the detail of a session
+    #[serde(skip_serializing_if = "Option::is_none")]
+    groups: Option<Vec<GroupInfo>>,
+}
+
+/// Task found by tags
+#[derive(Serialize)]
+struct TaskInfo {
+    id: TaskId,
+    task_type: String,
+    state: &'static str,
+    group: Option<String>,
+    /// Tags of the task including tags inherited from its session
+    tags: BTreeMap<String, String>,
+}
+
+/// Compact state of a session for frequent polling (e.g. progress bars in notebooks)
+#[derive(Serialize)]
+struct SessionSnapshot {
+    /// Pass as `cursor` to the next request to get only changed tasks
+    cursor: u64,
+    state: &'static str,
+    /// Numbers of tasks in the session by states
+    counts: BTreeMap<&'static str, usize>,
+    /// Tasks created or changed since the cursor as pairs [task id, state]
+    changed: Vec<(Id, &'static str)>,
+    error: Option<SessionErrorInfo>,
+}
+
+fn task_state_name(state: TaskState) -> &'static str {
+    match state {
+        TaskState::NotAssigned => "waiting",
+        TaskState::Ready | TaskState::Assigned => "ready",
+        TaskState::Running => "running",
+        TaskState::Finished => "finished",
+        TaskState::Failed => "failed",
+    }
+}
+
+fn session_state_name(session: &SessionRef) -> &'static str {
+    let s = session.get();
+    if s.error.is_some() {
+        "error"
+    } else if s.unfinished_tasks > 0 {
+        "running"
+    } else {
+        "finished"
+    }
+}
+
+fn session_error_info(session: &SessionRef) -> Option<SessionErrorInfo> {
+    session.get().error.as_ref().map(|e| SessionErrorInfo {
+        message: e.message().to_string(),
+        task: e.task_id(),
+    })
+}
+
+/// Snapshot of the session with tasks changed after `cursor` (all tasks when it is
+/// not given or when it is not a cursor of the session)
+fn session_snapshot(session: &SessionRef, cursor: Option<u64>) -> SessionSnapshot {
+    let s = session.get();
+    let cursor = cursor.filter(|c| *c <= s.revision).unwrap_or(0);
+    let mut counts = BTreeMap::new();
+    let mut changed = Vec::new();
+    for tref in &s.tasks {
+        let t = tref.get();
+        let name = task_state_name(t.state);
+        *counts.entry(name).or_insert(0) += 1;
+        if t.revision > cursor {
+            changed.push((t.id.get_id(), name));
+        }
+    }
+    changed.sort();
+    SessionSnapshot {
+        cursor: s.revision,
+        state: session_state_name(session),
+        counts,
+        changed,
+        error: session_error_info(session),
+    }
+}
+
+/// Decode "%XX" escapes and '+' of a value of the query string
+fn decode_query_value(value: &str) -> String {
+    let bytes = value.as_bytes();
+    let mut result = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let escaped = if bytes[i] == b'%' && i + 2 < bytes.len() {
+            ::std::str::from_utf8(&bytes[i + 1..i + 3])
+                .ok()
+                .and_then(|hex| u8::from_str_radix(hex, 16).ok())
+        } else {
+            None
+        };
+        match escaped {
+            Some(byte) => {
+                result.push(byte);
+                i += 3;
+            }
+            None => {
+                result.push(if bytes[i] == b'+' { b' ' } else { bytes[i] });
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&result).into_owned()
+}
+
+/// All values of a parameter in the query string (e.g. "tag=a%3D1&tag=b%3D2")
+fn query_params(query: Option<&str>, name: &str) -> Vec<String> {
+    query
+        .map(|query| {
+            query
+                .split('&')
+                .filter_map(|pair| {
+                    let mut items = pair.splitn(2, '=');
+                    if items.next() == Some(name) {
+                        Some(decode_query_value(items.next().unwrap_or("")))
+                    } else {
+                        None
+                    }
+                })
+                .collect()
+        })
+        .unwrap_or_else(Vec::new)
+}
+
+/// Value of a parameter in the query string
+fn query_param<'a>(query: Option<&'a str>, name: &str) -> Option<&'a str> {
+    query.and_then(|query| {
+        query.split('&').find_map(|pair| {
+            let mut items = pair.splitn(2, '=');
+            if items.next() == Some(name) {
+                Some(items.next().unwrap_or(""))
+            } else {
+                None
+            }
+        })
+    })
+}
+
+fn session_info(session: &SessionRef, detail: bool) -> SessionInfo {
+    let state = session_state_name(session);
+    let s = session.get();
+    let groups = if detail {
+        let mut groups: Vec<_> = s.groups
+            .iter()
+            .map(|(name, group)| GroupInfo {
+                name: name.clone(),
+                total: group.total,
+                finished: group.finished,
+                running: group.running(),
+                failed: group.failed.len(),
+            })
+            .collect();
+        groups.sort_by(|a, b| a.name.cmp(&b.name));
+        Some(groups)
+    } else {
+        None
+    };
+    SessionInfo {
+        id: s.id,
+        name: s.name.clone(),
+        parent: s.parent.as_ref().map(|p| p.get().id),
+        client: s.client.get().id.to_string(),
+        state,
+        tasks: s.tasks.len(),
+        objects: s.objects.len(),
+        unfinished_tasks: s.unfinished_tasks,
+        running_tasks: s.running_tasks,
+        max_running_tasks: s.max_running_tasks,
+        error: session_error_info(session),
+        tags: s.tags.clone(),
+        groups,
+    }
+}
+
+fn task_info(tref: &TaskRef) -> TaskInfo {
+    let t = tref.get();
+    TaskInfo {
+        id: t.id,
+        task_type: t.task_type.clone(),
+        state: task_state_name(t.state),
+        group: t.group.clone(),
+        tags: t.tags
+            .iter()
+            .map(|tag| {
+                let (key, value) = split_tag(tag);
+                (key.to_string(), value.to_string())
+            })
+            .collect(),
+    }
+}
+
+/// Does the session have all the tags ("key=value")?
+fn session_has_tags(session: &SessionRef, tags: &[String]) -> bool {
+    let s = session.get();
+    tags.iter().all(|tag| {
+        let (key, value) = split_tag(tag);
+        s.tags.get(key).map(|v| v == value).unwrap_or(false)
+    })
+}
+
+/// Tasks with all the tags, in the given state and session; tags or session are required
+fn find_tasks(
+    state: &State,
+    tags: &[String],
+    task_state: Option<&str>,
+    session_id: Option<SessionId>,
+) -> Vec<TaskInfo> {
+    let tasks = if !tags.is_empty() {
+        state.graph.tags.find(tags)
+    } else {
+        match session_id.and_then(|id| state.graph.sessions.get(&id)) {
+            Some(session) => session.get().tasks.iter().cloned().collect(),
+            None => Vec::new(),
+        }
+    };
+    let mut tasks: Vec<_> = tasks
+        .iter()
+        .filter(|tref| {
+            let t = tref.get();
+            session_id.map(|id| t.session.get_id() == id).unwrap_or(true)
+                && task_state
+                    .map(|name| task_state_name(t.state) == name)
+                    .unwrap_or(true)
+        })
+        .map(task_info)
+        .collect();
+    tasks.sort_by_key(|t| t.id);
+    tasks
+}
+
+fn server_info(state: &State) -> ServerInfo {
+    let mut workers: Vec<_> = state
+        .graph
+        .workers
+        .values()
+        .map(|worker| {
+            let w = worker.get();
+            WorkerInfo {
+                id: *w.id(),
+                cpus: w.resources.cpus,
+                assigned_tasks: w.assigned_tasks.len(),
+                located_objects: w.located_objects.len(),
+                error: w.error.clone(),
+                platform: w.platform.clone(),
+            }
+        })
+        .collect();
+    workers.sort_by_key(|w| w.id);
+    ServerInfo {
+        version: ::VERSION,
+        api_version: API_VERSION,
+        workers,
+        scheduler: state.scheduler_status(),
+    }
+}
+
+/// Snapshot for path "graph", of the whole graph or of a session given by query
+/// parameter "session". Returns `None` for an unknown session.
+pub fn graph_snapshot(state: &State, query: Option<&str>) -> Result<Option<GraphSnapshot>> {
+    let session_id = match query_param(query, "session") {
+        Some(value) => {
+            let id: SessionId = value
+                .parse()
+                .map_err(|_| format!("Invalid session id '{}'", value))?;
+            if !state.graph.sessions.contains_key(&id) {
+                return Ok(None);
+            }
+            Some(id)
+        }
+        None => None,
+    };
+    Ok(Some(state.graph_snapshot(session_id)))
+}
+
+/// Answer a GET request of JSON API, `path` is the part after "/api/v1/".
+/// Returns `None` for unknown paths.
+pub fn get(state: &State, path: &str, query: Option<&str>) -> Result<Option<String>> {
+    let parts: Vec<_> = path.trim_matches('/').split('/').collect();
+    let result = match parts.as_slice() {
+        ["info"] => ::serde_json::to_string(&server_info(state))?,
+        ["sessions"] => {
+            let tags = query_params(query, "tag");
+            let mut sessions: Vec<_> = state
+                .graph
+                .sessions
+                .values()
+                .filter(|s| session_has_tags(s, &tags))
+                .map(|s| session_info(s, false))
+                .collect();
+            sessions.sort_by_key(|s| s.id);
+            ::serde_json::to_string(&sessions)?
+        }
+        ["sessions", id] => {
+            let id: SessionId = id.parse()
+                .map_err(|_| format!("Invalid session id '{}'", id))?;
+            match state.graph.sessions.get(&id) {
+                Some(session) => ::serde_json::to_string(&session_info(session, true))?,
+                None => return Ok(None),
+            }
+        }
+        ["sessions", id, "snapshot"] => {
+            let id: SessionId = id.parse()
+                .map_err(|_| format!("Invalid session id '{}'", id))?;
+            let cursor = match query_param(query, "cursor") {
+                Some(value) => Some(value
+                    .parse()
+                    .map_err(|_| format!("Invalid cursor '{}'", value))?),
+                None => None,
+            };
+            match state.graph.sessions.get(&id) {
+                Some(session) => ::serde_json::to_string(&session_snapshot(session, cursor))?,
+                None => return Ok(None),
+            }
+        }
+        ["sessions", id, "stats"] => {
+            let id: SessionId = id.parse()
+                .map_err(|_| format!("Invalid session id '{}'", id))?;
+            match state.graph.sessions.get(&id) {
+                Some(session) => ::serde_json::to_string(&session.get().stats)?,
+                None => return Ok(None),
+            }
+        }
+        ["tasks"] => {
+            let session_id = match query_param(query, "session") {
+                Some(value) => Some(value
+                    .parse()
+                    .map_err(|_| format!("Invalid session id '{}'", value))?),
+                None => None,
+            };
+            let tags = query_params(query, "tag");
+            if tags.is_empty() && session_id.is_none() {
+                bail!("Parameter 'tag' or 'session' is required");
+            }
+            let tasks = find_tasks(state, &tags, query_param(query, "state"), session_id);
+            ::serde_json::to_string(&tasks)?
+        }
+        ["graph"] => match graph_snapshot(state, query)? {
+            Some(snapshot) => ::serde_json::to_string(&snapshot)?,
+            None => return Ok(None),
+        },
+        ["durations"] => ::serde_json::to_string(state.duration_history().stats())?,
+        ["explain", session_id, task_id] => {
+            let task_id = match (session_id.parse(), task_id.parse()) {
+                (Ok(session_id), Ok(id)) => TaskId::new(session_id, id),
+                _ => bail!("Invalid task id {}/{}", session_id, task_id),
+            };
+            ::serde_json::to_string(&state.scheduler_decision(&task_id))?
+        }
+        _ => return Ok(None),
+    };
+    Ok(Some(result))
+}
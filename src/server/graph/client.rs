@@ -9,17 +9,24 @@ use errors::Result;
 #[derive(Debug)]
 pub struct Client {
     pub(in super::super) id: ClientId,
+    /// Name of the authenticated identity of the client, it owns secrets used by tasks
+    pub(in super::super) identity: String,
     pub(in super::super) sessions: RcSet<SessionRef>,
+    /// Connection of the client was lost, the client is kept only
+    /// until its remaining (detached or orphaned) sessions are removed
+    pub(in super::super) disconnected: bool,
 }
 
 pub type ClientRef = WrappedRcRefCell<Client>;
 
 impl ClientRef {
     /// Create new Client object
-    pub fn new(address: SocketAddr) -> Self {
+    pub fn new(address: SocketAddr, identity: String) -> Self {
         ClientRef::wrap(Client {
             id: address.clone(),
+            identity,
             sessions: Default::default(),
+            disconnected: false,
         })
     }
 
@@ -6,7 +6,7 @@ use common::wrapped::WrappedRcRefCell;
 use common::id::{DataObjectId, SId};
 use common::DataType;
 use common::{Attributes, ConsistencyCheck, FinishHook, RcSet};
-use super::{SessionRef, TaskRef, TaskState, WorkerRef};
+use super::{SessionRef, TaskRef, TaskState, Worker, WorkerRef};
 pub use common_capnp::DataObjectState;
 use errors::Result;
 
@@ -47,12 +47,33 @@ pub struct DataObject {
     /// The object is requested to be kept by the client.
     pub(in super::super) client_keep: bool,
 
+    /// Name under which the object is published ("namespace/name").
+    /// Published object is kept and may be used by tasks of other sessions.
+    pub(in super::super) published: Option<String>,
+
+    /// Other sessions with tasks that use the published object. The finished object
+    /// is kept until they end, even when its own session is closed.
+    pub(in super::super) user_sessions: RcSet<SessionRef>,
+
+    /// Workers the object is pinned to by the client (attribute "placement").
+    /// Pins are worker addresses ("ip:port"), hosts ("ip") or worker labels ("key=value"),
+    /// empty means no pin.
+    pub(in super::super) pinned: Vec<String>,
+
+    /// Worker the finished object is scheduled on for its single consumer before
+    /// the consumer is assigned there (see `State::forward_to_consumer`)
+    pub(in super::super) forwarded_to: Option<WorkerRef>,
+
     /// Hooks executed when the task is finished
     pub(in super::super) finish_hooks: Vec<FinishHook>,
 
     /// Final size if known. Must match `data` size when `data` present.
     pub(in super::super) size: Option<usize>,
 
+    /// Expected size given by client (attribute "size_hint"), used in estimates
+    /// of transfers before the size is known
+    pub(in super::super) size_hint: Option<usize>,
+
     pub(in super::super) data_type: DataType,
 
     /// Optinal *final* data when submitted from client or downloaded
@@ -61,12 +82,20 @@ pub struct DataObject {
 
     /// Attributes
     pub(in super::super) attributes: Attributes,
+
+    /// Time when the object was created (submitted)
+    pub(in super::super) created: ::std::time::Instant,
 }
 
 impl DataObject {
     /// To capnp for worker message
     /// It does not fill `placement` and `assigned`, that must be done by caller
-    pub fn to_worker_capnp(&self, builder: &mut ::worker_capnp::data_object::Builder) {
+    /// Data held by the server are sent inline when they are not larger than `inline_threshold`
+    pub fn to_worker_capnp(
+        &self,
+        builder: &mut ::worker_capnp::data_object::Builder,
+        inline_threshold: usize,
+    ) {
         self.id.to_capnp(&mut builder.borrow().get_id().unwrap());
         self.attributes
             .to_capnp(&mut builder.borrow().get_attributes().unwrap());
@@ -74,6 +103,12 @@ impl DataObject {
         builder.set_label(&self.label);
         builder.set_state(self.state);
         builder.set_data_type(self.data_type.to_capnp());
+        if let Some(ref data) = self.data {
+            if data.len() <= inline_threshold {
+                builder.set_inlined(true);
+                builder.set_inline_data(data);
+            }
+        }
     }
 
     /// Inform observers that task is finished
@@ -103,6 +138,18 @@ impl DataObject {
         receiver
     }
 
+    /// Content type from attribute "spec" (set by the client or by the producing task)
+    pub fn content_type(&self) -> Option<String> {
+        self.attributes
+            .find::<::serde_json::Value>("spec")
+            .unwrap_or(None)
+            .and_then(|spec| {
+                spec.get("content_type")
+                    .and_then(|c| c.as_str())
+                    .map(|c| c.to_string())
+            })
+    }
+
     #[inline]
     pub fn state(&self) -> DataObjectState {
         self.state
@@ -113,7 +160,13 @@ impl DataObject {
     /// Asserts the object is finished.
     #[inline]
     pub fn is_needed(&self) -> bool {
-        self.client_keep || !self.need_by.is_empty()
+        self.client_keep || self.published.is_some() || !self.user_sessions.is_empty()
+            || !self.need_by.is_empty()
+    }
+
+    /// May the object be placed on the worker with respect to its pins?
+    pub fn is_allowed_on(&self, worker: &Worker) -> bool {
+        self.pinned.is_empty() || self.pinned.iter().any(|pin| worker.matches_pin(pin))
     }
 
     #[inline]
@@ -129,6 +182,28 @@ impl DataObject {
 
 pub type DataObjectRef = WrappedRcRefCell<DataObject>;
 
+/// Filter of kept objects, the default value matches all kept objects
+#[derive(Default, Debug)]
+pub struct KeptFilter {
+    pub label_prefix: String,
+    pub min_size: usize,
+    pub min_age: ::std::time::Duration,
+    /// Tags ("key=value") that the producer of the object has to have
+    pub tags: Vec<String>,
+}
+
+impl KeptFilter {
+    pub fn matches(&self, object: &DataObject) -> bool {
+        object.client_keep && object.label.starts_with(&self.label_prefix)
+            && object.size.unwrap_or(0) >= self.min_size
+            && object.created.elapsed() >= self.min_age
+            && (self.tags.is_empty() || object
+                .producer
+                .as_ref()
+                .map_or(false, |p| p.get().has_tags(&self.tags)))
+    }
+}
+
 impl DataObjectRef {
     /// Create new data object and link it to the owning session.
     pub fn new(
@@ -157,14 +232,24 @@ impl DataObjectRef {
             assigned: Default::default(),
             session: session.clone(),
             client_keep: client_keep,
+            published: None,
+            user_sessions: Default::default(),
+            pinned: Vec::new(),
+            forwarded_to: None,
             finish_hooks: Vec::new(),
             size: data.as_ref().map(|d| d.len()),
             data_type,
             data: data,
             attributes: attributes,
+            size_hint: None,
+            created: ::std::time::Instant::now(),
         });
         // add to session
-        session.get_mut().objects.insert(s.clone());
+        let mut session = session.get_mut();
+        session.objects.insert(s.clone());
+        if let Some(size) = s.get().size {
+            session.stats.object_produced(size);
+        }
         s
     }
 
@@ -179,6 +264,7 @@ impl DataObjectRef {
             w.get_mut().scheduled_objects.remove(&self);
         }
         inner.scheduled.clear();
+        inner.forwarded_to = None;
     }
 
     /// Check that no compulsory links exist and remove from owner.
@@ -1,5 +1,7 @@
 use std::collections::HashMap;
+use common::resources::ResourcePool;
 use common::id::{ClientId, DataObjectId, SessionId, TaskId, WorkerId};
+use server::tags::TagIndex;
 use super::{ClientRef, DataObjectRef, SessionRef, TaskRef, WorkerRef};
 
 #[derive(Clone, Default)]
@@ -11,6 +13,15 @@ pub struct Graph {
     pub(in super::super) sessions: HashMap<SessionId, SessionRef>,
     pub(in super::super) clients: HashMap<ClientId, ClientRef>,
 
+    /// Published objects, the key is "namespace/name"
+    pub(in super::super) published: HashMap<String, DataObjectRef>,
+
+    /// Tasks by their tags
+    pub(in super::super) tags: TagIndex,
+
+    /// Cluster-wide counted resources
+    pub(in super::super) global_resources: ResourcePool,
+
     /// The last SessionId assigned.
     session_id_counter: SessionId,
 }
@@ -48,11 +59,17 @@ mod tests {
             WorkerRef::new(
                 format!("0.0.0.{}:67", wi + 1).parse().unwrap(),
                 None,
-                Resources { cpus: 8 },
+                Resources {
+                    cpus: 8,
+                    ..Default::default()
+                },
             );
         }
         for ci in 0..clients {
-            let c = ClientRef::new(format!("0.0.0.{}:42", ci + 1).parse().unwrap());
+            let c = ClientRef::new(
+                format!("0.0.0.{}:42", ci + 1).parse().unwrap(),
+                "anonymous".to_string(),
+            );
             for si in 0..sessions {
                 let s = SessionRef::new(si as i32, &c);
                 let mut objs = Vec::new();
@@ -87,7 +104,10 @@ mod tests {
                         outputs,
                         "TType".to_string(),
                         Attributes::new(),
-                        Resources { cpus: 1 },
+                        Resources {
+                            cpus: 1,
+                            ..Default::default()
+                        },
                     ).unwrap();
                 }
             }
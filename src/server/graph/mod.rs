@@ -6,8 +6,8 @@ mod worker;
 mod graph;
 
 pub use self::client::{Client, ClientRef};
-pub use self::session::{Session, SessionError, SessionRef};
+pub use self::session::{Session, SessionError, SessionRef, SessionStats, TaskGroup};
 pub use self::task::{Task, TaskInput, TaskRef, TaskState};
-pub use self::dataobj::{DataObject, DataObjectRef, DataObjectState};
+pub use self::dataobj::{DataObject, DataObjectRef, DataObjectState, KeptFilter};
 pub use self::worker::{Worker, WorkerRef};
 pub use self::graph::Graph;
@@ -1,11 +1,16 @@
 use futures::unsync::oneshot::Receiver;
+use std::collections::{BTreeMap, HashMap};
 use std::fmt;
+use std::time::{Duration, Instant};
 
 use common::wrapped::WrappedRcRefCell;
 use common::{ConsistencyCheck, FinishHook, RcSet};
-use common::id::{SessionId, TaskId};
+use common::id::{Id, SessionId, TaskId};
 use common::convert::ToCapnp;
-use super::{ClientRef, DataObjectRef, DataObjectState, TaskRef, TaskState};
+use common::Attributes;
+use common::labels::Labels;
+use common::output_policy::OutputPolicy;
+use super::{ClientRef, DataObjectRef, DataObjectState, TaskRef, TaskState, WorkerRef};
 use errors::Result;
 
 #[derive(Debug)]
@@ -24,14 +29,253 @@ pub struct Session {
     /// NB: These are owned by the Session and are cleaned up by it.
     pub(in super::super) objects: RcSet<DataObjectRef>,
 
+    /// Published objects of other sessions used by tasks of the session
+    pub(in super::super) used_published: RcSet<DataObjectRef>,
+
+    /// Tasks and objects of the session were removed (the session is closed or failed),
+    /// `objects` only holds its published objects that are still used by other sessions
+    pub(in super::super) cleared: bool,
+
     /// Client holding the session alive.
     pub(in super::super) client: ClientRef,
 
+    /// Name of the sub-session (empty for top-level sessions)
+    pub(in super::super) name: String,
+
+    /// Parent of the sub-session. Tasks of the session may use objects of its ancestors.
+    pub(in super::super) parent: Option<SessionRef>,
+
+    /// Sub-sessions, they are removed together with the session
+    pub(in super::super) children: RcSet<SessionRef>,
+
     /// Number of unfinished tasks
     pub(in super::super) unfinished_tasks: usize,
 
+    /// Number of tasks that are scheduled to workers and not finished yet
+    pub(in super::super) running_tasks: usize,
+
+    /// Limit of `running_tasks` set by the client, the scheduler holds back
+    /// ready tasks of the session over the limit
+    pub(in super::super) max_running_tasks: Option<usize>,
+
+    /// Keep the session running when its client disconnects
+    pub(in super::super) detach_on_disconnect: bool,
+
+    /// When a task fails, the session keeps its tasks and finished objects (its unfinished
+    /// tasks are only stopped), so failed tasks may be retried
+    pub(in super::super) keep_failed: bool,
+
+    /// Weight of worker cost in scheduling (0 = cost is ignored, only speed matters)
+    pub(in super::super) cost_weight: f64,
+
+    /// Fraction of submitted tasks that are run again on another worker to compare
+    /// checksums of their outputs (0 = no sampling)
+    pub(in super::super) sample_rate: f64,
+
+    /// Retention and size cap of captured stdout/stderr of programs of the session,
+    /// sent to workers in attribute "output_policy" of tasks without their own policy
+    pub(in super::super) output_policy: Option<OutputPolicy>,
+
+    /// Tags of the session given by the client ("key=value"), tasks of the session
+    /// inherit them
+    pub(in super::super) tags: Labels,
+
+    /// Environment variables set for all tasks of the session
+    /// (variables in attribute "env" of a task take precedence)
+    pub(in super::super) env: HashMap<String, String>,
+
+    /// Time when the session is expired (computed from TTL set by the client).
+    /// Remaining tasks of an expired session are cancelled and its objects are removed
+    /// (after `expired_retention` when it is set).
+    pub(in super::super) expires: Option<Instant>,
+
+    /// How long finished objects of an expired session are kept, None = they are removed
+    /// when the session expires
+    pub(in super::super) expired_retention: Option<Duration>,
+
+    /// The session expired and its finished objects are retained until `expires`,
+    /// they may still be fetched
+    pub(in super::super) expired: bool,
+
     /// Hooks executed when all tasks are finished.
     pub(in super::super) finish_hooks: Vec<FinishHook>,
+
+    /// Task groups by name (attribute "group" of tasks)
+    pub(in super::super) groups: HashMap<String, TaskGroup>,
+
+    /// Workers of colocation groups (attribute "colocate" of tasks), a group is placed
+    /// on the worker of its first scheduled task
+    pub(in super::super) colocated: HashMap<String, WorkerRef>,
+
+    /// Counter of task changes (new tasks and changes of states), it serves
+    /// as a cursor of session snapshots polled by clients
+    pub(in super::super) revision: u64,
+
+    /// Observers waiting for the next change of the session (`watchSession`)
+    pub(in super::super) watchers: Vec<FinishHook>,
+
+    /// Aggregates of tasks and objects for dashboards and progress bars
+    pub(in super::super) stats: SessionStats,
+
+    /// Tasks submitted with `hold` since the last flush of the session
+    pub(in super::super) held_tasks: Vec<TaskRef>,
+
+    /// Lowest id greater than ids of all tasks and objects of the session,
+    /// ids assigned by the server to provisional ids start here
+    pub(in super::super) next_id: Id,
+}
+
+/// Aggregated state of tasks with the same "group" attribute (e.g. an expanded job array).
+/// Counters are kept even when the tasks are removed from the session.
+#[derive(Debug, Default)]
+pub struct TaskGroup {
+    /// Unfinished tasks of the group
+    pub(in super::super) unfinished: RcSet<TaskRef>,
+    pub(in super::super) total: usize,
+    pub(in super::super) finished: usize,
+    pub(in super::super) failed: Vec<TaskId>,
+}
+
+impl TaskGroup {
+    pub fn add_task(&mut self, task: &TaskRef) {
+        if self.unfinished.insert(task.clone()) {
+            self.total += 1;
+        }
+    }
+
+    pub fn task_finished(&mut self, task: &TaskRef) {
+        if self.unfinished.remove(task) {
+            self.finished += 1;
+        }
+    }
+
+    /// The finished task is computed again (its output was invalidated)
+    pub fn task_reopened(&mut self, task: &TaskRef) {
+        if self.unfinished.insert(task.clone()) {
+            self.finished -= 1;
+        }
+    }
+
+    pub fn task_failed(&mut self, task: &TaskRef) {
+        if self.unfinished.remove(task) {
+            self.failed.push(task.get_id());
+        }
+    }
+
+    /// The failed task is computed again (see `State::retry_failed`)
+    pub fn task_retried(&mut self, task: &TaskRef) {
+        let id = task.get_id();
+        if let Some(pos) = self.failed.iter().position(|t| *t == id) {
+            self.failed.remove(pos);
+            self.unfinished.insert(task.clone());
+        }
+    }
+
+    /// Number of tasks that are assigned to workers or running
+    pub fn running(&self) -> usize {
+        self.unfinished
+            .iter()
+            .filter(|t| {
+                let state = t.get().state;
+                state == TaskState::Assigned || state == TaskState::Running
+            })
+            .count()
+    }
+}
+
+/// Summary statistics of a session. They are updated incrementally when tasks and objects
+/// change, so clients can poll them without going through all tasks of the session.
+#[derive(Debug, Default, Clone, Serialize)]
+pub struct SessionStats {
+    /// Tasks waiting for their inputs
+    pub(in super::super) waiting: usize,
+    /// Tasks ready to run, including tasks assigned to workers and not started yet
+    pub(in super::super) ready: usize,
+    pub(in super::super) running: usize,
+    pub(in super::super) finished: usize,
+    pub(in super::super) failed: usize,
+    /// CPU time of finished and failed tasks in seconds (from attribute "profile")
+    pub(in super::super) cpu_time: f64,
+    /// Bytes of objects produced by tasks or uploaded by the client
+    pub(in super::super) produced_bytes: u64,
+    /// Bytes of objects copied between workers
+    pub(in super::super) transferred_bytes: u64,
+    /// Numbers of failed tasks by kinds of their errors (see `error_kind`)
+    pub(in super::super) failures: BTreeMap<&'static str, usize>,
+    /// Sampled tasks compared with their copies (see `sample_rate` of session)
+    pub(in super::super) sampled: usize,
+    /// Sampled tasks whose copies produced different outputs (or failed)
+    pub(in super::super) sample_mismatches: Vec<TaskId>,
+}
+
+impl SessionStats {
+    fn counter(&mut self, state: TaskState) -> &mut usize {
+        match state {
+            TaskState::NotAssigned => &mut self.waiting,
+            TaskState::Ready | TaskState::Assigned => &mut self.ready,
+            TaskState::Running => &mut self.running,
+            TaskState::Finished => &mut self.finished,
+            TaskState::Failed => &mut self.failed,
+        }
+    }
+
+    /// A task was added to the session (`old` is `None`) or its state changed
+    pub fn task_state_changed(&mut self, old: Option<TaskState>, new: TaskState) {
+        if let Some(old) = old {
+            *self.counter(old) -= 1;
+        }
+        *self.counter(new) += 1;
+    }
+
+    pub fn task_removed(&mut self, state: TaskState) {
+        *self.counter(state) -= 1;
+    }
+
+    /// Account resource usage of a task that finished or failed
+    pub fn task_done(&mut self, attributes: &Attributes) {
+        if let Ok(Some(profile)) = attributes.find::<::serde_json::Value>("profile") {
+            if let Some(cpu_time) = profile.get("cpu_time").and_then(|t| t.as_u64()) {
+                self.cpu_time += cpu_time as f64 / 1000.0;
+            }
+        }
+    }
+
+    pub fn task_failed(&mut self, error: &str) {
+        *self.failures.entry(error_kind(error)).or_insert(0) += 1;
+    }
+
+    /// Outputs of a sampled task were compared with outputs of its copy
+    pub fn sample_compared(&mut self, task_id: TaskId, matched: bool) {
+        self.sampled += 1;
+        if !matched {
+            self.sample_mismatches.push(task_id);
+        }
+    }
+
+    #[inline]
+    pub fn object_produced(&mut self, size: usize) {
+        self.produced_bytes += size as u64;
+    }
+
+    #[inline]
+    pub fn object_transferred(&mut self, size: usize) {
+        self.transferred_bytes += size as u64;
+    }
+}
+
+/// Kind of the error of a failed task, derived from the error message
+pub fn error_kind(error: &str) -> &'static str {
+    if error.starts_with("Process limit") {
+        "limit_exceeded"
+    } else if error.starts_with("Program exit with exit code") {
+        "exit_code"
+    } else if error.starts_with("Program terminated by signal") {
+        "signal"
+    } else if error.contains("Traceback (most recent call last)") {
+        "python_exception"
+    } else {
+        "other"
+    }
 }
 
 pub type SessionRef = WrappedRcRefCell<Session>;
@@ -46,7 +290,48 @@ impl Session {
     pub fn is_failed(&self) -> bool {
         self.error.is_some()
     }
-}
+
+    /// The session failed but it kept its tasks (see `keep_failed`),
+    /// so its failed tasks may be retried
+    #[inline]
+    pub fn is_retryable(&self) -> bool {
+        self.error.is_some() && !self.tasks.is_empty()
+    }
+
+    #[inline]
+    pub fn unfinished_tasks(&self) -> usize {
+        self.unfinished_tasks
+    }
+
+    /// Is the time-to-live of the session exceeded?
+    #[inline]
+    pub fn is_expired(&self, now: Instant) -> bool {
+        self.expires.map(|t| t <= now).unwrap_or(false)
+    }
+
+    /// May the scheduler schedule another task of the session?
+    #[inline]
+    pub fn can_run_task(&self) -> bool {
+        self.error.is_none()
+            && self.max_running_tasks
+                .map(|limit| self.running_tasks < limit)
+                .unwrap_or(true)
+    }
+
+    /// Note an id of a task or an object added to the session
+    #[inline]
+    pub fn use_id(&mut self, id: Id) {
+        if id >= self.next_id {
+            self.next_id = id + 1;
+        }
+    }
+
+    /// New id for a task or an object that is not used in the session
+    pub fn new_id(&mut self) -> Id {
+        let id = self.next_id;
+        self.next_id += 1;
+        id
+    }
 
 impl Session {
     /// Returns a future that is triggered when session has no unfinished tasks
@@ -70,6 +355,32 @@ impl Session {
             }
         }
     }
+
+    /// This should be called when a finished task of the session is computed again
+    pub fn task_reopened(&mut self) {
+        self.unfinished_tasks += 1;
+    }
+
+    /// Record a change of tasks of the session, returns the new revision
+    pub fn bump_revision(&mut self) -> u64 {
+        self.revision += 1;
+        self.notify_watchers();
+        self.revision
+    }
+
+    /// Returns a future that is triggered by the next change of the session
+    pub fn watch(&mut self) -> Receiver<()> {
+        let (sender, receiver) = ::futures::unsync::oneshot::channel();
+        self.watchers.push(sender);
+        receiver
+    }
+
+    pub fn notify_watchers(&mut self) {
+        for sender in ::std::mem::replace(&mut self.watchers, Vec::new()) {
+            // The observer may be gone already
+            let _ = sender.send(());
+        }
+    }
 }
 
 impl SessionRef {
@@ -80,8 +391,32 @@ impl SessionRef {
             tasks: Default::default(),
             objects: Default::default(),
             client: client.clone(),
+            name: String::new(),
+            parent: None,
+            children: Default::default(),
             unfinished_tasks: 0,
+            running_tasks: 0,
+            max_running_tasks: None,
+            detach_on_disconnect: false,
+            keep_failed: false,
+            cost_weight: 0f64,
+            sample_rate: 0f64,
+            output_policy: None,
+            tags: Default::default(),
+            env: Default::default(),
+            expires: None,
+            expired_retention: None,
+            expired: false,
             finish_hooks: Default::default(),
+            groups: Default::default(),
+            colocated: Default::default(),
+            revision: 0,
+            watchers: Default::default(),
+            stats: Default::default(),
+            held_tasks: Vec::new(),
+            next_id: 0,
+            used_published: Default::default(),
+            cleared: false,
             error: None,
         });
         // add to client
@@ -94,16 +429,37 @@ impl SessionRef {
         self.get().id
     }
 
+    /// Is `other` the parent of the session (or parent of the parent, ...)?
+    pub fn has_ancestor(&self, other: &SessionRef) -> bool {
+        let mut parent = self.get().parent.clone();
+        while let Some(p) = parent {
+            if p == *other {
+                return true;
+            }
+            parent = p.get().parent.clone();
+        }
+        false
+    }
+
     /// Check that no objects or tasks exist and remove from owner.
     /// Clears (and fails) any finish_hooks. Leaves the unlinked object in in consistent state.
     pub fn unlink(&self) {
         let mut inner = self.get_mut();
-        assert!(inner.objects.is_empty(), "Can only unlink empty session.");
+        assert!(
+            inner.objects.iter().all(|o| !o.get().user_sessions.is_empty()),
+            "Can only unlink empty session."
+        );
         assert!(inner.tasks.is_empty(), "Can only unlink empty session.");
+        assert!(inner.children.is_empty(), "Can only unlink session without children.");
+        // remove from parent
+        if let Some(parent) = inner.parent.take() {
+            assert!(parent.get_mut().children.remove(self));
+        }
         // remove from owner
         assert!(inner.client.get_mut().sessions.remove(&self));
         // clear finish_hooks
         inner.finish_hooks.clear();
+        inner.watchers.clear();
     }
 }
 
@@ -126,6 +482,16 @@ impl ConsistencyCheck for SessionRef {
         if !s.client.get().sessions.contains(self) {
             bail!("owning client does not contain {:?}", s);
         }
+        if let Some(ref parent) = s.parent {
+            if !parent.get().children.contains(self) {
+                bail!("parent session does not contain {:?}", s);
+            }
+        }
+        for child in s.children.iter() {
+            if child.get().parent.as_ref() != Some(self) {
+                bail!("child session ref {:?} inconsistency in {:?}", child, s);
+            }
+        }
         // finished?
         if !s.finish_hooks.is_empty()
             && s.tasks
@@ -158,6 +524,8 @@ pub struct SessionError {
     message: String,
     debug: Option<String>,
     task_id: TaskId,
+    /// Failure digest of the task (JSON)
+    failure: Option<String>,
 }
 
 impl SessionError {
@@ -166,9 +534,24 @@ impl SessionError {
             message,
             debug,
             task_id,
+            failure: None,
         }
     }
 
+    pub fn set_failure(&mut self, failure: Option<String>) {
+        self.failure = failure;
+    }
+
+    #[inline]
+    pub fn message(&self) -> &str {
+        &self.message
+    }
+
+    #[inline]
+    pub fn task_id(&self) -> TaskId {
+        self.task_id
+    }
+
     pub fn to_capnp(&self, builder: &mut ::common_capnp::error::Builder) {
         builder.borrow().set_message(&self.message);
         if let Some(ref m) = self.debug {
@@ -176,6 +559,9 @@ impl SessionError {
         }
         self.task_id
             .to_capnp(&mut builder.borrow().get_task().unwrap());
+        if let Some(ref f) = self.failure {
+            builder.borrow().set_failure(&f);
+        }
     }
 }
 
@@ -1,12 +1,17 @@
 use futures::unsync::oneshot;
+use std::collections::HashMap;
 use std::fmt;
+use std::time::{Duration, Instant};
 
-use common::resources::Resources;
+use common::labels::Constraint;
+use common::resources::{ResourcePool, Resources};
 use common::convert::ToCapnp;
 use common::wrapped::WrappedRcRefCell;
-use common::{Attributes, ConsistencyCheck, FinishHook, RcSet};
-use common::id::{SId, TaskId};
-use super::{DataObjectRef, DataObjectState, SessionRef, WorkerRef};
+use common::{Attributes, ConsistencyCheck, DataType, FinishHook, RcSet};
+use common::id::{SId, TaskId, WorkerId};
+use super::{DataObjectRef, DataObjectState, SessionRef, Worker, WorkerRef};
+use server::secrets::SecretStore;
+use server::tags::Tag;
 pub use common_capnp::TaskState;
 use errors::Result;
 
@@ -61,13 +66,119 @@ pub struct Task {
 
     /// Task resources
     pub(in super::super) resources: Resources,
+
+    /// Name of the group of the task in its session
+    pub(in super::super) group: Option<String>,
+
+    /// Task may run on preemptible workers (it is retryable or checkpointed),
+    /// set from attribute "preemptible"
+    pub(in super::super) preemptible: bool,
+
+    /// Placement constraints on labels of workers, set from attribute "constraints"
+    pub(in super::super) constraints: Vec<Constraint>,
+
+    /// CPU architecture of workers the task may run on (attribute "arch"),
+    /// e.g. programs built for "x86_64"
+    pub(in super::super) arch: Option<String>,
+
+    /// Tags of the task and of its session (attribute "tags"), see `TagIndex`
+    pub(in super::super) tags: Vec<Tag>,
+
+    /// Name of the spread group in the session (attribute "spread"), tasks of the group
+    /// do not run on the same worker at the same time
+    pub(in super::super) spread: Option<String>,
+
+    /// Name of the colocation group in the session (attribute "colocate"), tasks of
+    /// the group run on one worker
+    pub(in super::super) colocate: Option<String>,
+
+    /// Task "!run" with a script embedded in its config
+    pub(in super::super) embedded_script: bool,
+
+    /// Revision of the session when the state was changed (see `Session::revision`)
+    pub(in super::super) revision: u64,
+
+    /// When the task became ready, for the age of ready tasks watched by alerts
+    pub(in super::super) ready_since: Option<Instant>,
+
+    /// Task was submitted with `hold`, it is not scheduled until its session is flushed
+    pub(in super::super) held: bool,
+
+    /// Tasks with higher priority are scheduled first and they may preempt running tasks
+    /// with lower priority (attribute "priority", default 0)
+    pub(in super::super) priority: i32,
+
+    /// Expected duration in seconds given by client (attribute "duration")
+    pub(in super::super) duration: Option<f64>,
+
+    /// Duration in seconds predicted from durations of finished tasks of the same type
+    /// (see `history::DurationHistory`), used when `duration` is not given
+    pub(in super::super) predicted_duration: Option<f64>,
+
+    /// Upward rank, expected time from the start of the task to the end of the graph
+    /// (see `scheduler::update_ranks`)
+    pub(in super::super) rank: f64,
+
+    /// The task is a copy of the sampled task run to compare their outputs
+    /// (see `sample_rate` of session)
+    pub(in super::super) sample_of: Option<TaskRef>,
+
+    /// Copy of the task when the task was sampled
+    pub(in super::super) sample_copy: Option<TaskRef>,
+
+    /// Worker where the task finished
+    pub(in super::super) finished_on: Option<WorkerId>,
+
+    /// Failed runs of the task are retried up to this many times (attribute "max_retries")
+    pub(in super::super) max_retries: u32,
+
+    /// Delay before the first retry, it doubles with each retry (attribute "retry_delay")
+    pub(in super::super) retry_delay: Duration,
+
+    /// Number of failed runs retried so far
+    pub(in super::super) retries: u32,
+
+    /// Workers where runs of the task failed, other workers are preferred for retries
+    pub(in super::super) failed_on: Vec<WorkerId>,
+
+    /// The task waits for the delay of its retry, it does not become ready until then
+    pub(in super::super) retry_pending: bool,
 }
 
 pub type TaskRef = WrappedRcRefCell<Task>;
 
 impl Task {
-    // To capnp for worker message
-    pub fn to_worker_capnp(&self, builder: &mut ::worker_capnp::task::Builder) {
+    /// Change the state, the change is recorded for session snapshots
+    pub fn set_state(&mut self, state: TaskState) {
+        if state == TaskState::Ready && self.state != TaskState::Ready {
+            self.ready_since = Some(Instant::now());
+        }
+        let mut session = self.session.get_mut();
+        session.stats.task_state_changed(Some(self.state), state);
+        self.state = state;
+        self.revision = session.bump_revision();
+    }
+
+    /// Secrets referenced by the task (attribute "secrets" maps names of environment
+    /// variables to names of secrets): names of variables with decrypted values.
+    /// Only secrets of the identity of the client of the session are used.
+    pub fn secret_values(&self, secrets: &SecretStore) -> Result<Vec<(String, Vec<u8>)>> {
+        let owner = self.session.get().client.get().identity.clone();
+        let names: HashMap<String, String> = self.attributes.find("secrets")?.unwrap_or_default();
+        names
+            .iter()
+            .map(|(env_name, secret_name)| {
+                Ok((env_name.clone(), secrets.get(&owner, secret_name)?))
+            })
+            .collect()
+    }
+
+    // To capnp for worker message, values of secrets (see `secret_values`) are included
+    pub fn to_worker_capnp(
+        &self,
+        builder: &mut ::worker_capnp::task::Builder,
+        secrets: &[(String, Vec<u8>)],
+    ) {
         self.id.to_capnp(&mut builder.borrow().get_id().unwrap());
         {
             let mut cinputs = builder.borrow().init_inputs(self.inputs.len() as u32);
@@ -87,10 +198,38 @@ impl Task {
             }
         }
 
-        self.attributes
-            .to_capnp(&mut builder.borrow().get_attributes().unwrap());
+        let session = self.session.get();
+        if session.env.is_empty() && session.output_policy.is_none() {
+            self.attributes
+                .to_capnp(&mut builder.borrow().get_attributes().unwrap());
+        } else {
+            let mut attributes = self.attributes.clone();
+            if !session.env.is_empty() {
+                // Environment of the session is merged into attribute "env" of the task
+                let mut env = session.env.clone();
+                if let Ok(Some(task_env)) = self.attributes.find::<HashMap<String, String>>("env")
+                {
+                    env.extend(task_env);
+                }
+                attributes.set("env", env).unwrap();
+            }
+            // Output policy of the session is used by tasks without their own policy
+            if let Some(ref policy) = session.output_policy {
+                if !attributes.as_hashmap().contains_key("output_policy") {
+                    attributes.set("output_policy", policy).unwrap();
+                }
+            }
+            attributes.to_capnp(&mut builder.borrow().get_attributes().unwrap());
+        }
 
         builder.set_task_type(&self.task_type);
+
+        let mut csecrets = builder.borrow().init_secrets(secrets.len() as u32);
+        for (i, &(ref env_name, ref value)) in secrets.iter().enumerate() {
+            let mut csecret = csecrets.borrow().get(i as u32);
+            csecret.set_name(env_name);
+            csecret.set_value(value);
+        }
     }
 
     #[inline]
@@ -108,6 +247,109 @@ impl Task {
         &self.attributes
     }
 
+    /// May the task run on the worker with respect to the pins of its outputs?
+    pub fn is_allowed_on(&self, worker: &Worker) -> bool {
+        self.outputs.iter().all(|o| o.get().is_allowed_on(worker))
+    }
+
+    /// Has the task all the tags ("key=value")?
+    pub fn has_tags(&self, tags: &[String]) -> bool {
+        tags.iter()
+            .all(|tag| self.tags.iter().any(|t| &**t == tag.as_str()))
+    }
+
+    /// A placement constraint of the task that the worker does not satisfy
+    pub fn unsatisfied_constraint(&self, worker: &Worker) -> Option<&Constraint> {
+        self.constraints
+            .iter()
+            .find(|c| !c.is_satisfied(&worker.labels))
+    }
+
+    /// Architecture required by the task when the worker has another one; workers
+    /// that do not report their platform (older versions) are not restricted
+    pub fn unsatisfied_arch(&self, worker: &Worker) -> Option<&str> {
+        match self.arch {
+            Some(ref arch)
+                if !worker.platform.is_unknown() && worker.platform.arch != *arch =>
+            {
+                Some(arch)
+            }
+            _ => None,
+        }
+    }
+
+    /// A task of the same spread group that is scheduled on the worker
+    pub fn spread_conflict(&self, worker: &Worker) -> Option<TaskId> {
+        let name = match self.spread {
+            Some(ref name) => name,
+            None => return None,
+        };
+        worker
+            .scheduled_tasks
+            .iter()
+            .find(|tref| {
+                let t = tref.get();
+                t.session == self.session && t.spread.as_ref() == Some(name)
+            })
+            .map(|tref| tref.get_id())
+    }
+
+    /// A run of the task failed on the worker (see `max_retries`)
+    #[inline]
+    pub fn failed_on(&self, worker: &Worker) -> bool {
+        self.failed_on.contains(worker.id())
+    }
+
+    /// The other task of a sampled task and its copy when it is scheduled or finished
+    /// on the worker (they have to run on different workers)
+    pub fn sample_conflict(&self, worker: &Worker) -> Option<TaskId> {
+        let other = match self.sample_of.as_ref().or(self.sample_copy.as_ref()) {
+            Some(other) => other.get(),
+            None => return None,
+        };
+        let on_worker = other.finished_on.as_ref() == Some(worker.id())
+            || other
+                .scheduled
+                .as_ref()
+                .map(|w| w.get().id() == worker.id())
+                .unwrap_or(false);
+        if on_worker {
+            Some(other.id)
+        } else {
+            None
+        }
+    }
+
+    /// The worker where the colocation group of the task is placed, `None` if the group
+    /// is not placed yet or its worker is lost or draining
+    pub fn colocated_worker(&self) -> Option<WorkerRef> {
+        let name = match self.colocate {
+            Some(ref name) => name,
+            None => return None,
+        };
+        let session = self.session.get();
+        match session.colocated.get(name) {
+            Some(wref) => {
+                let w = wref.get();
+                if w.error.is_none() && !w.draining && !w.leaving {
+                    Some(wref.clone())
+                } else {
+                    None
+                }
+            }
+            None => None,
+        }
+    }
+
+    /// Has the task an input or an output of type directory?
+    pub fn uses_directories(&self) -> bool {
+        self.inputs
+            .iter()
+            .map(|i| &i.object)
+            .chain(self.outputs.iter())
+            .any(|o| o.get().data_type == DataType::Directory)
+    }
+
     #[inline]
     pub fn is_finished(&self) -> bool {
         match self.state {
@@ -121,6 +363,12 @@ impl Task {
         &self.task_type
     }
 
+    /// Expected duration given by client, or the predicted one
+    #[inline]
+    pub fn expected_duration(&self) -> Option<f64> {
+        self.duration.or(self.predicted_duration)
+    }
+
     /// Inform observers that task is finished
     pub fn trigger_finish_hooks(&mut self) {
         assert!(self.is_finished());
@@ -209,9 +457,10 @@ impl TaskRef {
                 );
             }
         }
+        let ready = waiting.is_empty();
         let sref = TaskRef::wrap(Task {
             id: id,
-            state: if waiting.is_empty() {
+            state: if ready {
                 TaskState::Ready
             } else {
                 TaskState::NotAssigned
@@ -226,12 +475,38 @@ impl TaskRef {
             finish_hooks: Default::default(),
             attributes: attributes,
             resources: resources,
+            group: None,
+            preemptible: false,
+            constraints: Vec::new(),
+            arch: None,
+            tags: Vec::new(),
+            spread: None,
+            colocate: None,
+            embedded_script: false,
+            revision: 0,
+            ready_since: if ready { Some(Instant::now()) } else { None },
+            held: false,
+            priority: 0,
+            duration: None,
+            predicted_duration: None,
+            rank: 0f64,
+            sample_of: None,
+            sample_copy: None,
+            finished_on: None,
+            max_retries: 0,
+            retry_delay: Duration::from_secs(0),
+            retries: 0,
+            failed_on: Vec::new(),
+            retry_pending: false,
         });
         {
             // add to session
             let mut s = session.get_mut();
             s.tasks.insert(sref.clone());
             s.unfinished_tasks += 1;
+            let mut t = sref.get_mut();
+            t.revision = s.bump_revision();
+            s.stats.task_state_changed(None, t.state);
         }
         {
             let s = sref.get_mut();
@@ -249,25 +524,46 @@ impl TaskRef {
         Ok(sref)
     }
 
-    pub fn unschedule(&self) {
+    /// Remove the task from the worker it is scheduled to and release its global
+    /// resources (acquired when the task was scheduled)
+    pub fn unschedule(&self, global_resources: &mut ResourcePool) {
         let mut inner = self.get_mut();
         if let Some(ref w) = inner.scheduled {
+            global_resources.release(&inner.resources);
             assert!(w.get_mut().scheduled_tasks.remove(&self));
             if inner.state == TaskState::Ready {
                 assert!(w.get_mut().scheduled_ready_tasks.remove(&self));
             }
 
             if inner.state != TaskState::NotAssigned {
-                w.get_mut().active_resources -= inner.resources.cpus();
+                w.get_mut().remove_active(&inner.resources);
             }
+            inner.session.get_mut().running_tasks -= 1;
         }
         inner.scheduled = None;
     }
 
+    /// Unschedule inputs forwarded to the worker for the task (see `State::forward_to_consumer`)
+    /// when the task leaves the worker. Returns the objects whose assignment on the worker
+    /// has to be updated.
+    pub fn unforward_inputs(&self, worker: &WorkerRef) -> Vec<DataObjectRef> {
+        let mut result = Vec::new();
+        for input in &self.get().inputs {
+            let mut o = input.object.get_mut();
+            if o.forwarded_to.as_ref() == Some(worker) {
+                o.forwarded_to = None;
+                o.scheduled.remove(worker);
+                worker.get_mut().scheduled_objects.remove(&input.object);
+                result.push(input.object.clone());
+            }
+        }
+        result
+    }
+
     /// Remove the task from outputs, inputs, from workers if scheduled, and the owner.
     /// Clears (and fails) any finish_hooks. Leaves the unlinked Task in in consistent state.
-    pub fn unlink(&self) {
-        self.unschedule();
+    pub fn unlink(&self, global_resources: &mut ResourcePool) {
+        self.unschedule(global_resources);
         let mut inner = self.get_mut();
         assert!(
             inner.assigned.is_none(),
@@ -284,8 +580,25 @@ impl TaskRef {
             i.object.get_mut().consumers.remove(&self);
         }
 
+        // remove from group, the task does not count into the group if it was never finished
+        if let Some(ref name) = inner.group {
+            let mut session = inner.session.get_mut();
+            let has_error = session.error.is_some();
+            if let Some(group) = session.groups.get_mut(name) {
+                if group.unfinished.remove(&self) && !has_error {
+                    group.total -= 1;
+                }
+            }
+        }
         // remove from owner
-        assert!(inner.session.get_mut().tasks.remove(&self));
+        {
+            let mut session = inner.session.get_mut();
+            assert!(session.tasks.remove(&self));
+            session.stats.task_removed(inner.state);
+        }
+        // break the reference cycle of a sampled task and its copy
+        inner.sample_of = None;
+        inner.sample_copy = None;
         // clear and fail finish_hooks
         inner.finish_hooks.clear();
     }
@@ -1,3 +1,4 @@
+use std::collections::HashMap;
 use std::net::SocketAddr;
 use std::fmt;
 
@@ -5,10 +6,13 @@ use futures::Future;
 
 use errors::Error;
 use common::asycinit::AsyncInitWrapper;
+use common::capabilities::Capabilities;
+use common::labels::Labels;
 use common::wrapped::WrappedRcRefCell;
 use common::{ConsistencyCheck, RcSet};
 use common::id::WorkerId;
 use common::resources::Resources;
+use common::sys::Platform;
 use super::{DataObjectRef, TaskRef};
 use errors::Result;
 
@@ -33,6 +37,15 @@ pub struct Worker {
     // (TODO: Generalize for Resource not only cpus)
     pub(in super::super) active_resources: u32,
 
+    /// The sum of memory (bytes) requested by the same tasks as `active_resources`
+    pub(in super::super) active_memory: u64,
+
+    /// The sum of GPUs requested by the same tasks as `active_resources`
+    pub(in super::super) active_gpus: u32,
+
+    /// The sums of named resources requested by the same tasks as `active_resources`
+    pub(in super::super) active_named: HashMap<String, u32>,
+
     /// Obects fully located on the worker.
     pub(in super::super) located_objects: RcSet<DataObjectRef>,
 
@@ -48,6 +61,37 @@ pub struct Worker {
 
     datastore: Option<AsyncInitWrapper<::datastore_capnp::data_store::Client>>,
 
+    /// Worker is connected through a gateway and it does not accept connections from server;
+    /// its datastore is obtained through the control interface
+    pub(in super::super) via_gateway: bool,
+
+    /// Cost of running on the worker per cpu (e.g. spot price or power class), set by worker
+    pub(in super::super) cost: f64,
+
+    /// Worker may be evicted (e.g. spot instance)
+    pub(in super::super) preemptible: bool,
+
+    /// Worker is going to leave, no new tasks are scheduled to it
+    pub(in super::super) draining: bool,
+
+    /// Capabilities announced by the worker at registration (empty for older workers)
+    pub(in super::super) capabilities: Capabilities,
+
+    /// Installation of the worker (`--image`), used by rolling upgrades
+    pub(in super::super) image: String,
+
+    /// Labels of the worker (`--label`) matched by placement constraints of tasks
+    pub(in super::super) labels: Labels,
+
+    /// OS, architecture, kernel and version of rain reported at registration
+    pub(in super::super) platform: Platform,
+
+    /// Worker was asked to leave (drained during an upgrade)
+    pub(in super::super) leaving: bool,
+
+    /// Total size of objects deleted on the worker by `deleteObjects` calls
+    pub(in super::super) freed_bytes: u64,
+
     pub(in super::super) resources: Resources,
 }
 
@@ -59,6 +103,45 @@ impl Worker {
         &self.id
     }
 
+    /// Count resources of a task that may run (or is running) on the worker
+    pub fn add_active(&mut self, resources: &Resources) {
+        self.active_resources += resources.cpus();
+        self.active_memory += resources.memory();
+        self.active_gpus += resources.gpus();
+        for (name, &count) in &resources.named {
+            *self.active_named.entry(name.clone()).or_insert(0) += count;
+        }
+    }
+
+    pub fn remove_active(&mut self, resources: &Resources) {
+        self.active_resources -= resources.cpus();
+        self.active_memory -= resources.memory();
+        self.active_gpus -= resources.gpus();
+        for (name, &count) in &resources.named {
+            if count > 0 {
+                *self.active_named.get_mut(name).unwrap() -= count;
+            }
+        }
+    }
+
+    /// The sum of a named resource requested by tasks that may run on the worker
+    #[inline]
+    pub fn active_named(&self, name: &str) -> u32 {
+        self.active_named.get(name).cloned().unwrap_or(0)
+    }
+
+    /// Does the placement pin refer to this worker? A pin is a worker address "ip:port",
+    /// a host "ip" or a label of the worker "key=value" (see `--label`).
+    pub fn matches_pin(&self, pin: &str) -> bool {
+        if let Some(pos) = pin.find('=') {
+            return self.labels
+                .get(&pin[..pos])
+                .map(|value| value == &pin[pos + 1..])
+                .unwrap_or(false);
+        }
+        self.id.to_string() == pin || self.id.ip().to_string() == pin
+    }
+
     /// Get datastore of worker,
     /// First you have to call wait_for_datastore to make sure that
     /// datastore exists
@@ -79,8 +162,38 @@ impl Worker {
         self.datastore = Some(AsyncInitWrapper::new());
 
         let worker_ref = worker_ref.clone();
+        let fail_worker_ref = worker_ref.clone();
         let handle = handle.clone();
 
+        if self.via_gateway {
+            let req = self.control.as_ref().unwrap().get_data_store_request();
+            return Box::new(
+                req.send()
+                    .promise
+                    .map(move |response| {
+                        let store = response.get().unwrap().get_store().unwrap();
+                        worker_ref
+                            .get_mut()
+                            .datastore
+                            .as_mut()
+                            .unwrap()
+                            .set_value(store);
+                    })
+                    .map_err(move |e| {
+                        let mut worker = fail_worker_ref.get_mut();
+                        let error = Error::with_chain(
+                            e,
+                            format!("Cannot obtain datastore of {} through gateway", worker.id),
+                        );
+                        // Dropping the wrapper allows a new attempt
+                        match worker.datastore.take() {
+                            Some(wrapper) => wrapper.fail(error),
+                            None => error,
+                        }
+                    }),
+            );
+        }
+
         Box::new(
             ::tokio_core::net::TcpStream::connect(&self.id, &handle)
                 .map(move |stream| {
@@ -96,7 +209,18 @@ impl Worker {
                         .unwrap()
                         .set_value(bootstrap);
                 })
-                .map_err(|e| e.into()),
+                .map_err(move |e| {
+                    let mut worker = fail_worker_ref.get_mut();
+                    let error = Error::with_chain(
+                        e,
+                        format!("Cannot connect to datastore of {}", worker.id),
+                    );
+                    // Dropping the wrapper allows a new attempt
+                    match worker.datastore.take() {
+                        Some(wrapper) => wrapper.fail(error),
+                        None => error,
+                    }
+                }),
         )
     }
 }
@@ -118,8 +242,21 @@ impl WorkerRef {
             scheduled_objects: Default::default(),
             control: control,
             active_resources: 0,
+            active_memory: 0,
+            active_gpus: 0,
+            active_named: HashMap::new(),
             resources: resources,
             datastore: None,
+            via_gateway: false,
+            cost: 0f64,
+            preemptible: false,
+            draining: false,
+            capabilities: Capabilities::new(),
+            image: String::new(),
+            labels: Labels::new(),
+            platform: Platform::default(),
+            leaving: false,
+            freed_bytes: 0,
         })
     }
 
@@ -135,10 +272,16 @@ impl ConsistencyCheck for WorkerRef {
     fn check_consistency(&self) -> Result<()> {
         let s = self.get();
 
-        if s.scheduled_tasks.is_empty() && s.active_resources != 0 {
+        if s.scheduled_tasks.is_empty()
+            && (s.active_resources != 0 || s.active_memory != 0 || s.active_gpus != 0
+                || s.active_named.values().any(|&count| count != 0))
+        {
             bail!(
-                "Invalid active resources: active_resources = {}",
-                s.active_resources
+                "Invalid active resources: active_resources = {}, active_memory = {}, \
+                 active_gpus = {}",
+                s.active_resources,
+                s.active_memory,
+                s.active_gpus
             );
         }
 
@@ -198,3 +341,24 @@ impl fmt::Debug for Worker {
             .finish()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::WorkerRef;
+    use common::resources::Resources;
+
+    #[test]
+    fn test_matches_pin() {
+        let wref = WorkerRef::new("10.0.0.1:7211".parse().unwrap(), None, Resources::default());
+        wref.get_mut()
+            .labels
+            .insert("site".to_string(), "cloud".to_string());
+        let w = wref.get();
+        assert!(w.matches_pin("10.0.0.1:7211"));
+        assert!(w.matches_pin("10.0.0.1"));
+        assert!(w.matches_pin("site=cloud"));
+        assert!(!w.matches_pin("10.0.0.2"));
+        assert!(!w.matches_pin("site=onprem"));
+        assert!(!w.matches_pin("rack=cloud"));
+    }
+}
@@ -0,0 +1,119 @@
+use std::collections::HashMap;
+
+use server::graph::Task;
+
+/// Once there are this many samples, the mean becomes a moving average, so predictions
+/// follow changes of programs and workers
+const MAX_SAMPLES: u32 = 50;
+
+/// Runtime statistics of finished tasks of a type with inputs of similar size
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct DurationStats {
+    /// Number of finished tasks, at most `MAX_SAMPLES`
+    pub count: u32,
+    /// Mean duration in seconds (moving average after `MAX_SAMPLES` samples)
+    pub mean: f64,
+}
+
+impl DurationStats {
+    fn add(&mut self, duration: f64) {
+        if self.count < MAX_SAMPLES {
+            self.count += 1;
+        }
+        self.mean += (duration - self.mean) / self.count as f64;
+    }
+}
+
+/// Bucket of the total size of inputs, sizes within a power of two share a bucket
+#[inline]
+fn size_bucket(input_bytes: usize) -> u32 {
+    0usize.leading_zeros() - input_bytes.leading_zeros()
+}
+
+/// Total size of inputs of the task; the size hint (or zero) is used for unfinished objects
+pub fn input_bytes(t: &Task) -> usize {
+    t.inputs
+        .iter()
+        .map(|i| {
+            let o = i.object.get();
+            o.size.or(o.size_hint).unwrap_or(0)
+        })
+        .sum()
+}
+
+/// Durations of finished tasks by task type and size of inputs. Durations of submitted
+/// tasks without the expected duration given by the client are predicted from it,
+/// so ranks of tasks of repeated workflows improve over time (see `scheduler::update_ranks`).
+/// The history is kept for the lifetime of the server.
+#[derive(Default)]
+pub struct DurationHistory {
+    stats: HashMap<String, HashMap<u32, DurationStats>>,
+}
+
+impl DurationHistory {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    pub fn record(&mut self, task_type: &str, input_bytes: usize, duration: f64) {
+        if !self.stats.contains_key(task_type) {
+            self.stats.insert(task_type.to_string(), HashMap::new());
+        }
+        self.stats
+            .get_mut(task_type)
+            .unwrap()
+            .entry(size_bucket(input_bytes))
+            .or_insert_with(Default::default)
+            .add(duration);
+    }
+
+    /// Mean duration of tasks of the type with inputs of the same size bucket,
+    /// or of the nearest bucket when there is no such task
+    pub fn predict(&self, task_type: &str, input_bytes: usize) -> Option<f64> {
+        let buckets = self.stats.get(task_type)?;
+        let bucket = size_bucket(input_bytes);
+        buckets
+            .iter()
+            .min_by_key(|&(b, _)| ((*b as i64 - bucket as i64).abs(), *b))
+            .map(|(_, s)| s.mean)
+    }
+
+    /// Statistics by task types and buckets (the bucket is the bit length
+    /// of the total size of inputs)
+    #[inline]
+    pub fn stats(&self) -> &HashMap<String, HashMap<u32, DurationStats>> {
+        &self.stats
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_predict() {
+        let mut history = DurationHistory::new();
+        assert_eq!(history.predict("run", 100), None);
+        history.record("run", 100, 2.0);
+        history.record("run", 120, 4.0);
+        history.record("run", 1 << 20, 60.0);
+        assert_eq!(history.predict("run", 70), Some(3.0));
+        assert_eq!(history.predict("run", 1 << 20), Some(60.0));
+        assert_eq!(history.predict("run", 1 << 22), Some(60.0));
+        assert_eq!(history.predict("run", 0), Some(3.0));
+        assert_eq!(history.predict("!sleep", 100), None);
+    }
+
+    #[test]
+    fn test_moving_average() {
+        let mut history = DurationHistory::new();
+        for _ in 0..(2 * MAX_SAMPLES) {
+            history.record("run", 0, 1.0);
+        }
+        for _ in 0..(2 * MAX_SAMPLES) {
+            history.record("run", 0, 3.0);
+        }
+        let prediction = history.predict("run", 0).unwrap();
+        assert!(prediction > 2.7 && prediction < 3.0);
+    }
+}
@@ -1,13 +1,45 @@
-use hyper::{Error, StatusCode};
-use hyper::header::{AccessControlAllowOrigin, ContentEncoding, ContentLength, Encoding};
+use hyper::{Error, Method, StatusCode};
+use hyper::header::{AccessControlAllowOrigin, ContentEncoding, ContentLength, ContentType,
+                    Encoding};
 use hyper::server::{Request, Response, Service};
 use futures::Stream;
 use futures;
 use futures::Future;
 use server::state::StateRef;
+use server::access::Role;
+use server::api;
+use common::id::{SId, TaskId};
+use std::net::{IpAddr, SocketAddr};
+
+/// Settings of HTTP interface
+#[derive(Clone, Debug, Default)]
+pub struct HttpConfig {
+    /// Path prefix under which the proxy exposes the interface (e.g. "/rain")
+    pub base_path: String,
+    /// Addresses of proxies whose X-Forwarded-* headers are trusted
+    pub trusted_proxies: Vec<IpAddr>,
+    /// Header with the name of a user authenticated by the proxy (e.g. X-Forwarded-User);
+    /// when set, requests without it or not coming from a trusted proxy are rejected
+    pub auth_header: Option<String>,
+    /// Origins allowed to call JSON API from browsers ("*" allows any origin)
+    pub cors_origins: Vec<String>,
+}
+
+const API_PREFIX: &str = "/api/v1/";
 
 pub struct RequestHandler {
     state: ::server::state::StateRef,
+    /// Address of the peer when it is not provided by hyper (TLS connections)
+    remote: Option<SocketAddr>,
+}
+
+/// Request as seen by the client of a reverse proxy
+struct Forwarded {
+    client: String,
+    base_path: String,
+    path: String,
+    /// The request comes from a trusted proxy
+    trusted: bool,
 }
 
 fn wrap_elements<I>(open_tag: &str, close_tag: &str, elements: I) -> String
@@ -23,12 +55,294 @@ where
     result
 }
 
+/// The base path is put into HTML of the dashboard, so only plain paths are allowed
+/// ("/" followed by letters, digits and "-._~/"); the empty path is the root
+pub fn is_valid_base_path(path: &str) -> bool {
+    path.is_empty()
+        || (path.starts_with('/')
+            && path.chars()
+                .all(|c| c.is_ascii_alphanumeric() || "-._~/".contains(c)))
+}
+
+fn header_value<'a>(req: &'a Request, name: &str) -> Option<&'a str> {
+    req.headers()
+        .get_raw(name)
+        .and_then(|raw| raw.one())
+        .and_then(|value| ::std::str::from_utf8(value).ok())
+}
+
 impl RequestHandler {
-    pub fn new(state: ::server::state::StateRef) -> Self {
-        Self { state: state }
+    pub fn new(state: ::server::state::StateRef, remote: Option<SocketAddr>) -> Self {
+        Self {
+            state: state,
+            remote: remote,
+        }
+    }
+
+    /// Resolve the client, the base path and the path of the request;
+    /// X-Forwarded-* headers are used only when the request comes from a trusted proxy
+    fn forwarded(&self, req: &Request) -> Forwarded {
+        let state = self.state.get();
+        let config = &state.http_config;
+        let remote = req.remote_addr().or(self.remote);
+        let trusted = remote
+            .map(|address| config.trusted_proxies.contains(&address.ip()))
+            .unwrap_or(false);
+
+        let mut client = remote
+            .map(|address| address.to_string())
+            .unwrap_or_else(|| "unknown".to_string());
+        let mut base_path = config.base_path.clone();
+        if trusted {
+            if let Some(value) = header_value(req, "X-Forwarded-For") {
+                client = value.split(',').next().unwrap().trim().to_string();
+            }
+            if let Some(value) = header_value(req, "X-Forwarded-Prefix") {
+                let value = value.trim_right_matches('/');
+                if is_valid_base_path(value) {
+                    base_path = value.to_string();
+                } else {
+                    warn!("Invalid X-Forwarded-Prefix {:?} is ignored", value);
+                }
+            }
+        }
+
+        // Proxies that do not strip the prefix forward the full path
+        let mut path = req.path();
+        if !base_path.is_empty() && path.starts_with(&base_path) {
+            let rest = &path[base_path.len()..];
+            if rest.is_empty() || rest.starts_with('/') {
+                path = rest;
+            }
+        }
+        let path = if path.is_empty() { "/" } else { path }.to_string();
+        Forwarded {
+            client,
+            base_path,
+            path,
+            trusted,
+        }
+    }
+
+    /// Authenticate the request. When access control is enabled (access file) or
+    /// the authentication header is configured, every route except static files of
+    /// the dashboard needs a user authenticated by a trusted proxy or a bearer token.
+    /// Preflight requests of browsers to JSON API are not authenticated.
+    fn authorize(
+        &self,
+        req: &Request,
+        forwarded: &Forwarded,
+    ) -> Result<(), (StatusCode, String)> {
+        let path = forwarded.path.as_str();
+        let preflight = *req.method() == Method::Options && path.starts_with(API_PREFIX);
+        if is_static_file(path) || preflight {
+            return Ok(());
+        }
+        let state = self.state.get();
+        let config = &state.http_config;
+        let user = config
+            .auth_header
+            .as_ref()
+            .filter(|_| forwarded.trusted)
+            .and_then(|header| header_value(req, header));
+        if let Some(name) = user {
+            return state
+                .authenticate_user(name)
+                .and_then(|identity| identity.check_role(Role::Readonly))
+                .map_err(|e| (StatusCode::Forbidden, e.to_string()));
+        }
+        let token = header_value(req, "Authorization")
+            .map(|value| value.trim_left_matches("Bearer ").trim());
+        match token {
+            Some(token) if state.has_access_control() => state
+                .authenticate(token)
+                .and_then(|identity| identity.check_role(Role::Readonly))
+                .map_err(|e| (StatusCode::Unauthorized, e.to_string())),
+            _ if state.has_access_control() || config.auth_header.is_some() => Err((
+                StatusCode::Unauthorized,
+                "Authentication required".to_string(),
+            )),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Files of the dashboard, they contain no data and they are served to anyone
+fn is_static_file(path: &str) -> bool {
+    (path.starts_with("/static/js/main.") && path.ends_with(".js"))
+        || (path.starts_with("/static/css/main.") && path.ends_with(".css"))
+}
+
+fn json_response(status: StatusCode, data: String) -> Response {
+    Response::new()
+        .with_status(status)
+        .with_header(ContentType::json())
+        .with_header(ContentLength(data.len() as u64))
+        .with_body(data)
+}
+
+fn json_error(status: StatusCode, message: &str) -> Response {
+    json_response(
+        status,
+        format!("{{\"error\":{}}}", ::serde_json::to_string(message).unwrap()),
+    )
+}
+
+/// Allow the origin of a browser request when it is configured
+fn add_cors_headers(response: &mut Response, cors_origins: &[String], origin: Option<&str>) {
+    let headers = response.headers_mut();
+    headers.remove::<AccessControlAllowOrigin>();
+    let origin = match origin {
+        Some(origin) if cors_origins.iter().any(|o| o == "*" || o == origin) => origin,
+        _ => return,
+    };
+    headers.set_raw("Access-Control-Allow-Origin", origin.to_string());
+    headers.set_raw("Vary", "Origin");
+    headers.set_raw("Access-Control-Allow-Methods", "GET, POST, OPTIONS");
+    headers.set_raw("Access-Control-Allow-Headers", "Authorization, Content-Type");
+    headers.set_raw("Access-Control-Max-Age", "600");
+}
+
+/// Answer an authenticated request of JSON API
+fn api_dispatch(
+    state: &StateRef,
+    method: Method,
+    path: &str,
+    query: Option<&str>,
+    body: &[u8],
+) -> ResponseFuture {
+    let api_path = &path[API_PREFIX.len()..];
+    match (method, api_path) {
+        (Method::Post, "events") => {
+            let body = ::std::str::from_utf8(body).unwrap_or("");
+            Box::new(get_events(state, body).map(|mut response| {
+                response.headers_mut().set(ContentType::json());
+                response
+            }))
+        }
+        // Large snapshots are serialized by the serialization thread
+        (Method::Get, _) if api_path.trim_matches('/') == "graph" => {
+            let s = state.get();
+            let snapshot = api::graph_snapshot(&s, query);
+            match snapshot {
+                Ok(Some(snapshot)) => {
+                    let items = snapshot.tasks.len() + snapshot.objects.len();
+                    Box::new(s.serializer().to_json(snapshot, items).then(|r| {
+                        Ok(match r {
+                            Ok(data) => json_response(StatusCode::Ok, data),
+                            Err(e) => json_error(StatusCode::InternalServerError, &e.to_string()),
+                        })
+                    }))
+                }
+                Ok(None) => Box::new(::futures::future::ok(json_error(
+                    StatusCode::NotFound,
+                    "Not found",
+                ))),
+                Err(e) => Box::new(::futures::future::ok(json_error(
+                    StatusCode::BadRequest,
+                    &e.to_string(),
+                ))),
+            }
+        }
+        (Method::Get, _) => Box::new(::futures::future::ok(
+            match api::get(&state.get(), api_path, query) {
+                Ok(Some(data)) => json_response(StatusCode::Ok, data),
+                Ok(None) => json_error(StatusCode::NotFound, "Not found"),
+                Err(e) => json_error(StatusCode::BadRequest, &e.to_string()),
+            },
+        )),
+        _ => Box::new(::futures::future::ok(json_error(
+            StatusCode::MethodNotAllowed,
+            "Method not allowed",
+        ))),
     }
 }
 
+/// Response to a request refused by `RequestHandler::authorize`;
+/// JSON API answers with JSON errors and with CORS headers, so browsers can read them
+fn rejected_response(
+    state: &StateRef,
+    req: &Request,
+    path: &str,
+    status: StatusCode,
+    message: String,
+) -> Response {
+    if !path.starts_with(API_PREFIX) {
+        return Response::new()
+            .with_status(status)
+            .with_header(ContentLength(message.len() as u64))
+            .with_body(message);
+    }
+    let mut response = json_error(status, &message);
+    if status == StatusCode::Unauthorized {
+        response.headers_mut().set_raw("WWW-Authenticate", "Bearer");
+    }
+    add_cors_headers(
+        &mut response,
+        &state.get().http_config.cors_origins,
+        header_value(req, "Origin"),
+    );
+    response
+}
+
+/// JSON API for custom dashboards and notebooks (see `server::api`), the request
+/// is already authenticated (see `RequestHandler::authorize`)
+fn api_request(
+    state: StateRef,
+    req: Request,
+    path: String,
+) -> Box<futures::Future<Item = Response, Error = Error>> {
+    let origin = header_value(&req, "Origin").map(|s| s.to_string());
+    let cors_origins = state.get().http_config.cors_origins.clone();
+    let method = req.method().clone();
+    let query = req.query().map(|s| s.to_string());
+    Box::new(req.body().concat2().and_then(move |body| {
+        let future: ResponseFuture = if method == Method::Options {
+            Box::new(::futures::future::ok(
+                Response::new().with_status(StatusCode::NoContent),
+            ))
+        } else {
+            api_dispatch(
+                &state,
+                method,
+                &path,
+                query.as_ref().map(|s| s.as_str()),
+                &body,
+            )
+        };
+        future.then(move |result| {
+            let mut response = result.unwrap_or_else(|e| {
+                json_error(StatusCode::InternalServerError, &e.to_string())
+            });
+            add_cors_headers(
+                &mut response,
+                &cors_origins,
+                origin.as_ref().map(|s| s.as_str()),
+            );
+            Ok(response)
+        })
+    }))
+}
+
+/// Dashboard index with links under the base path
+fn index_response(base_path: &str) -> ResponseFuture {
+    let html = ::std::str::from_utf8(&include_bytes!("./../../dashboard/dist/index.html")[..])
+        .unwrap()
+        .replace("=\"/", &format!("=\"{}/", base_path))
+        .replace(
+            "</head>",
+            &format!(
+                "<script>window.RAIN_BASE_PATH={}</script></head>",
+                ::serde_json::to_string(base_path).unwrap()
+            ),
+        );
+    Box::new(::futures::future::ok(
+        Response::new()
+            .with_header(ContentLength(html.len() as u64))
+            .with_body(html),
+    ))
+}
+
 type ResponseFuture = Box<futures::Future<Item = Response, Error = ::errors::Error>>;
 
 fn get_events(state: &StateRef, body: &str) -> ResponseFuture {
@@ -53,6 +367,29 @@ fn get_events(state: &StateRef, body: &str) -> ResponseFuture {
     }
 }
 
+/// Explain scheduling of a task, path is "/explain/<session_id>/<task_id>"
+fn explain_task(state: &StateRef, path: &str) -> ResponseFuture {
+    let ids: Vec<_> = path.trim_matches('/').split('/').skip(1).collect();
+    if ids.len() != 2 {
+        return Box::new(::futures::future::failed(
+            format!("Invalid path {:?}", path).into(),
+        ));
+    }
+    let task_id = match (ids[0].parse(), ids[1].parse()) {
+        (Ok(session_id), Ok(id)) => TaskId::new(session_id, id),
+        _ => {
+            return Box::new(::futures::future::failed(
+                format!("Invalid task id in {:?}", path).into(),
+            ))
+        }
+    };
+    let result = match state.get().scheduler_decision(&task_id) {
+        Some(decision) => ::serde_json::to_string(decision).unwrap(),
+        None => "null".to_string(),
+    };
+    Box::new(::futures::future::ok(make_text_response(result)))
+}
+
 fn lite_dashboard(state: &StateRef) -> ResponseFuture {
     Box::new(::futures::future::ok(make_text_response(format!(
         "<html>
@@ -105,14 +442,6 @@ fn make_text_response(data: String) -> Response {
     }*/
 }
 
-fn static_data_response(data: &'static [u8]) -> ResponseFuture {
-    Box::new(::futures::future::ok(
-        Response::new()
-            .with_header(ContentLength(data.len() as u64))
-            .with_body(data),
-    ))
-}
-
 fn static_gzipped_response(data: &'static [u8]) -> ResponseFuture {
     Box::new(::futures::future::ok(
         Response::new()
@@ -131,13 +460,33 @@ impl Service for RequestHandler {
 
     fn call(&self, req: Request) -> Self::Future {
         let state_ref = self.state.clone();
-        debug!("HTTP request: {}", req.path());
-        let path = req.path().to_string();
+        let forwarded = self.forwarded(&req);
+        if let Err((status, message)) = self.authorize(&req, &forwarded) {
+            warn!("HTTP request {} rejected: {}", req.path(), message);
+            return Box::new(::futures::future::ok(rejected_response(
+                &state_ref,
+                &req,
+                &forwarded.path,
+                status,
+                message,
+            )));
+        }
+        let Forwarded {
+            client,
+            base_path,
+            path,
+            ..
+        } = forwarded;
+        debug!("HTTP request: {} from {}", path, client);
+        if path.starts_with(API_PREFIX) {
+            return api_request(state_ref, req, path);
+        }
         Box::new(req.body().concat2().and_then(move |body| {
             let body = ::std::str::from_utf8(&body).unwrap();
             let future = match path.as_str() {
                 "/events" => get_events(&state_ref, &body),
                 "/lite" | "/lite/" => lite_dashboard(&state_ref),
+                path if path.starts_with("/explain/") => explain_task(&state_ref, path),
                 // to protect against caching, .js contain hash in index.html, the same for .css file
                 path if path.starts_with("/static/js/main.") && path.ends_with(".js") => {
                     static_gzipped_response(
@@ -149,7 +498,7 @@ impl Service for RequestHandler {
                         &include_bytes!("./../../dashboard/dist/main.css.gz")[..],
                     )
                 }
-                _ => static_data_response(&include_bytes!("./../../dashboard/dist/index.html")[..]),
+                _ => index_response(&base_path),
                 /*path =>  {
                         warn!("Invalid HTTP request: {}", path);
                         Response::new().with_status(StatusCode::NotFound)
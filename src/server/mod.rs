@@ -1,6 +1,18 @@
 pub mod state;
+pub mod access;
+pub mod alerts;
+pub mod api;
 pub mod graph;
+pub mod history;
+pub mod redact;
 pub mod rpc;
 pub mod scheduler;
+pub mod secrets;
+pub mod serializer;
+pub mod snapshot;
+pub mod tags;
+pub mod upgrade;
+pub mod release;
 pub mod http;
+pub mod notify;
 pub mod testmode;
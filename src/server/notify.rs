@@ -0,0 +1,315 @@
+use std::fs::File;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::TcpStream;
+use std::path::Path;
+use std::sync::mpsc;
+use std::time::Duration;
+
+use native_tls;
+
+use errors::Result;
+
+/// Timeout of connections of notifier backends
+const NOTIFY_TIMEOUT: u64 = 30; // seconds
+
+/// Events the server notifies about
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum NotificationKind {
+    /// All submitted tasks of a session are finished
+    SessionFinished,
+    /// A session failed; the notification contains the failure digest of the failed task
+    SessionFailed,
+    /// Alert rule (--alerts) fired or was resolved
+    Alert,
+}
+
+#[derive(Serialize, Debug)]
+pub struct Notification {
+    pub kind: NotificationKind,
+    pub subject: String,
+    pub body: String,
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+enum Backend {
+    /// JSON of `Notification` posted to `url`
+    Webhook,
+    /// Message posted to an incoming webhook of Slack at `url`
+    Slack,
+    /// E-mail sent through SMTP `server` (without authentication) from `from` to `to`
+    Smtp,
+}
+
+/// Item of the notification file (server option --notifications)
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+struct NotifierConfig {
+    backend: Backend,
+    url: Option<String>,
+    server: Option<String>,
+    from: Option<String>,
+    #[serde(default)]
+    to: Vec<String>,
+    /// Kinds of notifications sent by the backend, all kinds when empty
+    #[serde(default)]
+    events: Vec<NotificationKind>,
+}
+
+impl NotifierConfig {
+    fn validate(&self) -> Result<()> {
+        match self.backend {
+            Backend::Webhook | Backend::Slack => {
+                if self.url.is_none() {
+                    bail!("Notifier {:?} needs 'url'", self.backend);
+                }
+            }
+            Backend::Smtp => {
+                if self.server.is_none() || self.from.is_none() || self.to.is_empty() {
+                    bail!("Notifier smtp needs 'server', 'from' and 'to'");
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn accepts(&self, kind: NotificationKind) -> bool {
+        self.events.is_empty() || self.events.contains(&kind)
+    }
+
+    fn deliver(&self, notification: &Notification) -> Result<()> {
+        match self.backend {
+            Backend::Webhook => http_post(
+                self.url.as_ref().unwrap(),
+                &::serde_json::to_string(notification)?,
+            ),
+            Backend::Slack => {
+                let text = format!("*{}*\n{}", notification.subject, notification.body);
+                http_post(
+                    self.url.as_ref().unwrap(),
+                    &json!({ "text": text }).to_string(),
+                )
+            }
+            Backend::Smtp => smtp_send(
+                self.server.as_ref().unwrap(),
+                self.from.as_ref().unwrap(),
+                &self.to,
+                &notification.subject,
+                &notification.body,
+            ),
+        }
+    }
+}
+
+/// Sends notifications to backends configured by --notifications. Notifications are
+/// delivered by a separate thread, so slow backends do not block the server.
+pub struct Notifier {
+    sender: mpsc::Sender<Notification>,
+    backends: usize,
+}
+
+impl Notifier {
+    pub fn load(path: &Path) -> Result<Self> {
+        let file = File::open(path).map_err(|e| format!("Cannot open {:?}: {}", path, e))?;
+        let configs: Vec<NotifierConfig> = ::serde_json::from_reader(file)
+            .map_err(|e| format!("Invalid notification file {:?}: {}", path, e))?;
+        for config in &configs {
+            config.validate()?;
+        }
+        let backends = configs.len();
+        let (sender, receiver) = mpsc::channel::<Notification>();
+        ::std::thread::Builder::new()
+            .name("notifier".to_string())
+            .spawn(move || {
+                for notification in receiver {
+                    for config in configs.iter().filter(|c| c.accepts(notification.kind)) {
+                        if let Err(e) = config.deliver(&notification) {
+                            warn!("Notification by {:?} failed: {}", config.backend, e);
+                        }
+                    }
+                }
+            })?;
+        Ok(Notifier { sender, backends })
+    }
+
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.backends
+    }
+
+    pub fn notify(&self, kind: NotificationKind, subject: String, body: String) {
+        let notification = Notification {
+            kind,
+            subject,
+            body,
+        };
+        if self.sender.send(notification).is_err() {
+            warn!("Notifier is not running");
+        }
+    }
+}
+
+fn connect(host: &str, port: u16) -> Result<TcpStream> {
+    let stream = TcpStream::connect((host, port))
+        .map_err(|e| format!("Cannot connect to {}:{}: {}", host, port, e))?;
+    stream.set_read_timeout(Some(Duration::from_secs(NOTIFY_TIMEOUT)))?;
+    stream.set_write_timeout(Some(Duration::from_secs(NOTIFY_TIMEOUT)))?;
+    Ok(stream)
+}
+
+/// POST a JSON body to http(s) URL, the response has to have status 2xx
+fn http_post(url: &str, body: &str) -> Result<()> {
+    let uri: ::hyper::Uri = url.parse()
+        .map_err(|e| format!("Invalid URL '{}': {}", url, e))?;
+    let https = match uri.scheme() {
+        Some("https") => true,
+        Some("http") => false,
+        _ => bail!("Unsupported URL '{}'", url),
+    };
+    let host = uri.host().ok_or_else(|| format!("URL '{}' has no host", url))?;
+    let port = uri.port().unwrap_or(if https { 443 } else { 80 });
+    let path = match uri.query() {
+        Some(query) => format!("{}?{}", uri.path(), query),
+        None => uri.path().to_string(),
+    };
+    let request = format!(
+        "POST {} HTTP/1.1\r\nHost: {}\r\nContent-Type: application/json\r\n\
+         Content-Length: {}\r\nConnection: close\r\n\r\n{}",
+        path,
+        host,
+        body.len(),
+        body
+    );
+    let stream = connect(host, port)?;
+    let status_line = if https {
+        let connector =
+            native_tls::TlsConnector::new().map_err(|e| format!("Cannot initialize TLS: {}", e))?;
+        let mut stream = connector
+            .connect(host, stream)
+            .map_err(|e| format!("TLS connection to {} failed: {}", host, e))?;
+        http_exchange(&mut stream, &request)?
+    } else {
+        let mut stream = stream;
+        http_exchange(&mut stream, &request)?
+    };
+    let status = status_line.split_whitespace().nth(1).unwrap_or("");
+    if !status.starts_with('2') {
+        bail!("{} returned '{}'", url, status_line.trim());
+    }
+    Ok(())
+}
+
+/// Send the request and return the status line of the response
+fn http_exchange<S: Read + Write>(stream: &mut S, request: &str) -> Result<String> {
+    stream.write_all(request.as_bytes())?;
+    let mut status_line = String::new();
+    BufReader::new(stream).read_line(&mut status_line)?;
+    Ok(status_line)
+}
+
+/// Read a (possibly multiline) reply of SMTP server and check its code
+fn smtp_reply<R: BufRead>(reader: &mut R, expected: &str) -> Result<()> {
+    loop {
+        let mut line = String::new();
+        if reader.read_line(&mut line)? == 0 {
+            bail!("SMTP server closed the connection");
+        }
+        // "250-..." continues, "250 ..." is the last line of the reply
+        if line.len() < 4 || line.as_bytes()[3] != b'-' {
+            if !line.starts_with(expected) {
+                bail!("Unexpected SMTP reply '{}'", line.trim());
+            }
+            return Ok(());
+        }
+    }
+}
+
+/// Message in the DATA format: CRLF line ends, lines starting with a dot are escaped,
+/// terminated by a line with a single dot
+fn smtp_message(from: &str, to: &[String], subject: &str, body: &str) -> String {
+    let mut message = format!(
+        "From: {}\r\nTo: {}\r\nSubject: {}\r\nContent-Type: text/plain; charset=utf-8\r\n\r\n",
+        from,
+        to.join(", "),
+        subject.replace(|c| c == '\r' || c == '\n', " ")
+    );
+    for line in body.lines() {
+        if line.starts_with('.') {
+            message.push('.');
+        }
+        message.push_str(line);
+        message.push_str("\r\n");
+    }
+    message.push_str(".\r\n");
+    message
+}
+
+fn smtp_send(server: &str, from: &str, to: &[String], subject: &str, body: &str) -> Result<()> {
+    let (host, port) = match server.rfind(':') {
+        Some(index) => (
+            &server[..index],
+            server[index + 1..]
+                .parse()
+                .map_err(|_| format!("Invalid SMTP server '{}'", server))?,
+        ),
+        None => (server, 25),
+    };
+    let mut writer = connect(host, port)?;
+    let mut reader = BufReader::new(writer.try_clone()?);
+    smtp_reply(&mut reader, "220")?;
+    let mut command = |line: String, expected: &str| -> Result<()> {
+        writer.write_all(line.as_bytes())?;
+        writer.write_all(b"\r\n")?;
+        smtp_reply(&mut reader, expected)
+    };
+    command(format!("HELO {}", ::common::sys::get_hostname()), "250")?;
+    command(format!("MAIL FROM:<{}>", from), "250")?;
+    for address in to {
+        command(format!("RCPT TO:<{}>", address), "25")?;
+    }
+    command("DATA".to_string(), "354")?;
+    let message = smtp_message(from, to, subject, body);
+    command(message.trim_right_matches("\r\n").to_string(), "250")?;
+    command("QUIT".to_string(), "221")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_notifier_config() {
+        let configs: Vec<NotifierConfig> = ::serde_json::from_str(
+            r#"[{"backend": "slack", "url": "https://hooks.example.com/x", "events": ["alert"]},
+                {"backend": "smtp", "server": "localhost:25", "from": "rain@example.com",
+                 "to": ["ops@example.com"]}]"#,
+        ).unwrap();
+        assert!(configs.iter().all(|c| c.validate().is_ok()));
+        assert!(configs[0].accepts(NotificationKind::Alert));
+        assert!(!configs[0].accepts(NotificationKind::SessionFinished));
+        assert!(configs[1].accepts(NotificationKind::SessionFailed));
+
+        let config: NotifierConfig = ::serde_json::from_str(r#"{"backend": "webhook"}"#).unwrap();
+        assert!(config.validate().is_err());
+    }
+
+    #[test]
+    fn test_smtp_message() {
+        let to = vec!["a@example.com".to_string(), "b@example.com".to_string()];
+        let message = smtp_message("r@example.com", &to, "Failed\nsession", "line\n.dot\n");
+        assert_eq!(
+            message,
+            "From: r@example.com\r\nTo: a@example.com, b@example.com\r\nSubject: Failed session\r\n\
+             Content-Type: text/plain; charset=utf-8\r\n\r\nline\r\n..dot\r\n.\r\n"
+        );
+    }
+
+    #[test]
+    fn test_smtp_reply() {
+        let mut reader = "250-hello\r\n250-SIZE\r\n250 OK\r\n".as_bytes();
+        assert!(smtp_reply(&mut reader, "250").is_ok());
+        let mut reader = "550 No such user\r\n".as_bytes();
+        assert!(smtp_reply(&mut reader, "25").is_err());
+    }
+}
@@ -0,0 +1,207 @@
+use std::collections::HashMap;
+
+use serde_json::Value;
+
+use common::Attributes;
+use errors::Result;
+
+/// Value that replaces redacted values
+pub const REDACTED: &str = "<redacted>";
+
+/// Part of tasks and sessions where a rule of redaction applies
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Scope {
+    /// Keys of the task configuration (attribute "config"), also in nested objects
+    Config,
+    /// Names of environment variables of tasks (attribute "env") and of sessions
+    Env,
+    /// Names of attributes of tasks and objects, the whole value is redacted
+    Attribute,
+}
+
+#[derive(Clone, Debug)]
+struct Rule {
+    scope: Scope,
+    pattern: String,
+}
+
+/// Redaction of values of tasks before they leave the server state (server option
+/// --redact): event logs, graph snapshots for the dashboard and tools, and exported
+/// session bundles. Rules are "SCOPE:PATTERN", the pattern matches names case
+/// insensitively and may contain wildcards '*' and '?'. Tasks are sent to workers
+/// and returned to their clients unchanged.
+#[derive(Clone, Debug, Default)]
+pub struct Redaction {
+    rules: Vec<Rule>,
+}
+
+/// Match a glob pattern (wildcards '*' and '?') against a name
+fn glob_match(pattern: &[char], name: &[char]) -> bool {
+    match pattern.first() {
+        None => name.is_empty(),
+        Some(&'*') => (0..name.len() + 1).any(|i| glob_match(&pattern[1..], &name[i..])),
+        Some(&'?') => !name.is_empty() && glob_match(&pattern[1..], &name[1..]),
+        Some(c) => name.first() == Some(c) && glob_match(&pattern[1..], &name[1..]),
+    }
+}
+
+impl Redaction {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Add a rule "SCOPE:PATTERN", scope is "config", "env" or "attribute"
+    pub fn add_rule(&mut self, rule: &str) -> Result<()> {
+        let (scope, pattern) = match rule.find(':') {
+            Some(i) => (&rule[..i], &rule[i + 1..]),
+            None => bail!("Invalid redaction rule '{}': expected SCOPE:PATTERN", rule),
+        };
+        let scope = match scope {
+            "config" => Scope::Config,
+            "env" => Scope::Env,
+            "attribute" => Scope::Attribute,
+            _ => bail!(
+                "Invalid redaction rule '{}': unknown scope '{}' (use config, env or attribute)",
+                rule,
+                scope
+            ),
+        };
+        if pattern.is_empty() {
+            bail!("Invalid redaction rule '{}': empty pattern", rule);
+        }
+        self.rules.push(Rule {
+            scope,
+            pattern: pattern.to_lowercase(),
+        });
+        Ok(())
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.rules.is_empty()
+    }
+
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.rules.len()
+    }
+
+    /// The name is matched by a rule of the scope
+    pub fn matches(&self, scope: Scope, name: &str) -> bool {
+        let name: Vec<char> = name.to_lowercase().chars().collect();
+        self.rules.iter().any(|r| {
+            r.scope == scope && glob_match(&r.pattern.chars().collect::<Vec<_>>(), &name)
+        })
+    }
+
+    fn redact_keys(&self, scope: Scope, value: &mut Value) {
+        match *value {
+            Value::Object(ref mut map) => {
+                for (key, value) in map.iter_mut() {
+                    if self.matches(scope, key) {
+                        *value = Value::String(REDACTED.to_string());
+                    } else if scope == Scope::Config {
+                        self.redact_keys(scope, value);
+                    }
+                }
+            }
+            Value::Array(ref mut items) if scope == Scope::Config => {
+                for item in items.iter_mut() {
+                    self.redact_keys(scope, item);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// Copy of attributes with redacted values
+    pub fn attributes(&self, attributes: &Attributes) -> Attributes {
+        let mut result = attributes.clone();
+        if self.is_empty() {
+            return result;
+        }
+        for (key, value) in attributes.as_hashmap() {
+            let redacted = if self.matches(Scope::Attribute, key) {
+                Some(Value::String(REDACTED.to_string()))
+            } else if key == "config" || key == "env" {
+                let scope = if key == "config" {
+                    Scope::Config
+                } else {
+                    Scope::Env
+                };
+                match ::serde_json::from_str::<Value>(value) {
+                    Ok(mut value) => {
+                        self.redact_keys(scope, &mut value);
+                        Some(value)
+                    }
+                    Err(_) => None,
+                }
+            } else {
+                None
+            };
+            if let Some(value) = redacted {
+                result.set(key, value).unwrap();
+            }
+        }
+        result
+    }
+
+    /// Copy of environment variables with redacted values
+    pub fn env(&self, env: &HashMap<String, String>) -> HashMap<String, String> {
+        env.iter()
+            .map(|(name, value)| {
+                if self.matches(Scope::Env, name) {
+                    (name.clone(), REDACTED.to_string())
+                } else {
+                    (name.clone(), value.clone())
+                }
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_redact_attributes() {
+        let mut redaction = Redaction::new();
+        redaction.add_rule("config:*secret*").unwrap();
+        redaction.add_rule("env:AWS_*").unwrap();
+        redaction.add_rule("attribute:model_?").unwrap();
+        assert!(redaction.add_rule("config").is_err());
+        assert!(redaction.add_rule("input:x").is_err());
+        assert_eq!(redaction.len(), 3);
+
+        let mut attributes = Attributes::new();
+        attributes
+            .set(
+                "config",
+                json!({"args": ["a"], "Secret_Key": 12, "nested": [{"secret": "x", "y": 1}]}),
+            )
+            .unwrap();
+        attributes
+            .set("env", json!({"aws_token": "abc", "PATH": "/bin"}))
+            .unwrap();
+        attributes.set("model_a", "weights").unwrap();
+        attributes.set("model_ab", "weights").unwrap();
+
+        let redacted = redaction.attributes(&attributes);
+        let config: Value = redacted.get("config").unwrap();
+        assert_eq!(
+            config,
+            json!({"args": ["a"], "Secret_Key": REDACTED,
+                   "nested": [{"secret": REDACTED, "y": 1}]})
+        );
+        let env: Value = redacted.get("env").unwrap();
+        assert_eq!(env, json!({"aws_token": REDACTED, "PATH": "/bin"}));
+        assert_eq!(redacted.get::<String>("model_a").unwrap(), REDACTED);
+        assert_eq!(redacted.get::<String>("model_ab").unwrap(), "weights");
+
+        let env: HashMap<_, _> = vec![("AWS_KEY".to_string(), "k".to_string())]
+            .into_iter()
+            .collect();
+        assert_eq!(redaction.env(&env)["AWS_KEY"], REDACTED);
+    }
+}
@@ -0,0 +1,67 @@
+use std::time::{Duration, Instant};
+
+use server::graph::WorkerRef;
+use server::state::State;
+
+/// How often the drain status of released workers is checked (in milliseconds)
+const RELEASE_CHECK_INTERVAL: u64 = 1000;
+
+/// Workers released back to the broker (see `releaseWorkers` in client.capnp);
+/// they are drained and asked to leave when nothing would be lost
+#[derive(Debug, Default)]
+pub struct WorkerRelease {
+    workers: Vec<WorkerRef>,
+    last_check: Option<Instant>,
+}
+
+impl WorkerRelease {
+    /// Drain `count` workers with the least work, returns the number of released workers
+    pub fn release(&mut self, state: &mut State, count: usize) -> usize {
+        let mut workers: Vec<_> = state
+            .graph
+            .workers
+            .values()
+            .filter(|w| {
+                let w = w.get();
+                w.error.is_none() && !w.draining
+            })
+            .cloned()
+            .collect();
+        workers.sort_by_key(|w| (w.get().assigned_tasks.len(), w.get_id()));
+        workers.truncate(count);
+        for wref in &workers {
+            info!("Releasing worker {} to the broker", wref.get_id());
+            state.drain_worker(wref);
+        }
+        let released = workers.len();
+        self.workers.extend(workers);
+        released
+    }
+
+    /// Ask drained workers to leave
+    pub fn drive(&mut self, state: &mut State) {
+        if self.workers.is_empty() {
+            return;
+        }
+        let now = Instant::now();
+        if let Some(last_check) = self.last_check {
+            if now < last_check + Duration::from_millis(RELEASE_CHECK_INTERVAL) {
+                return;
+            }
+        }
+        self.last_check = Some(now);
+
+        let workers = ::std::mem::replace(&mut self.workers, Vec::new());
+        for wref in workers {
+            if wref.get().leaving {
+                continue;
+            }
+            if state.drain_status(&wref) {
+                info!("Released worker {} leaves", wref.get_id());
+                state.ask_worker_to_leave(&wref);
+            } else {
+                self.workers.push(wref);
+            }
+        }
+    }
+}
@@ -4,9 +4,12 @@ use capnp::capability::Promise;
 use capnp;
 
 use super::{ClientServiceImpl, WorkerUpstreamImpl};
+use common::capabilities::Capabilities;
 use common::id::WorkerId;
+use common::labels::parse_labels;
 use common::convert::{FromCapnp, ToCapnp};
 use common::resources::Resources;
+use common::sys::Platform;
 use server::state::StateRef;
 use server_capnp::server_bootstrap;
 
@@ -59,14 +62,33 @@ impl server_bootstrap::Server for ServerBootstrapImpl {
             return Promise::err(capnp::Error::failed(format!("Protocol mismatch")));
         }
 
+        let mut identity = match self.state.get().authenticate(pry!(params.get_token())) {
+            Ok(identity) => identity,
+            Err(e) => {
+                warn!("Client {}: {}", self.address, e);
+                return Promise::err(capnp::Error::failed(e.to_string()));
+            }
+        };
+        if params.get_observer() {
+            identity = identity.observer();
+        }
+
         self.registered = true;
 
+        info!(
+            "Connection {} registered as {} '{}' with role {}",
+            self.address,
+            if params.get_observer() { "observer" } else { "client" },
+            identity.name,
+            identity.role
+        );
+
         let service = ::client_capnp::client_service::ToClient::new(pry!(ClientServiceImpl::new(
             &self.state,
-            &self.address
+            &self.address,
+            identity
         ))).from_server::<::capnp_rpc::Server>();
 
-        info!("Connection {} registered as client", self.address);
         results.get().set_service(service);
         Promise::ok(())
     }
@@ -102,11 +124,45 @@ impl server_bootstrap::Server for ServerBootstrapImpl {
         };
 
         let resources = Resources::from_capnp(&pry!(params.get_resources()));
+        let via_gateway = params.get_via_gateway();
+        let cost = params.get_cost();
+        let preemptible = params.get_preemptible();
+        let capabilities = pry!(Capabilities::from_capnp(&pry!(params.get_capabilities())));
+        let image = pry!(params.get_image()).to_string();
+        let labels = {
+            let specs: Vec<&str> = pry!(pry!(params.get_labels()).iter().collect());
+            pry!(parse_labels(specs))
+        };
+        let platform = pry!(Platform::from_capnp(&pry!(params.get_platform())));
 
         info!(
-            "Connection {} registered as worker {} with {:?}",
-            self.address, worker_id, resources
+            "Connection {} registered as worker {} with {:?}, cost {}, image '{}'{}",
+            self.address,
+            worker_id,
+            resources,
+            cost,
+            image,
+            if via_gateway { " (via gateway)" } else { "" }
         );
+        if preemptible {
+            info!("Worker {} is preemptible", worker_id);
+        }
+        if !labels.is_empty() {
+            info!("Worker {} labels: {:?}", worker_id, labels);
+        }
+        if platform.is_unknown() {
+            info!("Worker {} does not report its platform", worker_id);
+        } else {
+            info!(
+                "Worker {} platform: {} {} (kernel {}), rain {}",
+                worker_id, platform.os, platform.arch, platform.kernel, platform.version
+            );
+        }
+        if capabilities.is_legacy() {
+            info!("Worker {} does not announce capabilities", worker_id);
+        } else {
+            debug!("Worker {} capabilities: {:?}", worker_id, capabilities);
+        }
 
         let control = pry!(params.get_control());
         let state = self.state.clone();
@@ -123,11 +179,30 @@ impl server_bootstrap::Server for ServerBootstrapImpl {
                     .get_mut()
                     .add_worker(worker_id, Some(control), resources,)
             );
+            worker.get_mut().via_gateway = via_gateway;
+            worker.get_mut().cost = cost;
+            worker.get_mut().preemptible = preemptible;
+            worker.get_mut().capabilities = capabilities;
+            worker.get_mut().image = image;
+            worker.get_mut().labels = labels;
+            worker.get_mut().platform = platform;
+            state.get().init_worker_chaos(&worker);
             let upstream = ::worker_capnp::worker_upstream::ToClient::new(
                 WorkerUpstreamImpl::new(&state, &worker),
             ).from_server::<::capnp_rpc::Server>();
             results.get().set_upstream(upstream);
             worker_id.to_capnp(&mut results.get().get_worker_id().unwrap());
+            results
+                .get()
+                .set_inline_threshold(state.get().inline_threshold() as u64);
+            ::chrono::Utc::now().to_capnp(&mut results.get().init_server_time());
+            results.get().set_server_instance(state.get().instance_id());
+            let announced = state.get().capabilities();
+            announced.to_capnp(
+                &mut results
+                    .get()
+                    .init_capabilities(announced.iter().count() as u32),
+            );
             Promise::ok(())
         }))
     }
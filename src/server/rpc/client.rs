@@ -1,31 +1,100 @@
 use capnp::capability::Promise;
 use std::net::SocketAddr;
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
 use futures::{future, Future};
 
 use common::resources::Resources;
-use common::id::{DataObjectId, SId, TaskId};
+use common::id::{is_provisional, DataObjectId, IdAssignment, SId, TaskId};
 use common::convert::{FromCapnp, ToCapnp};
 use client_capnp::client_service;
 use server::state::StateRef;
-use server::graph::{ClientRef, DataObjectRef, SessionError, TaskInput, TaskRef};
+use server::scheduler::{update_ranks, SchedulerConfig};
+use server::graph::{ClientRef, DataObjectRef, KeptFilter, SessionError, SessionRef, TaskInput,
+                    TaskRef};
 use errors::{Error, ErrorKind, Result};
 use common::{Attributes, DataType};
 use common::RcSet;
+use common::labels::parse_labels;
+use common::output_policy::{OutputPolicy, Retention};
 use server::rpc::ClientDataStoreImpl;
+use server::access::{Identity, Role};
 use common::events::{ObjectDescriptor, TaskDescriptor};
+use bundle::SessionBundle;
+
+/// Max. time (in seconds) `watchSession` waits for a change of the session
+const WATCH_SESSION_TIMEOUT: u64 = 30;
 
 pub struct ClientServiceImpl {
     state: StateRef,
     client: ClientRef,
+    /// Authenticated identity of the client, its role limits allowed operations
+    identity: Identity,
 }
 
 impl ClientServiceImpl {
-    pub fn new(state: &StateRef, address: &SocketAddr) -> Result<Self> {
+    pub fn new(state: &StateRef, address: &SocketAddr, identity: Identity) -> Result<Self> {
         Ok(Self {
             state: state.clone(),
-            client: state.get_mut().add_client(address.clone())?,
+            client: state
+                .get_mut()
+                .add_client(address.clone(), &identity.name)?,
+            identity,
         })
     }
+
+    /// Sessions may be used only by the client that created them
+    fn check_owner(&self, session: &SessionRef) -> Result<()> {
+        if session.get().client != self.client {
+            bail!("Session {} belongs to another client", session.get_id());
+        }
+        Ok(())
+    }
+}
+
+fn kept_filter_from_capnp(reader: &::client_capnp::kept_filter::Reader) -> Result<KeptFilter> {
+    let min_age = reader.get_min_age().max(0.0);
+    let tags: Vec<&str> = reader.get_tags()?.iter().collect::<::capnp::Result<_>>()?;
+    Ok(KeptFilter {
+        label_prefix: reader.get_label_prefix()?.to_string(),
+        min_size: reader.get_min_size() as usize,
+        min_age: ::std::time::Duration::new(
+            min_age as u64,
+            (min_age.fract() * 1_000_000_000f64) as u32,
+        ),
+        tags: tags.iter().map(|tag| tag.to_string()).collect(),
+    })
+}
+
+/// Tasks of the session changed after `cursor` (all tasks when it is not a cursor
+/// of the session), see `watchSession`
+fn session_changes_to_capnp(
+    session: &SessionRef,
+    cursor: u64,
+    builder: &mut ::client_capnp::session_changes::Builder,
+) {
+    let s = session.get();
+    let cursor = if cursor <= s.revision { cursor } else { 0 };
+    let mut changed: Vec<&TaskRef> = s.tasks
+        .iter()
+        .filter(|t| t.get().revision > cursor)
+        .collect();
+    changed.sort_by_key(|t| t.get().id.get_id());
+    builder.set_cursor(s.revision);
+    builder.set_finished(s.error.is_none() && s.unfinished_tasks == 0 && s.revision > 0);
+    {
+        let mut tasks = builder.borrow().init_tasks(changed.len() as u32);
+        for (i, tref) in changed.iter().enumerate() {
+            let t = tref.get();
+            let mut change = tasks.borrow().get(i as u32);
+            t.id.to_capnp(&mut change.borrow().get_id().unwrap());
+            change.set_state(t.state);
+        }
+    }
+    match s.error {
+        Some(ref e) => e.to_capnp(&mut builder.borrow().get_state().unwrap().init_error()),
+        None => builder.borrow().get_state().unwrap().set_ok(()),
+    }
 }
 
 impl Drop for ClientServiceImpl {
@@ -43,6 +112,7 @@ impl client_service::Server for ClientServiceImpl {
         _: client_service::GetServerInfoParams,
         mut results: client_service::GetServerInfoResults,
     ) -> Promise<(), ::capnp::Error> {
+        pry!(self.identity.check_role(Role::Readonly));
         debug!("Client asked for info");
         let s = self.state.get();
 
@@ -54,18 +124,19 @@ impl client_service::Server for ClientServiceImpl {
                 let control = w.control.as_ref().unwrap();
                 let worker_id = worker_id.clone();
                 let resources = w.resources.clone();
+                let freed_bytes = w.freed_bytes;
                 control
                     .get_info_request()
                     .send()
                     .promise
-                    .map(move |r| (worker_id, r, resources))
+                    .map(move |r| (worker_id, r, resources, freed_bytes))
             })
             .collect();
 
         Promise::from_future(future::join_all(futures).map(move |rs| {
             let results = results.get();
             let mut workers = results.init_workers(rs.len() as u32);
-            for (i, &(ref worker_id, ref r, ref resources)) in rs.iter().enumerate() {
+            for (i, &(ref worker_id, ref r, ref resources, freed_bytes)) in rs.iter().enumerate() {
                 let mut w = workers.borrow().get(i as u32);
                 let r = r.get().unwrap();
                 w.set_tasks(r.get_tasks().unwrap()).unwrap();
@@ -73,6 +144,7 @@ impl client_service::Server for ClientServiceImpl {
                 w.set_objects_to_delete(r.get_objects_to_delete().unwrap())
                     .unwrap();
                 resources.to_capnp(&mut w.borrow().get_resources().unwrap());
+                w.set_freed_bytes(freed_bytes);
                 worker_id.to_capnp(&mut w.get_worker_id().unwrap());
             }
             ()
@@ -81,21 +153,99 @@ impl client_service::Server for ClientServiceImpl {
 
     fn new_session(
         &mut self,
-        _: client_service::NewSessionParams,
+        params: client_service::NewSessionParams,
         mut results: client_service::NewSessionResults,
     ) -> Promise<(), ::capnp::Error> {
+        pry!(self.identity.check_role(Role::User));
+        let params = pry!(params.get());
+        let mut env = HashMap::new();
+        for var in pry!(params.get_env()).iter() {
+            env.insert(
+                pry!(var.get_name()).to_string(),
+                pry!(var.get_value()).to_string(),
+            );
+        }
+        let tags = {
+            let specs: Vec<&str> = pry!(pry!(params.get_tags()).iter().collect());
+            pry!(parse_labels(specs))
+        };
         let mut s = self.state.get_mut();
         let session = pry!(s.add_session(&self.client));
+        session.get_mut().env = env;
+        session.get_mut().tags = tags;
         results.get().set_session_id(session.get_id());
         debug!("Client asked for a new session, got {:?}", session.get_id());
         Promise::ok(())
     }
 
+    fn new_sub_session(
+        &mut self,
+        params: client_service::NewSubSessionParams,
+        mut results: client_service::NewSubSessionResults,
+    ) -> Promise<(), ::capnp::Error> {
+        pry!(self.identity.check_role(Role::User));
+        let params = pry!(params.get());
+        let mut s = self.state.get_mut();
+        let parent = pry!(s.session_by_id(params.get_parent_id()));
+        pry!(self.check_owner(&parent));
+        let session = pry!(s.add_sub_session(&parent, pry!(params.get_name()).to_string()));
+        results.get().set_session_id(session.get_id());
+        Promise::ok(())
+    }
+
+    fn terminate_server(
+        &mut self,
+        _: client_service::TerminateServerParams,
+        _: client_service::TerminateServerResults,
+    ) -> Promise<(), ::capnp::Error> {
+        pry!(self.identity.check_role(Role::Admin));
+        info!("Server terminated by client '{}'", self.identity.name);
+        self.state.get_mut().terminate();
+        Promise::ok(())
+    }
+
+    fn set_secret(
+        &mut self,
+        params: client_service::SetSecretParams,
+        _: client_service::SetSecretResults,
+    ) -> Promise<(), ::capnp::Error> {
+        pry!(self.identity.check_role(Role::User));
+        let params = pry!(params.get());
+        let name = pry!(params.get_name());
+        // Never log the value
+        debug!("Client '{}' sets secret '{}'", self.identity.name, name);
+        pry!(self.state.get_mut().secrets.set(
+            &self.identity.name,
+            name,
+            pry!(params.get_value())
+        ));
+        Promise::ok(())
+    }
+
+    fn remove_secret(
+        &mut self,
+        params: client_service::RemoveSecretParams,
+        _: client_service::RemoveSecretResults,
+    ) -> Promise<(), ::capnp::Error> {
+        pry!(self.identity.check_role(Role::User));
+        let params = pry!(params.get());
+        let name = pry!(params.get_name());
+        debug!("Client '{}' removes secret '{}'", self.identity.name, name);
+        pry!(
+            self.state
+                .get_mut()
+                .secrets
+                .remove(&self.identity.name, name)
+        );
+        Promise::ok(())
+    }
+
     fn close_session(
         &mut self,
         params: client_service::CloseSessionParams,
         _: client_service::CloseSessionResults,
     ) -> Promise<(), ::capnp::Error> {
+        pry!(self.identity.check_role(Role::User));
         let params = pry!(params.get());
         let mut s = self.state.get_mut();
         let session = pry!(s.session_by_id(params.get_session_id()));
@@ -106,9 +256,15 @@ impl client_service::Server for ClientServiceImpl {
     fn submit(
         &mut self,
         params: client_service::SubmitParams,
-        _: client_service::SubmitResults,
+        mut results: client_service::SubmitResults,
     ) -> Promise<(), ::capnp::Error> {
+        pry!(self.identity.check_role(Role::User));
         let mut s = self.state.get_mut();
+        if s.is_stopping() {
+            return Promise::err(::capnp::Error::failed(
+                "Server is shutting down".to_string(),
+            ));
+        }
         let params = pry!(params.get());
         let tasks = pry!(params.get_tasks());
         let objects = pry!(params.get_objects());
@@ -121,11 +277,49 @@ impl client_service::Server for ClientServiceImpl {
         debug!("Sessions: {:?}", s.graph.sessions);
         let mut created_tasks = Vec::<TaskRef>::new();
         let mut created_objects = Vec::<DataObjectRef>::new();
+        let mut task_ids = IdAssignment::new();
+        let mut object_ids = IdAssignment::new();
         // catch any insertion error and clean up later
         let res: Result<()> = (|| {
+            // check ids before the graph is changed
+            let mut submitted_tasks = Vec::new();
+            for ct in tasks.iter() {
+                submitted_tasks.push(TaskId::from_capnp(&ct.get_id()?));
+            }
+            let mut submitted_objects = Vec::new();
+            for co in objects.iter() {
+                submitted_objects.push(DataObjectId::from_capnp(&co.get_id()?));
+            }
+            s.check_submitted_ids(&submitted_tasks, &submitted_objects)?;
+            // assign ids to provisional ids, after all other ids of the submit
+            let ids = submitted_tasks
+                .iter()
+                .map(|id| (id.get_session_id(), id.get_id()))
+                .chain(
+                    submitted_objects
+                        .iter()
+                        .map(|id| (id.get_session_id(), id.get_id())),
+                );
+            for (session_id, id) in ids.filter(|&(_, id)| !is_provisional(id)) {
+                s.session_by_id(session_id)?.get_mut().use_id(id);
+            }
+            for id in submitted_tasks.iter().filter(|id| is_provisional(id.get_id())) {
+                let session = s.session_by_id(id.get_session_id())?;
+                let new_id = session.get_mut().new_id();
+                task_ids.insert(*id, TaskId::new(id.get_session_id(), new_id));
+            }
+            for id in submitted_objects
+                .iter()
+                .filter(|id| is_provisional(id.get_id()))
+            {
+                let session = s.session_by_id(id.get_session_id())?;
+                let new_id = session.get_mut().new_id();
+                object_ids.insert(*id, DataObjectId::new(id.get_session_id(), new_id));
+            }
+
             // first create the objects
             for co in objects.iter() {
-                let id = DataObjectId::from_capnp(&co.borrow().get_id()?);
+                let id = object_ids.resolve(DataObjectId::from_capnp(&co.borrow().get_id()?))?;
                 let session = s.session_by_id(id.get_session_id())?;
                 let data_type = DataType::from_capnp(co.get_data_type().unwrap());
                 let data = if co.get_has_data() {
@@ -147,21 +341,37 @@ impl client_service::Server for ClientServiceImpl {
             }
             // second create the tasks
             for ct in tasks.iter() {
-                let id = TaskId::from_capnp(&ct.get_id()?);
+                let id = task_ids.resolve(TaskId::from_capnp(&ct.get_id()?))?;
                 let session = s.session_by_id(id.get_session_id())?;
                 let attributes = Attributes::from_capnp(&ct.get_attributes().unwrap());
                 let resources: Resources = attributes.get("resources")?;
                 let mut inputs = Vec::<TaskInput>::new();
                 for ci in ct.get_inputs()?.iter() {
+                    let object_id = object_ids.resolve(DataObjectId::from_capnp(&ci.get_id()?))?;
+                    let object = s.object_by_id(object_id)?;
+                    {
+                        let o = object.get();
+                        if o.session != session && o.published.is_none()
+                            && !session.has_ancestor(&o.session)
+                        {
+                            bail!(
+                                "Task {} uses object {} of another session that is not published \
+                                 (nor of a parent session)",
+                                id,
+                                o.id
+                            );
+                        }
+                    }
                     inputs.push(TaskInput {
-                        object: s.object_by_id(DataObjectId::from_capnp(&ci.get_id()?))?,
+                        object,
                         label: ci.get_label()?.into(),
                         path: ci.get_path()?.into(),
                     });
                 }
                 let mut outputs = Vec::<DataObjectRef>::new();
                 for co in ct.get_outputs()?.iter() {
-                    outputs.push(s.object_by_id(DataObjectId::from_capnp(&co))?);
+                    let object_id = object_ids.resolve(DataObjectId::from_capnp(&co))?;
+                    outputs.push(s.object_by_id(object_id)?);
                 }
                 let t = s.add_task(
                     &session,
@@ -176,11 +386,12 @@ impl client_service::Server for ClientServiceImpl {
             }
             debug!("New tasks: {:?}", created_tasks);
             debug!("New objects: {:?}", created_objects);
+            let descriptors = created_tasks
+                .iter()
+                .map(|t| TaskDescriptor::from(&t.get(), s.redaction()))
+                .collect();
             s.logger.add_client_submit_event(
-                created_tasks
-                    .iter()
-                    .map(|t| TaskDescriptor::from(&t.get()))
-                    .collect(),
+                descriptors,
                 created_objects
                     .iter()
                     .map(|o| ObjectDescriptor::from(&o.get()))
@@ -189,15 +400,55 @@ impl client_service::Server for ClientServiceImpl {
             // verify submit integrity
             s.verify_submit(&created_tasks, &created_objects)
         })();
-        if res.is_err() {
-            debug!("Error: {:?}", res);
+        if let Err(e) = res {
+            debug!("Error: {:?}", e);
             for t in created_tasks {
                 pry!(s.remove_task(&t));
             }
             for o in created_objects {
                 pry!(s.remove_object(&o));
             }
-            pry!(res);
+            if let ErrorKind::IdCollision(ref tasks, ref objects) = *e.kind() {
+                info!("Submit rejected: {}", e);
+                let mut collision = results.get().init_id_collision();
+                {
+                    let mut list = collision.borrow().init_tasks(tasks.len() as u32);
+                    for (i, id) in tasks.iter().enumerate() {
+                        id.to_capnp(&mut list.borrow().get(i as u32));
+                    }
+                }
+                let mut list = collision.init_objects(objects.len() as u32);
+                for (i, id) in objects.iter().enumerate() {
+                    id.to_capnp(&mut list.borrow().get(i as u32));
+                }
+                return Promise::ok(());
+            }
+            return Promise::err(e.into());
+        }
+        if params.get_hold() {
+            s.hold_tasks(&created_tasks);
+        }
+        s.predict_durations(&created_tasks);
+        update_ranks(&created_tasks);
+        if let Err(e) = s.sample_tasks(&created_tasks) {
+            warn!("Sampling of submitted tasks failed: {}", e);
+        }
+        let mut ok = results.get().init_ok();
+        {
+            let mut list = ok.borrow().init_tasks(task_ids.len() as u32);
+            for (i, (provisional, id)) in task_ids.iter().enumerate() {
+                let mut assigned = list.borrow().get(i as u32);
+                assigned.set_session_id(provisional.get_session_id());
+                assigned.set_provisional(provisional.get_id());
+                assigned.set_id(id.get_id());
+            }
+        }
+        let mut list = ok.init_objects(object_ids.len() as u32);
+        for (i, (provisional, id)) in object_ids.iter().enumerate() {
+            let mut assigned = list.borrow().get(i as u32);
+            assigned.set_session_id(provisional.get_session_id());
+            assigned.set_provisional(provisional.get_id());
+            assigned.set_id(id.get_id());
         }
         Promise::ok(())
     }
@@ -207,6 +458,7 @@ impl client_service::Server for ClientServiceImpl {
         _params: client_service::GetDataStoreParams,
         mut results: client_service::GetDataStoreResults,
     ) -> Promise<(), ::capnp::Error> {
+        pry!(self.identity.check_role(Role::Readonly));
         debug!("server data store requested from client");
         let datastore = ::datastore_capnp::data_store::ToClient::new(ClientDataStoreImpl::new(
             &self.state,
@@ -220,6 +472,7 @@ impl client_service::Server for ClientServiceImpl {
         params: client_service::WaitParams,
         mut result: client_service::WaitResults,
     ) -> Promise<(), ::capnp::Error> {
+        pry!(self.identity.check_role(Role::Readonly));
         // Set error from session to result
         fn set_error(result: &mut ::common_capnp::unit_result::Builder, error: &SessionError) {
             error.to_capnp(&mut result.borrow().init_error());
@@ -317,6 +570,7 @@ impl client_service::Server for ClientServiceImpl {
         params: client_service::WaitSomeParams,
         _results: client_service::WaitSomeResults,
     ) -> Promise<(), ::capnp::Error> {
+        pry!(self.identity.check_role(Role::Readonly));
         let params = pry!(params.get());
         let task_ids = pry!(params.get_task_ids());
         let object_ids = pry!(params.get_object_ids());
@@ -335,6 +589,7 @@ impl client_service::Server for ClientServiceImpl {
         params: client_service::UnkeepParams,
         mut results: client_service::UnkeepResults,
     ) -> Promise<(), ::capnp::Error> {
+        pry!(self.identity.check_role(Role::User));
         let mut s = self.state.get_mut();
         let params = pry!(params.get());
         let object_ids = pry!(params.get_object_ids());
@@ -364,11 +619,442 @@ impl client_service::Server for ClientServiceImpl {
         Promise::ok(())
     }
 
+    fn publish(
+        &mut self,
+        params: client_service::PublishParams,
+        _: client_service::PublishResults,
+    ) -> Promise<(), ::capnp::Error> {
+        pry!(self.identity.check_role(Role::User));
+        let params = pry!(params.get());
+        let id = DataObjectId::from_capnp(&pry!(params.get_object_id()));
+        let name = pry!(params.get_name());
+        let namespace = &self.identity.name;
+        debug!("Client publishes object {} as '{}/{}'", id, namespace, name);
+        let mut s = self.state.get_mut();
+        let object = pry!(s.object_by_id_check_session(id));
+        pry!(s.publish_object(&object, namespace, name));
+        Promise::ok(())
+    }
+
+    fn unpublish(
+        &mut self,
+        params: client_service::UnpublishParams,
+        _: client_service::UnpublishResults,
+    ) -> Promise<(), ::capnp::Error> {
+        pry!(self.identity.check_role(Role::User));
+        let params = pry!(params.get());
+        let name = pry!(params.get_name());
+        let namespace = &self.identity.name;
+        debug!("Client unpublishes '{}/{}'", namespace, name);
+        pry!(self.state.get_mut().unpublish_object(namespace, name));
+        Promise::ok(())
+    }
+
+    fn get_published(
+        &mut self,
+        params: client_service::GetPublishedParams,
+        mut results: client_service::GetPublishedResults,
+    ) -> Promise<(), ::capnp::Error> {
+        pry!(self.identity.check_role(Role::Readonly));
+        let params = pry!(params.get());
+        let name = pry!(params.get_name());
+        let object = pry!(
+            self.state
+                .get()
+                .published_object(&self.identity.name, name)
+        );
+        let o = object.get();
+        let mut results = results.get();
+        o.id.to_capnp(&mut results.borrow().get_object_id().unwrap());
+        results.set_label(&o.label);
+        results.set_data_type(o.data_type.to_capnp());
+        o.attributes
+            .to_capnp(&mut results.get_attributes().unwrap());
+        Promise::ok(())
+    }
+
+    fn list_kept(
+        &mut self,
+        params: client_service::ListKeptParams,
+        mut results: client_service::ListKeptResults,
+    ) -> Promise<(), ::capnp::Error> {
+        pry!(self.identity.check_role(Role::Readonly));
+        let params = pry!(params.get());
+        let filter = pry!(kept_filter_from_capnp(&pry!(params.get_filter())));
+        let s = self.state.get();
+        let session = pry!(s.session_by_id(params.get_session_id()));
+        pry!(self.check_owner(&session));
+        let objects = s.kept_objects(&session, &filter);
+        debug!(
+            "Client asked for kept objects of session {}, found {}",
+            session.get_id(),
+            objects.len()
+        );
+
+        let mut list = results.get().init_objects(objects.len() as u32);
+        for (i, oref) in objects.iter().enumerate() {
+            let mut item = list.borrow().get(i as u32);
+            let o = oref.get();
+            o.id.to_capnp(&mut item.borrow().get_id().unwrap());
+            item.set_label(&o.label);
+            item.set_state(o.state);
+            o.size.map(|size| item.set_size(size as i64));
+            let age = o.created.elapsed();
+            item.set_age(age.as_secs() as f64 + age.subsec_nanos() as f64 * 1e-9);
+            let mut placement = item.init_placement(o.located.len() as u32);
+            for (j, w) in o.located.iter().enumerate() {
+                w.get_id().to_capnp(&mut placement.borrow().get(j as u32));
+            }
+        }
+        Promise::ok(())
+    }
+
+    fn unkeep_matching(
+        &mut self,
+        params: client_service::UnkeepMatchingParams,
+        mut results: client_service::UnkeepMatchingResults,
+    ) -> Promise<(), ::capnp::Error> {
+        pry!(self.identity.check_role(Role::User));
+        let params = pry!(params.get());
+        let filter = pry!(kept_filter_from_capnp(&pry!(params.get_filter())));
+        let mut s = self.state.get_mut();
+        let session = pry!(s.session_by_id(params.get_session_id()));
+        pry!(self.check_owner(&session));
+        let objects = s.kept_objects(&session, &filter);
+        debug!(
+            "Client unkeeps {} objects of session {} by filter {:?}",
+            objects.len(),
+            session.get_id(),
+            filter
+        );
+
+        for o in objects.iter() {
+            s.unkeep_object(&o);
+        }
+        s.logger
+            .add_client_unkeep_event(objects.iter().map(|o| o.get().id).collect());
+
+        let mut ids = results.get().init_object_ids(objects.len() as u32);
+        for (i, o) in objects.iter().enumerate() {
+            o.get().id.to_capnp(&mut ids.borrow().get(i as u32));
+        }
+        Promise::ok(())
+    }
+
+    fn kept_size(
+        &mut self,
+        params: client_service::KeptSizeParams,
+        mut results: client_service::KeptSizeResults,
+    ) -> Promise<(), ::capnp::Error> {
+        pry!(self.identity.check_role(Role::Readonly));
+        let params = pry!(params.get());
+        let s = self.state.get();
+        let session = pry!(s.session_by_id(params.get_session_id()));
+        pry!(self.check_owner(&session));
+        let objects = s.kept_objects(&session, &Default::default());
+        let size: usize = objects.iter().map(|o| o.get().size.unwrap_or(0)).sum();
+        let mut results = results.get();
+        results.set_count(objects.len() as u64);
+        results.set_size(size as u64);
+        Promise::ok(())
+    }
+
+    fn set_session_options(
+        &mut self,
+        params: client_service::SetSessionOptionsParams,
+        _: client_service::SetSessionOptionsResults,
+    ) -> Promise<(), ::capnp::Error> {
+        pry!(self.identity.check_role(Role::User));
+        let params = pry!(params.get());
+        let options = pry!(params.get_options());
+        let retention = pry!(options.get_output_retention());
+        let max_size = match options.get_output_max_size() {
+            0 => None,
+            n => Some(n),
+        };
+        let output_policy = if retention.is_empty() && max_size.is_none() {
+            None
+        } else {
+            let retention = match retention {
+                "" => Retention::Discard,
+                r => match Retention::parse(r) {
+                    Ok(retention) => retention,
+                    Err(e) => {
+                        return Promise::err(::capnp::Error::failed(e.description().to_string()))
+                    }
+                },
+            };
+            Some(OutputPolicy {
+                retention,
+                max_size,
+            })
+        };
+        let mut s = self.state.get_mut();
+        let session = pry!(s.session_by_id(params.get_session_id()));
+        let max_running_tasks = match options.get_max_running_tasks() {
+            0 => None,
+            n => Some(n as usize),
+        };
+        debug!(
+            "Session {} max running tasks set to {:?}",
+            session.get_id(),
+            max_running_tasks
+        );
+        session.get_mut().max_running_tasks = max_running_tasks;
+        session.get_mut().detach_on_disconnect = options.get_detach_on_disconnect();
+        session.get_mut().keep_failed = options.get_keep_failed();
+        session.get_mut().cost_weight = options.get_cost_weight().max(0f64);
+        session.get_mut().sample_rate = options.get_sample_rate().max(0f64).min(1f64);
+        session.get_mut().output_policy = output_policy;
+        let ttl = options.get_ttl();
+        session.get_mut().expires = if ttl > 0f64 {
+            debug!("Session {} expires in {}s", session.get_id(), ttl);
+            Some(Instant::now() + Duration::from_millis((ttl * 1000f64) as u64))
+        } else {
+            None
+        };
+        let retention = options.get_expired_retention();
+        session.get_mut().expired_retention = if retention > 0f64 {
+            Some(Duration::from_millis((retention * 1000f64) as u64))
+        } else {
+            None
+        };
+        // Tasks held back by the old limit may be schedulable now
+        s.updates.sessions.insert(session);
+        Promise::ok(())
+    }
+
+    fn get_group_status(
+        &mut self,
+        params: client_service::GetGroupStatusParams,
+        mut results: client_service::GetGroupStatusResults,
+    ) -> Promise<(), ::capnp::Error> {
+        pry!(self.identity.check_role(Role::Readonly));
+        let params = pry!(params.get());
+        let name = pry!(params.get_name());
+        let s = self.state.get();
+        let session = pry!(s.session_by_id(params.get_session_id()));
+        let session = session.get();
+        let group = match session.groups.get(name) {
+            Some(group) => group,
+            None => return Promise::err(::capnp::Error::failed(format!(
+                "Task group {:?} not found",
+                name
+            ))),
+        };
+        let mut results = results.get();
+        results.set_total(group.total as u64);
+        results.set_finished(group.finished as u64);
+        results.set_running(group.running() as u64);
+        let mut failed = results.init_failed(group.failed.len() as u32);
+        for (i, task_id) in group.failed.iter().enumerate() {
+            task_id.to_capnp(&mut failed.borrow().get(i as u32));
+        }
+        Promise::ok(())
+    }
+
+    fn get_session_stats(
+        &mut self,
+        params: client_service::GetSessionStatsParams,
+        mut results: client_service::GetSessionStatsResults,
+    ) -> Promise<(), ::capnp::Error> {
+        pry!(self.identity.check_role(Role::Readonly));
+        let params = pry!(params.get());
+        let s = self.state.get();
+        let session = pry!(s.session_by_id(params.get_session_id()));
+        let session = session.get();
+        let stats = &session.stats;
+        let mut results = results.get();
+        results.set_waiting(stats.waiting as u64);
+        results.set_ready(stats.ready as u64);
+        results.set_running(stats.running as u64);
+        results.set_finished(stats.finished as u64);
+        results.set_failed(stats.failed as u64);
+        results.set_cpu_time(stats.cpu_time);
+        results.set_produced_bytes(stats.produced_bytes);
+        results.set_transferred_bytes(stats.transferred_bytes);
+        results.set_sampled(stats.sampled as u64);
+        {
+            let mut mismatches = results
+                .borrow()
+                .init_sample_mismatches(stats.sample_mismatches.len() as u32);
+            for (i, task_id) in stats.sample_mismatches.iter().enumerate() {
+                task_id.to_capnp(&mut mismatches.borrow().get(i as u32));
+            }
+        }
+        let mut failures = results.init_failures(stats.failures.len() as u32);
+        for (i, (kind, count)) in stats.failures.iter().enumerate() {
+            let mut failure = failures.borrow().get(i as u32);
+            failure.set_kind(kind);
+            failure.set_count(*count as u64);
+        }
+        Promise::ok(())
+    }
+
+    fn export_session(
+        &mut self,
+        params: client_service::ExportSessionParams,
+        mut results: client_service::ExportSessionResults,
+    ) -> Promise<(), ::capnp::Error> {
+        pry!(self.identity.check_role(Role::Readonly));
+        let params = pry!(params.get());
+        let s = self.state.get();
+        let session = pry!(s.session_by_id(params.get_session_id()));
+        let bundle = pry!(SessionBundle::new(&session.get(), s.redaction()));
+        let items = bundle.tasks.len() + bundle.objects.len();
+        Promise::from_future(
+            s.serializer()
+                .to_json(bundle, items)
+                .map(move |bundle| results.get().set_bundle(&bundle))
+                .map_err(::capnp::Error::from),
+        )
+    }
+
+    fn flush_session(
+        &mut self,
+        params: client_service::FlushSessionParams,
+        _: client_service::FlushSessionResults,
+    ) -> Promise<(), ::capnp::Error> {
+        pry!(self.identity.check_role(Role::User));
+        let params = pry!(params.get());
+        let mut s = self.state.get_mut();
+        let session = pry!(s.session_by_id(params.get_session_id()));
+        s.flush_session(&session);
+        Promise::ok(())
+    }
+
+    fn stop_scheduling(
+        &mut self,
+        _: client_service::StopSchedulingParams,
+        mut results: client_service::StopSchedulingResults,
+    ) -> Promise<(), ::capnp::Error> {
+        pry!(self.identity.check_role(Role::Admin));
+        info!("Scheduling stopped by client '{}'", self.identity.name);
+        let sessions = pry!(self.state.get_mut().stop_scheduling());
+        results.get().set_sessions(sessions);
+        Promise::ok(())
+    }
+
+    fn stop_workers(
+        &mut self,
+        _: client_service::StopWorkersParams,
+        mut results: client_service::StopWorkersResults,
+    ) -> Promise<(), ::capnp::Error> {
+        pry!(self.identity.check_role(Role::Admin));
+        let workers = self.state.get_mut().stop_workers();
+        info!(
+            "Client '{}' asked {} workers to terminate",
+            self.identity.name, workers
+        );
+        results.get().set_workers(workers);
+        Promise::ok(())
+    }
+
+    fn release_workers(
+        &mut self,
+        params: client_service::ReleaseWorkersParams,
+        mut results: client_service::ReleaseWorkersResults,
+    ) -> Promise<(), ::capnp::Error> {
+        pry!(self.identity.check_role(Role::Admin));
+        let count = pry!(params.get()).get_count();
+        let workers = self.state.get_mut().release_workers(count as usize);
+        info!(
+            "Client '{}' released {} workers to the broker",
+            self.identity.name, workers
+        );
+        results.get().set_workers(workers);
+        Promise::ok(())
+    }
+
+    fn get_shard_load(
+        &mut self,
+        _: client_service::GetShardLoadParams,
+        mut results: client_service::GetShardLoadResults,
+    ) -> Promise<(), ::capnp::Error> {
+        pry!(self.identity.check_role(Role::Readonly));
+        let load = self.state.get().shard_load();
+        let mut results = results.get();
+        results.set_workers(load.workers);
+        results.set_draining(load.draining);
+        results.set_ready_tasks(load.ready_tasks);
+        results.set_running_tasks(load.running_tasks);
+        Promise::ok(())
+    }
+
+    fn watch_session(
+        &mut self,
+        params: client_service::WatchSessionParams,
+        mut results: client_service::WatchSessionResults,
+    ) -> Promise<(), ::capnp::Error> {
+        pry!(self.identity.check_role(Role::Readonly));
+        let params = pry!(params.get());
+        let session_id = params.get_session_id();
+        let cursor = params.get_cursor();
+        let session = pry!(self.state.get().session_by_id(session_id));
+        let changed = {
+            let s = session.get();
+            cursor == 0 || s.revision != cursor || s.error.is_some()
+        };
+        if changed {
+            session_changes_to_capnp(&session, cursor, &mut results.get());
+            return Promise::ok(());
+        }
+        let state = self.state.clone();
+        let future = self.state
+            .get()
+            .watch_session(&session, Duration::from_secs(WATCH_SESSION_TIMEOUT));
+        Promise::from_future(future.then(
+            move |_| -> ::std::result::Result<(), ::capnp::Error> {
+                // The session may have been closed meanwhile
+                let session = state.get().session_by_id(session_id)?;
+                session_changes_to_capnp(&session, cursor, &mut results.get());
+                Ok(())
+            },
+        ))
+    }
+
+    fn wait_group(
+        &mut self,
+        params: client_service::WaitGroupParams,
+        mut result: client_service::WaitGroupResults,
+    ) -> Promise<(), ::capnp::Error> {
+        pry!(self.identity.check_role(Role::Readonly));
+        let params = pry!(params.get());
+        let name = pry!(params.get_name());
+        let s = self.state.get();
+        let session = pry!(s.session_by_id(params.get_session_id()));
+        if let Some(ref e) = *session.get().get_error() {
+            e.to_capnp(&mut result.get().init_error());
+            return Promise::ok(());
+        }
+        let task_futures: Vec<_> = match session.get().groups.get(name) {
+            Some(group) => group.unfinished.iter().map(|t| t.get_mut().wait()).collect(),
+            None => return Promise::err(::capnp::Error::failed(format!(
+                "Task group {:?} not found",
+                name
+            ))),
+        };
+        debug!("Waiting for {} tasks of group {:?}", task_futures.len(), name);
+        Promise::from_future(::futures::future::join_all(task_futures).then(move |r| {
+            match r {
+                Ok(_) => result.get().set_ok(()),
+                Err(_) => session
+                    .get()
+                    .get_error()
+                    .as_ref()
+                    .unwrap()
+                    .to_capnp(&mut result.get().init_error()),
+            };
+            Ok(())
+        }))
+    }
+
     fn get_state(
         &mut self,
         params: client_service::GetStateParams,
         mut results: client_service::GetStateResults,
     ) -> Promise<(), ::capnp::Error> {
+        pry!(self.identity.check_role(Role::Readonly));
         let params = pry!(params.get());
         let task_ids = pry!(params.get_task_ids());
         let object_ids = pry!(params.get_object_ids());
@@ -431,4 +1117,205 @@ impl client_service::Server for ClientServiceImpl {
         results.get_state().unwrap().set_ok(());
         Promise::ok(())
     }
+
+    fn get_graph_snapshot(
+        &mut self,
+        params: client_service::GetGraphSnapshotParams,
+        mut results: client_service::GetGraphSnapshotResults,
+    ) -> Promise<(), ::capnp::Error> {
+        pry!(self.identity.check_role(Role::Readonly));
+        let params = pry!(params.get());
+        let s = self.state.get();
+        let session_id = match params.get_session_id() {
+            -1 => None,
+            id => {
+                pry!(s.session_by_id(id));
+                Some(id)
+            }
+        };
+        let snapshot = s.graph_snapshot(session_id);
+        let items = snapshot.tasks.len() + snapshot.objects.len();
+        Promise::from_future(
+            s.serializer()
+                .to_json(snapshot, items)
+                .map(move |snapshot| results.get().set_snapshot(&snapshot))
+                .map_err(::capnp::Error::from),
+        )
+    }
+
+    fn upgrade_workers(
+        &mut self,
+        params: client_service::UpgradeWorkersParams,
+        _: client_service::UpgradeWorkersResults,
+    ) -> Promise<(), ::capnp::Error> {
+        pry!(self.identity.check_role(Role::Admin));
+        let params = pry!(params.get());
+        let image = pry!(params.get_image()).to_string();
+        info!("Upgrade of workers requested by client '{}'", self.identity.name);
+        pry!(
+            self.state
+                .get_mut()
+                .start_upgrade(image, params.get_batch() as usize)
+        );
+        Promise::ok(())
+    }
+
+    fn get_upgrade_status(
+        &mut self,
+        _: client_service::GetUpgradeStatusParams,
+        mut results: client_service::GetUpgradeStatusResults,
+    ) -> Promise<(), ::capnp::Error> {
+        pry!(self.identity.check_role(Role::Readonly));
+        let status = self.state.get().upgrade_status();
+        let mut results = results.get();
+        results.set_image(&status.image);
+        results.set_outdated(status.outdated as u32);
+        results.set_draining(status.draining as u32);
+        results.set_upgraded(status.upgraded as u32);
+        results.set_done(status.done);
+        Promise::ok(())
+    }
+
+    fn set_chaos(
+        &mut self,
+        params: client_service::SetChaosParams,
+        _: client_service::SetChaosResults,
+    ) -> Promise<(), ::capnp::Error> {
+        pry!(self.identity.check_role(Role::Admin));
+        let config = pry!(pry!(params.get()).get_config());
+        let config = pry!(::serde_json::from_str(config).map_err(Error::from));
+        info!("Fault injection set by client '{}'", self.identity.name);
+        let future = pry!(self.state.get_mut().set_chaos(config));
+        Promise::from_future(future.map_err(|e| e.into()))
+    }
+
+    fn invalidate(
+        &mut self,
+        params: client_service::InvalidateParams,
+        mut results: client_service::InvalidateResults,
+    ) -> Promise<(), ::capnp::Error> {
+        pry!(self.identity.check_role(Role::User));
+        let params = pry!(params.get());
+        let object_ids = pry!(params.get_object_ids());
+        let reason = pry!(params.get_reason());
+        let mut s = self.state.get_mut();
+        let objects: Vec<DataObjectRef> = pry!(
+            object_ids
+                .iter()
+                .map(|id| s.object_by_id_check_session(DataObjectId::from_capnp(&id)))
+                .collect()
+        );
+
+        let mut tasks: RcSet<TaskRef> = Default::default();
+        for oref in objects {
+            // The object may be already computed again due to a previous object
+            if oref.get()
+                .producer()
+                .as_ref()
+                .map(|p| tasks.contains(p))
+                .unwrap_or(false)
+            {
+                continue;
+            }
+            tasks.extend(pry!(s.invalidate_object(&oref, reason)));
+        }
+
+        let mut ctasks = results.get().init_task_ids(tasks.len() as u32);
+        for (i, tref) in tasks.iter().enumerate() {
+            tref.get_id().to_capnp(&mut ctasks.borrow().get(i as u32));
+        }
+        Promise::ok(())
+    }
+
+    fn cancel_tasks(
+        &mut self,
+        params: client_service::CancelTasksParams,
+        mut results: client_service::CancelTasksResults,
+    ) -> Promise<(), ::capnp::Error> {
+        pry!(self.identity.check_role(Role::User));
+        let params = pry!(params.get());
+        let tags: Vec<&str> = pry!(pry!(params.get_tags()).iter().collect());
+        let tags: Vec<String> = tags.iter().map(|tag| tag.to_string()).collect();
+        let mut s = self.state.get_mut();
+        let session = pry!(s.session_by_id(params.get_session_id()));
+        let task_ids = pry!(s.cancel_tasks(&session, &tags));
+
+        let mut ctasks = results.get().init_task_ids(task_ids.len() as u32);
+        for (i, id) in task_ids.iter().enumerate() {
+            id.to_capnp(&mut ctasks.borrow().get(i as u32));
+        }
+        Promise::ok(())
+    }
+
+    fn retry_failed(
+        &mut self,
+        params: client_service::RetryFailedParams,
+        mut results: client_service::RetryFailedResults,
+    ) -> Promise<(), ::capnp::Error> {
+        pry!(self.identity.check_role(Role::User));
+        let params = pry!(params.get());
+        let tags: Vec<&str> = pry!(pry!(params.get_tags()).iter().collect());
+        let tags: Vec<String> = tags.iter().map(|tag| tag.to_string()).collect();
+        let mut s = self.state.get_mut();
+        if s.is_stopping() {
+            return Promise::err(::capnp::Error::failed(
+                "Server is shutting down".to_string(),
+            ));
+        }
+        let session = pry!(s.session_by_id(params.get_session_id()));
+        let task_ids = pry!(s.retry_failed(&session, &tags));
+
+        let mut ctasks = results.get().init_task_ids(task_ids.len() as u32);
+        for (i, id) in task_ids.iter().enumerate() {
+            id.to_capnp(&mut ctasks.borrow().get(i as u32));
+        }
+        Promise::ok(())
+    }
+
+    fn archive_kept(
+        &mut self,
+        params: client_service::ArchiveKeptParams,
+        mut results: client_service::ArchiveKeptResults,
+    ) -> Promise<(), ::capnp::Error> {
+        pry!(self.identity.check_role(Role::User));
+        let params = pry!(params.get());
+        let filter = pry!(kept_filter_from_capnp(&pry!(params.get_filter())));
+        let mut s = self.state.get_mut();
+        if s.is_stopping() {
+            return Promise::err(::capnp::Error::failed(
+                "Server is shutting down".to_string(),
+            ));
+        }
+        let session = pry!(s.session_by_id(params.get_session_id()));
+        pry!(self.check_owner(&session));
+        let (object, count) = pry!(s.archive_kept(&session, &filter, params.get_min_id()));
+        debug!(
+            "Client archives {} kept objects of session {} into {}",
+            count,
+            session.get_id(),
+            object.get_id()
+        );
+        let mut results = results.get();
+        object
+            .get_id()
+            .to_capnp(&mut results.borrow().get_object_id().unwrap());
+        results.set_count(count as u32);
+        Promise::ok(())
+    }
+
+    fn configure_scheduler(
+        &mut self,
+        params: client_service::ConfigureSchedulerParams,
+        mut results: client_service::ConfigureSchedulerResults,
+    ) -> Promise<(), ::capnp::Error> {
+        pry!(self.identity.check_role(Role::Admin));
+        let config = pry!(pry!(params.get()).get_config());
+        let config: SchedulerConfig = pry!(::serde_json::from_str(config).map_err(Error::from));
+        info!("Scheduler configured by client '{}'", self.identity.name);
+        let status = pry!(self.state.get_mut().configure_scheduler(config));
+        results
+            .get()
+            .set_status(&::serde_json::to_string(&status).unwrap());
+        Promise::ok(())
+    }
 }
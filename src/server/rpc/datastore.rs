@@ -40,6 +40,7 @@ impl data_store::Server for ClientDataStoreImpl {
             Err(e) => return Promise::err(::capnp::Error::failed(e.description().to_string())),
         };
         let offset = params.get_offset();
+        let length = params.get_length();
         if object.get().state == DataObjectState::Removed {
             return Promise::err(::capnp::Error::failed(format!(
                 "create_reader on removed object {:?}",
@@ -76,6 +77,21 @@ impl data_store::Server for ClientDataStoreImpl {
                             .to_capnp(&mut results.get().init_error());
                         return future::Either::A(future::result(Ok(())));
                     }
+                    if obj.data.is_some() {
+                        // Small object sent inline by its worker, the server holds its data
+                        let size = obj.size.map(|s| s as i64).unwrap_or(-1i64);
+                        let reader = reader::ToClient::new(LocalReaderImpl::new(
+                            object4.clone(),
+                            offset as usize,
+                            length as usize,
+                        )).from_server::<::capnp_rpc::Server>();
+                        let mut results = results.get();
+                        results.set_reader(reader);
+                        results.set_size(size);
+                        results.set_data_type(obj.data_type.to_capnp());
+                        results.set_ok(());
+                        return future::Either::A(future::result(Ok(())));
+                    }
 
                     future::Either::B(
                         future::lazy(move || {
@@ -85,9 +101,6 @@ impl data_store::Server for ClientDataStoreImpl {
                                 DataObjectState::Finished,
                                 "triggered finish hook on unfinished object"
                             );
-                            if obj.data.is_some() {
-                                unimplemented!();
-                            }
                             let worker = obj.located.iter().next().unwrap().clone();
                             let worker2 = worker.clone();
                             let handle = state.get().handle().clone();
@@ -103,6 +116,7 @@ impl data_store::Server for ClientDataStoreImpl {
                             {
                                 let mut params = req.get();
                                 params.set_offset(offset);
+                                params.set_length(length);
                                 id.to_capnp(&mut params.get_id().unwrap());
                             }
                             req.send().promise.map_err(|e| e.into())
@@ -120,6 +134,82 @@ impl data_store::Server for ClientDataStoreImpl {
                 .map_err(|e| panic!("Fetch failed: {:?}", e)),
         )
     }
+
+    fn get_info(
+        &mut self,
+        params: data_store::GetInfoParams,
+        mut results: data_store::GetInfoResults,
+    ) -> Promise<(), ::capnp::Error> {
+        let params = pry!(params.get());
+        let id = DataObjectId::from_capnp(&pry!(params.get_id()));
+        let checksum = params.get_checksum();
+        let object = match self.state.get().object_by_id_check_session(id) {
+            Ok(t) => t,
+            Err(Error(ErrorKind::SessionErr(ref e), _)) => {
+                e.to_capnp(&mut results.get().init_error());
+                return Promise::ok(());
+            }
+            Err(e) => return Promise::err(::capnp::Error::failed(e.description().to_string())),
+        };
+        if object.get().state == DataObjectState::Removed {
+            results.get().set_removed(());
+            return Promise::ok(());
+        }
+
+        let state = self.state.clone();
+        let session = object.get().session.clone();
+        let finished = object.get_mut().wait();
+        Promise::from_future(finished.then(
+            move |r| -> Box<Future<Item = (), Error = ::capnp::Error>> {
+                let obj = object.get();
+                if r.is_err() || obj.state == DataObjectState::Removed {
+                    match *session.get().get_error() {
+                        Some(ref e) => e.to_capnp(&mut results.get().init_error()),
+                        None => results.get().set_removed(()),
+                    }
+                    return Box::new(future::ok(()));
+                }
+                {
+                    let mut info = results.get();
+                    info.set_size(obj.size.map(|s| s as i64).unwrap_or(-1i64));
+                    info.set_data_type(obj.data_type.to_capnp());
+                    info.set_content_type(&obj.content_type().unwrap_or_default());
+                    info.set_ok(());
+                }
+                if !checksum {
+                    return Box::new(future::ok(()));
+                }
+                if let Some(ref data) = obj.data {
+                    let mut crc = ::flate2::Crc::new();
+                    crc.update(data);
+                    results
+                        .get()
+                        .set_checksum(&format!("crc32:{:08x}", crc.sum()));
+                    return Box::new(future::ok(()));
+                }
+                // Checksum is computed by a worker that holds the data
+                let worker = obj.located.iter().next().unwrap().clone();
+                let worker2 = worker.clone();
+                let handle = state.get().handle().clone();
+                let future = worker.get_mut().wait_for_datastore(&worker, &handle);
+                Box::new(
+                    future
+                        .map_err(::capnp::Error::from)
+                        .and_then(move |()| {
+                            let mut req = worker2.get().get_datastore().get_info_request();
+                            id.to_capnp(&mut req.get().init_id());
+                            req.get().set_checksum(true);
+                            req.send().promise
+                        })
+                        .and_then(move |response| {
+                            let response = pry!(response.get());
+                            results.get().set_checksum(pry!(response.get_checksum()));
+                            Promise::ok(())
+                        }),
+                )
+            },
+        ))
+    }
 }
 
 // Datastore provided for workers
@@ -158,8 +248,9 @@ impl data_store::Server for WorkerDataStoreImpl {
         let size = object.get().size.map(|s| s as i64).unwrap_or(-1i64);
         let data_type = object.get().data_type;
 
-        let offset = params.get_offset();
-        let reader = reader::ToClient::new(LocalReaderImpl::new(object, offset as usize))
+        let offset = params.get_offset() as usize;
+        let length = params.get_length() as usize;
+        let reader = reader::ToClient::new(LocalReaderImpl::new(object, offset, length))
             .from_server::<::capnp_rpc::Server>();
 
         let mut results = results.get();
@@ -172,7 +263,7 @@ impl data_store::Server for WorkerDataStoreImpl {
 }
 
 /// The implementation of reader that reads object
-/// that is localy stored in server
+/// that is localy stored in server (at most `length` bytes, 0 = to the end)
 pub struct LocalReaderImpl {
     object: DataObjectRef,
     offset: usize,
@@ -180,8 +271,11 @@ pub struct LocalReaderImpl {
 }
 
 impl LocalReaderImpl {
-    pub fn new(object: DataObjectRef, offset: usize) -> Self {
-        let size = object.get().data.as_ref().unwrap().len();
+    pub fn new(object: DataObjectRef, offset: usize, length: usize) -> Self {
+        let mut size = object.get().data.as_ref().unwrap().len();
+        if length > 0 && offset + length < size {
+            size = offset + length;
+        }
         Self {
             object,
             offset,
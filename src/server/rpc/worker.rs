@@ -1,12 +1,12 @@
-use common::convert::FromCapnp;
+use common::convert::{FromCapnp, ToCapnp};
 use common::Attributes;
 use common::id::{DataObjectId, TaskId};
+use common::events::Event;
 use server::state::StateRef;
 use server::graph::{Worker, WorkerRef};
 use worker_capnp::worker_upstream;
 use capnp::capability::Promise;
 use server::rpc::WorkerDataStoreImpl;
-use chrono::TimeZone;
 
 pub struct WorkerUpstreamImpl {
     state: StateRef,
@@ -26,8 +26,10 @@ impl Drop for WorkerUpstreamImpl {
     fn drop(&mut self) {
         error!("Connection to worker {} lost", self.worker.get_id());
         let mut s = self.state.get_mut();
-        s.remove_worker(&self.worker)
-            .expect("dropping worker upstream");
+        // Panicking in drop would abort the server, the error is only logged
+        if let Err(e) = s.remove_worker(&self.worker) {
+            error!("Removing worker {} failed: {}", self.worker.get_id(), e);
+        }
     }
 }
 
@@ -68,7 +70,12 @@ impl worker_upstream::Server for WorkerUpstreamImpl {
                 let object = pry!(state.object_by_id(id));
                 let size = obj_update.get_size() as usize;
                 let attributes = Attributes::from_capnp(&obj_update.get_attributes().unwrap());
-                obj_updates.push((object, pry!(obj_update.get_state()), size, attributes));
+                let data = if obj_update.get_inlined() {
+                    Some(pry!(obj_update.get_inline_data()).to_vec())
+                } else {
+                    None
+                };
+                obj_updates.push((object, pry!(obj_update.get_state()), size, attributes, data));
             }
 
             for task_update in pry!(update.get_tasks()).iter() {
@@ -107,15 +114,66 @@ impl worker_upstream::Server for WorkerUpstreamImpl {
         let mut state = self.state.get_mut();
 
         for cevent in cevents.iter() {
-            let event = cevent.get_event().unwrap().to_string();
-            let timestamp = pry!(cevent.get_timestamp());
-            let seconds = timestamp.get_seconds() as i64;
-            let subsec_nanos = timestamp.get_subsec_nanos();
-            state.logger.add_event_with_timestamp(
-                ::serde_json::from_str(&event).unwrap(),
-                ::chrono::Utc.timestamp(seconds, subsec_nanos),
-            );
+            let (event, time) = match Event::from_capnp(&cevent) {
+                Ok(result) => result,
+                Err(e) => {
+                    warn!("Invalid event from worker {}: {}", self.worker.get_id(), e);
+                    continue;
+                }
+            };
+            // Shipped logs of workers appear also in the log of server
+            if let Event::WorkerLog(ref e) = event {
+                if e.level == "ERROR" {
+                    error!("Worker {}: {}", e.worker, e.message);
+                } else {
+                    warn!("Worker {}: {}", e.worker, e.message);
+                }
+            }
+            state.logger.add_event_with_timestamp(event, time);
+        }
+        Promise::ok(())
+    }
+
+    fn restore_objects(
+        &mut self,
+        params: worker_upstream::RestoreObjectsParams,
+        mut results: worker_upstream::RestoreObjectsResults,
+    ) -> Promise<(), ::capnp::Error> {
+        let params = pry!(params.get());
+        let mut objects = Vec::new();
+        for object in pry!(params.get_objects()).iter() {
+            objects.push((
+                DataObjectId::from_capnp(&pry!(object.get_id())),
+                object.get_size() as usize,
+            ));
         }
+        let accepted = self.state
+            .get_mut()
+            .restore_worker_objects(&self.worker, objects);
+
+        let mut result_ids = results.get().init_accepted(accepted.len() as u32);
+        for (i, id) in accepted.iter().enumerate() {
+            id.to_capnp(&mut result_ids.borrow().get(i as u32));
+        }
+        Promise::ok(())
+    }
+
+    fn drain(
+        &mut self,
+        _: worker_upstream::DrainParams,
+        _: worker_upstream::DrainResults,
+    ) -> Promise<(), ::capnp::Error> {
+        self.state.get_mut().drain_worker(&self.worker);
+        Promise::ok(())
+    }
+
+    fn drain_status(
+        &mut self,
+        _: worker_upstream::DrainStatusParams,
+        mut results: worker_upstream::DrainStatusResults,
+    ) -> Promise<(), ::capnp::Error> {
+        let done = self.state.get_mut().drain_status(&self.worker);
+        results.get().set_done(done);
         Promise::ok(())
     }
 }
@@ -1,7 +1,16 @@
-use std::collections::hash_map::HashMap;
+use std::collections::hash_map::{DefaultHasher, HashMap};
+use std::collections::VecDeque;
 use std::clone::Clone;
-use super::graph::{DataObjectRef, Graph, TaskRef, TaskState, WorkerRef};
+use std::cmp::Reverse;
+use std::hash::{Hash, Hasher};
+use std::time::Duration;
+use super::graph::{DataObjectRef, Graph, Task, TaskRef, TaskState, Worker, WorkerRef};
 use common::RcSet;
+use common::capabilities;
+use common::resources::Resources;
+use common::units::deserialize_duration_opt;
+use common::id::{TaskId, WorkerId};
+use errors::Result;
 use server::graph::SessionRef;
 
 #[derive(Default, Clone, Debug)]
@@ -10,6 +19,9 @@ pub struct UpdatedOut {
     pub(in super::super) tasks: RcSet<TaskRef>,
     /// Worker-DataObject updated pairs, grouped by worker
     pub(in super::super) objects: HashMap<WorkerRef, RcSet<DataObjectRef>>,
+    /// Tasks unscheduled to make room for tasks with higher priority, the assigned ones
+    /// have to be stopped
+    pub(in super::super) preempted: RcSet<TaskRef>,
 }
 
 #[derive(Default, Clone, Debug)]
@@ -26,12 +38,14 @@ pub struct UpdatedIn {
     /// and the server assigning Object to Worker. Scheduler-requested operations
     /// (unscheduled already Assigned or Finished object) are not included.
     pub(in super::super) objects: HashMap<DataObjectRef, RcSet<WorkerRef>>,
+    /// Sessions with changed options.
+    pub(in super::super) sessions: RcSet<SessionRef>,
 }
 
 impl UpdatedIn {
     pub fn is_empty(&self) -> bool {
         self.tasks.is_empty() && self.objects.is_empty() && self.new_tasks.is_empty()
-            && self.new_objects.is_empty()
+            && self.new_objects.is_empty() && self.sessions.is_empty()
     }
 
     pub fn clear(&mut self) {
@@ -39,12 +53,29 @@ impl UpdatedIn {
         self.new_objects = Default::default();
         self.tasks.clear();
         self.objects.clear();
+        self.sessions.clear();
     }
 
     pub fn remove_task(&mut self, task_ref: &TaskRef) {
         self.new_tasks.remove(task_ref);
         self.tasks.remove(task_ref);
     }
+
+    pub fn remove_object(&mut self, object_ref: &DataObjectRef) {
+        self.new_objects.remove(object_ref);
+        self.objects.remove(object_ref);
+    }
+}
+
+/// Tasks moved by the rebalancing pass
+#[derive(Default, Clone, Debug)]
+pub struct Rebalanced {
+    /// Ready tasks (not assigned yet) scheduled on other workers, in the form returned
+    /// by `schedule`
+    pub(in super::super) moved: UpdatedOut,
+    /// Assigned tasks that have not started, to be pulled back from the workers (the keys)
+    /// and placed on the given idle workers
+    pub(in super::super) pull_back: HashMap<WorkerRef, Vec<(TaskRef, WorkerRef)>>,
 }
 
 /// Scheduler interface. The Extra types are the types of a scheduler-specific attribute
@@ -60,9 +91,489 @@ impl UpdatedIn {
     fn schedule(&mut self, graph: &mut Graph, updated: &UpdatedIn) -> UpdatedOut;
 }*/
 
-#[derive(Default, Clone, Debug)]
+/// How many scheduling decisions are remembered for explanation
+const MAX_DECISIONS: usize = 10000;
+
+/// Evaluation of a worker as a candidate for a task
+#[derive(Clone, Debug, Serialize)]
+pub struct Candidate {
+    pub worker: WorkerId,
+    /// Final score, the worker with the highest score among feasible candidates wins
+    pub score: i64,
+    /// Bytes of task inputs already present (located) or scheduled on the worker
+    pub locality_bytes: usize,
+    /// Cpus used by tasks scheduled on the worker (before the decision)
+    pub active_cpus: u32,
+    /// Penalty for the cost of the worker (by cost weight of the session)
+    /// and for a preemptible worker when the task is not preemptible
+    pub cost_penalty: i64,
+    /// Why the worker cannot run the task, `None` if it can
+    #[serde(skip)]
+    pub rejected: Option<Rejection>,
+    /// Description of `rejected`, it is filled only in recorded decisions
+    #[serde(rename = "rejected")]
+    pub reason: Option<String>,
+}
+
+/// Record of a single scheduling decision
+#[derive(Clone, Debug, Serialize)]
+pub struct Decision {
+    pub task: TaskId,
+    pub worker: WorkerId,
+    /// Mean size of inputs transferred to a worker
+    pub avg_transfer_bytes: i64,
+    /// Upward rank of the task (see `update_ranks`) in seconds
+    pub rank: f64,
+    /// Duration of the task used in the rank, given by client or predicted
+    pub duration: Option<f64>,
+    pub candidates: Vec<Candidate>,
+}
+
+/// Default weight of bytes of inputs present on a worker in its score
+pub const DEFAULT_LOCALITY_WEIGHT: f64 = 1.0;
+
+/// Score of a free cpu of a worker under placement policies `Pack` and `Spread`
+const PLACEMENT_WEIGHT: i64 = 5000;
+
+/// How tasks are placed among workers that are otherwise equally good (`--placement`)
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Placement {
+    /// Load of workers is not considered
+    Any,
+    /// Prefer workers with the fewest free cpus, so that idle workers can be released
+    Pack,
+    /// Prefer workers with the most free cpus, tasks share less I/O bandwidth
+    Spread,
+}
+
+impl Placement {
+    pub fn parse(value: &str) -> Result<Self> {
+        match value {
+            "any" => Ok(Placement::Any),
+            "pack" => Ok(Placement::Pack),
+            "spread" => Ok(Placement::Spread),
+            _ => bail!("Invalid placement policy '{}' (any, pack or spread)", value),
+        }
+    }
+}
+
+/// Changes of the scheduler at runtime (`configureScheduler`), fields that are not given
+/// are not changed
+#[derive(Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SchedulerConfig {
+    /// Paused scheduling assigns no tasks to workers, running tasks continue
+    pub paused: Option<bool>,
+    pub placement: Option<Placement>,
+    pub locality_weight: Option<f64>,
+    #[serde(default, deserialize_with = "deserialize_duration_opt")]
+    pub scheduling_interval: Option<Duration>,
+}
+
+/// Current parameters of the scheduler and the load it drains when paused
+#[derive(Clone, Debug, Serialize)]
+pub struct SchedulerStatus {
+    pub paused: bool,
+    pub placement: Placement,
+    pub locality_weight: f64,
+    /// Seconds
+    pub scheduling_interval: f64,
+    /// Tasks waiting for the scheduler
+    pub ready_tasks: u32,
+    /// Tasks assigned to workers, paused scheduling is drained when it reaches zero
+    pub running_tasks: u32,
+}
+
+/// Assigned tasks are pulled back from a worker only when they have been ready for so long
+/// without starting (seconds)
+const REBALANCE_MIN_WAIT: u64 = 5;
+
+#[derive(Clone, Debug)]
 pub struct ReactiveScheduler {
     ready_tasks: RcSet<TaskRef>,
+    /// Recent decisions, `decision_order` is used to drop the oldest ones
+    decisions: HashMap<TaskId, Decision>,
+    decision_order: VecDeque<TaskId>,
+    /// Seed of the deterministic mode (`--scheduler-seed`), `None` = ordinary mode
+    seed: Option<u64>,
+    /// Logical clock of the deterministic mode, incremented with every decision
+    clock: u64,
+    /// How much a byte of inputs present on a worker is preferred over a byte
+    /// transferred to it (`--locality-weight`)
+    locality_weight: f64,
+    placement: Placement,
+}
+
+impl Default for ReactiveScheduler {
+    fn default() -> Self {
+        ReactiveScheduler {
+            ready_tasks: Default::default(),
+            decisions: Default::default(),
+            decision_order: Default::default(),
+            seed: None,
+            clock: 0,
+            locality_weight: DEFAULT_LOCALITY_WEIGHT,
+            placement: Placement::Any,
+        }
+    }
+}
+
+/// Average input size (times the number of its replicas) over all workers
+fn avg_transfer_size(t: &Task, n_workers: i64) -> i64 {
+    let mut total_size = 0;
+    for input in &t.inputs {
+        let o = input.object.get();
+        total_size += o.size.unwrap() * o.scheduled.len();
+    }
+    (total_size as i64) / n_workers
+}
+
+/// Score penalty of running a task that does not tolerate eviction on a preemptible worker
+const PREEMPTIBLE_PENALTY: i64 = 1 << 40;
+
+/// Score penalty of a worker where a run of the task failed before
+const RETRY_PENALTY: i64 = 1 << 41;
+
+/// Transfer rate (bytes per second) of estimates of transfer times in ranks of tasks
+const ESTIMATED_BANDWIDTH: f64 = 100.0 * 1024.0 * 1024.0;
+
+/// Upward rank of HEFT: expected duration of the task plus the longest chain of
+/// estimated transfers of outputs and expected durations of consumers that follows it
+fn upward_rank(t: &Task) -> f64 {
+    let mut longest = 0f64;
+    for oref in &t.outputs {
+        let o = oref.get();
+        let transfer = o.size.or(o.size_hint).unwrap_or(0) as f64 / ESTIMATED_BANDWIDTH;
+        for c in &o.consumers {
+            longest = longest.max(transfer + c.get().rank);
+        }
+    }
+    t.expected_duration().unwrap_or(0f64) + longest
+}
+
+/// Distinct producers of inputs of the task
+fn producers(t: &Task) -> RcSet<TaskRef> {
+    t.inputs
+        .iter()
+        .filter_map(|i| i.object.get().producer.clone())
+        .collect()
+}
+
+/// Compute ranks of submitted tasks (see `upward_rank`); tasks with higher ranks
+/// are scheduled first, so long chains of expected durations start early and
+/// the makespan of the graph is shorter. Consumers of the tasks are submitted with
+/// them or later, so ranks are computed from the last tasks of the graph up and
+/// then increased for unfinished producers submitted before.
+pub fn update_ranks(tasks: &[TaskRef]) {
+    // Number of consumers among `tasks` whose ranks are not computed yet
+    let mut waiting: HashMap<TaskRef, usize> = HashMap::new();
+    for tref in tasks {
+        waiting.insert(tref.clone(), 0);
+    }
+    for tref in tasks {
+        for p in producers(&tref.get()) {
+            if let Some(count) = waiting.get_mut(&p) {
+                *count += 1;
+            }
+        }
+    }
+    let mut queue: Vec<TaskRef> = waiting
+        .iter()
+        .filter(|&(_, &count)| count == 0)
+        .map(|(t, _)| t.clone())
+        .collect();
+    let mut earlier = Vec::new();
+    while let Some(tref) = queue.pop() {
+        let rank = upward_rank(&tref.get());
+        tref.get_mut().rank = rank;
+        for p in producers(&tref.get()) {
+            match waiting.get_mut(&p) {
+                Some(count) => {
+                    *count -= 1;
+                    if *count == 0 {
+                        queue.push(p.clone());
+                    }
+                }
+                None => earlier.push(p.clone()),
+            }
+        }
+    }
+    while let Some(tref) = earlier.pop() {
+        let rank = {
+            let t = tref.get();
+            if t.state == TaskState::Finished || t.state == TaskState::Failed {
+                continue;
+            }
+            upward_rank(&t)
+        };
+        if rank > tref.get().rank {
+            tref.get_mut().rank = rank;
+            earlier.extend(producers(&tref.get()));
+        }
+    }
+}
+
+/// Rank in milliseconds, for comparisons of ranks
+#[inline]
+fn rank_key(t: &Task) -> i64 {
+    (t.rank * 1000f64) as i64
+}
+
+/// Evaluate the worker for the task
+fn evaluate(
+    t: &Task,
+    wref: &WorkerRef,
+    avg_size: i64,
+    locality_weight: f64,
+    placement: Placement,
+) -> Candidate {
+    let colocated = t.colocated_worker();
+    let w = wref.get();
+    let cpus = t.resources.cpus();
+    let memory = t.resources.memory();
+    let gpus = t.resources.gpus();
+    // A named resource the worker does not have enough of, or one that is not free enough
+    let missing_named = missing_named_resource(t, &w).is_some();
+    let busy_named = busy_named_resource(t, &w).is_some();
+    let rejected = if cpus > w.resources.cpus() {
+        Some(Rejection::Cpus)
+    } else if memory > w.resources.memory() {
+        Some(Rejection::Memory)
+    } else if gpus > w.resources.gpus() {
+        Some(Rejection::Gpus)
+    } else if missing_named {
+        Some(Rejection::Named)
+    } else if cpus + w.active_resources > w.resources.cpus() {
+        Some(Rejection::FreeCpus)
+    } else if memory + w.active_memory > w.resources.memory() {
+        Some(Rejection::FreeMemory)
+    } else if gpus + w.active_gpus > w.resources.gpus() {
+        Some(Rejection::FreeGpus)
+    } else if busy_named {
+        Some(Rejection::FreeNamed)
+    } else if !t.is_allowed_on(&w) {
+        Some(Rejection::Pinned)
+    } else if t.unsatisfied_constraint(&w).is_some() {
+        Some(Rejection::Constraint)
+    } else if t.unsatisfied_arch(&w).is_some() {
+        Some(Rejection::Arch)
+    } else if colocated.as_ref().map(|c| c != wref).unwrap_or(false) {
+        Some(Rejection::Colocation)
+    } else if t.spread_conflict(&w).is_some() {
+        Some(Rejection::Spread)
+    } else if t.sample_conflict(&w).is_some() {
+        Some(Rejection::Sample)
+    } else if w.draining {
+        Some(Rejection::Draining)
+    } else if !w.capabilities.supports_task(&t.task_type) {
+        Some(Rejection::TaskType)
+    } else if !w.capabilities.has(capabilities::DATA_DIRECTORY) && t.uses_directories() {
+        Some(Rejection::Directories)
+    } else if !w.capabilities.has(capabilities::RUN_SCRIPT) && t.embedded_script {
+        Some(Rejection::Scripts)
+    } else {
+        None
+    };
+    // Inputs already on the worker or produced there do not need to be transferred;
+    // each object is counted once even when it is several times among the inputs
+    let mut locality_bytes = 0;
+    let mut counted = RcSet::new();
+    for input in &t.inputs {
+        let o = input.object.get();
+        if (o.located.contains(wref) || o.scheduled.contains(wref))
+            && counted.insert(input.object.clone())
+        {
+            locality_bytes += o.size.unwrap();
+        }
+    }
+    // Cost of the worker per used cpu, the weight says how many bytes of transfer
+    // the session is willing to pay for a unit of cost
+    let mut cost_penalty =
+        (t.session.get().cost_weight * w.cost * ::std::cmp::max(cpus, 1) as f64) as i64;
+    // Preemptible workers are used for tasks that tolerate eviction, other tasks go there
+    // only when there is nothing better
+    if w.preemptible && !t.preemptible {
+        cost_penalty += PREEMPTIBLE_PENALTY;
+    }
+    // Retries of failed tasks go to workers where the task has not failed yet, if possible
+    if t.failed_on(&w) {
+        cost_penalty += RETRY_PENALTY;
+    }
+    let free_cpus = w.resources.cpus() as i64 - w.active_resources as i64;
+    let placement_score = match placement {
+        Placement::Any => 0,
+        Placement::Pack => -free_cpus * PLACEMENT_WEIGHT,
+        Placement::Spread => free_cpus * PLACEMENT_WEIGHT,
+    };
+    Candidate {
+        worker: *w.id(),
+        score: -avg_size + cpus as i64 * 5000i64 + (locality_bytes as f64 * locality_weight) as i64
+            + placement_score - cost_penalty,
+        locality_bytes,
+        active_cpus: w.active_resources,
+        cost_penalty,
+        rejected,
+        reason: None,
+    }
+}
+
+fn missing_named_resource<'a>(t: &'a Task, w: &Worker) -> Option<(&'a String, u32)> {
+    t.resources
+        .named
+        .iter()
+        .find(|&(name, &count)| count > w.resources.named(name))
+        .map(|(name, &count)| (name, count))
+}
+
+fn busy_named_resource<'a>(t: &'a Task, w: &Worker) -> Option<(&'a String, u32)> {
+    t.resources
+        .named
+        .iter()
+        .find(|&(name, &count)| count + w.active_named(name) > w.resources.named(name))
+        .map(|(name, &count)| (name, count))
+}
+
+/// Why a worker cannot run a task. It is cheap to evaluate for every pair of task
+/// and worker; the text for explanation is made only for recorded decisions.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Rejection {
+    Cpus,
+    Memory,
+    Gpus,
+    Named,
+    FreeCpus,
+    FreeMemory,
+    FreeGpus,
+    FreeNamed,
+    Pinned,
+    Constraint,
+    Arch,
+    Colocation,
+    Spread,
+    Sample,
+    Draining,
+    TaskType,
+    Directories,
+    Scripts,
+}
+
+impl Rejection {
+    /// Describe the rejection of the worker for the task
+    pub fn describe(self, t: &Task, wref: &WorkerRef) -> String {
+        let w = wref.get();
+        match self {
+            Rejection::Cpus => format!(
+                "task needs {} cpus, worker has {}",
+                t.resources.cpus(),
+                w.resources.cpus()
+            ),
+            Rejection::Memory => format!(
+                "task needs {} bytes of memory, worker has {}",
+                t.resources.memory(),
+                w.resources.memory()
+            ),
+            Rejection::Gpus => format!(
+                "task needs {} gpus, worker has {}",
+                t.resources.gpus(),
+                w.resources.gpus()
+            ),
+            Rejection::Named => {
+                let (name, count) = missing_named_resource(t, &w).unwrap();
+                format!(
+                    "task needs {} of resource '{}', worker has {}",
+                    count,
+                    name,
+                    w.resources.named(name)
+                )
+            }
+            Rejection::FreeCpus => format!(
+                "not enough free cpus ({} of {} used)",
+                w.active_resources,
+                w.resources.cpus()
+            ),
+            Rejection::FreeMemory => format!(
+                "not enough free memory ({} of {} bytes used)",
+                w.active_memory,
+                w.resources.memory()
+            ),
+            Rejection::FreeGpus => format!(
+                "not enough free gpus ({} of {} used)",
+                w.active_gpus,
+                w.resources.gpus()
+            ),
+            Rejection::FreeNamed => {
+                let (name, _) = busy_named_resource(t, &w).unwrap();
+                format!(
+                    "not enough free resource '{}' ({} of {} used)",
+                    name,
+                    w.active_named(name),
+                    w.resources.named(name)
+                )
+            }
+            Rejection::Pinned => "outputs are pinned elsewhere".to_string(),
+            Rejection::Constraint => format!(
+                "worker does not satisfy constraint '{}'",
+                t.unsatisfied_constraint(&w).unwrap()
+            ),
+            Rejection::Arch => format!(
+                "task needs architecture {}, worker is {}",
+                t.unsatisfied_arch(&w).unwrap(),
+                w.platform.arch
+            ),
+            Rejection::Colocation => format!(
+                "colocation group '{}' is placed on worker {}",
+                t.colocate.as_ref().unwrap(),
+                t.colocated_worker().unwrap().get_id()
+            ),
+            Rejection::Spread => format!(
+                "task {} of spread group '{}' is scheduled on the worker",
+                t.spread_conflict(&w).unwrap(),
+                t.spread.as_ref().unwrap()
+            ),
+            Rejection::Sample => format!(
+                "task {} of the same sample runs on the worker",
+                t.sample_conflict(&w).unwrap()
+            ),
+            Rejection::Draining => "worker is draining".to_string(),
+            Rejection::TaskType => {
+                format!("worker does not support task type '{}'", t.task_type)
+            }
+            Rejection::Directories => "worker does not support directories".to_string(),
+            Rejection::Scripts => "worker does not support embedded scripts".to_string(),
+        }
+    }
+}
+
+/// Worker that can take more tasks and has none waiting to start
+fn is_idle(w: &Worker) -> bool {
+    w.error.is_none() && !w.draining && !w.leaving && w.active_resources < w.resources.cpus()
+        && w.scheduled_ready_tasks.is_empty()
+        && w.assigned_tasks
+            .iter()
+            .all(|t| t.get().state != TaskState::Assigned)
+}
+
+/// Tasks waiting on the worker: ready tasks that are not assigned yet and assigned tasks
+/// that have not started for `REBALANCE_MIN_WAIT`, the oldest first
+fn backlog(w: &Worker) -> Vec<TaskRef> {
+    let min_wait = Duration::from_secs(REBALANCE_MIN_WAIT);
+    let mut tasks: Vec<TaskRef> = w.scheduled_ready_tasks.iter().cloned().collect();
+    for tref in &w.assigned_tasks {
+        let t = tref.get();
+        if t.state == TaskState::Assigned
+            && t.ready_since
+                .map(|since| since.elapsed() >= min_wait)
+                .unwrap_or(false)
+        {
+            tasks.push(tref.clone());
+        }
+    }
+    tasks.sort_by_key(|t| {
+        let t = t.get();
+        (t.ready_since, t.id)
+    });
+    tasks
 }
 
 impl ReactiveScheduler {
@@ -72,41 +583,111 @@ impl ReactiveScheduler {
     type SessionExtra = ();
     type ClientExtra = ();*/
 
+    /// Switch to the deterministic mode: ready tasks and workers are visited in the order
+    /// of their ids and ties of scores are broken by the seed and the logical clock,
+    /// so the same graph on the same workers gets the same placement in every run
+    pub fn set_seed(&mut self, seed: u64) {
+        info!("Scheduler runs in deterministic mode (seed {})", seed);
+        self.seed = Some(seed);
+        self.clock = 0;
+    }
+
+    /// Weight of bytes of inputs present on a worker in its score; 0 ignores locality,
+    /// higher values keep tasks near their data even when other workers are idle
+    pub fn set_locality_weight(&mut self, weight: f64) {
+        info!("Scheduler locality weight is {}", weight);
+        self.locality_weight = weight;
+    }
+
+    /// Placement policy among workers that are otherwise equally good
+    pub fn set_placement(&mut self, placement: Placement) {
+        info!("Scheduler placement policy is {:?}", placement);
+        self.placement = placement;
+    }
+
+    #[inline]
+    pub fn locality_weight(&self) -> f64 {
+        self.locality_weight
+    }
+
+    #[inline]
+    pub fn placement(&self) -> Placement {
+        self.placement
+    }
+
+    #[inline]
+    fn evaluate(&self, t: &Task, wref: &WorkerRef, avg_size: i64) -> Candidate {
+        evaluate(t, wref, avg_size, self.locality_weight, self.placement)
+    }
+
+    /// Ready tasks in the order they are visited
+    fn ready_tasks(&self) -> Vec<TaskRef> {
+        let mut tasks: Vec<_> = self.ready_tasks.iter().cloned().collect();
+        if self.seed.is_some() {
+            tasks.sort_by_key(|t| t.get().id);
+        }
+        tasks
+    }
+
+    /// Workers in the order they are visited
+    fn workers(&self, graph: &Graph) -> Vec<WorkerRef> {
+        let mut workers: Vec<_> = graph.workers.values().cloned().collect();
+        if self.seed.is_some() {
+            workers.sort_by_key(|w| w.get_id());
+        }
+        workers
+    }
+
+    /// Key breaking ties of equal scores in the deterministic mode (lower wins)
+    fn tie_key(&self, seed: u64, task_id: TaskId, worker_id: WorkerId) -> u64 {
+        let mut hasher = DefaultHasher::new();
+        (seed, self.clock, task_id, worker_id).hash(&mut hasher);
+        hasher.finish()
+    }
+
     fn pick_best(&self, graph: &mut Graph) -> Option<(TaskRef, WorkerRef)> {
         let mut best_worker = None;
+        let mut best_priority = 0;
+        let mut best_rank = 0;
         let mut best_score = 0;
+        let mut best_key = 0;
         let mut best_task = None;
 
         let n_workers = graph.workers.len() as i64;
+        let workers = self.workers(graph);
 
-        for tref in &self.ready_tasks {
+        for tref in &self.ready_tasks() {
             let t = tref.get();
-            let mut total_size = 0;
-            for input in &t.inputs {
-                let o = input.object.get();
-                total_size += o.size.unwrap() * o.scheduled.len();
-            }
-            let neg_avg_size = -(total_size as i64) / n_workers;
-            //debug!("!!! {} AVG SIZE {}", t.id, -neg_avg_size);
-
-            for (_, wref) in &graph.workers {
-                let w = wref.get();
-                let cpus = t.resources.cpus();
-                if cpus + w.active_resources <= w.resources.cpus()
-                    && t.resources.is_subset_of(&w.resources)
+            if t.held || !t.session.get().can_run_task()
+                || !graph.global_resources.is_available(&t.resources)
+            {
+                continue;
+            }
+            let avg_size = avg_transfer_size(&t, n_workers);
+            let rank = rank_key(&t);
+
+            for wref in &workers {
+                let candidate = self.evaluate(&t, wref, avg_size);
+                if candidate.rejected.is_some() {
+                    continue;
+                }
+                let key = match self.seed {
+                    Some(seed) => self.tie_key(seed, t.id, candidate.worker),
+                    None => 0,
+                };
+                // Priority first, then rank of the task, then score of the worker
+                if best_worker.is_none()
+                    || (best_priority, best_rank, best_score) < (t.priority, rank, candidate.score)
+                    || ((best_priority, best_rank, best_score)
+                        == (t.priority, rank, candidate.score)
+                        && key < best_key)
                 {
-                    let mut score = neg_avg_size + cpus as i64 * 5000i64;
-                    for input in &t.inputs {
-                        let o = input.object.get();
-                        if o.scheduled.contains(wref) {
-                            score += o.size.unwrap() as i64;
-                        }
-                    }
-                    if best_score < score || best_worker.is_none() {
-                        best_score = score;
-                        best_worker = Some(wref.clone());
-                        best_task = Some(tref.clone());
-                    }
+                    best_priority = t.priority;
+                    best_rank = rank;
+                    best_score = candidate.score;
+                    best_key = key;
+                    best_worker = Some(wref.clone());
+                    best_task = Some(tref.clone());
                 }
             }
         }
@@ -117,6 +698,46 @@ impl ReactiveScheduler {
         }
     }
 
+    /// Record all candidates for the task scheduled on the worker
+    fn record_decision(&mut self, graph: &Graph, tref: &TaskRef, wref: &WorkerRef) {
+        let t = tref.get();
+        let avg_size = avg_transfer_size(&t, graph.workers.len() as i64);
+        let mut candidates: Vec<Candidate> = self.workers(graph)
+            .iter()
+            .map(|w| {
+                let mut candidate = self.evaluate(&t, w, avg_size);
+                candidate.reason = candidate.rejected.map(|r| r.describe(&t, w));
+                candidate
+            })
+            .collect();
+        candidates.sort_by(|a, b| b.score.cmp(&a.score));
+        let decision = Decision {
+            task: t.id,
+            worker: wref.get_id(),
+            avg_transfer_bytes: avg_size,
+            rank: t.rank,
+            duration: t.expected_duration(),
+            candidates,
+        };
+        if self.decisions.insert(t.id, decision).is_none() {
+            if self.decision_order.len() >= MAX_DECISIONS {
+                let old = self.decision_order.pop_front().unwrap();
+                self.decisions.remove(&old);
+            }
+            self.decision_order.push_back(t.id);
+        }
+    }
+
+    /// Return the recorded decision for the task (if it is still remembered)
+    pub fn explain(&self, task_id: &TaskId) -> Option<&Decision> {
+        self.decisions.get(task_id)
+    }
+
+    /// Forget the ready (not yet scheduled) task, it is no longer ready
+    pub fn remove_ready_task(&mut self, task: &TaskRef) {
+        self.ready_tasks.remove(task);
+    }
+
     pub fn clear_session(&mut self, session: &SessionRef) {
         let s = session.get();
         for tref in &s.tasks {
@@ -124,6 +745,287 @@ impl ReactiveScheduler {
         }
     }
 
+    /// Schedule the ready task on the worker
+    fn place(
+        &mut self,
+        graph: &mut Graph,
+        tref: &TaskRef,
+        wref: &WorkerRef,
+        up_out: &mut UpdatedOut,
+    ) {
+        self.clock += 1;
+        self.record_decision(graph, tref, wref);
+        {
+            let mut w = wref.get_mut();
+            let mut t = tref.get_mut();
+
+            assert!(t.state == TaskState::Ready);
+            w.add_active(&t.resources);
+            w.scheduled_tasks.insert(tref.clone());
+
+            // Scheduler "picks" only ready tasks, so we do need to test readiness of task
+            w.scheduled_ready_tasks.insert(tref.clone());
+
+            t.scheduled = Some(wref.clone());
+            t.session.get_mut().running_tasks += 1;
+            if let Some(ref name) = t.colocate {
+                t.session
+                    .get_mut()
+                    .colocated
+                    .insert(name.clone(), wref.clone());
+            }
+            graph.global_resources.acquire(&t.resources);
+
+            debug!("Scheduler: {} -> {}", t.id, w.id());
+            for oref in &t.outputs {
+                w.scheduled_objects.insert(oref.clone());
+                oref.get_mut().scheduled.insert(wref.clone());
+
+                up_out
+                    .objects
+                    .entry(wref.clone())
+                    .or_insert(Default::default())
+                    .insert(oref.clone());
+            }
+        }
+        self.ready_tasks.remove(tref);
+        up_out.tasks.insert(tref.clone());
+    }
+
+    /// Tasks on the worker with lower priority than the task whose preemption frees enough
+    /// resources for the task; tasks with the lowest priority are taken first, from them
+    /// tasks that have not started yet and then the most recent ones.
+    /// `None` when the task cannot run on the worker even without them.
+    fn find_victims(
+        &self,
+        graph: &Graph,
+        tref: &TaskRef,
+        wref: &WorkerRef,
+    ) -> Option<Vec<TaskRef>> {
+        let t = tref.get();
+        let mut candidates: Vec<TaskRef> = wref.get()
+            .scheduled_tasks
+            .iter()
+            .filter(|v| {
+                let v = v.get();
+                v.priority < t.priority && v.state != TaskState::NotAssigned
+            })
+            .cloned()
+            .collect();
+        if candidates.is_empty() {
+            return None;
+        }
+        candidates.sort_by_key(|v| {
+            let v = v.get();
+            (v.priority, v.state == TaskState::Running, Reverse(v.id))
+        });
+        let avg_size = avg_transfer_size(&t, graph.workers.len() as i64);
+        // Resources of the victims are released only for the evaluation
+        let mut victims = Vec::new();
+        let mut fits = false;
+        for v in candidates {
+            wref.get_mut().remove_active(&v.get().resources);
+            victims.push(v);
+            if self.evaluate(&t, wref, avg_size).rejected.is_none() {
+                fits = true;
+                break;
+            }
+        }
+        for v in &victims {
+            wref.get_mut().add_active(&v.get().resources);
+        }
+        if fits {
+            Some(victims)
+        } else {
+            None
+        }
+    }
+
+    /// Schedule ready tasks that do not fit on any worker by preempting tasks with lower
+    /// priority, on the worker where the fewest tasks are preempted. Tasks are visited
+    /// from the highest priority.
+    fn preempt(&mut self, graph: &mut Graph, up_out: &mut UpdatedOut) {
+        let workers = self.workers(graph);
+        // The lowest priority of scheduled tasks, only tasks above it may preempt anything
+        let mut lowest = None;
+        for wref in &workers {
+            for v in &wref.get().scheduled_tasks {
+                let priority = v.get().priority;
+                if lowest.map(|p| priority < p).unwrap_or(true) {
+                    lowest = Some(priority);
+                }
+            }
+        }
+        let lowest = match lowest {
+            Some(lowest) => lowest,
+            None => return,
+        };
+        let mut waiting: Vec<TaskRef> = self.ready_tasks()
+            .into_iter()
+            .filter(|tref| {
+                let t = tref.get();
+                t.priority > lowest && !t.held && t.session.get().can_run_task()
+                    && graph.global_resources.is_available(&t.resources)
+            })
+            .collect();
+        waiting.sort_by_key(|tref| Reverse(tref.get().priority));
+        for tref in waiting {
+            let mut best: Option<(WorkerRef, Vec<TaskRef>)> = None;
+            for wref in &workers {
+                if let Some(victims) = self.find_victims(graph, &tref, wref) {
+                    if best.as_ref()
+                        .map(|b| victims.len() < b.1.len())
+                        .unwrap_or(true)
+                    {
+                        best = Some((wref.clone(), victims));
+                    }
+                }
+            }
+            let (wref, victims) = match best {
+                Some(best) => best,
+                None => continue,
+            };
+            for victim in victims {
+                debug!(
+                    "Scheduler: {} preempted on {} by {}",
+                    victim.get_id(),
+                    wref.get_id(),
+                    tref.get_id()
+                );
+                victim.unschedule(&mut graph.global_resources);
+                for oref in victim.get().outputs.iter() {
+                    oref.get_mut().scheduled.remove(&wref);
+                    wref.get_mut().scheduled_objects.remove(oref);
+                }
+                for oref in victim.unforward_inputs(&wref) {
+                    up_out
+                        .objects
+                        .entry(wref.clone())
+                        .or_insert(Default::default())
+                        .insert(oref);
+                }
+                up_out.preempted.insert(victim);
+            }
+            self.place(graph, &tref, &wref, up_out);
+        }
+    }
+
+    /// Idle worker with the best score for the task, if any can run it
+    fn best_idle(&self, graph: &Graph, tref: &TaskRef, idle: &[WorkerRef]) -> Option<WorkerRef> {
+        let t = tref.get();
+        let avg_size = avg_transfer_size(&t, graph.workers.len() as i64);
+        let mut best: Option<(i64, WorkerRef)> = None;
+        for wref in idle {
+            let candidate = self.evaluate(&t, wref, avg_size);
+            if candidate.rejected.is_none()
+                && best.as_ref().map(|b| b.0 < candidate.score).unwrap_or(true)
+            {
+                best = Some((candidate.score, wref.clone()));
+            }
+        }
+        best.map(|b| b.1)
+    }
+
+    /// Move tasks waiting on workers with a backlog to idle workers (that can take more
+    /// tasks and have none waiting). The oldest waiting task is left to its worker.
+    /// Ready tasks that are not assigned yet are scheduled on the idle workers directly,
+    /// assigned tasks that have not started are returned to be pulled back; the capacity
+    /// of the idle workers is counted for them as if they were moved.
+    /// Nothing is moved in the deterministic mode, it depends on timing.
+    pub fn rebalance(&mut self, graph: &mut Graph) -> Rebalanced {
+        let mut result: Rebalanced = Default::default();
+        if self.seed.is_some() {
+            return result;
+        }
+        let workers = self.workers(graph);
+        let idle: Vec<WorkerRef> = workers
+            .iter()
+            .filter(|w| is_idle(&w.get()))
+            .cloned()
+            .collect();
+        if idle.is_empty() || idle.len() == workers.len() {
+            return result;
+        }
+        // Resources of pulled back tasks counted on idle workers during the pass
+        let mut reserved: Vec<(WorkerRef, Resources)> = Vec::new();
+        for wref in &workers {
+            if idle.contains(wref) {
+                continue;
+            }
+            let tasks = backlog(&wref.get());
+            for tref in tasks.iter().skip(1) {
+                let target = match self.best_idle(graph, tref, &idle) {
+                    Some(target) => target,
+                    None => continue,
+                };
+                debug!(
+                    "Rebalancing task {} from {} to {}",
+                    tref.get_id(),
+                    wref.get_id(),
+                    target.get_id()
+                );
+                if tref.get().assigned.is_none() {
+                    tref.unschedule(&mut graph.global_resources);
+                    for oref in tref.get().outputs.iter() {
+                        oref.get_mut().scheduled.remove(wref);
+                        wref.get_mut().scheduled_objects.remove(oref);
+                    }
+                    for oref in tref.unforward_inputs(wref) {
+                        result
+                            .moved
+                            .objects
+                            .entry(wref.clone())
+                            .or_insert(Default::default())
+                            .insert(oref);
+                    }
+                    self.place(graph, tref, &target, &mut result.moved);
+                } else {
+                    let resources = tref.get().resources.clone();
+                    target.get_mut().add_active(&resources);
+                    reserved.push((target.clone(), resources));
+                    result
+                        .pull_back
+                        .entry(wref.clone())
+                        .or_insert_with(Vec::new)
+                        .push((tref.clone(), target));
+                }
+            }
+        }
+        for (wref, resources) in reserved {
+            wref.get_mut().remove_active(&resources);
+        }
+        result
+    }
+
+    /// Schedule a task pulled back from a worker on the idle worker chosen by `rebalance`,
+    /// `None` when the worker cannot take it anymore (the task is then scheduled as any
+    /// other ready task)
+    pub fn place_pulled_back(
+        &mut self,
+        graph: &mut Graph,
+        tref: &TaskRef,
+        wref: &WorkerRef,
+    ) -> Option<UpdatedOut> {
+        {
+            let t = tref.get();
+            if t.state != TaskState::Ready || t.scheduled.is_some() || t.held
+                || !t.session.get().can_run_task()
+                || !graph.global_resources.is_available(&t.resources)
+                || !graph.workers.contains_key(&wref.get_id())
+                || wref.get().error.is_some() || wref.get().leaving
+            {
+                return None;
+            }
+            let avg_size = avg_transfer_size(&t, graph.workers.len() as i64);
+            if self.evaluate(&t, wref, avg_size).rejected.is_some() {
+                return None;
+            }
+        }
+        let mut up_out = Default::default();
+        self.place(graph, tref, wref, &mut up_out);
+        Some(up_out)
+    }
+
     pub fn schedule(&mut self, graph: &mut Graph, updated: &UpdatedIn) -> UpdatedOut {
         let mut up_out: UpdatedOut = Default::default();
 
@@ -149,37 +1051,32 @@ impl ReactiveScheduler {
             }
         }
 
+        // Objects with data from client that are pinned by client are placed directly
+        let workers = self.workers(graph);
+        for oref in &updated.new_objects {
+            let mut o = oref.get_mut();
+            if o.pinned.is_empty() || o.data.is_none() || !o.scheduled.is_empty() {
+                continue;
+            }
+            let wref = workers.iter().find(|w| o.is_allowed_on(&w.get())).cloned();
+            if let Some(wref) = wref {
+                debug!("Scheduler: pinned object {} -> {}", o.id, wref.get_id());
+                wref.get_mut().scheduled_objects.insert(oref.clone());
+                o.scheduled.insert(wref.clone());
+                up_out
+                    .objects
+                    .entry(wref)
+                    .or_insert(Default::default())
+                    .insert(oref.clone());
+            }
+        }
+
         debug!("Scheduler started");
 
         while let Some((tref, wref)) = self.pick_best(graph) {
-            {
-                let mut w = wref.get_mut();
-                let mut t = tref.get_mut();
-
-                assert!(t.state == TaskState::Ready);
-                w.active_resources += t.resources.cpus();
-                w.scheduled_tasks.insert(tref.clone());
-
-                // Scheduler "picks" only ready tasks, so we do need to test readiness of task
-                w.scheduled_ready_tasks.insert(tref.clone());
-
-                t.scheduled = Some(wref.clone());
-
-                debug!("Scheduler: {} -> {}", t.id, w.id());
-                for oref in &t.outputs {
-                    w.scheduled_objects.insert(oref.clone());
-                    oref.get_mut().scheduled.insert(wref.clone());
-
-                    up_out
-                        .objects
-                        .entry(wref.clone())
-                        .or_insert(Default::default())
-                        .insert(oref.clone());
-                }
-            }
-            self.ready_tasks.remove(&tref);
-            up_out.tasks.insert(tref);
+            self.place(graph, &tref, &wref, &mut up_out);
         }
+        self.preempt(graph, &mut up_out);
         up_out
 
         /*if graph.workers.is_empty() {
@@ -228,3 +1125,86 @@ fn random_worker(g: &mut Graph, seed: usize) -> WorkerRef {
     ws[seed % ws.len()].clone()
 }
 */
+
+#[cfg(test)]
+mod tests {
+    use super::{ReactiveScheduler, UpdatedIn};
+    use server::graph::{ClientRef, Graph, SessionRef, TaskRef, WorkerRef};
+    use common::attributes::Attributes;
+    use common::id::{SId, TaskId, WorkerId};
+    use common::resources::Resources;
+
+    /// Schedule independent one-cpu tasks on identical workers, so every decision is a tie
+    fn seeded_placement(seed: u64) -> Vec<(TaskId, WorkerId)> {
+        let mut graph = Graph::new();
+        for wi in 0..4 {
+            let wref = WorkerRef::new(
+                format!("10.0.0.{}:7211", wi + 1).parse().unwrap(),
+                None,
+                Resources {
+                    cpus: 2,
+                    ..Default::default()
+                },
+            );
+            graph.workers.insert(wref.get_id(), wref);
+        }
+        let client = ClientRef::new("10.0.1.1:42".parse().unwrap(), "anonymous".to_string());
+        let session = SessionRef::new(1, &client);
+        let mut updated = UpdatedIn::default();
+        for ti in 0..6 {
+            let tref = TaskRef::new(
+                &session,
+                TaskId::new(1, ti),
+                Vec::new(),
+                Vec::new(),
+                "TType".to_string(),
+                Attributes::new(),
+                Resources {
+                    cpus: 1,
+                    ..Default::default()
+                },
+            ).unwrap();
+            updated.new_tasks.insert(tref);
+        }
+
+        let mut scheduler = ReactiveScheduler::default();
+        scheduler.set_seed(seed);
+        let up_out = scheduler.schedule(&mut graph, &updated);
+        let mut placement: Vec<_> = up_out
+            .tasks
+            .iter()
+            .map(|t| {
+                let t = t.get();
+                (t.id, t.scheduled.as_ref().unwrap().get_id())
+            })
+            .collect();
+        placement.sort();
+        placement
+    }
+
+    #[test]
+    fn test_seeded_placement_is_repeatable() {
+        let placement = seeded_placement(42);
+        assert_eq!(placement.len(), 6);
+        for _ in 0..5 {
+            assert_eq!(seeded_placement(42), placement);
+        }
+    }
+
+    #[test]
+    fn test_tie_key() {
+        let mut scheduler = ReactiveScheduler::default();
+        scheduler.set_seed(7);
+        let task = TaskId::new(1, 1);
+        let w1: WorkerId = "10.0.0.1:7211".parse().unwrap();
+        let w2: WorkerId = "10.0.0.2:7211".parse().unwrap();
+        assert_eq!(scheduler.tie_key(7, task, w1), scheduler.tie_key(7, task, w1));
+        assert_ne!(scheduler.tie_key(7, task, w1), scheduler.tie_key(7, task, w2));
+        assert_ne!(scheduler.tie_key(7, task, w1), scheduler.tie_key(8, task, w1));
+
+        // The clock is a part of the key, so later ties are broken differently
+        let key = scheduler.tie_key(7, task, w1);
+        scheduler.clock += 1;
+        assert_ne!(scheduler.tie_key(7, task, w1), key);
+    }
+}
@@ -0,0 +1,74 @@
+use std::collections::HashMap;
+use std::fmt;
+
+use chacha20poly1305::aead::{Aead, AeadCore, KeyInit, OsRng};
+use chacha20poly1305::{ChaCha20Poly1305, Nonce};
+
+use errors::Result;
+
+/// Named secrets registered by clients (passwords, tokens, ...).
+/// Secrets are owned by the identity of the client that registered them (name from
+/// the access file), tasks may use only secrets of the identity of their client.
+/// Values are kept encrypted by a key generated at the server start (which is kept
+/// in memory too), so they are not stored in plain text. They are decrypted only when
+/// a task that references them is sent to a worker.
+pub struct SecretStore {
+    cipher: ChaCha20Poly1305,
+    /// (owner, name) -> encrypted value
+    secrets: HashMap<(String, String), (Nonce, Vec<u8>)>,
+}
+
+impl SecretStore {
+    pub fn new() -> Self {
+        SecretStore {
+            cipher: ChaCha20Poly1305::new(&ChaCha20Poly1305::generate_key(&mut OsRng)),
+            secrets: HashMap::new(),
+        }
+    }
+
+    /// Register a new secret of the owner or replace the value of an existing one
+    pub fn set(&mut self, owner: &str, name: &str, value: &[u8]) -> Result<()> {
+        if name.is_empty() {
+            bail!("Name of secret is empty");
+        }
+        let nonce = ChaCha20Poly1305::generate_nonce(&mut OsRng);
+        let encrypted = self.cipher
+            .encrypt(&nonce, value)
+            .map_err(|_| format!("Secret '{}' cannot be encrypted", name))?;
+        self.secrets
+            .insert((owner.to_string(), name.to_string()), (nonce, encrypted));
+        Ok(())
+    }
+
+    pub fn remove(&mut self, owner: &str, name: &str) -> Result<()> {
+        if self.secrets
+            .remove(&(owner.to_string(), name.to_string()))
+            .is_none()
+        {
+            bail!("Secret '{}' not found", name);
+        }
+        Ok(())
+    }
+
+    #[inline]
+    pub fn contains(&self, owner: &str, name: &str) -> bool {
+        self.secrets
+            .contains_key(&(owner.to_string(), name.to_string()))
+    }
+
+    /// Decrypted value of the secret
+    pub fn get(&self, owner: &str, name: &str) -> Result<Vec<u8>> {
+        match self.secrets.get(&(owner.to_string(), name.to_string())) {
+            Some(&(ref nonce, ref encrypted)) => Ok(self.cipher
+                .decrypt(nonce, encrypted.as_slice())
+                .map_err(|_| format!("Secret '{}' cannot be decrypted", name))?),
+            None => bail!("Secret '{}' not found", name),
+        }
+    }
+}
+
+impl fmt::Debug for SecretStore {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "SecretStore ({} secret(s))", self.secrets.len())
+    }
+}
@@ -0,0 +1,70 @@
+use std::sync::mpsc;
+
+use futures::{future, Future};
+use futures::sync::oneshot;
+use serde::Serialize;
+
+use errors::{Error, Result};
+
+/// Responses with fewer items (tasks and objects) are serialized directly on the reactor
+pub const BACKGROUND_MIN_ITEMS: usize = 1000;
+
+/// Work for the thread, it is run exactly once
+trait Job: Send {
+    fn run(self: Box<Self>);
+}
+
+impl<F: FnOnce() + Send> Job for F {
+    fn run(self: Box<Self>) {
+        (*self)()
+    }
+}
+
+/// Serializes large responses (graph snapshots, session bundles) into JSON by a separate
+/// thread. The reactor only makes an owned copy of the data and hands it off, so updates
+/// from workers and scheduling are not delayed by large queries of clients. Only JSON is
+/// made by the thread; the reactor still copies the resulting text into the capnp message
+/// of the response (`setBundle`/`setSnapshot`), which is a plain copy of bytes.
+pub struct Serializer {
+    sender: mpsc::Sender<Box<Job>>,
+}
+
+impl Serializer {
+    pub fn start() -> Result<Self> {
+        let (sender, receiver) = mpsc::channel::<Box<Job>>();
+        ::std::thread::Builder::new()
+            .name("serializer".to_string())
+            .spawn(move || {
+                for job in receiver {
+                    job.run();
+                }
+            })?;
+        Ok(Serializer { sender })
+    }
+
+    /// JSON of the value; it is serialized by the thread when it has at least
+    /// `BACKGROUND_MIN_ITEMS` items
+    pub fn to_json<T>(&self, value: T, items: usize) -> Box<Future<Item = String, Error = Error>>
+    where
+        T: Serialize + Send + 'static,
+    {
+        if items < BACKGROUND_MIN_ITEMS {
+            return Box::new(future::result(
+                ::serde_json::to_string(&value).map_err(Error::from),
+            ));
+        }
+        let (sender, receiver) = oneshot::channel();
+        let job: Box<Job> = Box::new(move || {
+            // Errors are sent as strings, since `Error` cannot be sent between threads
+            let _ = sender.send(::serde_json::to_string(&value).map_err(|e| e.to_string()));
+        });
+        if self.sender.send(job).is_err() {
+            return Box::new(future::err("Serialization thread is not running".into()));
+        }
+        Box::new(
+            receiver
+                .map_err(|_| Error::from("Serialization was cancelled"))
+                .and_then(|r| r.map_err(Error::from)),
+        )
+    }
+}
@@ -0,0 +1,300 @@
+use server::graph::{DataObject, DataObjectState, Session, Task, TaskState, Worker};
+use server::state::State;
+use server::redact::Redaction;
+use common::id::{DataObjectId, SId, SessionId, TaskId, WorkerId};
+use common::labels::Labels;
+use common::resources::Resources;
+use common::sys::Platform;
+use common::{Attributes, DataType};
+use bundle::{BundleInput, BundleObject, BundleTask, SessionBundle, RESULT_ATTRIBUTES,
+             SESSION_BUNDLE_VERSION};
+use errors::Result;
+
+/// Version of `GraphSnapshot`; fields may be added within a version,
+/// other changes need a new version
+pub const GRAPH_SNAPSHOT_VERSION: u32 = 1;
+
+/// Read-only copy of the graph of the server (or of its part) for tooling
+/// (CLI, dashboard, external tools). Relations are expressed by ids.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct GraphSnapshot {
+    pub version: u32,
+    pub sessions: Vec<SessionNode>,
+    pub tasks: Vec<TaskNode>,
+    pub objects: Vec<ObjectNode>,
+    pub workers: Vec<WorkerNode>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SessionNode {
+    pub id: SessionId,
+    pub name: String,
+    pub parent: Option<SessionId>,
+    pub client: String,
+    /// Message of the error when the session failed
+    pub error: Option<String>,
+    pub unfinished_tasks: usize,
+    pub running_tasks: usize,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct TaskNode {
+    pub id: TaskId,
+    pub task_type: String,
+    /// "not_assigned", "ready", "assigned", "running", "finished" or "failed"
+    pub state: String,
+    pub inputs: Vec<DataObjectId>,
+    pub outputs: Vec<DataObjectId>,
+    pub worker: Option<WorkerId>,
+    pub resources: Resources,
+    pub group: Option<String>,
+    pub attributes: Attributes,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ObjectNode {
+    pub id: DataObjectId,
+    pub label: String,
+    /// "unfinished", "finished" or "removed"
+    pub state: String,
+    pub data_type: DataType,
+    pub producer: Option<TaskId>,
+    pub consumers: Vec<TaskId>,
+    /// Workers holding the data
+    pub located: Vec<WorkerId>,
+    pub size: Option<usize>,
+    pub keep: bool,
+    pub published: Option<String>,
+    pub attributes: Attributes,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct WorkerNode {
+    pub id: WorkerId,
+    pub resources: Resources,
+    pub assigned_tasks: Vec<TaskId>,
+    pub located_objects: Vec<DataObjectId>,
+    pub cost: f64,
+    pub preemptible: bool,
+    pub draining: bool,
+    pub error: Option<String>,
+    /// Capabilities announced by the worker (empty for workers of older versions)
+    pub capabilities: Vec<String>,
+    pub image: String,
+    pub leaving: bool,
+    #[serde(default)]
+    pub labels: Labels,
+    /// Empty strings for workers of older versions
+    #[serde(default)]
+    pub platform: Platform,
+}
+
+fn task_state_name(state: TaskState) -> &'static str {
+    match state {
+        TaskState::NotAssigned => "not_assigned",
+        TaskState::Ready => "ready",
+        TaskState::Assigned => "assigned",
+        TaskState::Running => "running",
+        TaskState::Finished => "finished",
+        TaskState::Failed => "failed",
+    }
+}
+
+fn object_state_name(state: DataObjectState) -> &'static str {
+    match state {
+        DataObjectState::Unfinished => "unfinished",
+        DataObjectState::Finished => "finished",
+        DataObjectState::Removed => "removed",
+    }
+}
+
+fn session_node(s: &Session) -> SessionNode {
+    SessionNode {
+        id: s.id,
+        name: s.name.clone(),
+        parent: s.parent.as_ref().map(|p| p.get().id),
+        client: s.client.get().id.to_string(),
+        error: s.error.as_ref().map(|e| e.message().to_string()),
+        unfinished_tasks: s.unfinished_tasks,
+        running_tasks: s.running_tasks,
+    }
+}
+
+fn task_node(t: &Task, redaction: &Redaction) -> TaskNode {
+    TaskNode {
+        id: t.id,
+        task_type: t.task_type.clone(),
+        state: task_state_name(t.state).to_string(),
+        inputs: t.inputs.iter().map(|i| i.object.get_id()).collect(),
+        outputs: t.outputs.iter().map(|o| o.get_id()).collect(),
+        worker: t.assigned.as_ref().map(|w| w.get_id()),
+        resources: t.resources.clone(),
+        group: t.group.clone(),
+        attributes: redaction.attributes(&t.attributes),
+    }
+}
+
+fn object_node(o: &DataObject, redaction: &Redaction) -> ObjectNode {
+    let mut consumers: Vec<_> = o.consumers.iter().map(|t| t.get_id()).collect();
+    consumers.sort();
+    let mut located: Vec<_> = o.located.iter().map(|w| w.get_id()).collect();
+    located.sort();
+    ObjectNode {
+        id: o.id,
+        label: o.label.clone(),
+        state: object_state_name(o.state).to_string(),
+        data_type: o.data_type,
+        producer: o.producer.as_ref().map(|t| t.get_id()),
+        consumers,
+        located,
+        size: o.size,
+        keep: o.client_keep,
+        published: o.published.clone(),
+        attributes: redaction.attributes(&o.attributes),
+    }
+}
+
+fn worker_node(w: &Worker) -> WorkerNode {
+    let mut assigned_tasks: Vec<_> = w.assigned_tasks.iter().map(|t| t.get_id()).collect();
+    assigned_tasks.sort();
+    let mut located_objects: Vec<_> = w.located_objects.iter().map(|o| o.get_id()).collect();
+    located_objects.sort();
+    WorkerNode {
+        id: *w.id(),
+        resources: w.resources.clone(),
+        assigned_tasks,
+        located_objects,
+        cost: w.cost,
+        preemptible: w.preemptible,
+        draining: w.draining,
+        error: w.error.clone(),
+        capabilities: w.capabilities.iter().cloned().collect(),
+        image: w.image.clone(),
+        leaving: w.leaving,
+        labels: w.labels.clone(),
+        platform: w.platform.clone(),
+    }
+}
+
+impl GraphSnapshot {
+    /// Snapshot of the whole graph, or only of the given session (and its sub-sessions)
+    /// when `session_id` is given; workers are always included
+    pub fn new(state: &State, session_id: Option<SessionId>) -> Self {
+        let mut sessions: Vec<_> = state
+            .graph
+            .sessions
+            .values()
+            .filter(|session| match session_id {
+                Some(id) => {
+                    let mut current = Some((*session).clone());
+                    while let Some(s) = current {
+                        if s.get().id == id {
+                            return true;
+                        }
+                        current = s.get().parent.clone();
+                    }
+                    false
+                }
+                None => true,
+            })
+            .collect();
+        sessions.sort_by_key(|s| s.get().id);
+
+        let mut tasks = Vec::new();
+        let mut objects = Vec::new();
+        for session in &sessions {
+            let s = session.get();
+            tasks.extend(s.tasks.iter().map(|t| task_node(&t.get(), state.redaction())));
+            objects.extend(
+                s.objects
+                    .iter()
+                    .map(|o| object_node(&o.get(), state.redaction())),
+            );
+        }
+        tasks.sort_by_key(|t| t.id);
+        objects.sort_by_key(|o| o.id);
+
+        let mut workers: Vec<_> = state
+            .graph
+            .workers
+            .values()
+            .map(|w| worker_node(&w.get()))
+            .collect();
+        workers.sort_by_key(|w| w.id);
+
+        GraphSnapshot {
+            version: GRAPH_SNAPSHOT_VERSION,
+            sessions: sessions.iter().map(|s| session_node(&s.get())).collect(),
+            tasks,
+            objects,
+            workers,
+        }
+    }
+}
+
+impl SessionBundle {
+    /// Definition of the task graph of the session; objects of other sessions
+    /// (e.g. of the parent of a sub-session) cannot be exported. Redacted values
+    /// are not exported, such bundles do not reproduce them.
+    pub fn new(session: &Session, redaction: &Redaction) -> Result<Self> {
+        let mut objects: Vec<_> = session
+            .objects
+            .iter()
+            .map(|o| {
+                let o = o.get();
+                BundleObject {
+                    id: o.id.get_id(),
+                    label: o.label.clone(),
+                    data_type: o.data_type,
+                    keep: o.client_keep,
+                    constant: o.producer.is_none(),
+                    attributes: redaction.attributes(&o.attributes),
+                }
+            })
+            .collect();
+        objects.sort_by_key(|o| o.id);
+
+        let mut tasks = Vec::new();
+        for tref in session.tasks.iter() {
+            let t = tref.get();
+            let mut inputs = Vec::new();
+            for input in &t.inputs {
+                let id = input.object.get_id();
+                if id.get_session_id() != session.id {
+                    bail!(
+                        "Task {} uses object {} of another session, it cannot be exported",
+                        t.id,
+                        id
+                    );
+                }
+                inputs.push(BundleInput {
+                    id: id.get_id(),
+                    label: input.label.clone(),
+                    path: input.path.clone(),
+                });
+            }
+            let mut attributes = redaction.attributes(&t.attributes);
+            for name in RESULT_ATTRIBUTES {
+                attributes.remove(name);
+            }
+            tasks.push(BundleTask {
+                id: t.id.get_id(),
+                task_type: t.task_type.clone(),
+                inputs,
+                outputs: t.outputs.iter().map(|o| o.get_id().get_id()).collect(),
+                attributes,
+            });
+        }
+        tasks.sort_by_key(|t| t.id);
+
+        Ok(SessionBundle {
+            version: SESSION_BUNDLE_VERSION,
+            rain_version: ::VERSION.to_string(),
+            env: redaction.env(&session.env).into_iter().collect(),
+            max_running_tasks: session.max_running_tasks,
+            cost_weight: session.cost_weight,
+            objects,
+            tasks,
+        })
+    }
+}
@@ -1,38 +1,76 @@
 use std::net::SocketAddr;
 use std::path::PathBuf;
-use std::time::Duration;
-use std::collections::HashSet;
+use std::time::{Duration, Instant};
+use std::collections::{HashMap, HashSet};
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hash, Hasher};
 
 use futures::{Future, Stream};
 use tokio_core::reactor::Handle;
 use tokio_core::net::{TcpListener, TcpStream};
 use tokio_timer;
+use tokio_io::{AsyncRead, AsyncWrite};
+use tokio_tls::TlsAcceptor;
 
-use errors::Result;
+use errors::{Error, ErrorKind, Result};
 use common::{DataType, RcSet};
-use common::id::{ClientId, DataObjectId, SId, SessionId, TaskId, WorkerId};
-use common::rpc::new_rpc_system;
-use server::graph::{ClientRef, DataObjectRef, DataObjectState, Graph, SessionError, SessionRef,
-                    TaskInput, TaskRef, TaskState, WorkerRef};
+use common::capabilities::{self, Capabilities};
+use common::chaos::ChaosConfig;
+use common::events::{ObjectDescriptor, TaskDescriptor};
+use common::labels::{Constraint, Labels};
+use common::id::{is_provisional, ClientId, DataObjectId, Id, SId, SessionId, TaskId,
+                 WorkerId};
+use common::framing::detect_framing;
+use common::rpc::new_framed_rpc_system;
+use server::graph::{ClientRef, DataObject, DataObjectRef, DataObjectState, Graph, KeptFilter,
+                    SessionError, SessionRef, TaskInput, TaskRef, TaskState, Worker, WorkerRef};
 use server::rpc::ServerBootstrapImpl;
-use server::scheduler::{ReactiveScheduler, UpdatedIn};
-use common::convert::ToCapnp;
+use server::scheduler::{Decision, Placement, ReactiveScheduler, SchedulerConfig, SchedulerStatus,
+                        UpdatedIn, UpdatedOut};
+use server::secrets::SecretStore;
+use server::serializer::Serializer;
+use server::history::{input_bytes, DurationHistory};
+use server::snapshot::GraphSnapshot;
+use server::upgrade::{UpgradePlan, UpgradeStatus};
+use server::release::WorkerRelease;
+use server::access::{AccessControl, Identity};
+use server::alerts::{AlertChange, Alerts, Metric};
+use server::notify::{NotificationKind, Notifier};
+use server::redact::Redaction;
+use common::convert::{FromCapnp, ToCapnp};
 use common::wrapped::WrappedRcRefCell;
-use common::resources::Resources;
+use common::resources::{ResourcePool, Resources};
+use common::units::{self, Span};
 use common::{Attributes, ConsistencyCheck};
+use broker::ShardLoad;
 
 use hyper::server::Http;
-use server::http::RequestHandler;
+use server::http::{HttpConfig, RequestHandler};
 use server::testmode;
 
 use common::logging::logger::Logger;
 use common::logging::sqlite_logger::SQLiteLogger;
 
 const LOGGING_INTERVAL: u64 = 1; // Logging interval in seconds
+const SESSION_EXPIRATION_INTERVAL: u64 = 1; // How often are session TTLs checked (seconds)
+const ALERT_CHECK_INTERVAL: u64 = 10; // How often are alert rules evaluated (seconds)
+const REBALANCE_INTERVAL: u64 = 2; // How often are waiting tasks moved to idle workers (seconds)
+const DRAIN_REPLICATION_LIMIT: usize = 64 * 1024 * 1024; // Max size of replicated object (bytes)
+const DEFAULT_RETRY_DELAY: u64 = 1; // Delay before the first retry of a failed task (seconds)
+const MAX_RETRY_DELAY: u64 = 300; // Limit of the growing delay between retries (seconds)
+
+/// Max number of tasks computed again after an invalidation of an object (`--invalidation-limit`)
+pub const DEFAULT_INVALIDATION_LIMIT: usize = 1000;
 
 /// How long should be ID from worker ignored when it is task/object is unassigned
 const IGNORE_ID_TIME_SECONDS: u64 = 30;
 
+/// Max. number of objects in one deleteObjects call to a worker
+const DELETE_BATCH_SIZE: usize = 4096;
+
+/// Time for a new connection to send its first bytes (framing preamble or bootstrap message)
+const FRAMING_TIMEOUT_SECONDS: u64 = 10;
+
 pub struct State {
     // Contained objects
     pub(super) graph: Graph,
@@ -41,13 +79,46 @@ pub struct State {
     /// from worker
     pub(in super::super) ignored_sessions: HashSet<SessionId>,
 
+    /// Ids of recently cancelled tasks and of their outputs (see `cancel_tasks`),
+    /// they are ignored for incoming messages from worker
+    ignored_tasks: HashSet<TaskId>,
+    ignored_objects: HashSet<DataObjectId>,
+
     /// Tokio core handle.
     handle: Handle,
 
     stop_server: bool,
 
+    /// Scheduling is stopped before shutdown (`stopScheduling`), submits are rejected
+    stopping: bool,
+
+    /// Running (or the last) rolling upgrade of workers
+    upgrade: Option<UpgradePlan>,
+
+    /// Workers released to the broker of session shards
+    release: WorkerRelease,
+
+    /// Fault injection configured by a client, passed also to workers connected later
+    chaos_config: Option<ChaosConfig>,
+
     pub(super) updates: UpdatedIn,
 
+    /// Objects evicted from workers, deletions are sent in batches by `turn()`
+    objects_to_delete: HashMap<WorkerRef, Vec<DataObjectId>>,
+
+    /// Secrets registered by clients, referenced by tasks
+    pub(super) secrets: SecretStore,
+
+    /// Thread serializing large responses to clients
+    serializer: Serializer,
+
+    /// Durations of finished tasks, predictions of durations of submitted tasks
+    duration_history: DurationHistory,
+
+    /// Access tokens of clients (set by --access-file), all clients have
+    /// role admin when it is `None`
+    access: Option<AccessControl>,
+
     /// Workers that will checked by reactor in the next turn()
     underload_workers: RcSet<WorkerRef>,
 
@@ -67,6 +138,56 @@ pub struct State {
 
     /// Listening port for HTTP interface
     http_listen_address: SocketAddr,
+
+    /// TLS of RPC connections (clients and workers), plain TCP when None
+    rpc_tls: Option<TlsAcceptor>,
+
+    /// TLS of HTTP interface (dashboard), independent on RPC
+    http_tls: Option<TlsAcceptor>,
+
+    /// Reverse proxy and CORS settings of HTTP interface
+    pub(super) http_config: HttpConfig,
+
+    /// Interval of TCP keepalive probes on connections, it detects silently
+    /// disappeared clients
+    client_keepalive: Option<Duration>,
+
+    /// How long are sessions of a disconnected client kept before they are cancelled
+    orphan_grace: Duration,
+
+    /// Finished objects up to this size (in bytes) travel inline in messages
+    /// between server and workers
+    inline_threshold: usize,
+
+    /// Random id of this run of the server, workers restore their stored objects
+    /// only when they reconnect to the same instance
+    instance_id: String,
+
+    /// Invalidation of an object is refused when more tasks than this would be computed
+    /// again, 0 disables invalidations
+    invalidation_limit: usize,
+
+    /// Updates are collected and scheduled in one round at most once per this interval,
+    /// zero runs the scheduler after every event
+    scheduling_interval: Duration,
+
+    /// Time of the last scheduling round
+    last_scheduling: Instant,
+
+    /// A wakeup of the main loop for the next scheduling round is pending
+    scheduling_wakeup: bool,
+
+    /// No tasks are scheduled and assigned to workers (`configureScheduler`)
+    scheduling_paused: bool,
+
+    /// Alert rules (set by --alerts)
+    alerts: Option<Alerts>,
+
+    /// Notifier backends (set by --notifications)
+    notifier: Option<Notifier>,
+
+    /// Values of tasks redacted in events, snapshots and exports (set by --redact)
+    redaction: Redaction,
 }
 
 impl State {
@@ -82,37 +203,234 @@ impl State {
             bail!("State already contains worker {}", address);
         }
         let w = WorkerRef::new(address, control, resources);
+        if let Some(ref mut alerts) = self.alerts {
+            alerts.worker_added(&address.ip().to_string());
+        }
         self.graph.workers.insert(w.get_id(), w.clone());
         self.underload_workers.insert(w.clone());
         self.logger.add_new_worker_event(w.get_id());
         Ok(w)
     }
 
+    /// Accept objects (with their sizes) that the worker still holds from its previous run
+    /// connected to this server instance (the worker discards objects of other instances).
+    /// Only finished objects that are still needed, have the same size and have no copy on
+    /// the worker are accepted. Returns ids of accepted objects.
+    pub fn restore_worker_objects(
+        &mut self,
+        worker: &WorkerRef,
+        objects: Vec<(DataObjectId, usize)>,
+    ) -> Vec<DataObjectId> {
+        let mut accepted = Vec::new();
+        for (id, size) in objects {
+            if self.is_object_ignored(&id) {
+                continue;
+            }
+            let oref = match self.graph.objects.get(&id) {
+                Some(o) => o.clone(),
+                None => continue,
+            };
+            {
+                let mut o = oref.get_mut();
+                if o.state != DataObjectState::Finished || !o.is_needed()
+                    || o.assigned.contains(worker)
+                {
+                    continue;
+                }
+                if o.size != Some(size) {
+                    warn!(
+                        "Stored object {} on worker {} has size {}, expected {:?}",
+                        id,
+                        worker.get_id(),
+                        size,
+                        o.size
+                    );
+                    continue;
+                }
+                o.assigned.insert(worker.clone());
+                o.located.insert(worker.clone());
+            }
+            {
+                let mut w = worker.get_mut();
+                w.assigned_objects.insert(oref.clone());
+                w.located_objects.insert(oref.clone());
+            }
+            // Inform the scheduler
+            self.updates
+                .objects
+                .entry(oref.clone())
+                .or_insert(Default::default())
+                .insert(worker.clone());
+            debug!("Object {} restored on worker {}", id, worker.get_id());
+            accepted.push(id);
+        }
+        worker.check_consistency_opt().unwrap(); // non-recoverable
+        accepted
+    }
+
     /// Remove the worker from the graph, forcefully unassigning all tasks and objects.
-    /// TODO: better specs and context of worker removal
-    pub fn remove_worker(&mut self, _worker: &WorkerRef) -> Result<()> {
-        unimplemented!() /*
-            pub fn delete(self, graph: &mut Graph) {
-        debug!("Deleting worker {}", self.get_id());
-        // remove from objects
-        for o in self.get_mut().assigned_objects.iter() {
-            assert!(o.get_mut().assigned.remove(&self));
+    /// Called when the connection to the worker is lost, so no calls are sent to it.
+    /// Tasks scheduled (but not assigned) on the worker are scheduled again, sessions
+    /// of tasks assigned to the worker and sessions that need an object only present
+    /// on the worker are failed.
+    pub fn remove_worker(&mut self, worker: &WorkerRef) -> Result<()> {
+        let worker_id = worker.get_id();
+        debug!("Removing worker {}", worker_id);
+        if worker.get().error.is_none() {
+            worker.get_mut().error = Some("Connection to worker lost".to_string());
         }
-        for o in self.get_mut().located_objects.iter() {
-            assert!(o.get_mut().located.remove(&self));
+        let cause = format!("Worker {} was lost", worker_id);
+
+        let ready: Vec<TaskRef> = worker.get().scheduled_ready_tasks.iter().cloned().collect();
+        for tref in ready {
+            self.reschedule_task(&tref, worker);
         }
-        // remove from tasks
-        for t in self.get_mut().assigned_tasks.iter() {
-            t.get_mut().assigned = None;
+
+        let mut failed: Vec<(SessionRef, TaskId)> = worker
+            .get()
+            .assigned_tasks
+            .iter()
+            .map(|t| {
+                let t = t.get();
+                (t.session.clone(), t.id)
+            })
+            .collect();
+        for oref in worker.get().located_objects.iter() {
+            let o = oref.get();
+            if o.is_needed() && o.located.len() == 1 && o.data.is_none() {
+                let task_id = o.producer
+                    .as_ref()
+                    .map(|p| p.get().id)
+                    .unwrap_or_else(TaskId::invalid);
+                failed.push((o.session.clone(), task_id));
+            }
         }
-        for t in self.get_mut().scheduled_tasks.iter() {
-            t.get_mut().scheduled = None;
+        for (session, task_id) in failed {
+            if self.graph.sessions.contains_key(&session.get_id()) && !session.get().is_failed() {
+                self.fail_session(&session, cause.clone(), None, task_id)?;
+            }
         }
-        // remove from graph
-        graph.workers.remove(&self.get().id).unwrap();
-        // assert that we hold the last reference, then drop it
-        assert_eq!(self.get_num_refs(), 1);
-        */
+
+        // Forget the worker in objects of the remaining sessions
+        let objects: Vec<DataObjectRef> = worker
+            .get()
+            .assigned_objects
+            .iter()
+            .chain(worker.get().scheduled_objects.iter())
+            .cloned()
+            .collect();
+        for oref in objects {
+            let mut o = oref.get_mut();
+            o.assigned.remove(worker);
+            o.located.remove(worker);
+            o.scheduled.remove(worker);
+            if o.state == DataObjectState::Finished && o.assigned.is_empty()
+                && o.data.is_none()
+            {
+                o.state = DataObjectState::Removed;
+            }
+        }
+        {
+            let mut w = worker.get_mut();
+            assert!(w.assigned_tasks.is_empty());
+            assert!(w.scheduled_tasks.is_empty());
+            w.assigned_objects.clear();
+            w.located_objects.clear();
+            w.scheduled_objects.clear();
+            w.active_resources = 0;
+        }
+        self.graph.workers.remove(&worker_id);
+        self.underload_workers.remove(worker);
+        if let Some(ref mut alerts) = self.alerts {
+            let w = worker.get();
+            if !w.leaving && !w.draining {
+                alerts.worker_lost(&worker_id.ip().to_string());
+            }
+        }
+        self.logger.add_worker_removed_event(worker_id, cause);
+        Ok(())
+    }
+
+    /// Take back a task scheduled (but not assigned) on the worker,
+    /// the scheduler places it again
+    fn reschedule_task(&mut self, tref: &TaskRef, worker: &WorkerRef) {
+        debug!("Rescheduling task {} from {}", tref.get_id(), worker.get_id());
+        assert!(tref.get().assigned.is_none());
+        tref.unschedule(&mut self.graph.global_resources);
+        for oref in tref.get().outputs.iter() {
+            oref.get_mut().scheduled.remove(worker);
+            worker.get_mut().scheduled_objects.remove(oref);
+        }
+        for oref in tref.unforward_inputs(worker) {
+            self.update_object_assignments(&oref, Some(worker));
+        }
+        self.updates.tasks.insert(tref.clone());
+    }
+
+    /// Start draining of the worker (e.g. preempted spot instance).
+    /// No new tasks are scheduled to the worker, tasks that are not assigned yet are
+    /// scheduled elsewhere and small needed objects are replicated to other workers.
+    pub fn drain_worker(&mut self, worker: &WorkerRef) {
+        if worker.get().draining {
+            return;
+        }
+        info!("Draining worker {}", worker.get_id());
+        worker.get_mut().draining = true;
+        let ready: Vec<TaskRef> = worker.get().scheduled_ready_tasks.iter().cloned().collect();
+        for tref in ready {
+            self.reschedule_task(&tref, worker);
+        }
+        self.replicate_from_draining(worker);
+    }
+
+    /// Replicate needed objects that are present only on the draining worker
+    /// (up to `DRAIN_REPLICATION_LIMIT` bytes each) to the least loaded other worker
+    fn replicate_from_draining(&mut self, worker: &WorkerRef) {
+        let objects: Vec<DataObjectRef> = worker
+            .get()
+            .located_objects
+            .iter()
+            .filter(|o| {
+                let o = o.get();
+                o.is_needed() && o.assigned.len() == 1 && o.data.is_none()
+                    && o.size.map(|s| s <= DRAIN_REPLICATION_LIMIT).unwrap_or(false)
+            })
+            .cloned()
+            .collect();
+        for oref in objects {
+            let target = self.graph
+                .workers
+                .values()
+                .filter(|w| {
+                    let w = w.get();
+                    !w.draining && w.error.is_none() && oref.get().is_allowed_on(&w)
+                })
+                .min_by_key(|w| w.get().active_resources)
+                .cloned();
+            if let Some(wref) = target {
+                debug!(
+                    "Replicating object {} from draining worker {} to {}",
+                    oref.get_id(),
+                    worker.get_id(),
+                    wref.get_id()
+                );
+                wref.get_mut().scheduled_objects.insert(oref.clone());
+                oref.get_mut().scheduled.insert(wref.clone());
+                self.assign_object(&oref, &wref);
+            }
+        }
+    }
+
+    /// Check whether the draining worker may leave without losing running tasks
+    /// or replicable objects; replicates objects that were finished meanwhile.
+    pub fn drain_status(&mut self, worker: &WorkerRef) -> bool {
+        self.replicate_from_draining(worker);
+        let w = worker.get();
+        w.assigned_tasks.is_empty() && w.located_objects.iter().all(|o| {
+            let o = o.get();
+            !o.is_needed() || o.located.len() > 1 || o.data.is_some()
+                || o.size.map(|s| s > DRAIN_REPLICATION_LIMIT).unwrap_or(false)
+        })
     }
 
     /// Put the worker into a failed state, unassigning all tasks and objects.
@@ -128,21 +446,24 @@ impl State {
     }
 
     /// Add new client, register it in the graph
-    pub fn add_client(&mut self, address: SocketAddr) -> Result<ClientRef> {
-        debug!("New client {}", address);
+    pub fn add_client(&mut self, address: SocketAddr, identity: &str) -> Result<ClientRef> {
+        debug!("New client {} ({})", address, identity);
         if self.graph.clients.contains_key(&address) {
             bail!("State already contains client {}", address);
         }
-        let c = ClientRef::new(address);
+        let c = ClientRef::new(address, identity.to_string());
         self.graph.clients.insert(c.get().id, c.clone());
         self.logger.add_new_client_event(c.get().id);
         Ok(c)
     }
 
-    /// Remove Client and its (owned) sessions. Called on client disconnect,
+    /// Handle disconnection of the client. Called on client disconnect,
     /// so assume the client is inaccesible.
+    /// Detached sessions keep running until their tasks are finished, other sessions
+    /// are removed after the grace period (immediately by default).
+    /// The client is removed from the graph together with its last session.
     pub fn remove_client(&mut self, client: &ClientRef) -> Result<()> {
-        // remove owned sessions
+        client.get_mut().disconnected = true;
         let sessions = client
             .get()
             .sessions
@@ -150,15 +471,52 @@ impl State {
             .map(|x| x.clone())
             .collect::<Vec<_>>();
         for s in sessions {
-            self.remove_session(&s)?;
+            if !self.graph.sessions.contains_key(&s.get_id()) {
+                // Sub-session already removed with its parent
+                continue;
+            }
+            let future = if s.get().detach_on_disconnect && !s.get().is_failed() {
+                info!("Session {} detached from disconnected client", s.get_id());
+                Box::new(s.get_mut().wait().then(|_| Ok(())))
+                    as Box<Future<Item = (), Error = ()>>
+            } else if self.orphan_grace > Duration::from_secs(0) {
+                info!(
+                    "Session {} orphaned, it will be removed in {:?}",
+                    s.get_id(),
+                    self.orphan_grace
+                );
+                Box::new(
+                    ::tokio_core::reactor::Timeout::new(self.orphan_grace, &self.handle)?
+                        .then(|_| Ok(())),
+                )
+            } else {
+                self.remove_session(&s)?;
+                continue;
+            };
+            let state_ref = self.self_ref.clone().unwrap();
+            self.handle.spawn(future.then(move |_| {
+                let mut state = state_ref.get_mut();
+                if state.graph.sessions.contains_key(&s.get_id()) {
+                    state.remove_session(&s).unwrap();
+                }
+                Ok(())
+            }));
         }
-        // remove from graph
-        self.graph.clients.remove(&client.get_id()).unwrap();
-        self.logger
-            .add_removed_client_event(client.get_id(), String::from("client disconnected"));
+        self.forget_client_if_done(client);
         Ok(())
     }
 
+    /// Remove the disconnected client from the graph if it has no sessions left
+    fn forget_client_if_done(&mut self, client: &ClientRef) {
+        if !client.get().disconnected || !client.get().sessions.is_empty() {
+            return;
+        }
+        if self.graph.clients.remove(&client.get_id()).is_some() {
+            self.logger
+                .add_removed_client_event(client.get_id(), String::from("client disconnected"));
+        }
+    }
+
     /// Create a new session fr a client, register it in the graph.
     pub fn add_session(&mut self, client: &ClientRef) -> Result<SessionRef> {
         let s = SessionRef::new(self.graph.new_session_id(), client);
@@ -168,6 +526,30 @@ impl State {
         Ok(s)
     }
 
+    /// Create a new sub-session of the given session (for the same client).
+    pub fn add_sub_session(&mut self, parent: &SessionRef, name: String) -> Result<SessionRef> {
+        if parent.get().is_failed() {
+            bail!("Session {} is failed", parent.get_id());
+        }
+        let client = parent.get().client.clone();
+        let s = self.add_session(&client)?;
+        {
+            let mut session = s.get_mut();
+            session.name = name;
+            session.parent = Some(parent.clone());
+            session.env = parent.get().env.clone();
+            session.tags = parent.get().tags.clone();
+            session.output_policy = parent.get().output_policy.clone();
+        }
+        parent.get_mut().children.insert(s.clone());
+        debug!(
+            "Session {} created as sub-session of {}",
+            s.get_id(),
+            parent.get_id()
+        );
+        Ok(s)
+    }
+
     /// Helper for .remove_session() and .fail_session(). Remove all session tasks,
     /// objects and cancel all finish hooks.
     fn clear_session(&mut self, s: &SessionRef) -> Result<()> {
@@ -187,23 +569,100 @@ impl State {
             .map_err(|e| panic!("Cleaning ignored id failed {:?}", e));
         self.handle.spawn(clean_id_future);
 
+        // Published names of the session are withdrawn
+        for o in s.get().objects.iter() {
+            let mut o = o.get_mut();
+            if let Some(name) = o.published.take() {
+                debug!("Unpublishing object {} ({})", o.id, name);
+                self.graph.published.remove(&name);
+            }
+        }
+        s.get_mut().cleared = true;
+
         let tasks = s.get_mut().tasks.clone();
         for t in tasks {
-            t.unschedule();
+            t.unschedule(&mut self.graph.global_resources);
             self.updates.remove_task(&t);
             self.remove_task(&t)?;
         }
+
+        // Finished objects used by other sessions are kept until these sessions end,
+        // sessions that use other objects of the session cannot continue
+        let mut dependent_tasks = Vec::new();
+        for o in s.get().objects.iter() {
+            let o = o.get();
+            let retained = is_retained(&o);
+            if retained {
+                debug!(
+                    "Object {} is kept for {} other session(s)",
+                    o.id,
+                    o.user_sessions.len()
+                );
+            }
+            for t in o.consumers.iter() {
+                let session = t.get().session.clone();
+                if session != *s && !(retained && o.user_sessions.contains(&session)) {
+                    dependent_tasks.push((t.clone(), o.id));
+                }
+            }
+        }
+        for (t, object_id) in dependent_tasks {
+            let (session, task_id) = {
+                let t = t.get();
+                (t.session.clone(), t.id)
+            };
+            if !session.get().is_failed() {
+                self.fail_session(
+                    &session,
+                    format!("Published object {} was removed", object_id),
+                    None,
+                    task_id,
+                )?;
+            }
+        }
+
         let objects = s.get_mut().objects.clone();
         for o in objects {
             o.get_mut().client_keep = false;
+            if is_retained(&o.get()) {
+                continue;
+            }
             o.unschedule();
             self.remove_object(&o)?;
         }
+        self.release_published_inputs(s)?;
         // Remove all finish hooks
         s.get_mut().finish_hooks.clear();
         Ok(())
     }
 
+    /// Helper for .clear_session(). The session stops using published objects
+    /// of other sessions, objects of cleared sessions are removed when they are not
+    /// used by any session.
+    fn release_published_inputs(&mut self, s: &SessionRef) -> Result<()> {
+        let objects: Vec<DataObjectRef> = s.get_mut().used_published.drain().collect();
+        for o in objects {
+            let unused = {
+                let mut obj = o.get_mut();
+                obj.user_sessions.remove(s);
+                obj.user_sessions.is_empty()
+            };
+            if !unused {
+                continue;
+            }
+            let owner = o.get().session.clone();
+            if owner.get().cleared {
+                debug!("Removing object {} of closed session {}", o.get_id(), owner.get_id());
+                o.unschedule();
+                self.remove_object(&o)?;
+            } else if !o.get().is_needed() {
+                o.unschedule();
+                self.update_object_assignments(&o, None);
+            }
+        }
+        Ok(())
+    }
+
     /// Remove a session and all the tasks and objects, both from the graph and from the workers,
     /// cancel all the finish hooks.
     pub fn remove_session(&mut self, session: &SessionRef) -> Result<()> {
@@ -212,15 +671,27 @@ impl State {
             session.get_id(),
             session.get().client.get_id()
         );
+        // remove sub-sessions first, they may use objects of this session
+        let children: Vec<SessionRef> = session.get().children.iter().cloned().collect();
+        for child in children {
+            self.remove_session(&child)?;
+        }
         // remove children objects
         let has_error = session.get().error.is_some();
-        if !has_error {
+        if !has_error && session.get().unfinished_tasks == 0 {
+            if let Some(ref mut alerts) = self.alerts {
+                alerts.session_finished(false);
+            }
+        }
+        if !session.get().cleared {
             self.clear_session(session)?;
         }
         // remove from graph
         self.graph.sessions.remove(&session.get_id()).unwrap();
         // unlink
         session.unlink();
+        let client = session.get().client.clone();
+        self.forget_client_if_done(&client);
         Ok(())
     }
 
@@ -234,6 +705,21 @@ impl State {
         cause: String,
         debug: Option<String>,
         task_id: TaskId,
+    ) -> Result<()> {
+        self.fail_session_with_failure(session, cause, debug, task_id, None, false)
+    }
+
+    /// Fail the session because of a failed task with failure digest `failure` (JSON).
+    /// When `task_failed` is true and the session keeps failed tasks (`keep_failed`),
+    /// its tasks are only stopped and they may be retried by `retry_failed`.
+    fn fail_session_with_failure(
+        &mut self,
+        session: &SessionRef,
+        cause: String,
+        debug: Option<String>,
+        task_id: TaskId,
+        failure: Option<String>,
+        task_failed: bool,
     ) -> Result<()> {
         debug!(
             "Failing session {} of client {} with cause {:?}",
@@ -242,11 +728,228 @@ impl State {
             cause
         );
         assert!(session.get_mut().error.is_none());
-        session.get_mut().error = Some(SessionError::new(cause, debug, task_id));
+        if let Some(ref notifier) = self.notifier {
+            let mut body = format!("Error: {}\nTask: {}\n", cause, task_id);
+            if let Some(ref debug) = debug {
+                body.push_str(&format!("Debug: {}\n", debug));
+            }
+            if let Some(ref failure) = failure {
+                body.push_str(&format!("Failure digest: {}\n", failure));
+            }
+            notifier.notify(
+                NotificationKind::SessionFailed,
+                format!("Session {} '{}' failed", session.get_id(), session.get().name),
+                body,
+            );
+        }
+        let mut error = SessionError::new(cause, debug, task_id);
+        error.set_failure(failure);
+        session.get_mut().error = Some(error);
+        session.get_mut().notify_watchers();
+        if let Some(ref mut alerts) = self.alerts {
+            alerts.session_finished(true);
+        }
+        let stop = (task_failed && session.get().keep_failed)
+            || (session.get().expired && session.get().expired_retention.is_some());
+        if stop {
+            self.stop_session(session);
+            return Ok(());
+        }
         // Remove all tasks + objects (with their finish hooks)
         self.clear_session(session)
     }
 
+    /// Helper for .fail_session_with_failure() of sessions that keep failed tasks
+    /// and of expired sessions that retain their objects.
+    /// Unfinished tasks are stopped and finished objects are kept, so the session
+    /// may continue after its failed tasks are retried. Cancels all finish hooks.
+    fn stop_session(&mut self, s: &SessionRef) {
+        debug!("Stopping session {}", s.get_id());
+        let tasks: Vec<TaskRef> = s.get().tasks.iter().cloned().collect();
+        for tref in &tasks {
+            let (state, scheduled) = {
+                let t = tref.get();
+                (t.state, t.scheduled.clone())
+            };
+            match state {
+                TaskState::NotAssigned | TaskState::Finished => (),
+                // Ready tasks stay known to the scheduler, it holds them back
+                // until the session is retried
+                TaskState::Ready => if let Some(wref) = scheduled {
+                    self.reschedule_task(tref, &wref);
+                },
+                TaskState::Assigned | TaskState::Running => {
+                    tref.unschedule(&mut self.graph.global_resources);
+                    for oref in &tref.get().outputs {
+                        oref.unschedule();
+                    }
+                    self.unassign_task(tref);
+                    self.updates.tasks.insert(tref.clone());
+                }
+                TaskState::Failed => {
+                    tref.unschedule(&mut self.graph.global_resources);
+                    let outputs = tref.get().outputs.clone();
+                    for oref in &outputs {
+                        oref.unschedule();
+                    }
+                    let assigned = tref.get_mut().assigned.take();
+                    if let Some(wref) = assigned {
+                        wref.get_mut().assigned_tasks.remove(tref);
+                        for oref in &outputs {
+                            if oref.get().assigned.contains(&wref) {
+                                self.unassign_object(oref, &wref);
+                            }
+                        }
+                    }
+                }
+            }
+            tref.get_mut().finish_hooks.clear();
+        }
+        for oref in s.get().objects.iter() {
+            oref.get_mut().finish_hooks.clear();
+        }
+        s.get_mut().finish_hooks.clear();
+    }
+
+    /// Retry failed tasks of a session stopped after a failure (see `keep_failed`),
+    /// only failed tasks with all the tags are retried when `tags` is not empty.
+    /// Finished objects of the session are reused, retried tasks wait only for their
+    /// unfinished inputs. When no failed task remains, the error of the session is cleared
+    /// and its stopped tasks are scheduled again. Returns ids of the retried tasks.
+    pub fn retry_failed(&mut self, session: &SessionRef, tags: &[String]) -> Result<Vec<TaskId>> {
+        if session.get().expired {
+            bail!("Session {} expired, it cannot be retried", session.get_id());
+        }
+        if !session.get().is_retryable() {
+            bail!(
+                "Session {} has no failed tasks to retry (it has not failed or it did not \
+                 keep failed tasks)",
+                session.get_id()
+            );
+        }
+        // Data of finished objects lost together with a worker are not computed again
+        for tref in session.get().tasks.iter() {
+            let t = tref.get();
+            if t.state == TaskState::Finished {
+                continue;
+            }
+            for input in &t.inputs {
+                let o = input.object.get();
+                if o.state == DataObjectState::Removed && o.data.is_none() {
+                    bail!(
+                        "Object {} needed by task {} was lost, session {} cannot be retried",
+                        o.id,
+                        t.id,
+                        session.get_id()
+                    );
+                }
+            }
+        }
+
+        let failed: Vec<TaskRef> = session
+            .get()
+            .tasks
+            .iter()
+            .filter(|tref| {
+                let t = tref.get();
+                t.state == TaskState::Failed && t.has_tags(tags)
+            })
+            .cloned()
+            .collect();
+        info!(
+            "Retrying {} failed tasks of session {}",
+            failed.len(),
+            session.get_id()
+        );
+        for tref in &failed {
+            {
+                let mut t = tref.get_mut();
+                let waiting_for = t.inputs
+                    .iter()
+                    .filter(|i| i.object.get().state == DataObjectState::Unfinished)
+                    .map(|i| i.object.clone())
+                    .collect();
+                t.waiting_for = waiting_for;
+                for key in &["error", "debug", "failure"] {
+                    t.attributes.remove(key);
+                }
+                // Automatic retries (attribute "max_retries") start again
+                t.retries = 0;
+                t.set_state(TaskState::NotAssigned);
+            }
+            let group = tref.get().group.clone();
+            if let Some(name) = group {
+                session.get_mut().groups.get_mut(&name).unwrap().task_retried(tref);
+            }
+            self.update_task_assignment(tref);
+        }
+
+        let resumed = !session
+            .get()
+            .tasks
+            .iter()
+            .any(|t| t.get().state == TaskState::Failed);
+        if resumed {
+            info!("Session {} continues after retry", session.get_id());
+            session.get_mut().error = None;
+            session.get_mut().notify_watchers();
+            self.updates.sessions.insert(session.clone());
+        }
+        Ok(failed.iter().map(|t| t.get_id()).collect())
+    }
+
+    /// Fail all sessions whose time-to-live is exceeded. Remaining tasks are cancelled,
+    /// objects of the sessions (including kept ones) are removed, or they are kept
+    /// for the retention of the session (`expired_retention`) and removed afterwards.
+    pub fn expire_sessions(&mut self) -> Result<()> {
+        let now = Instant::now();
+        let expired: Vec<SessionRef> = self.graph
+            .sessions
+            .values()
+            .filter(|s| {
+                let s = s.get();
+                !s.cleared && s.is_expired(now)
+            })
+            .cloned()
+            .collect();
+        for session in expired {
+            if session.get().is_failed() {
+                // Stopped session waiting for a retry or retaining objects after
+                // its expiration, its tasks and objects are removed
+                info!("Failed session {} expired", session.get_id());
+                session.get_mut().expires = None;
+                self.clear_session(&session)?;
+                continue;
+            }
+            let unfinished_tasks = session.get().unfinished_tasks;
+            info!(
+                "Session {} expired ({} unfinished tasks)",
+                session.get_id(),
+                unfinished_tasks
+            );
+            self.logger
+                .add_session_expired_event(session.get_id(), unfinished_tasks);
+            session.get_mut().expires = None;
+            session.get_mut().expired = true;
+            self.fail_session(
+                &session,
+                "Session expired".to_string(),
+                None,
+                TaskId::invalid(),
+            )?;
+            let retention = session.get().expired_retention;
+            if let Some(retention) = retention {
+                debug!(
+                    "Objects of expired session {} are retained for {:?}",
+                    session.get_id(),
+                    retention
+                );
+                session.get_mut().expires = Some(now + retention);
+            }
+        }
+        Ok(())
+    }
+
     /// Add a new object, register it in the graph and the session.
     pub fn add_object(
         &mut self,
@@ -259,9 +962,17 @@ impl State {
         attributes: Attributes,
     ) -> Result<DataObjectRef> {
         if self.graph.objects.contains_key(&id) {
-            bail!("State already contains object with id {}", id);
+            bail!(ErrorKind::IdCollision(Vec::new(), vec![id]));
+        }
+        if is_provisional(id.get_id()) {
+            bail!("Object {} has a provisional id that was not assigned", id);
         }
+        let pinned: Vec<String> = attributes.find("placement")?.unwrap_or_default();
+        let size_hint: Option<f64> = attributes.find("size_hint")?;
         let oref = DataObjectRef::new(session, id, client_keep, label, data_type, data, attributes);
+        oref.get_mut().pinned = pinned;
+        oref.get_mut().size_hint = size_hint.map(|s| s as usize);
+        session.get_mut().use_id(id.get_id());
         // add to graph
         self.graph.objects.insert(oref.get_id(), oref.clone());
         // add to updated objects
@@ -277,7 +988,7 @@ impl State {
                                                // unassign the object
         let ws = oref.get().assigned.clone();
         for w in ws {
-            self.unassign_object(oref, &w);
+            self.evict_object(oref, &w);
         }
         // unlink from owner, consistency checks
         oref.unlink();
@@ -299,7 +1010,51 @@ impl State {
         resources: Resources,
     ) -> Result<TaskRef> {
         if self.graph.tasks.contains_key(&id) {
-            bail!("Task {} already in the graph", id);
+            bail!(ErrorKind::IdCollision(vec![id], Vec::new()));
+        }
+        if is_provisional(id.get_id()) {
+            bail!("Task {} has a provisional id that was not assigned", id);
+        }
+        let group: Option<String> = attributes.find("group")?;
+        let preemptible: bool = attributes.find("preemptible")?.unwrap_or(false);
+        let priority: i32 = attributes.find("priority")?.unwrap_or(0);
+        let duration = attributes
+            .find::<Span>("duration")?
+            .map(|s| units::duration_secs(s.0));
+        let mut tags = session.get().tags.clone();
+        for (key, value) in attributes.find::<Labels>("tags")?.unwrap_or_default() {
+            if key.is_empty() || key.contains('=') {
+                bail!("Invalid tag key '{}'", key);
+            }
+            tags.insert(key, value);
+        }
+        let spread: Option<String> = attributes.find("spread")?;
+        let colocate: Option<String> = attributes.find("colocate")?;
+        let constraints = attributes
+            .find::<Vec<String>>("constraints")?
+            .unwrap_or_default()
+            .iter()
+            .map(|c| Constraint::parse(c))
+            .collect::<Result<Vec<_>>>()?;
+        let arch: Option<String> = attributes.find("arch")?;
+        let max_retries: u32 = attributes.find("max_retries")?.unwrap_or(0);
+        let retry_delay = attributes
+            .find::<Span>("retry_delay")?
+            .map(|s| s.0)
+            .unwrap_or_else(|| Duration::from_secs(DEFAULT_RETRY_DELAY));
+        let embedded_script = task_type == "!run"
+            && attributes
+                .find::<::serde_json::Value>("config")?
+                .map(|config| !config["script"].is_null())
+                .unwrap_or(false);
+        // Only checked here, it is merged with environment of the session on assignment
+        attributes.find::<HashMap<String, String>>("env")?;
+        let secrets: HashMap<String, String> = attributes.find("secrets")?.unwrap_or_default();
+        let owner = session.get().client.get().identity.clone();
+        for name in secrets.values() {
+            if !self.secrets.contains(&owner, name) {
+                bail!("Secret '{}' not found", name);
+            }
         }
         let tref = TaskRef::new(
             session,
@@ -310,6 +1065,45 @@ impl State {
             attributes,
             resources,
         )?;
+        // Published objects of other sessions are kept while the session uses them
+        for input in tref.get().inputs.iter() {
+            let foreign = {
+                let o = input.object.get();
+                o.session != *session && !session.has_ancestor(&o.session)
+            };
+            if foreign {
+                input
+                    .object
+                    .get_mut()
+                    .user_sessions
+                    .insert(session.clone());
+                session
+                    .get_mut()
+                    .used_published
+                    .insert(input.object.clone());
+            }
+        }
+        if let Some(name) = group {
+            session
+                .get_mut()
+                .groups
+                .entry(name.clone())
+                .or_insert_with(Default::default)
+                .add_task(&tref);
+            tref.get_mut().group = Some(name);
+        }
+        tref.get_mut().preemptible = preemptible;
+        tref.get_mut().priority = priority;
+        tref.get_mut().duration = duration;
+        tref.get_mut().constraints = constraints;
+        tref.get_mut().arch = arch;
+        tref.get_mut().tags = self.graph.tags.add(&tref, &tags);
+        tref.get_mut().spread = spread;
+        tref.get_mut().colocate = colocate;
+        tref.get_mut().embedded_script = embedded_script;
+        tref.get_mut().max_retries = max_retries;
+        tref.get_mut().retry_delay = retry_delay;
+        session.get_mut().use_id(id.get_id());
         // add to graph
         self.graph.tasks.insert(tref.get_id(), tref.clone());
         // add to scheduler updates
@@ -328,8 +1122,9 @@ impl State {
         if tref.get().assigned.is_some() {
             self.unassign_task(tref);
         }
+        self.graph.tags.remove(tref, &tref.get().tags);
         // Unlink from parent and objects.
-        tref.unlink();
+        tref.unlink(&mut self.graph.global_resources);
         // Remove from graph
         self.graph.tasks.remove(&tref.get_id()).unwrap();
         Ok(())
@@ -338,11 +1133,37 @@ impl State {
     #[inline]
     pub fn is_task_ignored(&self, task_id: &TaskId) -> bool {
         self.ignored_sessions.contains(&task_id.get_session_id())
+            || self.ignored_tasks.contains(task_id)
     }
 
     #[inline]
     pub fn is_object_ignored(&self, object_id: &DataObjectId) -> bool {
-        self.ignored_sessions.contains(&object_id.get_session_id())
+        // objects of a cleared session that are kept for other sessions are not ignored
+        (self.ignored_sessions.contains(&object_id.get_session_id())
+            && !self.graph.objects.contains_key(object_id))
+            || self.ignored_objects.contains(object_id)
+    }
+
+    /// Ignore incoming messages from workers about removed tasks and objects
+    /// for IGNORE_ID_TIME_SECONDS
+    fn ignore_ids(&mut self, task_ids: Vec<TaskId>, object_ids: Vec<DataObjectId>) {
+        self.ignored_tasks.extend(task_ids.iter().cloned());
+        self.ignored_objects.extend(object_ids.iter().cloned());
+        let state_ref = self.self_ref.clone().unwrap();
+        let duration = ::std::time::Duration::from_secs(IGNORE_ID_TIME_SECONDS);
+        let clean_id_future = self.timer
+            .sleep(duration)
+            .map(move |()| {
+                let mut state = state_ref.get_mut();
+                for id in task_ids {
+                    state.ignored_tasks.remove(&id);
+                }
+                for id in object_ids {
+                    state.ignored_objects.remove(&id);
+                }
+            })
+            .map_err(|e| panic!("Cleaning ignored id failed {:?}", e));
+        self.handle.spawn(clean_id_future);
     }
 
     pub fn worker_by_id(&self, id: WorkerId) -> Result<WorkerRef> {
@@ -366,6 +1187,18 @@ impl State {
         }
     }
 
+    /// Future resolved by the next change of the session (a task is submitted or changes
+    /// its state, the session fails) or when `timeout` expires
+    pub fn watch_session(
+        &self,
+        session: &SessionRef,
+        timeout: Duration,
+    ) -> Box<Future<Item = (), Error = ()>> {
+        let changed = session.get_mut().watch().map_err(|_| ());
+        let expired = self.timer.sleep(timeout).map_err(|_| ());
+        Box::new(changed.select(expired).map(|_| ()).map_err(|_| ()))
+    }
+
     pub fn object_by_id(&self, id: DataObjectId) -> Result<DataObjectRef> {
         match self.graph.objects.get(&id) {
             Some(o) => Ok(o.clone()),
@@ -380,8 +1213,11 @@ impl State {
         match self.graph.objects.get(&id) {
             Some(o) => {
                 let obj = o.get();
-                if obj.session.get().is_failed() {
-                    return Err(obj.session.get().get_error().clone().unwrap().into());
+                let session = obj.session.get();
+                // finished objects retained after expiration of the session may be used
+                let retained = session.expired && obj.state == DataObjectState::Finished;
+                if session.is_failed() && !retained {
+                    return Err(session.get_error().clone().unwrap().into());
                 }
                 Ok(o.clone())
             }
@@ -426,6 +1262,52 @@ impl State {
         }
     }
 
+    /// Check that ids of submitted tasks and objects are not used in their sessions
+    /// and that they are not repeated in the submit (provisional ids included)
+    pub fn check_submitted_ids(&self, tasks: &[TaskId], objects: &[DataObjectId]) -> Result<()> {
+        let mut seen_tasks = HashSet::new();
+        let task_collisions: Vec<TaskId> = tasks
+            .iter()
+            .filter(|id| !seen_tasks.insert(**id) || self.graph.tasks.contains_key(id))
+            .cloned()
+            .collect();
+        let mut seen_objects = HashSet::new();
+        let object_collisions: Vec<DataObjectId> = objects
+            .iter()
+            .filter(|id| !seen_objects.insert(**id) || self.graph.objects.contains_key(id))
+            .cloned()
+            .collect();
+        if !task_collisions.is_empty() || !object_collisions.is_empty() {
+            bail!(ErrorKind::IdCollision(task_collisions, object_collisions));
+        }
+        Ok(())
+    }
+
+    /// Hold submitted tasks until their sessions are flushed (submit with `hold`)
+    pub fn hold_tasks(&mut self, tasks: &[TaskRef]) {
+        for tref in tasks {
+            let mut t = tref.get_mut();
+            t.held = true;
+            t.session.get_mut().held_tasks.push(tref.clone());
+        }
+    }
+
+    /// Release tasks of the session held by submits with `hold`
+    pub fn flush_session(&mut self, session: &SessionRef) {
+        let held = ::std::mem::replace(&mut session.get_mut().held_tasks, Vec::new());
+        debug!("Session {} flushed, {} tasks released", session.get_id(), held.len());
+        let now = Instant::now();
+        for tref in held {
+            let mut t = tref.get_mut();
+            t.held = false;
+            if t.state == TaskState::Ready {
+                t.ready_since = Some(now);
+            }
+        }
+        // Released ready tasks are already known to the scheduler, it only needs to run
+        self.updates.sessions.insert(session.clone());
+    }
+
     /// Verify submit integrity: all objects have either data or producers, acyclicity.
     pub fn verify_submit(&mut self, tasks: &[TaskRef], objects: &[DataObjectRef]) -> Result<()> {
         // TODO: Check acyclicity
@@ -444,13 +1326,149 @@ impl State {
                 bail!("Object {} submitted with neither producer nor data.", o.id);
             }
         }
-        // Verify every submitted object
-        for oref in objects.iter() {
-            oref.check_consistency()?;
+        // Every task has to fit into some of registered workers
+        if !self.graph.workers.is_empty() {
+            let infeasible: Vec<TaskId> = tasks
+                .iter()
+                .filter(|tref| {
+                    let t = tref.get();
+                    !self.graph
+                        .workers
+                        .values()
+                        .any(|w| t.resources.is_subset_of(&w.get().resources))
+                })
+                .map(|tref| tref.get_id())
+                .collect();
+            if !infeasible.is_empty() {
+                // The most of each resource (possibly of different workers)
+                let largest = self.graph.workers.values().fold(
+                    Resources::default(),
+                    |mut largest, w| {
+                        let w = w.get();
+                        largest.cpus = ::std::cmp::max(largest.cpus, w.resources.cpus);
+                        largest.memory = ::std::cmp::max(largest.memory, w.resources.memory);
+                        largest.gpus = ::std::cmp::max(largest.gpus, w.resources.gpus);
+                        for (name, &count) in &w.resources.named {
+                            let largest = largest.named.entry(name.clone()).or_insert(0);
+                            *largest = ::std::cmp::max(*largest, count);
+                        }
+                        largest
+                    },
+                );
+                bail!(ErrorKind::InfeasibleTasks(infeasible, largest));
+            }
         }
-        // Verify every submitted task
-        for tref in tasks.iter() {
-            tref.check_consistency()?;
+        // Placement constraints (and the architecture) have to be satisfied by a worker
+        // with enough resources
+        if !self.graph.workers.is_empty() {
+            let unplaceable: Vec<TaskId> = tasks
+                .iter()
+                .filter(|tref| {
+                    let t = tref.get();
+                    (!t.constraints.is_empty() || t.arch.is_some())
+                        && !self.graph.workers.values().any(|w| {
+                            let w = w.get();
+                            t.unsatisfied_constraint(&w).is_none()
+                                && t.unsatisfied_arch(&w).is_none()
+                                && t.resources.is_subset_of(&w.resources)
+                        })
+                })
+                .map(|tref| tref.get_id())
+                .collect();
+            if !unplaceable.is_empty() {
+                bail!(
+                    "Tasks {} have placement constraints or architecture that no worker \
+                     with enough resources satisfies",
+                    unplaceable
+                        .iter()
+                        .map(|t| t.to_string())
+                        .collect::<Vec<_>>()
+                        .join(", ")
+                );
+            }
+        }
+        // Global resources have to exist and have enough capacity
+        let infeasible: Vec<TaskId> = tasks
+            .iter()
+            .filter(|tref| !self.graph.global_resources.is_feasible(&tref.get().resources))
+            .map(|tref| tref.get_id())
+            .collect();
+        if !infeasible.is_empty() {
+            bail!(
+                "Tasks {} request unknown global resources or more than their capacity",
+                infeasible
+                    .iter()
+                    .map(|t| t.to_string())
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            );
+        }
+        // Pinned objects must be placeable on a worker that is able to run the producer
+        if !self.graph.workers.is_empty() {
+            for oref in objects.iter() {
+                let o = oref.get();
+                if o.pinned.is_empty() {
+                    continue;
+                }
+                let allowed: Vec<WorkerRef> = self.graph
+                    .workers
+                    .values()
+                    .filter(|w| match o.producer {
+                        Some(ref tref) => tref.get().is_allowed_on(&w.get()),
+                        None => o.is_allowed_on(&w.get()),
+                    })
+                    .cloned()
+                    .collect();
+                if allowed.is_empty() {
+                    bail!(
+                        "Object {} is pinned to {:?}, but no such worker is available \
+                         (or the pins conflict with pins of other outputs of its producer)",
+                        o.id,
+                        o.pinned
+                    );
+                }
+                if let Some(ref tref) = o.producer {
+                    let t = tref.get();
+                    if !allowed
+                        .iter()
+                        .any(|w| t.resources.is_subset_of(&w.get().resources))
+                    {
+                        if allowed.len() == 1 {
+                            let w = allowed[0].get();
+                            bail!(
+                                "Object {} is pinned to {:?}, but the only matching worker {} can \
+                                 never satisfy resources of its producer task {} (task needs {:?}, \
+                                 worker has {:?})",
+                                o.id,
+                                o.pinned,
+                                w.id(),
+                                t.id,
+                                t.resources,
+                                w.resources
+                            );
+                        }
+                        bail!(
+                            "Object {} is pinned to {:?}, but none of these workers has enough \
+                             resources for its producer task {} ({} cpus, {} bytes of memory, \
+                             {} gpus)",
+                            o.id,
+                            o.pinned,
+                            t.id,
+                            t.resources.cpus(),
+                            t.resources.memory(),
+                            t.resources.gpus()
+                        );
+                    }
+                }
+            }
+        }
+        // Verify every submitted object
+        for oref in objects.iter() {
+            oref.check_consistency()?;
+        }
+        // Verify every submitted task
+        for tref in tasks.iter() {
+            tref.check_consistency()?;
         }
 
         self.check_consistency_opt().unwrap(); // non-recoverable
@@ -472,7 +1490,7 @@ impl State {
             let mut new_objects = req.get().init_new_objects(1);
             let mut co = &mut new_objects.borrow().get(0);
             let o = object.get();
-            o.to_worker_capnp(&mut co);
+            o.to_worker_capnp(&mut co, self.inline_threshold_for(&wref.get()));
             let placement = o.located
                 .iter()
                 .next()
@@ -512,11 +1530,49 @@ impl State {
     /// Panics if the object is not assigned on the worker.
     pub fn unassign_object(&mut self, object: &DataObjectRef, wref: &WorkerRef) {
         debug!("unassign_object {:?} at {:?}", object, wref);
+        self.detach_object(object, wref);
+        self.send_unassign_object(object, wref);
+    }
+
+    /// Unassign an object that is not needed anymore from a worker and delete its data
+    /// there. Deletions are batched and sent by `send_object_deletes()`.
+    /// Panics if the object is not assigned on the worker.
+    pub fn evict_object(&mut self, object: &DataObjectRef, wref: &WorkerRef) {
+        debug!("evict_object {:?} at {:?}", object, wref);
+        self.detach_object(object, wref);
+        if wref.get().error.is_none() {
+            self.objects_to_delete
+                .entry(wref.clone())
+                .or_insert_with(Vec::new)
+                .push(object.get_id());
+        }
+    }
+
+    /// Remove the assignment of the object to the worker from the graph
+    fn detach_object(&mut self, object: &DataObjectRef, wref: &WorkerRef) {
         assert!(object.get().assigned.contains(wref));
         object.check_consistency_opt().unwrap(); // non-recoverable
         wref.check_consistency_opt().unwrap(); // non-recoverable
 
-        // Create request
+        object.get_mut().assigned.remove(wref);
+        wref.get_mut().assigned_objects.remove(object);
+        object.get_mut().located.remove(wref); // may not be present
+        wref.get_mut().located_objects.remove(object); // may not be present
+        if object.get().assigned.is_empty() && object.get().state == DataObjectState::Finished {
+            object.get_mut().state = DataObjectState::Removed;
+            assert!(object.get().scheduled.is_empty());
+            assert!(!object.get().client_keep);
+        }
+
+        object.check_consistency_opt().unwrap(); // non-recoverable
+        wref.check_consistency_opt().unwrap(); // non-recoverable
+    }
+
+    /// Send the unassign call of the object to the worker, a lost worker is not informed
+    fn send_unassign_object(&self, object: &DataObjectRef, wref: &WorkerRef) {
+        if wref.get().error.is_some() {
+            return;
+        }
         let mut req = wref.get()
             .control
             .as_ref()
@@ -528,30 +1584,64 @@ impl State {
             object.get_id().to_capnp(co);
         }
 
-        {
-            let o2 = object.clone();
-            let w2 = wref.clone();
-            self.handle
-                .spawn(req.send().promise.map(|_| ()).map_err(move |e| {
-                    panic!(
-                        "Sending unassign_object {:?} to {:?} failed {:?}",
-                        o2, w2, e
-                    )
-                }));
-        }
+        let o2 = object.clone();
+        let w2 = wref.clone();
+        self.handle
+            .spawn(req.send().promise.map(|_| ()).map_err(move |e| {
+                panic!(
+                    "Sending unassign_object {:?} to {:?} failed {:?}",
+                    o2, w2, e
+                )
+            }));
+    }
 
-        object.get_mut().assigned.remove(wref);
-        wref.get_mut().assigned_objects.remove(object);
-        object.get_mut().located.remove(wref); // may not be present
-        wref.get_mut().located_objects.remove(object); // may not be present
-        if object.get().assigned.is_empty() && object.get().state == DataObjectState::Finished {
-            object.get_mut().state = DataObjectState::Removed;
-            assert!(object.get().scheduled.is_empty());
-            assert!(!object.get().client_keep);
+    /// Send batched deletions of evicted objects to workers; workers confirm deleted
+    /// objects and report the freed size
+    fn send_object_deletes(&mut self) {
+        let deletes = ::std::mem::replace(&mut self.objects_to_delete, HashMap::new());
+        for (wref, ids) in deletes {
+            if wref.get().error.is_some() {
+                continue;
+            }
+            for batch in ids.chunks(DELETE_BATCH_SIZE) {
+                let mut req = wref.get()
+                    .control
+                    .as_ref()
+                    .unwrap()
+                    .delete_objects_request();
+                {
+                    let mut objects = req.get().init_objects(batch.len() as u32);
+                    for (i, id) in batch.iter().enumerate() {
+                        id.to_capnp(&mut objects.borrow().get(i as u32));
+                    }
+                }
+                let requested = batch.len();
+                let w2 = wref.clone();
+                self.handle
+                    .spawn(req.send().promise.then(move |r| -> ::std::result::Result<(), ()> {
+                        let result = r.and_then(|r| {
+                            let r = r.get()?;
+                            Ok((r.get_deleted()?.len() as usize, r.get_freed_bytes()))
+                        });
+                        match result {
+                            Ok((deleted, freed_bytes)) => {
+                                debug!(
+                                    "Worker {} deleted {}/{} objects, freed {} bytes",
+                                    w2.get().id(),
+                                    deleted,
+                                    requested,
+                                    freed_bytes
+                                );
+                                w2.get_mut().freed_bytes += freed_bytes;
+                            }
+                            Err(e) => {
+                                warn!("Deleting objects on worker {} failed: {}", w2.get().id(), e)
+                            }
+                        }
+                        Ok(())
+                    }));
+            }
         }
-
-        object.check_consistency_opt().unwrap(); // non-recoverable
-        wref.check_consistency_opt().unwrap(); // non-recoverable
     }
 
     /// Assign and send the task to the worker it is scheduled for.
@@ -560,6 +1650,21 @@ impl State {
     pub fn assign_task(&mut self, task: &TaskRef) {
         task.check_consistency_opt().unwrap(); // non-recoverable
 
+        // The task cannot run without its secrets (they may be removed after the submit)
+        let secrets = match task.get().secret_values(&self.secrets) {
+            Ok(secrets) => secrets,
+            Err(e) => {
+                let (session, task_id) = {
+                    let t = task.get();
+                    (t.session.clone(), t.id)
+                };
+                warn!("Task {} cannot be assigned: {}", task_id, e);
+                self.fail_session(&session, e.to_string(), None, task_id)
+                    .unwrap(); // non-recoverable
+                return;
+            }
+        };
+
         {
             // lexical scoping for `t`
             let mut t = task.get_mut();
@@ -600,6 +1705,7 @@ impl State {
 
             // Create request
             let mut req = wref.get().control.as_ref().unwrap().add_nodes_request();
+            let inline_threshold = self.inline_threshold_for(&wref.get());
 
             // Serialize objects
             {
@@ -608,7 +1714,7 @@ impl State {
                     let mut co = &mut new_objects.borrow().get(i as u32);
                     placement.to_capnp(&mut co.borrow().get_placement().unwrap());
                     let obj = object.get();
-                    obj.to_worker_capnp(&mut co);
+                    obj.to_worker_capnp(&mut co, inline_threshold);
                     // only assign output tasks - they are all assigned
                     co.set_assigned(obj.assigned.contains(&wref));
                 }
@@ -617,7 +1723,7 @@ impl State {
             // Serialize the task
             {
                 let new_tasks = req.get().init_new_tasks(1);
-                t.to_worker_capnp(&mut new_tasks.get(0));
+                t.to_worker_capnp(&mut new_tasks.get(0), &secrets);
             }
 
             self.handle.spawn(
@@ -633,7 +1739,7 @@ impl State {
                 w.scheduled_ready_tasks.remove(task);
             }
             t.assigned = Some(wref.clone());
-            t.state = TaskState::Assigned;
+            t.set_state(TaskState::Assigned);
 
             /*
             for oref in t.outputs.iter() {
@@ -654,23 +1760,25 @@ impl State {
         //task.check_consistency_opt().unwrap(); // non-recoverable
         //wref.check_consistency_opt().unwrap(); // non-recoverable
 
-        // Create request
-        let mut req = wref.get().control.as_ref().unwrap().stop_tasks_request();
-        {
-            let mut tasks = req.get().init_tasks(1);
-            let ct = &mut tasks.borrow().get(0);
-            task.get_id().to_capnp(ct);
-        }
+        // Lost worker is not informed
+        if wref.get().error.is_none() {
+            let mut req = wref.get().control.as_ref().unwrap().stop_tasks_request();
+            {
+                let mut tasks = req.get().init_tasks(1);
+                let ct = &mut tasks.borrow().get(0);
+                task.get_id().to_capnp(ct);
+            }
 
-        self.handle.spawn(
-            req.send()
-                .promise
-                .map(|_| ())
-                .map_err(|e| panic!("[unassign_task] Send failed {:?}", e)),
-        );
+            self.handle.spawn(
+                req.send()
+                    .promise
+                    .map(|_| ())
+                    .map_err(|e| panic!("[unassign_task] Send failed {:?}", e)),
+            );
+        }
 
         task.get_mut().assigned = None;
-        task.get_mut().state = TaskState::Ready;
+        task.get_mut().set_state(TaskState::Ready);
         wref.get_mut().assigned_tasks.remove(task);
         self.update_task_assignment(task);
 
@@ -687,6 +1795,242 @@ impl State {
         wref.check_consistency_opt().unwrap(); // non-recoverable
     }
 
+    /// Invalidate a finished object that was found corrupt (e.g. by a checksum
+    /// verification or a validation done by a client after the object was finished).
+    /// The producer of the object is computed again together with all finished tasks
+    /// that transitively depend on its outputs, and with producers of removed inputs
+    /// of these tasks. Unfinished consumers of the outputs are stopped and wait for
+    /// the new outputs. When more than `invalidation_limit` tasks would be computed
+    /// again, nothing is changed and an error is returned.
+    /// Returns the tasks that are computed again.
+    pub fn invalidate_object(
+        &mut self,
+        object: &DataObjectRef,
+        reason: &str,
+    ) -> Result<Vec<TaskRef>> {
+        {
+            let o = object.get();
+            if o.state != DataObjectState::Finished {
+                bail!("Object {} is not finished", o.id);
+            }
+            if o.producer.is_none() {
+                bail!("Object {} has no producer, it cannot be computed again", o.id);
+            }
+        }
+        if self.invalidation_limit == 0 {
+            bail!("Invalidation of objects is disabled on the server");
+        }
+        let tasks = self.invalidated_tasks(object)?;
+        info!(
+            "Object {} invalidated ({}), {} task(s) computed again",
+            object.get_id(),
+            reason,
+            tasks.len()
+        );
+
+        let mut outputs: RcSet<DataObjectRef> = Default::default();
+        let mut consumers: RcSet<TaskRef> = Default::default();
+        for tref in &tasks {
+            for oref in &tref.get().outputs {
+                outputs.insert(oref.clone());
+                for cref in &oref.get().consumers {
+                    if !tasks.contains(cref) {
+                        consumers.insert(cref.clone());
+                    }
+                }
+            }
+        }
+
+        // Stop unfinished consumers while the graph is still consistent
+        for cref in &consumers {
+            let (state, scheduled) = {
+                let c = cref.get();
+                (c.state, c.scheduled.clone())
+            };
+            match state {
+                TaskState::NotAssigned => (),
+                TaskState::Ready => match scheduled {
+                    Some(wref) => self.reschedule_task(cref, &wref),
+                    None => self.scheduler.remove_ready_task(cref),
+                },
+                TaskState::Assigned | TaskState::Running => {
+                    cref.unschedule(&mut self.graph.global_resources);
+                    for oref in &cref.get().outputs {
+                        oref.unschedule();
+                    }
+                    self.unassign_task(cref);
+                }
+                _ => panic!("Invalid state of consumer {:?}", *cref.get()),
+            }
+        }
+
+        // Outputs are removed from workers and become unfinished
+        for oref in &outputs {
+            oref.unschedule();
+            let assigned = oref.get().assigned.clone();
+            for wref in &assigned {
+                self.send_unassign_object(oref, wref);
+                let mut w = wref.get_mut();
+                w.assigned_objects.remove(oref);
+                w.located_objects.remove(oref);
+            }
+            let mut o = oref.get_mut();
+            o.assigned.clear();
+            o.located.clear();
+            o.state = DataObjectState::Unfinished;
+            o.size = None;
+            o.data = None;
+        }
+
+        for tref in &tasks {
+            let mut waiting_for: RcSet<DataObjectRef> = Default::default();
+            for input in &tref.get().inputs {
+                let mut o = input.object.get_mut();
+                if o.state == DataObjectState::Removed {
+                    // Removed inputs without data are outputs of invalidated tasks,
+                    // the server is the source of the remaining ones
+                    assert!(o.data.is_some());
+                    o.state = DataObjectState::Finished;
+                }
+                if o.state == DataObjectState::Unfinished {
+                    waiting_for.insert(input.object.clone());
+                }
+                o.need_by.insert(tref.clone());
+            }
+            let mut t = tref.get_mut();
+            t.session.get_mut().task_reopened();
+            if let Some(ref name) = t.group {
+                t.session.get_mut().groups.get_mut(name).unwrap().task_reopened(tref);
+            }
+            t.waiting_for = waiting_for;
+            t.set_state(TaskState::NotAssigned);
+        }
+
+        for cref in &consumers {
+            let mut c = cref.get_mut();
+            let waiting_for = c.inputs
+                .iter()
+                .filter(|i| i.object.get().state == DataObjectState::Unfinished)
+                .map(|i| i.object.clone())
+                .collect();
+            c.waiting_for = waiting_for;
+            c.set_state(TaskState::NotAssigned);
+        }
+
+        for tref in &tasks {
+            self.update_task_assignment(tref);
+        }
+        self.logger.add_dataobject_invalidated_event(
+            object.get_id(),
+            tasks.iter().map(|t| t.get_id()).collect(),
+            reason.to_string(),
+        );
+        Ok(tasks.into_iter().collect())
+    }
+
+    /// Tasks computed again when the object is invalidated (see `invalidate_object`)
+    fn invalidated_tasks(&self, object: &DataObjectRef) -> Result<RcSet<TaskRef>> {
+        let mut tasks: RcSet<TaskRef> = Default::default();
+        let mut stack = vec![object.get().producer.clone().unwrap()];
+        while let Some(tref) = stack.pop() {
+            if !tasks.insert(tref.clone()) {
+                continue;
+            }
+            if tasks.len() > self.invalidation_limit {
+                bail!(
+                    "Invalidation of object {} would compute again more than {} tasks",
+                    object.get_id(),
+                    self.invalidation_limit
+                );
+            }
+            let t = tref.get();
+            for oref in &t.outputs {
+                for cref in &oref.get().consumers {
+                    if cref.get().state == TaskState::Finished {
+                        stack.push(cref.clone());
+                    }
+                }
+            }
+            for input in &t.inputs {
+                let o = input.object.get();
+                if o.state != DataObjectState::Removed || o.data.is_some() {
+                    continue;
+                }
+                match o.producer {
+                    Some(ref producer) => stack.push(producer.clone()),
+                    None => bail!(
+                        "Input {} of task {} was removed and cannot be computed again",
+                        o.id,
+                        t.id
+                    ),
+                }
+            }
+        }
+        Ok(tasks)
+    }
+
+    /// Publish a kept object under the given name in the namespace
+    pub fn publish_object(
+        &mut self,
+        object: &DataObjectRef,
+        namespace: &str,
+        name: &str,
+    ) -> Result<()> {
+        let key = format!("{}/{}", namespace, name);
+        if self.graph.published.contains_key(&key) {
+            bail!("Name '{}' is already published", key);
+        }
+        {
+            let mut o = object.get_mut();
+            if !o.client_keep {
+                bail!("Only kept objects can be published, object {} is not kept", o.id);
+            }
+            if let Some(ref other) = o.published {
+                bail!("Object {} is already published as '{}'", o.id, other);
+            }
+            o.published = Some(key.clone());
+        }
+        debug!("Object {} published as '{}'", object.get_id(), key);
+        self.graph.published.insert(key, object.clone());
+        Ok(())
+    }
+
+    /// Remove published name; the object is removed if it is not needed anymore
+    pub fn unpublish_object(&mut self, namespace: &str, name: &str) -> Result<()> {
+        let key = format!("{}/{}", namespace, name);
+        let object = match self.graph.published.remove(&key) {
+            Some(o) => o,
+            None => bail!("Name '{}' is not published", key),
+        };
+        debug!("Object {} unpublished", object.get_id());
+        object.get_mut().published = None;
+        if !object.get().is_needed() {
+            object.unschedule();
+        }
+        self.update_object_assignments(&object, None);
+        object.check_consistency_opt().unwrap(); // non-recoverable
+        Ok(())
+    }
+
+    pub fn published_object(&self, namespace: &str, name: &str) -> Result<DataObjectRef> {
+        let key = format!("{}/{}", namespace, name);
+        match self.graph.published.get(&key) {
+            Some(o) => Ok(o.clone()),
+            None => bail!("Name '{}' is not published", key),
+        }
+    }
+
+    /// Kept objects of the session that match the filter
+    pub fn kept_objects(&self, session: &SessionRef, filter: &KeptFilter) -> Vec<DataObjectRef> {
+        session
+            .get()
+            .objects
+            .iter()
+            .filter(|o| filter.matches(&o.get()))
+            .cloned()
+            .collect()
+    }
+
     /// Removes a keep flag from an object.
     pub fn unkeep_object(&mut self, object: &DataObjectRef) {
         object.check_consistency_opt().unwrap(); // non-recoverable
@@ -699,6 +2043,450 @@ impl State {
         object.check_consistency_opt().unwrap(); // non-recoverable
     }
 
+    /// Cancel unfinished tasks of the session that have all the given tags together
+    /// with all tasks that (transitively) consume their outputs. Running tasks are stopped,
+    /// the tasks and their outputs (also kept ones) are removed from the graph.
+    /// Returns ids of the cancelled tasks.
+    pub fn cancel_tasks(&mut self, session: &SessionRef, tags: &[String]) -> Result<Vec<TaskId>> {
+        if tags.is_empty() {
+            bail!("No tags of cancelled tasks were given");
+        }
+        // Tasks of a stopped failed session may be cancelled before it is retried
+        if session.get().is_failed() && !session.get().is_retryable() {
+            bail!("Session {} has failed", session.get_id());
+        }
+        let mut queue: Vec<TaskRef> = self.graph
+            .tags
+            .find(tags)
+            .into_iter()
+            .filter(|tref| {
+                let t = tref.get();
+                t.session == *session && t.state != TaskState::Finished
+            })
+            .collect();
+        let mut tasks: RcSet<TaskRef> = Default::default();
+        while let Some(tref) = queue.pop() {
+            if tasks.insert(tref.clone()) {
+                for output in &tref.get().outputs {
+                    queue.extend(output.get().consumers.iter().cloned());
+                }
+            }
+        }
+        let objects: RcSet<DataObjectRef> = tasks
+            .iter()
+            .flat_map(|tref| tref.get().outputs.clone())
+            .collect();
+        info!(
+            "Cancelling {} tasks of session {} with tags {:?}",
+            tasks.len(),
+            session.get_id(),
+            tags
+        );
+
+        let mut sessions: RcSet<SessionRef> = Default::default();
+        for tref in &tasks {
+            let inputs: Vec<DataObjectRef> = {
+                let t = tref.get();
+                if t.held {
+                    t.session.get_mut().held_tasks.retain(|h| h != tref);
+                }
+                t.session.get_mut().task_finished();
+                sessions.insert(t.session.clone());
+                let inputs = t.inputs.iter().map(|i| i.object.clone()).collect();
+                inputs
+            };
+            tref.unschedule(&mut self.graph.global_resources);
+            self.remove_task(tref)?;
+            self.updates.remove_task(tref);
+            self.scheduler.remove_ready_task(tref);
+            // Finished inputs that are no longer needed are removed from workers
+            for oref in inputs {
+                let not_needed = {
+                    let mut o = oref.get_mut();
+                    o.need_by.remove(tref) && !o.is_needed()
+                        && o.state == DataObjectState::Finished
+                };
+                if not_needed && !objects.contains(&oref) {
+                    self.purge_object(&oref);
+                }
+            }
+        }
+        for oref in &objects {
+            oref.get_mut().client_keep = false;
+            oref.unschedule();
+            self.updates.remove_object(oref);
+            self.remove_object(oref)?;
+        }
+        let task_ids: Vec<TaskId> = tasks.iter().map(|t| t.get_id()).collect();
+        self.ignore_ids(
+            task_ids.clone(),
+            objects.iter().map(|o| o.get_id()).collect(),
+        );
+        for s in sessions {
+            if s.get().unfinished_tasks == 0 {
+                self.notify_session_finished(&s);
+            }
+        }
+        Ok(task_ids)
+    }
+
+    /// Submit a task that packs kept objects of the session that match the filter into
+    /// one directory object (fetched as a tar archive), each object is stored
+    /// as "<id>-<label>". The new task and object get ids not smaller than `min_id`
+    /// (the client may have already used lower ids). The returned object is kept.
+    /// Returns the object and the number of packed objects.
+    pub fn archive_kept(
+        &mut self,
+        session: &SessionRef,
+        filter: &KeptFilter,
+        min_id: Id,
+    ) -> Result<(DataObjectRef, usize)> {
+        let objects = self.kept_objects(session, filter);
+        if objects.is_empty() {
+            bail!("No kept objects of session {} match the filter", session.get_id());
+        }
+        let session_id = session.get_id();
+        let (task_id, object_id) = {
+            let mut s = session.get_mut();
+            if min_id > 0 {
+                s.use_id(min_id - 1);
+            }
+            let task_id = s.new_id();
+            (
+                TaskId::new(session_id, task_id),
+                DataObjectId::new(session_id, s.new_id()),
+            )
+        };
+        let paths: Vec<String> = objects
+            .iter()
+            .map(|o| {
+                let o = o.get();
+                let path = format!("{}-{}", o.id.get_id(), o.label.replace('/', "_"));
+                path
+            })
+            .collect();
+        let resources = Resources {
+            cpus: 1,
+            ..Default::default()
+        };
+        let mut attributes = Attributes::new();
+        attributes.set("config", json!({ "paths": paths }))?;
+        attributes.set("resources", &resources)?;
+        let mut object_attributes = Attributes::new();
+        object_attributes.set("spec", json!({ "content_type": null }))?;
+
+        let output = self.add_object(
+            session,
+            object_id,
+            true,
+            "archive".to_string(),
+            DataType::Directory,
+            None,
+            object_attributes,
+        )?;
+        let inputs = objects
+            .iter()
+            .map(|o| TaskInput {
+                object: o.clone(),
+                label: String::new(),
+                path: String::new(),
+            })
+            .collect();
+        let task = match self.add_task(
+            session,
+            task_id,
+            inputs,
+            vec![output.clone()],
+            "!make_directory".to_string(),
+            attributes,
+            resources,
+        ) {
+            Ok(task) => task,
+            Err(e) => {
+                self.remove_object(&output)?;
+                return Err(e);
+            }
+        };
+        let descriptor = TaskDescriptor::from(&task.get(), &self.redaction);
+        self.logger.add_client_submit_event(
+            vec![descriptor],
+            vec![ObjectDescriptor::from(&output.get())],
+        );
+        Ok((output, objects.len()))
+    }
+
+    /// Run a random part (`sample_rate` of their sessions) of the submitted tasks again
+    /// on other workers. The copy of a task gets the same inputs and configuration and
+    /// new outputs that are not kept; workers compute checksums of outputs of both
+    /// (attribute "compute_checksum") and they are compared when both tasks finish.
+    /// Nothing is sampled when there are less than two workers.
+    pub fn sample_tasks(&mut self, tasks: &[TaskRef]) -> Result<()> {
+        if self.graph.workers.len() < 2 {
+            return Ok(());
+        }
+        let mut copies = Vec::new();
+        let mut objects = Vec::new();
+        for tref in tasks {
+            let rate = {
+                let t = tref.get();
+                if t.outputs.is_empty() || t.sample_of.is_some() || t.sample_copy.is_some() {
+                    continue;
+                }
+                let rate = t.session.get().sample_rate;
+                rate
+            };
+            if rate <= 0f64 || !sample_hit(rate, tref.get_id()) {
+                continue;
+            }
+            let copy = self.add_sample_copy(tref)?;
+            debug!("Task {} sampled, copy {}", tref.get_id(), copy.get_id());
+            objects.extend(
+                copy.get()
+                    .outputs
+                    .iter()
+                    .map(|o| ObjectDescriptor::from(&o.get())),
+            );
+            copies.push(TaskDescriptor::from(&copy.get(), &self.redaction));
+        }
+        if !copies.is_empty() {
+            info!("{} task(s) sampled", copies.len());
+            self.logger.add_client_submit_event(copies, objects);
+        }
+        Ok(())
+    }
+
+    /// Create the copy of the sampled task with new outputs
+    fn add_sample_copy(&mut self, tref: &TaskRef) -> Result<TaskRef> {
+        let (session, task_type, mut attributes, resources, inputs, outputs) = {
+            let t = tref.get();
+            (
+                t.session.clone(),
+                t.task_type.clone(),
+                t.attributes.clone(),
+                t.resources.clone(),
+                t.inputs.clone(),
+                t.outputs.clone(),
+            )
+        };
+        // The copy does not count into groups of the task and it is placed freely
+        attributes.remove("group");
+        attributes.remove("spread");
+        attributes.remove("colocate");
+        let session_id = session.get_id();
+        let mut copy_outputs = Vec::new();
+        for oref in &outputs {
+            let (label, data_type, mut object_attributes) = {
+                let o = oref.get();
+                (o.label.clone(), o.data_type, o.attributes.clone())
+            };
+            object_attributes.remove("placement");
+            object_attributes.set("compute_checksum", true)?;
+            oref.get_mut().attributes.set("compute_checksum", true)?;
+            let id = DataObjectId::new(session_id, session.get_mut().new_id());
+            copy_outputs.push(self.add_object(
+                &session,
+                id,
+                false,
+                label,
+                data_type,
+                None,
+                object_attributes,
+            )?);
+        }
+        let id = TaskId::new(session_id, session.get_mut().new_id());
+        let copy = self.add_task(
+            &session,
+            id,
+            inputs,
+            copy_outputs,
+            task_type,
+            attributes,
+            resources,
+        )?;
+        copy.get_mut().sample_of = Some(tref.clone());
+        tref.get_mut().sample_copy = Some(copy.clone());
+        Ok(copy)
+    }
+
+    /// Compare checksums of outputs of a finished sampled task and its copy when both
+    /// are finished. The result is set in attribute "sample" of the sampled task
+    /// and counted in the statistics of the session.
+    fn compare_sample(&mut self, tref: &TaskRef) {
+        let (original, copy) = {
+            let t = tref.get();
+            match (t.sample_of.clone(), t.sample_copy.clone()) {
+                (Some(original), _) => (original, tref.clone()),
+                (None, Some(copy)) => (tref.clone(), copy),
+                (None, None) => return,
+            }
+        };
+        if original.get().state != TaskState::Finished || copy.get().state != TaskState::Finished
+        {
+            return;
+        }
+        let mismatch = {
+            let o = original.get();
+            let c = copy.get();
+            let checksum = |oref: &DataObjectRef| -> Option<String> {
+                oref.get().attributes.find("checksum").unwrap_or(None)
+            };
+            let mismatch = o.outputs
+                .iter()
+                .zip(c.outputs.iter())
+                .find(|&(a, b)| checksum(a).is_none() || checksum(a) != checksum(b))
+                .map(|(a, _)| a.get().label.clone());
+            mismatch
+        };
+        let error = mismatch.map(|label| format!("Output '{}' differs", label));
+        self.record_sample(&original, &copy, error);
+    }
+
+    /// A run of the task failed on the worker and the task has retries left
+    /// (attribute "max_retries"). The task is taken back from the worker and it becomes
+    /// ready again after the delay of the retry, which doubles with each retry; other
+    /// workers are preferred. Its session and consumers are not affected.
+    fn retry_task(&mut self, tref: &TaskRef, worker: &WorkerRef, error: &str) {
+        let (retries, delay) = {
+            let mut t = tref.get_mut();
+            t.retries += 1;
+            let worker_id = worker.get_id();
+            if !t.failed_on.contains(&worker_id) {
+                t.failed_on.push(worker_id);
+            }
+            let max_delay = Duration::from_secs(MAX_RETRY_DELAY);
+            let delay = t.retry_delay
+                .checked_mul(1u32 << ::std::cmp::min(t.retries - 1, 16))
+                .map(|d| ::std::cmp::min(d, max_delay))
+                .unwrap_or(max_delay);
+            (t.retries, delay)
+        };
+        info!(
+            "Task {} failed on {}, retry {}/{} in {}s: {}",
+            tref.get_id(),
+            worker.get_id(),
+            retries,
+            tref.get().max_retries,
+            units::duration_secs(delay),
+            error
+        );
+        tref.unschedule(&mut self.graph.global_resources);
+        let outputs = tref.get().outputs.clone();
+        for oref in &outputs {
+            oref.unschedule();
+        }
+        tref.get_mut().assigned = None;
+        worker.get_mut().assigned_tasks.remove(tref);
+        for oref in &outputs {
+            if oref.get().assigned.contains(worker) {
+                self.unassign_object(oref, worker);
+            }
+        }
+        self.underload_workers.insert(worker.clone());
+        {
+            let mut t = tref.get_mut();
+            let waiting_for = t.inputs
+                .iter()
+                .filter(|i| i.object.get().state == DataObjectState::Unfinished)
+                .map(|i| i.object.clone())
+                .collect();
+            t.waiting_for = waiting_for;
+            t.retry_pending = true;
+            t.attributes
+                .set("retries", json!({ "count": retries, "last_error": error }))
+                .unwrap();
+            t.set_state(TaskState::NotAssigned);
+        }
+        self.updates.tasks.insert(tref.clone());
+
+        let state_ref = self.self_ref.clone().unwrap();
+        let tref = tref.clone();
+        let wakeup = ::tokio_core::reactor::Timeout::new(delay, &self.handle).unwrap();
+        self.handle.spawn(wakeup.then(move |_| {
+            state_ref.get_mut().retry_delay_passed(&tref);
+            Ok(())
+        }));
+    }
+
+    /// The task waiting for its retry may become ready, unless it was removed meanwhile
+    fn retry_delay_passed(&mut self, tref: &TaskRef) {
+        if !tref.get().retry_pending {
+            return;
+        }
+        tref.get_mut().retry_pending = false;
+        let removed = self.graph.tasks.get(&tref.get_id()) != Some(tref);
+        if !removed && tref.get().state == TaskState::NotAssigned {
+            self.update_task_assignment(tref);
+        }
+    }
+
+    /// The copy of a sampled task failed; it is removed with its outputs
+    /// and the sample is recorded as a mismatch
+    fn sample_copy_failed(&mut self, tref: &TaskRef, worker: &WorkerRef, error: &str) {
+        debug!("Copy {} of a sampled task failed: {}", tref.get_id(), error);
+        let original = tref.get().sample_of.clone().unwrap();
+        let (session, inputs, outputs) = {
+            let t = tref.get();
+            t.session.get_mut().task_finished();
+            (
+                t.session.clone(),
+                t.inputs
+                    .iter()
+                    .map(|i| i.object.clone())
+                    .collect::<Vec<_>>(),
+                t.outputs.clone(),
+            )
+        };
+        tref.unschedule(&mut self.graph.global_resources);
+        tref.get_mut().assigned = None;
+        worker.get_mut().assigned_tasks.remove(tref);
+        self.underload_workers.insert(worker.clone());
+        self.record_sample(&original, tref, Some(format!("Copy failed: {}", error)));
+        self.remove_task(tref).unwrap();
+        self.updates.remove_task(tref);
+        for oref in &outputs {
+            oref.unschedule();
+            self.updates.remove_object(oref);
+            self.remove_object(oref).unwrap();
+        }
+        for oref in inputs {
+            let not_needed = {
+                let mut o = oref.get_mut();
+                o.need_by.remove(tref) && !o.is_needed() && o.state == DataObjectState::Finished
+            };
+            if not_needed {
+                self.purge_object(&oref);
+            }
+        }
+        self.ignore_ids(
+            vec![tref.get_id()],
+            outputs.iter().map(|o| o.get_id()).collect(),
+        );
+        if session.get().unfinished_tasks == 0 {
+            self.notify_session_finished(&session);
+        }
+    }
+
+    /// Set the result of comparison of the sampled task with its copy
+    /// (`error` is `None` when they match)
+    fn record_sample(&mut self, original: &TaskRef, copy: &TaskRef, error: Option<String>) {
+        let mut t = original.get_mut();
+        let matched = error.is_none();
+        if let Some(ref error) = error {
+            warn!("Sampled task {} does not match its copy: {}", t.id, error);
+        }
+        let result = json!({
+            "copy": copy.get_id().get_id(),
+            "match": matched,
+            "error": error,
+        });
+        t.attributes.set("sample", result).unwrap();
+        let task_id = t.id;
+        t.session
+            .get_mut()
+            .stats
+            .sample_compared(task_id, matched);
+    }
+
     /// Update any assignments depending on the task state, and set to Ready on all inputs ready.
     ///
     /// * Check if all task inputs are ready, and switch state.
@@ -710,12 +2498,13 @@ impl State {
     pub fn update_task_assignment(&mut self, tref: &TaskRef) {
         assert!(tref.get().state != TaskState::Failed);
 
-        if tref.get().state == TaskState::NotAssigned && tref.get().waiting_for.is_empty() {
-            tref.get_mut().state = TaskState::Ready;
+        if tref.get().state == TaskState::NotAssigned && tref.get().waiting_for.is_empty()
+            && !tref.get().retry_pending
+        {
+            tref.get_mut().set_state(TaskState::Ready);
             self.updates.tasks.insert(tref.clone());
             if let Some(ref wref) = tref.get().scheduled {
-                let mut w = wref.get_mut();
-                w.active_resources += tref.get().resources.cpus();
+                wref.get_mut().add_active(&tref.get().resources);
             }
         }
 
@@ -752,6 +2541,38 @@ impl State {
         tref.check_consistency_opt().unwrap(); // unrecoverable
     }
 
+    /// If the finished object has a single consumer that is already scheduled on another
+    /// worker, but not assigned yet, schedule the object on that worker and update its
+    /// assignments, so the worker starts fetching the object before the consumer is assigned
+    /// there. The schedule is undone by `TaskRef::unforward_inputs` when the consumer leaves
+    /// the worker. NOP for Unfinished and Removed objects.
+    pub fn forward_to_consumer(&mut self, oref: &DataObjectRef) {
+        let wref = {
+            let o = oref.get();
+            if o.state != DataObjectState::Finished || o.consumers.len() != 1 {
+                return;
+            }
+            let consumer = o.consumers.iter().next().unwrap().get();
+            if consumer.assigned.is_some() {
+                // Assigned task has already received its inputs
+                return;
+            }
+            let wref = match consumer.scheduled {
+                Some(ref wref) if !o.assigned.contains(wref) => wref.clone(),
+                _ => return,
+            };
+            wref
+        };
+        debug!("Forwarding object {:?} to consumer worker {:?}", oref, wref);
+        wref.get_mut().scheduled_objects.insert(oref.clone());
+        {
+            let mut o = oref.get_mut();
+            o.scheduled.insert(wref.clone());
+            o.forwarded_to = Some(wref.clone());
+        }
+        self.update_object_assignments(oref, Some(&wref));
+    }
+
     /// Update finished object assignment to match the schedule on the given worker (optional) and
     /// needed-ness. NOP for Unfinished and Removed objects.
     ///
@@ -789,7 +2610,7 @@ impl State {
                     if !oref.get().is_needed() {
                         let assigned = oref.get().assigned.clone();
                         for wa in assigned {
-                            self.unassign_object(oref, &wa);
+                            self.evict_object(oref, &wa);
                         }
                         oref.get_mut().state = DataObjectState::Removed;
                     }
@@ -809,7 +2630,7 @@ impl State {
     pub fn updates_from_worker(
         &mut self,
         worker: &WorkerRef,
-        obj_updates: Vec<(DataObjectRef, DataObjectState, usize, Attributes)>,
+        obj_updates: Vec<(DataObjectRef, DataObjectState, usize, Attributes, Option<Vec<u8>>)>,
         task_updates: Vec<(TaskRef, TaskState, Attributes)>,
     ) {
         debug!(
@@ -821,31 +2642,55 @@ impl State {
         worker.check_consistency_opt().unwrap(); // non-recoverable
 
         let mut ignore_check_again = false;
+        // Finished sampled tasks and copies, compared when all updates are processed
+        let mut sampled = Vec::new();
 
         for (tref, state, attributes) in task_updates {
             if ignore_check_again && self.is_task_ignored(&tref.get().id()) {
                 continue;
             }
+            if tref.get().assigned.as_ref() != Some(worker) {
+                // The task was stopped (e.g. its input was invalidated) before the update came
+                debug!("Ignoring update of task {} not assigned to {:?}", tref.get_id(), worker);
+                continue;
+            }
             // inform the scheduler
             self.updates.tasks.insert(tref.clone());
             // set the state and possibly propagate
             match state {
                 TaskState::Finished => {
+                    tref.unschedule(&mut self.graph.global_resources);
                     {
                         let mut t = tref.get_mut();
                         t.session.get_mut().task_finished();
-                        t.state = state;
+                        if let Some(ref name) = t.group {
+                            t.session.get_mut().groups.get_mut(name).unwrap().task_finished(&tref);
+                        }
+                        t.set_state(state);
                         t.attributes.update(attributes);
-                        t.scheduled = None;
+                        t.session.get_mut().stats.task_done(&t.attributes);
+                        if let Ok(Some(info)) = t.attributes.find::<::serde_json::Value>("info")
+                        {
+                            if let Some(ms) = info.get("duration").and_then(|d| d.as_i64()) {
+                                let bytes = input_bytes(&t);
+                                self.duration_history
+                                    .record(&t.task_type, bytes, ms as f64 / 1000.0);
+                            }
+                        }
                         t.assigned = None;
-                        let mut w = worker.get_mut();
-                        w.scheduled_tasks.remove(&tref);
-                        w.assigned_tasks.remove(&tref);
-                        w.active_resources -= t.resources.cpus();
+                        t.finished_on = Some(worker.get_id());
+                        worker.get_mut().assigned_tasks.remove(&tref);
                         self.logger.add_task_finished_event(t.id);
                     }
                     tref.get_mut().trigger_finish_hooks();
+                    if tref.get().sample_of.is_some() || tref.get().sample_copy.is_some() {
+                        sampled.push(tref.clone());
+                    }
                     self.update_task_assignment(&tref);
+                    let session = tref.get().session.clone();
+                    if session.get().unfinished_tasks == 0 {
+                        self.notify_session_finished(&session);
+                    }
 
                     for input in &tref.get().inputs {
                         // We check that need_by was really decreased to protect against
@@ -864,7 +2709,7 @@ impl State {
                 TaskState::Running => {
                     let mut t = tref.get_mut();
                     assert_eq!(t.state, TaskState::Assigned);
-                    t.state = state;
+                    t.set_state(state);
                     t.attributes = attributes;
                     self.logger.add_task_started_event(t.id, worker.get_id());
                 }
@@ -884,14 +2729,54 @@ impl State {
                         .find("debug")
                         .unwrap_or_else(|_| Some("Invalid value in 'debug' attribute".to_string()));
 
+                    let failure = attributes.as_hashmap().get("failure").cloned();
+
+                    if tref.get().sample_of.is_some() {
+                        // A failed copy is a mismatch of the sample, the session goes on
+                        self.sample_copy_failed(&tref, worker, &error_message);
+                        continue;
+                    }
+
+                    let retry = {
+                        let t = tref.get();
+                        t.retries < t.max_retries && !t.session.get().is_failed()
+                    };
+                    if retry {
+                        // Resources used by the failed run are accounted
+                        let session = tref.get().session.clone();
+                        session.get_mut().stats.task_done(&attributes);
+                        self.logger.add_task_failed_event(
+                            tref.get().id,
+                            worker.get_id(),
+                            error_message.clone(),
+                        );
+                        self.retry_task(&tref, worker, &error_message);
+                        continue;
+                    }
+
                     ignore_check_again = true;
                     self.underload_workers.insert(worker.clone());
-                    tref.get_mut().state = state;
-                    tref.get_mut().attributes = attributes;
+                    if let Some(ref name) = tref.get().group {
+                        let session = tref.get().session.clone();
+                        session.get_mut().groups.get_mut(name).unwrap().task_failed(&tref);
+                    }
+                    tref.get_mut().set_state(state);
                     let session = tref.get().session.clone();
+                    {
+                        let mut s = session.get_mut();
+                        s.stats.task_done(&attributes);
+                        s.stats.task_failed(&error_message);
+                    }
+                    tref.get_mut().attributes = attributes;
                     let task_id = tref.get().id;
-                    self.fail_session(&session, error_message.clone(), debug_message, task_id)
-                        .unwrap();
+                    self.fail_session_with_failure(
+                        &session,
+                        error_message.clone(),
+                        debug_message,
+                        task_id,
+                        failure,
+                        true,
+                    ).unwrap();
                     self.logger.add_task_failed_event(
                         tref.get().id,
                         worker.get_id(),
@@ -907,7 +2792,7 @@ impl State {
             }
         }
 
-        for (oref, state, size, attributes) in obj_updates {
+        for (oref, state, size, attributes, data) in obj_updates {
             // Inform the scheduler
             self.updates
                 .objects
@@ -933,7 +2818,11 @@ impl State {
                                 // first completion
                                 o.state = state;
                                 o.size = Some(size);
+                                o.session.get_mut().stats.object_produced(size);
                                 o.attributes.update(attributes);
+                                // Small objects are kept also by server, so they
+                                // are sent inline to other workers and clients
+                                o.data = data;
                                 o.trigger_finish_hooks();
                             }
                             for cref in oref.get().consumers.clone() {
@@ -942,6 +2831,7 @@ impl State {
                                 self.update_task_assignment(&cref);
                             }
                             if oref.get().is_needed() {
+                                self.forward_to_consumer(&oref);
                                 self.update_object_assignments(&oref, Some(worker));
                             } else {
                                 self.purge_object(&oref);
@@ -949,6 +2839,10 @@ impl State {
                         }
                         DataObjectState::Finished => {
                             // cloning to some other worker done
+                            {
+                                let o = oref.get();
+                                o.session.get_mut().stats.object_transferred(size);
+                            }
                             self.update_object_assignments(&oref, Some(worker));
                         }
                         _ => {
@@ -971,13 +2865,18 @@ impl State {
                 }
             }
         }
+
+        // Checksums of outputs are known only after the object updates
+        for tref in sampled {
+            self.compare_sample(&tref);
+        }
         worker.check_consistency_opt().unwrap(); // non-recoverable
     }
 
     /// For all workers, if the worker is not overbooked and has ready messages, distribute
     /// more scheduled ready tasks to workers.
     pub fn distribute_tasks(&mut self) {
-        if self.underload_workers.is_empty() {
+        if self.underload_workers.is_empty() || self.stopping || self.scheduling_paused {
             return;
         }
         debug!("Distributing tasks");
@@ -1011,6 +2910,17 @@ impl State {
         // Run scheduler and reset updated objects.
         let changed = self.scheduler.schedule(&mut self.graph, &self.updates);
         self.updates.clear();
+        self.apply_schedule(&changed);
+    }
+
+    /// Do immediate updates of assignments of tasks and objects placed by the scheduler
+    fn apply_schedule(&mut self, changed: &UpdatedOut) {
+        // Preempted tasks are stopped on their workers and scheduled again later
+        for tref in changed.preempted.iter() {
+            info!("Task {} preempted", tref.get_id());
+            self.update_task_assignment(tref);
+            self.updates.tasks.insert(tref.clone());
+        }
 
         // Update assignments of (possibly) changed objects.
         for (wref, os) in changed.objects.iter() {
@@ -1021,13 +2931,554 @@ impl State {
 
         for tref in changed.tasks.iter() {
             self.update_task_assignment(tref);
+            let inputs: Vec<_> = tref.get().inputs.iter().map(|i| i.object.clone()).collect();
+            for oref in inputs {
+                self.forward_to_consumer(&oref);
+            }
         }
         self.underload_workers = self.graph.workers.values().map(|w| w.clone()).collect();
     }
 
+    /// Move tasks waiting on workers with a backlog to idle workers
+    /// (see `ReactiveScheduler::rebalance`). Assigned tasks that have not started
+    /// are pulled back from their workers, only the tasks given up by the workers are moved.
+    pub fn rebalance_tasks(&mut self) {
+        if self.stopping || self.scheduling_paused || self.graph.workers.len() < 2 {
+            return;
+        }
+        let rebalanced = self.scheduler.rebalance(&mut self.graph);
+        if !rebalanced.moved.tasks.is_empty() {
+            info!("Rebalancing moved {} task(s)", rebalanced.moved.tasks.len());
+            self.apply_schedule(&rebalanced.moved);
+        }
+        for (wref, moves) in rebalanced.pull_back {
+            let mut req = wref.get()
+                .control
+                .as_ref()
+                .unwrap()
+                .pull_back_tasks_request();
+            {
+                let mut tasks = req.get().init_tasks(moves.len() as u32);
+                for (i, &(ref tref, _)) in moves.iter().enumerate() {
+                    tref.get_id().to_capnp(&mut tasks.borrow().get(i as u32));
+                }
+            }
+            let state_ref = self.self_ref.clone().unwrap();
+            self.handle
+                .spawn(req.send().promise.then(move |r| -> ::std::result::Result<(), ()> {
+                    let result = r.and_then(|r| {
+                        let r = r.get()?;
+                        Ok(r.get_tasks()?
+                            .iter()
+                            .map(|id| TaskId::from_capnp(&id))
+                            .collect::<HashSet<_>>())
+                    });
+                    match result {
+                        Ok(pulled) => state_ref.get_mut().tasks_pulled_back(&wref, moves, &pulled),
+                        Err(e) => {
+                            warn!("Pulling back tasks from worker {} failed: {}", wref.get_id(), e)
+                        }
+                    }
+                    Ok(())
+                }));
+        }
+    }
+
+    /// Detach tasks pulled back from the worker and place them on the workers chosen
+    /// by rebalancing. Tasks that changed meanwhile (e.g. removed with their session)
+    /// are skipped.
+    fn tasks_pulled_back(
+        &mut self,
+        worker: &WorkerRef,
+        moves: Vec<(TaskRef, WorkerRef)>,
+        pulled: &HashSet<TaskId>,
+    ) {
+        for (tref, target) in moves {
+            let task_id = tref.get_id();
+            if !pulled.contains(&task_id) || !self.graph.tasks.contains_key(&task_id)
+                || tref.get().state != TaskState::Assigned
+                || tref.get().assigned.as_ref() != Some(worker)
+            {
+                continue;
+            }
+            debug!("Task {} pulled back from {}", task_id, worker.get_id());
+            tref.unschedule(&mut self.graph.global_resources);
+            let outputs: Vec<DataObjectRef> = tref.get().outputs.clone();
+            for oref in &outputs {
+                oref.get_mut().scheduled.remove(worker);
+                worker.get_mut().scheduled_objects.remove(oref);
+            }
+            for oref in tref.unforward_inputs(worker) {
+                self.update_object_assignments(&oref, Some(worker));
+            }
+            {
+                let mut t = tref.get_mut();
+                t.assigned = None;
+                t.set_state(TaskState::Ready);
+            }
+            worker.get_mut().assigned_tasks.remove(&tref);
+            for oref in &outputs {
+                self.unassign_object(oref, worker);
+            }
+            match self.scheduler
+                .place_pulled_back(&mut self.graph, &tref, &target)
+            {
+                Some(changed) => self.apply_schedule(&changed),
+                None => {
+                    self.updates.tasks.insert(tref.clone());
+                }
+            }
+            tref.check_consistency_opt().unwrap(); // non-recoverable
+        }
+        worker.check_consistency_opt().unwrap(); // non-recoverable
+    }
+
+    #[inline]
+    pub fn serializer(&self) -> &Serializer {
+        &self.serializer
+    }
+
+    #[inline]
+    pub fn duration_history(&self) -> &DurationHistory {
+        &self.duration_history
+    }
+
+    /// Predict durations of submitted tasks without expected duration from durations
+    /// of finished tasks of the same type and similar size of inputs
+    pub fn predict_durations(&self, tasks: &[TaskRef]) {
+        for tref in tasks {
+            let prediction = {
+                let t = tref.get();
+                if t.duration.is_some() {
+                    continue;
+                }
+                self.duration_history.predict(&t.task_type, input_bytes(&t))
+            };
+            tref.get_mut().predicted_duration = prediction;
+        }
+    }
+
+    /// Read-only snapshot of the graph, or of a session and its sub-sessions
+    pub fn graph_snapshot(&self, session_id: Option<SessionId>) -> GraphSnapshot {
+        GraphSnapshot::new(self, session_id)
+    }
+
+    /// Make placements of the scheduler reproducible (see `ReactiveScheduler::set_seed`)
+    pub fn set_scheduler_seed(&mut self, seed: u64) {
+        self.scheduler.set_seed(seed);
+    }
+
+    /// Weight of inputs present on workers in scheduling
+    /// (see `ReactiveScheduler::set_locality_weight`)
+    pub fn set_locality_weight(&mut self, weight: f64) {
+        self.scheduler.set_locality_weight(weight);
+    }
+
+    pub fn set_placement(&mut self, placement: Placement) {
+        self.scheduler.set_placement(placement);
+    }
+
+    pub fn set_invalidation_limit(&mut self, limit: usize) {
+        self.invalidation_limit = limit;
+    }
+
+    pub fn set_scheduling_interval(&mut self, interval: Duration) {
+        self.scheduling_interval = interval;
+    }
+
+    /// Pause or resume scheduling and change parameters of the scheduler without
+    /// a restart; all parameters are checked before any is changed
+    pub fn configure_scheduler(&mut self, config: SchedulerConfig) -> Result<SchedulerStatus> {
+        if let Some(weight) = config.locality_weight {
+            if !(weight >= 0f64) {
+                bail!("Invalid locality weight {}", weight);
+            }
+        }
+        if let Some(paused) = config.paused {
+            if paused != self.scheduling_paused {
+                info!("Scheduling {}", if paused { "paused" } else { "resumed" });
+            }
+            self.scheduling_paused = paused;
+        }
+        if let Some(placement) = config.placement {
+            self.scheduler.set_placement(placement);
+        }
+        if let Some(weight) = config.locality_weight {
+            self.scheduler.set_locality_weight(weight);
+        }
+        if let Some(interval) = config.scheduling_interval {
+            info!("Scheduling interval is {:?}", interval);
+            self.scheduling_interval = interval;
+        }
+        Ok(self.scheduler_status())
+    }
+
+    pub fn scheduler_status(&self) -> SchedulerStatus {
+        let load = self.shard_load();
+        SchedulerStatus {
+            paused: self.scheduling_paused,
+            placement: self.scheduler.placement(),
+            locality_weight: self.scheduler.locality_weight(),
+            scheduling_interval: units::duration_secs(self.scheduling_interval),
+            ready_tasks: load.ready_tasks,
+            running_tasks: load.running_tasks,
+        }
+    }
+
+    /// Whether the scheduling round should run now. Tasks that become ready sooner than
+    /// `scheduling_interval` after the last round wait for the next one, so bursts
+    /// of submitted or finished tasks are placed together; the main loop is woken up
+    /// when the interval elapses.
+    fn scheduling_due(&mut self) -> bool {
+        let elapsed = self.last_scheduling.elapsed();
+        if elapsed >= self.scheduling_interval {
+            self.last_scheduling = Instant::now();
+            return true;
+        }
+        if !self.scheduling_wakeup {
+            self.scheduling_wakeup = true;
+            let state_ref = self.self_ref.clone().unwrap();
+            let delay = self.scheduling_interval - elapsed;
+            let wakeup = ::tokio_core::reactor::Timeout::new(delay, &self.handle).unwrap();
+            self.handle.spawn(wakeup.then(move |_| {
+                state_ref.get_mut().scheduling_wakeup = false;
+                Ok(())
+            }));
+        }
+        false
+    }
+
+    pub fn set_alerts(&mut self, alerts: Alerts) {
+        self.alerts = Some(alerts);
+    }
+
+    pub fn set_notifier(&mut self, notifier: Notifier) {
+        self.notifier = Some(notifier);
+    }
+
+    pub fn set_redaction(&mut self, redaction: Redaction) {
+        self.redaction = redaction;
+    }
+
+    #[inline]
+    pub fn redaction(&self) -> &Redaction {
+        &self.redaction
+    }
+
+    fn notify_session_finished(&self, session: &SessionRef) {
+        if let Some(ref notifier) = self.notifier {
+            let s = session.get();
+            notifier.notify(
+                NotificationKind::SessionFinished,
+                format!("Session {} '{}' finished", s.id, s.name),
+                format!("All {} submitted tasks of the session are finished.\n", s.tasks.len()),
+            );
+        }
+    }
+
+    /// Values of metrics of alert rules computed from the graph
+    fn alert_metrics(&self) -> HashMap<Metric, f64> {
+        let now = Instant::now();
+        let mut ready_tasks = 0;
+        let mut ready_task_age = 0;
+        for task in self.graph.tasks.values() {
+            let task = task.get();
+            if task.state == TaskState::Ready && !task.held {
+                ready_tasks += 1;
+                if let Some(since) = task.ready_since {
+                    ready_task_age = ::std::cmp::max(ready_task_age, (now - since).as_secs());
+                }
+            }
+        }
+        let (cpus, active) = self.graph
+            .workers
+            .values()
+            .fold((0, 0), |(cpus, active), w| {
+                let w = w.get();
+                (cpus + w.resources.cpus(), active + w.active_resources)
+            });
+        let mut values = HashMap::new();
+        values.insert(Metric::ReadyTasks, ready_tasks as f64);
+        values.insert(Metric::ReadyTaskAge, ready_task_age as f64);
+        values.insert(Metric::Workers, self.graph.workers.len() as f64);
+        if cpus > 0 {
+            values.insert(Metric::CpuUtilization, active as f64 / cpus as f64);
+        }
+        values
+    }
+
+    /// Evaluate alert rules, log changes of alerts and post them to webhooks
+    pub fn check_alerts(&mut self) {
+        let values = self.alert_metrics();
+        let changes = match self.alerts {
+            Some(ref mut alerts) => alerts.check(&values),
+            None => return,
+        };
+        for change in changes {
+            let message = change.message();
+            if change.firing {
+                warn!("{}", message);
+            } else {
+                info!("{}", message);
+            }
+            if let Some(ref notifier) = self.notifier {
+                notifier.notify(NotificationKind::Alert, message.clone(), message.clone());
+            }
+            self.logger
+                .add_alert_event(change.alert.clone(), change.firing, message);
+            if let Some(ref url) = change.webhook {
+                self.post_alert(url, &change);
+            }
+        }
+    }
+
+    fn post_alert(&self, url: &str, change: &AlertChange) {
+        use hyper::header::ContentType;
+
+        let uri = match url.parse() {
+            Ok(uri) => uri,
+            Err(e) => {
+                warn!("Invalid webhook URL '{}': {}", url, e);
+                return;
+            }
+        };
+        let mut request = ::hyper::Request::new(::hyper::Method::Post, uri);
+        request.headers_mut().set(ContentType::json());
+        request.set_body(::serde_json::to_string(change).unwrap());
+        let url = url.to_string();
+        let client = ::hyper::Client::new(&self.handle);
+        self.handle.spawn(
+            client
+                .request(request)
+                .map(|response| {
+                    if !response.status().is_success() {
+                        warn!("Webhook of alert returned {}", response.status());
+                    }
+                })
+                .map_err(move |e| warn!("Posting alert to {} failed: {}", url, e)),
+        );
+    }
+
+    /// Return the scheduler's record of why the task was placed where it was
+    pub fn scheduler_decision(&self, task_id: &TaskId) -> Option<&Decision> {
+        self.scheduler.explain(task_id)
+    }
+
     pub fn handle(&self) -> &Handle {
         &self.handle
     }
+
+    #[inline]
+    pub fn worker_count(&self) -> usize {
+        self.graph.workers.len()
+    }
+
+    #[inline]
+    pub fn inline_threshold(&self) -> usize {
+        self.inline_threshold
+    }
+
+    #[inline]
+    pub fn instance_id(&self) -> &str {
+        &self.instance_id
+    }
+
+    /// Capabilities announced by the server to workers at registration
+    pub fn capabilities(&self) -> Capabilities {
+        let mut capabilities = Capabilities::new();
+        capabilities.insert(capabilities::TRANSFER_INLINE);
+        capabilities
+    }
+
+    /// Threshold of inlining data sent to the worker (0 when the worker does not accept
+    /// inline data)
+    fn inline_threshold_for(&self, worker: &Worker) -> usize {
+        if worker.capabilities.has(capabilities::TRANSFER_INLINE) {
+            self.inline_threshold
+        } else {
+            0
+        }
+    }
+
+    /// Start a rolling upgrade of workers to the image, a running upgrade is replaced
+    pub fn start_upgrade(&mut self, image: String, batch: usize) -> Result<()> {
+        let plan = UpgradePlan::new(image, batch)?;
+        info!(
+            "Upgrade of workers to image '{}' requested (batch {})",
+            plan.image, plan.batch
+        );
+        self.upgrade = Some(plan);
+        Ok(())
+    }
+
+    pub fn upgrade_status(&self) -> UpgradeStatus {
+        match self.upgrade {
+            Some(ref plan) => plan.status(self),
+            None => UpgradeStatus {
+                done: true,
+                ..Default::default()
+            },
+        }
+    }
+
+    /// Progress of the running upgrade
+    fn drive_upgrade(&mut self) {
+        if let Some(mut plan) = self.upgrade.take() {
+            plan.drive(self);
+            self.upgrade = Some(plan);
+        }
+    }
+
+    /// Release workers to the broker of session shards, returns their number
+    pub fn release_workers(&mut self, count: usize) -> u32 {
+        let mut release = ::std::mem::replace(&mut self.release, Default::default());
+        let released = release.release(self, count);
+        self.release = release;
+        released as u32
+    }
+
+    fn drive_release(&mut self) {
+        let mut release = ::std::mem::replace(&mut self.release, Default::default());
+        release.drive(self);
+        self.release = release;
+    }
+
+    /// Load of the server polled by the broker of session shards
+    pub fn shard_load(&self) -> ShardLoad {
+        let mut load = ShardLoad::default();
+        for w in self.graph.workers.values() {
+            let w = w.get();
+            if w.draining {
+                load.draining += 1;
+            } else if w.error.is_none() {
+                load.workers += 1;
+            }
+            load.running_tasks += w.assigned_tasks.len() as u32;
+        }
+        load.ready_tasks = self.graph
+            .tasks
+            .values()
+            .filter(|t| {
+                let t = t.get();
+                t.state == TaskState::Ready && !t.held
+            })
+            .count() as u32;
+        load
+    }
+
+    /// Ask the (drained) worker to terminate, it is removed when its connection is closed
+    pub fn ask_worker_to_leave(&mut self, worker: &WorkerRef) {
+        worker.get_mut().leaving = true;
+        let req = match worker.get().control {
+            Some(ref control) => control.leave_request(),
+            None => return,
+        };
+        let worker_id = worker.get_id();
+        self.handle.spawn(req.send().promise.map(|_| ()).map_err(move |e| {
+            // The worker usually exits before the reply is delivered
+            debug!("Leave request of worker {}: {}", worker_id, e)
+        }));
+    }
+
+    #[inline]
+    pub fn is_stopping(&self) -> bool {
+        self.stopping
+    }
+
+    /// Stop scheduling before shutdown and fail all sessions (their running tasks are
+    /// stopped). Returns the number of failed sessions.
+    pub fn stop_scheduling(&mut self) -> Result<u32> {
+        self.stopping = true;
+        let sessions: Vec<SessionRef> = self.graph.sessions.values().cloned().collect();
+        let mut failed = 0;
+        for session in sessions {
+            // Sub-sessions are failed with their parents
+            if session.get().is_failed() {
+                continue;
+            }
+            self.fail_session(
+                &session,
+                "Server is shutting down".to_string(),
+                None,
+                TaskId::invalid(),
+            )?;
+            failed += 1;
+        }
+        Ok(failed)
+    }
+
+    /// Ask all workers to terminate, returns their number
+    pub fn stop_workers(&mut self) -> u32 {
+        let workers: Vec<WorkerRef> = self.graph.workers.values().cloned().collect();
+        for worker in &workers {
+            self.ask_worker_to_leave(worker);
+        }
+        workers.len() as u32
+    }
+
+    /// Configure fault injection on all workers (and on workers connected later)
+    pub fn set_chaos(
+        &mut self,
+        config: ChaosConfig,
+    ) -> Result<Box<Future<Item = (), Error = Error>>> {
+        config.check()?;
+        let futures: Vec<_> = self.graph
+            .workers
+            .values()
+            .filter_map(|w| w.get().control.clone())
+            .map(|control| send_chaos_config(&control, &config))
+            .collect();
+        self.chaos_config = if config.is_empty() {
+            None
+        } else {
+            Some(config)
+        };
+        Ok(Box::new(::futures::future::join_all(futures).map(|_| ())))
+    }
+
+    /// Pass the fault injection configuration to a newly connected worker
+    pub fn init_worker_chaos(&self, worker: &WorkerRef) {
+        let worker = worker.get();
+        if let (Some(config), Some(control)) =
+            (self.chaos_config.as_ref(), worker.control.as_ref())
+        {
+            let worker_id = *worker.id();
+            self.handle.spawn(
+                send_chaos_config(control, config)
+                    .map_err(move |e| error!("Fault injection on worker {}: {}", worker_id, e)),
+            );
+        }
+    }
+
+    /// Stop the server in the next turn
+    pub fn terminate(&mut self) {
+        self.stop_server = true;
+    }
+
+    /// Clients are authenticated by tokens (--access-file)
+    #[inline]
+    pub fn has_access_control(&self) -> bool {
+        self.access.is_some()
+    }
+
+    /// Identity of a client with the access token
+    pub fn authenticate(&self, token: &str) -> Result<Identity> {
+        match self.access {
+            Some(ref access) => access.authenticate(token),
+            None => Ok(Identity::anonymous()),
+        }
+    }
+
+    /// Identity of a user authenticated by a trusted reverse proxy
+    pub fn authenticate_user(&self, name: &str) -> Result<Identity> {
+        match self.access {
+            Some(ref access) => access.identity_by_name(name),
+            None => Ok(Identity {
+                name: name.to_string(),
+                role: Identity::anonymous().role,
+            }),
+        }
+    }
 }
 
 impl ConsistencyCheck for State {
@@ -1056,6 +3507,36 @@ impl ConsistencyCheck for State {
 /// Note: No `Drop` impl as a `State` is assumed to live forever.
 pub type StateRef = WrappedRcRefCell<State>;
 
+fn send_chaos_config(
+    control: &::worker_capnp::worker_control::Client,
+    config: &ChaosConfig,
+) -> Box<Future<Item = (), Error = Error>> {
+    let mut req = control.set_chaos_request();
+    req.get()
+        .set_config(&::serde_json::to_string(config).unwrap());
+    Box::new(req.send().promise.map(|_| ()).map_err(Error::from))
+}
+
+/// Finished published object that is kept for other sessions using it
+/// when its own session is cleared
+fn is_retained(object: &DataObject) -> bool {
+    object.state == DataObjectState::Finished && !object.user_sessions.is_empty()
+}
+
+/// Random choice of a task sampled with probability `rate`
+fn sample_hit(rate: f64, task_id: TaskId) -> bool {
+    let mut hasher = RandomState::new().build_hasher();
+    task_id.hash(&mut hasher);
+    (hasher.finish() as f64) < rate * (u64::max_value() as f64)
+}
+
+/// Random id of a run of the server
+fn new_instance_id() -> String {
+    use chacha20poly1305::aead::OsRng;
+    use chacha20poly1305::aead::rand_core::RngCore;
+    format!("{:016x}{:016x}", OsRng.next_u64(), OsRng.next_u64())
+}
+
 impl StateRef {
     pub fn new(
         handle: Handle,
@@ -1063,17 +3544,51 @@ impl StateRef {
         http_listen_address: SocketAddr,
         log_dir: PathBuf,
         test_mode: bool,
+        global_resources: ResourcePool,
+        client_keepalive: Option<Duration>,
+        orphan_grace: Duration,
+        inline_threshold: usize,
+        access: Option<AccessControl>,
+        rpc_tls: Option<TlsAcceptor>,
+        http_tls: Option<TlsAcceptor>,
+        http_config: HttpConfig,
     ) -> Self {
+        let mut graph = Graph::new();
+        graph.global_resources = global_resources;
         let s = Self::wrap(State {
-            graph: Default::default(),
+            graph,
             test_mode: test_mode,
             listen_address: listen_address,
             http_listen_address: http_listen_address,
+            rpc_tls,
+            http_tls,
+            http_config,
+            client_keepalive,
+            orphan_grace,
+            inline_threshold,
+            instance_id: new_instance_id(),
+            invalidation_limit: DEFAULT_INVALIDATION_LIMIT,
+            scheduling_interval: Duration::from_secs(0),
+            last_scheduling: Instant::now(),
+            scheduling_wakeup: false,
+            scheduling_paused: false,
+            alerts: None,
+            notifier: None,
+            redaction: Default::default(),
             handle: handle,
             scheduler: Default::default(),
             underload_workers: Default::default(),
             updates: Default::default(),
+            objects_to_delete: Default::default(),
+            secrets: SecretStore::new(),
+            serializer: Serializer::start().unwrap(),
+            duration_history: DurationHistory::new(),
+            access,
             stop_server: false,
+            stopping: false,
+            upgrade: None,
+            release: Default::default(),
+            chaos_config: None,
             self_ref: None,
             logger: Box::new(SQLiteLogger::new(&log_dir).unwrap()),
             timer: tokio_timer::wheel()
@@ -1081,6 +3596,8 @@ impl StateRef {
                 .num_slots(512)
                 .build(),
             ignored_sessions: Default::default(),
+            ignored_tasks: Default::default(),
+            ignored_objects: Default::default(),
         });
         s.get_mut().self_ref = Some(s.clone());
         s
@@ -1091,6 +3608,8 @@ impl StateRef {
         let http_listen_address = self.get().http_listen_address;
         let handle = self.get().handle.clone();
         let listener = TcpListener::bind(&listen_address, &handle).unwrap();
+        // Since listen port may be 0, we need to update the real port
+        self.get_mut().listen_address = listener.local_addr().unwrap();
 
         let state = self.clone();
         let future = listener
@@ -1105,33 +3624,69 @@ impl StateRef {
         handle.spawn(future);
 
         // ---- Start HTTP server ----
-        //let listener = TcpListener::bind(&http_listen_address, &handle).unwrap();
         let handle1 = self.get().handle.clone();
         let state = self.clone();
-        let http_server = Http::new()
-            .serve_addr_handle(&http_listen_address, &handle1, move || {
-                Ok(RequestHandler::new(state.clone()))
-            })
-            .unwrap();
-        handle.spawn(
-            http_server
-                .for_each(move |conn| {
-                    handle1.spawn(conn.map(|_| ()).map_err(|e| {
-                        error!("Http connection error: {:?}", e);
-                    }));
-                    Ok(())
+        let http_tls = self.get().http_tls.clone();
+        let scheme = if http_tls.is_some() { "https" } else { "http" };
+        if let Some(acceptor) = http_tls {
+            // Hyper serves TLS streams connection by connection
+            let listener = TcpListener::bind(&http_listen_address, &handle).unwrap();
+            let http = Http::<::hyper::Chunk>::new();
+            handle.spawn(
+                listener
+                    .incoming()
+                    .for_each(move |(stream, address)| {
+                        let state = state.clone();
+                        let http = http.clone();
+                        handle1.spawn(
+                            acceptor
+                                .accept(stream)
+                                .map_err(move |e| {
+                                    error!("TLS handshake with {} failed: {}", address, e)
+                                })
+                                .and_then(move |stream| {
+                                    let handler = RequestHandler::new(state, Some(address));
+                                    http.serve_connection(stream, handler)
+                                        .map(|_| ())
+                                        .map_err(|e| {
+                                            error!("Http connection error: {:?}", e);
+                                        })
+                                }),
+                        );
+                        Ok(())
+                    })
+                    .map_err(|e| {
+                        panic!("Listening failed {:?}", e);
+                    }),
+            );
+        } else {
+            let http_server = Http::new()
+                .serve_addr_handle(&http_listen_address, &handle1, move || {
+                    Ok(RequestHandler::new(state.clone(), None))
                 })
-                .map_err(|_| ()),
-        );
+                .unwrap();
+            handle.spawn(
+                http_server
+                    .for_each(move |conn| {
+                        handle1.spawn(conn.map(|_| ()).map_err(|e| {
+                            error!("Http connection error: {:?}", e);
+                        }));
+                        Ok(())
+                    })
+                    .map_err(|_| ()),
+            );
+        }
 
         let hostname = ::common::sys::get_hostname();
         info!(
-            "Dashboard: http://{}:{}/",
+            "Dashboard: {}://{}:{}/",
+            scheme,
             hostname,
             http_listen_address.port()
         );
         info!(
-            "Lite dashboard: http://{}:{}/lite/",
+            "Lite dashboard: {}://{}:{}/lite/",
+            scheme,
             hostname,
             http_listen_address.port()
         );
@@ -1148,39 +3703,127 @@ impl StateRef {
             })
             .map_err(|e| error!("Logging error {}", e));
         handle.spawn(logging);
+
+        // ---- Start checking of session TTLs ----
+        let state = self.clone();
+        let interval = timer.interval(Duration::from_secs(SESSION_EXPIRATION_INTERVAL));
+        let expiration = interval
+            .for_each(move |()| {
+                state.get_mut().expire_sessions().unwrap();
+                Ok(())
+            })
+            .map_err(|e| error!("Session expiration error {}", e));
+        handle.spawn(expiration);
+
+        // ---- Start rebalancing of waiting tasks ----
+        let state = self.clone();
+        let interval = timer.interval(Duration::from_secs(REBALANCE_INTERVAL));
+        let rebalancing = interval
+            .for_each(move |()| {
+                state.get_mut().rebalance_tasks();
+                Ok(())
+            })
+            .map_err(|e| error!("Rebalancing error {}", e));
+        handle.spawn(rebalancing);
+
+        // ---- Start checking of alert rules ----
+        if self.get().alerts.is_some() {
+            let state = self.clone();
+            let interval = timer.interval(Duration::from_secs(ALERT_CHECK_INTERVAL));
+            let alerts = interval
+                .for_each(move |()| {
+                    state.get_mut().check_alerts();
+                    Ok(())
+                })
+                .map_err(|e| error!("Alert checking error {}", e));
+            handle.spawn(alerts);
+        }
     }
 
     /// Main loop State entry. Returns `false` when the server should stop.
     pub fn turn(&self) -> bool {
-        // TODO: better conditional scheduling
-        if !self.get().updates.is_empty() {
+        if !self.get().updates.is_empty() && !self.get().scheduling_paused
+            && self.get_mut().scheduling_due()
+        {
             self.get_mut().run_scheduler();
             self.get().check_consistency_opt().unwrap(); // unrecoverable
         }
 
         // Assign ready tasks to workers (up to overbook limit)
         self.get_mut().distribute_tasks();
+        self.get_mut().drive_upgrade();
+        self.get_mut().drive_release();
+        self.get_mut().send_object_deletes();
         !self.get().stop_server
     }
 
+    /// Serve the connection of a worker running in the same process
+    /// (`rain start --simple --in-process`)
+    pub fn connect_local_worker<S>(&self, stream: S)
+    where
+        S: AsyncRead + AsyncWrite + 'static,
+    {
+        let address: SocketAddr = ([127, 0, 0, 1], 0).into();
+        info!("Connecting worker running in the same process");
+        self.serve_connection(stream, address);
+    }
+
     fn on_connection(&self, stream: TcpStream, address: SocketAddr) {
         // Handle an incoming connection; spawn gate object for it
 
         info!("New connection from {}", address);
         stream.set_nodelay(true).unwrap();
-        let bootstrap = ::server_capnp::server_bootstrap::ToClient::new(ServerBootstrapImpl::new(
-            self,
-            address,
-        )).from_server::<::capnp_rpc::Server>();
+        if let Err(e) = stream.set_keepalive(self.get().client_keepalive) {
+            warn!("Cannot set keepalive on connection {}: {}", address, e);
+        }
+        let tls = self.get().rpc_tls.clone();
+        match tls {
+            Some(acceptor) => {
+                let state = self.clone();
+                let future = acceptor
+                    .accept(stream)
+                    .map_err(move |e| error!("TLS handshake with {} failed: {}", address, e))
+                    .map(move |stream| state.serve_connection(stream, address));
+                self.get().handle.spawn(future);
+            }
+            None => self.serve_connection(stream, address),
+        }
+    }
 
-        let rpc_system = new_rpc_system(stream, Some(bootstrap.client));
-        self.get()
-            .handle
-            .spawn(rpc_system.map_err(|e| panic!("RPC error: {:?}", e)));
+    fn serve_connection<S>(&self, stream: S, address: SocketAddr)
+    where
+        S: AsyncRead + AsyncWrite + 'static,
+    {
+        // Workers may request compressed framing by a preamble, clients use plain framing
+        let state = self.clone();
+        let future = detect_framing(
+            stream,
+            Duration::from_secs(FRAMING_TIMEOUT_SECONDS),
+            &self.get().handle,
+        ).map_err(move |e| error!("Reading from connection {} failed: {}", address, e))
+            .and_then(move |(stream, framing, prefix)| {
+                debug!("Connection {} uses {:?} framing", address, framing);
+                let bootstrap = ::server_capnp::server_bootstrap::ToClient::new(
+                    ServerBootstrapImpl::new(&state, address),
+                ).from_server::<::capnp_rpc::Server>();
+
+                let rpc_system =
+                    new_framed_rpc_system(stream, framing, prefix, Some(bootstrap.client));
+                // Connection errors (e.g. keepalive timeout of a silently disappeared client)
+                // end the connection, the peer is then handled as disconnected
+                rpc_system.map_err(move |e| error!("RPC error on connection {}: {:?}", address, e))
+            });
+        self.get().handle.spawn(future);
     }
 
     #[inline]
     pub fn handle(&self) -> Handle {
         self.get().handle.clone()
     }
+
+    /// Address of RPC listener (with the real port after `start`)
+    #[inline]
+    pub fn listen_address(&self) -> SocketAddr {
+        self.get().listen_address
+    }
 }
@@ -0,0 +1,71 @@
+use std::collections::HashMap;
+use std::rc::Rc;
+
+use common::RcSet;
+use common::labels::Labels;
+use server::graph::TaskRef;
+
+/// Tag of a task in form "key=value"; the string is shared by all tasks with the tag
+pub type Tag = Rc<str>;
+
+/// Split the tag into its key and value
+pub fn split_tag(tag: &str) -> (&str, &str) {
+    match tag.find('=') {
+        Some(pos) => (&tag[..pos], &tag[pos + 1..]),
+        None => (tag, ""),
+    }
+}
+
+/// Index of tasks by their tags (attribute "tags" of tasks merged with tags of their
+/// sessions), so tasks with given tags are found without visiting all tasks
+#[derive(Default, Clone)]
+pub struct TagIndex {
+    tasks: HashMap<Tag, RcSet<TaskRef>>,
+}
+
+impl TagIndex {
+    /// Add the task under its tags, returns the shared tags stored in the task
+    pub fn add(&mut self, tref: &TaskRef, tags: &Labels) -> Vec<Tag> {
+        tags.iter()
+            .map(|(key, value)| {
+                let entry = self.tasks.entry(Rc::from(format!("{}={}", key, value)));
+                let tag = entry.key().clone();
+                entry.or_insert_with(Default::default).insert(tref.clone());
+                tag
+            })
+            .collect()
+    }
+
+    pub fn remove(&mut self, tref: &TaskRef, tags: &[Tag]) {
+        for tag in tags {
+            let empty = match self.tasks.get_mut(tag) {
+                Some(tasks) => {
+                    tasks.remove(tref);
+                    tasks.is_empty()
+                }
+                None => false,
+            };
+            if empty {
+                self.tasks.remove(tag);
+            }
+        }
+    }
+
+    /// Tasks that have all the tags ("key=value"), `tags` must not be empty
+    pub fn find(&self, tags: &[String]) -> Vec<TaskRef> {
+        assert!(!tags.is_empty());
+        let mut sets = Vec::new();
+        for tag in tags {
+            match self.tasks.get(tag.as_str()) {
+                Some(tasks) => sets.push(tasks),
+                None => return Vec::new(),
+            }
+        }
+        sets.sort_by_key(|tasks| tasks.len());
+        sets[0]
+            .iter()
+            .filter(|tref| sets[1..].iter().all(|tasks| tasks.contains(tref)))
+            .cloned()
+            .collect()
+    }
+}
@@ -0,0 +1,119 @@
+use std::time::{Duration, Instant};
+
+use server::graph::WorkerRef;
+use server::state::State;
+use errors::Result;
+
+/// How often the drain status of workers is checked during an upgrade (in milliseconds)
+const UPGRADE_CHECK_INTERVAL: u64 = 1000;
+
+/// Rolling upgrade of workers (see `upgradeWorkers` in client.capnp)
+#[derive(Debug)]
+pub struct UpgradePlan {
+    /// Image the workers are upgraded to
+    pub image: String,
+    /// Maximal number of workers drained at once
+    pub batch: usize,
+    pub done: bool,
+    last_check: Option<Instant>,
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct UpgradeStatus {
+    pub image: String,
+    pub outdated: usize,
+    pub draining: usize,
+    pub upgraded: usize,
+    pub done: bool,
+}
+
+impl UpgradePlan {
+    pub fn new(image: String, batch: usize) -> Result<Self> {
+        if image.is_empty() {
+            bail!("Image of the upgrade is empty");
+        }
+        if batch == 0 {
+            bail!("Batch of the upgrade has to be at least 1");
+        }
+        Ok(UpgradePlan {
+            image,
+            batch,
+            done: false,
+            last_check: None,
+        })
+    }
+
+    /// Connected workers that are not running the target image
+    fn outdated(&self, state: &State) -> Vec<WorkerRef> {
+        let mut workers: Vec<_> = state
+            .graph
+            .workers
+            .values()
+            .filter(|w| {
+                let w = w.get();
+                w.error.is_none() && w.image != self.image
+            })
+            .cloned()
+            .collect();
+        // Workers with the least work are drained first
+        workers.sort_by_key(|w| (w.get().assigned_tasks.len(), w.get_id()));
+        workers
+    }
+
+    pub fn status(&self, state: &State) -> UpgradeStatus {
+        let outdated = self.outdated(state);
+        UpgradeStatus {
+            image: self.image.clone(),
+            outdated: outdated.len(),
+            draining: outdated.iter().filter(|w| w.get().draining).count(),
+            upgraded: state
+                .graph
+                .workers
+                .values()
+                .filter(|w| w.get().image == self.image)
+                .count(),
+            done: self.done,
+        }
+    }
+
+    /// Drain next workers of the batch and ask drained workers to leave
+    pub fn drive(&mut self, state: &mut State) {
+        if self.done {
+            return;
+        }
+        let now = Instant::now();
+        if let Some(last_check) = self.last_check {
+            if now < last_check + Duration::from_millis(UPGRADE_CHECK_INTERVAL) {
+                return;
+            }
+        }
+        self.last_check = Some(now);
+
+        let outdated = self.outdated(state);
+        if outdated.is_empty() {
+            info!("Upgrade of workers to image '{}' finished", self.image);
+            self.done = true;
+            return;
+        }
+        let draining = outdated.iter().filter(|w| w.get().draining).count();
+        for wref in outdated
+            .iter()
+            .filter(|w| !w.get().draining)
+            .take(self.batch.saturating_sub(draining))
+        {
+            info!(
+                "Upgrade to '{}': draining worker {} (image '{}')",
+                self.image,
+                wref.get_id(),
+                wref.get().image
+            );
+            state.drain_worker(wref);
+        }
+        for wref in outdated.iter().filter(|w| w.get().draining) {
+            if !wref.get().leaving && state.drain_status(wref) {
+                info!("Upgrade to '{}': worker {} leaves", self.image, wref.get_id());
+                state.ask_worker_to_leave(wref);
+            }
+        }
+    }
+}
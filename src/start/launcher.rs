@@ -0,0 +1,78 @@
+use std::process::Command;
+
+use librain::errors::Result;
+
+/// How the starter runs workers on remote hosts (option --remote-launcher)
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RemoteLauncher {
+    /// SSH connection to each host (see `RemoteProcess`)
+    Ssh,
+    /// Torque/PBS task manager (`pbsdsh -h HOST`)
+    Pbsdsh,
+    /// Slurm job step (`srun --nodelist=HOST`)
+    Srun,
+    /// MPI launcher (`mpirun -np 1 -host HOST`)
+    Mpirun,
+}
+
+impl RemoteLauncher {
+    pub fn parse(value: &str) -> Result<Self> {
+        Ok(match value {
+            "ssh" => RemoteLauncher::Ssh,
+            "pbsdsh" => RemoteLauncher::Pbsdsh,
+            "srun" => RemoteLauncher::Srun,
+            "mpirun" => RemoteLauncher::Mpirun,
+            _ => bail!("Unknown remote launcher '{}'", value),
+        })
+    }
+
+    /// Local command that runs shell script `script` on `host`.
+    /// The launcher process lives as long as the remote process, so it is
+    /// monitored and stopped as a local process; not used for SSH.
+    pub fn command(&self, host: &str, script: &str) -> Command {
+        let mut command = match *self {
+            RemoteLauncher::Pbsdsh => {
+                let mut command = Command::new("pbsdsh");
+                command.arg("-h").arg(host);
+                command
+            }
+            RemoteLauncher::Srun => {
+                let mut command = Command::new("srun");
+                command
+                    .arg("--nodes=1")
+                    .arg("--ntasks=1")
+                    .arg(format!("--nodelist={}", host));
+                command
+            }
+            RemoteLauncher::Mpirun => {
+                let mut command = Command::new("mpirun");
+                command.arg("-np").arg("1").arg("-host").arg(host);
+                command
+            }
+            RemoteLauncher::Ssh => unreachable!("SSH workers are started by RemoteProcess"),
+        };
+        command.arg("/bin/sh").arg("-c").arg(script);
+        command
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::RemoteLauncher;
+
+    #[test]
+    fn test_launcher_command() {
+        assert_eq!(RemoteLauncher::parse("srun").unwrap(), RemoteLauncher::Srun);
+        assert!(RemoteLauncher::parse("rsh").is_err());
+        let command = RemoteLauncher::Srun.command("node1", "true");
+        assert_eq!(
+            format!("{:?}", command),
+            r#""srun" "--nodes=1" "--ntasks=1" "--nodelist=node1" "/bin/sh" "-c" "true""#
+        );
+        let command = RemoteLauncher::Pbsdsh.command("node2", "true");
+        assert_eq!(
+            format!("{:?}", command),
+            r#""pbsdsh" "-h" "node2" "/bin/sh" "-c" "true""#
+        );
+    }
+}
@@ -1,4 +1,6 @@
 pub mod common;
+pub mod launcher;
 pub mod process;
+pub mod spec;
 pub mod ssh;
 pub mod starter;
@@ -1,6 +1,10 @@
 use std::process::{Child, Command, Stdio};
 use std::os::unix::io::{FromRawFd, IntoRawFd};
+use std::os::unix::process::CommandExt;
 use std::path::Path;
+use std::time::{Duration, Instant};
+
+use nix::libc;
 
 use librain::errors::Result;
 
@@ -40,6 +44,14 @@ impl Process {
 
         command.stdout(log_path_out_pipe);
         command.stderr(log_path_err_pipe);
+        // Own process group, so Ctrl-C in the terminal reaches only the starter
+        // that stops the processes in order
+        command.before_exec(|| {
+            unsafe {
+                libc::setpgid(0, 0);
+            }
+            Ok(())
+        });
 
         Ok(Self {
             name: name.to_string(),
@@ -52,24 +64,55 @@ impl Process {
         self.child.id()
     }
 
-    pub fn kill(&mut self) -> Result<()> {
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Remove the ready file of a process that is not ready yet
+    pub fn remove_ready_file(&self) {
         if let Readiness::WaitingForReadyFile(ref path) = self.ready {
             if path.exists() {
                 use std::error::Error;
                 // This error is non fatal, so we just log an error and continue
                 match ::std::fs::remove_file(path) {
-                    Ok(_) => debug!("Ready file of killed process removed"),
+                    Ok(_) => debug!("Ready file of process '{}' removed", self.name),
                     Err(e) => error!(
-                        "Cannot remove ready file for killed process: {}",
+                        "Cannot remove ready file of process '{}': {}",
+                        self.name,
                         e.description()
                     ),
                 }
             }
         }
+    }
+
+    pub fn kill(&mut self) -> Result<()> {
+        self.remove_ready_file();
         self.child.kill()?;
         Ok(())
     }
 
+    /// Ask the process to terminate (SIGTERM)
+    pub fn terminate(&mut self) {
+        unsafe {
+            libc::kill(self.child.id() as libc::pid_t, libc::SIGTERM);
+        }
+    }
+
+    /// Wait until the process exits, returns false when it is still running after the timeout
+    pub fn wait_timeout(&mut self, timeout: Duration) -> Result<bool> {
+        let deadline = Instant::now() + timeout;
+        loop {
+            if self.child.try_wait()?.is_some() {
+                return Ok(true);
+            }
+            if Instant::now() >= deadline {
+                return Ok(false);
+            }
+            ::std::thread::sleep(Duration::from_millis(100));
+        }
+    }
+
     pub fn check_run(&mut self) -> Result<()> {
         if let Some(exit_code) = self.child.try_wait()? {
             bail!(
@@ -0,0 +1,217 @@
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+use librain::common::units::deserialize_size_opt;
+use librain::errors::Result;
+use librain::worker::subworkers::load_subworker_kinds;
+use start::launcher::RemoteLauncher;
+use start::starter::{StarterConfig, WorkerGroup};
+
+/// Cluster launched by `rain start --spec=FILE` (a TOML file), see "Cluster spec"
+/// in the documentation. Relative paths are relative to the directory of the file.
+#[derive(Deserialize, Debug, Default)]
+#[serde(deny_unknown_fields)]
+pub struct ClusterSpec {
+    /// Directory for logs of the server and workers
+    pub log_dir: Option<PathBuf>,
+    /// Commands executed on each remote host before a worker is started
+    pub remote_init: Option<String>,
+    /// "ssh" (default), "pbsdsh", "srun" or "mpirun"
+    pub remote_launcher: Option<String>,
+    #[serde(default)]
+    pub server: ServerSpec,
+    #[serde(default)]
+    pub workers: Vec<WorkerGroupSpec>,
+}
+
+#[derive(Deserialize, Debug, Default)]
+#[serde(deny_unknown_fields)]
+pub struct ServerSpec {
+    /// Listening address: port, address or address:port
+    pub listen: Option<String>,
+    pub http_listen: Option<String>,
+    /// Cluster-wide resources: name -> count
+    #[serde(default)]
+    pub global_resources: BTreeMap<String, u32>,
+    /// Additional arguments of `rain server`
+    #[serde(default)]
+    pub args: Vec<String>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(untagged)]
+pub enum SubworkersSpec {
+    /// JSON file of worker option --subworkers
+    File(PathBuf),
+    /// The same content inline: type -> subworker kind
+    Inline(BTreeMap<String, ::toml::Value>),
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct WorkerGroupSpec {
+    /// Number of workers (on each host)
+    #[serde(default = "default_count")]
+    pub count: u32,
+    /// Hosts of remote workers, local workers are started when empty
+    #[serde(default)]
+    pub hosts: Vec<String>,
+    pub cpus: Option<i32>,
+    /// Memory in bytes, units are accepted (e.g. "16GiB")
+    #[serde(default, deserialize_with = "deserialize_size_opt")]
+    pub memory: Option<u64>,
+    pub gpus: Option<u32>,
+    /// Named resources of workers: name -> count
+    #[serde(default)]
+    pub resources: BTreeMap<String, u32>,
+    /// Labels of workers matched by placement constraints of tasks: key -> value
+    #[serde(default)]
+    pub labels: BTreeMap<String, String>,
+    /// Working directory of workers (data objects of the worker are placed there)
+    pub work_dir: Option<PathBuf>,
+    pub subworkers: Option<SubworkersSpec>,
+    /// Additional arguments of `rain worker`
+    #[serde(default)]
+    pub args: Vec<String>,
+}
+
+fn default_count() -> u32 {
+    1
+}
+
+impl ClusterSpec {
+    pub fn load(path: &Path) -> Result<Self> {
+        let mut content = String::new();
+        File::open(path)
+            .and_then(|mut file| file.read_to_string(&mut content))
+            .map_err(|e| format!("Cannot read cluster spec {:?}: {}", path, e))?;
+        let mut spec: ClusterSpec = ::toml::from_str(&content)
+            .map_err(|e| format!("Invalid cluster spec {:?}: {}", path, e))?;
+        let base = path.parent().unwrap_or_else(|| Path::new("."));
+        spec.resolve_paths(base);
+        Ok(spec)
+    }
+
+    fn resolve_paths(&mut self, base: &Path) {
+        if let Some(ref mut log_dir) = self.log_dir {
+            *log_dir = base.join(&log_dir);
+        }
+        for group in &mut self.workers {
+            if let Some(ref mut work_dir) = group.work_dir {
+                *work_dir = base.join(&work_dir);
+            }
+            if let Some(SubworkersSpec::File(ref mut path)) = group.subworkers {
+                *path = base.join(&path);
+            }
+        }
+    }
+
+    /// Set servers arguments and worker groups of the starter. Inline subworker kinds
+    /// are written into the log directory (it has to be shared with remote hosts).
+    pub fn apply(&self, config: &mut StarterConfig) -> Result<()> {
+        if let Some(ref remote_init) = self.remote_init {
+            config.remote_init = remote_init.clone();
+        }
+        if let Some(ref launcher) = self.remote_launcher {
+            config.remote_launcher = RemoteLauncher::parse(launcher)?;
+        }
+        for (name, count) in &self.server.global_resources {
+            config.global_resources.push(format!("{}={}", name, count));
+        }
+        config.server_args.extend(self.server.args.iter().cloned());
+
+        for (i, group) in self.workers.iter().enumerate() {
+            let mut args = Vec::new();
+            if let Some(cpus) = group.cpus {
+                args.push(format!("--cpus={}", cpus));
+            }
+            if let Some(memory) = group.memory {
+                args.push(format!("--memory={}", memory));
+            }
+            if let Some(gpus) = group.gpus {
+                args.push(format!("--gpus={}", gpus));
+            }
+            for (name, count) in &group.resources {
+                args.push("--resource".to_string());
+                args.push(format!("{}={}", name, count));
+            }
+            for (key, value) in &group.labels {
+                args.push("--label".to_string());
+                args.push(format!("{}={}", key, value));
+            }
+            if let Some(ref work_dir) = group.work_dir {
+                args.push("--workdir".to_string());
+                args.push(work_dir.to_str().unwrap().to_string());
+            }
+            match group.subworkers {
+                Some(SubworkersSpec::File(ref path)) => {
+                    load_subworker_kinds(path)?;
+                    args.push("--subworkers".to_string());
+                    args.push(path.to_str().unwrap().to_string());
+                }
+                Some(SubworkersSpec::Inline(ref kinds)) => {
+                    let path = config.log_dir.join(format!("subworkers-{}.json", i));
+                    ::serde_json::to_writer(File::create(&path)?, kinds)
+                        .map_err(|e| format!("Cannot write {:?}: {}", path, e))?;
+                    load_subworker_kinds(&path)?;
+                    args.push("--subworkers".to_string());
+                    args.push(path.to_str().unwrap().to_string());
+                }
+                None => {}
+            }
+            args.extend(group.args.iter().cloned());
+            config.worker_groups.push(WorkerGroup {
+                hosts: group.hosts.clone(),
+                count: group.count,
+                args,
+            });
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cluster_spec() {
+        let mut spec: ClusterSpec = ::toml::from_str(
+            r#"
+            log_dir = "logs"
+            remote_launcher = "srun"
+
+            [server]
+            listen = "7300"
+            global_resources = { "license:gurobi" = 4 }
+
+            [[workers]]
+            count = 2
+            cpus = 4
+            work_dir = "/scratch/rain"
+
+            [[workers]]
+            hosts = ["node1", "node2"]
+            memory = "16GiB"
+            subworkers = "kinds.json"
+            "#,
+        ).unwrap();
+        spec.resolve_paths(Path::new("/cluster"));
+        assert_eq!(spec.log_dir, Some(PathBuf::from("/cluster/logs")));
+        assert_eq!(spec.server.listen, Some("7300".to_string()));
+        assert_eq!(spec.workers[0].count, 2);
+        assert_eq!(spec.workers[0].work_dir, Some(PathBuf::from("/scratch/rain")));
+        assert_eq!(spec.workers[1].count, 1);
+        assert_eq!(spec.workers[1].memory, Some(16 << 30));
+        match spec.workers[1].subworkers {
+            Some(SubworkersSpec::File(ref path)) => {
+                assert_eq!(path, Path::new("/cluster/kinds.json"))
+            }
+            _ => panic!("Subworkers file expected"),
+        }
+        assert!(::toml::from_str::<ClusterSpec>("[[workers]]\ncores = 4\n").is_err());
+        assert!(::toml::from_str::<ClusterSpec>("[[workers]]\nmemory = \"4 GB?\"\n").is_err());
+    }
+}
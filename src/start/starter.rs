@@ -1,16 +1,42 @@
 use std::process::Command;
 use std::path::{Path, PathBuf};
 use std::net::SocketAddr;
+use std::sync::atomic::{AtomicBool, Ordering, ATOMIC_BOOL_INIT};
+use std::time::Duration;
 use start::common::Readiness;
+use start::launcher::RemoteLauncher;
 use start::process::Process;
 use start::ssh::RemoteProcess;
+use librain::admin::AdminClient;
 use librain::errors::Result;
 
+use nix::libc;
 use nix::unistd::getpid;
 use std::io::BufReader;
 use std::io::BufRead;
 use std::fs::File;
 
+/// How long processes may take to exit when the cluster is stopped, then they are killed
+const STOP_TIMEOUT: u64 = 10;
+
+static INTERRUPTED: AtomicBool = ATOMIC_BOOL_INIT;
+
+extern "C" fn on_interrupt(_: libc::c_int) {
+    INTERRUPTED.store(true, Ordering::SeqCst);
+}
+
+/// Catch SIGINT (Ctrl-C) and SIGTERM, so the starter can stop started processes in order
+pub fn install_interrupt_handlers() {
+    unsafe {
+        libc::signal(libc::SIGINT, on_interrupt as libc::sighandler_t);
+        libc::signal(libc::SIGTERM, on_interrupt as libc::sighandler_t);
+    }
+}
+
+pub fn is_interrupted() -> bool {
+    INTERRUPTED.load(Ordering::SeqCst)
+}
+
 pub struct StarterConfig {
     /// Number of local worker that will be spawned
     pub local_workers: Vec<Option<u32>>,
@@ -33,6 +59,33 @@ pub struct StarterConfig {
 
     /// Rain will be executed with this prefix, used for debugging and profiling
     pub run_prefix: Vec<String>,
+
+    /// Cluster-wide resources of server ("name=count")
+    pub global_resources: Vec<String>,
+
+    /// Remote workers use compressed connection to server
+    pub compress_control: bool,
+
+    /// How workers are started on remote hosts
+    pub remote_launcher: RemoteLauncher,
+
+    /// Further workers (e.g. from a cluster spec), started with the workers above
+    pub worker_groups: Vec<WorkerGroup>,
+
+    /// Additional arguments of the server
+    pub server_args: Vec<String>,
+}
+
+/// Workers started with the same arguments
+pub struct WorkerGroup {
+    /// Hosts of remote workers; workers are local when it is empty
+    pub hosts: Vec<String>,
+
+    /// Number of workers (on each host)
+    pub count: u32,
+
+    /// Additional arguments of the workers
+    pub args: Vec<String>,
 }
 
 impl StarterConfig {
@@ -54,6 +107,11 @@ impl StarterConfig {
             remote_init,
             reserve_cpu_on_server,
             run_prefix,
+            global_resources: Vec::new(),
+            compress_control: false,
+            remote_launcher: RemoteLauncher::Ssh,
+            worker_groups: Vec::new(),
+            server_args: Vec::new(),
         }
     }
 
@@ -76,10 +134,14 @@ pub struct Starter {
     /// Configuration of starter
     config: StarterConfig,
 
-    /// Spawned and running processes
+    /// Spawned server
+    server: Option<Process>,
+
+    /// Spawned and running local workers (and remote workers started by
+    /// a cluster launcher, see `RemoteLauncher`)
     processes: Vec<Process>,
 
-    /// Spawned and running processes
+    /// Spawned and running remote workers
     remote_processes: Vec<RemoteProcess>,
 
     /// PID of server
@@ -109,6 +171,7 @@ impl Starter {
     pub fn new(config: StarterConfig) -> Self {
         Self {
             config,
+            server: None,
             processes: Vec::new(),
             remote_processes: Vec::new(),
             server_pid: 0,
@@ -116,7 +179,7 @@ impl Starter {
     }
 
     pub fn has_processes(&self) -> bool {
-        !self.processes.is_empty()
+        self.server.is_some() || !self.processes.is_empty() || !self.remote_processes.is_empty()
     }
 
     /// Main method of starter that launch everything
@@ -131,23 +194,44 @@ impl Starter {
             Vec::new()
         };
 
-        if self.config.local_workers.is_empty() && worker_hosts.is_empty() {
+        let mut groups = ::std::mem::replace(&mut self.config.worker_groups, Vec::new());
+        groups.extend(self.config.local_workers.iter().map(|cpus| WorkerGroup {
+            hosts: Vec::new(),
+            count: 1,
+            args: cpus.map(|cpus| vec!["--cpus".to_string(), cpus.to_string()])
+                .unwrap_or_else(Vec::new),
+        }));
+        if !worker_hosts.is_empty() {
+            groups.push(WorkerGroup {
+                hosts: worker_hosts,
+                count: 1,
+                args: Vec::new(),
+            });
+        }
+
+        if groups.iter().all(|group| group.count == 0) {
             bail!("No workers are specified.");
         }
 
         self.start_server()?;
         self.busy_wait_for_ready()?;
 
-        if !self.config.local_workers.is_empty() {
-            self.start_local_workers()?;
-        }
-        if !worker_hosts.is_empty() {
-            self.start_remote_workers(&worker_hosts)?;
+        for group in &groups {
+            if group.hosts.is_empty() {
+                self.start_local_workers(group)?;
+            } else {
+                self.start_remote_workers(group)?;
+            }
         }
         self.busy_wait_for_ready()?;
         Ok(())
     }
 
+    /// Index of the next started worker, it names its logs
+    fn next_worker_index(&self) -> usize {
+        self.processes.len() + self.remote_processes.len()
+    }
+
     /// Command for starting rain
     pub fn local_rain_command(&self) -> (String, Vec<String>) {
         let rain_program = ::std::env::args().nth(0).unwrap();
@@ -161,18 +245,17 @@ impl Starter {
     }
 
     fn spawn_process(
-        &mut self,
+        &self,
         name: &str,
         ready_file: &Path,
         command: &mut Command,
-    ) -> Result<&Process> {
-        self.processes.push(Process::spawn(
+    ) -> Result<Process> {
+        Process::spawn(
             &self.config.log_dir,
             name,
             Readiness::WaitingForReadyFile(ready_file.to_path_buf()),
             command,
-        )?);
-        Ok(self.processes.last().unwrap())
+        )
     }
 
     /// Create a temporory filename
@@ -189,49 +272,68 @@ impl Starter {
 
         info!("Starting local server ({})", server_address);
         let log_dir = self.config.log_dir.join("server");
-        self.server_pid = {
-            let process = self.spawn_process(
-                "server",
-                &ready_file,
-                Command::new(program)
-                    .args(program_args)
-                    .arg("server")
-                    .arg("--logdir")
-                    .arg(&log_dir)
-                    .arg("--listen")
-                    .arg(&server_address)
-                    .arg("--http-listen")
-                    .arg(&server_http_address)
-                    .arg("--ready-file")
-                    .arg(&ready_file),
-            )?;
-            let server_pid = process.id();
-            let hostname = ::librain::common::sys::get_hostname();
-            info!("Dashboard: http://{}:{}/", hostname, http_port);
-            info!("Server pid = {}", server_pid);
-            server_pid
-        };
+        let mut command = Command::new(program);
+        command
+            .args(program_args)
+            .arg("server")
+            .arg("--logdir")
+            .arg(&log_dir)
+            .arg("--listen")
+            .arg(&server_address)
+            .arg("--http-listen")
+            .arg(&server_http_address)
+            .arg("--ready-file")
+            .arg(&ready_file);
+        for resource in &self.config.global_resources {
+            command.arg("--global-resource").arg(resource);
+        }
+        command.args(&self.config.server_args);
+        let process = self.spawn_process("server", &ready_file, &mut command)?;
+        self.server_pid = process.id();
+        self.server = Some(process);
+        let hostname = ::librain::common::sys::get_hostname();
+        info!("Dashboard: http://{}:{}/", hostname, http_port);
+        info!("Server pid = {}", self.server_pid);
         Ok(())
     }
 
-    fn start_remote_workers(&mut self, worker_hosts: &[String]) -> Result<()> {
-        info!("Starting {} remote worker(s)", worker_hosts.len());
+    fn start_remote_workers(&mut self, group: &WorkerGroup) -> Result<()> {
+        info!(
+            "Starting {} remote worker(s) ({:?})",
+            group.hosts.len() * group.count as usize,
+            self.config.remote_launcher
+        );
         let (program, program_args) = self.local_rain_command();
         let dir = ::std::env::current_dir().unwrap(); // TODO: Do it configurable
         let server_address = self.server_address(false);
+        let mut worker_args = if self.config.compress_control {
+            " --compress-control".to_string()
+        } else {
+            String::new()
+        };
+        for arg in &group.args {
+            worker_args += &format!(" {:?}", arg);
+        }
 
-        for (i, host) in worker_hosts.iter().enumerate() {
-            info!(
-                "Connecting to {} (remote log dir: {:?})",
-                host, self.config.log_dir
-            );
-            let ready_file = self.create_tmp_filename(&format!("worker-{}-ready", i));
+        let hosts = group
+            .hosts
+            .iter()
+            .flat_map(|host| ::std::iter::repeat(host).take(group.count as usize));
+        for host in hosts {
+            let i = self.next_worker_index();
             let name = format!("worker-{}", i);
-            let mut process = RemoteProcess::new(
-                name,
-                host,
-                Readiness::WaitingForReadyFile(ready_file.to_path_buf()),
-            );
+            let ready_file = if self.config.remote_launcher == RemoteLauncher::Ssh {
+                info!(
+                    "Connecting to {} (remote log dir: {:?})",
+                    host, self.config.log_dir
+                );
+                self.create_tmp_filename(&format!("worker-{}-ready", i))
+            } else {
+                // The launcher runs locally, so the ready file is checked here;
+                // the log directory has to be on a filesystem shared with the host
+                info!("Launching worker on {}", host);
+                self.config.log_dir.join(format!("{}.ready", name))
+            };
             let command = if self.config.reserve_cpu_on_server {
                 format!(
                     "if (ps --pid {server_pid} | grep rain); then \n\
@@ -240,26 +342,50 @@ impl Starter {
                     CPUS=detect \n\
                     fi \n\
                     {remote_init}
-                    {program} {program_args} worker {server_address} --cpus=$CPUS --ready-file {ready_file:?}",
+                    {program} {program_args} worker {server_address} --cpus=$CPUS --ready-file {ready_file:?}{worker_args}",
                     program = program,
                     remote_init = self.config.remote_init,
                     program_args = program_args.join(" "),
                     server_address = server_address,
                     ready_file = ready_file,
                     server_pid = self.server_pid,
+                    worker_args = worker_args,
                 )
             } else {
                 format!(
-                    "{remote_init}\n{program} {program_args} worker {server_address} --ready-file {ready_file:?}",
+                    "{remote_init}\n{program} {program_args} worker {server_address} --ready-file {ready_file:?}{worker_args}",
                     program = program,
                     remote_init = self.config.remote_init,
                     program_args = program_args.join(" "),
                     server_address = server_address,
                     ready_file = ready_file,
+                    worker_args = worker_args,
                 )
             };
-            process.start(&command, &dir, &self.config.log_dir)?;
-            self.remote_processes.push(process);
+            if self.config.remote_launcher == RemoteLauncher::Ssh {
+                let mut process = RemoteProcess::new(
+                    name,
+                    host,
+                    Readiness::WaitingForReadyFile(ready_file.to_path_buf()),
+                );
+                process.start(&command, &dir, &self.config.log_dir)?;
+                self.remote_processes.push(process);
+            } else {
+                if ready_file.exists() {
+                    ::std::fs::remove_file(&ready_file)?;
+                }
+                let script = format!("cd {:?} || exit 1\n{}", dir, command);
+                let mut launcher = self.config.remote_launcher.command(host, &script);
+                let process = self.spawn_process(&name, &ready_file, &mut launcher)
+                    .map_err(|e| {
+                        format!(
+                            "Cannot run launcher {:?}: {}",
+                            self.config.remote_launcher,
+                            e.description()
+                        )
+                    })?;
+                self.processes.push(process);
+            }
         }
         Ok(())
     }
@@ -273,20 +399,12 @@ impl Starter {
         format!("{}:{}", hostname, self.config.server_listen_address.port())
     }
 
-    fn start_local_workers(&mut self) -> Result<()> {
-        info!(
-            "Starting {} local worker(s)",
-            self.config.local_workers.len()
-        );
+    fn start_local_workers(&mut self, group: &WorkerGroup) -> Result<()> {
+        info!("Starting {} local worker(s)", group.count);
         let server_address = self.server_address(true);
         let (program, program_args) = self.local_rain_command();
-        let workers: Vec<_> = self.config
-            .local_workers
-            .iter()
-            .cloned()
-            .enumerate()
-            .collect();
-        for (i, resource) in workers {
+        for _ in 0..group.count {
+            let i = self.next_worker_index();
             let ready_file = self.create_tmp_filename(&format!("worker-{}-ready", i));
             let mut cmd = Command::new(&program);
             cmd.args(&program_args)
@@ -295,12 +413,10 @@ impl Starter {
                 .arg("--logdir")
                 .arg(self.config.log_dir.join(format!("worker-{}", i)))
                 .arg("--ready-file")
-                .arg(&ready_file);
-            if let Some(cpus) = resource {
-                cmd.arg("--cpus");
-                cmd.arg(cpus.to_string());
-            }
-            self.spawn_process(&format!("worker-{}", i), &ready_file, &mut cmd)?;
+                .arg(&ready_file)
+                .args(&group.args);
+            let process = self.spawn_process(&format!("worker-{}", i), &ready_file, &mut cmd)?;
+            self.processes.push(process);
         }
         Ok(())
     }
@@ -309,6 +425,9 @@ impl Starter {
     pub fn busy_wait_for_ready(&mut self) -> Result<()> {
         let mut timeout_ms = 50; // Timeout, it it increased every cycle upto 1.5 seconds
         while 0 != self.check_all_ready()? {
+            if is_interrupted() {
+                bail!("Interrupted");
+            }
             ::std::thread::sleep(::std::time::Duration::from_millis(timeout_ms));
             if timeout_ms < 1500 {
                 timeout_ms += 50;
@@ -324,7 +443,7 @@ impl Starter {
         // Here we intentionally goes through all processes
         // even we found first non-ready one, since we also
         // want to check that other processes are not terminated
-        for process in &mut self.processes {
+        for process in self.server.iter_mut().chain(self.processes.iter_mut()) {
             if !process.check_ready()? {
                 not_ready += 1;
             }
@@ -338,20 +457,101 @@ impl Starter {
         Ok(not_ready)
     }
 
+    /// Block until the starter is interrupted (Ctrl-C), fails when a local process exits
+    pub fn wait(&mut self) -> Result<()> {
+        while !is_interrupted() {
+            for process in self.server.iter_mut().chain(self.processes.iter_mut()) {
+                process.check_run()?;
+            }
+            ::std::thread::sleep(Duration::from_millis(500));
+        }
+        Ok(())
+    }
+
+    /// Stop the started processes in order: stop scheduling and cancel sessions (admin API),
+    /// stop workers, then the server, and finally remove remaining ready files.
     /// This is cleanup method, so we want to silent errors
-    pub fn kill_all(&mut self) {
-        for mut process in ::std::mem::replace(&mut self.processes, Vec::new()) {
-            match process.kill() {
-                Ok(()) => {}
-                Err(e) => debug!("Kill failed: {}", e.description()),
-            };
+    pub fn shutdown(&mut self) {
+        let timeout = Duration::from_secs(STOP_TIMEOUT);
+        let mut admin = self.connect_admin();
+        if let Some(ref mut admin) = admin {
+            match admin.stop_scheduling() {
+                Ok(sessions) => info!("Scheduling stopped, {} session(s) cancelled", sessions),
+                Err(e) => warn!("Cannot stop scheduling: {}", e.description()),
+            }
+            match admin.stop_workers() {
+                Ok(workers) => info!("Stopping {} worker(s)", workers),
+                Err(e) => warn!("Cannot stop workers: {}", e.description()),
+            }
         }
 
+        // Without the server, workers are terminated directly
+        if admin.is_none() {
+            for process in &mut self.processes {
+                process.terminate();
+            }
+        }
+        for mut process in ::std::mem::replace(&mut self.processes, Vec::new()) {
+            stop_process(&mut process, timeout);
+        }
         for mut process in ::std::mem::replace(&mut self.remote_processes, Vec::new()) {
             match process.kill() {
                 Ok(()) => {}
                 Err(e) => debug!("Kill failed: {}", e.description()),
             };
         }
+
+        if let Some(mut process) = self.server.take() {
+            match admin {
+                Some(mut admin) => if let Err(e) = admin.terminate_server() {
+                    // The server may close the connection before the reply
+                    debug!("Terminating server: {}", e.description());
+                },
+                None => process.terminate(),
+            }
+            stop_process(&mut process, timeout);
+        }
+        info!("All started processes stopped");
+    }
+
+    /// Admin client of the started server, None when the server is not ready
+    fn connect_admin(&self) -> Option<AdminClient> {
+        if self.server.is_none() || self.server_pid == 0 {
+            return None;
+        }
+        let address: SocketAddr = match self.server_address(true).parse() {
+            Ok(address) => address,
+            Err(_) => return None,
+        };
+        let token = ::std::env::var("RAIN_TOKEN").unwrap_or_default();
+        match AdminClient::connect(address, &token) {
+            Ok(admin) => Some(admin),
+            Err(e) => {
+                warn!("Cannot connect to server: {}", e.description());
+                None
+            }
+        }
+    }
+}
+
+/// Wait for the process to exit, then terminate it (SIGTERM) and kill it
+/// when it does not exit in time; its ready file is removed
+fn stop_process(process: &mut Process, timeout: Duration) {
+    let stopped = process.wait_timeout(timeout).and_then(|stopped| {
+        if stopped {
+            return Ok(true);
+        }
+        process.terminate();
+        process.wait_timeout(Duration::from_secs(1))
+    });
+    match stopped {
+        Ok(true) => process.remove_ready_file(),
+        Ok(false) => {
+            warn!("Process '{}' did not stop in time, killing it", process.name());
+            if let Err(e) = process.kill() {
+                debug!("Kill failed: {}", e.description());
+            }
+        }
+        Err(e) => debug!("Waiting for process failed: {}", e.description()),
     }
 }
@@ -17,22 +17,21 @@ pub struct DataBuilder {
 }
 
 impl DataBuilder {
-    pub fn new(workdir: &WorkDir, data_type: DataType, expected_size: Option<usize>) -> Self {
-        fn file_storage(workdir: &WorkDir) -> BuilderStorage {
-            let f = workdir.make_temp_file();
-            BuilderStorage::File((File::create(f.path()).unwrap(), f))
+    /// Builder keeping data in memory
+    pub fn new_in_memory(data_type: DataType, capacity: usize) -> Self {
+        DataBuilder {
+            data_type,
+            storage: BuilderStorage::Memory(Vec::with_capacity(capacity)),
         }
+    }
 
-        let storage = if let Some(size) = expected_size {
-            if size < 256 * 1024 {
-                BuilderStorage::Memory(Vec::with_capacity(size))
-            } else {
-                file_storage(workdir)
-            }
-        } else {
-            file_storage(workdir)
-        };
-        DataBuilder { data_type, storage }
+    /// Builder writing data into a temporary file in the working directory
+    pub fn new_in_file(workdir: &WorkDir, data_type: DataType) -> Self {
+        let f = workdir.make_temp_file();
+        DataBuilder {
+            data_type,
+            storage: BuilderStorage::File((File::create(f.path()).unwrap(), f)),
+        }
     }
 
     // TODO: Get rid of this method
@@ -77,7 +77,8 @@ fn isolate_file(source_path: &Path, prefix_path: &Path, metadata: &::std::fs::Me
     }
 }
 
-fn set_readonly_dir(path: &Path, value: bool) {
+/// Set (or unset) all items of the directory read-only
+pub fn set_readonly_dir(path: &Path, value: bool) {
     for entry in ::walkdir::WalkDir::new(path)
         .contents_first(true)
         .into_iter()
@@ -90,6 +91,35 @@ fn set_readonly_dir(path: &Path, value: bool) {
     }
 }
 
+/// Add execute permissions to the file wherever it has read permissions
+/// (a symlink is followed, i.e. the mapped data become executable)
+pub fn set_executable(path: &Path) -> Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+    let mut perms = ::std::fs::metadata(path)?.permissions();
+    let mode = perms.mode();
+    perms.set_mode(mode | (mode & 0o444) >> 2);
+    ::std::fs::set_permissions(path, perms)?;
+    Ok(())
+}
+
+/// Extract a tar archive, optionally compressed by gzip, into a new directory;
+/// permissions of archived files (e.g. executables) are kept
+pub fn unpack_archive<R: ::std::io::Read>(reader: R, path: &Path) -> Result<()> {
+    use std::io::BufRead;
+    let mut reader = ::std::io::BufReader::new(reader);
+    let is_gzip = {
+        let header = reader.fill_buf()?;
+        header.len() >= 2 && header[0] == 0x1f && header[1] == 0x8b
+    };
+    ::std::fs::create_dir(path)?;
+    let result = if is_gzip {
+        ::tar::Archive::new(::flate2::read::GzDecoder::new(reader)).unpack(path)
+    } else {
+        ::tar::Archive::new(reader).unpack(path)
+    };
+    result.map_err(|e| format!("Archive cannot be extracted: {}", e).into())
+}
+
 impl Data {
     /// Create Data from vector
     pub fn new(storage: Storage, data_type: DataType) -> Data {
@@ -125,7 +155,22 @@ impl Data {
                 DataType::Blob
             }
         } else {
-            ::std::fs::rename(source_path, &target_path)?;
+            if let Err(e) = ::std::fs::rename(&source_path, &target_path) {
+                if e.raw_os_error() != Some(::nix::libc::EXDEV) {
+                    return Err(e.into());
+                }
+                // Source is on another filesystem (e.g. tmpfs of a task), copy it
+                if metadata.is_dir() {
+                    let mut flags = ::fs_extra::dir::CopyOptions::new();
+                    flags.copy_inside = true;
+                    ::fs_extra::dir::copy(&source_path, &target_path, &flags)
+                        .map_err(|e| format!("Cannot copy {:?}: {}", source_path, e))?;
+                    ::std::fs::remove_dir_all(&source_path)?;
+                } else {
+                    ::std::fs::copy(&source_path, &target_path)?;
+                    ::std::fs::remove_file(&source_path)?;
+                }
+            }
             if metadata.is_dir() {
                 isolate_directory(&target_path, workdir_prefix).unwrap();
                 DataType::Directory
@@ -226,6 +271,18 @@ impl Data {
         }
     }
 
+    /// Return the content of data kept in memory or of a blob stored in a file,
+    /// `None` for a directory on the file system
+    pub fn read_bytes(&self) -> Result<Option<Vec<u8>>> {
+        match self.storage {
+            Storage::Memory(ref data) => Ok(Some(data.clone())),
+            Storage::Path(ref data) => match self.data_type {
+                DataType::Blob => Ok(Some(::std::fs::read(&data.path)?)),
+                DataType::Directory => Ok(None),
+            },
+        }
+    }
+
     #[inline]
     pub fn is_blob(&self) -> bool {
         self.data_type == DataType::Blob
@@ -241,15 +298,29 @@ impl Data {
         self.data_type
     }
 
-    pub fn to_subworker_capnp(&self, builder: &mut ::subworker_capnp::local_data::Builder) {
+    /// Serialize data for a subworker; with `in_memory`, blobs stored in files are sent
+    /// by their content (subworkers that cannot read files of the worker)
+    pub fn to_subworker_capnp(
+        &self,
+        builder: &mut ::subworker_capnp::local_data::Builder,
+        in_memory: bool,
+    ) -> Result<()> {
         match self.storage {
             Storage::Memory(ref data) => builder.borrow().get_storage().set_memory(&data),
+            Storage::Path(ref data) if in_memory => match self.data_type {
+                DataType::Blob => builder
+                    .borrow()
+                    .get_storage()
+                    .set_memory(&::std::fs::read(&data.path)?),
+                DataType::Directory => bail!("Directory cannot be sent in memory"),
+            },
             Storage::Path(ref data) => builder
                 .borrow()
                 .get_storage()
                 .set_path(data.path.to_str().unwrap()),
         };
         builder.borrow().set_data_type(self.data_type.to_capnp());
+        Ok(())
     }
 }
 
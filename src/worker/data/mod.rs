@@ -1,7 +1,11 @@
 pub mod data;
 pub mod pack;
 pub mod builder;
+pub mod storage;
+pub mod validate;
 
-pub use self::data::{Data, Storage};
+pub use self::data::{set_executable, set_readonly_dir, unpack_archive, Data, Storage};
 pub use self::builder::DataBuilder;
-pub use self::pack::{new_pack_stream, PackStream};
+pub use self::validate::ValidationSpec;
+pub use self::storage::{new_data_storage, DataStorage, DiskStorage, MemoryStorage};
+pub use self::pack::{new_pack_stream, pack_stream_checksum, range_pack_stream, PackStream};
@@ -44,6 +44,60 @@ pub fn new_pack_stream(state: &State, data: Arc<Data>) -> Result<Box<PackStream>
     })
 }
 
+/// Part of the stream: `offset` bytes are skipped and at most `length` bytes
+/// are returned (0 = until the end of the stream)
+pub fn range_pack_stream(
+    mut stream: Box<PackStream>,
+    offset: usize,
+    length: usize,
+) -> Box<PackStream> {
+    let mut skipped = 0;
+    while skipped < offset {
+        let (slice, eof) = stream.read(offset - skipped);
+        if eof {
+            return Box::new(EmptyPackStream {
+                dummy: Default::default(),
+            });
+        }
+        skipped += slice.len();
+    }
+    if length == 0 {
+        stream
+    } else {
+        Box::new(RangePackStream {
+            stream,
+            remaining: length,
+        })
+    }
+}
+
+/// CRC32 of the whole stream, formatted as "crc32:<8 hex digits>"
+pub fn pack_stream_checksum(stream: &mut PackStream) -> String {
+    let mut crc = ::flate2::Crc::new();
+    loop {
+        let (slice, eof) = stream.read(1 << 20 /* 1 MB */);
+        crc.update(slice);
+        if eof {
+            break;
+        }
+    }
+    format!("crc32:{:08x}", crc.sum())
+}
+
+struct RangePackStream {
+    stream: Box<PackStream>,
+    remaining: usize,
+}
+
+impl PackStream for RangePackStream {
+    fn read(&mut self, read_size: usize) -> (&[u8], bool) {
+        let (slice, eof) = self.stream
+            .read(::std::cmp::min(read_size, self.remaining));
+        self.remaining -= slice.len();
+        (slice, eof || self.remaining == 0)
+    }
+}
+
 struct EmptyPackStream {
     dummy: [u8; 0],
 }
@@ -0,0 +1,156 @@
+use std::fs::File;
+use std::io::{Cursor, Read};
+use std::path::Path;
+
+use common::DataType;
+use errors::Result;
+use worker::fs::workdir::WorkDir;
+use super::builder::DataBuilder;
+use super::data::{Data, Storage};
+
+/// Data up to this size are kept in memory by `DiskStorage::default()`
+pub const DEFAULT_MEMORY_THRESHOLD: usize = 256 * 1024;
+
+/// Placement of data of objects on a worker (selected by `--data-storage`).
+///
+/// Storage decides where new data are created; access to existing data
+/// goes through the storage too, so an implementation may keep data elsewhere
+/// (e.g. in an external store) and materialize them only when they are needed.
+/// Data are immutable once created; they are removed when the last reference is dropped.
+pub trait DataStorage {
+    /// Name of the storage as used by `--data-storage`
+    fn name(&self) -> &'static str;
+
+    /// Create a builder for data that are written incrementally (e.g. fetched
+    /// from another worker); `expected_size` is `None` when the size is not known
+    fn builder(
+        &self,
+        work_dir: &WorkDir,
+        data_type: DataType,
+        expected_size: Option<usize>,
+    ) -> DataBuilder;
+
+    /// Store complete data (a blob or a tar archive of a directory)
+    fn put(&self, work_dir: &WorkDir, data_type: DataType, bytes: Vec<u8>) -> Result<Data>;
+
+    /// Take over data created on the file system (outputs of tasks and subworkers)
+    fn adopt(&self, data: Data) -> Result<Data> {
+        Ok(data)
+    }
+
+    /// Content of the data, `None` for a directory on the file system
+    fn get(&self, data: &Data) -> Result<Option<Vec<u8>>> {
+        data.read_bytes()
+    }
+
+    /// Reader of the content of a blob
+    fn stream<'a>(&self, data: &'a Data) -> Result<Box<Read + 'a>> {
+        if !data.is_blob() {
+            bail!("Only blobs can be streamed");
+        }
+        Ok(match *data.storage() {
+            Storage::Memory(ref bytes) => Box::new(Cursor::new(&bytes[..])),
+            Storage::Path(ref data) => Box::new(File::open(&data.path)?),
+        })
+    }
+
+    /// Make the data available on the path (caller is responsible for removal of the path)
+    fn map(&self, data: &Data, path: &Path) -> Result<()> {
+        data.link_to_path(path)
+    }
+
+    /// Release the data
+    fn delete(&self, data: Data) {
+        drop(data)
+    }
+}
+
+/// Keeps all data in memory; data created by tasks on the file system are
+/// loaded into memory (except directories)
+#[derive(Default)]
+pub struct MemoryStorage;
+
+impl DataStorage for MemoryStorage {
+    fn name(&self) -> &'static str {
+        "memory"
+    }
+
+    fn builder(
+        &self,
+        _work_dir: &WorkDir,
+        data_type: DataType,
+        expected_size: Option<usize>,
+    ) -> DataBuilder {
+        DataBuilder::new_in_memory(data_type, expected_size.unwrap_or(0))
+    }
+
+    fn put(&self, _work_dir: &WorkDir, data_type: DataType, bytes: Vec<u8>) -> Result<Data> {
+        Ok(Data::new(Storage::Memory(bytes), data_type))
+    }
+
+    fn adopt(&self, data: Data) -> Result<Data> {
+        if let Storage::Memory(_) = *data.storage() {
+            return Ok(data);
+        }
+        match data.read_bytes()? {
+            Some(bytes) => Ok(Data::new(Storage::Memory(bytes), data.data_type())),
+            None => Ok(data),
+        }
+    }
+}
+
+/// Keeps data larger than `memory_threshold` (or of unknown size) in files
+/// in the working directory, smaller data in memory
+pub struct DiskStorage {
+    memory_threshold: usize,
+}
+
+impl DiskStorage {
+    pub fn new(memory_threshold: usize) -> Self {
+        DiskStorage { memory_threshold }
+    }
+}
+
+impl Default for DiskStorage {
+    fn default() -> Self {
+        DiskStorage::new(DEFAULT_MEMORY_THRESHOLD)
+    }
+}
+
+impl DataStorage for DiskStorage {
+    fn name(&self) -> &'static str {
+        "disk"
+    }
+
+    fn builder(
+        &self,
+        work_dir: &WorkDir,
+        data_type: DataType,
+        expected_size: Option<usize>,
+    ) -> DataBuilder {
+        match expected_size {
+            Some(size) if size < self.memory_threshold => {
+                DataBuilder::new_in_memory(data_type, size)
+            }
+            _ => DataBuilder::new_in_file(work_dir, data_type),
+        }
+    }
+
+    fn put(&self, work_dir: &WorkDir, data_type: DataType, bytes: Vec<u8>) -> Result<Data> {
+        if bytes.len() < self.memory_threshold {
+            return Ok(Data::new(Storage::Memory(bytes), data_type));
+        }
+        let mut builder = DataBuilder::new_in_file(work_dir, data_type);
+        builder.write(&bytes);
+        Ok(builder.build(work_dir))
+    }
+}
+
+/// Create a storage by its name ("memory" or "disk")
+pub fn new_data_storage(name: &str) -> Result<Box<DataStorage>> {
+    match name {
+        "memory" => Ok(Box::new(MemoryStorage)),
+        "disk" => Ok(Box::new(DiskStorage::default())),
+        _ => bail!("Unknown data storage '{}' (expected 'memory' or 'disk')", name),
+    }
+}
@@ -0,0 +1,107 @@
+use std::io::Read;
+
+use regex::bytes::Regex;
+
+use errors::Result;
+use super::data::Data;
+use super::storage::DataStorage;
+
+/// Number of bytes at the beginning of data matched by `ValidationSpec::regex`
+pub const VALIDATE_PREFIX_SIZE: u64 = 64 * 1024;
+
+/// Checks of an output declared in its attribute "validate"; the worker applies them
+/// before the output is marked as finished and fails the task when a check does not pass
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ValidationSpec {
+    /// Data must not be empty (for a directory: it has to contain a non-empty file)
+    pub non_empty: bool,
+    pub min_size: Option<usize>,
+    pub max_size: Option<usize>,
+    /// Regular expression that has to match within the first `VALIDATE_PREFIX_SIZE` bytes
+    /// (use "^" to anchor it to the beginning); blobs only
+    pub regex: Option<String>,
+    /// Data have to be a valid JSON document; blobs only
+    pub json: bool,
+}
+
+impl ValidationSpec {
+    pub fn check(&self, data: &Data, storage: &DataStorage) -> Result<()> {
+        let size = data.size();
+        if self.non_empty && size == 0 {
+            bail!("data are empty");
+        }
+        if let Some(min_size) = self.min_size {
+            if size < min_size {
+                bail!("size {} is smaller than {}", size, min_size);
+            }
+        }
+        if let Some(max_size) = self.max_size {
+            if size > max_size {
+                bail!("size {} is larger than {}", size, max_size);
+            }
+        }
+        if (self.regex.is_some() || self.json) && !data.is_blob() {
+            bail!("regex and json checks can be applied only to blobs");
+        }
+        if let Some(ref pattern) = self.regex {
+            let regex =
+                Regex::new(pattern).map_err(|e| format!("invalid regex '{}': {}", pattern, e))?;
+            let mut prefix = Vec::new();
+            storage
+                .stream(data)?
+                .take(VALIDATE_PREFIX_SIZE)
+                .read_to_end(&mut prefix)?;
+            if !regex.is_match(&prefix) {
+                bail!("beginning of data does not match regex '{}'", pattern);
+            }
+        }
+        if self.json {
+            ::serde_json::from_reader::<_, ::serde::de::IgnoredAny>(storage.stream(data)?)
+                .map_err(|e| format!("data are not valid JSON: {}", e))?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use common::DataType;
+    use worker::data::{MemoryStorage, Storage};
+
+    fn blob(bytes: &[u8]) -> Data {
+        Data::new(Storage::Memory(bytes.to_vec()), DataType::Blob)
+    }
+
+    fn spec(json: &str) -> ValidationSpec {
+        ::serde_json::from_str(json).unwrap()
+    }
+
+    #[test]
+    fn test_validate_size() {
+        let storage = MemoryStorage;
+        assert!(spec(r#"{"non_empty": true}"#).check(&blob(b""), &storage).is_err());
+        assert!(spec(r#"{"non_empty": true}"#).check(&blob(b"x"), &storage).is_ok());
+        let bounds = spec(r#"{"min_size": 2, "max_size": 3}"#);
+        assert!(bounds.check(&blob(b"x"), &storage).is_err());
+        assert!(bounds.check(&blob(b"xyz"), &storage).is_ok());
+        assert!(bounds.check(&blob(b"wxyz"), &storage).is_err());
+    }
+
+    #[test]
+    fn test_validate_content() {
+        let storage = MemoryStorage;
+        let header = spec(r#"{"regex": "^id,name\n"}"#);
+        assert!(header.check(&blob(b"id,name\n1,a\n"), &storage).is_ok());
+        assert!(header.check(&blob(b"Traceback"), &storage).is_err());
+        let json = spec(r#"{"json": true}"#);
+        assert!(json.check(&blob(br#"{"a": [1, 2]}"#), &storage).is_ok());
+        assert!(json.check(&blob(br#"{"a": [1, 2"#), &storage).is_err());
+    }
+
+    #[test]
+    fn test_validate_unknown_check() {
+        assert!(::serde_json::from_str::<ValidationSpec>(r#"{"nonempty": true}"#).is_err());
+    }
+}
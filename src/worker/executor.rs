@@ -0,0 +1,163 @@
+use std::collections::VecDeque;
+
+use futures::Future;
+use tokio_core::reactor::Handle;
+
+use common::wrapped::WrappedRcRefCell;
+
+type QueuedFuture = Box<Future<Item = (), Error = ()>>;
+
+/// Executor that runs at most `limit` futures at once on the reactor.
+/// Futures spawned over the limit are queued and started (in FIFO order)
+/// when one of the running futures is finished.
+///
+/// Futures that need to defer their side effects until they are actually
+/// started should be created via `futures::future::lazy`.
+pub struct LimitedExecutor {
+    handle: Handle,
+    limit: usize,
+    running: usize,
+    queue: VecDeque<QueuedFuture>,
+}
+
+pub type LimitedExecutorRef = WrappedRcRefCell<LimitedExecutor>;
+
+impl LimitedExecutorRef {
+    /// Create executor, limit 0 (e.g. derived from 0 cpus) is treated as 1
+    pub fn new(handle: Handle, limit: usize) -> Self {
+        Self::wrap(LimitedExecutor {
+            handle,
+            limit: ::std::cmp::max(limit, 1),
+            running: 0,
+            queue: VecDeque::new(),
+        })
+    }
+
+    /// Spawn future if limit is not reached, otherwise put it into the queue
+    pub fn spawn_limited<F>(&self, future: F)
+    where
+        F: Future<Item = (), Error = ()> + 'static,
+    {
+        let future: QueuedFuture = Box::new(future);
+        {
+            let mut inner = self.get_mut();
+            if inner.running >= inner.limit {
+                inner.queue.push_back(future);
+                return;
+            }
+            inner.running += 1;
+        }
+        self.start(future);
+    }
+
+    fn start(&self, future: QueuedFuture) {
+        let executor = self.clone();
+        self.get().handle.spawn(future.then(move |r| {
+            executor.on_finished();
+            r
+        }));
+    }
+
+    fn on_finished(&self) {
+        let next = {
+            let mut inner = self.get_mut();
+            let next = inner.queue.pop_front();
+            if next.is_none() {
+                inner.running -= 1;
+            }
+            next
+        };
+        if let Some(future) = next {
+            self.start(future);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::{Cell, RefCell};
+    use std::rc::Rc;
+    use std::time::Duration;
+
+    use futures::future;
+    use futures::unsync::oneshot;
+    use tokio_core::reactor::Core;
+
+    /// Spawn `count` futures that wait for their senders, returns the senders,
+    /// the order in which the futures were started and the max. number of
+    /// futures running at once
+    fn spawn_waiting(
+        executor: &LimitedExecutorRef,
+        count: usize,
+    ) -> (Vec<oneshot::Sender<()>>, Rc<RefCell<Vec<usize>>>, Rc<Cell<usize>>) {
+        let started = Rc::new(RefCell::new(Vec::new()));
+        let running = Rc::new(Cell::new(0));
+        let max_running = Rc::new(Cell::new(0));
+        let mut senders = Vec::new();
+        for i in 0..count {
+            let (sender, receiver) = oneshot::channel::<()>();
+            senders.push(sender);
+            let started = started.clone();
+            let running = running.clone();
+            let max_running = max_running.clone();
+            executor.spawn_limited(future::lazy(move || {
+                started.borrow_mut().push(i);
+                running.set(running.get() + 1);
+                max_running.set(::std::cmp::max(max_running.get(), running.get()));
+                receiver.then(move |_| {
+                    running.set(running.get() - 1);
+                    Ok(())
+                })
+            }));
+        }
+        (senders, started, max_running)
+    }
+
+    fn run_until<F: Fn() -> bool>(core: &mut Core, condition: F) {
+        for _ in 0..100 {
+            if condition() {
+                return;
+            }
+            core.turn(Some(Duration::from_millis(10)));
+        }
+        panic!("Futures of executor were not finished");
+    }
+
+    #[test]
+    fn test_limited_concurrency() {
+        let mut core = Core::new().unwrap();
+        let executor = LimitedExecutorRef::new(core.handle(), 2);
+        let (mut senders, started, max_running) = spawn_waiting(&executor, 5);
+        run_until(&mut core, || started.borrow().len() == 2);
+        assert_eq!(*started.borrow(), vec![0, 1]);
+        assert_eq!(executor.get().queue.len(), 3);
+
+        senders.remove(1).send(()).unwrap();
+        run_until(&mut core, || started.borrow().len() == 3);
+        assert_eq!(*started.borrow(), vec![0, 1, 2]);
+
+        for sender in senders {
+            sender.send(()).unwrap();
+        }
+        run_until(&mut core, || executor.get().running == 0);
+        assert_eq!(*started.borrow(), vec![0, 1, 2, 3, 4]);
+        assert_eq!(max_running.get(), 2);
+        assert!(executor.get().queue.is_empty());
+    }
+
+    #[test]
+    fn test_zero_limit() {
+        let mut core = Core::new().unwrap();
+        let executor = LimitedExecutorRef::new(core.handle(), 0);
+        let (senders, started, max_running) = spawn_waiting(&executor, 3);
+        run_until(&mut core, || started.borrow().len() == 1);
+        assert_eq!(*started.borrow(), vec![0]);
+        for sender in senders {
+            sender.send(()).unwrap();
+        }
+        run_until(&mut core, || executor.get().running == 0);
+        assert_eq!(*started.borrow(), vec![0, 1, 2]);
+        assert_eq!(max_running.get(), 1);
+    }
+}
@@ -0,0 +1,174 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+use errors::Result;
+
+/// Size of the cache of downloads when not set by `--download-cache`
+pub const DEFAULT_DOWNLOAD_CACHE: u64 = 1024 * 1024 * 1024;
+
+/// Downloaded file kept for tasks `!download` of the same URL
+#[derive(Clone, Debug)]
+pub struct CachedDownload {
+    pub path: PathBuf,
+    /// ETag (or Last-Modified) of the response, the file is reused when the server
+    /// confirms it is still valid
+    pub validator: String,
+    pub size: u64,
+}
+
+struct Entry {
+    download: CachedDownload,
+    last_use: u64,
+}
+
+/// Files downloaded by tasks `!download`, keyed by URL and validated by the ETag of the
+/// response, so repeated tasks on the worker do not download the same resource again.
+/// Outputs are hard links of the cached files, so the cache takes no extra disk space
+/// while the objects live. The least recently used files are removed when the total size
+/// exceeds the capacity. The cache is not kept across restarts of the worker.
+pub struct DownloadCache {
+    dir: PathBuf,
+    capacity: u64,
+    size: u64,
+    entries: HashMap<String, Entry>,
+    /// Logical clock of uses of entries
+    clock: u64,
+    id_counter: u64,
+}
+
+impl DownloadCache {
+    pub fn new(dir: PathBuf, capacity: u64) -> Result<Self> {
+        if dir.exists() {
+            ::std::fs::remove_dir_all(&dir)?;
+        }
+        ::std::fs::create_dir_all(&dir)?;
+        Ok(DownloadCache {
+            dir,
+            capacity,
+            size: 0,
+            entries: HashMap::new(),
+            clock: 0,
+            id_counter: 0,
+        })
+    }
+
+    /// Change the capacity in bytes, 0 disables caching
+    pub fn set_capacity(&mut self, capacity: u64) {
+        self.capacity = capacity;
+        self.evict(0);
+    }
+
+    #[inline]
+    pub fn is_enabled(&self) -> bool {
+        self.capacity > 0
+    }
+
+    /// Total size of cached files
+    #[inline]
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+
+    /// Path of a new file in the directory of the cache
+    pub fn new_path(&mut self) -> PathBuf {
+        self.id_counter += 1;
+        self.dir.join(format!("{}", self.id_counter))
+    }
+
+    pub fn get(&mut self, url: &str) -> Option<CachedDownload> {
+        self.clock += 1;
+        let clock = self.clock;
+        self.entries.get_mut(url).map(|entry| {
+            entry.last_use = clock;
+            entry.download.clone()
+        })
+    }
+
+    /// Take over a downloaded file (it has to be in the directory of the cache, see
+    /// `new_path`); the previous file of the URL is removed. Returns false when
+    /// the file is larger than the capacity, it is not cached and the caller removes it.
+    pub fn insert(&mut self, url: &str, download: CachedDownload) -> bool {
+        self.remove(url);
+        if download.size > self.capacity {
+            return false;
+        }
+        self.evict(download.size);
+        self.clock += 1;
+        self.size += download.size;
+        self.entries.insert(
+            url.to_string(),
+            Entry {
+                download,
+                last_use: self.clock,
+            },
+        );
+        true
+    }
+
+    pub fn remove(&mut self, url: &str) {
+        if let Some(entry) = self.entries.remove(url) {
+            self.size -= entry.download.size;
+            remove_file(&entry.download.path);
+        }
+    }
+
+    /// Remove the least recently used files until `reserve` more bytes fit
+    fn evict(&mut self, reserve: u64) {
+        while self.size + reserve > self.capacity && !self.entries.is_empty() {
+            let url = self.entries
+                .iter()
+                .min_by_key(|&(_, e)| e.last_use)
+                .map(|(url, _)| url.clone())
+                .unwrap();
+            debug!("Download of {} evicted from cache", url);
+            self.remove(&url);
+        }
+    }
+}
+
+fn remove_file(path: &Path) {
+    if let Err(e) = ::std::fs::remove_file(path) {
+        warn!("Cached download {:?} cannot be removed: {}", path, e);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn add(cache: &mut DownloadCache, url: &str, size: u64) -> bool {
+        let path = cache.new_path();
+        ::std::fs::write(&path, vec![0u8; size as usize]).unwrap();
+        cache.insert(
+            url,
+            CachedDownload {
+                path,
+                validator: "\"v1\"".to_string(),
+                size,
+            },
+        )
+    }
+
+    #[test]
+    fn test_evict_least_recently_used() {
+        let dir = ::tempdir::TempDir::new("downloads").unwrap();
+        let mut cache = DownloadCache::new(dir.path().join("cache"), 100).unwrap();
+        assert!(add(&mut cache, "http://a", 40));
+        assert!(add(&mut cache, "http://b", 40));
+        assert!(cache.get("http://a").is_some());
+        assert!(add(&mut cache, "http://c", 40));
+        assert!(cache.get("http://b").is_none());
+        assert!(cache.get("http://a").is_some());
+        assert_eq!(cache.size(), 80);
+
+        let path = cache.get("http://c").unwrap().path;
+        assert!(add(&mut cache, "http://c", 10));
+        assert!(!path.exists());
+        assert_eq!(cache.size(), 50);
+
+        assert!(!add(&mut cache, "http://d", 200));
+        cache.set_capacity(0);
+        assert_eq!(cache.size(), 0);
+        assert!(cache.get("http://a").is_none());
+    }
+}
@@ -1,2 +1,5 @@
 pub mod workdir;
 pub mod tempfile;
+pub mod store;
+pub mod tmpfs;
+pub mod downloads;
@@ -0,0 +1,119 @@
+use std::collections::HashMap;
+use std::path::PathBuf;
+
+use common::id::DataObjectId;
+use common::{Attributes, DataType};
+use errors::Result;
+
+/// Record of a finished object whose data are stored in the working directory
+#[derive(Serialize, Deserialize)]
+pub struct StoredObject {
+    pub id: DataObjectId,
+    pub path: PathBuf,
+    pub size: usize,
+    pub data_type: DataType,
+    pub label: String,
+    pub attributes: Attributes,
+}
+
+/// Content of the index file
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Index {
+    server_instance: Option<String>,
+    objects: Vec<StoredObject>,
+}
+
+/// On-disk index of finished objects held by the worker.
+/// When the worker is restarted with the same working directory,
+/// the objects from the index are offered to the server, but only when
+/// the server is the same instance (ids of objects are not unique across servers).
+/// Only objects that are stored on the filesystem are recorded.
+pub struct ObjectStore {
+    index_path: PathBuf,
+    server_instance: Option<String>,
+    objects: HashMap<DataObjectId, StoredObject>,
+    dirty: bool,
+}
+
+impl ObjectStore {
+    pub fn new(index_path: PathBuf) -> Self {
+        ObjectStore {
+            index_path,
+            server_instance: None,
+            objects: HashMap::new(),
+            dirty: false,
+        }
+    }
+
+    /// Load the index if it exists (e.g. it was created by previous run of worker)
+    pub fn load(&mut self) -> Result<()> {
+        if !self.index_path.exists() {
+            return Ok(());
+        }
+        let file = ::std::fs::File::open(&self.index_path)?;
+        let index: Index = ::serde_json::from_reader(file)?;
+        info!("{} stored object(s) found in index", index.objects.len());
+        self.server_instance = index.server_instance;
+        self.objects = index.objects.into_iter().map(|o| (o.id, o)).collect();
+        Ok(())
+    }
+
+    /// Set the instance of the server that the worker is connected to.
+    /// When the objects were stored for another instance, they are removed
+    /// from the index and returned (their data should be deleted).
+    pub fn set_server_instance(&mut self, instance: &str) -> Vec<StoredObject> {
+        if self.server_instance.as_ref().map(|s| s.as_str()) == Some(instance) {
+            return Vec::new();
+        }
+        self.server_instance = Some(instance.to_string());
+        self.dirty = true;
+        self.objects.drain().map(|(_, o)| o).collect()
+    }
+
+    pub fn insert(&mut self, object: StoredObject) {
+        self.objects.insert(object.id, object);
+        self.dirty = true;
+    }
+
+    pub fn remove(&mut self, id: &DataObjectId) {
+        if self.objects.remove(id).is_some() {
+            self.dirty = true;
+        }
+    }
+
+    /// Ids and sizes of recorded objects
+    pub fn sizes(&self) -> Vec<(DataObjectId, usize)> {
+        self.objects.values().map(|o| (o.id, o.size)).collect()
+    }
+
+    /// Remove the record from the index and return it
+    pub fn take(&mut self, id: &DataObjectId) -> Option<StoredObject> {
+        let object = self.objects.remove(id);
+        if object.is_some() {
+            self.dirty = true;
+        }
+        object
+    }
+
+    /// Write the index on disk if it was changed
+    pub fn flush(&mut self) -> Result<()> {
+        if !self.dirty {
+            return Ok(());
+        }
+        // Write into temporary file and rename it, so index is never half-written
+        let tmp_path = self.index_path.with_extension("tmp");
+        {
+            let file = ::std::fs::File::create(&tmp_path)?;
+            let objects: Vec<&StoredObject> = self.objects.values().collect();
+            let index = json!({
+                "server_instance": self.server_instance,
+                "objects": objects,
+            });
+            ::serde_json::to_writer(file, &index)?;
+        }
+        ::std::fs::rename(&tmp_path, &self.index_path)?;
+        self.dirty = false;
+        Ok(())
+    }
+}
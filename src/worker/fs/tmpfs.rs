@@ -0,0 +1,93 @@
+use std::cell::Cell;
+use std::path::Path;
+use std::process::Command;
+use std::rc::Rc;
+
+use tempdir::TempDir;
+
+use errors::Result;
+
+/// Memory that may be used by tmpfs working directories of tasks (`--task-tmpfs`)
+pub struct TmpfsBudget {
+    limit: usize,
+    used: Rc<Cell<usize>>,
+}
+
+impl TmpfsBudget {
+    pub fn new(limit: usize) -> Self {
+        TmpfsBudget {
+            limit,
+            used: Rc::new(Cell::new(0)),
+        }
+    }
+
+    /// Mount a tmpfs of `size` bytes over `dir`. Returns false when the size does not fit
+    /// into the remaining budget.
+    fn mount(&self, dir: &Path, size: usize) -> Result<bool> {
+        if self.used.get() + size > self.limit {
+            return Ok(false);
+        }
+        let status = Command::new("mount")
+            .args(&["-t", "tmpfs", "-o", &format!("size={},mode=0700", size), "tmpfs"])
+            .arg(dir)
+            .status()?;
+        if !status.success() {
+            bail!("Mounting tmpfs on {:?} failed ({})", dir, status);
+        }
+        self.used.set(self.used.get() + size);
+        Ok(true)
+    }
+}
+
+/// Working directory of a task; with a tmpfs mounted over it when the task asked
+/// for one by attribute "tmpfs" and the worker had enough budget left
+pub struct TaskDir {
+    dir: TempDir,
+    /// Size of the mounted tmpfs and the counter of the budget it is reserved from
+    tmpfs: Option<(usize, Rc<Cell<usize>>)>,
+}
+
+impl TaskDir {
+    pub fn new(dir: TempDir, tmpfs_size: Option<usize>, budget: Option<&TmpfsBudget>) -> Self {
+        let tmpfs = match (tmpfs_size, budget) {
+            (Some(size), Some(budget)) => match budget.mount(dir.path(), size) {
+                Ok(true) => Some((size, budget.used.clone())),
+                Ok(false) => {
+                    warn!(
+                        "Tmpfs budget exhausted, task directory {:?} ({} bytes) is on disk",
+                        dir.path(),
+                        size
+                    );
+                    None
+                }
+                Err(e) => {
+                    warn!("{}, task directory is on disk", e);
+                    None
+                }
+            },
+            (Some(_), None) => {
+                debug!("Worker has no tmpfs budget, task directory is on disk");
+                None
+            }
+            _ => None,
+        };
+        TaskDir { dir, tmpfs }
+    }
+
+    pub fn path(&self) -> &Path {
+        self.dir.path()
+    }
+}
+
+impl Drop for TaskDir {
+    fn drop(&mut self) {
+        if let Some((size, used)) = self.tmpfs.take() {
+            match Command::new("umount").arg(self.dir.path()).status() {
+                Ok(ref status) if status.success() => (),
+                Ok(status) => warn!("Unmounting tmpfs {:?} failed ({})", self.dir.path(), status),
+                Err(e) => warn!("Unmounting tmpfs {:?} failed: {}", self.dir.path(), e),
+            }
+            used.set(used.get() - size);
+        }
+    }
+}
@@ -4,28 +4,83 @@ use std::cell::Cell;
 use common::id::{SId, SubworkerId, TaskId};
 use errors::Result;
 use super::tempfile::TempFileName;
+use super::tmpfs::{TaskDir, TmpfsBudget};
 
 pub struct WorkDir {
     path: PathBuf,
     id_counter: Cell<u64>,
     data_path: PathBuf,
+    tmpfs: Option<TmpfsBudget>,
 }
 
 impl WorkDir {
+    /// Create working directory structure. If the directory was already used
+    /// by a previous run of worker, existing data are preserved.
     pub fn new(path: PathBuf) -> Self {
-        ::std::fs::create_dir(path.join("data")).unwrap();
-        ::std::fs::create_dir(path.join("tasks")).unwrap();
-        ::std::fs::create_dir(path.join("tmp")).unwrap();
-        ::std::fs::create_dir(path.join("subworkers")).unwrap();
-        ::std::fs::create_dir(path.join("subworkers/work")).unwrap();
+        ::std::fs::create_dir_all(path.join("data")).unwrap();
+        ::std::fs::create_dir_all(path.join("tasks")).unwrap();
+        ::std::fs::create_dir_all(path.join("tmp")).unwrap();
+        ::std::fs::create_dir_all(path.join("subworkers/work")).unwrap();
         // Canonilize is very imporant here,
         // We often check if symlinks goes to data dir
         let path = ::std::fs::canonicalize(path).unwrap();
+
+        // Remove socket that may remain from previous run
+        let listen_path = path.join("subworkers/listen");
+        if listen_path.exists() {
+            ::std::fs::remove_file(&listen_path).unwrap();
+        }
+
+        // New data objects must not overwrite data from previous run
+        let first_id = ::std::fs::read_dir(path.join("data"))
+            .unwrap()
+            .filter_map(|entry| {
+                entry
+                    .ok()
+                    .and_then(|e| e.file_name().to_str().and_then(|s| s.parse::<u64>().ok()))
+            })
+            .max()
+            .map(|id| id + 1)
+            .unwrap_or(0);
+
         WorkDir {
             data_path: path.join("data"),
             path,
-            id_counter: Cell::new(0),
+            id_counter: Cell::new(first_id),
+            tmpfs: None,
+        }
+    }
+
+    /// Remove everything that the current run left in the directory: data of objects,
+    /// directories of tasks and subworkers and the index of stored objects
+    pub fn clear(&self) -> Result<()> {
+        for name in &["data", "tasks", "tmp", "subworkers"] {
+            let path = self.path.join(name);
+            if path.exists() {
+                ::worker::data::set_readonly_dir(&path, false);
+                ::std::fs::remove_dir_all(&path)?;
+            }
+        }
+        let index_path = self.store_index_path();
+        if index_path.exists() {
+            ::std::fs::remove_file(&index_path)?;
         }
+        Ok(())
+    }
+
+    /// Allow working directories of tasks on tmpfs, using at most `limit` bytes of memory
+    pub fn set_task_tmpfs(&mut self, limit: usize) {
+        self.tmpfs = Some(TmpfsBudget::new(limit));
+    }
+
+    /// Get path to index of stored objects
+    pub fn store_index_path(&self) -> PathBuf {
+        self.path.join("store.json")
+    }
+
+    /// Directory of the cache of downloads (see `DownloadCache`)
+    pub fn downloads_path(&self) -> PathBuf {
+        self.path.join("downloads")
     }
 
     /// Get path to unix socket where worker is listening
@@ -56,6 +111,13 @@ impl WorkDir {
         ))
     }
 
+    /// Create working directory of a task, on a tmpfs of `tmpfs_size` bytes if requested
+    /// and it fits into the tmpfs budget of worker; otherwise on disk
+    pub fn make_task_dir(&self, task_id: TaskId, tmpfs_size: Option<usize>) -> Result<TaskDir> {
+        let dir = self.make_task_temp_dir(task_id)?;
+        Ok(TaskDir::new(dir, tmpfs_size, self.tmpfs.as_ref()))
+    }
+
     fn new_id(&self) -> u64 {
         let value = self.id_counter.get();
         self.id_counter.set(value + 1);
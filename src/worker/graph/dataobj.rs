@@ -2,7 +2,7 @@ use common::id::{DataObjectId, WorkerId};
 use common::wrapped::WrappedRcRefCell;
 use common::{Attributes, DataType, RcSet};
 use super::{Graph, TaskRef};
-use worker::data::Data;
+use worker::data::{Data, DataStorage, ValidationSpec};
 use worker::graph::SubworkerRef;
 use worker::WorkDir;
 use errors::{ErrorKind, Result};
@@ -15,6 +15,20 @@ use std::fmt;
 #[derive(Deserialize)]
 pub struct DataObjectAttributeSpec {
     pub content_type: Option<String>,
+    #[serde(default)]
+    pub mode: Option<DataMode>,
+}
+
+/// How a blob is mapped into the working directory of a program (`!run`);
+/// without a mode it is mapped as a read-only file
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DataMode {
+    /// The file is executable, e.g. a tool binary shipped by the client
+    Executable,
+    /// The blob is a tar archive (optionally gzipped), it is extracted into a directory
+    /// with permissions of the archived files
+    Extract,
 }
 
 #[derive(Debug)]
@@ -57,6 +71,12 @@ pub struct DataObject {
     pub(in super::super) attributes: Attributes,
 
     pub(in super::super) new_attributes: Attributes,
+
+    /// Size counted in the pinned memory of worker when data are pinned in memory
+    pub(in super::super) pinned_size: Option<usize>,
+
+    /// NUMA node of the subworker that produced or cached data of the object (--numa)
+    pub(in super::super) numa_node: Option<u32>,
 }
 
 pub type DataObjectRef = WrappedRcRefCell<DataObject>;
@@ -87,6 +107,16 @@ impl DataObject {
         Ok(())
     }
 
+    /// Apply checks of attribute "validate" to the data of the finished object
+    pub fn validate(&self, storage: &DataStorage) -> Result<()> {
+        if let Some(spec) = self.attributes.find::<ValidationSpec>("validate")? {
+            if let Err(e) = spec.check(self.data(), storage) {
+                bail!("Output '{}' failed validation: {}", self.label, e);
+            }
+        }
+        Ok(())
+    }
+
     pub fn set_attributes(&mut self, attributes: Attributes) {
         // TODO Check content type
         self.new_attributes = attributes;
@@ -103,6 +133,18 @@ impl DataObject {
             .unwrap_or(None)
     }
 
+    /// Mode of the object from attribute "spec"
+    pub fn mode(&self) -> Result<Option<DataMode>> {
+        let mode = match self.attributes.find::<DataObjectAttributeSpec>("spec")? {
+            Some(spec) => spec.mode,
+            None => None,
+        };
+        if mode.is_some() && self.data_type != DataType::Blob {
+            bail!("Object '{}' has mode {:?}, but it is not a blob", self.label, mode.unwrap());
+        }
+        Ok(mode)
+    }
+
     #[inline]
     pub fn is_finished(&self) -> bool {
         match self.state {
@@ -126,6 +168,17 @@ impl DataObject {
         }
     }
 
+    /// True if the object has attribute "pin_in_memory" set
+    pub fn is_pin_requested(&self) -> bool {
+        self.attributes
+            .find("pin_in_memory")
+            .unwrap_or_else(|e| {
+                warn!("Object {}: {}", self.id, e);
+                None
+            })
+            .unwrap_or(false)
+    }
+
     pub fn remote(&self) -> Option<WorkerId> {
         match self.state {
             DataObjectState::Remote(ref addr) | DataObjectState::Pulling((ref addr, _)) => {
@@ -140,6 +193,7 @@ impl DataObject {
         source_path: &Path,
         info_path: Option<&str>,
         work_dir: &WorkDir,
+        storage: &DataStorage,
     ) -> Result<()> {
         let metadata = ::std::fs::metadata(source_path).map_err(|_| {
             ErrorKind::Msg(format!(
@@ -149,7 +203,7 @@ impl DataObject {
         })?;
         let target_path = work_dir.new_path_for_dataobject();
         let data = Data::new_by_fs_move(source_path, &metadata, target_path, work_dir.data_path())?;
-        self.set_data(Arc::new(data))
+        self.set_data(Arc::new(storage.adopt(data)?))
     }
 }
 
@@ -179,6 +233,8 @@ impl DataObjectRef {
                     data_type,
                     new_attributes: Attributes::new(),
                     subworker_cache: Default::default(),
+                    pinned_size: None,
+                    numa_node: None,
                 });
                 e.insert(dataobj.clone());
                 dataobj
@@ -3,7 +3,7 @@ pub mod task;
 pub mod graph;
 pub mod subworker;
 
-pub use self::subworker::{subworker_command, SubworkerRef};
-pub use self::dataobj::{DataObject, DataObjectRef, DataObjectState};
-pub use self::task::{TaskInput, TaskRef, TaskState};
+pub use self::subworker::{startup_failure, subworker_command, subworker_paths, SubworkerRef};
+pub use self::dataobj::{DataMode, DataObject, DataObjectRef, DataObjectState};
+pub use self::task::{Task, TaskInput, TaskRef, TaskSecrets, TaskState};
 pub use self::graph::Graph;
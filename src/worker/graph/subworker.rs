@@ -1,12 +1,15 @@
+use std::collections::BTreeMap;
 use std::process::{Command, Stdio};
 use std::fs::File;
 use std::os::unix::io::{FromRawFd, IntoRawFd};
-use std::path::Path;
+use std::path::{Path, PathBuf};
 
 use common::id::SubworkerId;
 use common::wrapped::WrappedRcRefCell;
 use common::fs::LogDir;
 use worker::fs::workdir::WorkDir;
+use worker::limits::ProcessLimits;
+use worker::tasks::run::read_tail;
 
 use errors::Result;
 
@@ -16,6 +19,9 @@ pub struct Subworker {
     control: ::subworker_capnp::subworker_control::Client,
     work_dir: ::tempdir::TempDir,
     kill_sender: Option<::futures::unsync::oneshot::Sender<()>>,
+    limits: ProcessLimits,
+    /// NUMA node to which the process is bound (cpus and memory)
+    numa_node: Option<u32>,
 }
 
 pub type SubworkerRef = WrappedRcRefCell<Subworker>;
@@ -36,6 +42,16 @@ impl Subworker {
         self.work_dir.path()
     }
 
+    #[inline]
+    pub fn limits(&self) -> &ProcessLimits {
+        &self.limits
+    }
+
+    #[inline]
+    pub fn numa_node(&self) -> Option<u32> {
+        self.numa_node
+    }
+
     #[inline]
     pub fn control(&self) -> &::subworker_capnp::subworker_control::Client {
         &self.control
@@ -65,6 +81,8 @@ impl SubworkerRef {
         control: ::subworker_capnp::subworker_control::Client,
         work_dir: ::tempdir::TempDir,
         kill_sender: ::futures::unsync::oneshot::Sender<()>,
+        limits: ProcessLimits,
+        numa_node: Option<u32>,
     ) -> Self {
         Self::wrap(Subworker {
             subworker_id,
@@ -72,18 +90,20 @@ impl SubworkerRef {
             control,
             work_dir,
             kill_sender: Some(kill_sender),
+            limits,
+            numa_node,
         })
     }
 }
 
-pub fn subworker_command(
+/// Create working directory of a new subworker and return it together with
+/// paths of its stdout and stderr logs
+pub fn subworker_paths(
     work_dir: &WorkDir,
     log_dir: &LogDir,
     subworker_id: SubworkerId,
     subworker_type: &str,
-    program_name: &str,
-    program_args: &[String],
-) -> Result<(Command, ::tempdir::TempDir)> {
+) -> Result<(::tempdir::TempDir, PathBuf, PathBuf)> {
     let (log_path_out, log_path_err) = log_dir.subworker_log_paths(subworker_id);
     let subworker_dir = work_dir.make_subworker_work_dir(subworker_id)?;
 
@@ -93,6 +113,21 @@ pub fn subworker_command(
     );
     info!("Subworker stdout log: {:?}", log_path_out);
     info!("Subworker stderr log: {:?}", log_path_err);
+    Ok((subworker_dir, log_path_out, log_path_err))
+}
+
+pub fn subworker_command(
+    work_dir: &WorkDir,
+    log_dir: &LogDir,
+    subworker_id: SubworkerId,
+    subworker_type: &str,
+    program_name: &str,
+    program_args: &[String],
+    env: &BTreeMap<String, String>,
+    limits: &ProcessLimits,
+) -> Result<(Command, ::tempdir::TempDir)> {
+    let (subworker_dir, log_path_out, log_path_err) =
+        subworker_paths(work_dir, log_dir, subworker_id, subworker_type)?;
 
     // --- Open log files ---
     let log_path_out_id = File::create(log_path_out)
@@ -112,8 +147,67 @@ pub fn subworker_command(
         .args(program_args)
         .stdout(log_path_out_pipe)
         .stderr(log_path_err_pipe)
+        .envs(env)
         .env("RAIN_SUBWORKER_SOCKET", work_dir.subworker_listen_path())
         .env("RAIN_SUBWORKER_ID", subworker_id.to_string())
+        .env("RAIN_SUBWORKER_TYPE", subworker_type)
         .current_dir(subworker_dir.path());
+    limits.apply(&mut command);
     Ok((command, subworker_dir))
 }
+
+/// Message of a failed start of a subworker: the reason, a hint when a Python module
+/// is missing and the tail of stderr of the subworker
+pub fn startup_failure(
+    subworker_id: SubworkerId,
+    subworker_type: &str,
+    reason: &str,
+    stderr_path: &Path,
+) -> String {
+    let mut message = format!("Subworker {} ({}) {}", subworker_id, subworker_type, reason);
+    match read_tail(stderr_path) {
+        Ok(ref stderr) if !stderr.trim().is_empty() => {
+            if let Some(module) = missing_module(stderr) {
+                message.push_str(&format!(
+                    "\nPython module '{}' is not installed in the environment of the worker",
+                    module
+                ));
+            }
+            message.push_str(&format!("\nStderr: {}", stderr));
+        }
+        _ => message.push_str("\nStderr of the subworker is empty"),
+    }
+    message
+}
+
+/// Module from the last "No module named ..." error of Python in `stderr`
+fn missing_module(stderr: &str) -> Option<String> {
+    let line = stderr
+        .lines()
+        .rev()
+        .find(|line| line.contains("No module named "))?;
+    let name = line.split("No module named ")
+        .nth(1)?
+        .trim()
+        .trim_matches(|c| c == '\'' || c == '"');
+    if name.is_empty() {
+        None
+    } else {
+        Some(name.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::missing_module;
+
+    #[test]
+    fn test_missing_module() {
+        let stderr = "Traceback (most recent call last):\n  File \"<frozen runpy>\", line 189\n\
+                      ModuleNotFoundError: No module named 'rain'\n";
+        assert_eq!(missing_module(stderr), Some("rain".to_string()));
+        let stderr = "ImportError: No module named capnp\n";
+        assert_eq!(missing_module(stderr), Some("capnp".to_string()));
+        assert_eq!(missing_module("Segmentation fault\n"), None);
+    }
+}
@@ -30,6 +30,17 @@ pub struct TaskInput {
     pub path: String,
 }
 
+/// Values of secrets of the task as pairs (name of environment variable, value);
+/// values are never printed
+#[derive(Default)]
+pub struct TaskSecrets(pub Vec<(String, Vec<u8>)>);
+
+impl fmt::Debug for TaskSecrets {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "<{} secret(s)>", self.0.len())
+    }
+}
+
 #[derive(Debug)]
 pub struct Task {
     pub(in super::super) id: TaskId,
@@ -53,6 +64,11 @@ pub struct Task {
     pub(in super::super) attributes: Attributes,
 
     pub(in super::super) new_attributes: Attributes,
+
+    pub(in super::super) secrets: TaskSecrets,
+
+    /// Devices of GPUs allocated to the running task
+    pub(in super::super) gpus: Vec<u32>,
 }
 
 impl Task {
@@ -137,6 +153,8 @@ impl TaskRef {
             resources: resources,
             attributes: attributes,
             new_attributes: Attributes::new(),
+            secrets: Default::default(),
+            gpus: Vec::new(),
         });
 
         for input in &task.get().inputs {
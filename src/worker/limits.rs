@@ -0,0 +1,95 @@
+use std::process::{Command, ExitStatus};
+use std::io;
+
+use nix::libc;
+
+use worker::profile::ResourceUsage;
+
+/// Limits of processes spawned by the worker (`!run` programs and subworkers).
+/// They are taken from task attribute "limits", e.g. {"nofile": 256, "nproc": 64, "cpu": 60}.
+/// Note that RLIMIT_NPROC is counted for all processes of the user,
+/// not only for children of the limited process.
+/// Exceeding "nofile" or "nproc" is not detected (see `detect_exceeded`).
+#[derive(Deserialize, Default, Debug, Clone, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+pub struct ProcessLimits {
+    /// Max number of open file descriptors (RLIMIT_NOFILE)
+    pub nofile: Option<u64>,
+    /// Max number of processes (RLIMIT_NPROC)
+    pub nproc: Option<u64>,
+    /// Max CPU time in seconds (RLIMIT_CPU), only for `!run` programs as subworkers
+    /// are reused by more tasks
+    pub cpu: Option<u64>,
+}
+
+/// Set the soft limit to `value` and the hard limit to `value + slack`
+fn set_rlimit(resource: libc::c_int, value: u64, slack: u64) -> io::Result<()> {
+    let mut rlim = libc::rlimit {
+        rlim_cur: 0,
+        rlim_max: 0,
+    };
+    if unsafe { libc::getrlimit(resource as _, &mut rlim) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    // Limit can be only lowered, hard limit is also set so the process cannot raise it back
+    let max = ::std::cmp::min(value.saturating_add(slack) as libc::rlim_t, rlim.rlim_max);
+    rlim.rlim_cur = ::std::cmp::min(value as libc::rlim_t, max);
+    rlim.rlim_max = max;
+    if unsafe { libc::setrlimit(resource as _, &rlim) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+impl ProcessLimits {
+    pub fn is_empty(&self) -> bool {
+        self.nofile.is_none() && self.nproc.is_none() && self.cpu.is_none()
+    }
+
+    /// Set limits to the command; they are applied in the child process before exec
+    pub fn apply(&self, command: &mut Command) {
+        use std::os::unix::process::CommandExt;
+
+        if self.is_empty() {
+            return;
+        }
+        let limits = self.clone();
+        command.before_exec(move || {
+            if let Some(value) = limits.nofile {
+                set_rlimit(libc::RLIMIT_NOFILE as libc::c_int, value, 0)?;
+            }
+            if let Some(value) = limits.nproc {
+                set_rlimit(libc::RLIMIT_NPROC as libc::c_int, value, 0)?;
+            }
+            if let Some(value) = limits.cpu {
+                // The process gets SIGXCPU at the soft limit and SIGKILL a second later
+                set_rlimit(libc::RLIMIT_CPU as libc::c_int, value, 1)?;
+            }
+            Ok(())
+        });
+    }
+
+    /// Detect from the exit status and the resource usage of a failed process, if it was
+    /// terminated by the kernel for exceeding a limit. Only the CPU time limit is detected,
+    /// as it is enforced by a signal. Exceeding "nofile" or "nproc" is NOT detected:
+    /// system calls of the process fail (EMFILE, EAGAIN) and the process fails with
+    /// its own error (e.g. "Too many open files" in its stderr).
+    pub fn detect_exceeded(
+        &self,
+        status: &ExitStatus,
+        usage: &ResourceUsage,
+    ) -> Option<&'static str> {
+        use std::os::unix::process::ExitStatusExt;
+
+        let cpu = match self.cpu {
+            Some(cpu) => cpu,
+            None => return None,
+        };
+        match status.signal() {
+            Some(libc::SIGXCPU) => Some("cpu"),
+            // SIGKILL is also sent by others (e.g. OOM killer), so the usage is checked
+            Some(libc::SIGKILL) if usage.cpu_time >= cpu * 1000 => Some("cpu"),
+            _ => None,
+        }
+    }
+}
@@ -4,6 +4,13 @@ pub mod graph;
 pub mod data;
 pub mod rpc;
 pub mod tasks;
+pub mod executor;
+pub mod limits;
+pub mod numa;
+pub mod profile;
+pub mod zygote;
+pub mod subworkers;
+pub mod timers;
 
 pub use self::fs::workdir::WorkDir;
 pub use self::state::{State, StateRef};
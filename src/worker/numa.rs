@@ -0,0 +1,101 @@
+use std::collections::HashMap;
+use std::path::Path;
+
+use errors::Result;
+
+const NUMA_SYSFS_PATH: &str = "/sys/devices/system/node";
+
+/// NUMA nodes of the machine (enabled by --numa). Subworkers are bound to
+/// a node (cpus and memory) by `numactl`, so objects held in memory of a subworker
+/// stay on its node and consumers of the objects are preferably run on the same node.
+pub struct NumaTopology {
+    nodes: Vec<u32>,
+}
+
+impl NumaTopology {
+    pub fn detect() -> Result<Self> {
+        Self::from_sysfs(Path::new(NUMA_SYSFS_PATH))
+    }
+
+    fn from_sysfs(path: &Path) -> Result<Self> {
+        let mut nodes: Vec<u32> = ::std::fs::read_dir(path)
+            .map_err(|e| format!("Cannot read NUMA nodes from {:?}: {}", path, e))?
+            .filter_map(|entry| {
+                let name = entry.ok()?.file_name().into_string().ok()?;
+                if name.starts_with("node") {
+                    name[4..].parse().ok()
+                } else {
+                    None
+                }
+            })
+            .collect();
+        if nodes.is_empty() {
+            bail!("No NUMA nodes found in {:?}", path);
+        }
+        nodes.sort();
+        Ok(NumaTopology { nodes })
+    }
+
+    #[inline]
+    pub fn nodes(&self) -> &[u32] {
+        &self.nodes
+    }
+
+    /// Node with the lowest count of subworkers bound to it
+    pub fn least_loaded_node(&self, subworkers: &HashMap<u32, usize>) -> u32 {
+        *self.nodes
+            .iter()
+            .min_by_key(|node| subworkers.get(node).cloned().unwrap_or(0))
+            .unwrap()
+    }
+
+    /// Program and arguments running the given program bound to cpus and memory of `node`
+    pub fn bind_command(node: u32, program: &str, args: &[String]) -> (String, Vec<String>) {
+        let mut bound_args = vec![
+            format!("--cpunodebind={}", node),
+            format!("--membind={}", node),
+            program.to_string(),
+        ];
+        bound_args.extend(args.iter().cloned());
+        ("numactl".to_string(), bound_args)
+    }
+}
+
+/// Node holding most bytes of the given objects, given as pairs (node of object, size)
+pub fn preferred_node<I: Iterator<Item = (Option<u32>, usize)>>(objects: I) -> Option<u32> {
+    let mut sizes: HashMap<u32, usize> = HashMap::new();
+    for (node, size) in objects {
+        if let Some(node) = node {
+            *sizes.entry(node).or_insert(0) += size;
+        }
+    }
+    sizes
+        .into_iter()
+        .max_by_key(|&(node, size)| (size, ::std::cmp::Reverse(node)))
+        .map(|(node, _)| node)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_preferred_node() {
+        assert_eq!(preferred_node(vec![].into_iter()), None);
+        assert_eq!(preferred_node(vec![(None, 100)].into_iter()), None);
+        let objects = vec![(Some(0), 10), (Some(1), 15), (None, 100), (Some(0), 10)];
+        assert_eq!(preferred_node(objects.into_iter()), Some(0));
+        assert_eq!(preferred_node(vec![(Some(1), 5), (Some(0), 5)].into_iter()), Some(0));
+    }
+
+    #[test]
+    fn test_least_loaded_node() {
+        let topology = NumaTopology { nodes: vec![0, 1, 2] };
+        let mut subworkers = HashMap::new();
+        subworkers.insert(0, 2);
+        subworkers.insert(2, 1);
+        assert_eq!(topology.least_loaded_node(&subworkers), 1);
+        subworkers.insert(1, 3);
+        assert_eq!(topology.least_loaded_node(&subworkers), 2);
+    }
+}
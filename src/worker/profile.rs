@@ -0,0 +1,127 @@
+use std::io;
+use std::process::{Command, ExitStatus};
+use std::sync::{Arc, Mutex};
+
+use futures::{Async, Future, Poll};
+use futures::sync::oneshot;
+use nix::libc;
+
+use errors::{Error, Result};
+
+/// Resource usage of a task, stored into task attribute "profile".
+/// The wall time of the task is in attribute "info" (duration).
+#[derive(Serialize, Deserialize, Debug, Default, Clone, PartialEq)]
+pub struct ResourceUsage {
+    /// User + system CPU time in milliseconds
+    pub cpu_time: u64,
+    /// Maximal resident set size in bytes
+    pub max_rss: u64,
+    /// Bytes read from the storage layer
+    pub read_bytes: u64,
+    /// Bytes written to the storage layer
+    pub write_bytes: u64,
+}
+
+impl ResourceUsage {
+    fn from_rusage(rusage: &libc::rusage) -> Self {
+        let millis = |t: &libc::timeval| t.tv_sec as u64 * 1000 + t.tv_usec as u64 / 1000;
+        ResourceUsage {
+            cpu_time: millis(&rusage.ru_utime) + millis(&rusage.ru_stime),
+            // ru_maxrss is in kilobytes, block counts are in 512 byte units
+            max_rss: rusage.ru_maxrss as u64 * 1024,
+            read_bytes: rusage.ru_inblock as u64 * 512,
+            write_bytes: rusage.ru_oublock as u64 * 512,
+        }
+    }
+}
+
+/// Wait until the process exits but leave it as a zombie, so its pid cannot be reused;
+/// then reap it by wait4 that also returns its resource usage
+fn wait_with_usage(
+    pid: libc::pid_t,
+    reaped: &Mutex<bool>,
+) -> io::Result<(ExitStatus, ResourceUsage)> {
+    use std::os::unix::process::ExitStatusExt;
+
+    loop {
+        let mut info: libc::siginfo_t = unsafe { ::std::mem::zeroed() };
+        let r = unsafe {
+            libc::waitid(
+                libc::P_PID,
+                pid as libc::id_t,
+                &mut info,
+                libc::WEXITED | libc::WNOWAIT,
+            )
+        };
+        if r == 0 {
+            break;
+        }
+        let error = io::Error::last_os_error();
+        if error.kind() != io::ErrorKind::Interrupted {
+            return Err(error);
+        }
+    }
+    let mut reaped = reaped.lock().unwrap();
+    let mut status = 0;
+    let mut rusage: libc::rusage = unsafe { ::std::mem::zeroed() };
+    if unsafe { libc::wait4(pid, &mut status, 0, &mut rusage) } < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    *reaped = true;
+    Ok((
+        ExitStatus::from_raw(status),
+        ResourceUsage::from_rusage(&rusage),
+    ))
+}
+
+/// Exit status and resource usage of a spawned process. The process is waited for
+/// in a separate thread, since the waiting of tokio-process does not provide the usage.
+/// The process is killed when the future is dropped before the process finished.
+pub struct ProcessUsage {
+    pid: libc::pid_t,
+    reaped: Arc<Mutex<bool>>,
+    receiver: oneshot::Receiver<io::Result<(ExitStatus, ResourceUsage)>>,
+}
+
+impl ProcessUsage {
+    pub fn spawn(command: &mut Command) -> Result<Self> {
+        let pid = command.spawn()?.id() as libc::pid_t;
+        let reaped = Arc::new(Mutex::new(false));
+        let (sender, receiver) = oneshot::channel();
+        let thread_reaped = reaped.clone();
+        ::std::thread::Builder::new()
+            .name(format!("wait-{}", pid))
+            .spawn(move || {
+                let _ = sender.send(wait_with_usage(pid, &thread_reaped));
+            })?;
+        Ok(ProcessUsage {
+            pid,
+            reaped,
+            receiver,
+        })
+    }
+}
+
+impl Future for ProcessUsage {
+    type Item = (ExitStatus, ResourceUsage);
+    type Error = Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Error> {
+        match self.receiver.poll() {
+            Ok(Async::Ready(result)) => Ok(Async::Ready(result?)),
+            Ok(Async::NotReady) => Ok(Async::NotReady),
+            Err(_) => bail!("Waiting for process {} was cancelled", self.pid),
+        }
+    }
+}
+
+impl Drop for ProcessUsage {
+    fn drop(&mut self) {
+        let reaped = self.reaped.lock().unwrap();
+        if !*reaped {
+            unsafe {
+                libc::kill(self.pid, libc::SIGKILL);
+            }
+        }
+    }
+}
@@ -3,7 +3,8 @@ use std::sync::Arc;
 use common::{Attributes, DataType, Resources};
 use common::convert::{FromCapnp, ToCapnp};
 use common::id::{DataObjectId, TaskId, WorkerId};
-use worker::graph::{DataObjectState, TaskInput};
+use common::chaos::ChaosConfig;
+use worker::graph::{DataObjectState, TaskInput, TaskSecrets};
 use worker::StateRef;
 use worker_capnp::worker_control;
 use capnp::capability::Promise;
@@ -30,14 +31,28 @@ impl Drop for WorkerControlImpl {
 }
 
 impl worker_control::Server for WorkerControlImpl {
+    fn get_data_store(
+        &mut self,
+        _params: worker_control::GetDataStoreParams,
+        mut results: worker_control::GetDataStoreResults,
+    ) -> Promise<(), ::capnp::Error> {
+        // Used by server when it cannot connect to the worker (worker behind gateway)
+        let datastore = ::datastore_capnp::data_store::ToClient::new(
+            ::worker::rpc::datastore::DataStoreImpl::new(&self.state),
+        ).from_server::<::capnp_rpc::Server>();
+        results.get().set_store(datastore);
+        Promise::ok(())
+    }
+
     fn get_worker_resources(
         &mut self,
         _params: worker_control::GetWorkerResourcesParams,
         mut results: worker_control::GetWorkerResourcesResults,
     ) -> Promise<(), ::capnp::Error> {
-        results
+        self.state
             .get()
-            .set_n_cpus(self.state.get().get_resources().cpus);
+            .get_resources()
+            .to_capnp(&mut results.get());
         Promise::ok(())
     }
 
@@ -67,6 +82,42 @@ impl worker_control::Server for WorkerControlImpl {
         Promise::ok(())
     }
 
+    fn delete_objects(
+        &mut self,
+        params: worker_control::DeleteObjectsParams,
+        mut results: worker_control::DeleteObjectsResults,
+    ) -> Promise<(), ::capnp::Error> {
+        let params = pry!(params.get());
+        let objects = pry!(params.get_objects());
+
+        let mut deleted = Vec::new();
+        let mut freed_bytes = 0u64;
+        {
+            let mut state = self.state.get_mut();
+            for cid in objects.iter() {
+                let id = DataObjectId::from_capnp(&cid);
+                if let Some(size) = state.delete_object(id) {
+                    deleted.push(id);
+                    freed_bytes += size as u64;
+                }
+            }
+        }
+        debug!(
+            "Deleted {}/{} objects requested by server, freed {} bytes",
+            deleted.len(),
+            objects.len(),
+            freed_bytes
+        );
+
+        let mut results = results.get();
+        results.set_freed_bytes(freed_bytes);
+        let mut list = results.init_deleted(deleted.len() as u32);
+        for (i, id) in deleted.iter().enumerate() {
+            id.to_capnp(&mut list.borrow().get(i as u32));
+        }
+        Promise::ok(())
+    }
+
     fn stop_tasks(
         &mut self,
         params: worker_control::StopTasksParams,
@@ -81,6 +132,28 @@ impl worker_control::Server for WorkerControlImpl {
         Promise::ok(())
     }
 
+    fn pull_back_tasks(
+        &mut self,
+        params: worker_control::PullBackTasksParams,
+        mut results: worker_control::PullBackTasksResults,
+    ) -> Promise<(), ::capnp::Error> {
+        let params = pry!(params.get());
+        let mut state = self.state.get_mut();
+        let mut pulled = Vec::new();
+        for tid in pry!(params.get_tasks()).iter() {
+            let task_id = TaskId::from_capnp(&tid);
+            if state.pull_back_task(&task_id) {
+                pulled.push(task_id);
+            }
+        }
+        debug!("Pulled back {} task(s) requested by server", pulled.len());
+        let mut list = results.get().init_tasks(pulled.len() as u32);
+        for (i, id) in pulled.iter().enumerate() {
+            id.to_capnp(&mut list.borrow().get(i as u32));
+        }
+        Promise::ok(())
+    }
+
     fn add_nodes(
         &mut self,
         params: worker_control::AddNodesParams,
@@ -106,7 +179,8 @@ impl worker_control::Server for WorkerControlImpl {
             }
 
             let placement = WorkerId::from_capnp(&co.get_placement().unwrap());
-            let (object_state, is_remote) = if placement == *state.worker_id() {
+            let inlined = co.get_inlined();
+            let (object_state, is_remote) = if inlined || placement == *state.worker_id() {
                 (DataObjectState::Assigned, false)
             } else {
                 (DataObjectState::Remote(placement), true)
@@ -139,7 +213,21 @@ impl worker_control::Server for WorkerControlImpl {
                 is_remote
             );
 
-            if is_remote {
+            if inlined {
+                // Small object with data in the message, nothing to fetch
+                let data = pry!(state.data_storage().put(
+                    state.work_dir(),
+                    data_type,
+                    pry!(co.get_inline_data()).to_vec(),
+                ));
+                pry!(
+                    dataobject
+                        .get_mut()
+                        .set_data(Arc::new(data))
+                        .map_err(|e| ::capnp::Error::failed(e.description().to_string()))
+                );
+                state.object_is_finished(&dataobject);
+            } else if is_remote {
                 remote_objects.push(dataobject);
             }
         }
@@ -168,13 +256,17 @@ impl worker_control::Server for WorkerControlImpl {
                 .map(|co| state.object_by_id(DataObjectId::from_capnp(&co)).unwrap())
                 .collect();
             let task = state.add_task(id, inputs, outputs, resources, task_type.into(), attributes);
+            let secrets: Vec<_> = pry!(ct.get_secrets())
+                .iter()
+                .map(|cs| (cs.get_name().unwrap().to_string(), cs.get_value().unwrap().to_vec()))
+                .collect();
+            task.get_mut().secrets = TaskSecrets(secrets);
 
             debug!("Received Task {:?}", task.get());
         }
 
-        // Start fetching remote objects
-        // TODO: Introduce some kind of limitations of how many tasks are
-        // fetched at once
+        // Start fetching remote objects, fetch executor limits
+        // how many objects are fetched at once
         for object in remote_objects {
             let object_ref = object.clone();
             let mut o = object.get_mut();
@@ -184,13 +276,16 @@ impl worker_control::Server for WorkerControlImpl {
             o.state = DataObjectState::Pulling((worker_id.clone(), sender));
 
             let state_ref = self.state.clone();
-            let future = state
-                .fetch_from_datastore(&worker_id, object_id, 0)
-                .map(move |data| {
+            let future = ::futures::future::lazy(move || {
+                let future = state_ref
+                    .get_mut()
+                    .fetch_from_datastore(&worker_id, object_id);
+                future.map(move |data| {
                     object_ref.get_mut().set_data(Arc::new(data)).unwrap();
                     state_ref.get_mut().object_is_finished(&object_ref);
-                });
-            state.handle().spawn(
+                })
+            });
+            state.fetch_executor().spawn_limited(
                 future
                     .map_err(move |e| {
                         match e {
@@ -245,4 +340,32 @@ impl worker_control::Server for WorkerControlImpl {
         }
         Promise::ok(())
     }
+
+    fn set_chaos(
+        &mut self,
+        params: worker_control::SetChaosParams,
+        _results: worker_control::SetChaosResults,
+    ) -> Promise<(), ::capnp::Error> {
+        let config = pry!(pry!(params.get()).get_config());
+        let config: ChaosConfig = pry!(::serde_json::from_str(config).map_err(Error::from));
+        pry!(self.state.get_mut().chaos_mut().configure(config));
+        Promise::ok(())
+    }
+
+    fn leave(
+        &mut self,
+        _params: worker_control::LeaveParams,
+        _results: worker_control::LeaveResults,
+    ) -> Promise<(), ::capnp::Error> {
+        info!("Server asked the worker to leave");
+        // Leave in the next turn, so the reply is sent
+        let state_ref = self.state.clone();
+        self.state
+            .get()
+            .handle()
+            .spawn(::futures::future::lazy(move || -> Result<(), ()> {
+                state_ref.get_mut().leave()
+            }));
+        Promise::ok(())
+    }
 }
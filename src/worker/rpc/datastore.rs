@@ -1,7 +1,7 @@
 use capnp::capability::Promise;
 use common::convert::FromCapnp;
 use common::id::DataObjectId;
-use worker::data::{new_pack_stream, PackStream};
+use worker::data::{new_pack_stream, pack_stream_checksum, range_pack_stream, PackStream};
 
 use datastore_capnp::{data_store, read_reply, reader};
 use worker::state::StateRef;
@@ -36,15 +36,23 @@ impl data_store::Server for DataStoreImpl {
                 return Promise::ok(());
             }
         };
-        let size = object.get().size.map(|s| s as i64).unwrap_or(-1i64);
-
-        let offset = params.get_offset();
-
-        assert!(offset == 0); // TODO: implement for different offset
+        let offset = params.get_offset() as usize;
+        let length = params.get_length() as usize;
+        let size = match object.get().size {
+            Some(size) => {
+                let size = size.saturating_sub(offset);
+                if length > 0 && length < size {
+                    length as i64
+                } else {
+                    size as i64
+                }
+            }
+            None => -1i64,
+        };
 
         let data = object.get().data().clone();
         let data_type = data.data_type();
-        let pack_stream = new_pack_stream(&state, data).unwrap();
+        let pack_stream = range_pack_stream(new_pack_stream(&state, data).unwrap(), offset, length);
         let reader = reader::ToClient::new(ReaderImpl::new(pack_stream))
             .from_server::<::capnp_rpc::Server>();
 
@@ -55,6 +63,35 @@ impl data_store::Server for DataStoreImpl {
         results.set_data_type(data_type.to_capnp());
         Promise::ok(())
     }
+
+    fn get_info(
+        &mut self,
+        params: data_store::GetInfoParams,
+        mut results: data_store::GetInfoResults,
+    ) -> Promise<(), ::capnp::Error> {
+        let params = pry!(params.get());
+        let id = DataObjectId::from_capnp(&pry!(params.get_id()));
+        let state = self.state.get();
+        let object = match state.object_by_id(id) {
+            Ok(ref o) if o.get().is_finished() => o.clone(),
+            _ => {
+                results.get().set_not_here(());
+                return Promise::ok(());
+            }
+        };
+        let object = object.get();
+        let data = object.data().clone();
+        let mut results = results.get();
+        results.set_size(object.size.map(|s| s as i64).unwrap_or(-1i64));
+        results.set_data_type(data.data_type().to_capnp());
+        results.set_content_type(&object.content_type().unwrap_or_default());
+        if params.get_checksum() {
+            let mut pack_stream = pry!(new_pack_stream(&state, data));
+            results.set_checksum(&pack_stream_checksum(&mut *pack_stream));
+        }
+        results.set_ok(());
+        Promise::ok(())
+    }
 }
 
 pub struct ReaderImpl {
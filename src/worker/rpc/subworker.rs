@@ -8,7 +8,7 @@ use common::id::{DataObjectId, SubworkerId};
 use common::convert::FromCapnp;
 use common::DataType;
 use worker::{State, StateRef};
-use worker::data::{Data, Storage};
+use worker::data::Data;
 use subworker_capnp::subworker_upstream;
 use capnp;
 use capnp::capability::Promise;
@@ -77,10 +77,14 @@ pub fn data_from_capnp(
     reader: &::subworker_capnp::local_data::Reader,
 ) -> Result<Arc<Data>> {
     match reader.get_storage().which()? {
-        ::subworker_capnp::local_data::storage::Memory(data) => Ok(Arc::new(Data::new(
-            Storage::Memory(data?.into()),
-            DataType::from_capnp(reader.get_data_type()?),
-        ))),
+        ::subworker_capnp::local_data::storage::Memory(data) => {
+            let data = state.data_storage().put(
+                state.work_dir(),
+                DataType::from_capnp(reader.get_data_type()?),
+                data?.into(),
+            )?;
+            Ok(Arc::new(data))
+        }
         ::subworker_capnp::local_data::storage::Path(data) => {
             let source_path = Path::new(data?);
             if !source_path.is_absolute() {
@@ -91,12 +95,13 @@ pub fn data_from_capnp(
             }
             let work_dir = state.work_dir();
             let target_path = work_dir.new_path_for_dataobject();
-            Ok(Arc::new(Data::new_by_fs_move(
+            let data = Data::new_by_fs_move(
                 &Path::new(source_path),
                 &::std::fs::metadata(source_path)?,
                 target_path,
                 work_dir.data_path(),
-            )?))
+            )?;
+            Ok(Arc::new(state.data_storage().adopt(data)?))
         }
         ::subworker_capnp::local_data::storage::InWorker(data) => {
             let object_id = DataObjectId::from_capnp(&data?);
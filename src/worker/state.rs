@@ -1,8 +1,11 @@
-use std::net::SocketAddr;
-use std::process::exit;
+use std::net::{IpAddr, SocketAddr};
+use std::os::unix::process::CommandExt;
+use std::process::{exit, Command};
 use std::path::{Path, PathBuf};
-use std::time::Duration;
-use std::collections::HashMap;
+use std::time::{Duration, Instant};
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering, ATOMIC_BOOL_INIT};
 
 use common::asycinit::AsyncInitWrapper;
 use common::RcSet;
@@ -12,25 +15,46 @@ use common::wrapped::WrappedRcRefCell;
 use common::resources::Resources;
 use common::monitor::Monitor;
 use common::Attributes;
+use common::capabilities::{self, Capabilities};
+use common::chaos::Chaos;
+use common::labels::Labels;
+use common::sys::Platform;
 use common::fs::logdir::LogDir;
 use common::events;
+use common::logging::shipping;
 use common::DataType;
+use common::framing::{announce_framing, Framing};
+use common::rpc::new_framed_rpc_system;
 
-use worker::graph::{subworker_command, DataObject, DataObjectRef, DataObjectState, Graph,
-                    SubworkerRef, TaskInput, TaskRef, TaskState};
-use worker::data::{Data, DataBuilder};
+use worker::graph::{startup_failure, subworker_command, subworker_paths, DataObject,
+                    DataObjectRef, DataObjectState, Graph, SubworkerRef, TaskInput, TaskRef,
+                    TaskState};
+use worker::data::{new_pack_stream, pack_stream_checksum, Data, DataStorage, Storage};
 use worker::tasks::TaskInstance;
+use worker::tasks::instance::BUILTIN_TASKS;
 use worker::rpc::{SubworkerUpstreamImpl, WorkerControlImpl};
 use worker::fs::workdir::WorkDir;
+use worker::fs::store::{ObjectStore, StoredObject};
+use worker::fs::downloads::{DownloadCache, DEFAULT_DOWNLOAD_CACHE};
+use worker::executor::LimitedExecutorRef;
+use worker::limits::ProcessLimits;
+use worker::numa::NumaTopology;
+use worker::subworkers::SubworkerKind;
+use worker::timers::{TimerEvent, Timers, TIMER_TICK_MS};
+use worker::zygote::ZygoteRef;
 
 use futures::Future;
 use futures::Stream;
 use futures::IntoFuture;
+use futures_cpupool::{Builder as CpuPoolBuilder, CpuPool};
 use tokio_core::reactor::Handle;
 use tokio_core::net::TcpListener;
 use tokio_core::net::TcpStream;
 use tokio_timer;
+use tokio_io::{AsyncRead, AsyncWrite};
+use tokio_tls::TlsConnector;
 use tokio_uds::{UnixListener, UnixStream};
+use nix::libc;
 use capnp_rpc::rpc_twoparty_capnp;
 use capnp::capability::Promise;
 use errors::{Error, ErrorKind, Result};
@@ -38,8 +62,19 @@ use errors::{Error, ErrorKind, Result};
 use WORKER_PROTOCOL_VERSION;
 
 const MONITORING_INTERVAL: u64 = 5; // Monitoring interval in seconds
-const DELETE_WAIT_LIST_INTERVAL: u64 = 2; // How often is delete_wait_list checked in seconds
+const LOG_SHIPPING_INTERVAL: u64 = 1; // How often are logs shipped to server (seconds)
 const DEFAULT_DELETE_LIST_MAX_TIMEOUT: u32 = 5;
+const MAX_CONCURRENT_FETCHES: usize = 32; // Max number of objects fetched at once
+const MAX_STARTING_SUBWORKERS: usize = 4; // Max number of subworkers that are started at once
+const DEFAULT_SUBWORKER_TIMEOUT: u64 = 60; // Time for a subworker to register in seconds
+const DRAIN_CHECK_INTERVAL: u64 = 1; // How often is draining checked in seconds
+
+/// Set by SIGTERM handler of a preemptible worker
+static TERMINATION_REQUESTED: AtomicBool = ATOMIC_BOOL_INIT;
+
+extern "C" fn on_sigterm(_: libc::c_int) {
+    TERMINATION_REQUESTED.store(true, Ordering::SeqCst);
+}
 
 pub struct State {
     pub(super) graph: Graph,
@@ -50,6 +85,30 @@ pub struct State {
     /// Tokio core handle
     handle: Handle,
 
+    /// Framing of the connection to server (set by --compress-control)
+    server_framing: Framing,
+
+    /// Worker is connected to server through a gateway (set by --via-gateway)
+    via_gateway: bool,
+
+    /// Cost of running on the worker per cpu announced to server (set by --cost)
+    cost: f64,
+
+    /// Grace period of draining after SIGTERM; `Some` for preemptible workers
+    /// (set by --preemptible and --drain-grace)
+    drain_grace: Option<Duration>,
+
+    /// When the draining has to be finished, `Some` when the worker is draining
+    drain_deadline: Option<Instant>,
+
+    /// Finished objects up to this size are sent inline in updates to server
+    /// (announced by server at registration)
+    inline_threshold: usize,
+
+    /// Offset of the server clock against the local clock, estimated at registration;
+    /// times of events and task starts reported to the server are corrected by it
+    clock_offset: ::chrono::Duration,
+
     /// Handle to WorkerUpstream (that resides in server)
     upstream: Option<::worker_capnp::worker_upstream::Client>,
 
@@ -62,22 +121,42 @@ pub struct State {
     /// A worker assigned to this worker
     worker_id: WorkerId,
 
+    /// Drives `timers` by a single interval
     timer: tokio_timer::Timer,
 
+    /// Timers of the worker (sleeps, timeouts and periodic actions)
+    timers: Timers,
+
     /// This is hard limit for number of simultaneously executed tasks
     /// The purpose is to limit task with empty resources
-    /// The initial value is 4 * n_cpus
+    /// The initial value is --max-parallel-tasks (default 4 * n_cpus)
     free_slots: u32,
 
     resources: Resources,
 
     free_resources: Resources,
 
+    /// Devices of GPUs not allocated to running tasks
+    free_gpus: Vec<u32>,
+
+    /// Bounded executors, they prevent flooding of reactor when
+    /// many tasks are assigned at once
+    task_executor: LimitedExecutorRef,
+    fetch_executor: LimitedExecutorRef,
+    subworker_executor: LimitedExecutorRef,
+
+    /// Threads for CPU-heavy work of built-in tasks (e.g. columnar conversions),
+    /// so it does not block the reactor
+    blocking_pool: CpuPool,
+
     /// Path to working directory
     work_dir: WorkDir,
 
     log_dir: LogDir,
 
+    /// Index of finished objects stored in working directory
+    store: ObjectStore,
+
     delete_list_max_timeout: u32,
 
     monitor: Monitor,
@@ -90,14 +169,60 @@ pub struct State {
             SubworkerId,
             String,                                           // type (e.g. "py")
             ::tempdir::TempDir,                               // working dir
-            ::futures::unsync::oneshot::Sender<SubworkerRef>, // when finished
-            ::futures::unsync::oneshot::Sender<()>,
-        ), // kill switch of worker
+            ::futures::unsync::oneshot::Sender<Result<SubworkerRef>>, // when finished
+            ::futures::unsync::oneshot::Sender<()>,           // kill switch of worker
+            ProcessLimits,                                    // limits of process
+            Option<u32>,                                      // bound NUMA node
+        ),
     >,
 
-    // Map from name of subworkers to its arguments
+    // Map from types of subworkers to how they are started
     // e.g. "py" => ["python", "-m", "rain.subworker"]
-    subworker_args: HashMap<String, Vec<String>>,
+    subworker_kinds: HashMap<String, SubworkerKind>,
+
+    /// Subworkers are forked from initialized zygotes (set by --warm-subworkers)
+    warm_subworkers: bool,
+
+    /// A subworker that does not register within the timeout is killed
+    /// and the task waiting for it fails (set by --subworker-timeout)
+    subworker_timeout: Duration,
+
+    /// Max. number of bytes kept of stdout/stderr of each program (set by --max-task-output)
+    task_output_limit: Option<u64>,
+
+    /// Files downloaded by tasks `!download` (size set by --download-cache)
+    download_cache: DownloadCache,
+
+    /// The worker was assigned to its server by a broker (set by --broker), when
+    /// the server asks it to leave, it starts again and asks the broker for a server
+    rejoin: bool,
+
+    /// Zygotes of subworker types, started when the first subworker of the type is needed
+    zygotes: HashMap<String, ZygoteRef>,
+
+    /// Memory budget for objects pinned in memory (set by --pin-memory)
+    pin_memory_limit: usize,
+
+    /// NUMA nodes to which subworkers are bound (--numa)
+    numa: Option<NumaTopology>,
+
+    /// Size of data currently pinned in memory
+    pinned_memory: usize,
+
+    /// Placement of data of objects (set by --data-storage)
+    data_storage: Box<DataStorage>,
+
+    /// Capabilities announced by the server at registration
+    server_capabilities: Capabilities,
+
+    /// Installation of the worker announced to the server (set by --image)
+    image: String,
+
+    /// Labels matched by placement constraints of tasks (set by --label)
+    labels: Labels,
+
+    /// Fault injection (only with feature "chaos")
+    chaos: Chaos,
 
     self_ref: Option<StateRef>,
 }
@@ -110,6 +235,32 @@ impl State {
         &self.work_dir
     }
 
+    /// Capabilities announced to the server: built-in tasks, configured subworkers,
+    /// supported data types and transfer features
+    pub fn capabilities(&self) -> Capabilities {
+        let mut capabilities = Capabilities::new();
+        for &(task_type, _) in BUILTIN_TASKS {
+            capabilities.insert_task(task_type);
+        }
+        for subworker_type in self.subworker_kinds.keys() {
+            capabilities.insert_subworker(subworker_type);
+        }
+        capabilities.insert(capabilities::TRANSFER_INLINE);
+        capabilities.insert(capabilities::DATA_DIRECTORY);
+        capabilities.insert(capabilities::RUN_SCRIPT);
+        capabilities
+    }
+
+    #[inline]
+    pub fn chaos_mut(&mut self) -> &mut Chaos {
+        &mut self.chaos
+    }
+
+    #[inline]
+    pub fn data_storage(&self) -> &DataStorage {
+        &*self.data_storage
+    }
+
     #[inline]
     pub fn handle(&self) -> &Handle {
         &self.handle
@@ -121,8 +272,45 @@ impl State {
     }
 
     #[inline]
-    pub fn timer(&self) -> &tokio_timer::Timer {
-        &self.timer
+    /// Future resolved after the given time, with the precision of the timer tick
+    pub fn sleep(&mut self, delay: Duration) -> Box<Future<Item = (), Error = Error>> {
+        self.timers.sleep(delay)
+    }
+
+    /// Cancel the running task when it does not finish within the timeout
+    pub fn add_task_timeout(&mut self, task_id: TaskId, timeout: Duration) {
+        self.timers
+            .add_after(timeout, TimerEvent::TaskTimeout(task_id, timeout));
+    }
+
+    #[inline]
+    pub fn log_dir(&self) -> &LogDir {
+        &self.log_dir
+    }
+
+    #[inline]
+    pub fn task_output_limit(&self) -> Option<u64> {
+        self.task_output_limit
+    }
+
+    #[inline]
+    pub fn download_cache_mut(&mut self) -> &mut DownloadCache {
+        &mut self.download_cache
+    }
+
+    #[inline]
+    pub fn task_executor(&self) -> &LimitedExecutorRef {
+        &self.task_executor
+    }
+
+    #[inline]
+    pub fn fetch_executor(&self) -> &LimitedExecutorRef {
+        &self.fetch_executor
+    }
+
+    #[inline]
+    pub fn blocking_pool(&self) -> &CpuPool {
+        &self.blocking_pool
     }
 
     pub fn plan_scheduling(&mut self) {
@@ -176,6 +364,22 @@ impl State {
         }
     }
 
+    /// Compute the checksum of a finished output that requests it (attribute
+    /// "compute_checksum", outputs of sampled tasks), it is reported to the server
+    /// in attribute "checksum"
+    pub fn compute_checksum(&self, dataobj: &DataObjectRef) -> Result<()> {
+        let mut o = dataobj.get_mut();
+        if !o.attributes
+            .find::<bool>("compute_checksum")?
+            .unwrap_or(false)
+        {
+            return Ok(());
+        }
+        let mut stream = new_pack_stream(self, o.data().clone())?;
+        let checksum = pack_stream_checksum(&mut *stream);
+        o.new_attributes.set("checksum", checksum)
+    }
+
     pub fn object_is_finished(&mut self, dataobj: &DataObjectRef) {
         let mut dataobject = dataobj.get_mut();
         if dataobject.is_removed() {
@@ -185,6 +389,14 @@ impl State {
         debug!("Object id={} is finished", dataobject.id);
         self.updated_objects.insert(dataobj.clone());
 
+        if dataobject.is_pin_requested() {
+            self.pin_in_memory(&mut dataobject);
+        }
+
+        if dataobject.assigned {
+            self.record_stored_object(&dataobject);
+        }
+
         let mut new_ready = false;
         for task in &dataobject.consumers {
             if task.get_mut().input_finished(dataobj) {
@@ -202,6 +414,11 @@ impl State {
 
     /// Send status of updated elements (updated_tasks/updated_objects) and then clear this sets
     pub fn send_update(&mut self) {
+        if self.chaos.drop_message() {
+            // Updates stay pending, so they are sent with the next update
+            warn!("Fault injection: update to server dropped");
+            return;
+        }
         debug!(
             "Sending update objs={}, tasks={}",
             self.updated_objects.len(),
@@ -209,6 +426,7 @@ impl State {
         );
 
         let mut req = self.upstream.as_ref().unwrap().update_states_request();
+        let inline_threshold = self.inline_threshold;
 
         {
             // Data Objects
@@ -221,7 +439,18 @@ impl State {
 
                 if object.is_finished() {
                     co.set_state(::common_capnp::DataObjectState::Finished);
-                    co.set_size(object.data().size() as u64);
+                    let size = object.data().size();
+                    co.set_size(size as u64);
+                    if object.assigned && size <= inline_threshold {
+                        match self.data_storage.get(object.data()) {
+                            Ok(Some(bytes)) => {
+                                co.set_inlined(true);
+                                co.set_inline_data(&bytes);
+                            }
+                            Ok(None) => { /* Directory on fs is not inlined */ }
+                            Err(e) => warn!("Cannot read object {} for inlining: {}", object.id, e),
+                        }
+                    }
                 } else {
                     // TODO: Handle failure state
                     panic!("Updating non finished object");
@@ -275,31 +504,92 @@ impl State {
         }
     }
 
+    /// NUMA node for a new subworker: the node preferred by the task or the node
+    /// with the least subworkers; None when NUMA awareness is disabled
+    fn subworker_numa_node(&self, preferred: Option<u32>) -> Option<u32> {
+        self.numa.as_ref().map(|numa| {
+            preferred.unwrap_or_else(|| {
+                let mut counts = HashMap::new();
+                let nodes = self.graph
+                    .subworkers
+                    .values()
+                    .filter_map(|sw| sw.get().numa_node())
+                    .chain(
+                        self.initializing_subworkers
+                            .iter()
+                            .filter_map(|&(_, _, _, _, _, _, node)| node),
+                    );
+                for node in nodes {
+                    *counts.entry(node).or_insert(0) += 1;
+                }
+                numa.least_loaded_node(&counts)
+            })
+        })
+    }
+
+    /// Get an idle subworker or start a new one. With NUMA awareness, an idle subworker
+    /// bound to `numa_node` (node holding inputs of the task) is preferred.
     pub fn get_subworker(
         &mut self,
         subworker_type: &str,
+        limits: &ProcessLimits,
+        numa_node: Option<u32>,
     ) -> Result<Box<Future<Item = SubworkerRef, Error = Error>>> {
         use tokio_process::CommandExt;
-        let sw_result = self.graph
-            .idle_subworkers
-            .iter()
-            .find(|sw| sw.get().subworker_type() == subworker_type)
-            .cloned();
+        let sw_result = {
+            let mut candidates = self.graph.idle_subworkers.iter().filter(|sw| {
+                let sw = sw.get();
+                sw.subworker_type() == subworker_type && sw.limits() == limits
+            });
+            if numa_node.is_some() && self.numa.is_some() {
+                candidates.find(|sw| sw.get().numa_node() == numa_node).cloned()
+            } else {
+                candidates.next().cloned()
+            }
+        };
         match sw_result {
             None => {
                 let subworker_id = self.graph.make_id();
-                if let Some(args) = self.subworker_args.get(subworker_type) {
+                if let Some(kind) = self.subworker_kinds.get(subworker_type).cloned() {
+                    let args = &kind.command;
                     let (ready_sender, ready_receiver) = ::futures::unsync::oneshot::channel();
                     let (kill_sender, kill_receiver) = ::futures::unsync::oneshot::channel();
-                    let program_name = &args[0];
-                    let (mut command, subworker_dir) = subworker_command(
-                        &self.work_dir,
-                        &self.log_dir,
-                        subworker_id,
-                        subworker_type,
-                        program_name,
-                        &args[1..],
-                    )?;
+                    let numa_node = self.subworker_numa_node(numa_node);
+                    // Warm start is not used for limited and NUMA bound subworkers,
+                    // since limits and binding are set when the process is spawned
+                    let zygote = if self.warm_subworkers && kind.zygote && limits.is_empty()
+                        && numa_node.is_none()
+                    {
+                        Some(self.get_zygote(subworker_type, &kind)?)
+                    } else {
+                        None
+                    };
+                    let (program_name, program_args) = match numa_node {
+                        Some(node) => NumaTopology::bind_command(node, &args[0], &args[1..]),
+                        None => (args[0].clone(), args[1..].to_vec()),
+                    };
+                    let (subworker_dir, command, fork_paths) = if zygote.is_some() {
+                        let (subworker_dir, log_path_out, log_path_err) = subworker_paths(
+                            &self.work_dir,
+                            &self.log_dir,
+                            subworker_id,
+                            subworker_type,
+                        )?;
+                        let path = subworker_dir.path().to_path_buf();
+                        (subworker_dir, None, Some((path, log_path_out, log_path_err)))
+                    } else {
+                        let (command, subworker_dir) = subworker_command(
+                            &self.work_dir,
+                            &self.log_dir,
+                            subworker_id,
+                            subworker_type,
+                            &program_name,
+                            &program_args,
+                            &kind.env,
+                            limits,
+                        )?;
+                        (subworker_dir, Some(command), None)
+                    };
 
                     self.initializing_subworkers.push((
                         subworker_id,
@@ -307,33 +597,149 @@ impl State {
                         subworker_dir,
                         ready_sender,
                         kill_sender,
+                        limits.clone(),
+                        numa_node,
                     ));
 
-                    let command_future = command
-                        .status_async2(&self.handle)?
-                        .map_err(|e| e.into())
-                        .and_then(move |status| {
-                            error!(
-                                "Subworker {} terminated with exit code: {}",
-                                subworker_id, status
-                            );
-                            bail!("Subworker terminated; TODO handle this situation");
+                    // Process is started through subworker executor, so only limited
+                    // number of subworkers is initialized at once
+                    let state_ref = self.self_ref();
+                    let handle = self.handle.clone();
+                    let start = ::futures::future::lazy(move || -> Result<()> {
+                        let exit_future: Box<Future<Item = String, Error = Error>> = match zygote {
+                            Some(ref zygote) => {
+                                let (path, log_path_out, log_path_err) = fork_paths.unwrap();
+                                let zygote_state_ref = state_ref.clone();
+                                Box::new(
+                                    zygote
+                                        .get_mut()
+                                        .fork(subworker_id, &path, &log_path_out, &log_path_err)
+                                        .map(|code| format!("exit code: {}", code))
+                                        .or_else(move |e| {
+                                            // Exit of a registered subworker cannot be observed
+                                            // without zygote, it is handled by its connection
+                                            let initializing = zygote_state_ref
+                                                .get()
+                                                .initializing_subworkers
+                                                .iter()
+                                                .any(|&(id, _, _, _, _, _, _)| id == subworker_id);
+                                            if initializing {
+                                                Box::new(Ok(e.to_string()).into_future())
+                                                    as Box<Future<Item = String, Error = Error>>
+                                            } else {
+                                                Box::new(::futures::future::empty())
+                                            }
+                                        }),
+                                )
+                            }
+                            None => Box::new(
+                                command
+                                    .unwrap()
+                                    .status_async2(&handle)?
+                                    .map(|status| status.to_string())
+                                    .map_err(|e| e.into()),
+                            ),
+                        };
+                        let exit_state_ref = state_ref.clone();
+                        let command_future = exit_future.and_then(move |status| {
+                                error!(
+                                    "Subworker {} terminated with exit code: {}",
+                                    subworker_id, status
+                                );
+                                let mut state = exit_state_ref.get_mut();
+                                let index = state
+                                    .initializing_subworkers
+                                    .iter()
+                                    .position(|&(id, _, _, _, _, _, _)| id == subworker_id);
+                                if let Some(index) = index {
+                                    // Subworker died before registration, fail the waiting task
+                                    let (_, sw_type, _, ready_sender, _, _, _) =
+                                        state.initializing_subworkers.remove(index);
+                                    let stderr_path =
+                                        state.log_dir.subworker_log_paths(subworker_id).1;
+                                    let error = startup_failure(
+                                        subworker_id,
+                                        &sw_type,
+                                        &format!(
+                                            "terminated during initialization with exit code: {}",
+                                            status
+                                        ),
+                                        &stderr_path,
+                                    );
+                                    if ready_sender.send(Err(error.into())).is_err() {
+                                        debug!("Failed to inform about failed subworker");
+                                    }
+                                    return Ok(());
+                                }
+                                bail!("Subworker terminated; TODO handle this situation");
+                            });
+
+                        // We do not care how kill switch was activated, so receiving () or CancelError is ok
+                        // Spawned process is killed when its future is dropped,
+                        // forked subworker is killed by its zygote
+                        let kill_switch = kill_receiver.then(move |_| {
+                            if let Some(zygote) = zygote {
+                                zygote.get_mut().kill(subworker_id);
+                            }
+                            Ok(())
                         });
-
-                    // We do not care how kill switch was activated, so receiving () or CancelError is ok
-                    let kill_switch = kill_receiver.then(|_| Ok(()));
-                    self.spawn_panic_on_error(
-                        command_future
-                            .select(kill_switch)
-                            .map_err(|(e, _)| e)
-                            .map(|_| {
-                                // Process was terminated. We do not handle error here, since
-                                // it is handled when connection (not process) is terminated
-                                debug!("Subworker process terminated");
+                        {
+                            let mut state = state_ref.get_mut();
+                            let timeout = state.subworker_timeout;
+                            state.timers.add_after(
+                                timeout,
+                                TimerEvent::SubworkerStart(subworker_id, timeout),
+                            );
+                        }
+                        state_ref.get().spawn_panic_on_error(
+                            command_future
+                                .select(kill_switch)
+                                .map_err(|(e, _)| e)
+                                .map(|_| {
+                                    // Process was terminated. We do not handle error here, since
+                                    // it is handled when connection (not process) is terminated
+                                    debug!("Subworker process terminated");
+                                }),
+                        );
+                        Ok(())
+                    });
+
+                    let state_ref = self.self_ref();
+                    let idle_state_ref = state_ref.clone();
+                    let (result_sender, result_receiver) = ::futures::unsync::oneshot::channel();
+                    self.subworker_executor.spawn_limited(
+                        start
+                            .map_err(move |e| {
+                                // Process was not spawned at all
+                                state_ref
+                                    .get_mut()
+                                    .initializing_subworkers
+                                    .retain(|&(id, _, _, _, _, _, _)| id != subworker_id);
+                                e
+                            })
+                            .and_then(|()| {
+                                ready_receiver
+                                    .map_err(|_| "Subworker start cancelled".into())
+                                    .and_then(|r| r)
+                            })
+                            .then(move |r| {
+                                if let Err(r) = result_sender.send(r) {
+                                    debug!("Failed to inform about new subworker");
+                                    if let Ok(subworker) = r {
+                                        idle_state_ref
+                                            .get_mut()
+                                            .graph
+                                            .idle_subworkers
+                                            .insert(subworker);
+                                    }
+                                }
+                                Ok(())
                             }),
                     );
                     Ok(Box::new(
-                        ready_receiver.map_err(|_| "Subwork start cancelled".into()),
+                        result_receiver
+                            .map_err(|_| "Subworker start cancelled".into())
+                            .and_then(|r| r),
                     ))
                 } else {
                     bail!("Unknown subworker")
@@ -346,6 +752,185 @@ impl State {
         }
     }
 
+    /// Fail the start of the subworker if it is still not registered after the timeout;
+    /// the process is killed and the waiting task gets diagnostics from its stderr
+    fn subworker_start_timeout(&mut self, subworker_id: SubworkerId, timeout: Duration) {
+        let index = match self.initializing_subworkers
+            .iter()
+            .position(|&(id, _, _, _, _, _, _)| id == subworker_id)
+        {
+            Some(index) => index,
+            None => return,
+        };
+        let (_, sw_type, _, ready_sender, kill_sender, _, _) =
+            self.initializing_subworkers.remove(index);
+        let stderr_path = self.log_dir.subworker_log_paths(subworker_id).1;
+        let error = startup_failure(
+            subworker_id,
+            &sw_type,
+            &format!("did not register within {}s", timeout.as_secs()),
+            &stderr_path,
+        );
+        warn!("{}", error);
+        let _ = kill_sender.send(());
+        if ready_sender.send(Err(error.into())).is_err() {
+            debug!("Failed to inform about failed subworker");
+        }
+    }
+
+    /// Handle timers that expired since the last tick; periodic actions are added again
+    fn expire_timers(&mut self) {
+        let now = Instant::now();
+        for event in self.timers.expired(now) {
+            match event {
+                TimerEvent::Wakeup(sender) => {
+                    // The receiver is dropped when the waiting task was stopped
+                    let _ = sender.send(());
+                }
+                TimerEvent::SubworkerStart(subworker_id, timeout) => {
+                    self.subworker_start_timeout(subworker_id, timeout)
+                }
+                TimerEvent::TaskTimeout(task_id, timeout) => {
+                    // The task may have finished, or it runs again and its new timer is set
+                    if let Some(instance) = self.graph.running_tasks.get_mut(&task_id) {
+                        if instance.running_time() >= timeout {
+                            warn!("Task {} timed out", task_id);
+                            instance.time_out(timeout);
+                        }
+                    }
+                }
+                TimerEvent::DeleteObject(id) => {
+                    let object_ref = match self.graph.objects.get(&id) {
+                        Some(o) => o.clone(),
+                        None => continue,
+                    };
+                    // The object may have been retaken or its deletion postponed
+                    let expired = self.graph
+                        .delete_wait_list
+                        .get(&object_ref)
+                        .map(|deadline| *deadline <= now)
+                        .unwrap_or(false);
+                    if expired {
+                        debug!("Object id={} removed from the delete wait list", id);
+                        self.graph.delete_wait_list.remove(&object_ref);
+                        self.remove_object(&mut object_ref.get_mut());
+                    }
+                }
+                TimerEvent::Monitoring => {
+                    self.send_monitoring_event();
+                    self.timers.add(
+                        now + Duration::from_secs(MONITORING_INTERVAL),
+                        TimerEvent::Monitoring,
+                    );
+                }
+                TimerEvent::LogShipping => {
+                    // Records are kept until the worker is registered
+                    if self.upstream.is_some() {
+                        self.ship_logs();
+                    }
+                    self.timers.add(
+                        now + Duration::from_secs(LOG_SHIPPING_INTERVAL),
+                        TimerEvent::LogShipping,
+                    );
+                }
+                TimerEvent::DrainCheck => {
+                    if TERMINATION_REQUESTED.load(Ordering::SeqCst) {
+                        self.check_drain();
+                    }
+                    self.timers.add(
+                        now + Duration::from_secs(DRAIN_CHECK_INTERVAL),
+                        TimerEvent::DrainCheck,
+                    );
+                }
+            }
+        }
+    }
+
+    fn send_monitoring_event(&mut self) {
+        debug!("Monitoring wakeup");
+        let worker_id = self.worker_id;
+
+        // Check that we already know our address
+        if worker_id.ip().is_unspecified() {
+            debug!("Monitoring skipped, registration is not completed yet");
+            return;
+        }
+
+        let event = self.monitor.build_event(&worker_id);
+        self.send_event(event);
+    }
+
+    /// How subworkers of the type are started, None when the type is not configured
+    #[inline]
+    pub fn subworker_kind(&self, subworker_type: &str) -> Option<&SubworkerKind> {
+        self.subworker_kinds.get(subworker_type)
+    }
+
+    /// Memory of the worker available for tasks, announced to the server at registration
+    pub fn set_memory(&mut self, memory: u64) {
+        self.resources.memory = memory;
+        self.free_resources.memory = memory;
+    }
+
+    /// GPUs of the worker (their device numbers) available for tasks,
+    /// announced to the server at registration
+    pub fn set_gpus(&mut self, devices: Vec<u32>) {
+        self.resources.gpus = devices.len() as u32;
+        self.free_resources.gpus = devices.len() as u32;
+        self.free_gpus = devices;
+    }
+
+    /// Counted resources declared for the worker (e.g. "fpga" = 1),
+    /// announced to the server at registration
+    pub fn set_named_resources(&mut self, named: HashMap<String, u32>) {
+        self.resources.named = named.clone();
+        self.free_resources.named = named;
+    }
+
+    /// Timeout of registration of started subworkers
+    pub fn set_subworker_timeout(&mut self, timeout: Duration) {
+        self.subworker_timeout = timeout;
+    }
+
+    /// Size cap of captured stdout/stderr of programs, stricter caps of sessions apply
+    pub fn set_task_output_limit(&mut self, limit: Option<u64>) {
+        self.task_output_limit = limit;
+    }
+
+    /// Capacity of the cache of downloads in bytes, 0 disables it
+    pub fn set_download_cache(&mut self, capacity: u64) {
+        self.download_cache.set_capacity(capacity);
+    }
+
+    pub fn set_rejoin(&mut self, rejoin: bool) {
+        self.rejoin = rejoin;
+    }
+
+    pub fn set_labels(&mut self, labels: Labels) {
+        self.labels = labels;
+    }
+
+    /// Get the zygote of the subworker type, start it if it is not running
+    fn get_zygote(&mut self, subworker_type: &str, kind: &SubworkerKind) -> Result<ZygoteRef> {
+        if let Some(zygote) = self.zygotes.get(subworker_type) {
+            if zygote.get().is_alive() {
+                return Ok(zygote.clone());
+            }
+        }
+        let zygote = ZygoteRef::start(
+            &self.handle,
+            &self.work_dir,
+            &self.log_dir,
+            subworker_type,
+            &kind.command[0],
+            &kind.command[1..],
+            &kind.env,
+        )?;
+        self.zygotes
+            .insert(subworker_type.to_string(), zygote.clone());
+        Ok(zygote)
+    }
+
     /// This method is called when subworker is connected & registered
     pub fn add_subworker(
         &mut self,
@@ -355,29 +940,41 @@ impl State {
     ) -> Result<()> {
         let index = self.initializing_subworkers
             .iter()
-            .position(|&(id, _, _, _, _)| id == subworker_id)
+            .position(|&(id, _, _, _, _, _, _)| id == subworker_id)
             .ok_or("Subworker registered under unexpected id")?;
 
         info!("Subworker registered (subworker_id={})", subworker_id);
 
-        let (_, sw_type, work_dir, ready_sender, kill_sender) =
+        let (_, sw_type, work_dir, ready_sender, kill_sender, limits, numa_node) =
             self.initializing_subworkers.remove(index);
 
         if sw_type != subworker_type {
-            bail!("Unexpected type of worker registered");
+            bail!(
+                "Subworker {} registered as type '{}', it was started as '{}'",
+                subworker_id,
+                subworker_type,
+                sw_type
+            );
         }
 
-        let subworker =
-            SubworkerRef::new(subworker_id, subworker_type, control, work_dir, kill_sender);
+        let subworker = SubworkerRef::new(
+            subworker_id,
+            subworker_type,
+            control,
+            work_dir,
+            kill_sender,
+            limits,
+            numa_node,
+        );
 
         let r = self.graph
             .subworkers
             .insert(subworker_id, subworker.clone());
         assert!(r.is_none());
 
-        if let Err(subworker) = ready_sender.send(subworker) {
+        if let Err(r) = ready_sender.send(Ok(subworker)) {
             debug!("Failed to inform about new subworker");
-            self.graph.idle_subworkers.insert(subworker);
+            self.graph.idle_subworkers.insert(r.unwrap());
         }
         Ok(())
     }
@@ -387,6 +984,67 @@ impl State {
         self.datastores.get(worker_id).unwrap().get()
     }
 
+    /// Drive draining of a preemptible worker after SIGTERM: ask server to drain
+    /// the worker and leave when the server confirms that nothing would be lost
+    /// or when the grace period expires
+    fn check_drain(&mut self) {
+        if self.upstream.is_none() {
+            // Not registered yet, there is nothing to lose
+            self.leave();
+        }
+        let now = Instant::now();
+        let deadline = match self.drain_deadline {
+            Some(deadline) => deadline,
+            None => {
+                let grace = self.drain_grace.unwrap();
+                info!("Termination requested, draining (grace period {:?})", grace);
+                self.drain_deadline = Some(now + grace);
+                let req = self.upstream.as_ref().unwrap().drain_request();
+                self.spawn_panic_on_error(req.send().promise.map(|_| ()).map_err(|e| e.into()));
+                return;
+            }
+        };
+        if now >= deadline {
+            warn!("Grace period expired, leaving before the draining is finished");
+            self.leave();
+        }
+        let req = self.upstream.as_ref().unwrap().drain_status_request();
+        let state_ref = self.self_ref.clone().unwrap();
+        self.spawn_panic_on_error(req.send().promise.map_err(Error::from).and_then(
+            move |response| -> Result<()> {
+                if response.get()?.get_done() {
+                    info!("Draining finished");
+                    state_ref.get_mut().leave();
+                }
+                Ok(())
+            },
+        ));
+    }
+
+    /// Terminate the worker, the index of stored objects is saved first.
+    /// A worker of a broker is started again with the same command line (the broker
+    /// is asked for a server again); the new program does not know anything of this run,
+    /// so subworkers (and zygotes) are killed and the working directory is cleared first.
+    pub fn leave(&mut self) -> ! {
+        if self.rejoin {
+            info!("Worker rejoins the pool of its broker");
+            ::common::sys::kill_descendants();
+            if let Err(e) = self.work_dir.clear() {
+                error!("Cannot clear the working directory: {}", e);
+            }
+            let mut args = ::std::env::args_os();
+            let program = args.next().unwrap();
+            let error = Command::new(program).args(args).exec();
+            error!("Cannot start the worker again: {}", error);
+            exit(1);
+        }
+        if let Err(e) = self.store.flush() {
+            error!("Cannot write index of stored objects: {}", e);
+        }
+        info!("Worker terminated");
+        exit(0);
+    }
+
     pub fn spawn_panic_on_error<F>(&self, f: F)
     where
         F: Future<Item = (), Error = Error> + 'static,
@@ -395,6 +1053,15 @@ impl State {
             .spawn(f.map_err(|e| panic!("Future failed {:?}", e.description())));
     }
 
+    /// Same as `spawn_panic_on_error`, but the future is started through
+    /// a bounded executor, i.e. it may wait in queue until a slot is free
+    pub fn spawn_limited<F>(&self, executor: &LimitedExecutorRef, f: F)
+    where
+        F: Future<Item = (), Error = Error> + 'static,
+    {
+        executor.spawn_limited(f.map_err(|e| panic!("Future failed {:?}", e.description())));
+    }
+
     pub fn add_dataobject(
         &mut self,
         id: DataObjectId,
@@ -417,11 +1084,39 @@ impl State {
         )
     }
 
-    /// n_redirects is a protection against ifinite loop of redirections
+    /// Fetch the object from the datastore of the worker (or of the server)
     pub fn fetch_from_datastore(
         &mut self,
         worker_id: &WorkerId,
         dataobj_id: DataObjectId,
+    ) -> Box<Future<Item = Data, Error = Error>> {
+        if self.chaos.fail_fetch() {
+            let message = format!("Fault injection: fetch of object {} failed", dataobj_id);
+            warn!("{}", message);
+            return Box::new(Err(Error::from(message)).into_future());
+        }
+        let delay = self.chaos.transfer_delay();
+        if delay > 0 {
+            let state_ref = self.self_ref();
+            let worker_id = worker_id.clone();
+            return Box::new(
+                self.timers
+                    .sleep(Duration::from_millis(delay))
+                    .and_then(move |()| {
+                        state_ref
+                            .get_mut()
+                            .fetch_from_datastore_with_redirects(&worker_id, dataobj_id, 0)
+                    }),
+            );
+        }
+        self.fetch_from_datastore_with_redirects(worker_id, dataobj_id, 0)
+    }
+
+    /// n_redirects is a protection against ifinite loop of redirections
+    fn fetch_from_datastore_with_redirects(
+        &mut self,
+        worker_id: &WorkerId,
+        dataobj_id: DataObjectId,
         n_redirects: i32,
     ) -> Box<Future<Item = Data, Error = Error>> {
         if n_redirects > 32 {
@@ -456,7 +1151,7 @@ impl State {
                             } else {
                                 Some(size as usize)
                             };
-                            let builder = DataBuilder::new(
+                            let builder = state.data_storage.builder(
                                 &state.work_dir,
                                 DataType::from_capnp(response.get_data_type().unwrap()),
                                 size,
@@ -471,14 +1166,22 @@ impl State {
                                 "Datastore redirection; id={}, worker={}",
                                 dataobj_id, worker_id
                             );
-                            state.fetch_from_datastore(&worker_id, dataobj_id, n_redirects + 1)
+                            state.fetch_from_datastore_with_redirects(
+                                &worker_id,
+                                dataobj_id,
+                                n_redirects + 1,
+                            )
                         }
                         ::datastore_capnp::reader_response::Which::NotHere(()) => {
                             assert!(!is_server);
                             debug!("Datastore redirection to server; id={}", dataobj_id);
                             // Ask for server for placing of data object
                             let worker_id = empty_worker_id();
-                            state.fetch_from_datastore(&worker_id, dataobj_id, n_redirects + 1)
+                            state.fetch_from_datastore_with_redirects(
+                                &worker_id,
+                                dataobj_id,
+                                n_redirects + 1,
+                            )
                         }
                         ::datastore_capnp::reader_response::Which::Ignored(()) => {
                             assert!(is_server);
@@ -491,8 +1194,138 @@ impl State {
         }))
     }
 
+    /// Record finished object into index of stored objects, if its data are on filesystem
+    fn record_stored_object(&mut self, object: &DataObject) {
+        if let Storage::Path(ref data) = *object.data().storage() {
+            self.store.insert(StoredObject {
+                id: object.id,
+                path: data.path.clone(),
+                size: data.size,
+                data_type: object.data_type,
+                label: object.label.clone(),
+                attributes: object.attributes.clone(),
+            });
+        }
+    }
+
+    /// Offer objects stored by previous run of worker to the server,
+    /// objects that are not accepted by server are deleted.
+    /// Objects stored for another instance of the server are deleted without offering.
+    fn restore_stored_objects(&mut self, server_instance: &str) {
+        let discarded = self.store.set_server_instance(server_instance);
+        if !discarded.is_empty() {
+            info!(
+                "Deleting {} stored object(s) of another server instance",
+                discarded.len()
+            );
+            for stored in discarded {
+                if stored.path.exists() {
+                    // Data are deleted when dropped
+                    Data::new_from_path(stored.path, stored.size, stored.data_type);
+                }
+            }
+        }
+        if let Err(e) = self.store.flush() {
+            error!("Cannot write index of stored objects: {}", e);
+        }
+
+        let objects = self.store.sizes();
+        if objects.is_empty() {
+            return;
+        }
+        info!("Offering {} stored object(s) to server", objects.len());
+        let mut req = self.upstream.as_ref().unwrap().restore_objects_request();
+        {
+            let mut req_objects = req.get().init_objects(objects.len() as u32);
+            for (i, &(ref id, size)) in objects.iter().enumerate() {
+                let mut object = req_objects.borrow().get(i as u32);
+                id.to_capnp(&mut object.borrow().init_id());
+                object.set_size(size as u64);
+            }
+        }
+        let state_ref = self.self_ref();
+        self.spawn_panic_on_error(req.send().promise.map_err::<_, Error>(|e| e.into()).and_then(
+            move |response| -> Result<()> {
+                let accepted: HashSet<DataObjectId> = response
+                    .get()?
+                    .get_accepted()?
+                    .iter()
+                    .map(|id| DataObjectId::from_capnp(&id))
+                    .collect();
+                let mut state = state_ref.get_mut();
+                for (id, _) in objects {
+                    let stored = match state.store.take(&id) {
+                        Some(stored) => stored,
+                        None => continue,
+                    };
+                    if !stored.path.exists() {
+                        warn!("Data of stored object id={} are missing", id);
+                        continue;
+                    }
+                    let data = Data::new_from_path(stored.path, stored.size, stored.data_type);
+                    if !accepted.contains(&id) {
+                        // Data are deleted when dropped
+                        debug!("Stored object id={} was not accepted", id);
+                        continue;
+                    }
+                    debug!("Stored object id={} restored", id);
+                    let object_ref = state.add_dataobject(
+                        id,
+                        DataObjectState::Finished(Arc::new(data)),
+                        true,
+                        Some(stored.size),
+                        stored.label,
+                        stored.data_type,
+                        stored.attributes,
+                    );
+                    state.record_stored_object(&object_ref.get());
+                }
+                Ok(())
+            },
+        ));
+    }
+
+    /// Keep data of the object in memory (objects with attribute "pin_in_memory").
+    /// Pinned data are never written to the file system and they are counted
+    /// against the pinned memory limit; the object is not pinned when the limit is reached.
+    fn pin_in_memory(&mut self, object: &mut DataObject) {
+        let size = object.data().size();
+        if self.pinned_memory + size > self.pin_memory_limit {
+            warn!(
+                "Object id={} is not pinned in memory, limit of pinned memory reached ({} of {} bytes used)",
+                object.id, self.pinned_memory, self.pin_memory_limit
+            );
+            return;
+        }
+        let data = match *object.data().storage() {
+            Storage::Memory(_) => None,
+            Storage::Path(_) => match object.data().read_bytes() {
+                Ok(Some(bytes)) => Some(Data::new(Storage::Memory(bytes), object.data_type)),
+                Ok(None) => {
+                    warn!("Directory object id={} cannot be pinned in memory", object.id);
+                    return;
+                }
+                Err(e) => {
+                    warn!("Object id={} cannot be loaded into memory: {}", object.id, e);
+                    return;
+                }
+            },
+        };
+        if let Some(data) = data {
+            // The file is removed when the previous data are dropped
+            object.state = DataObjectState::Finished(Arc::new(data));
+        }
+        debug!("Object id={} pinned in memory ({} bytes)", object.id, size);
+        self.pinned_memory += size;
+        object.pinned_size = Some(size);
+    }
+
     pub fn remove_object(&mut self, object: &mut DataObject) {
         debug!("Removing object {}", object.id);
+        if let Some(size) = object.pinned_size.take() {
+            self.pinned_memory -= size;
+        }
+        self.store.remove(&object.id);
         for sw in ::std::mem::replace(&mut object.subworker_cache, Default::default()) {
             let mut req = sw.get().control().remove_cached_objects_request();
             {
@@ -506,6 +1339,25 @@ impl State {
         self.graph.objects.remove(&object.id);
     }
 
+    /// Unassign the object and remove it now if no task uses it (server eviction).
+    /// Returns the freed size, None when the object is still used by a task.
+    pub fn delete_object(&mut self, id: DataObjectId) -> Option<usize> {
+        let object_ref = match self.graph.objects.get(&id) {
+            Some(o) => o.clone(),
+            None => return Some(0),
+        };
+        let mut object = object_ref.get_mut();
+        object.assigned = false;
+        if !object.consumers.is_empty() {
+            debug!("Object id={} is deleted after its consumers finish", id);
+            return None;
+        }
+        self.graph.delete_wait_list.remove(&object_ref);
+        let size = object.size.unwrap_or(0);
+        self.remove_object(&mut object);
+        Some(size)
+    }
+
     // Call when object may be waiting for delete, but now is needed again
     pub fn mark_as_needed(&mut self, object_ref: &DataObjectRef) {
         if self.graph.delete_wait_list.remove(&object_ref).is_some() {
@@ -530,6 +1382,7 @@ impl State {
                 let object_ref = self.graph.objects.get(&object.id).unwrap().clone();
                 let r = self.graph.delete_wait_list.insert(object_ref, timeout);
                 assert!(r.is_none()); // it should not be in delete list
+                self.timers.add(timeout, TimerEvent::DeleteObject(object.id));
             }
         }
     }
@@ -580,30 +1433,66 @@ impl State {
         self.unregister_task(&task_ref);
     }
 
+    /// Remove task from worker if it has not started yet, returns `false` (and changes
+    /// nothing) when the task is running, finished or unknown
+    pub fn pull_back_task(&mut self, task_id: &TaskId) -> bool {
+        let task_ref = match self.graph.tasks.get(task_id) {
+            Some(task_ref) => task_ref.clone(),
+            None => return false,
+        };
+        if task_ref.get().state != TaskState::Assigned
+            || self.graph.running_tasks.contains_key(task_id)
+        {
+            return false;
+        }
+        debug!("Pulling back task {}", task_id);
+        if let Some(p) = self.graph.ready_tasks.iter().position(|t| t == &task_ref) {
+            self.graph.ready_tasks.remove(p);
+        }
+        self.unregister_task(&task_ref);
+        true
+    }
+
     #[inline]
     pub fn task_updated(&mut self, task: &TaskRef) {
         self.updated_tasks.insert(task.clone());
     }
 
-    pub fn alloc_resources(&mut self, resources: &Resources) {
+    /// Returns devices of GPUs allocated for the task,
+    /// None when the worker has no GPUs (visible devices of the task are not restricted)
+    pub fn alloc_resources(&mut self, resources: &Resources) -> Option<Vec<u32>> {
         self.free_resources.remove(resources);
         assert!(self.free_slots > 0);
         self.free_slots -= 1;
+        let gpus = if self.resources.gpus() > 0 {
+            let n = resources.gpus() as usize;
+            Some(self.free_gpus.drain(..n).collect())
+        } else {
+            None
+        };
         debug!(
-            "{} cpus allocated, free now: {}",
+            "{} cpus, {} bytes and gpus {:?} allocated, free now: {} cpus and {} bytes",
             resources.cpus(),
-            self.free_resources.cpus()
+            resources.memory(),
+            gpus,
+            self.free_resources.cpus(),
+            self.free_resources.memory()
         );
+        gpus
     }
 
-    pub fn free_resources(&mut self, resources: &Resources) {
+    pub fn free_resources(&mut self, resources: &Resources, gpus: &[u32]) {
         self.free_resources.add(resources);
+        self.free_gpus.extend_from_slice(gpus);
         self.free_slots += 1;
         self.need_scheduling();
         debug!(
-            "{} cpus disposed, free now: {}",
+            "{} cpus, {} bytes and gpus {:?} disposed, free now: {} cpus and {} bytes",
             resources.cpus(),
-            self.free_resources.cpus()
+            resources.memory(),
+            gpus,
+            self.free_resources.cpus(),
+            self.free_resources.memory()
         );
     }
 
@@ -617,10 +1506,12 @@ impl State {
             if self.free_slots == 0 {
                 break;
             }
-            let n_cpus = self.free_resources.cpus;
-            let j = self.graph.ready_tasks[i..]
-                .iter()
-                .position(|task| n_cpus >= task.get().resources.cpus);
+            let j = {
+                let free = &self.free_resources;
+                self.graph.ready_tasks[i..]
+                    .iter()
+                    .position(|task| task.get().resources.is_subset_of(free))
+            };
             if j.is_none() {
                 break;
             }
@@ -645,6 +1536,22 @@ impl State {
         let state = self.self_ref();
         let worker_id = worker_id.clone();
 
+        // When the datastore cannot be obtained, all waiters are failed and the wrapper
+        // is removed, so the next request tries to obtain the datastore again
+        let fail_state = state.clone();
+        let fail_worker_id = worker_id.clone();
+        let fail = move |e: Error| -> Error {
+            let error = Error::with_chain(
+                e,
+                format!("Cannot obtain datastore of {}", fail_worker_id),
+            );
+            let wrapper = fail_state.get_mut().datastores.remove(&fail_worker_id);
+            match wrapper {
+                Some(wrapper) => wrapper.fail(error),
+                None => error,
+            }
+        };
+
         if worker_id.ip().is_unspecified() {
             // Data are on server
             let req = self.upstream.as_ref().unwrap().get_data_store_request();
@@ -658,7 +1565,7 @@ impl State {
                         let wrapper = inner.datastores.get_mut(&worker_id).unwrap();
                         wrapper.set_value(datastore);
                     })
-                    .map_err(|e| e.into()),
+                    .map_err(move |e| fail(e.into())),
             )
         } else {
             Box::new(
@@ -675,7 +1582,7 @@ impl State {
                         }
                         s.spawn_panic_on_error(rpc_system.map_err(|e| e.into()));
                     })
-                    .map_err(|e| e.into()),
+                    .map_err(move |e| fail(e.into())),
             )
         }
     }
@@ -684,59 +1591,192 @@ impl State {
         &mut self.monitor
     }
 
+    /// Data of a finished object held by the worker
+    pub fn object_data(&self, id: DataObjectId) -> Option<Arc<Data>> {
+        self.graph
+            .objects
+            .get(&id)
+            .map(|o| o.get())
+            .filter(|o| o.is_finished())
+            .map(|o| o.data().clone())
+    }
+
+    /// Current time corrected to the server clock
+    pub fn now(&self) -> ::chrono::DateTime<::chrono::Utc> {
+        self.to_server_time(::chrono::Utc::now())
+    }
+
+    /// Convert a time of the local clock to the server clock
+    pub fn to_server_time(
+        &self,
+        time: ::chrono::DateTime<::chrono::Utc>,
+    ) -> ::chrono::DateTime<::chrono::Utc> {
+        time + self.clock_offset
+    }
+
     /// Send event to server
     pub fn send_event(&mut self, event: events::Event) {
-        debug!("Sending event to server");
-        let now = ::chrono::Utc::now();
+        let now = self.now();
+        self.send_events(vec![(event, now)]);
+    }
+
+    /// Send events with their times to server in a single message
+    pub fn send_events(&mut self, events: Vec<(events::Event, ::chrono::DateTime<::chrono::Utc>)>) {
+        debug!("Sending {} event(s) to server", events.len());
         let mut req = self.upstream.as_ref().unwrap().push_events_request();
         {
-            let mut req_events = req.get().init_events(1);
-            let mut capnp_event = req_events.borrow().get(0);
-            capnp_event.set_event(&::serde_json::to_string(&event).unwrap());
-            let mut capnp_ts = capnp_event.init_timestamp();
-            capnp_ts.set_seconds(now.timestamp() as u64);
-            capnp_ts.set_subsec_nanos(now.timestamp_subsec_nanos() as u32);
+            let mut req_events = req.get().init_events(events.len() as u32);
+            for (i, &(ref event, ref time)) in events.iter().enumerate() {
+                event.to_capnp(time, &mut req_events.borrow().get(i as u32));
+            }
         }
         self.spawn_panic_on_error(req.send().promise.map(|_| ()).map_err(|e| e.into()));
     }
 
+    /// Send warnings and errors logged since the last call (see `--ship-logs`)
+    fn ship_logs(&mut self) {
+        let (records, dropped) = shipping::take_records();
+        let worker = self.worker_id;
+        let mut events: Vec<_> = records
+            .into_iter()
+            .map(|record| {
+                let event = events::Event::WorkerLog(events::WorkerLogEvent {
+                    worker,
+                    level: record.level.to_string(),
+                    target: record.target,
+                    message: record.message,
+                });
+                (event, self.to_server_time(record.time))
+            })
+            .collect();
+        if dropped > 0 {
+            let event = events::Event::WorkerLog(events::WorkerLogEvent {
+                worker,
+                level: "WARN".to_string(),
+                target: "rain".to_string(),
+                message: format!("{} log record(s) dropped", dropped),
+            });
+            events.push((event, self.now()));
+        }
+        if !events.is_empty() {
+            self.send_events(events);
+        }
+    }
+
     #[inline]
     pub fn self_ref(&self) -> StateRef {
         self.self_ref.as_ref().unwrap().clone()
     }
 }
 
+/// Offset of the server clock against the local clock; the server time is
+/// expected in the middle of the round trip (measured by the monotonic clock,
+/// so it is not affected by adjustments of the local clock), the error is at most
+/// half of the round trip
+fn estimate_clock_offset(
+    sent_time: ::chrono::DateTime<::chrono::Utc>,
+    round_trip: Duration,
+    server_time: ::chrono::DateTime<::chrono::Utc>,
+) -> ::chrono::Duration {
+    let half_trip = ::chrono::Duration::from_std(round_trip / 2).unwrap();
+    let offset = server_time.signed_duration_since(sent_time + half_trip);
+    info!(
+        "Clock offset against the server is {} ms (± {} ms)",
+        offset.num_milliseconds(),
+        half_trip.num_milliseconds()
+    );
+    offset
+}
+
 impl StateRef {
     pub fn new(
         handle: Handle,
         work_dir: PathBuf,
         log_dir: PathBuf,
         n_cpus: u32,
-        subworkers: HashMap<String, Vec<String>>,
+        max_parallel_tasks: u32,
+        subworkers: HashMap<String, SubworkerKind>,
+        server_framing: Framing,
+        via_gateway: bool,
+        cost: f64,
+        drain_grace: Option<Duration>,
+        warm_subworkers: bool,
+        pin_memory_limit: usize,
+        task_tmpfs: Option<usize>,
+        numa: Option<NumaTopology>,
+        data_storage: Box<DataStorage>,
+        image: String,
     ) -> Self {
-        let resources = Resources { cpus: n_cpus };
+        assert!(max_parallel_tasks > 0);
+        let resources = Resources {
+            cpus: n_cpus,
+            ..Default::default()
+        };
+        let mut work_dir = WorkDir::new(work_dir);
+        if let Some(limit) = task_tmpfs {
+            work_dir.set_task_tmpfs(limit);
+        }
+
+        let download_cache =
+            DownloadCache::new(work_dir.downloads_path(), DEFAULT_DOWNLOAD_CACHE).unwrap();
+
+        let mut store = ObjectStore::new(work_dir.store_index_path());
+        if let Err(e) = store.load() {
+            error!("Cannot load index of stored objects: {}", e);
+        }
 
         let state = Self::wrap(State {
+            task_executor: LimitedExecutorRef::new(handle.clone(), max_parallel_tasks as usize),
+            fetch_executor: LimitedExecutorRef::new(handle.clone(), MAX_CONCURRENT_FETCHES),
+            subworker_executor: LimitedExecutorRef::new(handle.clone(), MAX_STARTING_SUBWORKERS),
+            blocking_pool: CpuPoolBuilder::new()
+                .pool_size(::std::cmp::max(n_cpus, 1) as usize)
+                .name_prefix("blocking-")
+                .create(),
             handle,
-            free_slots: 4 * n_cpus,
+            free_slots: max_parallel_tasks,
             resources: resources.clone(),
             free_resources: resources,
+            free_gpus: Vec::new(),
+            server_framing,
+            via_gateway,
+            cost,
+            drain_grace,
+            drain_deadline: None,
+            inline_threshold: 0,
+            clock_offset: ::chrono::Duration::zero(),
             upstream: None,
             datastores: HashMap::new(),
             updated_objects: Default::default(),
             updated_tasks: Default::default(),
             timer: tokio_timer::wheel()
-                .tick_duration(Duration::from_millis(100))
+                .tick_duration(Duration::from_millis(TIMER_TICK_MS))
                 .num_slots(256)
                 .build(),
-            work_dir: WorkDir::new(work_dir),
+            timers: Timers::new(),
+            work_dir,
+            store,
             log_dir: LogDir::new(log_dir),
             worker_id: empty_worker_id(),
             graph: Graph::new(),
             need_scheduling: false,
             monitor: Monitor::new(),
             initializing_subworkers: Vec::new(),
-            subworker_args: subworkers,
+            subworker_kinds: subworkers,
+            warm_subworkers,
+            subworker_timeout: Duration::from_secs(DEFAULT_SUBWORKER_TIMEOUT),
+            task_output_limit: None,
+            download_cache,
+            rejoin: false,
+            zygotes: HashMap::new(),
+            pin_memory_limit,
+            pinned_memory: 0,
+            numa,
+            data_storage,
+            server_capabilities: Capabilities::new(),
+            image,
+            labels: Labels::new(),
+            chaos: Default::default(),
             self_ref: None,
             delete_list_max_timeout: ::std::env::var("RAIN_DELETE_LIST_TIMEOUT")
                 .ok()
@@ -763,15 +1803,26 @@ impl StateRef {
     }
 
     // This is called when worker connection to server is established
-    pub fn on_connected_to_server(
+    pub fn on_connected_to_server<S>(
         &self,
-        stream: TcpStream,
+        stream: S,
+        local_ip: IpAddr,
         listen_address: SocketAddr,
         ready_file: Option<String>,
-    ) {
+    ) where
+        S: AsyncRead + AsyncWrite + 'static,
+    {
         info!("Connected to server; registering as worker");
-        stream.set_nodelay(true).unwrap();
-        let mut rpc_system = ::common::rpc::new_rpc_system(stream, None);
+        let framing = self.get().server_framing;
+        let via_gateway = self.get().via_gateway;
+        // Server sees the address of gateway, so the worker announces its own address
+        // (as seen by other workers of the site)
+        let listen_address = if via_gateway && listen_address.ip().is_unspecified() {
+            SocketAddr::new(local_ip, listen_address.port())
+        } else {
+            listen_address
+        };
+        let mut rpc_system = new_framed_rpc_system(stream, framing, Vec::new(), None);
         let bootstrap: ::server_capnp::server_bootstrap::Client =
             rpc_system.bootstrap(rpc_twoparty_capnp::Side::Server);
 
@@ -783,23 +1834,54 @@ impl StateRef {
 
         req.get().set_version(WORKER_PROTOCOL_VERSION);
         req.get().set_control(worker_control);
+        req.get().set_via_gateway(via_gateway);
+        req.get().set_cost(self.get().cost);
+        req.get().set_preemptible(self.get().drain_grace.is_some());
+        req.get().set_image(&self.get().image);
+        {
+            let labels = &self.get().labels;
+            let mut builder = req.get().init_labels(labels.len() as u32);
+            for (i, (key, value)) in labels.iter().enumerate() {
+                builder.set(i as u32, &format!("{}={}", key, value));
+            }
+        }
+        let announced = self.get().capabilities();
+        announced.to_capnp(&mut req.get().init_capabilities(announced.iter().count() as u32));
+        Platform::detect().to_capnp(&mut req.get().init_platform());
         listen_address.to_capnp(&mut req.get().get_address().unwrap());
         self.get()
             .resources
             .to_capnp(&mut req.get().get_resources().unwrap());
 
         let state = self.clone();
+        let sent_time = ::chrono::Utc::now();
+        let sent_instant = Instant::now();
         let future = req.send()
             .promise
             .and_then(move |response| {
                 let response = pry!(response.get());
                 let upstream = pry!(response.get_upstream());
                 let worker_id = pry!(response.get_worker_id());
+                let server_time =
+                    ::chrono::DateTime::from_capnp(&pry!(response.get_server_time()));
                 let mut inner = state.get_mut();
                 inner.upstream = Some(upstream);
                 inner.worker_id = WorkerId::from_capnp(&worker_id);
+                inner.server_capabilities =
+                    pry!(Capabilities::from_capnp(&pry!(response.get_capabilities())));
+                // Server that does not accept inline data gets everything through datastore
+                inner.inline_threshold =
+                    if inner.server_capabilities.has(capabilities::TRANSFER_INLINE) {
+                        response.get_inline_threshold() as usize
+                    } else {
+                        0
+                    };
+                inner.clock_offset =
+                    estimate_clock_offset(sent_time, sent_instant.elapsed(), server_time);
                 debug!("Registration completed");
 
+                inner.restore_stored_objects(pry!(response.get_server_instance()));
+
                 // Create ready file - a file that is created when worker is connected & registered
                 if let Some(name) = ready_file {
                     ::common::fs::create_ready_file(Path::new(&name));
@@ -850,12 +1932,9 @@ impl StateRef {
             );
     }
 
-    pub fn start(
-        &self,
-        server_address: SocketAddr,
-        mut listen_address: SocketAddr,
-        ready_file: Option<&str>,
-    ) {
+    /// Start listening for subworkers and other workers and the timers;
+    /// returns the listen address with the port assigned by the system
+    fn start_services(&self, mut listen_address: SocketAddr, ship_logs: bool) -> SocketAddr {
         let handle = self.get().handle.clone();
 
         // --- Start listening Unix socket for subworkers ----
@@ -903,69 +1982,102 @@ impl StateRef {
             });
         handle.spawn(future);
 
-        // --- Start monitoring ---
-        let state = self.clone();
-
-        let interval = state
-            .get()
-            .timer
-            .interval(Duration::from_secs(MONITORING_INTERVAL));
-        let monitoring = interval
-            .for_each(move |()| {
-                debug!("Monitoring wakeup");
-                let mut s = state.get_mut();
-                let worker_id = s.worker_id;
-
-                // Check that we already know our address
-                if worker_id.ip().is_unspecified() {
-                    debug!("Monitoring skipped, registration is not completed yet");
-                    return Ok(());
+        // --- Start periodic actions ---
+        {
+            let mut s = self.get_mut();
+            s.timers.add_after(
+                Duration::from_secs(MONITORING_INTERVAL),
+                TimerEvent::Monitoring,
+            );
+            if ship_logs {
+                shipping::enable();
+                s.timers.add_after(
+                    Duration::from_secs(LOG_SHIPPING_INTERVAL),
+                    TimerEvent::LogShipping,
+                );
+            }
+            // Draining on SIGTERM (preemptible workers)
+            if s.drain_grace.is_some() {
+                unsafe {
+                    libc::signal(libc::SIGTERM, on_sigterm as libc::sighandler_t);
                 }
+                s.timers.add_after(
+                    Duration::from_secs(DRAIN_CHECK_INTERVAL),
+                    TimerEvent::DrainCheck,
+                );
+            }
+        }
 
-                let event = s.monitor.build_event(&worker_id);
-                s.send_event(event);
-                Ok(())
-            })
-            .map_err(|e| error!("Monitoring error {}", e));
-        handle.spawn(monitoring);
-
-        // --- Start checking wait list ----
+        // --- Start timers ----
         let state = self.clone();
         let interval = state
             .get()
             .timer
-            .interval(Duration::from_secs(DELETE_WAIT_LIST_INTERVAL));
-        let check_list = interval
+            .interval(Duration::from_millis(TIMER_TICK_MS));
+        let timers = interval
             .for_each(move |()| {
-                debug!("Checking wait list wakeup");
-                let mut s = state.get_mut();
-                if s.graph.delete_wait_list.is_empty() {
-                    return Ok(());
-                }
-                let now = ::std::time::Instant::now();
-                let to_delete: Vec<_> = s.graph
-                    .delete_wait_list
-                    .iter()
-                    .filter(|pair| pair.1 < &now)
-                    .map(|pair| pair.0.clone())
-                    .collect();
-                for obj in to_delete {
-                    s.remove_object(&mut obj.get_mut());
-                    s.graph.delete_wait_list.remove(&obj);
-                }
+                state.get_mut().expire_timers();
                 Ok(())
             })
-            .map_err(|e| panic!("Error during checking wait list {}", e));
-        handle.spawn(check_list);
+            .map_err(|e| panic!("Error during checking timers {}", e));
+        handle.spawn(timers);
+
+        listen_address
+    }
+
+    /// Start the worker; `server_tls` is a connector with the domain name
+    /// of the server when the server requires TLS
+    pub fn start(
+        &self,
+        server_address: SocketAddr,
+        server_tls: Option<(TlsConnector, String)>,
+        listen_address: SocketAddr,
+        ready_file: Option<&str>,
+        ship_logs: bool,
+    ) {
+        let handle = self.get().handle.clone();
+        let listen_address = self.start_services(listen_address, ship_logs);
 
         // --- Start connection to server ----
         let core1 = self.clone();
         let ready_file = ready_file.map(|f| f.to_string());
         info!("Connecting to server addr={}", server_address);
+        let framing = self.get().server_framing;
         let connect = TcpStream::connect(&server_address, &handle)
-            .and_then(move |stream| {
-                core1.on_connected_to_server(stream, listen_address, ready_file);
-                Ok(())
+            .map_err(|e| e.to_string())
+            .and_then(move |stream| -> Box<Future<Item = (), Error = String>> {
+                stream.set_nodelay(true).unwrap();
+                let local_ip = stream.local_addr().unwrap().ip();
+                match server_tls {
+                    Some((connector, domain)) => Box::new(
+                        connector
+                            .connect(&domain, stream)
+                            .map_err(|e| format!("TLS handshake failed: {}", e))
+                            .and_then(move |stream| {
+                                announce_framing(stream, framing).map_err(|e| e.to_string())
+                            })
+                            .map(move |stream| {
+                                core1.on_connected_to_server(
+                                    stream,
+                                    local_ip,
+                                    listen_address,
+                                    ready_file,
+                                )
+                            }),
+                    ),
+                    None => Box::new(
+                        announce_framing(stream, framing)
+                            .map_err(|e| e.to_string())
+                            .map(move |stream| {
+                                core1.on_connected_to_server(
+                                    stream,
+                                    local_ip,
+                                    listen_address,
+                                    ready_file,
+                                )
+                            }),
+                    ),
+                }
             })
             .map_err(|e| {
                 error!("Connecting to server failed: {}", e);
@@ -974,6 +2086,25 @@ impl StateRef {
         handle.spawn(connect);
     }
 
+    /// Start the worker connected by `stream` to the server running in the same
+    /// process (`rain start --simple --in-process`)
+    pub fn start_in_process<S>(&self, stream: S, listen_address: SocketAddr, ship_logs: bool)
+    where
+        S: AsyncRead + AsyncWrite + 'static,
+    {
+        let listen_address = self.start_services(listen_address, ship_logs);
+        let state = self.clone();
+        let framing = self.get().server_framing;
+        let local_ip = listen_address.ip();
+        self.get().spawn_panic_on_error(
+            announce_framing(stream, framing)
+                .map_err(Error::from)
+                .map(move |stream| {
+                    state.on_connected_to_server(stream, local_ip, listen_address, None)
+                }),
+        );
+    }
+
     pub fn turn(&self) {
         let mut state = self.get_mut();
         if state.need_scheduling {
@@ -985,5 +2116,39 @@ impl StateRef {
         if !state.updated_objects.is_empty() || !state.updated_tasks.is_empty() {
             state.send_update()
         }
+
+        if let Err(e) = state.store.flush() {
+            error!("Cannot write index of stored objects: {}", e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::estimate_clock_offset;
+    use chrono::{Duration, TimeZone, Utc};
+
+    #[test]
+    fn test_estimate_clock_offset() {
+        let sent = Utc.timestamp(1_500_000_000, 0);
+        let round_trip = ::std::time::Duration::from_millis(200);
+
+        // Server clock is 10 s ahead, its time is taken in the middle of the round trip
+        let server_time = sent + Duration::seconds(10) + Duration::milliseconds(100);
+        assert_eq!(
+            estimate_clock_offset(sent, round_trip, server_time),
+            Duration::seconds(10)
+        );
+
+        // Server clock is 3 s behind
+        let server_time = sent - Duration::seconds(3) + Duration::milliseconds(100);
+        assert_eq!(
+            estimate_clock_offset(sent, round_trip, server_time),
+            Duration::seconds(-3)
+        );
+
+        // The same clocks, the error is at most a half of the round trip
+        let offset = estimate_clock_offset(sent, round_trip, sent + Duration::milliseconds(150));
+        assert!(offset.num_milliseconds().abs() <= 100);
     }
 }
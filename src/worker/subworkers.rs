@@ -0,0 +1,135 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fs::File;
+use std::path::Path;
+
+use errors::Result;
+
+/// Type of the built-in Python subworker
+pub const PYTHON_SUBWORKER: &str = "py";
+
+/// How subworkers of one type are started (item of worker option --subworkers).
+/// The started program has to implement the subworker protocol (capnp/subworker.capnp,
+/// see "Subworker protocol" in the developer guide).
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct SubworkerKind {
+    /// Program and its arguments
+    pub command: Vec<String>,
+    /// Environment variables of the subworker
+    #[serde(default)]
+    pub env: BTreeMap<String, String>,
+    /// The program can run as a zygote (`--zygote`), so --warm-subworkers forks
+    /// subworkers of this type from an initialized process
+    #[serde(default)]
+    pub zygote: bool,
+    /// The first input of each task is cached in the subworker between tasks
+    /// (the Python subworker gets the pickled function there)
+    #[serde(default)]
+    pub cache_first_input: bool,
+    /// Inputs are sent in memory instead of paths of files of the worker, for
+    /// languages that cannot map files (e.g. JavaScript); directories are not accepted
+    #[serde(default)]
+    pub memory_inputs: bool,
+}
+
+impl SubworkerKind {
+    pub fn new(command: Vec<String>) -> Self {
+        SubworkerKind {
+            command,
+            env: BTreeMap::new(),
+            zygote: false,
+            cache_first_input: false,
+            memory_inputs: false,
+        }
+    }
+
+    /// The built-in Python subworker (`python3 -m rain.subworker`)
+    pub fn python() -> Self {
+        SubworkerKind {
+            zygote: true,
+            cache_first_input: true,
+            ..Self::new(vec![
+                "python3".to_string(),
+                "-m".to_string(),
+                "rain.subworker".to_string(),
+            ])
+        }
+    }
+
+    fn validate(&self, subworker_type: &str) -> Result<()> {
+        if subworker_type.is_empty() || subworker_type.starts_with('!') {
+            bail!(
+                "Invalid subworker type '{}' (types starting with '!' are built-in tasks)",
+                subworker_type
+            );
+        }
+        if self.command.is_empty() || self.command[0].is_empty() {
+            bail!("Subworker type '{}' has no command", subworker_type);
+        }
+        Ok(())
+    }
+}
+
+/// Load subworker kinds from a JSON file with an object: type -> `SubworkerKind`
+pub fn load_subworker_kinds(path: &Path) -> Result<HashMap<String, SubworkerKind>> {
+    let file = File::open(path).map_err(|e| format!("Cannot open {:?}: {}", path, e))?;
+    let kinds: HashMap<String, SubworkerKind> = ::serde_json::from_reader(file)
+        .map_err(|e| format!("Invalid subworker file {:?}: {}", path, e))?;
+    for (subworker_type, kind) in &kinds {
+        kind.validate(subworker_type)?;
+    }
+    Ok(kinds)
+}
+
+/// Parse "TYPE=COMMAND" (worker option --subworker); the command is split by whitespace
+pub fn parse_subworker_spec(spec: &str) -> Result<(String, SubworkerKind)> {
+    let index = match spec.find('=') {
+        Some(index) => index,
+        None => bail!("Invalid subworker '{}', expected TYPE=COMMAND", spec),
+    };
+    let subworker_type = spec[..index].trim().to_string();
+    let kind = SubworkerKind::new(
+        spec[index + 1..]
+            .split_whitespace()
+            .map(|s| s.to_string())
+            .collect(),
+    );
+    kind.validate(&subworker_type)?;
+    Ok((subworker_type, kind))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_subworker_spec() {
+        let (subworker_type, kind) =
+            parse_subworker_spec("r=python3 -m rain.subworker.bridge  Rscript").unwrap();
+        assert_eq!(subworker_type, "r");
+        assert_eq!(
+            kind.command,
+            vec!["python3", "-m", "rain.subworker.bridge", "Rscript"]
+        );
+        assert!(!kind.zygote && !kind.cache_first_input);
+
+        assert!(parse_subworker_spec("r").is_err());
+        assert!(parse_subworker_spec("r=").is_err());
+        assert!(parse_subworker_spec("!run=sh").is_err());
+    }
+
+    #[test]
+    fn test_subworker_kinds_file() {
+        let kinds: HashMap<String, SubworkerKind> = ::serde_json::from_str(
+            r#"{"jl": {"command": ["julia", "subworker.jl"], "env": {"JULIA_NUM_THREADS": "1"}}}"#,
+        ).unwrap();
+        assert!(kinds["jl"].validate("jl").is_ok());
+        assert_eq!(kinds["jl"].env["JULIA_NUM_THREADS"], "1");
+        let js: HashMap<String, SubworkerKind> =
+            ::serde_json::from_str(r#"{"js": {"command": ["node"], "memory_inputs": true}}"#)
+                .unwrap();
+        assert!(js["js"].memory_inputs && !kinds["jl"].memory_inputs);
+        let unknown = r#"{"jl": {"command": ["julia"], "warm": true}}"#;
+        assert!(::serde_json::from_str::<HashMap<String, SubworkerKind>>(unknown).is_err());
+    }
+}
@@ -5,7 +5,7 @@ use super::TaskResult;
 use common::DataType;
 use worker::state::State;
 use worker::graph::TaskRef;
-use worker::data::{Data, DataBuilder};
+use worker::data::Data;
 use futures::{future, Future};
 use errors::ErrorKind;
 
@@ -28,7 +28,9 @@ pub fn task_concat(state: &mut State, task_ref: TaskRef) -> TaskResult {
         let result_size: usize = inputs.iter().map(|d| d.size()).sum();
         let state = state_ref.get();
         let work_dir = state.work_dir();
-        let mut builder = DataBuilder::new(work_dir, DataType::Blob, Some(result_size));
+        let mut builder = state
+            .data_storage()
+            .builder(work_dir, DataType::Blob, Some(result_size));
         for input in inputs {
             builder.write_blob(&input).unwrap();
         }
@@ -48,20 +50,48 @@ pub fn task_sleep(state: &mut State, task_ref: TaskRef) -> TaskResult {
     };
     debug!("Starting sleep task for {} ms", sleep_ms);
     let duration = ::std::time::Duration::from_millis(sleep_ms);
-    Ok(Box::new(
-        state
-            .timer()
-            .sleep(duration)
-            .map_err(|e| e.into())
-            .and_then(move |()| {
-                {
-                    let task = task_ref.get();
-                    let output = task.output(0);
-                    output.get_mut().set_data(task.input_data(0))?;
-                }
-                Ok(())
-            }),
-    ))
+    Ok(Box::new(state.sleep(duration).and_then(move |()| {
+        {
+            let task = task_ref.get();
+            let output = task.output(0);
+            output.get_mut().set_data(task.input_data(0))?;
+        }
+        Ok(())
+    })))
+}
+
+/// Task whose output is its input under another id (and label); data are shared, not copied
+pub fn task_identity(_: &mut State, task_ref: TaskRef) -> TaskResult {
+    {
+        let task = task_ref.get();
+        task.check_number_of_args(1)?;
+        let output = task.output(0);
+        output.get_mut().set_data(task.input_data(0))?;
+    }
+    Ok(Box::new(future::ok(())))
+}
+
+#[derive(Deserialize)]
+struct SelectConfig {
+    index: usize,
+}
+
+/// Task whose output is the input chosen by index in config; data are shared, not copied
+pub fn task_select(_: &mut State, task_ref: TaskRef) -> TaskResult {
+    {
+        let task = task_ref.get();
+        let config: SelectConfig = task.attributes.get("config")?;
+        if config.index >= task.inputs.len() {
+            bail!(
+                "Index {} of selected input is out of range ({} inputs)",
+                config.index,
+                task.inputs.len()
+            );
+        }
+        let output = task.output(0);
+        output.get_mut().set_data(task.input_data(config.index))?;
+    }
+    Ok(Box::new(future::ok(())))
 }
 
 #[derive(Deserialize)]
@@ -147,11 +177,11 @@ pub fn task_make_directory(state: &mut State, task_ref: TaskRef) -> TaskResult {
             }
             let target_path = main_dir.join(&p);
             ::std::fs::create_dir_all(&target_path.parent().unwrap())?;
-            data.link_to_path(&target_path)?;
+            state.data_storage().map(data, &target_path)?;
         }
         let output = task.output(0);
         let mut obj = output.get_mut();
-        obj.set_data_by_fs_move(&main_dir, None, state.work_dir())
+        obj.set_data_by_fs_move(&main_dir, None, state.work_dir(), state.data_storage())
     })))
 }
 
@@ -175,6 +205,11 @@ pub fn task_slice_directory(state: &mut State, task_ref: TaskRef) -> TaskResult
         let path = main_dir.join(&config.path);
         let output = task.output(0);
         let mut obj = output.get_mut();
-        obj.set_data_by_fs_move(&path, Some(&config.path), state.work_dir())
+        obj.set_data_by_fs_move(
+            &path,
+            Some(&config.path),
+            state.work_dir(),
+            state.data_storage(),
+        )
     })))
 }
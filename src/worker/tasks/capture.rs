@@ -0,0 +1,124 @@
+use std::collections::VecDeque;
+use std::fs::File;
+use std::io::{self, Read, Write};
+use std::os::unix::io::FromRawFd;
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+
+use futures::Future;
+use futures::sync::oneshot;
+use nix::fcntl::OFlag;
+use nix::unistd::pipe2;
+
+use errors::{Error, Result};
+
+/// Size of the end of a capped stream that is always kept
+const KEPT_TAIL_SIZE: usize = 8 * 1024;
+
+/// Copy `reader` into `writer`, at most `limit` bytes are written: the beginning of
+/// the stream, a note about the omitted part and the last `KEPT_TAIL_SIZE` bytes.
+/// Returns the size of the whole stream.
+fn copy_capped<R: Read, W: Write>(reader: &mut R, writer: &mut W, limit: u64) -> io::Result<u64> {
+    let tail_size = ::std::cmp::min(KEPT_TAIL_SIZE as u64, limit / 2) as usize;
+    let head_size = limit - tail_size as u64;
+    let mut total = 0u64;
+    let mut tail = VecDeque::with_capacity(tail_size);
+    let mut buffer = [0u8; 64 * 1024];
+    loop {
+        let n = match reader.read(&mut buffer) {
+            Ok(0) => break,
+            Ok(n) => n,
+            Err(ref e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        };
+        let mut data = &buffer[..n];
+        if total < head_size {
+            let head = ::std::cmp::min(head_size - total, n as u64) as usize;
+            writer.write_all(&data[..head])?;
+            data = &data[head..];
+        }
+        total += n as u64;
+        for &byte in data {
+            if tail.len() == tail_size {
+                tail.pop_front();
+            }
+            if tail_size > 0 {
+                tail.push_back(byte);
+            }
+        }
+    }
+    let omitted = total.saturating_sub(head_size + tail.len() as u64);
+    if omitted > 0 {
+        write!(writer, "\n[... {} bytes omitted ...]\n", omitted)?;
+    }
+    let (first, second) = tail.as_slices();
+    writer.write_all(first)?;
+    writer.write_all(second)?;
+    Ok(total)
+}
+
+/// Stdout or stderr of a program written into a file with a size cap. The program
+/// writes into a pipe, a thread copies the pipe into the file.
+pub struct CappedCapture {
+    pub path: PathBuf,
+    /// Resolves to the size of the whole stream when the program closes it
+    pub done: Box<Future<Item = u64, Error = Error>>,
+}
+
+impl CappedCapture {
+    /// Returns the capture and the end of the pipe for the program
+    pub fn start(path: &Path, limit: u64) -> Result<(Self, Stdio)> {
+        let mut file = File::create(path)?;
+        // Close-on-exec, so other spawned programs do not keep the pipe open;
+        // the end given to the program is duplicated onto its stdout/stderr
+        let (read_fd, write_fd) =
+            pipe2(OFlag::O_CLOEXEC).map_err(|e| format!("Cannot create pipe: {}", e))?;
+        let mut reader = unsafe { File::from_raw_fd(read_fd) };
+        let stdio = unsafe { Stdio::from_raw_fd(write_fd) };
+        let (sender, receiver) = oneshot::channel();
+        ::std::thread::Builder::new()
+            .name("capture".to_string())
+            .spawn(move || {
+                let _ = sender.send(copy_capped(&mut reader, &mut file, limit));
+            })?;
+        let done = receiver.then(|r| -> Result<u64> {
+            match r {
+                Ok(result) => Ok(result?),
+                Err(_) => bail!("Capturing of output was cancelled"),
+            }
+        });
+        Ok((
+            CappedCapture {
+                path: path.to_path_buf(),
+                done: Box::new(done),
+            },
+            stdio,
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::copy_capped;
+
+    fn capped(input: &[u8], limit: u64) -> (Vec<u8>, u64) {
+        let mut output = Vec::new();
+        let total = copy_capped(&mut &input[..], &mut output, limit).unwrap();
+        (output, total)
+    }
+
+    #[test]
+    fn test_copy_capped() {
+        assert_eq!(capped(b"short", 100), (b"short".to_vec(), 5));
+        assert_eq!(capped(b"0123456789", 10), (b"0123456789".to_vec(), 10));
+        let (output, total) = capped(b"0123456789", 4);
+        assert_eq!(total, 10);
+        assert_eq!(output, b"01\n[... 6 bytes omitted ...]\n89".to_vec());
+
+        let input = vec![b'x'; 100 * 1024];
+        let (output, total) = capped(&input, 20 * 1024);
+        assert_eq!(total, 100 * 1024);
+        let note = format!("\n[... {} bytes omitted ...]\n", 80 * 1024);
+        assert_eq!(output.len(), 20 * 1024 + note.len());
+    }
+}
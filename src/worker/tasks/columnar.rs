@@ -0,0 +1,201 @@
+use std::fs::File;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use arrow::csv;
+use arrow::record_batch::RecordBatchReader;
+use parquet::arrow::ArrowWriter;
+use parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+use parquet::arrow::ProjectionMask;
+use parquet::basic::Compression;
+use parquet::file::properties::WriterProperties;
+
+use super::TaskResult;
+use worker::state::State;
+use worker::graph::TaskRef;
+use futures::{future, Future};
+use errors::{Error, Result};
+
+/// Number of rows used to infer the schema of CSV
+const SCHEMA_INFERENCE_ROWS: usize = 1000;
+
+/// Number of rows in a batch processed at once
+const BATCH_SIZE: usize = 64 * 1024;
+
+fn format_error<E: ::std::fmt::Display>(e: E) -> Error {
+    format!("{}", e).into()
+}
+
+fn default_delimiter() -> char {
+    ','
+}
+
+fn default_true() -> bool {
+    true
+}
+
+#[derive(Deserialize)]
+struct CsvToParquetConfig {
+    #[serde(default = "default_delimiter")]
+    delimiter: char,
+    #[serde(default = "default_true")]
+    has_header: bool,
+    /// "snappy" (default), "gzip", "zstd" or "none"
+    compression: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct ParquetSelectConfig {
+    /// Selected columns, all columns when not set
+    columns: Option<Vec<String>>,
+    /// Maximal number of rows
+    limit: Option<usize>,
+    compression: Option<String>,
+}
+
+fn writer_properties(compression: &Option<String>) -> Result<WriterProperties> {
+    let compression = match compression.as_ref().map(|s| s.as_str()) {
+        None | Some("snappy") => Compression::SNAPPY,
+        Some("gzip") => Compression::GZIP(Default::default()),
+        Some("zstd") => Compression::ZSTD(Default::default()),
+        Some("none") => Compression::UNCOMPRESSED,
+        Some(other) => bail!("Unknown compression '{}'", other),
+    };
+    Ok(WriterProperties::builder()
+        .set_compression(compression)
+        .build())
+}
+
+/// Make the only input of the task available as a file in the temporary directory,
+/// returns path of the input and path for the output
+fn prepare_paths(task_ref: &TaskRef, dir: &Path) -> Result<(PathBuf, PathBuf)> {
+    let task = task_ref.get();
+    let input = task.input_data(0);
+    if !input.is_blob() {
+        bail!("Input object is not blob");
+    }
+    let input_path = dir.join("input");
+    input.link_to_path(&input_path)?;
+    Ok((input_path, dir.join("output")))
+}
+
+/// Run `convert(input_path, output_path)` in the blocking pool of the worker, as conversions
+/// of large files would block the reactor. The state is borrowed only to prepare the paths
+/// and to set the converted file as the data of the output.
+fn run_conversion<F>(state: &mut State, task_ref: TaskRef, convert: F) -> TaskResult
+where
+    F: FnOnce(&Path, &Path) -> Result<()> + Send + 'static,
+{
+    let state_ref = state.self_ref();
+    let pool = state.blocking_pool().clone();
+    Ok(Box::new(
+        future::lazy(move || -> Result<_> {
+            let (dir, input_path, output_path) = {
+                let state = state_ref.get();
+                let dir = state
+                    .work_dir()
+                    .make_task_temp_dir(task_ref.get().id)?;
+                let (input_path, output_path) = prepare_paths(&task_ref, dir.path())?;
+                (dir, input_path, output_path)
+            };
+            let target = output_path.clone();
+            let converted = pool.spawn_fn(move || convert(&input_path, &target));
+            Ok(converted.and_then(move |()| {
+                let state = state_ref.get();
+                let output = task_ref.get().output(0);
+                let mut obj = output.get_mut();
+                let result = obj.set_data_by_fs_move(
+                    &output_path,
+                    None,
+                    state.work_dir(),
+                    state.data_storage(),
+                );
+                // The temporary directory is removed after the output was moved out
+                drop(dir);
+                result
+            }))
+        }).flatten(),
+    ))
+}
+
+/// Convert CSV blob into Parquet blob. The schema is inferred from the beginning of CSV.
+pub fn task_csv_to_parquet(state: &mut State, task_ref: TaskRef) -> TaskResult {
+    let config: CsvToParquetConfig = {
+        let task = task_ref.get();
+        task.check_number_of_args(1)?;
+        task.attributes.get("config")?
+    };
+    if !config.delimiter.is_ascii() {
+        bail!("Delimiter has to be an ASCII character");
+    }
+    let properties = writer_properties(&config.compression)?;
+    run_conversion(state, task_ref, move |input_path, output_path| {
+        let delimiter = config.delimiter as u8;
+
+        let format = csv::reader::Format::default()
+            .with_header(config.has_header)
+            .with_delimiter(delimiter);
+        let (schema, _) = format
+            .infer_schema(File::open(input_path)?, Some(SCHEMA_INFERENCE_ROWS))
+            .map_err(format_error)?;
+        let schema = Arc::new(schema);
+        let reader = csv::ReaderBuilder::new(schema.clone())
+            .with_header(config.has_header)
+            .with_delimiter(delimiter)
+            .with_batch_size(BATCH_SIZE)
+            .build(File::open(input_path)?)
+            .map_err(format_error)?;
+
+        let mut writer = ArrowWriter::try_new(File::create(output_path)?, schema, Some(properties))
+            .map_err(format_error)?;
+        for batch in reader {
+            writer
+                .write(&batch.map_err(format_error)?)
+                .map_err(format_error)?;
+        }
+        writer.close().map_err(format_error)?;
+        Ok(())
+    })
+}
+
+/// Select columns and/or first rows of Parquet blob; the result is Parquet blob
+pub fn task_parquet_select(state: &mut State, task_ref: TaskRef) -> TaskResult {
+    let config: ParquetSelectConfig = {
+        let task = task_ref.get();
+        task.check_number_of_args(1)?;
+        task.attributes.get("config")?
+    };
+    let properties = writer_properties(&config.compression)?;
+    run_conversion(state, task_ref, move |input_path, output_path| {
+        let mut builder = ParquetRecordBatchReaderBuilder::try_new(File::open(input_path)?)
+            .map_err(format_error)?
+            .with_batch_size(BATCH_SIZE);
+        if let Some(ref columns) = config.columns {
+            let mut indices = Vec::new();
+            for name in columns {
+                let index = builder
+                    .schema()
+                    .index_of(name)
+                    .map_err(|_| format!("Column '{}' not found", name))?;
+                indices.push(index);
+            }
+            let mask = ProjectionMask::roots(builder.parquet_schema(), indices);
+            builder = builder.with_projection(mask);
+        }
+        if let Some(limit) = config.limit {
+            builder = builder.with_limit(limit);
+        }
+        let reader = builder.build().map_err(format_error)?;
+
+        let mut writer =
+            ArrowWriter::try_new(File::create(output_path)?, reader.schema(), Some(properties))
+                .map_err(format_error)?;
+        for batch in reader {
+            writer
+                .write(&batch.map_err(format_error)?)
+                .map_err(format_error)?;
+        }
+        writer.close().map_err(format_error)?;
+        Ok(())
+    })
+}
@@ -0,0 +1,255 @@
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::TcpStream;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+
+use futures::Future;
+use futures::sync::oneshot;
+use native_tls;
+
+use super::TaskResult;
+use common::DataType;
+use worker::data::Data;
+use worker::fs::downloads::CachedDownload;
+use worker::graph::TaskRef;
+use worker::state::State;
+use errors::Result;
+
+/// Maximal number of followed redirects
+const MAX_REDIRECTS: usize = 5;
+
+/// Timeout of connecting to the server and of reading the response (seconds)
+const DOWNLOAD_TIMEOUT: u64 = 60;
+
+#[derive(Deserialize)]
+struct DownloadConfig {
+    url: String,
+}
+
+/// Result of the task, stored in the task attribute "download"
+#[derive(Serialize)]
+struct DownloadInfo {
+    url: String,
+    size: u64,
+    /// The file was taken from the cache of the worker
+    cached: bool,
+}
+
+enum Response {
+    /// The cached file is still valid
+    NotModified,
+    Downloaded {
+        /// ETag or Last-Modified of the response
+        validator: Option<String>,
+        size: u64,
+    },
+}
+
+enum Exchange {
+    Redirect(String),
+    Response(Response),
+}
+
+/// Send the request and read the response, a successful body is written into the file
+fn exchange<S: Read + Write>(stream: &mut S, request: &str, path: &Path) -> Result<Exchange> {
+    stream.write_all(request.as_bytes())?;
+    let mut reader = BufReader::new(stream);
+    let mut status_line = String::new();
+    reader.read_line(&mut status_line)?;
+    let mut headers = HashMap::new();
+    loop {
+        let mut line = String::new();
+        if reader.read_line(&mut line)? == 0 || line.trim().is_empty() {
+            break;
+        }
+        if let Some(i) = line.find(':') {
+            headers.insert(line[..i].trim().to_lowercase(), line[i + 1..].trim().to_string());
+        }
+    }
+    let status = status_line.split_whitespace().nth(1).unwrap_or("");
+    match status {
+        "304" => Ok(Exchange::Response(Response::NotModified)),
+        "301" | "302" | "303" | "307" | "308" => match headers.remove("location") {
+            Some(location) => Ok(Exchange::Redirect(location)),
+            None => bail!("Redirect without location ('{}')", status_line.trim()),
+        },
+        _ if status.starts_with('2') => {
+            let size = ::std::io::copy(&mut reader, &mut File::create(path)?)?;
+            if let Some(length) = headers.get("content-length") {
+                if length.parse::<u64>().ok() != Some(size) {
+                    bail!("Download ended after {} of {} bytes", size, length);
+                }
+            }
+            let validator = headers
+                .remove("etag")
+                .or_else(|| headers.remove("last-modified"));
+            Ok(Exchange::Response(Response::Downloaded { validator, size }))
+        }
+        _ => bail!("Server returned '{}'", status_line.trim()),
+    }
+}
+
+/// GET the URL (http or https) into the file; with a validator of a cached file,
+/// the file is not downloaded again when it has not changed
+fn http_get(url: &str, validator: Option<&str>, path: &Path) -> Result<Response> {
+    let mut url = url.to_string();
+    for _ in 0..MAX_REDIRECTS + 1 {
+        let uri: ::hyper::Uri = url.parse()
+            .map_err(|e| format!("Invalid URL '{}': {}", url, e))?;
+        let https = match uri.scheme() {
+            Some("https") => true,
+            Some("http") => false,
+            _ => bail!("Unsupported URL '{}'", url),
+        };
+        let host = uri.host()
+            .ok_or_else(|| format!("URL '{}' has no host", url))?
+            .to_string();
+        let port = uri.port().unwrap_or(if https { 443 } else { 80 });
+        let target = match uri.query() {
+            Some(query) => format!("{}?{}", uri.path(), query),
+            None => uri.path().to_string(),
+        };
+        let mut request = format!(
+            "GET {} HTTP/1.0\r\nHost: {}\r\nUser-Agent: rain/{}\r\nConnection: close\r\n",
+            target,
+            host,
+            ::VERSION
+        );
+        if let Some(validator) = validator {
+            // ETags are quoted, otherwise it is the date of Last-Modified
+            let header = if validator.starts_with('"') || validator.starts_with("W/") {
+                "If-None-Match"
+            } else {
+                "If-Modified-Since"
+            };
+            request.push_str(&format!("{}: {}\r\n", header, validator));
+        }
+        request.push_str("\r\n");
+
+        let stream = TcpStream::connect((host.as_str(), port))
+            .map_err(|e| format!("Cannot connect to {}:{}: {}", host, port, e))?;
+        stream.set_read_timeout(Some(Duration::from_secs(DOWNLOAD_TIMEOUT)))?;
+        stream.set_write_timeout(Some(Duration::from_secs(DOWNLOAD_TIMEOUT)))?;
+        let result = if https {
+            let connector = native_tls::TlsConnector::new()
+                .map_err(|e| format!("Cannot initialize TLS: {}", e))?;
+            let mut stream = connector
+                .connect(&host, stream)
+                .map_err(|e| format!("TLS connection to {} failed: {}", host, e))?;
+            exchange(&mut stream, &request, path)?
+        } else {
+            let mut stream = stream;
+            exchange(&mut stream, &request, path)?
+        };
+        match result {
+            Exchange::Response(response) => return Ok(response),
+            Exchange::Redirect(location) => {
+                url = if location.starts_with('/') {
+                    format!("{}://{}:{}{}", uri.scheme().unwrap(), host, port, location)
+                } else {
+                    location
+                };
+                debug!("Download redirected to {}", url);
+            }
+        }
+    }
+    bail!("Too many redirects")
+}
+
+/// Download a file from http(s) URL; files are cached by the worker (`--download-cache`)
+/// and downloaded again only when the server reports a change
+pub fn task_download(state: &mut State, task_ref: TaskRef) -> TaskResult {
+    let config: DownloadConfig = {
+        let task = task_ref.get();
+        task.check_number_of_args(0)?;
+        task.attributes.get("config")?
+    };
+    let validator = {
+        let cache = state.download_cache_mut();
+        if cache.is_enabled() {
+            cache.get(&config.url).map(|c| c.validator)
+        } else {
+            None
+        }
+    };
+    let path = state.download_cache_mut().new_path();
+
+    let (sender, receiver) = oneshot::channel();
+    {
+        let url = config.url.clone();
+        let path = path.clone();
+        ::std::thread::Builder::new()
+            .name("download".to_string())
+            .spawn(move || {
+                let result = http_get(&url, validator.as_ref().map(|v| v.as_str()), &path);
+                if result.is_err() && path.exists() {
+                    let _ = ::std::fs::remove_file(&path);
+                }
+                let _ = sender.send(result);
+            })?;
+    }
+
+    let state_ref = state.self_ref();
+    Ok(Box::new(receiver.then(move |r| -> Result<()> {
+        let response = match r {
+            Ok(response) => response.map_err(|e| format!("Download of {}: {}", config.url, e))?,
+            Err(_) => bail!("Download was cancelled"),
+        };
+        let mut state = state_ref.get_mut();
+        let target = state.work_dir().new_path_for_dataobject();
+        // Output is a hard link of the cached file
+        let (size, from_cache) = match response {
+            // The file may have been evicted while the server was asked
+            Response::NotModified => match state.download_cache_mut().get(&config.url) {
+                Some(c) => {
+                    ::std::fs::hard_link(&c.path, &target)?;
+                    (c.size, true)
+                }
+                None => bail!("Cached download of {} is not available", config.url),
+            },
+            Response::Downloaded { validator, size } => {
+                let is_cached = {
+                    let cache = state.download_cache_mut();
+                    cache.remove(&config.url);
+                    match validator {
+                        Some(validator) if cache.is_enabled() => cache.insert(
+                            &config.url,
+                            CachedDownload {
+                                path: path.clone(),
+                                validator,
+                                size,
+                            },
+                        ),
+                        _ => false,
+                    }
+                };
+                if is_cached {
+                    ::std::fs::hard_link(&path, &target)?;
+                } else {
+                    // Not kept by the cache, the file becomes the data of the output
+                    ::std::fs::rename(&path, &target)?;
+                }
+                (size, false)
+            }
+        };
+        let data = Data::new_from_path(target, size as usize, DataType::Blob);
+        let data = state.data_storage().adopt(data)?;
+        let output = task_ref.get().output(0);
+        output.get_mut().set_data(Arc::new(data))?;
+        if from_cache {
+            debug!("Download of {} taken from cache", config.url);
+        }
+        task_ref.get_mut().new_attributes.set(
+            "download",
+            DownloadInfo {
+                url: config.url.clone(),
+                size,
+                cached: from_cache,
+            },
+        )?;
+        Ok(())
+    })))
+}
@@ -0,0 +1,51 @@
+use std::sync::Arc;
+
+use super::TaskResult;
+use common::DataType;
+use worker::state::State;
+use worker::graph::TaskRef;
+use futures::future;
+
+#[derive(Deserialize)]
+struct JmespathConfig {
+    expression: String,
+}
+
+/// Evaluate JMESPath expression over the JSON input blob, the result is JSON blob.
+/// It runs directly in the worker, it serves for small transformations
+/// between other tasks without a round trip to a subworker.
+pub fn task_jmespath(state: &mut State, task_ref: TaskRef) -> TaskResult {
+    let (config, input): (JmespathConfig, _) = {
+        let task = task_ref.get();
+        task.check_number_of_args(1)?;
+        (task.attributes.get("config")?, task.input_data(0))
+    };
+    if !input.is_blob() {
+        bail!("Input object is not blob");
+    }
+    // Expression is compiled before the task is started, so invalid expression
+    // fails the task immediately
+    let expression = ::jmespath::compile(&config.expression)
+        .map_err(|e| format!("Invalid expression: {}", e))?;
+
+    let state_ref = state.self_ref();
+    Ok(Box::new(future::lazy(move || {
+        let state = state_ref.get();
+        let bytes = state.data_storage().get(&input)?.unwrap();
+        let text = ::std::str::from_utf8(&bytes)
+            .map_err(|e| format!("Input is not valid UTF-8: {}", e))?;
+        let value = ::jmespath::Variable::from_json(text)
+            .map_err(|e| format!("Input is not valid JSON: {}", e))?;
+        let result = expression
+            .search(value)
+            .map_err(|e| format!("Evaluation of expression failed: {}", e))?;
+        let data = state.data_storage().put(
+            state.work_dir(),
+            DataType::Blob,
+            ::serde_json::to_vec(&*result)?,
+        )?;
+        let output = task_ref.get().output(0);
+        output.get_mut().set_data(Arc::new(data))?;
+        Ok(())
+    })))
+}
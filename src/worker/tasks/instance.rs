@@ -1,12 +1,18 @@
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
 use futures::Future;
 use chrono::{DateTime, Utc};
 
-use worker::graph::{SubworkerRef, TaskRef, TaskState};
+use worker::graph::{SubworkerRef, Task, TaskRef, TaskState};
 use worker::state::State;
 use worker::tasks;
 use worker::rpc::subworker::data_from_capnp;
+use worker::limits::ProcessLimits;
+use worker::numa::preferred_node;
 use common::Attributes;
 use common::convert::ToCapnp;
+use common::units::{duration_secs, Span};
 use errors::{Error, Result};
 
 /// Instance represents a running task. It contains resource allocations and
@@ -20,17 +26,43 @@ pub struct TaskInstance {
     // When cancel_sender is None, termination is actually running
     cancel_sender: Option<::futures::unsync::oneshot::Sender<()>>,
 
+    /// Start of the task by the server clock
     start_timestamp: DateTime<Utc>,
+    /// Start of the task by the monotonic clock (for measuring the duration)
+    start_instant: Instant,
+    /// The task was cancelled since it exceeded this timeout
+    timed_out: Option<Duration>,
     //pub subworker: Option<SubworkerRef>
 }
 
 pub type TaskFuture = Future<Item = (), Error = Error>;
 pub type TaskResult = Result<Box<TaskFuture>>;
 
+type TaskFn = fn(&mut State, TaskRef) -> TaskResult;
+
+/// Tasks implemented directly in the worker (announced as capabilities "task:<type>")
+pub static BUILTIN_TASKS: &[(&str, TaskFn)] = &[
+    ("!run", tasks::run::task_run),
+    ("!concat", tasks::basic::task_concat),
+    ("!open", tasks::basic::task_open),
+    ("!export", tasks::basic::task_export),
+    ("!slice_directory", tasks::basic::task_slice_directory),
+    ("!make_directory", tasks::basic::task_make_directory),
+    ("!sleep", tasks::basic::task_sleep),
+    ("!identity", tasks::basic::task_identity),
+    ("!select", tasks::basic::task_select),
+    ("!csv_to_parquet", tasks::columnar::task_csv_to_parquet),
+    ("!parquet_select", tasks::columnar::task_parquet_select),
+    ("!jmespath", tasks::expression::task_jmespath),
+    ("!download", tasks::download::task_download),
+];
+
 #[derive(Serialize)]
 struct AttributeInfo {
     worker: String,
+    /// Start time corrected to the server clock
     start: String,
+    /// Duration in milliseconds measured by the monotonic clock
     duration: i64,
 }
 
@@ -63,11 +95,25 @@ impl Drop for KillOnDrop {
     }
 }
 
+/// Only GPUs allocated to the task are visible to it (CUDA_VISIBLE_DEVICES in attribute "env",
+/// used by "!run" and subworkers)
+fn set_visible_gpus(task: &mut Task, gpus: &[u32]) -> Result<()> {
+    let mut env: HashMap<String, String> = task.attributes.find("env")?.unwrap_or_default();
+    let devices: Vec<_> = gpus.iter().map(|device| device.to_string()).collect();
+    env.insert("CUDA_VISIBLE_DEVICES".to_string(), devices.join(","));
+    task.attributes.set("env", env)
+}
+
 impl TaskInstance {
     pub fn start(state: &mut State, task_ref: TaskRef) {
         {
             let mut task = task_ref.get_mut();
-            state.alloc_resources(&task.resources);
+            if let Some(gpus) = state.alloc_resources(&task.resources) {
+                if let Err(e) = set_visible_gpus(&mut task, &gpus) {
+                    warn!("Cannot set visible GPUs of task {}: {}", task.id, e);
+                }
+                task.gpus = gpus;
+            }
             task.state = TaskState::Running;
             state.task_updated(&task_ref);
         }
@@ -75,26 +121,33 @@ impl TaskInstance {
         let task_fn = {
             let task = task_ref.get();
             let task_type: &str = task.task_type.as_ref();
-            // Build-in task
-            match task_type {
-                task_type if !task_type.starts_with("!") => Self::start_task_in_subworker,
-                "!run" => tasks::run::task_run,
-                "!concat" => tasks::basic::task_concat,
-                "!open" => tasks::basic::task_open,
-                "!export" => tasks::basic::task_export,
-                "!slice_directory" => tasks::basic::task_slice_directory,
-                "!make_directory" => tasks::basic::task_make_directory,
-                "!sleep" => tasks::basic::task_sleep,
-                _ => fail_unknown_type,
+            if !task_type.starts_with("!") {
+                Self::start_task_in_subworker as TaskFn
+            } else {
+                // Build-in task
+                BUILTIN_TASKS
+                    .iter()
+                    .find(|&&(name, _)| name == task_type)
+                    .map(|&(_, task_fn)| task_fn)
+                    .unwrap_or(fail_unknown_type)
             }
         };
 
-        let future: Box<TaskFuture> = match task_fn(state, task_ref.clone()) {
+        // Attribute "timeout": seconds or a string with units (e.g. "1h30m")
+        let timeout = task_ref
+            .get()
+            .attributes
+            .find::<Span>("timeout")
+            .map(|timeout| timeout.map(|t| t.0));
+        let started = timeout.and_then(|timeout| {
+            task_fn(state, task_ref.clone()).map(|future| (future, timeout))
+        });
+        let (future, timeout): (Box<TaskFuture>, _) = match started {
             Ok(f) => f,
             Err(e) => {
                 state.unregister_task(&task_ref);
                 let mut task = task_ref.get_mut();
-                state.free_resources(&task.resources);
+                state.free_resources(&task.resources, &task.gpus);
                 task.set_failed(e.description().to_string());
                 state.task_updated(&task_ref);
                 return;
@@ -107,12 +160,19 @@ impl TaskInstance {
         let instance = TaskInstance {
             task_ref: task_ref,
             cancel_sender: Some(sender),
-            start_timestamp: Utc::now(),
+            start_timestamp: state.now(),
+            start_instant: Instant::now(),
+            timed_out: None,
         };
         let state_ref = state.self_ref();
         state.graph.running_tasks.insert(task_id, instance);
+        if let Some(timeout) = timeout {
+            state.add_task_timeout(task_id, timeout);
+        }
 
-        state.spawn_panic_on_error(
+        let executor = state.task_executor().clone();
+        state.spawn_limited(
+            &executor,
             future
                 .map(|()| true)
                 .select(receiver.map(|()| false).map_err(|_| unreachable!()))
@@ -122,12 +182,13 @@ impl TaskInstance {
                     state.task_updated(&instance.task_ref);
                     state.unregister_task(&instance.task_ref);
                     let mut task = instance.task_ref.get_mut();
-                    state.free_resources(&task.resources);
+                    state.free_resources(&task.resources, &task.gpus);
 
                     let info = AttributeInfo {
                         worker: format!("{}", state.worker_id()),
                         start: instance.start_timestamp.to_rfc3339(),
-                        duration: (Utc::now().signed_duration_since(instance.start_timestamp))
+                        duration: ::chrono::Duration::from_std(instance.start_instant.elapsed())
+                            .unwrap()
                             .num_milliseconds(),
                     };
                     task.new_attributes.set("info", info).unwrap();
@@ -135,22 +196,43 @@ impl TaskInstance {
                     match r {
                         Ok((true, _)) => {
                             let all_finished = task.outputs.iter().all(|o| o.get().is_finished());
+                            let invalid = if all_finished {
+                                task.outputs
+                                    .iter()
+                                    .filter_map(|o| o.get().validate(state.data_storage()).err())
+                                    .next()
+                            } else {
+                                None
+                            };
                             if !all_finished {
                                 task.set_failed("Some of outputs were not produced".to_string());
+                            } else if let Some(e) = invalid {
+                                task.set_failed(e.to_string());
                             } else {
                                 for output in &task.outputs {
+                                    if let Err(e) = state.compute_checksum(output) {
+                                        warn!("Checksum of output failed: {}", e);
+                                    }
                                     state.object_is_finished(output);
                                 }
                                 debug!("Task was successfully finished");
                                 task.state = TaskState::Finished;
                             }
                         }
-                        Ok((false, _)) => {
-                            debug!("Task {} was terminated", task.id);
-                            task.set_failed("Task terminated by server".into());
-                        }
+                        Ok((false, _)) => match instance.timed_out {
+                            Some(timeout) => {
+                                task.set_failed(format!(
+                                    "Task timed out after {}s (attribute 'timeout')",
+                                    duration_secs(timeout)
+                                ));
+                            }
+                            None => {
+                                debug!("Task {} was terminated", task.id);
+                                task.set_failed("Task terminated by server".into());
+                            }
+                        },
                         Err((e, _)) => {
-                            task.set_failed(e.description().to_string());
+                            task.set_failed(e.to_string());
                         }
                     };
                     Ok(())
@@ -158,6 +240,20 @@ impl TaskInstance {
         );
     }
 
+    /// How long is the task running
+    #[inline]
+    pub fn running_time(&self) -> Duration {
+        self.start_instant.elapsed()
+    }
+
+    /// Cancel the task that exceeded its timeout, it fails with a timeout error
+    pub fn time_out(&mut self, timeout: Duration) {
+        if self.cancel_sender.is_some() {
+            self.timed_out = Some(timeout);
+        }
+        self.stop();
+    }
+
     pub fn stop(&mut self) {
         let cancel_sender = ::std::mem::replace(&mut self.cancel_sender, None);
         if let Some(sender) = cancel_sender {
@@ -168,7 +264,36 @@ impl TaskInstance {
     }
 
     fn start_task_in_subworker(state: &mut State, task_ref: TaskRef) -> TaskResult {
-        let future = state.get_subworker(task_ref.get().task_type.as_ref())?;
+        let limits: ProcessLimits = task_ref
+            .get()
+            .attributes
+            .find("limits")?
+            .unwrap_or_default();
+        if limits.cpu.is_some() {
+            bail!("Limit 'cpu' is supported only for programs run by tasks.Execute");
+        }
+        // Prefer a subworker on the NUMA node that holds most of the inputs
+        let numa_node = preferred_node(task_ref.get().inputs.iter().map(|input| {
+            let obj = input.object.get();
+            (obj.numa_node, obj.size.unwrap_or(0))
+        }));
+        let future = state.get_subworker(task_ref.get().task_type.as_ref(), &limits, numa_node)?;
+        let (cache_first_input, memory_inputs) = state
+            .subworker_kind(task_ref.get().task_type.as_ref())
+            .map(|kind| (kind.cache_first_input, kind.memory_inputs))
+            .unwrap_or((false, false));
+        if memory_inputs
+            && task_ref
+                .get()
+                .inputs
+                .iter()
+                .any(|input| input.object.get().data().is_directory())
+        {
+            bail!(
+                "Subworker type '{}' accepts only blobs (it has 'memory_inputs')",
+                task_ref.get().task_type
+            );
+        }
         let state_ref = state.self_ref();
         Ok(Box::new(future.and_then(move |subworker| {
             // Run task in subworker
@@ -177,7 +302,13 @@ impl TaskInstance {
             // This is can happen when task is terminated and feature dropped without finishhing
             let mut sw_wrapper = KillOnDrop::new(subworker.clone());
 
+            if state_ref.get_mut().chaos_mut().kill_subworker() {
+                warn!("Fault injection: killing {:?}", subworker);
+                subworker.get_mut().kill();
+            }
+
             let mut req = subworker.get().control().run_task_request();
+            let mut serialize_error = None;
             {
                 let task = task_ref.get();
                 debug!("Starting task id={} in subworker", task.id);
@@ -188,6 +319,17 @@ impl TaskInstance {
                 task.attributes
                     .to_capnp(&mut param_task.borrow().get_attributes().unwrap());
 
+                {
+                    let mut p_secrets = param_task
+                        .borrow()
+                        .init_secrets(task.secrets.0.len() as u32);
+                    for (i, &(ref name, ref value)) in task.secrets.0.iter().enumerate() {
+                        let mut p_secret = p_secrets.borrow().get(i as u32);
+                        p_secret.set_name(name);
+                        p_secret.set_value(value);
+                    }
+                }
+
                 param_task.borrow().init_inputs(task.inputs.len() as u32);
                 {
                     // Serialize inputs of task
@@ -203,14 +345,22 @@ impl TaskInstance {
                         } else {
                             // This is caching hack, since we know that 1st argument is function
                             // for Python subworker, we force to cache first argument
-                            if i == 0 {
+                            // (enabled by `cache_first_input` of the subworker kind)
+                            if i == 0 && cache_first_input {
                                 obj.subworker_cache.insert(subworker.clone());
                                 p_input.set_save_in_cache(true);
+                                if let Some(node) = subworker.get().numa_node() {
+                                    obj.numa_node = Some(node);
+                                }
                             }
 
                             {
                                 let mut p_data = p_input.borrow().get_data().unwrap();
-                                obj.data().to_subworker_capnp(&mut p_data.borrow());
+                                if let Err(e) = obj.data()
+                                    .to_subworker_capnp(&mut p_data.borrow(), memory_inputs)
+                                {
+                                    serialize_error = Some(e);
+                                }
                                 obj.attributes
                                     .to_capnp(&mut p_data.borrow().get_attributes().unwrap());
                             }
@@ -233,7 +383,16 @@ impl TaskInstance {
                     }
                 }
             }
-            req.send()
+            if let Some(e) = serialize_error {
+                let subworker_ref = sw_wrapper.deactive();
+                state_ref
+                    .get_mut()
+                    .graph
+                    .idle_subworkers
+                    .insert(subworker_ref);
+                return Box::new(::futures::future::err(e)) as Box<TaskFuture>;
+            }
+            Box::new(req.send()
                 .promise
                 .map_err::<_, Error>(|e| e.into())
                 .then(move |r| {
@@ -256,6 +415,7 @@ impl TaskInstance {
                                     let mut o = output.get_mut();
                                     o.set_attributes(attributes);
                                     o.set_data(data)?;
+                                    o.numa_node = subworker.numa_node();
                                 }
                             } else {
                                 debug!("Task id={} failed in subworker", task.id);
@@ -271,7 +431,7 @@ impl TaskInstance {
                         .idle_subworkers
                         .insert(subworker_ref);
                     result
-                })
+                })) as Box<TaskFuture>
         })))
     }
 }
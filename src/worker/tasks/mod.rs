@@ -1,5 +1,9 @@
 pub mod instance;
 pub mod basic;
 pub mod run;
+pub mod capture;
+pub mod columnar;
+pub mod expression;
+pub mod download;
 
 pub use self::instance::{TaskFuture, TaskInstance, TaskResult};
@@ -1,22 +1,53 @@
+use std::collections::HashMap;
+use std::ffi::OsStr;
+use std::os::unix::ffi::OsStrExt;
 use std::fs::File;
 use std::process::{Command, Stdio};
-use tokio_process::CommandExt;
-use futures::Future;
+use futures::{future, Future};
 use std::os::unix::io::{FromRawFd, IntoRawFd};
-use std::path::Path;
-use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::io::{Read, Seek, SeekFrom, Write};
 
 use super::TaskResult;
-use worker::graph::TaskRef;
+use super::capture::CappedCapture;
+use common::output_policy::{OutputPolicy, Retention};
+use common::units::Size;
+use worker::data::{set_executable, unpack_archive};
+use worker::graph::{DataMode, TaskRef};
 use worker::state::State;
-use errors::Result;
+use worker::limits::ProcessLimits;
+use worker::profile::ProcessUsage;
+use errors::{ErrorKind, Result};
 
-fn read_stderr(path: &Path) -> Result<String> {
-    // TODO: If the file is too big, truncate the beginning
+/// Max size of stdout/stderr tail collected for a failed program
+const MAX_TAIL_SIZE: u64 = 8 * 1024;
+
+/// Read at most `MAX_TAIL_SIZE` bytes from the end of the file
+pub fn read_tail(path: &Path) -> Result<String> {
     let mut file = File::open(path)?;
-    let mut s = String::new();
-    file.read_to_string(&mut s)?;
-    Ok(s)
+    let size = file.metadata()?.len();
+    let mut buffer = Vec::new();
+    if size > MAX_TAIL_SIZE {
+        file.seek(SeekFrom::Start(size - MAX_TAIL_SIZE))?;
+        buffer.extend_from_slice(b"[...] ");
+    }
+    file.read_to_end(&mut buffer)?;
+    Ok(String::from_utf8_lossy(&buffer).into_owned())
+}
+
+/// Context of a failed program, stored in the task attribute "failure"
+#[derive(Serialize)]
+struct FailureDigest {
+    command: Vec<String>,
+    exit_code: Option<i32>,
+    signal: Option<i32>,
+    stdout_tail: Option<String>,
+    stderr_tail: Option<String>,
+    /// Kept stdout/stderr in the log directory of the worker (retention "keep_on_failure")
+    stdout_log: Option<String>,
+    stderr_log: Option<String>,
+    worker: String,
+    host: String,
 }
 
 #[derive(Deserialize)]
@@ -25,33 +56,113 @@ struct RunConfigInput {
     pub write: bool,
 }
 
+/// Script embedded in the task config, the worker writes it into the task directory
+/// and runs it by the interpreter of its language (config args are passed to the script)
+#[derive(Deserialize)]
+struct RunConfigScript {
+    pub language: String,
+    pub source: String,
+}
+
 #[derive(Deserialize)]
 struct RunConfig {
     pub args: Vec<String>,
     pub in_paths: Vec<RunConfigInput>,
     pub out_paths: Vec<String>,
+    pub script: Option<RunConfigScript>,
+    /// Outputs that are stdout/stderr ("+out"/"+err") kept by the output policy
+    /// of the session, they are capped like streams that are not outputs
+    #[serde(default)]
+    pub log_outputs: Vec<String>,
+}
+
+/// Name of the file with the embedded script in the task directory
+const SCRIPT_PATH: &str = "+script";
+
+/// Move a captured stream of a failed task into the log directory, returns the new path
+fn keep_log(path: &Path, log_path: &Path) -> Option<String> {
+    let result = ::std::fs::rename(path, log_path)
+        .or_else(|_| ::std::fs::copy(path, log_path).map(|_| ()));
+    match result {
+        Ok(()) => Some(log_path.to_string_lossy().into_owned()),
+        Err(e) => {
+            warn!("Cannot keep {:?} in {:?}: {}", path, log_path, e);
+            None
+        }
+    }
+}
+
+/// Interpreter of embedded scripts of the language
+fn script_interpreter(language: &str) -> Result<&'static str> {
+    Ok(match language {
+        "sh" => "/bin/sh",
+        "bash" => "bash",
+        "python" => "python3",
+        _ => bail!("Unsupported language of embedded script '{}'", language),
+    })
 }
 
 pub fn task_run(state: &mut State, task_ref: TaskRef) -> TaskResult {
     let state_ref = state.self_ref();
     let config: RunConfig = task_ref.get().attributes.get("config")?;
+    let limits: ProcessLimits = task_ref
+        .get()
+        .attributes
+        .find("limits")?
+        .unwrap_or_default();
+    // Environment of the task merged with the environment of its session by server
+    let env: HashMap<String, String> = task_ref.get().attributes.find("env")?.unwrap_or_default();
+    // Requested size of tmpfs for the working directory
+    let tmpfs_size = task_ref
+        .get()
+        .attributes
+        .find::<Size>("tmpfs")?
+        .map(|s| s.0 as usize);
+    // Retention and size cap of stdout/stderr set for the session
+    let policy: OutputPolicy = task_ref
+        .get()
+        .attributes
+        .find("output_policy")?
+        .unwrap_or_default();
+    let output_limit = policy.effective_max_size(state.task_output_limit());
 
-    let (dir, future, stderr_path) = {
-        // Parse arguments
-        let name = config.args.get(0).ok_or_else(|| "Arguments are empty")?;
+    let (dir, future, stdout_path, stderr_path, args) = {
         let task = task_ref.get();
 
-        let dir = state.work_dir().make_task_temp_dir(task.id)?;
+        let dir = state.work_dir().make_task_dir(task.id, tmpfs_size)?;
+
+        // Parse arguments, an embedded script is the first argument of its interpreter
+        let args = match config.script {
+            Some(ref script) => {
+                let interpreter = script_interpreter(&script.language)?;
+                File::create(dir.path().join(SCRIPT_PATH))?.write_all(script.source.as_bytes())?;
+                let mut args = vec![interpreter.to_string(), SCRIPT_PATH.to_string()];
+                args.extend(config.args.iter().cloned());
+                args
+            }
+            None => config.args.clone(),
+        };
+        if args.is_empty() {
+            bail!("Arguments are empty");
+        }
 
         // Map inputs
         let mut in_io = Stdio::null();
 
         for (iconfig, input) in config.in_paths.iter().zip(&task.inputs) {
             let obj = input.object.get();
-            if iconfig.write {
-                obj.data().write_to_path(&dir.path().join(&iconfig.path))?;
+            let path = dir.path().join(&iconfig.path);
+            let mode = obj.mode()?;
+            if mode == Some(DataMode::Extract) {
+                unpack_archive(state.data_storage().stream(obj.data())?, &path)
+                    .map_err(|e| format!("Input '{}': {}", iconfig.path, e))?;
+            } else if iconfig.write {
+                obj.data().write_to_path(&path)?;
             } else {
-                obj.data().link_to_path(&dir.path().join(&iconfig.path))?;
+                state.data_storage().map(obj.data(), &path)?;
+            }
+            if mode == Some(DataMode::Executable) {
+                set_executable(&path)?;
             }
             if iconfig.path == "+in" {
                 let in_id = File::open(dir.path().join("+in"))?.into_raw_fd();
@@ -59,41 +170,95 @@ pub fn task_run(state: &mut State, task_ref: TaskRef) -> TaskResult {
             }
         }
 
-        // Create files for stdout/stderr
-        let out_id = File::create(dir.path().join("+out"))
-            .expect("File for stdout cannot be opened")
-            .into_raw_fd();
-        let stderr_path = dir.path().join("+err");
-        let err_id = File::create(&stderr_path)
-            .expect("File for stderr cannot be opened")
-            .into_raw_fd();
-
-        let out_io = unsafe { Stdio::from_raw_fd(out_id) };
-        let err_io = unsafe { Stdio::from_raw_fd(err_id) };
+        // Create files for stdout/stderr, streams that are not (plain) outputs are capped
+        let mut captures = Vec::new();
+        let ((stdout_path, out_io), (stderr_path, err_io)) = {
+            let mut open_stream = |name: &str| -> Result<(PathBuf, Stdio)> {
+                let path = dir.path().join(name);
+                let is_output = config.out_paths.iter().any(|p| p == name)
+                    && !config.log_outputs.iter().any(|p| p == name);
+                match output_limit {
+                    Some(limit) if !is_output => {
+                        let (capture, io) = CappedCapture::start(&path, limit)?;
+                        captures.push(capture.done);
+                        Ok((path, io))
+                    }
+                    _ => {
+                        let id = File::create(&path)
+                            .map_err(|e| format!("File for {} cannot be opened: {}", name, e))?
+                            .into_raw_fd();
+                        Ok((path, unsafe { Stdio::from_raw_fd(id) }))
+                    }
+                }
+            };
+            (open_stream("+out")?, open_stream("+err")?)
+        };
 
-        debug!("Starting command: {}", name);
+        debug!("Starting command: {}", args[0]);
 
-        let future = Command::new(&name)
-            .args(&config.args[1..])
+        let mut command = Command::new(&args[0]);
+        command
+            .args(&args[1..])
             .stdin(in_io)
             .stdout(out_io)
             .stderr(err_io)
-            .current_dir(dir.path())
-            .status_async2(state.handle())?;
+            .envs(&env)
+            .current_dir(dir.path());
+        for &(ref name, ref value) in &task.secrets.0 {
+            command.env(name, OsStr::from_bytes(value));
+        }
+        limits.apply(&mut command);
+        // Outputs are read when the process finished and its streams are copied
+        let future = ProcessUsage::spawn(&mut command)?
+            .join(future::join_all(captures))
+            .map(|(result, _)| result);
 
-        (dir, future, stderr_path)
+        (dir, future, stdout_path, stderr_path, args)
+    };
+    let worker = format!("{}", state.worker_id());
+    let log_paths = if policy.retention == Retention::KeepOnFailure {
+        Some(state.log_dir().task_log_paths(task_ref.get().id))
+    } else {
+        None
     };
 
-    Ok(Box::new(future.map_err(|e| e.into()).and_then(
-        move |status| {
+    Ok(Box::new(future.and_then(
+        move |(status, usage)| {
+            task_ref
+                .get_mut()
+                .new_attributes
+                .set("profile", &usage)?;
             if !status.success() {
-                let stderr = match read_stderr(&stderr_path) {
-                    Ok(s) => format!("Stderr: {}\n", s),
-                    Err(e) => format!(
-                        "Stderr could not be obtained: {}",
-                        ::std::error::Error::description(&e)
-                    ),
+                let stderr_tail = read_tail(&stderr_path).ok();
+                let stdout_tail = read_tail(&stdout_path).ok();
+                let (stdout_log, stderr_log) = match log_paths {
+                    Some((ref out, ref err)) => {
+                        (keep_log(&stdout_path, out), keep_log(&stderr_path, err))
+                    }
+                    None => (None, None),
+                };
+                let digest = FailureDigest {
+                    command: args,
+                    exit_code: status.code(),
+                    signal: ::std::os::unix::process::ExitStatusExt::signal(&status),
+                    stdout_tail,
+                    stderr_tail: stderr_tail.clone(),
+                    stdout_log,
+                    stderr_log,
+                    worker,
+                    host: ::common::sys::get_hostname(),
                 };
+                task_ref
+                    .get_mut()
+                    .new_attributes
+                    .set("failure", digest)?;
+                let stderr = match stderr_tail {
+                    Some(s) => format!("Stderr: {}\n", s),
+                    None => "Stderr could not be obtained".to_string(),
+                };
+                if let Some(limit) = limits.detect_exceeded(&status, &usage) {
+                    bail!(ErrorKind::LimitExceeded(limit.to_string(), stderr));
+                }
                 match status.code() {
                     Some(code) => bail!("Program exit with exit code {}\n{}", code, stderr),
                     None => bail!("Program terminated by signal\n{}", stderr),
@@ -109,6 +274,7 @@ pub fn task_run(state: &mut State, task_ref: TaskRef) -> TaskResult {
                         &abs_path,
                         Some(path),
                         &state.work_dir(),
+                        state.data_storage(),
                     )?;
                 }
             }
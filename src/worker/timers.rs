@@ -0,0 +1,161 @@
+use std::cmp::Ordering;
+use std::collections::BinaryHeap;
+use std::time::{Duration, Instant};
+
+use futures::Future;
+use futures::unsync::oneshot;
+
+use common::id::{DataObjectId, SubworkerId, TaskId};
+use errors::Error;
+
+/// How often expired timers are checked; it is also the precision of all timers
+pub const TIMER_TICK_MS: u64 = 100;
+
+/// What is done when a timer expires
+pub enum TimerEvent {
+    /// Wake up a future returned by `Timers::sleep`
+    Wakeup(oneshot::Sender<()>),
+    /// Fail the start of the subworker if it is still not registered
+    SubworkerStart(SubworkerId, Duration),
+    /// Remove the object if it is still in the delete wait list and its time has passed
+    DeleteObject(DataObjectId),
+    /// Cancel the task if it is still running after its timeout (attribute "timeout")
+    TaskTimeout(TaskId, Duration),
+    /// Periodic sending of the monitoring event to the server
+    Monitoring,
+    /// Periodic shipping of logs to the server
+    LogShipping,
+    /// Periodic check whether draining was requested by SIGTERM
+    DrainCheck,
+}
+
+struct Entry {
+    deadline: Instant,
+    /// Timers with the same deadline expire in the order they were added
+    seq: u64,
+    event: TimerEvent,
+}
+
+impl Entry {
+    #[inline]
+    fn key(&self) -> (Instant, u64) {
+        (self.deadline, self.seq)
+    }
+}
+
+impl PartialEq for Entry {
+    fn eq(&self, other: &Entry) -> bool {
+        self.key() == other.key()
+    }
+}
+
+impl Eq for Entry {}
+
+impl PartialOrd for Entry {
+    fn partial_cmp(&self, other: &Entry) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Entry {
+    // Reversed, so the entry with the earliest deadline is on the top of the max-heap
+    fn cmp(&self, other: &Entry) -> Ordering {
+        other.key().cmp(&self.key())
+    }
+}
+
+/// All timers of the worker in one min-heap by deadline. The heap is checked by a single
+/// future every tick (`TIMER_TICK_MS`), so no future is spawned per timer.
+/// Timers are not cancelled; the handler of an event checks whether it is still relevant.
+#[derive(Default)]
+pub struct Timers {
+    heap: BinaryHeap<Entry>,
+    next_seq: u64,
+}
+
+impl Timers {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Number of pending timers
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.heap.len()
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.heap.is_empty()
+    }
+
+    pub fn add(&mut self, deadline: Instant, event: TimerEvent) {
+        let seq = self.next_seq;
+        self.next_seq += 1;
+        self.heap.push(Entry {
+            deadline,
+            seq,
+            event,
+        });
+    }
+
+    #[inline]
+    pub fn add_after(&mut self, delay: Duration, event: TimerEvent) {
+        self.add(Instant::now() + delay, event);
+    }
+
+    /// Future resolved after the given time
+    pub fn sleep(&mut self, delay: Duration) -> Box<Future<Item = (), Error = Error>> {
+        let (sender, receiver) = oneshot::channel();
+        self.add_after(delay, TimerEvent::Wakeup(sender));
+        Box::new(receiver.map_err(|_| "Timer was dropped".into()))
+    }
+
+    /// Remove timers with deadline not later than `now`, in the order of deadlines
+    pub fn expired(&mut self, now: Instant) -> Vec<TimerEvent> {
+        let mut events = Vec::new();
+        while self.heap.peek().map(|e| e.deadline <= now).unwrap_or(false) {
+            events.push(self.heap.pop().unwrap().event);
+        }
+        events
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use common::id::SId;
+
+    fn object_ids(events: Vec<TimerEvent>) -> Vec<i32> {
+        events
+            .into_iter()
+            .map(|e| match e {
+                TimerEvent::DeleteObject(id) => id.get_id(),
+                _ => panic!("Unexpected event"),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_expired_in_order() {
+        let mut timers = Timers::new();
+        let now = Instant::now();
+        let object = |id| TimerEvent::DeleteObject(DataObjectId::new(1, id));
+        timers.add(now + Duration::from_secs(3), object(3));
+        timers.add(now + Duration::from_secs(1), object(1));
+        timers.add(now + Duration::from_secs(2), object(2));
+        timers.add(now + Duration::from_secs(1), object(4));
+        assert_eq!(timers.len(), 4);
+
+        assert!(timers.expired(now).is_empty());
+        assert_eq!(
+            object_ids(timers.expired(now + Duration::from_secs(2))),
+            vec![1, 4, 2]
+        );
+        assert_eq!(
+            object_ids(timers.expired(now + Duration::from_secs(10))),
+            vec![3]
+        );
+        assert!(timers.is_empty());
+    }
+}
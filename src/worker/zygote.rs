@@ -0,0 +1,185 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fs::File;
+use std::io::BufReader;
+use std::os::unix::io::{FromRawFd, IntoRawFd};
+use std::path::Path;
+use std::process::{Command, Stdio};
+
+use futures::{Future, Stream};
+use futures::unsync::{mpsc, oneshot};
+use tokio_core::reactor::Handle;
+use tokio_io::io::{lines, write_all};
+use tokio_process::CommandExt;
+
+use common::id::SubworkerId;
+use common::wrapped::WrappedRcRefCell;
+use common::fs::LogDir;
+use worker::fs::workdir::WorkDir;
+use errors::{Error, Result};
+
+#[derive(Serialize)]
+#[serde(rename_all = "lowercase")]
+enum Request<'a> {
+    Fork {
+        id: SubworkerId,
+        work_dir: &'a Path,
+        stdout: &'a Path,
+        stderr: &'a Path,
+    },
+    Kill {
+        id: SubworkerId,
+    },
+}
+
+#[derive(Deserialize)]
+struct ExitNotification {
+    exited: SubworkerId,
+    status: i32,
+}
+
+/// Initialized process of a subworker type (with imported libraries and loaded models)
+/// that starts new subworkers of the type by forking itself, so they skip
+/// the initialization (warm start).
+/// Requests are sent as JSON lines to stdin of the process, exits of forked
+/// subworkers are reported as JSON lines on its stdout.
+pub struct Zygote {
+    requests: mpsc::UnboundedSender<String>,
+
+    /// Receivers of exit codes of forked subworkers
+    exits: HashMap<SubworkerId, oneshot::Sender<i32>>,
+
+    /// False when the zygote process terminated, a new one has to be started
+    alive: bool,
+}
+
+pub type ZygoteRef = WrappedRcRefCell<Zygote>;
+
+impl Zygote {
+    #[inline]
+    pub fn is_alive(&self) -> bool {
+        self.alive
+    }
+
+    fn send(&self, request: &Request) {
+        let mut line = ::serde_json::to_string(request).unwrap();
+        line.push('\n');
+        if self.requests.unbounded_send(line).is_err() {
+            debug!("Request to terminated zygote dropped");
+        }
+    }
+
+    /// Fork a new subworker. Returned future is finished with the exit code
+    /// of the subworker when it terminates.
+    pub fn fork(
+        &mut self,
+        subworker_id: SubworkerId,
+        work_dir: &Path,
+        stdout: &Path,
+        stderr: &Path,
+    ) -> Box<Future<Item = i32, Error = Error>> {
+        let (sender, receiver) = oneshot::channel();
+        self.exits.insert(subworker_id, sender);
+        self.send(&Request::Fork {
+            id: subworker_id,
+            work_dir,
+            stdout,
+            stderr,
+        });
+        Box::new(receiver.map_err(|_| "Zygote terminated".into()))
+    }
+
+    /// Kill a forked subworker
+    pub fn kill(&mut self, subworker_id: SubworkerId) {
+        self.send(&Request::Kill { id: subworker_id });
+    }
+}
+
+impl ZygoteRef {
+    /// Start the zygote process for the subworker type,
+    /// the process is started with the subworker program and argument `--zygote`
+    pub fn start(
+        handle: &Handle,
+        work_dir: &WorkDir,
+        log_dir: &LogDir,
+        subworker_type: &str,
+        program_name: &str,
+        program_args: &[String],
+        env: &BTreeMap<String, String>,
+    ) -> Result<Self> {
+        let log_path = log_dir.zygote_log_path(subworker_type);
+        info!(
+            "Starting zygote of subworker type={}, log: {:?}",
+            subworker_type, log_path
+        );
+        let log_id = File::create(log_path)
+            .expect("Zygote log cannot be opened")
+            .into_raw_fd();
+        let log_pipe = unsafe { Stdio::from_raw_fd(log_id) };
+        let mut child = Command::new(program_name)
+            .args(program_args)
+            .arg("--zygote")
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(log_pipe)
+            .envs(env)
+            .env("RAIN_SUBWORKER_SOCKET", work_dir.subworker_listen_path())
+            .env("RAIN_SUBWORKER_TYPE", subworker_type)
+            .spawn_async(handle)?;
+        let stdin = child.stdin().take().unwrap();
+        let stdout = child.stdout().take().unwrap();
+
+        let (sender, receiver) = mpsc::unbounded();
+        let zygote = Self::wrap(Zygote {
+            requests: sender,
+            exits: HashMap::new(),
+            alive: true,
+        });
+
+        handle.spawn(
+            receiver
+                .fold(stdin, |stdin, line| {
+                    write_all(stdin, line.into_bytes())
+                        .map(|(stdin, _)| stdin)
+                        .map_err(|e| error!("Sending request to zygote failed: {}", e))
+                })
+                .map(|_| ()),
+        );
+
+        let zygote_ref = zygote.clone();
+        let end_zygote_ref = zygote.clone();
+        handle.spawn(
+            lines(BufReader::new(stdout))
+                .for_each(move |line| {
+                    match ::serde_json::from_str::<ExitNotification>(&line) {
+                        Ok(n) => {
+                            if let Some(sender) = zygote_ref.get_mut().exits.remove(&n.exited) {
+                                let _ = sender.send(n.status);
+                            }
+                        }
+                        Err(_) => warn!("Invalid message from zygote: {:?}", line),
+                    }
+                    Ok(())
+                })
+                .then(move |r| {
+                    if let Err(e) = r {
+                        error!("Reading from zygote failed: {}", e);
+                    }
+                    let mut zygote = end_zygote_ref.get_mut();
+                    zygote.alive = false;
+                    // Waiting subworkers are informed by cancelling the senders
+                    zygote.exits.clear();
+                    Ok(())
+                }),
+        );
+
+        let subworker_type = subworker_type.to_string();
+        handle.spawn(child.then(move |status| {
+            error!(
+                "Zygote of subworker type={} terminated: {:?}",
+                subworker_type, status
+            );
+            Ok(())
+        }));
+        Ok(zygote)
+    }
+}
@@ -78,7 +78,9 @@ class TestEnv(Env):
               listen_addr=None,
               listen_port=None,
               worker_defs=None,
-              delete_list_timeout=None):
+              delete_list_timeout=None,
+              worker_args=(),
+              server_args=()):
         """
         Start infrastructure: server & n workers
         """
@@ -88,6 +90,7 @@ class TestEnv(Env):
         env["RAIN_TEST_MODE"] = "1"
         env["RAIN_DEBUG_MODE"] = "1"
         env["PYTHONPATH"] = PYTHON_DIR
+        self.env = env
 
         if delete_list_timeout is not None:
             env["RAIN_DELETE_LIST_TIMEOUT"] = str(delete_list_timeout)
@@ -121,7 +124,7 @@ class TestEnv(Env):
         args = (RAIN_BIN, "server",
                 "--ready-file", server_ready_file,
                 "--logdir", os.path.join(WORK_DIR, "server"),
-                "--listen", str(addr))
+                "--listen", str(addr)) + tuple(server_args)
         self.server = self.start_process("server", args, env=env)
         assert self.server is not None
 
@@ -148,7 +151,7 @@ class TestEnv(Env):
                     "--ready-file", ready_file,
                     "--cpus", str(cpus),
                     "--logdir", os.path.join(wdir, "logs"),
-                    "--workdir", os.path.join(wdir, "work"))
+                    "--workdir", os.path.join(wdir, "work")) + tuple(worker_args)
             self.workers.append(self.start_process(name, args, env=env))
 
         it = 0
@@ -161,6 +164,40 @@ class TestEnv(Env):
 
         self.check_running_processes()
 
+    def restart(self, **kwargs):
+        """
+        Restart server & workers, workers keep their working directories
+        """
+        worker_defs = self.worker_defs
+        for p in [self.server] + self.workers:
+            os.killpg(os.getpgid(p.pid), signal.SIGTERM)
+            p.wait()
+        for name in os.listdir(WORK_DIR):
+            if name.endswith("-ready"):
+                os.unlink(os.path.join(WORK_DIR, name))
+        self._client = None
+        self.worker_defs = None
+        self.start(worker_defs=worker_defs, **kwargs)
+
+    def restart_worker(self, index):
+        """
+        Restart a single worker, the worker keeps its working directory
+        """
+        p = self.workers[index]
+        os.killpg(os.getpgid(p.pid), signal.SIGTERM)
+        p.wait()
+        name = "worker{}".format(index)
+        ready_file = os.path.join(WORK_DIR, name + "-ready")
+        os.unlink(ready_file)
+        self.workers[index] = self.start_process(name, p.args, env=self.env)
+        it = 0
+        while not os.path.isfile(ready_file):
+            time.sleep(0.05)
+            self.check_running_processes()
+            it += 1
+            if it > 100:
+                raise Exception("Worker not restarted after 5 s")
+
     def check_running_processes(self):
         """Checks that everything is still running"""
         for i, worker in enumerate(self.workers):
@@ -1,4 +1,4 @@
-from rain.client import remote
+from rain.client import remote, tasks
 import time
 
 
@@ -27,3 +27,51 @@ def test_update_attributes(test_env):
         # for now, lets just trim padding
         start = start[:start.index(".") + 6]
         time.strptime(start, '%Y-%m-%dT%H:%M:%S.%f')
+
+
+def test_start_time_by_server_clock(test_env):
+    """Start times of tasks are comparable with the local clock (the worker runs
+    on the same node, so its estimated clock offset against the server is small)"""
+    import datetime
+
+    test_env.start(1)
+    with test_env.client.new_session() as s:
+        before = datetime.datetime.now(datetime.timezone.utc)
+        t1 = tasks.execute("sleep 0.5")
+        t2 = tasks.execute("sleep 0.5")
+        s.submit()
+        s.wait_all()
+        after = datetime.datetime.now(datetime.timezone.utc)
+        t1.update()
+        t2.update()
+        for t in (t1, t2):
+            start = t.attributes["info"]["start"]
+            start = datetime.datetime.strptime(start[:start.index(".") + 7] + "+0000",
+                                               '%Y-%m-%dT%H:%M:%S.%f%z')
+            assert before - datetime.timedelta(seconds=0.1) <= start <= after
+            assert 500 <= int(t.attributes["info"]["duration"]) < 1000
+
+
+def test_profile_attributes(test_env):
+
+    @remote()
+    def burn(ctx):
+        data = bytearray(32 * 1024 * 1024)
+        total = 0
+        for i in range(200000):
+            total += i
+        return bytes(data[:1])
+
+    test_env.start(1)
+    with test_env.client.new_session() as s:
+        t1 = burn()
+        t2 = tasks.execute("dd if=/dev/zero of=f bs=1M count=16", shell=True)
+        s.submit()
+        s.wait_all()
+        t1.update()
+        t2.update()
+        for t in (t1, t2):
+            profile = t.attributes["profile"]
+            assert set(profile) == {"cpu_time", "max_rss", "read_bytes", "write_bytes"}
+            assert profile["max_rss"] > 0
+        assert t1.attributes["profile"]["max_rss"] > 32 * 1024 * 1024
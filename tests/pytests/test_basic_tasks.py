@@ -1,7 +1,11 @@
-from rain.client import tasks, blob, TaskException, directory
+from rain.client import tasks, blob, TaskException, directory, Task
+from rain.common import RainException
 from rain.client import InputDir, OutputDir
 import pytest
 import os
+import functools
+import http.server
+import threading
 
 
 def test_sleep1(test_env):
@@ -40,6 +44,24 @@ def test_concat1(test_env):
         assert t1.output.fetch().get_bytes() == b"Hello world!"
 
 
+def test_identity_select(test_env):
+    """Glue tasks passing inputs through"""
+    test_env.start(1)
+    with test_env.client.new_session() as s:
+        t1 = tasks.identity(blob("abc"))
+        t2 = tasks.select((blob("x"), blob("yz"), t1), 1)
+        t3 = tasks.select((blob("x"), t1), 1)
+        t1.output.keep()
+        t2.output.keep()
+        t3.output.keep()
+        s.submit()
+        assert t1.output.fetch().get_bytes() == b"abc"
+        assert t2.output.fetch().get_bytes() == b"yz"
+        assert t3.output.fetch().get_bytes() == b"abc"
+        with pytest.raises(RainException):
+            tasks.select((blob("x"),), 1)
+
+
 def test_concat2(test_env):
     """Merge empty list of blobs"""
     test_env.start(1)
@@ -64,6 +86,31 @@ def test_concat3(test_env):
         assert t1.output.fetch().get_bytes() == a + c + d + b + c + a
 
 
+def test_concat_pin_in_memory(test_env):
+    """Large blobs pinned in memory"""
+    test_env.start(1)
+    a = b"a123456789" * 1024 * 100
+    with test_env.client.new_session() as s:
+        t1 = tasks.concat((blob(a), blob(a)))
+        t1.output.pin_in_memory()
+        t2 = tasks.concat((t1, blob(a)))
+        t2.output.keep()
+        s.submit()
+        assert t2.output.fetch().get_bytes() == a * 3
+
+
+def test_concat_memory_storage(test_env):
+    """Large blobs on a worker keeping all data in memory"""
+    test_env.start(1, worker_args=("--data-storage", "memory"))
+    a = b"a123456789" * 1024 * 100
+    with test_env.client.new_session() as s:
+        t1 = tasks.concat((blob(a), blob(a)))
+        t2 = tasks.execute(("cat", t1), stdout=True)
+        t2.output.keep()
+        s.submit()
+        assert t2.output.fetch().get_bytes() == a * 2
+
+
 def test_chain_concat(test_env):
     test_env.start(1)
     with test_env.client.new_session() as s:
@@ -77,6 +124,46 @@ def test_chain_concat(test_env):
         assert t5.output.fetch().get_bytes() == b"abcdef"
 
 
+def test_csv_to_parquet(test_env):
+    csv = b"name,count,price\na,1,1.5\nb,2,2.5\nc,3,3.5\n"
+    test_env.start(1)
+    with test_env.client.new_session() as s:
+        t1 = tasks.csv_to_parquet(blob(csv))
+        t2 = tasks.parquet_select(t1, columns=("name", "price"), limit=2)
+        t1.output.keep()
+        t2.output.keep()
+        s.submit()
+        for t in (t1, t2):
+            data = t.output.fetch().get_bytes()
+            assert data[:4] == b"PAR1" and data[-4:] == b"PAR1"
+        assert t1.output.content_type == "parquet"
+
+    with test_env.client.new_session() as s:
+        t1 = tasks.parquet_select(tasks.csv_to_parquet(blob(csv)), columns=("xyz",))
+        t1.output.keep()
+        s.submit()
+        with pytest.raises(TaskException, match="Column 'xyz' not found"):
+            t1.wait()
+
+
+def test_jmespath(test_env):
+    data = {"models": [{"name": "a", "enabled": True},
+                       {"name": "b", "enabled": False},
+                       {"name": "c", "enabled": True}]}
+    test_env.start(1)
+    with test_env.client.new_session() as s:
+        t1 = tasks.jmespath(blob(data, encode="json"), "models[?enabled].name")
+        t1.output.keep()
+        s.submit()
+        assert t1.output.fetch().load() == ["a", "c"]
+
+    with test_env.client.new_session() as s:
+        t1 = tasks.jmespath(blob("{}", content_type="json"), "models[?")
+        s.submit()
+        with pytest.raises(TaskException, match="Invalid expression"):
+            t1.wait()
+
+
 def test_sleep3_last(test_env):
     test_env.start(1)
     with test_env.client.new_session() as s:
@@ -245,3 +332,72 @@ def test_make_directory(test_env):
         with open(os.path.join(test_env.work_dir, "rdir", "mydir", "d1b", "file.txt")) as f:
             assert f.read() == "My data 4"
         #  TODO: assert os.path.isdir(os.path.join(test_env.work_dir, "rdir", "mydir", "d2"))
+
+
+def test_task_group(test_env):
+    test_env.start(1)
+    with test_env.client.new_session() as s:
+        for i in range(5):
+            Task("!sleep", 10, inputs=(blob("data"),), outputs=1, group="array")
+        s.submit()
+        s.wait_group("array")
+        status = s.group_status("array")
+        assert status == {"total": 5, "finished": 5, "running": 0, "failed": []}
+
+
+def test_session_stats(test_env):
+    test_env.start(1)
+    with test_env.client.new_session() as s:
+        for i in range(3):
+            tasks.concat((blob("abc"), blob("de")))
+        s.submit()
+        s.wait_all()
+        stats = s.stats()
+        assert stats["finished"] == 3
+        assert stats["waiting"] == stats["ready"] == stats["running"] == stats["failed"] == 0
+        assert stats["produced_bytes"] == 3 * (3 + 2 + 5)
+        assert stats["failures"] == {}
+
+    with test_env.client.new_session() as s:
+        t = tasks.execute("exit 1", shell=True)
+        s.submit()
+        with pytest.raises(TaskException):
+            t.wait()
+        stats = s.stats()
+        assert stats["failed"] == 1
+        assert stats["failures"] == {"exit_code": 1}
+
+
+def test_download(test_env):
+    """Download files over http, the second task takes the file from the cache"""
+    path = os.path.join(test_env.work_dir, "download")
+    os.makedirs(path, exist_ok=True)
+    with open(os.path.join(path, "data.txt"), "wb") as f:
+        f.write(b"Downloaded data\n")
+    handler = functools.partial(http.server.SimpleHTTPRequestHandler, directory=path)
+    httpd = http.server.HTTPServer(("127.0.0.1", 0), handler)
+    threading.Thread(target=httpd.serve_forever, daemon=True).start()
+    url = "http://127.0.0.1:{}/data.txt".format(httpd.server_address[1])
+
+    try:
+        test_env.start(1)
+        for cached in (False, True):
+            with test_env.client.new_session() as s:
+                t = tasks.download(url)
+                t.output.keep()
+                s.submit()
+                assert t.output.fetch().get_bytes() == b"Downloaded data\n"
+                t.update()
+                assert t.attributes["download"]["cached"] == cached
+                assert t.attributes["download"]["size"] == 16
+
+        with test_env.client.new_session() as s:
+            t = tasks.download(url + ".missing")
+            s.submit()
+            with pytest.raises(TaskException):
+                t.wait()
+
+        with pytest.raises(RainException):
+            tasks.download("ftp://127.0.0.1/data.txt")
+    finally:
+        httpd.shutdown()
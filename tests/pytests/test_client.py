@@ -1,5 +1,5 @@
 from rain.client import rpc, session, tasks, blob
-from rain.client import RainException, TaskException
+from rain.client import RainException, TaskException, IdCollisionException
 from rain.client import Program
 
 import pytest
@@ -111,6 +111,141 @@ def test_submit(test_env):
         assert t2.state == rpc.common.TaskState.notAssigned
 
 
+def test_graph_snapshot(test_env):
+    test_env.start(1)
+    client = test_env.client
+    with client.new_session() as s:
+        t1 = tasks.concat((blob("a"), blob("b")))
+        t1.output.keep()
+        s.submit()
+        t1.wait()
+        with client.new_session() as s2:
+            snapshot = client.get_graph_snapshot(s)
+            assert snapshot["version"] == 1
+            assert [session["id"] for session in snapshot["sessions"]] == [s.session_id]
+            assert len(snapshot["tasks"]) == 1
+            task = snapshot["tasks"][0]
+            assert task["state"] == "finished"
+            assert task["task_type"] == "!concat"
+            assert len(task["inputs"]) == 2
+            output = [o for o in snapshot["objects"] if o["producer"] is not None]
+            assert len(output) == 1
+            assert output[0]["keep"]
+            assert len(snapshot["workers"]) == 1
+            capabilities = snapshot["workers"][0]["capabilities"]
+            assert "task:!concat" in capabilities
+            assert "subworker:py" in capabilities
+            ids = [session["id"] for session in client.get_graph_snapshot()["sessions"]]
+            assert s.session_id in ids and s2.session_id in ids
+
+
+def test_large_graph_snapshot(test_env):
+    """Snapshots from 1000 tasks and objects are serialized by a separate thread"""
+    import json
+    import urllib.request
+    test_env.start(1)
+    client = test_env.client
+    with client.new_session() as s:
+        ts = [tasks.concat((blob(str(i)),)) for i in range(400)]
+        s.submit()
+        s.wait_all()
+        snapshot = client.get_graph_snapshot(s)
+        assert len(snapshot["tasks"]) == 400
+        assert len(snapshot["objects"]) == 800
+        assert {t["id"]["id"] for t in snapshot["tasks"]} == {t.id.id for t in ts}
+
+        url = "http://127.0.0.1:8080/api/v1/graph?session={}".format(s.session_id)
+        with urllib.request.urlopen(url) as response:
+            assert json.loads(response.read().decode())["tasks"] == snapshot["tasks"]
+
+
+def test_upgrade_status(test_env):
+    test_env.start(1)
+    client = test_env.client
+    assert client.get_upgrade_status()["done"]
+    snapshot = client.get_graph_snapshot()
+    image = snapshot["workers"][0]["image"]
+    assert image
+
+    # All workers already run the image
+    client.upgrade_workers(image, batch=2)
+    for i in range(50):
+        status = client.get_upgrade_status()
+        if status["done"]:
+            break
+        time.sleep(0.1)
+    assert status["image"] == image
+    assert status["outdated"] == 0
+    assert status["upgraded"] == 1
+
+    with pytest.raises(Exception):
+        client.upgrade_workers(image, batch=0)
+
+
+def test_configure_scheduler(test_env):
+    test_env.start(1)
+    client = test_env.client
+    status = client.configure_scheduler()
+    assert not status["paused"]
+    assert status["placement"] == "any"
+
+    status = client.configure_scheduler(paused=True, placement="pack",
+                                        scheduling_interval="100ms")
+    assert status["paused"]
+    assert status["placement"] == "pack"
+    assert status["scheduling_interval"] == 0.1
+    assert client.get_server_info()["scheduler"]["paused"]
+    with client.new_session() as s:
+        t = tasks.concat((blob("a"), blob("b")))
+        t.output.keep()
+        s.submit()
+        time.sleep(0.3)
+        assert s.stats()["finished"] == 0
+        assert client.configure_scheduler()["ready_tasks"] == 1
+
+        status = client.configure_scheduler(paused=False)
+        assert not status["paused"]
+        assert status["placement"] == "pack"
+        assert t.output.fetch().get_bytes() == b"ab"
+
+    with pytest.raises(Exception):
+        client.configure_scheduler(locality_weight=-1)
+    with pytest.raises(Exception):
+        client.configure_scheduler(placement="random")
+
+
+def test_submit_id_collision(test_env):
+    test_env.start(1)
+    client = test_env.client
+    with client.new_session() as s:
+        t1 = tasks.concat((blob("a"), blob("b")))
+        s.submit()
+        t2 = tasks.concat((blob("c"), blob("d")))
+        t2.id = t1.id
+        with pytest.raises(IdCollisionException) as e:
+            s.submit()
+        assert e.value.task_ids == [t1.id]
+        assert e.value.object_ids == []
+        t1.wait()
+
+
+def test_submit_server_ids(test_env):
+    test_env.start(1)
+    client = test_env.client
+    with client.new_session(server_ids=True) as s:
+        t1 = tasks.concat((blob("a"), blob("b")))
+        t1.output.keep()
+        assert t1.id.id < 0
+        s.submit()
+        t2 = tasks.concat((t1.output, blob("c")))
+        t2.output.keep()
+        s.submit()
+        ids = [t1.id, t2.id, t1.output.id, t2.output.id]
+        assert all(i.id >= 0 for i in ids)
+        assert len(set(ids[:2])) == 2 and len(set(ids[2:])) == 2
+        assert t2.output.fetch().get_bytes() == b"abc"
+
+
 @pytest.mark.xfail(reason="wait_some not implemented")
 def test_wait_some(test_env):
     test_env.start(1)
@@ -223,6 +358,22 @@ def test_unkeep_finished(test_env):
         t2.wait()
 
 
+def test_unkeep_deletes_on_worker(test_env):
+    """Unkept object is deleted on the worker at once (not kept in its delete wait list)"""
+    test_env.start(1)
+    client = test_env.client
+    with client.new_session() as s:
+        t1 = tasks.concat((blob("a" * 1000), blob("b")))
+        t1.output.keep()
+        s.submit()
+        t1.wait()
+        t1.output.unkeep()
+        time.sleep(0.3)
+        worker = client.get_server_info()["workers"][0]
+        assert t1.output.id not in worker["objects"]
+        assert worker["freed_bytes"] >= 1001
+
+
 def test_unkeep_unfinished(test_env):
     test_env.start(1)
     client = test_env.client
@@ -356,3 +507,713 @@ def test_fetch_outputs(test_env):
 
         assert len(a.get_bytes()) > 4
         assert b[0].get_bytes() + b[1].get_bytes() == a.get_bytes()
+
+
+def test_fetch_range_and_info(test_env):
+    import zlib
+    test_env.start(1)
+    with test_env.client.new_session() as s:
+        data = bytes(range(256)) * 1000
+        t0 = tasks.concat((blob(data[:1000]), blob(data[1000:])))
+        t0.keep_outputs()
+        s.submit()
+        t0.wait()
+        assert t0.output.fetch(offset=100, length=50).get_bytes() == data[100:150]
+        assert t0.output.fetch(offset=255000).get_bytes() == data[255000:]
+        assert t0.output.fetch(offset=len(data)).get_bytes() == b""
+        info = t0.output.get_info()
+        assert info["size"] == len(data)
+        assert info["checksum"] is None
+        info = t0.output.get_info(checksum=True)
+        assert info["checksum"] == "crc32:{:08x}".format(zlib.crc32(data))
+
+
+def test_invalidate(test_env):
+    test_env.start(1, server_args=("--invalidation-limit", "2"))
+    with test_env.client.new_session() as s:
+        t0 = tasks.concat((blob(b"a"), blob(b"b")))
+        t1 = tasks.concat((t0, blob(b"c")))
+        t0.keep_outputs()
+        t1.keep_outputs()
+        s.submit()
+        assert t1.output.fetch().get_bytes() == b"abc"
+
+        ids = t0.output.invalidate("checksum mismatch")
+        assert sorted(ids) == sorted([t0.id, t1.id])
+        assert t1.output.fetch().get_bytes() == b"abc"
+        assert t0.output.fetch().get_bytes() == b"ab"
+        s.wait_all()
+
+        # t0, t1 and t2 would be computed again, over the limit
+        t2 = tasks.concat((t1, blob(b"d")))
+        t2.keep_outputs()
+        s.submit()
+        t2.wait()
+        with pytest.raises(Exception):
+            t0.output.invalidate()
+        assert t2.output.fetch().get_bytes() == b"abcd"
+
+
+def test_incremental_submit(test_env):
+    test_env.start(1)
+    with test_env.client.new_session() as s:
+        def build(suffix):
+            t0 = tasks.concat((blob(b"a"), blob(b"b")))
+            t0.keep_outputs()
+            t1 = tasks.concat((t0, blob(suffix)))
+            t1.keep_outputs()
+            return t0, t1
+
+        t0, t1 = build(b"c")
+        s.submit()
+        assert t1.output.fetch().get_bytes() == b"abc"
+
+        t0b, t1b = build(b"d")
+        assert s.diff() == ([t1b], [t0b])
+        s.submit(incremental=True)
+        assert t0b.id == t0.id
+        assert t0b.output.id == t0.output.id
+        assert t1b.id != t1.id
+        assert t1b.output.fetch().get_bytes() == b"abd"
+        assert t0b.output.fetch().get_bytes() == b"ab"
+
+        # Identical graph, nothing is computed again
+        t0c, t1c = build(b"d")
+        assert s.diff() == ([], [t0c, t1c])
+        s.submit(incremental=True)
+        assert t1c.output.id == t1b.output.id
+        assert t1c.output.fetch().get_bytes() == b"abd"
+
+
+def test_access_roles(test_env):
+    import os
+    from rain.client import Client
+    access_file = os.path.join(test_env.work_dir, "access")
+    with open(access_file, "w") as f:
+        f.write("# name role token\n"
+                "alice admin t-admin\n"
+                "bob user t-user\n"
+                "carol readonly t-readonly\n")
+    test_env.start(1, server_args=("--access-file", access_file))
+    port = test_env.running_port
+
+    with pytest.raises(Exception, match="invalid access token"):
+        Client("127.0.0.1", port, token="t-wrong")
+
+    readonly = Client("127.0.0.1", port, token="t-readonly")
+    assert len(readonly.get_server_info()["workers"]) == 1
+    with pytest.raises(Exception, match="Permission denied"):
+        readonly.new_session()
+
+    with pytest.raises(Exception, match="Permission denied"):
+        readonly.set_secret("x", "y")
+
+    user = Client("127.0.0.1", port, token="t-user")
+    with user.new_session() as s:
+        t1 = tasks.concat((blob("a"), blob("b")))
+        t1.output.keep()
+        s.submit()
+        assert t1.output.fetch().get_bytes() == b"ab"
+    with pytest.raises(Exception, match="Permission denied"):
+        user._service.terminateServer().wait()
+
+    admin = Client("127.0.0.1", port, token="t-admin")
+    admin.set_secret("x", "y")
+    with user.new_session() as s:
+        tasks.execute("echo $X", secrets={"X": "x"})
+        with pytest.raises(Exception, match="Secret 'x' not found"):
+            s.submit()
+    with pytest.raises(Exception, match="Secret 'x' not found"):
+        user.remove_secret("x")
+
+
+def test_http_access_control(test_env):
+    import os
+    import urllib.error
+    import urllib.request
+    access_file = os.path.join(test_env.work_dir, "access")
+    with open(access_file, "w") as f:
+        f.write("carol readonly t-readonly\n")
+    test_env.start(1, server_args=("--access-file", access_file,
+                                   "--http-cors-origin", "https://ui.example.com"))
+
+    def request(path, token=None, method="GET", origin=None):
+        req = urllib.request.Request("http://127.0.0.1:8080" + path, method=method)
+        if token is not None:
+            req.add_header("Authorization", "Bearer " + token)
+        if origin is not None:
+            req.add_header("Origin", origin)
+        try:
+            with urllib.request.urlopen(req) as response:
+                return response.status, response.headers
+        except urllib.error.HTTPError as e:
+            return e.code, e.headers
+
+    for path in ("/api/v1/sessions", "/lite", "/explain/1/1", "/"):
+        assert request(path)[0] == 401
+        assert request(path, token="t-wrong")[0] == 401
+        assert request(path, token="t-readonly")[0] == 200
+
+    status, headers = request("/api/v1/sessions", origin="https://ui.example.com")
+    assert status == 401
+    assert headers["WWW-Authenticate"] == "Bearer"
+    assert headers["Access-Control-Allow-Origin"] == "https://ui.example.com"
+
+    # Preflight requests are answered without a token
+    status, headers = request("/api/v1/sessions", method="OPTIONS",
+                              origin="https://ui.example.com")
+    assert status == 204
+    assert headers["Access-Control-Allow-Origin"] == "https://ui.example.com"
+    assert "Authorization" in headers["Access-Control-Allow-Headers"]
+    status, headers = request("/api/v1/sessions", method="OPTIONS",
+                              origin="https://evil.example.com")
+    assert status == 204
+    assert headers["Access-Control-Allow-Origin"] is None
+
+
+def test_http_delegated_auth_forbidden(test_env):
+    import os
+    import urllib.error
+    import urllib.request
+    access_file = os.path.join(test_env.work_dir, "access")
+    with open(access_file, "w") as f:
+        f.write("carol readonly t-readonly\n")
+    test_env.start(1, server_args=("--access-file", access_file,
+                                   "--http-trusted-proxy", "127.0.0.1",
+                                   "--http-auth-header", "X-Remote-User"))
+
+    def status(path, user):
+        req = urllib.request.Request("http://127.0.0.1:8080" + path)
+        req.add_header("X-Remote-User", user)
+        try:
+            with urllib.request.urlopen(req) as response:
+                return response.status
+        except urllib.error.HTTPError as e:
+            return e.code
+
+    for path in ("/events", "/lite", "/api/v1/sessions"):
+        assert status(path, "carol") != 403
+        assert status(path, "mallory") == 403
+
+
+def http_get(path, headers=()):
+    import urllib.error
+    import urllib.request
+    req = urllib.request.Request("http://127.0.0.1:8080" + path)
+    for name, value in headers:
+        req.add_header(name, value)
+    try:
+        with urllib.request.urlopen(req) as response:
+            return response.status, response.read().decode()
+    except urllib.error.HTTPError as e:
+        return e.code, e.read().decode()
+
+
+def get_events(event_type):
+    """Events of the type from the event log of the server (JSON API)"""
+    import json
+    import urllib.request
+    criteria = {"event_type": {"value": event_type, "mode": "="}}
+    req = urllib.request.Request("http://127.0.0.1:8080/api/v1/events",
+                                 data=json.dumps(criteria).encode(), method="POST")
+    with urllib.request.urlopen(req) as response:
+        return [e["event"] for e in json.loads(response.read().decode())]
+
+
+def test_http_trusted_proxy(test_env):
+    test_env.start(1, server_args=("--http-base-path", "/rain",
+                                   "--http-trusted-proxy", "127.0.0.1",
+                                   "--http-auth-header", "X-Remote-User"))
+    user = ("X-Remote-User", "alice")
+
+    # Delegated authentication
+    assert http_get("/lite")[0] == 401
+    assert http_get("/lite", [user])[0] == 200
+
+    # Proxies may forward the path with or without the prefix
+    assert "Dashboard Lite" in http_get("/rain/lite", [user])[1]
+    assert "Dashboard Lite" in http_get("/lite", [user])[1]
+    assert "Dashboard Lite" not in http_get("/rainbow/lite", [user])[1]
+
+    status, html = http_get("/rain/", [user])
+    assert status == 200
+    assert 'window.RAIN_BASE_PATH="/rain"' in html
+
+    # The prefix of a trusted proxy overrides the base path, if it is a plain path
+    status, html = http_get("/", [user, ("X-Forwarded-Prefix", "/other/")])
+    assert 'window.RAIN_BASE_PATH="/other"' in html
+    status, html = http_get("/", [user, ("X-Forwarded-Prefix", '/x"><script>')])
+    assert '/x"' not in html
+    assert 'window.RAIN_BASE_PATH="/rain"' in html
+
+
+def test_http_untrusted_proxy(test_env):
+    test_env.start(1, server_args=("--http-base-path", "/rain",
+                                   "--http-trusted-proxy", "10.0.0.5",
+                                   "--http-auth-header", "X-Remote-User"))
+    # Headers from other addresses than the trusted proxy are ignored
+    headers = [("X-Remote-User", "alice"), ("X-Forwarded-Prefix", "/other")]
+    assert http_get("/lite", headers)[0] == 401
+    assert http_get("/", headers)[0] == 401
+
+
+def test_observer(test_env):
+    from rain.client import Client
+    test_env.start(1)
+    observer = Client("127.0.0.1", test_env.running_port, observer=True)
+    with pytest.raises(Exception, match="Permission denied"):
+        observer.new_session()
+
+    with test_env.client.new_session() as s:
+        changes = observer.watch_session(s.session_id)
+        assert changes["tasks"] == [] and not changes["finished"]
+
+        t1 = tasks.sleep(0.3, blob("a"))
+        t2 = tasks.concat((t1, blob("b")))
+        s.submit()
+        observed = {}
+        for changes in observer.follow_session(s.session_id):
+            observed.update(changes["tasks"])
+        assert observed == {t1.id.id: "finished", t2.id.id: "finished"}
+        assert observer.watch_session(s.session_id)["finished"]
+
+        with pytest.raises(Exception, match="Permission denied"):
+            observer._service.closeSession(s.session_id).wait()
+
+
+def test_tls_rpc(test_env):
+    import os
+    import shutil
+    import subprocess
+    from rain.client import Client
+    if shutil.which("openssl") is None:
+        pytest.skip("openssl not available")
+    cert = os.path.join(test_env.work_dir, "server.crt")
+    key = os.path.join(test_env.work_dir, "server.key")
+    subprocess.check_call(("openssl", "req", "-x509", "-newkey", "rsa:2048", "-nodes",
+                           "-keyout", key, "-out", cert, "-days", "1",
+                           "-subj", "/CN=127.0.0.1",
+                           "-addext", "subjectAltName=IP:127.0.0.1"))
+    test_env.start(1,
+                   server_args=("--tls-cert", cert, "--tls-key", key),
+                   worker_args=("--server-tls-ca", cert))
+    port = test_env.running_port
+
+    client = Client("127.0.0.1", port, tls_ca=cert)
+    with client.new_session() as s:
+        t1 = tasks.concat((blob("a"), blob("b")))
+        t1.output.keep()
+        s.submit()
+        assert t1.output.fetch().get_bytes() == b"ab"
+
+
+def test_session_monitor(test_env):
+    from rain.client import SessionMonitor
+    test_env.start(1)
+    client = test_env.client
+    with client.new_session() as s:
+        t1 = tasks.concat((blob("a"), blob("b")))
+        t2 = tasks.sleep(0.3, t1)
+        t2.output.keep()
+        s.submit()
+        monitor = SessionMonitor("http://127.0.0.1:8080", s)
+        changed = monitor.poll()
+        assert sorted(task_id for task_id, _ in changed) == [t1.id.id, t2.id.id]
+        assert sum(monitor.counts.values()) == 2
+        s.wait_all()
+        monitor.poll()
+        assert monitor.state == "finished"
+        assert monitor.counts == {"finished": 2}
+        assert monitor.progress == 1.0
+        assert monitor.poll() == []
+
+
+def test_redacted_snapshot(test_env):
+    import json
+    test_env.start(1, server_args=("--redact=config:ARGS", "--redact=env:*token*"))
+    client = test_env.client
+    with client.new_session() as s:
+        t1 = tasks.execute("true", shell=True, env={"API_TOKEN": "xyz", "LANG": "C"})
+        s.submit()
+        t1.wait()
+        snapshot = client.get_graph_snapshot(s)
+        items = snapshot["tasks"][0]["attributes"]["items"]
+        assert json.loads(items["config"])["args"] == "<redacted>"
+        assert json.loads(items["env"]) == {"API_TOKEN": "<redacted>", "LANG": "C"}
+        # The client sees its task unchanged
+        t1.update()
+        assert t1.attributes["env"]["API_TOKEN"] == "xyz"
+
+
+def test_find_tasks_by_tags(test_env):
+    import json
+    import urllib.request
+    test_env.start(1)
+
+    def find(query):
+        url = "http://127.0.0.1:8080/api/v1/tasks?" + query
+        with urllib.request.urlopen(url) as response:
+            return json.loads(response.read().decode())
+
+    with test_env.client.new_session(tags={"sample": "S123"}) as s:
+        t1 = tasks.sleep(0.01, blob("a"), tags={"stage": "qc"})
+        t2 = tasks.sleep(0.01, blob("b"), tags={"stage": "align"})
+        t3 = tasks.sleep(0.01, blob("c"), tags={"stage": "qc", "sample": "S124"})
+        s.submit()
+        s.wait_all()
+        found = find("tag=stage%3Dqc&tag=sample=S123")
+        assert [t["id"]["id"] for t in found] == [t1.id.id]
+        assert found[0]["tags"] == {"sample": "S123", "stage": "qc"}
+        assert found[0]["state"] == "finished"
+        assert len(find("tag=sample=S123")) == 2
+        assert find("tag=stage=qc&state=running") == []
+        assert find("tag=stage=missing") == []
+        assert len(find("session={}".format(s.session_id))) == 3
+        assert {t["id"]["id"] for t in find("tag=stage=qc")} == {t1.id.id, t3.id.id}
+        assert [t["id"]["id"] for t in find("tag=stage=align")] == [t2.id.id]
+
+
+def test_explain_scheduling(test_env):
+    import json
+    import urllib.request
+    test_env.start(worker_defs=(1, 2))
+
+    def get(path):
+        with urllib.request.urlopen("http://127.0.0.1:8080" + path) as response:
+            return json.loads(response.read().decode())
+
+    with test_env.client.new_session() as s:
+        t1 = tasks.sleep(0.01, blob("a"), cpus=2)
+        s.submit()
+        t1.wait()
+        path = "explain/{}/{}".format(t1.id.session_id, t1.id.id)
+        for decision in (get("/" + path), get("/api/v1/" + path)):
+            assert decision["task"] == {"session_id": t1.id.session_id, "id": t1.id.id}
+            candidates = decision["candidates"]
+            assert len(candidates) == 2
+            chosen = [c for c in candidates if c["worker"] == decision["worker"]]
+            assert chosen[0]["rejected"] is None
+            rejected = [c["rejected"] for c in candidates if c["worker"] != decision["worker"]]
+            assert rejected == ["task needs 2 cpus, worker has 1"]
+        assert get("/api/v1/explain/{}/12345".format(t1.id.session_id)) is None
+
+
+def test_bulk_operations_by_tags(test_env, tmpdir):
+    test_env.start(1)
+    with test_env.client.new_session(tags={"sample": "S123"}) as s:
+        t1 = tasks.sleep(0.01, blob("first"), tags={"stage": "qc"})
+        t2 = tasks.sleep(0.01, blob("second"), tags={"stage": "qc"})
+        t3 = tasks.sleep(0.01, blob("third"), tags={"stage": "align"})
+        for t in (t1, t2, t3):
+            t.output.keep()
+        s.submit()
+        s.wait_all()
+
+        kept = s.list_kept(tags={"stage": "qc", "sample": "S123"})
+        assert sorted(o["id"] for o in kept) == sorted([t1.output.id, t2.output.id])
+        path = str(tmpdir.join("qc"))
+        s.fetch_kept_archive(tags={"stage": "qc"}).write(path)
+        assert sorted(tmpdir.join("qc").listdir()) == [
+            tmpdir.join("qc", "{}-output".format(t.output.id.id)) for t in (t1, t2)]
+        assert tmpdir.join("qc", "{}-output".format(t1.output.id.id)).read() == "first"
+        assert len(s.list_kept()) == 3
+        assert s.unkeep_matching(tags={"stage": "align"}) == 1
+        assert not t3.output.is_kept()
+
+        slow = tasks.sleep(5, blob("x"), tags={"stage": "slow"})
+        after = tasks.sleep(0.01, slow.output)
+        other = tasks.sleep(0.01, blob("y"), tags={"stage": "fast"})
+        after.output.keep()
+        other.output.keep()
+        s.submit()
+        time.sleep(0.3)
+        start = time.time()
+        assert sorted(s.cancel_tasks({"stage": "slow"})) == sorted([slow.id, after.id])
+        assert not after.output.is_kept()
+        s.wait_all()
+        assert time.time() - start < 2
+        assert other.output.fetch().get_bytes() == b"y"
+        assert s.cancel_tasks({"stage": "missing"}) == []
+        with pytest.raises(RainException):
+            s.cancel_tasks({})
+
+
+def test_retry_failed(test_env, tmpdir):
+    test_env.start(1)
+    counter = tmpdir.join("counter")
+    marker = tmpdir.join("marker")
+    with test_env.client.new_session() as s:
+        s.set_keep_failed()
+        first = tasks.execute("echo run >> {}; echo data".format(counter),
+                              shell=True, stdout=True, tags={"stage": "first"})
+        check = tasks.execute("test -f {} && cat".format(marker), shell=True,
+                              stdin=first, stdout=True, tags={"stage": "check"})
+        last = tasks.execute("cat", stdin=check, stdout=True)
+        last.output.keep()
+        s.submit()
+        with pytest.raises(TaskException):
+            s.wait_all()
+
+        marker.write("")
+        assert s.retry_failed(tags={"stage": "first"}) == []
+        assert s.retry_failed() == [check.id]
+        s.wait_all()
+        assert last.output.fetch().get_bytes() == b"data\n"
+        # The finished task was not computed again
+        assert counter.read() == "run\n"
+        with pytest.raises(Exception):
+            s.retry_failed()
+
+
+def test_sample_rate(test_env):
+    test_env.start(2)
+    with test_env.client.new_session() as s:
+        with pytest.raises(RainException):
+            s.set_sample_rate(0.5)
+    with test_env.client.new_session(server_ids=True) as s:
+        s.set_sample_rate(1)
+        same = tasks.execute("echo data", shell=True, stdout=True)
+        differs = tasks.execute("date +%N", shell=True, stdout=True)
+        s.submit()
+        s.wait_all()
+        same.update()
+        differs.update()
+        assert same.attributes["sample"]["match"]
+        assert not differs.attributes["sample"]["match"]
+        stats = s.stats()
+        assert stats["sampled"] == 2
+        assert stats["sample_mismatches"] == [differs.id]
+
+
+def test_priority_preemption(test_env):
+    test_env.start(1, n_cpus=1)
+    with test_env.client.new_session() as s:
+        low = tasks.execute("sleep 1.5", priority=-1, stdout=True)
+        low.keep()
+        s.submit()
+        time.sleep(0.3)
+        start = time.time()
+        high = tasks.execute("sleep 0.2", priority=1, stdout=True)
+        s.submit()
+        high.wait()
+        assert time.time() - start < 1.0
+        low.wait()
+        assert low.output.fetch().get_bytes() == b""
+
+
+def test_expected_duration(test_env):
+    test_env.start(1, n_cpus=1)
+    with test_env.client.new_session() as s:
+        with pytest.raises(RainException):
+            tasks.sleep(0.1, blob(b"x"), duration=-1)
+        short = tasks.sleep(0.2, blob(b"a"), duration=0.2)
+        first = tasks.sleep(0.2, blob(b"b"), duration=5)
+        second = tasks.sleep(0.2, first.output, duration=5)
+        s.submit()
+        s.wait_all()
+        for t in (short, first, second):
+            t.update()
+        # The chain of long expected durations starts first
+        assert first.attributes["info"]["start"] < short.attributes["info"]["start"]
+        assert second.output.fetch().get_bytes() == b"b"
+
+
+def test_suggest_resources(test_env):
+    test_env.start(1, n_cpus=2)
+    with test_env.client.new_session() as s:
+        for i in range(3):
+            tasks.execute("sleep 0.3", cpus=2)
+        s.submit()
+        assert s.suggest_resources() == {}
+        s.wait_all()
+
+        t = tasks.execute("sleep 0.3", cpus=2)
+        other = tasks.execute("sleep 0.2", cpus=2)
+        suggestions = s.suggest_resources()
+        assert list(suggestions) == [t]
+        assert suggestions[t]["cpus"] == 1
+        assert suggestions[t]["memory"] > 0
+        s.submit(autotune=True)
+        assert t.attributes["resources"]["cpus"] == 1
+        assert other.attributes["resources"]["cpus"] == 2
+        s.wait_all()
+
+
+def test_export_import_session(test_env):
+    import json
+    import os
+    import subprocess
+    from conftest import RAIN_BIN
+    test_env.start(1)
+    address = "127.0.0.1:{}".format(test_env.running_port)
+    with test_env.client.new_session() as s:
+        b = blob("abc")
+        t = tasks.concat((b, b))
+        t.output.keep()
+        s.submit()
+        s.wait_all()
+        bundle_path = os.path.join(test_env.work_dir, "bundle.json")
+        subprocess.check_call((RAIN_BIN, "export-session", "--output", bundle_path,
+                               address, str(s.session_id)))
+
+    with open(bundle_path) as f:
+        bundle = json.load(f)
+    assert bundle["version"] == 1
+    assert [task["task_type"] for task in bundle["tasks"]] == ["!concat"]
+    assert "info" not in bundle["tasks"][0]["attributes"]["items"]
+    constants = [o for o in bundle["objects"] if o["constant"]]
+    assert len(constants) == 1
+
+    # Data of constant objects are needed
+    assert subprocess.call((RAIN_BIN, "import-session", address, bundle_path)) != 0
+
+    data_dir = os.path.join(test_env.work_dir, "data")
+    os.mkdir(data_dir)
+    with open(os.path.join(data_dir, str(constants[0]["id"])), "wb") as f:
+        f.write(b"xyz")
+    session_id = int(subprocess.check_output(
+        (RAIN_BIN, "import-session", "--data", data_dir, address, bundle_path)))
+    assert session_id != s.session_id
+    snapshot = test_env.client.get_graph_snapshot(session_id)
+    assert [task["task_type"] for task in snapshot["tasks"]] == ["!concat"]
+
+
+def test_submit_hold_and_flush(test_env):
+    test_env.start(1)
+    with test_env.client.new_session() as s:
+        t1 = tasks.concat((blob("a"), blob("b")))
+        t1.output.keep()
+        s.submit(hold=True)
+        time.sleep(0.3)
+        stats = s.stats()
+        assert stats["ready"] == 1
+        assert stats["finished"] == 0
+        s.flush()
+        assert t1.output.fetch().get_bytes() == b"ab"
+
+
+def test_submit_stream(test_env):
+    test_env.start(1)
+    with test_env.client.new_session() as s:
+        def generate():
+            for i in range(10):
+                t = tasks.concat((blob(str(i)), blob("x")))
+                t.output.keep()
+                yield t
+        s.submit_stream(generate(), batch_size=3)
+        s.wait_all()
+        assert s.stats()["finished"] == 10
+
+
+def run_vanishing_client(test_env, marker, detach=False):
+    """Submit a task touching `marker` after a second from another process
+    that exits right after the submit"""
+    import os
+    import subprocess
+    import sys
+    from conftest import PYTHON_DIR
+
+    code = """if True:
+        import sys
+        from rain.client import Client, tasks
+        client = Client("127.0.0.1", int(sys.argv[1]))
+        s = client.new_session(detach_on_disconnect=sys.argv[3] == "1")
+        with s.bind_only():
+            tasks.execute(["sh", "-c", "sleep 1; touch " + sys.argv[2]])
+            s.submit()
+    """
+    env = dict(os.environ, PYTHONPATH=PYTHON_DIR)
+    subprocess.check_call((sys.executable, "-c", code, str(test_env.running_port),
+                           marker, "1" if detach else "0"), env=env)
+
+
+def test_orphan_session_cancelled(test_env):
+    import os
+    test_env.start(1)
+    marker = os.path.join(test_env.work_dir, "marker")
+    run_vanishing_client(test_env, marker)
+    time.sleep(2.5)
+    assert not os.path.exists(marker)
+    assert not test_env.client.get_server_info()["workers"][0]["tasks"]
+
+
+def test_orphan_session_detached(test_env):
+    import os
+    test_env.start(1)
+    marker = os.path.join(test_env.work_dir, "marker")
+    run_vanishing_client(test_env, marker, detach=True)
+    time.sleep(2.5)
+    assert os.path.exists(marker)
+
+
+def test_orphan_session_grace(test_env):
+    import os
+    test_env.start(1, server_args=("--orphan-grace", "10"))
+    marker = os.path.join(test_env.work_dir, "marker")
+    run_vanishing_client(test_env, marker)
+    time.sleep(2.5)
+    assert os.path.exists(marker)
+
+
+def test_ship_worker_logs(test_env):
+    import os
+    test_env.start(1, worker_args=("--ship-logs",))
+    with test_env.client.new_session() as s:
+        t = tasks.execute(["sh", "-c", "exit 3"])
+        s.submit()
+        with pytest.raises(TaskException):
+            t.wait()
+
+    # Logs are shipped and events are saved every second
+    for _ in range(50):
+        events = get_events("WorkerLog")
+        if events:
+            break
+        time.sleep(0.1)
+    assert any(e["level"] == "WARN" and "failed" in e["message"] for e in events)
+    worker = events[0]["worker"]
+    with open(os.path.join(test_env.work_dir, "server.out")) as f:
+        log = f.read()
+    assert "Worker {}: Task".format(worker) in log
+
+
+def test_event_schema_version(test_env):
+    """Events sent by workers are stored with the version of the event schema"""
+    test_env.start(1)
+    worker = test_env.client.get_server_info()["workers"][0]["worker_id"]
+
+    # Workers send monitoring events every 5 seconds
+    for _ in range(80):
+        events = get_events("Monitoring")
+        if events:
+            break
+        time.sleep(0.1)
+    assert events
+    assert events[0]["version"] == 1
+    assert events[0]["type"] == "Monitoring"
+    assert events[0]["worker"] == worker
+
+
+def test_bench(test_env):
+    import json
+    import subprocess
+    from conftest import RAIN_BIN
+    test_env.start(1)
+    address = "127.0.0.1:{}".format(test_env.running_port)
+    output = subprocess.check_output(
+        (RAIN_BIN, "bench", "--tasks", "20", "--bytes", "100000", "--repeats", "2", address))
+    results = [json.loads(line) for line in output.decode().splitlines()]
+    assert sorted(r["benchmark"] for r in results) == [
+        "object-transfer-download", "object-transfer-upload",
+        "submission-latency", "task-throughput"]
+    for r in results:
+        assert r["workers"] == 1
+        assert r["repeats"] == 2
+        assert r["seconds"] > 0
+        assert r["rate"] > 0
+        if r["benchmark"].startswith("object-transfer"):
+            assert (r["size"], r["unit"]) == (100000, "bytes/s")
+        else:
+            assert (r["size"], r["unit"]) == (20, "tasks/s")
+
+    assert subprocess.call((RAIN_BIN, "bench", "--repeats", "0", address)) != 0
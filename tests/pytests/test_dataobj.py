@@ -1,5 +1,5 @@
 from rain.client import blob, RainException, pickled, tasks, directory
-from rain.client import OutputDir, InputDir
+from rain.client import OutputDir, InputDir, Federation
 
 import rain
 import pytest
@@ -59,3 +59,178 @@ def test_dir_big(test_env):
         t.outputs["d"].fetch().write("result")
         with open("result/file1", "rb") as f:
             assert f.read() == data
+
+
+def test_publish_object(test_env):
+    test_env.start(1)
+    client = test_env.client
+    with client.new_session() as s1:
+        b = blob(b"abc")
+        b.keep()
+        s1.submit()
+        b.publish("shared-abc")
+
+        with client.new_session() as s2:
+            p = s2.published("shared-abc")
+            assert p.id == b.id
+            t = tasks.concat((p, p))
+            t.keep_outputs()
+            s2.submit()
+            assert t.output.fetch().get_bytes() == b"abcabc"
+
+        s1.unpublish("shared-abc")
+        with pytest.raises(Exception):
+            s1.published("shared-abc")
+
+
+def test_published_object_outlives_session(test_env):
+    test_env.start(1)
+    client = test_env.client
+    s1 = client.new_session()
+    with s1.bind_only():
+        b = blob(b"abc")
+        b.keep()
+        s1.submit()
+        b.publish("shared-abc")
+
+    with client.new_session() as s2:
+        p = s2.published("shared-abc")
+        t1 = tasks.concat((p, p))
+        t1.keep_outputs()
+        s2.submit()
+        assert t1.output.fetch().get_bytes() == b"abcabc"
+
+        s1.close()
+        with pytest.raises(Exception):
+            s2.published("shared-abc")
+
+        t2 = tasks.concat((p, blob(b"d")))
+        t2.keep_outputs()
+        s2.submit()
+        assert t2.output.fetch().get_bytes() == b"abcd"
+
+
+def test_publish_namespaced_by_identity(test_env):
+    from rain.client import Client
+    access_file = os.path.join(test_env.work_dir, "access")
+    with open(access_file, "w") as f:
+        f.write("alice user t-alice\n"
+                "bob user t-bob\n")
+    test_env.start(1, server_args=("--access-file", access_file))
+    alice = Client("127.0.0.1", test_env.running_port, token="t-alice")
+    bob = Client("127.0.0.1", test_env.running_port, token="t-bob")
+    with alice.new_session() as s1:
+        b = blob(b"abc")
+        b.keep()
+        s1.submit()
+        b.publish("shared")
+
+        with bob.new_session() as s2:
+            with pytest.raises(Exception):
+                s2.published("shared")
+            with pytest.raises(Exception):
+                s2.unpublish("shared")
+
+        with alice.new_session() as s3:
+            assert s3.published("shared").id == b.id
+
+
+def test_use_unpublished_object(test_env):
+    test_env.start(1)
+    client = test_env.client
+    with client.new_session() as s1:
+        b = blob(b"abc")
+        b.keep()
+        s1.submit()
+
+        with client.new_session() as s2:
+            tasks.concat((b, b))
+            with pytest.raises(Exception):
+                s2.submit()
+
+
+def test_federation_selects_server_with_data(test_env):
+    test_env.start(1)
+    federation = Federation({"site1": test_env.client})
+    with pytest.raises(RainException):
+        federation.new_session(published=["fed-data"])
+
+    with federation.new_session() as s1:
+        b = blob(b"abc")
+        b.keep()
+        s1.submit()
+        b.publish("fed-data")
+
+        with federation.new_session(published=["fed-data"]) as s2:
+            assert federation.server_of(s2) == "site1"
+            p = s2.published("fed-data")
+            t = tasks.concat((p, p))
+            t.keep_outputs()
+            s2.submit()
+            federation.wait_all()
+            assert t.output.fetch().get_bytes() == b"abcabc"
+            assert len(federation.status()["site1"]) == 2
+
+
+def test_sub_session_uses_parent_objects(test_env):
+    test_env.start(1)
+    with test_env.client.new_session() as s:
+        b = blob(b"abc")
+        b.keep()
+        s.submit()
+
+        sub = s.new_sub_session("experiment")
+        with sub:
+            t = tasks.concat((b, b))
+            t.keep_outputs()
+            sub.submit()
+            assert t.output.fetch().get_bytes() == b"abcabc"
+        assert not sub.active
+
+        # Parent objects survive closing of the sub-session
+        assert b.fetch().get_bytes() == b"abc"
+
+        sub2 = s.new_sub_session("experiment2")
+    assert not sub2.active
+
+
+def test_list_kept(test_env):
+    test_env.start(1)
+    with test_env.client.new_session() as s:
+        b1 = blob(b"a" * 100, label="first")
+        b2 = blob(b"b" * 10, label="second")
+        b3 = blob(b"c", label="third")
+        b1.keep()
+        b2.keep()
+        s.submit()
+
+        kept = s.list_kept()
+        assert sorted(o["label"] for o in kept) == ["first", "second"]
+        assert s.kept_size() == (2, 110)
+        assert [o["id"] for o in s.list_kept(min_size=50)] == [b1.id]
+        assert s.list_kept(min_age=3600) == []
+
+        assert s.unkeep_matching(label_prefix="sec") == 1
+        assert not b2.is_kept()
+        assert b1.is_kept()
+        assert s.kept_size() == (1, 100)
+        assert b3.state is not None
+
+
+def test_list_kept_of_another_client(test_env):
+    from rain.client import Client
+    test_env.start(1)
+    other = Client("127.0.0.1", test_env.running_port)
+    with test_env.client.new_session() as s:
+        b = blob(b"abc")
+        b.keep()
+        s.submit()
+        b.wait()
+
+        with pytest.raises(Exception, match="belongs to another client"):
+            other._list_kept(s, None, 0, 0, None)
+        with pytest.raises(Exception, match="belongs to another client"):
+            other._kept_size(s)
+        with pytest.raises(Exception, match="belongs to another client"):
+            other._unkeep_matching(s, None, 0, 0, None)
+        assert b.is_kept()
@@ -1,5 +1,5 @@
 from rain.client import Program, Input, InputDir, Output, OutputDir
-from rain.client import tasks, blob, pickled, directory
+from rain.client import tasks, blob, pickled, directory, artifact
 from rain.client import TaskException, RainException
 
 import os
@@ -176,6 +176,21 @@ def test_execute_fail(test_env):
         pytest.raises(TaskException, lambda: t1.wait())
 
 
+def test_execute_fail_digest(test_env):
+    """Failure context is collected into the exception"""
+    test_env.start(1)
+    args = ("sh", "-c", "echo 'some output'; echo 'some error' >&2; exit 3")
+    with test_env.client.new_session() as s:
+        t1 = tasks.execute(args, stdout="output")
+        s.submit()
+        with pytest.raises(TaskException) as e:
+            t1.wait()
+        failure = e.value.failure
+        assert failure["exit_code"] == 3
+        assert failure["stderr_tail"] == "some error\n"
+        assert failure["command"] == list(args)
+
+
 def test_program_fail(test_env):
     """Setting input file for program"""
     test_env.start(1)
@@ -208,6 +223,51 @@ def test_execute_shell(test_env):
         assert (os.getenv("HOME") + "\n").encode() == t4.output.fetch().get_bytes()
 
 
+def test_execute_session_env(test_env):
+    test_env.start(1)
+    env = {"RAIN_TEST_A": "session-a", "RAIN_TEST_B": "session-b"}
+    with test_env.client.new_session(env=env) as s:
+        t1 = tasks.execute("echo $RAIN_TEST_A $RAIN_TEST_B", stdout=True, shell=True)
+        t1.output.keep()
+        t2 = tasks.execute("echo $RAIN_TEST_A $RAIN_TEST_B", stdout=True, shell=True,
+                           env={"RAIN_TEST_B": "task-b"})
+        t2.output.keep()
+        s.submit()
+        assert t1.output.fetch().get_bytes() == b"session-a session-b\n"
+        assert t2.output.fetch().get_bytes() == b"session-a task-b\n"
+
+
+def test_execute_secrets(test_env):
+    test_env.start(1)
+    client = test_env.client
+    client.set_secret("db-password", "s3cr3t")
+    with client.new_session() as s:
+        t1 = tasks.execute("echo $DB_PASSWORD", stdout=True, shell=True,
+                           secrets={"DB_PASSWORD": "db-password"})
+        t1.output.keep()
+        s.submit()
+        assert t1.output.fetch().get_bytes() == b"s3cr3t\n"
+        t1.update()
+        assert "s3cr3t" not in str(t1.attributes)
+
+    client.remove_secret("db-password")
+    with client.new_session() as s:
+        tasks.execute("echo $DB_PASSWORD", secrets={"DB_PASSWORD": "db-password"})
+        with pytest.raises(Exception, match="Secret 'db-password' not found"):
+            s.submit()
+
+    # The secret is removed before the task is started
+    client.set_secret("db-password", "s3cr3t")
+    with client.new_session() as s:
+        t1 = tasks.sleep(0.5, blob("x"))
+        tasks.execute(["cat", Input("x", dataobj=t1)],
+                      secrets={"DB_PASSWORD": "db-password"})
+        s.submit()
+        client.remove_secret("db-password")
+        with pytest.raises(Exception, match="Secret 'db-password' not found"):
+            s.wait_all()
+
+
 def test_execute_termination(test_env):
     test_env.start(1)
     import time
@@ -225,6 +285,76 @@ def test_execute_termination(test_env):
         assert b"ab" == r.get_bytes()
 
 
+def test_execute_timeout(test_env):
+    test_env.start(1)
+    with test_env.client.new_session() as s:
+        t1 = tasks.execute("sleep 20", timeout="500ms")
+        s.submit()
+        with pytest.raises(TaskException, match="timed out"):
+            test_env.assert_max_duration(5, lambda: t1.wait())
+
+    # Resources of the task are free again
+    with test_env.client.new_session() as s:
+        t2 = tasks.execute("sleep 0.1", timeout=10)
+        s.submit()
+        t2.wait()
+
+
+def test_execute_cpu_limit(test_env):
+    test_env.start(1)
+    with test_env.client.new_session() as s:
+        t1 = tasks.execute(("sh", "-c", "while :; do :; done"), limits={"cpu": 1})
+        s.submit()
+        with pytest.raises(TaskException, match="Process limit 'cpu' exceeded"):
+            test_env.assert_max_duration(10, lambda: t1.wait())
+
+
+def test_execute_nofile_limit(test_env):
+    test_env.start(1)
+    program = "fs = [open('/dev/null') for _ in range(64)]"
+    with test_env.client.new_session() as s:
+        t1 = tasks.execute(("python3", "-c", program), limits={"nofile": 16})
+        s.submit()
+        # The limit is not detected, the program fails with its own error
+        with pytest.raises(TaskException) as e:
+            t1.wait()
+        assert "Too many open files" in str(e.value)
+        assert "Process limit" not in str(e.value)
+
+    with test_env.client.new_session() as s:
+        t1 = tasks.execute(("python3", "-c", program), limits={"nofile": 128})
+        s.submit()
+        t1.wait()
+
+    with test_env.client.new_session() as s:
+        t1 = tasks.execute("true", limits={"nofiles": 16})
+        s.submit()
+        with pytest.raises(TaskException, match="unknown field"):
+            t1.wait()
+
+
+def test_execute_max_retries(test_env, tmpdir):
+    test_env.start(1)
+    counter = tmpdir.join("counter")
+    counter.write("")
+    with test_env.client.new_session() as s:
+        # Fails in the first two runs
+        t1 = tasks.execute("echo x >> {0}; test $(wc -l < {0}) -gt 2 && echo ok".format(counter),
+                           shell=True, stdout=True, max_retries=2, retry_delay=0.1)
+        t2 = tasks.execute("cat", stdin=t1, stdout=True)
+        t2.output.keep()
+        s.submit()
+        assert t2.output.fetch().get_bytes() == b"ok\n"
+        t1.update()
+        assert t1.attributes["retries"]["count"] == 2
+
+    with test_env.client.new_session() as s:
+        t1 = tasks.execute("exit 3", shell=True, max_retries=1, retry_delay=0.1)
+        s.submit()
+        with pytest.raises(TaskException, match="exit code 3"):
+            t1.wait()
+
+
 def test_program_outputs(test_env):
     "Specify program content type on spec and instantiation."
     obj = ["1", 2.0, {'a': 42}]
@@ -380,3 +510,119 @@ def test_program_empty_output(test_env):
         task.output.keep()
         s.submit()
         assert task.output.fetch().get_bytes() == b""
+
+
+def test_execute_output_validation(test_env):
+    test_env.start(1)
+    with test_env.client.new_session() as s:
+        t1 = tasks.execute("echo '{\"a\": 1}'",
+                           stdout=Output(validate={"non_empty": True, "json": True}),
+                           shell=True)
+        t1.keep_outputs()
+        s.submit()
+        assert t1.output.fetch().get_bytes() == b'{"a": 1}\n'
+
+    with test_env.client.new_session() as s:
+        t1 = tasks.execute("echo Traceback",
+                           stdout=Output(validate={"regex": "^id,name"}),
+                           shell=True)
+        s.submit()
+        with pytest.raises(TaskException) as e:
+            t1.wait()
+        assert "failed validation" in str(e.value)
+
+    with pytest.raises(ValueError):
+        Output(validate={"nonempty": True})
+
+
+def test_execute_script(test_env):
+    test_env.start(1)
+    with test_env.client.new_session() as s:
+        t0 = tasks.script("cat $1 $1", args=[Input("data", dataobj=blob(b"ab"))],
+                          stdout=True)
+        t0.keep_outputs()
+        t1 = tasks.script("import sys\nprint(sys.argv[1].upper())",
+                          args=["hello"], language="python", stdout=True)
+        t1.keep_outputs()
+        s.submit()
+        assert t0.output.fetch().get_bytes() == b"abab"
+        assert t1.output.fetch().get_bytes() == b"HELLO\n"
+
+
+
+def test_execute_tmpfs_without_budget(test_env):
+    """Worker without --task-tmpfs runs the task in a directory on disk"""
+    test_env.start(1)
+    with test_env.client.new_session() as s:
+        t0 = tasks.execute("echo abc > f && cat f", shell=True, stdout=True, tmpfs=1024 * 1024)
+        t0.keep_outputs()
+        s.submit()
+        assert t0.attributes["tmpfs"] == 1024 * 1024
+        assert t0.output.fetch().get_bytes() == b"abc\n"
+
+
+def test_execute_output_policy(test_env):
+    test_env.start(1)
+    with test_env.client.new_session(output_retention="keep_as_object",
+                                     output_max_size=40) as s:
+        t0 = tasks.execute("seq 1000; echo done >&2", shell=True)
+        t0.keep_outputs()
+        s.submit()
+        stdout = t0.outputs["stdout"].fetch().get_bytes()
+        assert stdout.startswith(b"1\n2\n")
+        assert stdout.endswith(b"999\n1000\n")
+        assert b"bytes omitted" in stdout
+        assert t0.outputs["stderr"].fetch().get_bytes() == b"done\n"
+
+    with test_env.client.new_session() as s:
+        s.set_output_policy("keep_on_failure")
+        t0 = tasks.execute("echo some error >&2; exit 3", shell=True)
+        s.submit()
+        with pytest.raises(TaskException) as e:
+            t0.wait()
+        with open(e.value.failure["stderr_log"]) as f:
+            assert f.read() == "some error\n"
+
+    with test_env.client.new_session() as s:
+        with pytest.raises(RainException):
+            s.set_output_policy("keep")
+
+
+def test_execute_artifact(test_env):
+    path = os.path.join(test_env.work_dir, "tool")
+    with open(path, "w") as f:
+        f.write("#!/bin/sh\necho tool $1\n")
+    os.chmod(path, 0o755)
+    bundle = os.path.join(test_env.work_dir, "bundle")
+    os.mkdir(bundle)
+    os.mkdir(os.path.join(bundle, "bin"))
+    with open(os.path.join(bundle, "bin", "run"), "w") as f:
+        f.write("#!/bin/sh\necho bundle\n")
+    os.chmod(os.path.join(bundle, "bin", "run"), 0o755)
+
+    test_env.start(1)
+    with test_env.client.new_session() as s:
+        tool = artifact(path)
+        assert tool.mode == "executable"
+        assert tool.label == "tool"
+        t0 = tasks.execute([tool, "x"], stdout=True)
+        t1 = tasks.execute("bundle/bin/run",
+                           input_paths=[Input("bundle", dataobj=artifact(bundle))],
+                           stdout=True)
+        t2 = tasks.execute([blob(b"#!/bin/sh\necho blob\n", mode="executable")],
+                           stdout=True)
+        t0.keep_outputs()
+        t1.keep_outputs()
+        t2.keep_outputs()
+        s.submit()
+        assert t0.output.fetch().get_bytes() == b"tool x\n"
+        assert t1.output.fetch().get_bytes() == b"bundle\n"
+        assert t2.output.fetch().get_bytes() == b"blob\n"
+
+    with test_env.client.new_session() as s:
+        with pytest.raises(RainException):
+            blob(b"data", mode="setuid")
+        with pytest.raises(RainException):
+            artifact(path, extract=True)
+        with pytest.raises(ValueError):
+            OutputDir("dir", mode="executable")
@@ -755,3 +755,42 @@ def test_python_dir(test_env):
         with open(os.path.join(test_env.work_dir, "rdir2", "b", "g.txt")) as f:
             assert f.read() == "Hello 3"
         assert os.path.isdir("rdir2/test3")
+
+
+def test_python_warm_subworkers(test_env, monkeypatch):
+    """Subworkers forked from zygote have preloaded modules"""
+
+    @remote()
+    def preloaded(ctx):
+        import sys
+        return str("xml.dom.minidom" in sys.modules).encode()
+
+    monkeypatch.setenv("RAIN_SUBWORKER_PRELOAD", "xml.dom.minidom")
+    test_env.start(1, n_cpus=2, worker_args=("--warm-subworkers",))
+    with test_env.client.new_session() as s:
+        tasks = [preloaded() for i in range(4)]
+        for t in tasks:
+            t.output.keep()
+        s.submit()
+        for t in tasks:
+            assert t.output.fetch().get_bytes() == b"True"
+
+
+def test_python_session_env(test_env):
+    @remote()
+    def read_env(ctx):
+        return os.environ.get("RAIN_TEST_ENV", "unset").encode()
+
+    test_env.start(1)
+    with test_env.client.new_session(env={"RAIN_TEST_ENV": "abc"}) as s:
+        t1 = read_env()
+        t1.output.keep()
+        s.submit()
+        assert t1.output.fetch().get_bytes() == b"abc"
+
+    # Subworker is reused, the variable must not leak into other sessions
+    with test_env.client.new_session() as s:
+        t1 = read_env()
+        t1.output.keep()
+        s.submit()
+        assert t1.output.fetch().get_bytes() == b"unset"
@@ -1,5 +1,6 @@
 from rain.client import tasks, blob
 
+import pytest
 import time
 
 
@@ -43,6 +44,141 @@ def test_cpu_resources4(test_env):
         test_env.assert_duration(0.9, 1.1, lambda: s.wait_all())
 
 
+def test_session_max_running_tasks(test_env):
+    """2x 1cpu tasks on 2 cpu worker, but session limit is 1"""
+    test_env.start(1, n_cpus=2)
+    with test_env.client.new_session(max_running_tasks=1) as s:
+        tasks.sleep(1.0, blob("first"))
+        tasks.sleep(1.0, blob("second"))
+        s.submit()
+        test_env.assert_duration(1.9, 2.1, lambda: s.wait_all())
+
+
+def test_session_ttl(test_env):
+    """Long task of a session with short TTL is cancelled"""
+    test_env.start(1)
+    with test_env.client.new_session(ttl=1.0) as s:
+        t1 = tasks.sleep(10.0, blob("data"))
+        t1.output.keep()
+        s.submit()
+        with pytest.raises(Exception, match="Session expired"):
+            test_env.assert_duration(0.9, 2.2, lambda: s.wait_all())
+
+
+def test_session_ttl_retention(test_env):
+    """Kept objects of an expired session are retained for the retention"""
+    test_env.start(1)
+    with test_env.client.new_session() as s:
+        s.set_ttl(1.0, retention=30.0)
+        b = blob("data")
+        b.keep()
+        t1 = tasks.sleep(10.0, b)
+        t1.output.keep()
+        s.submit()
+        with pytest.raises(Exception, match="Session expired"):
+            s.wait_all()
+        assert b.fetch().get_bytes() == b"data"
+        with pytest.raises(Exception, match="Session expired"):
+            t1.output.fetch()
+
+
+def test_infeasible_task_rejected(test_env):
+    """3cpu task on 2 cpu worker is rejected at submit"""
+    test_env.start(1, n_cpus=2)
+    with test_env.client.new_session() as s:
+        tasks.sleep(1.0, blob("first"), cpus=3)
+        with pytest.raises(Exception, match="exceed capacity of every worker"):
+            s.submit()
+
+
+def test_memory_resources(test_env):
+    """2x 1cpu tasks that do not fit together into memory of 2 cpu worker"""
+    test_env.start(1, n_cpus=2, worker_args=("--memory", "1000000"))
+    with test_env.client.new_session() as s:
+        tasks.sleep(1.0, blob("first"), memory=600000)
+        tasks.sleep(1.0, blob("second"), memory=600000)
+        s.submit()
+        test_env.assert_duration(1.9, 2.1, lambda: s.wait_all())
+    info = test_env.client.get_server_info()
+    assert info["workers"][0]["resources"]["memory"] == 1000000
+
+
+def test_infeasible_memory_rejected(test_env):
+    test_env.start(1, worker_args=("--memory", "1000000"))
+    with test_env.client.new_session() as s:
+        tasks.sleep(1.0, blob("first"), memory=2000000)
+        with pytest.raises(Exception, match="exceed capacity of every worker"):
+            s.submit()
+
+
+def test_gpu_resources(test_env):
+    """Tasks see only their GPUs, the third task waits for a free GPU"""
+    test_env.start(1, n_cpus=3, worker_args=("--gpus", "2"))
+    with test_env.client.new_session() as s:
+        ts = [tasks.execute("printenv CUDA_VISIBLE_DEVICES; sleep 0.5",
+                            shell=True, stdout=True, gpus=1)
+              for _ in range(3)]
+        t4 = tasks.execute("printenv CUDA_VISIBLE_DEVICES", shell=True, stdout=True)
+        for t in ts + [t4]:
+            t.output.keep()
+        s.submit()
+        test_env.assert_duration(0.9, 1.4, lambda: s.wait_all())
+        devices = [t.output.fetch().get_bytes().strip() for t in ts]
+        assert sorted(devices) in ([b"0", b"0", b"1"], [b"0", b"1", b"1"])
+        assert t4.output.fetch().get_bytes().strip() == b""
+    info = test_env.client.get_server_info()
+    assert info["workers"][0]["resources"]["gpus"] == 2
+
+
+def test_named_resources(test_env):
+    """2x 1cpu tasks needing the only 'fpga' of 2 cpu worker"""
+    test_env.start(1, n_cpus=2, worker_args=("--resource", "fpga=1"))
+    with test_env.client.new_session() as s:
+        tasks.sleep(0.5, blob("first"), resources={"fpga": 1})
+        tasks.sleep(0.5, blob("second"), resources={"fpga": 1})
+        s.submit()
+        test_env.assert_duration(0.9, 1.2, lambda: s.wait_all())
+        tasks.sleep(0.5, blob("third"), resources={"fpga": 2})
+        with pytest.raises(Exception, match="exceed capacity of every worker"):
+            s.submit()
+
+
+def test_placement_constraints(test_env):
+    test_env.start(1, worker_args=("--label", "rack=a"))
+    with test_env.client.new_session() as s:
+        tasks.sleep(0.1, blob("first"), constraints=["rack=a", "!spot"])
+        s.submit()
+        s.wait_all()
+        tasks.sleep(0.1, blob("second"), constraints=["rack=b"])
+        with pytest.raises(Exception, match="placement constraints"):
+            s.submit()
+
+
+def test_pinned_objects_without_workers(test_env):
+    """Pins are not checked when no worker is connected yet"""
+    test_env.start(0)
+    with test_env.client.new_session() as s:
+        t1 = tasks.sleep(0.1, blob("first"))
+        t1.output.pin("rack=a")
+        # Submit is accepted, the task waits for a matching worker
+        s.submit()
+
+
+def test_spread_and_colocate(test_env):
+    test_env.start(2, n_cpus=2)
+    with test_env.client.new_session() as s:
+        spread = [tasks.sleep(0.5, blob("spread"), spread="s") for _ in range(2)]
+        colocated = [tasks.sleep(0.1, blob("colocated"), colocate="c") for _ in range(4)]
+        s.submit()
+        test_env.assert_duration(0.4, 0.8, lambda: s.wait_all())
+        for t in spread + colocated:
+            t.update()
+        workers = set(t.attributes["info"]["worker"] for t in spread)
+        assert len(workers) == 2
+        workers = set(t.attributes["info"]["worker"] for t in colocated)
+        assert len(workers) == 1
+
+
 def test_number_of_tasks_and_objects(test_env):
     """Sleep followed by wait"""
     test_env.start(1, delete_list_timeout=0)
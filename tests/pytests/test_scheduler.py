@@ -102,3 +102,107 @@ def test_scheduler_big_vs_dynamic_small(test_env):
     t0 = s.new_task([o1], expect_worker=w0)
     s.new_task([t0.output, o2], expect_worker=w1)
     s.run()
+
+
+# An object used several times by a task is transferred only once,
+# so o1 (3/5 BIG) twice does not outweigh o2 (BIG)
+def test_scheduler_repeated_input(test_env):
+    workers = [Worker(cpus=1) for i in range(2)]
+    s = Scenario(test_env, workers)
+    w0, w1 = workers
+    o1 = s.new_object(workers=w0, size=BIG * 3 // 5)
+    o2 = s.new_object(workers=w1, size=BIG)
+    s.new_task([o1, o1, o2], expect_worker=w1)
+    s.run()
+
+
+def test_rebalance_waiting_tasks(test_env):
+    """Tasks waiting on a busy worker are moved to an idle worker"""
+    from rain.client import tasks
+
+    # Tasks without cpus all go to the same worker, which runs one at a time;
+    # the other worker stays idle until tasks are rebalanced
+    test_env.start(2, worker_args=("--max-parallel-tasks", "1"))
+    with test_env.client.new_session() as s:
+        ts = [tasks.execute("sleep 1", cpus=0) for _ in range(10)]
+        s.submit()
+        # Assigned tasks are moved after 5 seconds of waiting
+        test_env.assert_max_duration(9.5, lambda: s.wait_all())
+        for t in ts:
+            t.update()
+        workers = set(t.attributes["info"]["worker"] for t in ts)
+        assert len(workers) == 2
+
+
+def test_forward_inputs_to_moved_consumers(test_env):
+    """Inputs forwarded to the worker of their consumer follow the consumer
+    when it is moved to another worker"""
+    from rain.client import tasks, Input
+
+    # Producers need 2 cpus, so they run on the second worker; consumers without
+    # cpus and locality all go to one worker until they are rebalanced
+    test_env.start(worker_defs=(1, 2),
+                   worker_args=("--max-parallel-tasks", "1"),
+                   server_args=("--locality-weight", "0"))
+    with test_env.client.new_session() as s:
+        ps = [tasks.execute(["printf", str(i)], stdout=True, cpus=2) for i in range(8)]
+        cs = [tasks.execute(["sh", "-c", "sleep 1; cat in"],
+                            input_paths=[Input("in", dataobj=p)],
+                            stdout=True, cpus=0)
+              for p in ps]
+        for c in cs:
+            c.keep_outputs()
+        s.submit()
+        s.wait_all()
+        for i, c in enumerate(cs):
+            assert c.output.fetch().get_bytes() == str(i).encode()
+            c.update()
+        workers = set(c.attributes["info"]["worker"] for c in cs)
+        assert len(workers) == 2
+
+
+def placed_workers(test_env, placement):
+    """Numbers of tasks on each worker when four one-cpu tasks are placed
+    on two workers with four cpus"""
+    from rain.client import tasks
+    from collections import Counter
+
+    test_env.start(2, n_cpus=4, server_args=("--placement", placement))
+    with test_env.client.new_session() as s:
+        ts = [tasks.execute("sleep 0.5", cpus=1) for _ in range(4)]
+        s.submit()
+        s.wait_all()
+        for t in ts:
+            t.update()
+        return sorted(Counter(t.attributes["info"]["worker"] for t in ts).values())
+
+
+def test_placement_pack(test_env):
+    assert placed_workers(test_env, "pack") == [4]
+
+
+def test_placement_spread(test_env):
+    assert placed_workers(test_env, "spread") == [2, 2]
+
+
+def test_scheduling_interval(test_env):
+    """Tasks that become ready between scheduling rounds wait for the next round"""
+    from rain.client import tasks, blob
+
+    def run_chain():
+        with test_env.client.new_session() as s:
+            t = blob("a")
+            for _ in range(4):
+                t = tasks.concat((t, blob("b")))
+            t.output.keep()
+            s.submit()
+            s.wait_all()
+            assert t.output.fetch().get_bytes() == b"abbbb"
+
+    test_env.start(1, server_args=("--scheduling-interval", "1s"))
+    # Every task of the chain gets ready after the previous one, in the next round
+    test_env.assert_duration(2.8, 6, run_chain)
+
+    status = test_env.client.configure_scheduler(scheduling_interval=0)
+    assert status["scheduling_interval"] == 0
+    test_env.assert_max_duration(1, run_chain)
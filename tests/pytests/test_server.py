@@ -5,3 +5,307 @@ def test_listen_argument1(test_env):
 
 def test_listen_argument2(test_env):
     test_env.start(1, listen_addr="0.0.0.0", listen_port="33112")
+
+
+def test_stored_objects_after_server_restart(test_env):
+    """Restarted server does not know objects stored by workers, they are deleted"""
+    import json
+    import os
+    import time
+    from rain.client import tasks
+
+    test_env.start(1)
+    index = os.path.join(test_env.work_dir, "worker-0", "work", "store.json")
+    s = test_env.client.new_session()
+    with s.bind_only():
+        t = tasks.execute(["echo", "abc"], stdout=True)
+        t.keep_outputs()
+        s.submit()
+        s.wait_all()
+    with open(index) as f:
+        stored = json.load(f)
+    assert len(stored["objects"]) == 1
+    path = stored["objects"][0]["path"]
+    assert os.path.exists(path)
+
+    test_env.restart()
+    time.sleep(0.5)
+    with open(index) as f:
+        restored = json.load(f)
+    assert restored["objects"] == []
+    assert restored["server_instance"] != stored["server_instance"]
+    assert not os.path.exists(path)
+
+    with test_env.client.new_session() as s:
+        t = tasks.execute(["echo", "abc"], stdout=True)
+        t.keep_outputs()
+        s.submit()
+        assert t.output.fetch().get_bytes() == b"abc\n"
+
+
+def test_stored_objects_after_worker_restart(test_env):
+    """Restarted worker offers its stored objects to the same server,
+    objects still needed by the server are accepted"""
+    import json
+    import os
+    import time
+    from rain.client import tasks
+
+    test_env.start(2)
+    s = test_env.client.new_session()
+    with s.bind_only():
+        t1 = tasks.execute(["echo", "abc"], stdout=True)
+        t1.keep_outputs()
+        # Two consumers that run in parallel on both workers,
+        # so both workers hold the output of t1
+        t2 = tasks.execute("sleep 0.5", input_paths=[t1.output])
+        t3 = tasks.execute("sleep 0.5", input_paths=[t1.output])
+        s.submit()
+        s.wait_all()
+
+    index = os.path.join(test_env.work_dir, "worker-0", "work", "store.json")
+    with open(index) as f:
+        stored = json.load(f)
+    ids = sorted((o["id"]["session_id"], o["id"]["id"]) for o in stored["objects"])
+    assert tuple(t1.output.id) in ids
+
+    test_env.restart_worker(0)
+    time.sleep(0.5)
+    with open(index) as f:
+        restored = json.load(f)
+    assert restored["server_instance"] == stored["server_instance"]
+    assert sorted((o["id"]["session_id"], o["id"]["id"])
+                  for o in restored["objects"]) == ids
+    assert t1.output.fetch().get_bytes() == b"abc\n"
+    s.close()
+
+
+def test_preemptible_worker_drain(test_env):
+    """SIGTERM of a preemptible worker replicates its needed objects,
+    the session continues on the other worker"""
+    import os
+    import signal
+    from rain.client import tasks, blob
+
+    # Objects are not inlined, so they have to be replicated to the other worker
+    test_env.start(worker_defs=(1, 2), worker_args=("--preemptible",),
+                   server_args=("--inline-threshold", "0"))
+    with test_env.client.new_session() as s:
+        # Only worker 1 has 2 cpus
+        t1 = tasks.execute(["echo", "abc"], stdout=True, cpus=2)
+        t1.keep_outputs()
+        s.submit()
+        t1.wait()
+
+        worker = test_env.workers.pop(1)
+        test_env.worker_defs = test_env.worker_defs[:1]
+        os.kill(worker.pid, signal.SIGTERM)
+        test_env.assert_max_duration(10, lambda: worker.wait())
+
+        t2 = tasks.concat((t1.output, blob("def")))
+        t2.keep_outputs()
+        s.submit()
+        s.wait_all()
+        assert t2.output.fetch().get_bytes() == b"abc\ndef"
+        assert t1.output.fetch().get_bytes() == b"abc\n"
+
+
+def kill_only_worker(test_env):
+    import os
+    import signal
+    import time
+
+    worker = test_env.workers.pop()
+    test_env.worker_defs = ()
+    os.killpg(os.getpgid(worker.pid), signal.SIGKILL)
+    worker.wait()
+    # Let the server notice the lost connection
+    time.sleep(0.5)
+
+
+def test_inline_threshold(test_env):
+    """Objects up to the threshold are sent inline to the server, so they
+    survive the loss of the worker that produced them"""
+    import pytest
+    from rain.client import tasks, blob, RainException
+
+    test_env.start(1, server_args=("--inline-threshold", "4"))
+    client = test_env.client
+    s1 = client.new_session()
+    s2 = client.new_session()
+    s3 = client.new_session()
+    with s1.bind_only():
+        # Exactly at the threshold
+        t1 = tasks.execute("printf abcd", stdout=True)
+        t1.keep_outputs()
+        s1.submit()
+    with s2.bind_only():
+        t2 = tasks.execute("printf abcde", stdout=True)
+        t2.keep_outputs()
+        s2.submit()
+    with s3.bind_only():
+        # Directories are never inlined
+        t3 = tasks.make_directory([("a", blob("x"))])
+        t3.keep_outputs()
+        s3.submit()
+    for s in (s1, s2, s3):
+        s.wait_all()
+
+    kill_only_worker(test_env)
+    assert t1.output.fetch().get_bytes() == b"abcd"
+    assert t1.output.fetch(1, 2).get_bytes() == b"bc"
+    with pytest.raises(RainException):
+        t2.output.fetch()
+    with pytest.raises(RainException):
+        t3.output.fetch()
+
+
+def test_inline_threshold_zero(test_env):
+    """No objects with data are inlined with threshold 0"""
+    import pytest
+    from rain.client import tasks, RainException
+
+    test_env.start(1, server_args=("--inline-threshold", "0"))
+    s = test_env.client.new_session()
+    with s.bind_only():
+        t1 = tasks.execute("printf a", stdout=True)
+        t1.keep_outputs()
+        s.submit()
+    s.wait_all()
+
+    kill_only_worker(test_env)
+    with pytest.raises(RainException):
+        t1.output.fetch()
+
+
+def test_worker_via_gateway(test_env):
+    """Worker connected through a gateway runs tasks and serves its data"""
+    import os
+    import socket
+    import time
+    from conftest import RAIN_BIN, WORK_DIR
+    from rain.client import tasks, blob
+
+    # Objects are not inlined, so they are fetched from the data store of the worker
+    test_env.start(0, server_args=("--inline-threshold", "0"))
+    gateway_address = "127.0.0.1:17011"
+    test_env.start_process("gateway", (RAIN_BIN, "gateway", "--listen", gateway_address,
+                                       "127.0.0.1:" + test_env.running_port),
+                           env=test_env.env)
+    for _ in range(100):
+        try:
+            socket.create_connection(("127.0.0.1", 17011)).close()
+            break
+        except ConnectionRefusedError:
+            time.sleep(0.05)
+
+    ready_file = os.path.join(WORK_DIR, "worker0-ready")
+    wdir = os.path.join(WORK_DIR, "worker-0")
+    test_env.workers.append(test_env.start_process(
+        "worker0", (RAIN_BIN, "worker", gateway_address, "--via-gateway",
+                    "--ready-file", ready_file, "--cpus", "1",
+                    "--logdir", os.path.join(wdir, "logs"),
+                    "--workdir", os.path.join(wdir, "work")),
+        env=test_env.env))
+    test_env.worker_defs = (1,)
+    for _ in range(100):
+        if os.path.isfile(ready_file):
+            break
+        time.sleep(0.05)
+        test_env.check_running_processes()
+    assert os.path.isfile(ready_file)
+
+    with test_env.client.new_session() as s:
+        t1 = tasks.concat((blob("abc"), blob("def")))
+        t1.keep_outputs()
+        s.submit()
+        s.wait_all()
+        assert t1.output.fetch().get_bytes() == b"abcdef"
+
+
+def test_start_interrupted(test_env):
+    """Interrupted `rain start --wait` cancels sessions, stops the worker and the
+    server and removes its ready files"""
+    import glob
+    import os
+    import signal
+    import socket
+    import tempfile
+    import time
+    import pytest
+    from conftest import RAIN_BIN, WORK_DIR
+    from rain.client import Client, tasks
+
+    starter = test_env.start_process("starter", (
+        RAIN_BIN, "start", "--simple", "--wait",
+        "--listen", "17020", "--http-listen", "17021",
+        "--logdir", os.path.join(WORK_DIR, "start-logs"),
+        "--workdir", os.path.join(WORK_DIR, "start-work")))
+    client = None
+    for _ in range(100):
+        try:
+            client = Client("127.0.0.1", 17020)
+            if client.get_server_info()["workers"]:
+                break
+        except Exception:
+            pass
+        time.sleep(0.1)
+    assert client is not None
+
+    pid_file = os.path.join(WORK_DIR, "task.pid")
+    s = client.new_session()
+    with s.bind_only():
+        tasks.execute(["sh", "-c", "echo $$ > {}; exec sleep 100".format(pid_file)])
+        s.submit()
+    for _ in range(50):
+        if os.path.isfile(pid_file) and os.path.getsize(pid_file) > 0:
+            break
+        time.sleep(0.1)
+    with open(pid_file) as f:
+        task_pid = int(f.read())
+
+    os.kill(starter.pid, signal.SIGINT)
+    test_env.assert_max_duration(15, lambda: starter.wait())
+    time.sleep(0.2)
+    assert not os.path.exists("/proc/{}".format(task_pid))
+    assert not glob.glob(os.path.join(tempfile.gettempdir(),
+                                      "rain-{}-*".format(starter.pid)))
+    with pytest.raises(ConnectionRefusedError):
+        socket.create_connection(("127.0.0.1", 17020))
+
+
+def test_start_in_process(test_env):
+    """`rain start --simple --in-process` runs the server and the worker
+    without child processes"""
+    import os
+    import time
+    from conftest import RAIN_BIN, WORK_DIR
+    from rain.client import Client, tasks, blob
+
+    starter = test_env.start_process("starter", (
+        RAIN_BIN, "start", "--simple", "--in-process",
+        "--listen", "17022", "--http-listen", "17023",
+        "--logdir", os.path.join(WORK_DIR, "start-logs")))
+    client = None
+    for _ in range(100):
+        try:
+            client = Client("127.0.0.1", 17022)
+            if client.get_server_info()["workers"]:
+                break
+        except Exception:
+            pass
+        time.sleep(0.1)
+    assert client is not None
+
+    with client.new_session() as s:
+        t1 = tasks.concat((blob("abc"), blob("def")))
+        t2 = tasks.execute(["cat", t1], stdout=True)
+        t2.keep_outputs()
+        s.submit()
+        assert t2.output.fetch().get_bytes() == b"abcdef"
+
+    children = set()
+    for tid in os.listdir("/proc/{}/task".format(starter.pid)):
+        with open("/proc/{}/task/{}/children".format(starter.pid, tid)) as f:
+            children.update(f.read().split())
+    assert not children
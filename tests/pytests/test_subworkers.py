@@ -0,0 +1,113 @@
+from rain.client import tasks, blob, directory, TaskException
+import pytest
+import json
+import os
+import shutil
+
+BRIDGE = ["python3", "-m", "rain.subworker.bridge"]
+BRIDGE_SH = "sh=python3 -m rain.subworker.bridge sh"
+
+# Subworker kinds checked by the protocol conformance tests: the kind, a script
+# copying input "input0" into output "out0" and a script failing with "broken" on stderr
+CONFORMANCE = {
+    "sh": ({"command": BRIDGE + ["sh"]},
+           "cat input0 > out0",
+           "echo broken >&2; exit 3"),
+    "sh-memory": ({"command": BRIDGE + ["sh"], "memory_inputs": True},
+                  "test ! -L input0 && cat input0 > out0",
+                  "echo broken >&2; exit 3"),
+    "js": ({"command": BRIDGE + ["node"], "memory_inputs": True},
+           "require('fs').copyFileSync('input0', 'out0')",
+           "console.error('broken'); process.exit(3)"),
+}
+
+
+def start_kind(test_env, name):
+    kind = CONFORMANCE[name][0]
+    if shutil.which(kind["command"][-1]) is None:
+        pytest.skip("{} is not installed".format(kind["command"][-1]))
+    path = os.path.join(test_env.work_dir, "subworkers.json")
+    with open(path, "w") as f:
+        json.dump({name: kind}, f)
+    test_env.start(1, worker_args=("--subworkers", path))
+    return CONFORMANCE[name][1:]
+
+
+@pytest.mark.parametrize("name", sorted(CONFORMANCE))
+def test_conformance_inputs(test_env, name):
+    """Small (in memory) and large (in a file) inputs, subworker reused by tasks"""
+    copy, _ = start_kind(test_env, name)
+    large = b"0123456789" * 100 * 1024
+    with test_env.client.new_session() as s:
+        t1 = tasks.bridge_script(name, copy, inputs=[blob("small")])
+        t2 = tasks.bridge_script(name, copy, inputs=[blob(large)])
+        t3 = tasks.bridge_script(name, copy, inputs=[t2])
+        t1.output.keep()
+        t3.output.keep()
+        s.submit()
+        assert t1.output.fetch().get_bytes() == b"small"
+        assert t3.output.fetch().get_bytes() == large
+
+
+@pytest.mark.parametrize("name", sorted(CONFORMANCE))
+def test_conformance_failure(test_env, name):
+    _, fail = start_kind(test_env, name)
+    with test_env.client.new_session() as s:
+        t1 = tasks.bridge_script(name, fail)
+        s.submit()
+        with pytest.raises(TaskException, match="broken"):
+            t1.wait()
+
+
+def test_memory_inputs_reject_directory(test_env):
+    start_kind(test_env, "sh-memory")
+    path = os.path.join(test_env.work_dir, "dir")
+    os.mkdir(path)
+    with open(os.path.join(path, "file"), "w") as f:
+        f.write("data")
+    with test_env.client.new_session() as s:
+        t1 = tasks.bridge_script("sh-memory", "true", inputs=[directory(path)])
+        s.submit()
+        with pytest.raises(TaskException, match="accepts only blobs"):
+            t1.wait()
+
+
+def test_bridge_subworker(test_env):
+    """Subworker type implemented by the reference bridge"""
+    test_env.start(1, worker_args=("--subworker", BRIDGE_SH))
+    with test_env.client.new_session() as s:
+        t1 = tasks.bridge_script("sh", "tr a-z A-Z < text > out0",
+                                 inputs=[("text", blob("hello"))])
+        t2 = tasks.bridge_script("sh", "cat input0 input1 > joined",
+                                 inputs=[t1, blob("!")], outputs=["joined"])
+        t2.output.keep()
+        s.submit()
+        assert t2.output.fetch().get_bytes() == b"HELLO!"
+
+
+def test_bridge_subworker_failure(test_env):
+    test_env.start(1, worker_args=("--subworker", BRIDGE_SH))
+    with test_env.client.new_session() as s:
+        t1 = tasks.bridge_script("sh", "echo broken >&2; exit 3")
+        s.submit()
+        with pytest.raises(TaskException, match="(?s)exited with code 3.*broken"):
+            t1.wait()
+
+    with test_env.client.new_session() as s:
+        t1 = tasks.bridge_script("sh", "true")
+        s.submit()
+        with pytest.raises(TaskException, match="Output 'out0' was not created"):
+            t1.wait()
+
+
+def test_subworker_kinds_file(test_env):
+    path = os.path.join(test_env.work_dir, "subworkers.json")
+    with open(path, "w") as f:
+        json.dump({"upper": {"command": BRIDGE + ["sh"],
+                             "env": {"CASE": "upper"}}}, f)
+    test_env.start(1, worker_args=("--subworkers", path))
+    with test_env.client.new_session() as s:
+        t1 = tasks.bridge_script("upper", "printf $CASE > out0")
+        t1.output.keep()
+        s.submit()
+        assert t1.output.fetch().get_bytes() == b"upper"
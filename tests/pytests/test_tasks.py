@@ -2,6 +2,7 @@
 from rain.client import Task
 from rain.client.data import DataObject
 from rain.client.output import Output
+from rain.common import RainException
 
 import pytest
 
@@ -16,6 +17,27 @@ def test_task_construction(fake_session):
         assert t1.id != t2.id
 
 
+def test_task_units(fake_session):
+    with fake_session:
+        t = Task("dummy", memory="512M", duration="2h30m")
+        assert t.attributes["resources"]["memory"] == 512 * 1024 * 1024
+        assert t.attributes["duration"] == 9000.0
+        assert Task("dummy", memory=1000).attributes["resources"]["memory"] == 1000
+        with pytest.raises(RainException, match="memory"):
+            Task("dummy", memory="12X")
+        with pytest.raises(RainException, match="duration"):
+            Task("dummy", duration="-5")
+
+
+def test_task_arch(fake_session):
+    from rain.client import tasks
+    with fake_session:
+        assert tasks.execute("ls").attributes.get("arch") == tasks.CLIENT_ARCH
+        assert "arch" not in tasks.execute("ls", arch=None).attributes
+        assert tasks.execute("ls", arch="aarch64").attributes["arch"] == "aarch64"
+        assert "arch" not in tasks.script("echo 1").attributes
+
+
 def test_task_outputs(fake_session):
     with fake_session:
         t1 = Task("dummy",